@@ -0,0 +1,27 @@
+use std::process::Command;
+
+/// Embeds the git commit and build time so `GET /api/version` can report
+/// exactly what is deployed. Builds outside a git checkout (e.g. from a
+/// source tarball) can pass `GIT_COMMIT` in the environment instead.
+fn main() {
+    let commit = std::env::var("GIT_COMMIT").ok().or_else(|| {
+        Command::new("git")
+            .args(["rev-parse", "--short=12", "HEAD"])
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+    });
+
+    println!(
+        "cargo:rustc-env=FILESHARE_GIT_COMMIT={}",
+        commit.unwrap_or_else(|| "unknown".to_string())
+    );
+    println!(
+        "cargo:rustc-env=FILESHARE_BUILD_DATE={}",
+        chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+    println!("cargo:rerun-if-env-changed=GIT_COMMIT");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs/heads");
+}
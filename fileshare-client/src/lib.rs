@@ -0,0 +1,479 @@
+//! Typed async client for the fileshare_rust HTTP API.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), fileshare_client::Error> {
+//! use fileshare_client::{Client, UploadOptions};
+//!
+//! let client = Client::new("http://localhost:3000")?;
+//! let docs = client.create_directory("docs", None).await?;
+//! let upload = client
+//!     .upload_path(
+//!         "report.pdf",
+//!         &UploadOptions {
+//!             parent_directory_id: Some(docs.id),
+//!             ..Default::default()
+//!         },
+//!     )
+//!     .await?;
+//! client.download_to_path(&upload.file.id, "copy.pdf").await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Requests that are safe to repeat are retried with exponential backoff on
+//! network errors and gateway/rate-limit responses; see [`RetryPolicy`].
+
+mod models;
+mod retry;
+
+pub use models::{
+    Capabilities, DirectoryInfo, FileInfo, FileSegment, LinkInfo, Listing, SegmentManifest,
+    UploadReceipt, UploadResult, VersionInfo,
+};
+pub use retry::RetryPolicy;
+
+use bytes::Bytes;
+use futures_util::{stream, Stream, StreamExt, TryStreamExt};
+use models::{
+    CreateDirectoryRequest, CreateDirectoryResponse, DirectoryAppearanceRequest, ErrorResponse,
+    MoveDirectoryRequest, MoveFileRequest,
+};
+use reqwest::header::{IF_MATCH, IF_RANGE, RANGE};
+use reqwest::multipart::{Form, Part};
+use reqwest::{Body, RequestBuilder, Response, StatusCode, Url};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::future::Future;
+use std::io::SeekFrom;
+use std::path::Path;
+use tokio::io::{AsyncRead, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Invalid URL: {0}")]
+    InvalidUrl(String),
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+    /// The server answered with an error status.
+    #[error("Server returned {status}: {message}")]
+    Api { status: StatusCode, message: String },
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    /// A downloaded segment did not match its checksum in the manifest.
+    #[error("Segment {0} failed its checksum")]
+    SegmentChecksum(u32),
+    /// The file changed after its segment manifest was fetched.
+    #[error("File changed during the download")]
+    Changed,
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Where an upload goes and what it's annotated with.
+#[derive(Debug, Clone, Default)]
+pub struct UploadOptions {
+    pub parent_directory_id: Option<String>,
+    pub description: Option<String>,
+    /// Overrides the file name taken from the path.
+    pub filename: Option<String>,
+    /// Hex SHA-256 the content must have; the server refuses the upload
+    /// with 422 otherwise.
+    pub checksum: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    http: reqwest::Client,
+    base_url: Url,
+    retry: RetryPolicy,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Result<Self> {
+        Self::with_http_client(base_url, reqwest::Client::new())
+    }
+
+    /// Uses a preconfigured `reqwest::Client`, e.g. one with timeouts,
+    /// default headers or a proxy.
+    pub fn with_http_client(base_url: &str, http: reqwest::Client) -> Result<Self> {
+        let base_url = Url::parse(base_url).map_err(|e| Error::InvalidUrl(e.to_string()))?;
+        if base_url.cannot_be_a_base() {
+            return Err(Error::InvalidUrl(base_url.to_string()));
+        }
+        Ok(Self {
+            http,
+            base_url,
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub async fn health(&self) -> Result<()> {
+        let url = self.url(&["health"])?;
+        self.execute(|| async { Ok(self.http.get(url.clone())) })
+            .await?;
+        Ok(())
+    }
+
+    pub async fn version(&self) -> Result<VersionInfo> {
+        self.get_json(&["api", "version"]).await
+    }
+
+    pub async fn capabilities(&self) -> Result<Capabilities> {
+        self.get_json(&["api", "capabilities"]).await
+    }
+
+    /// Lists the direct contents of a directory, or of the root for `None`.
+    pub async fn list(&self, parent_directory_id: Option<&str>) -> Result<Listing> {
+        let mut url = self.url(&["api", "files"])?;
+        if let Some(parent) = parent_directory_id {
+            url.query_pairs_mut()
+                .append_pair("parent_directory_id", parent);
+        }
+        let response = self
+            .execute(|| async { Ok(self.http.get(url.clone())) })
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    pub async fn file_info(&self, id: &str) -> Result<FileInfo> {
+        self.get_json(&["api", "files", id]).await
+    }
+
+    /// Uploads a file from disk, streaming it rather than reading it into
+    /// memory. Failed attempts are retried by reopening the file.
+    pub async fn upload_path(
+        &self,
+        path: impl AsRef<Path>,
+        options: &UploadOptions,
+    ) -> Result<UploadResult> {
+        let path = path.as_ref();
+        let filename = match &options.filename {
+            Some(name) => name.clone(),
+            None => path
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .ok_or_else(|| {
+                    Error::Io(std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        format!("{:?} has no file name", path),
+                    ))
+                })?,
+        };
+        let url = self.url(&["api", "files"])?;
+
+        let response = self
+            .execute(|| async {
+                let file = tokio::fs::File::open(path).await?;
+                let length = file.metadata().await?.len();
+                let part =
+                    Part::stream_with_length(Body::wrap_stream(ReaderStream::new(file)), length)
+                        .file_name(filename.clone());
+                Ok(self
+                    .http
+                    .post(url.clone())
+                    .multipart(upload_form(options, part)))
+            })
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Uploads from any reader. The body can only be read once, so this
+    /// request is never retried.
+    pub async fn upload_reader<R>(
+        &self,
+        filename: &str,
+        reader: R,
+        options: &UploadOptions,
+    ) -> Result<UploadResult>
+    where
+        R: AsyncRead + Send + Sync + 'static,
+    {
+        let part = Part::stream(Body::wrap_stream(ReaderStream::new(reader)))
+            .file_name(filename.to_string());
+        let response = self
+            .http
+            .post(self.url(&["api", "files"])?)
+            .multipart(upload_form(options, part))
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Streams a file's contents. Only starting the download is retried.
+    pub async fn download(&self, id: &str) -> Result<impl Stream<Item = Result<Bytes>>> {
+        let url = self.url(&["api", "files", id, "download"])?;
+        let response = self
+            .execute(|| async { Ok(self.http.get(url.clone())) })
+            .await?;
+        Ok(response.bytes_stream().map_err(Error::from))
+    }
+
+    /// Downloads a file into `writer`, returning the number of bytes written.
+    pub async fn download_to<W>(&self, id: &str, writer: &mut W) -> Result<u64>
+    where
+        W: AsyncWrite + Unpin,
+    {
+        let mut stream = std::pin::pin!(self.download(id).await?);
+        let mut written = 0;
+        while let Some(chunk) = stream.try_next().await? {
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+        }
+        writer.flush().await?;
+        Ok(written)
+    }
+
+    pub async fn download_to_path(&self, id: &str, path: impl AsRef<Path>) -> Result<u64> {
+        let mut file = tokio::fs::File::create(path).await?;
+        self.download_to(id, &mut file).await
+    }
+
+    /// Splits a file into at most `parts` byte ranges with a checksum for
+    /// each, for [`download_parallel_to_path`](Self::download_parallel_to_path)
+    /// or other clients that fetch parts concurrently.
+    pub async fn segments(&self, id: &str, parts: u32) -> Result<SegmentManifest> {
+        let mut url = self.url(&["api", "files", id, "segments"])?;
+        url.query_pairs_mut()
+            .append_pair("parts", &parts.to_string());
+        let response = self
+            .execute(|| async { Ok(self.http.get(url.clone())) })
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Downloads a file in up to `parts` segments at once, each written at
+    /// its offset in `path` and checked against its own checksum. Fails
+    /// with [`Error::Changed`] if the file changes partway through.
+    pub async fn download_parallel_to_path(
+        &self,
+        id: &str,
+        path: impl AsRef<Path>,
+        parts: u32,
+    ) -> Result<u64> {
+        let path = path.as_ref();
+        let manifest = self.segments(id, parts).await?;
+        let file = tokio::fs::File::create(path).await?;
+        file.set_len(manifest.file_size.max(0) as u64).await?;
+        drop(file);
+
+        let url = self.url(&["api", "files", id, "download"])?;
+        let concurrency = manifest.segments.len().max(1);
+        stream::iter(&manifest.segments)
+            .map(|segment| self.download_segment(&url, &manifest.etag, segment, path))
+            .buffer_unordered(concurrency)
+            .try_collect::<()>()
+            .await?;
+        Ok(manifest.file_size.max(0) as u64)
+    }
+
+    async fn download_segment(
+        &self,
+        url: &Url,
+        etag: &str,
+        segment: &FileSegment,
+        path: &Path,
+    ) -> Result<()> {
+        let range = format!("bytes={}-{}", segment.start, segment.end);
+        let response = self
+            .execute(|| async {
+                Ok(self
+                    .http
+                    .get(url.clone())
+                    .header(RANGE, &range)
+                    .header(IF_RANGE, etag))
+            })
+            .await?;
+        // The whole file comes back instead when If-Range no longer matches.
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(Error::Changed);
+        }
+
+        let mut file = tokio::fs::OpenOptions::new().write(true).open(path).await?;
+        file.seek(SeekFrom::Start(segment.start as u64)).await?;
+        let mut hasher = Sha256::new();
+        let mut body = std::pin::pin!(response.bytes_stream());
+        while let Some(chunk) = body.try_next().await? {
+            hasher.update(&chunk);
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        if hex::encode(hasher.finalize()) != segment.sha256 {
+            return Err(Error::SegmentChecksum(segment.index));
+        }
+        Ok(())
+    }
+
+    pub async fn delete_file(&self, id: &str) -> Result<()> {
+        let url = self.url(&["api", "files", id])?;
+        self.execute(|| async { Ok(self.http.delete(url.clone())) })
+            .await?;
+        Ok(())
+    }
+
+    /// Moves a file into another directory, or to the root for `None`.
+    /// `version` is the file's version when it was fetched; the server
+    /// refuses the move with 409 if the file has changed since.
+    pub async fn move_file(
+        &self,
+        id: &str,
+        parent_directory_id: Option<&str>,
+        version: i64,
+    ) -> Result<FileInfo> {
+        let body = MoveFileRequest {
+            parent_directory_id,
+        };
+        self.patch_json(&["api", "files", id], version, &body).await
+    }
+
+    /// Creates a directory. Not retried, since a repeat would fail as a
+    /// duplicate if the first attempt actually succeeded.
+    pub async fn create_directory(
+        &self,
+        name: &str,
+        parent_id: Option<&str>,
+    ) -> Result<DirectoryInfo> {
+        let response = self
+            .http
+            .post(self.url(&["api", "directories"])?)
+            .json(&CreateDirectoryRequest { name, parent_id })
+            .send()
+            .await?;
+        let created: CreateDirectoryResponse = check_status(response).await?.json().await?;
+        Ok(created.directory)
+    }
+
+    pub async fn directory_info(&self, id: &str) -> Result<DirectoryInfo> {
+        self.get_json(&["api", "directories", id]).await
+    }
+
+    /// Deletes a directory with everything in it.
+    pub async fn delete_directory(&self, id: &str) -> Result<()> {
+        let url = self.url(&["api", "directories", id])?;
+        self.execute(|| async { Ok(self.http.delete(url.clone())) })
+            .await?;
+        Ok(())
+    }
+
+    /// Moves a directory under another one, or to the root for `None`.
+    /// Refused with 409 if the directory is no longer at `version`.
+    pub async fn move_directory(
+        &self,
+        id: &str,
+        parent_id: Option<&str>,
+        version: i64,
+    ) -> Result<DirectoryInfo> {
+        let body = MoveDirectoryRequest { parent_id };
+        self.patch_json(&["api", "directories", id], version, &body)
+            .await
+    }
+
+    /// Sets a directory's color label and icon; `None` removes them.
+    /// Refused with 409 if the directory is no longer at `version`.
+    pub async fn set_directory_appearance(
+        &self,
+        id: &str,
+        color: Option<&str>,
+        icon: Option<&str>,
+        version: i64,
+    ) -> Result<DirectoryInfo> {
+        let body = DirectoryAppearanceRequest { color, icon };
+        self.patch_json(&["api", "directories", id], version, &body)
+            .await
+    }
+
+    fn url(&self, segments: &[&str]) -> Result<Url> {
+        let mut url = self.base_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| Error::InvalidUrl(self.base_url.to_string()))?
+            .pop_if_empty()
+            .extend(segments);
+        Ok(url)
+    }
+
+    /// Sends a change guarded by `If-Match`. Not retried: if the first
+    /// attempt went through, the version has moved on and a repeat would be
+    /// refused as a conflict.
+    async fn patch_json<B: Serialize, T: DeserializeOwned>(
+        &self,
+        segments: &[&str],
+        version: i64,
+        body: &B,
+    ) -> Result<T> {
+        let response = self
+            .http
+            .patch(self.url(segments)?)
+            .header(IF_MATCH, format!("\"{}\"", version))
+            .json(body)
+            .send()
+            .await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    async fn get_json<T: DeserializeOwned>(&self, segments: &[&str]) -> Result<T> {
+        let url = self.url(segments)?;
+        let response = self
+            .execute(|| async { Ok(self.http.get(url.clone())) })
+            .await?;
+        Ok(response.json().await?)
+    }
+
+    /// Sends the request built by `build`, rebuilding and resending it after
+    /// transient failures as allowed by the retry policy.
+    async fn execute<F, Fut>(&self, build: F) -> Result<Response>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<RequestBuilder>>,
+    {
+        let mut attempt = 0;
+        loop {
+            let transient = match build().await?.send().await {
+                Ok(response) if retry::is_transient_status(response.status()) => {
+                    Err(check_status(response).await.unwrap_err())
+                }
+                Ok(response) => return check_status(response).await,
+                Err(e) if retry::is_transient_error(&e) => Err(Error::Http(e)),
+                Err(e) => return Err(e.into()),
+            };
+
+            attempt += 1;
+            if attempt > self.retry.max_retries {
+                return transient;
+            }
+            tokio::time::sleep(self.retry.backoff(attempt)).await;
+        }
+    }
+}
+
+fn upload_form(options: &UploadOptions, file: Part) -> Form {
+    let mut form = Form::new();
+    if let Some(parent) = &options.parent_directory_id {
+        form = form.text("parent_directory_id", parent.clone());
+    }
+    if let Some(description) = &options.description {
+        form = form.text("description", description.clone());
+    }
+    if let Some(checksum) = &options.checksum {
+        form = form.text("checksum", checksum.clone());
+    }
+    form.part("file", file)
+}
+
+/// Turns error statuses into [`Error::Api`] with the server's message.
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let body = response.text().await.unwrap_or_default();
+    let message = serde_json::from_str::<ErrorResponse>(&body)
+        .map(|e| e.error)
+        .unwrap_or(body);
+    Err(Error::Api { status, message })
+}
@@ -0,0 +1,147 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileInfo {
+    pub id: String,
+    pub filename: String,
+    pub original_filename: String,
+    pub file_size: i64,
+    pub mime_type: Option<String>,
+    pub uploaded_at: String,
+    pub description: Option<String>,
+    pub parent_directory_id: Option<String>,
+    pub version: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DirectoryInfo {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    pub version: i64,
+    pub file_count: i64,
+    pub total_size: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LinkInfo {
+    pub id: String,
+    pub name: String,
+    pub target_url: Option<String>,
+    pub target_file_id: Option<String>,
+    pub parent_directory_id: Option<String>,
+    pub description: Option<String>,
+    pub created_at: String,
+}
+
+/// The direct contents of a directory (or the root).
+#[derive(Debug, Clone, Deserialize)]
+pub struct Listing {
+    pub files: Vec<FileInfo>,
+    pub directories: Vec<DirectoryInfo>,
+    pub links: Vec<LinkInfo>,
+    pub total: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadReceipt {
+    pub file_id: String,
+    pub sha256: String,
+    pub file_size: i64,
+    pub received_at: String,
+    pub scan_status: String,
+    pub signature_algorithm: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadResult {
+    pub file: FileInfo,
+    pub receipt: UploadReceipt,
+}
+
+/// Byte ranges of a file with the checksum of each, for downloading it in
+/// parallel parts.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SegmentManifest {
+    pub file_id: String,
+    pub file_size: i64,
+    pub sha256: Option<String>,
+    pub etag: String,
+    pub segment_size: i64,
+    pub segments: Vec<FileSegment>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct FileSegment {
+    pub index: u32,
+    pub start: i64,
+    /// Inclusive, as in a `Range` header.
+    pub end: i64,
+    pub length: i64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct VersionInfo {
+    pub version: String,
+    pub git_commit: String,
+    pub build_date: String,
+    pub database_backend: String,
+    pub storage_backend: String,
+    pub features: Vec<String>,
+    pub schema_version: i64,
+}
+
+/// Which optional features the server offers. Unknown features sent by
+/// newer servers are ignored.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Capabilities {
+    pub features: serde_json::Map<String, serde_json::Value>,
+    pub max_upload_size: Option<u64>,
+}
+
+impl Capabilities {
+    pub fn has(&self, feature: &str) -> bool {
+        self.features
+            .get(feature)
+            .and_then(|v| v.as_bool())
+            .unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct CreateDirectoryRequest<'a> {
+    pub name: &'a str,
+    pub parent_id: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct CreateDirectoryResponse {
+    pub directory: DirectoryInfo,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MoveFileRequest<'a> {
+    pub parent_directory_id: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct MoveDirectoryRequest<'a> {
+    pub parent_id: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct DirectoryAppearanceRequest<'a> {
+    pub color: Option<&'a str>,
+    pub icon: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct ErrorResponse {
+    pub error: String,
+}
@@ -0,0 +1,52 @@
+## Public pages
+
+error-title = Etwas ist schiefgelaufen
+share-not-found = Freigabe nicht gefunden
+not-found = Nicht gefunden
+share-terms-required = Die Bedingungen dieser Freigabe müssen zuerst akzeptiert werden
+terms-intro = Akzeptieren Sie die folgenden Bedingungen, um diese Datei herunterzuladen.
+terms-accept = Akzeptieren und herunterladen
+download-confirmation-required = Der Download dieses Dateityps muss zuerst bestätigt werden
+download-warning = .{ $extension }-Dateien können Programme auf Ihrem Gerät ausführen. Laden Sie diese Datei nur herunter, wenn Sie der Person vertrauen, die sie geteilt hat.
+download-confirm = Trotzdem herunterladen
+index-heading = Inhalt von
+column-name = Name
+column-size = Größe
+column-modified = Geändert
+bundle-heading =
+    { $count ->
+        [one] Eine geteilte Datei
+       *[other] { $count } geteilte Dateien
+    }
+bundle-download-all = Alle herunterladen
+upload-heading = Eine Datei an { $directory } senden
+upload-deadline = Dateien werden bis { $deadline } UTC angenommen.
+upload-name = Ihr Name
+upload-email = Ihre E-Mail-Adresse (optional)
+upload-file = Datei
+upload-submit = Senden
+upload-received = { $name } ist angekommen. Vielen Dank!
+upload-another = Weitere Datei senden
+upload-link-not-found = Upload-Link nicht gefunden
+upload-deadline-passed = Die Frist dieses Upload-Links ist abgelaufen
+upload-sender-required = Ihr Name muss vor der Datei angegeben werden
+upload-invalid-email = Die E-Mail-Adresse ist ungültig
+upload-no-file = Es wurde keine Datei gesendet
+upload-failed = Die Datei konnte nicht gespeichert werden
+share-report-received = Die Meldung wurde an die Moderation weitergeleitet. Vielen Dank!
+share-report-invalid-reason = Der Grund muss einer der folgenden sein: { $reasons }
+
+## Notifications
+
+new-file-subject = Neue Datei in { $directory }
+new-file-message = { $name } wurde zu { $directory } hinzugefügt
+share-access-subject = { $name } wurde über eine Freigabe heruntergeladen
+share-access-message = { $name } wurde über einen Freigabelink aus dem Netz { $network } heruntergeladen.
+job-finished-subject = Auftrag abgeschlossen
+quota-exceeded-subject = Speicherkontingent überschritten
+quota-exceeded-message = Der Speicher liegt über seinem weichen Kontingent: { $used } von { $limit } Bytes belegt. Nach { $grace_ends_at } werden Uploads abgelehnt, sofern keine Dateien gelöscht werden.
+digest-subject =
+    { $count ->
+        [one] 1 neue Benachrichtigung
+       *[other] { $count } neue Benachrichtigungen
+    }
@@ -0,0 +1,52 @@
+## Public pages
+
+error-title = Something went wrong
+share-not-found = Share not found
+not-found = Not found
+share-terms-required = The terms of this share must be accepted first
+terms-intro = Accept the following terms to download this file.
+terms-accept = Accept and download
+download-confirmation-required = Downloading this type of file must be confirmed first
+download-warning = .{ $extension } files can run programs on your device. Only download this file if you trust whoever shared it.
+download-confirm = Download anyway
+index-heading = Index of
+column-name = Name
+column-size = Size
+column-modified = Modified
+bundle-heading =
+    { $count ->
+        [one] One shared file
+       *[other] { $count } shared files
+    }
+bundle-download-all = Download all
+upload-heading = Send a file to { $directory }
+upload-deadline = Files are accepted until { $deadline } UTC.
+upload-name = Your name
+upload-email = Your email (optional)
+upload-file = File
+upload-submit = Send
+upload-received = { $name } was received. Thank you!
+upload-another = Send another file
+upload-link-not-found = Upload link not found
+upload-deadline-passed = The deadline for this upload link has passed
+upload-sender-required = Your name must be given before the file
+upload-invalid-email = The email address is not valid
+upload-no-file = No file was sent
+upload-failed = The file could not be stored
+share-report-received = The report was sent to the moderators. Thank you!
+share-report-invalid-reason = The reason must be one of: { $reasons }
+
+## Notifications
+
+new-file-subject = New file in { $directory }
+new-file-message = { $name } was added to { $directory }
+share-access-subject = { $name } was downloaded through a share
+share-access-message = { $name } was downloaded through a share link from the network { $network }.
+job-finished-subject = Job finished
+quota-exceeded-subject = Storage quota exceeded
+quota-exceeded-message = Storage is over its soft quota: { $used } of { $limit } bytes used. Uploads will be refused after { $grace_ends_at } unless files are deleted.
+digest-subject =
+    { $count ->
+        [one] 1 new notification
+       *[other] { $count } new notifications
+    }
@@ -0,0 +1,52 @@
+## Public pages
+
+error-title = Algo salió mal
+share-not-found = Enlace compartido no encontrado
+not-found = No encontrado
+share-terms-required = Primero deben aceptarse las condiciones de este enlace compartido
+terms-intro = Acepte las siguientes condiciones para descargar este archivo.
+terms-accept = Aceptar y descargar
+download-confirmation-required = Primero debe confirmarse la descarga de este tipo de archivo
+download-warning = Los archivos .{ $extension } pueden ejecutar programas en su dispositivo. Descargue este archivo solo si confía en quien lo compartió.
+download-confirm = Descargar de todos modos
+index-heading = Índice de
+column-name = Nombre
+column-size = Tamaño
+column-modified = Modificado
+bundle-heading =
+    { $count ->
+        [one] Un archivo compartido
+       *[other] { $count } archivos compartidos
+    }
+bundle-download-all = Descargar todo
+upload-heading = Enviar un archivo a { $directory }
+upload-deadline = Se aceptan archivos hasta el { $deadline } UTC.
+upload-name = Su nombre
+upload-email = Su correo electrónico (opcional)
+upload-file = Archivo
+upload-submit = Enviar
+upload-received = Se ha recibido { $name }. ¡Gracias!
+upload-another = Enviar otro archivo
+upload-link-not-found = Enlace de subida no encontrado
+upload-deadline-passed = El plazo de este enlace de subida ha vencido
+upload-sender-required = Debe indicar su nombre antes del archivo
+upload-invalid-email = La dirección de correo electrónico no es válida
+upload-no-file = No se ha enviado ningún archivo
+upload-failed = No se pudo guardar el archivo
+share-report-received = La denuncia se ha enviado a los moderadores. ¡Gracias!
+share-report-invalid-reason = El motivo debe ser uno de: { $reasons }
+
+## Notifications
+
+new-file-subject = Nuevo archivo en { $directory }
+new-file-message = { $name } se añadió a { $directory }
+share-access-subject = { $name } se descargó a través de un enlace compartido
+share-access-message = { $name } se descargó a través de un enlace compartido desde la red { $network }.
+job-finished-subject = Tarea finalizada
+quota-exceeded-subject = Cuota de almacenamiento superada
+quota-exceeded-message = El almacenamiento supera su cuota flexible: { $used } de { $limit } bytes usados. Las subidas se rechazarán después de { $grace_ends_at } a menos que se eliminen archivos.
+digest-subject =
+    { $count ->
+        [one] 1 notificación nueva
+       *[other] { $count } notificaciones nuevas
+    }
@@ -0,0 +1,52 @@
+## Public pages
+
+error-title = Une erreur est survenue
+share-not-found = Partage introuvable
+not-found = Introuvable
+share-terms-required = Les conditions de ce partage doivent d’abord être acceptées
+terms-intro = Acceptez les conditions suivantes pour télécharger ce fichier.
+terms-accept = Accepter et télécharger
+download-confirmation-required = Le téléchargement de ce type de fichier doit d’abord être confirmé
+download-warning = Les fichiers .{ $extension } peuvent exécuter des programmes sur votre appareil. Ne téléchargez ce fichier que si vous faites confiance à la personne qui l’a partagé.
+download-confirm = Télécharger quand même
+index-heading = Index de
+column-name = Nom
+column-size = Taille
+column-modified = Modifié
+bundle-heading =
+    { $count ->
+        [one] { $count } fichier partagé
+       *[other] { $count } fichiers partagés
+    }
+bundle-download-all = Tout télécharger
+upload-heading = Envoyer un fichier à { $directory }
+upload-deadline = Les fichiers sont acceptés jusqu’au { $deadline } UTC.
+upload-name = Votre nom
+upload-email = Votre e-mail (facultatif)
+upload-file = Fichier
+upload-submit = Envoyer
+upload-received = { $name } a bien été reçu. Merci !
+upload-another = Envoyer un autre fichier
+upload-link-not-found = Lien de dépôt introuvable
+upload-deadline-passed = La date limite de ce lien de dépôt est passée
+upload-sender-required = Votre nom doit être indiqué avant le fichier
+upload-invalid-email = L’adresse e-mail n’est pas valide
+upload-no-file = Aucun fichier n’a été envoyé
+upload-failed = Le fichier n’a pas pu être enregistré
+share-report-received = Le signalement a été transmis aux modérateurs. Merci !
+share-report-invalid-reason = Le motif doit être l’un des suivants : { $reasons }
+
+## Notifications
+
+new-file-subject = Nouveau fichier dans { $directory }
+new-file-message = { $name } a été ajouté à { $directory }
+share-access-subject = { $name } a été téléchargé via un partage
+share-access-message = { $name } a été téléchargé via un lien de partage depuis le réseau { $network }.
+job-finished-subject = Tâche terminée
+quota-exceeded-subject = Quota de stockage dépassé
+quota-exceeded-message = Le stockage dépasse son quota souple : { $used } octets utilisés sur { $limit }. Les envois seront refusés après { $grace_ends_at } si aucun fichier n’est supprimé.
+digest-subject =
+    { $count ->
+        [one] 1 nouvelle notification
+       *[other] { $count } nouvelles notifications
+    }
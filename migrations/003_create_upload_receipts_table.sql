@@ -0,0 +1,10 @@
+-- Signed integrity receipts issued for each upload
+CREATE TABLE IF NOT EXISTS upload_receipts (
+    file_id TEXT PRIMARY KEY,
+    sha256 TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    received_at TEXT NOT NULL,
+    scan_status TEXT NOT NULL,
+    signature_algorithm TEXT NOT NULL,
+    signature TEXT NOT NULL
+);
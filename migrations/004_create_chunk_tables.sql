@@ -0,0 +1,18 @@
+-- Content-defined chunks shared between files (chunk-level deduplication)
+CREATE TABLE IF NOT EXISTS chunks (
+    hash TEXT PRIMARY KEY,
+    size INTEGER NOT NULL,
+    ref_count INTEGER NOT NULL
+);
+
+-- Ordered chunk map for each chunked file
+CREATE TABLE IF NOT EXISTS file_chunks (
+    file_id TEXT NOT NULL,
+    seq INTEGER NOT NULL,
+    chunk_hash TEXT NOT NULL,
+    chunk_offset INTEGER NOT NULL,
+    length INTEGER NOT NULL,
+    PRIMARY KEY (file_id, seq)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_chunks_hash ON file_chunks(chunk_hash);
@@ -0,0 +1,8 @@
+-- "Keep the last N files matching a pattern" policies, applied by the scheduler
+CREATE TABLE IF NOT EXISTS retention_policies (
+    id TEXT PRIMARY KEY,
+    directory_id TEXT,
+    pattern TEXT NOT NULL,
+    keep_last INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
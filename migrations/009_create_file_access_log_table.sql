@@ -0,0 +1,11 @@
+-- Per-download access history shown to file owners; pruned by the scheduler
+CREATE TABLE IF NOT EXISTS file_access_log (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    file_id TEXT NOT NULL,
+    accessed_at TEXT NOT NULL,
+    share_id TEXT,
+    client_ip TEXT,
+    user_agent TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_access_log_file ON file_access_log(file_id, accessed_at);
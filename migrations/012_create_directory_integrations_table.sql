@@ -0,0 +1,12 @@
+-- Chat integrations that announce new files in a directory
+CREATE TABLE IF NOT EXISTS directory_integrations (
+    id TEXT PRIMARY KEY,
+    directory_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    url TEXT NOT NULL,
+    room_id TEXT,
+    access_token TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_directory_integrations_directory ON directory_integrations(directory_id);
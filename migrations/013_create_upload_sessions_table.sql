@@ -0,0 +1,21 @@
+-- Resumable chunked uploads, with the chunk size and parallelism negotiated
+-- for the client's network when the session was created
+CREATE TABLE IF NOT EXISTS upload_sessions (
+    id TEXT PRIMARY KEY,
+    filename TEXT NOT NULL,
+    file_size INTEGER NOT NULL,
+    mime_type TEXT,
+    description TEXT,
+    parent_directory_id TEXT,
+    network_profile TEXT NOT NULL,
+    chunk_size INTEGER NOT NULL,
+    parallelism INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS upload_session_chunks (
+    session_id TEXT NOT NULL,
+    chunk_index INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    PRIMARY KEY (session_id, chunk_index)
+);
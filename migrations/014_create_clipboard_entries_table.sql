@@ -0,0 +1,10 @@
+-- Short-lived text snippets shared between a user's devices, keyed by
+-- `user:<id>` or `token:<lan token>`
+CREATE TABLE IF NOT EXISTS clipboard_entries (
+    owner TEXT PRIMARY KEY,
+    text TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_clipboard_entries_expires_at ON clipboard_entries(expires_at);
@@ -0,0 +1,17 @@
+-- Properties, tags and waveform peaks read from uploaded audio files
+CREATE TABLE IF NOT EXISTS audio_metadata (
+    file_id TEXT PRIMARY KEY,
+    duration_secs REAL NOT NULL,
+    bitrate INTEGER,
+    sample_rate INTEGER,
+    channels INTEGER,
+    codec TEXT,
+    title TEXT,
+    artist TEXT,
+    album TEXT,
+    genre TEXT,
+    year TEXT,
+    track_number TEXT,
+    peaks BLOB NOT NULL,
+    analyzed_at TEXT NOT NULL
+);
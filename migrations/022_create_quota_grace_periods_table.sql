@@ -0,0 +1,6 @@
+-- When storage went over its soft quota, starting the grace period during
+-- which uploads are still accepted
+CREATE TABLE IF NOT EXISTS quota_grace_periods (
+    scope TEXT PRIMARY KEY,
+    started_at TEXT NOT NULL
+);
@@ -0,0 +1,11 @@
+-- Public, read-only indexes of a directory tree, opened at /s/<token>
+-- without authentication
+CREATE TABLE IF NOT EXISTS directory_shares (
+    id TEXT PRIMARY KEY,
+    token TEXT NOT NULL UNIQUE,
+    directory_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_directory_shares_directory_id ON directory_shares(directory_id);
@@ -0,0 +1,49 @@
+-- Trigram full-text index over file names and descriptions, so searches
+-- for any part of them don't scan the files table. The index reads its
+-- text from files_search_entries, which has the stable integer keys FTS5
+-- needs; triggers keep both in step with the files table.
+CREATE TABLE IF NOT EXISTS files_search_entries (
+    id INTEGER PRIMARY KEY,
+    file_id TEXT NOT NULL UNIQUE,
+    original_filename TEXT NOT NULL,
+    description TEXT NOT NULL
+);
+
+CREATE VIRTUAL TABLE IF NOT EXISTS files_search USING fts5(
+    original_filename,
+    description,
+    content = 'files_search_entries',
+    content_rowid = 'id',
+    tokenize = 'trigram'
+);
+
+CREATE TRIGGER IF NOT EXISTS files_search_entries_insert
+AFTER INSERT ON files_search_entries BEGIN
+    INSERT INTO files_search (rowid, original_filename, description)
+    VALUES (new.id, new.original_filename, new.description);
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_search_entries_delete
+AFTER DELETE ON files_search_entries BEGIN
+    INSERT INTO files_search (files_search, rowid, original_filename, description)
+    VALUES ('delete', old.id, old.original_filename, old.description);
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_search_insert AFTER INSERT ON files BEGIN
+    INSERT INTO files_search_entries (file_id, original_filename, description)
+    VALUES (new.id, new.original_filename, COALESCE(new.description, ''));
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_search_delete AFTER DELETE ON files BEGIN
+    DELETE FROM files_search_entries WHERE file_id = old.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_search_update
+AFTER UPDATE OF id, original_filename, description ON files BEGIN
+    DELETE FROM files_search_entries WHERE file_id = old.id;
+    INSERT INTO files_search_entries (file_id, original_filename, description)
+    VALUES (new.id, new.original_filename, COALESCE(new.description, ''));
+END;
+
+INSERT INTO files_search_entries (file_id, original_filename, description)
+SELECT id, original_filename, COALESCE(description, '') FROM files;
@@ -0,0 +1,24 @@
+-- Files flagged by virus scanning, DLP or moderation, hidden from everyone
+-- but admins until they are released or purged
+CREATE TABLE IF NOT EXISTS quarantined_files (
+    file_id TEXT PRIMARY KEY,
+    source TEXT NOT NULL,
+    reason TEXT,
+    quarantined_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_quarantined_files_quarantined_at ON quarantined_files(quarantined_at);
+
+-- Audit trail of quarantine decisions, kept after the file is purged
+CREATE TABLE IF NOT EXISTS quarantine_decisions (
+    id TEXT PRIMARY KEY,
+    file_id TEXT NOT NULL,
+    original_filename TEXT NOT NULL,
+    action TEXT NOT NULL,
+    actor TEXT,
+    reason TEXT,
+    decided_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_quarantine_decisions_file_id ON quarantine_decisions(file_id);
+CREATE INDEX IF NOT EXISTS idx_quarantine_decisions_decided_at ON quarantine_decisions(decided_at);
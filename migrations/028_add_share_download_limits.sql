@@ -0,0 +1,4 @@
+-- How many times a share's file may be downloaded before the link stops
+-- working (unlimited when NULL), and how many times it has been
+ALTER TABLE shares ADD COLUMN max_downloads INTEGER;
+ALTER TABLE shares ADD COLUMN download_count INTEGER NOT NULL DEFAULT 0;
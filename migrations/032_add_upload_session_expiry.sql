@@ -0,0 +1,5 @@
+-- When an upload session is abandoned: pushed back by each chunk received.
+-- Sessions from before this column expire by their creation time.
+ALTER TABLE upload_sessions ADD COLUMN expires_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_upload_sessions_expires_at ON upload_sessions(expires_at);
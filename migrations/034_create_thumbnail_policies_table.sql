@@ -0,0 +1,11 @@
+-- Directories whose image uploads get thumbnails rendered right away. The
+-- sizes are a comma-separated list of size names.
+CREATE TABLE IF NOT EXISTS thumbnail_policies (
+    id TEXT PRIMARY KEY,
+    directory_id TEXT,
+    recursive INTEGER NOT NULL DEFAULT 0,
+    sizes TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_thumbnail_policies_directory_id ON thumbnail_policies(directory_id);
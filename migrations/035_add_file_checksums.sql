@@ -0,0 +1,8 @@
+-- SHA-256 of each file's content, hashed while it was uploaded. Files
+-- uploaded before this column take it from their upload receipt; others,
+-- and files registered in place, have none.
+ALTER TABLE files ADD COLUMN sha256 TEXT;
+
+UPDATE files SET sha256 = (
+    SELECT r.sha256 FROM upload_receipts r WHERE r.file_id = files.id
+);
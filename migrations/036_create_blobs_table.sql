@@ -0,0 +1,9 @@
+-- Stored files shared by every file with the same content (whole-file
+-- deduplication); a blob is removed with the last file referencing it
+CREATE TABLE IF NOT EXISTS blobs (
+    sha256 TEXT PRIMARY KEY,
+    storage_path TEXT NOT NULL UNIQUE,
+    file_size INTEGER NOT NULL,
+    ref_count INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
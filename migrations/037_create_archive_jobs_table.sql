@@ -0,0 +1,15 @@
+-- Directory archives built in the background, downloaded through a ticket
+-- until they expire
+CREATE TABLE IF NOT EXISTS archive_jobs (
+    id TEXT PRIMARY KEY,
+    directory_id TEXT NOT NULL,
+    user_id TEXT,
+    status TEXT NOT NULL,
+    ticket TEXT NOT NULL UNIQUE,
+    file_name TEXT NOT NULL,
+    file_size INTEGER,
+    error TEXT,
+    created_at TEXT NOT NULL,
+    finished_at TEXT,
+    expires_at TEXT NOT NULL
+);
@@ -0,0 +1,9 @@
+-- SHA-256 of each segment of stored content, by content hash and segment
+-- size, for the parallel download manifest
+CREATE TABLE IF NOT EXISTS segment_checksums (
+    sha256 TEXT NOT NULL,
+    segment_size INTEGER NOT NULL,
+    checksums TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (sha256, segment_size)
+);
@@ -0,0 +1,11 @@
+-- Deleted files and directories go to the trash until they are restored or
+-- purged. `trashed` hides an item, including everything inside a trashed
+-- directory; `deleted_at` is set on the items that were deleted themselves,
+-- which are the ones the trash lists.
+ALTER TABLE files ADD COLUMN trashed INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN deleted_at TEXT;
+ALTER TABLE directories ADD COLUMN trashed INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE directories ADD COLUMN deleted_at TEXT;
+
+CREATE INDEX idx_files_deleted_at ON files(deleted_at);
+CREATE INDEX idx_directories_deleted_at ON directories(deleted_at);
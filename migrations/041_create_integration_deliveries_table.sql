@@ -0,0 +1,15 @@
+-- Messages sent to directory integrations, kept for a while so failed ones
+-- can be sent again. delivered_at is unset until a delivery succeeds.
+CREATE TABLE IF NOT EXISTS integration_deliveries (
+    id TEXT PRIMARY KEY,
+    integration_id TEXT NOT NULL,
+    event_id TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 1,
+    error TEXT,
+    created_at TEXT NOT NULL,
+    delivered_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_integration_deliveries_integration ON integration_deliveries(integration_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_integration_deliveries_created ON integration_deliveries(created_at);
@@ -0,0 +1,110 @@
+-- Every change to the metadata of files and directories, written by
+-- triggers in the same transaction as the change itself, so
+-- `fileshare_rust restore-to` can put the metadata back as it was at an
+-- earlier time. `old_row` holds the restorable columns before the change
+-- and is NULL when the change created the row; `tag` is set on entries for
+-- file_tags. `recorded_at` is always UTC with milliseconds, so entries
+-- compare in time order as text.
+CREATE TABLE IF NOT EXISTS metadata_journal (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    recorded_at TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    row_id TEXT NOT NULL,
+    tag TEXT,
+    operation TEXT NOT NULL,
+    old_row TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_metadata_journal_recorded_at ON metadata_journal(recorded_at);
+
+CREATE TRIGGER IF NOT EXISTS files_journal_insert AFTER INSERT ON files BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'files', new.id, 'insert');
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_journal_update
+AFTER UPDATE OF original_filename, description, parent_directory_id, storage_path, sha256,
+    trashed, deleted_at ON files BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'files', old.id, 'update', json_object(
+        'original_filename', old.original_filename,
+        'description', old.description,
+        'parent_directory_id', old.parent_directory_id,
+        'storage_path', old.storage_path,
+        'sha256', old.sha256,
+        'trashed', old.trashed,
+        'deleted_at', old.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS files_journal_delete AFTER DELETE ON files BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'files', old.id, 'delete', json_object(
+        'original_filename', old.original_filename,
+        'description', old.description,
+        'parent_directory_id', old.parent_directory_id,
+        'storage_path', old.storage_path,
+        'sha256', old.sha256,
+        'trashed', old.trashed,
+        'deleted_at', old.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS directories_journal_insert AFTER INSERT ON directories BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'directories', new.id, 'insert');
+END;
+
+CREATE TRIGGER IF NOT EXISTS directories_journal_update
+AFTER UPDATE OF name, parent_id, color, icon, trashed, deleted_at ON directories BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'directories', old.id, 'update', json_object(
+        'name', old.name,
+        'parent_id', old.parent_id,
+        'created_at', old.created_at,
+        'color', old.color,
+        'icon', old.icon,
+        'trashed', old.trashed,
+        'deleted_at', old.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS directories_journal_delete AFTER DELETE ON directories BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'directories', old.id, 'delete', json_object(
+        'name', old.name,
+        'parent_id', old.parent_id,
+        'created_at', old.created_at,
+        'color', old.color,
+        'icon', old.icon,
+        'trashed', old.trashed,
+        'deleted_at', old.deleted_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_tags_journal_insert AFTER INSERT ON file_tags BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, tag, operation)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'file_tags', new.file_id, new.tag, 'insert');
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_tags_journal_delete AFTER DELETE ON file_tags BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, tag, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'file_tags', old.file_id, old.tag, 'delete',
+        json_object('tag', old.tag));
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_expirations_journal_insert
+AFTER INSERT ON file_expirations BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'file_expirations', new.file_id, 'insert');
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_expirations_journal_update
+AFTER UPDATE OF expires_at ON file_expirations BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'file_expirations', old.file_id, 'update',
+        json_object('expires_at', old.expires_at));
+END;
+
+CREATE TRIGGER IF NOT EXISTS file_expirations_journal_delete
+AFTER DELETE ON file_expirations BEGIN
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, operation, old_row)
+    VALUES (strftime('%Y-%m-%dT%H:%M:%fZ', 'now'), 'file_expirations', old.file_id, 'delete',
+        json_object('expires_at', old.expires_at));
+END;
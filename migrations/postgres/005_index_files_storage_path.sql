@@ -0,0 +1,2 @@
+-- Look up files by their on-disk location (used by the directory importer)
+CREATE INDEX IF NOT EXISTS idx_files_storage_path ON files(storage_path);
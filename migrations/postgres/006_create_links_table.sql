@@ -0,0 +1,12 @@
+-- Link entries pointing at an external URL or another file
+CREATE TABLE IF NOT EXISTS links (
+    id TEXT PRIMARY KEY,
+    name TEXT NOT NULL,
+    target_url TEXT,
+    target_file_id TEXT,
+    parent_directory_id TEXT,
+    description TEXT,
+    created_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_links_parent_directory ON links(parent_directory_id);
@@ -0,0 +1,6 @@
+-- Download counters used by the admin cleanup reports
+CREATE TABLE IF NOT EXISTS file_access_stats (
+    file_id TEXT PRIMARY KEY,
+    download_count BIGINT NOT NULL DEFAULT 0,
+    last_downloaded_at TEXT
+);
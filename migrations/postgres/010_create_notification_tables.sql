@@ -0,0 +1,27 @@
+-- Per-user notification settings
+CREATE TABLE IF NOT EXISTS notification_preferences (
+    user_id TEXT PRIMARY KEY,
+    email TEXT,
+    on_share_access BIGINT NOT NULL DEFAULT 0,
+    on_new_files BIGINT NOT NULL DEFAULT 1,
+    delivery TEXT NOT NULL DEFAULT 'immediate',
+    updated_at TEXT NOT NULL
+);
+
+-- Folders a user wants to hear about
+CREATE TABLE IF NOT EXISTS watched_folders (
+    user_id TEXT NOT NULL,
+    directory_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (user_id, directory_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_watched_folders_directory ON watched_folders(directory_id);
+
+-- Notifications held back for the user's next digest
+CREATE TABLE IF NOT EXISTS pending_notifications (
+    id BIGSERIAL PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    message TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
@@ -0,0 +1,10 @@
+-- Public links to a single file, opened at /s/<token> without authentication
+CREATE TABLE IF NOT EXISTS shares (
+    id TEXT PRIMARY KEY,
+    token TEXT NOT NULL UNIQUE,
+    file_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_shares_file_id ON shares(file_id);
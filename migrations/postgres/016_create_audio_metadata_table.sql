@@ -0,0 +1,17 @@
+-- Properties, tags and waveform peaks read from uploaded audio files
+CREATE TABLE IF NOT EXISTS audio_metadata (
+    file_id TEXT PRIMARY KEY,
+    duration_secs DOUBLE PRECISION NOT NULL,
+    bitrate BIGINT,
+    sample_rate BIGINT,
+    channels BIGINT,
+    codec TEXT,
+    title TEXT,
+    artist TEXT,
+    album TEXT,
+    genre TEXT,
+    year TEXT,
+    track_number TEXT,
+    peaks BYTEA NOT NULL,
+    analyzed_at TEXT NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Related files attached to a video or audio file: subtitle tracks, posters
+CREATE TABLE IF NOT EXISTS file_attachments (
+    id TEXT PRIMARY KEY,
+    file_id TEXT NOT NULL,
+    attachment_id TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    language TEXT,
+    label TEXT,
+    created_at TEXT NOT NULL,
+    UNIQUE (file_id, attachment_id, kind)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_attachments_file_id ON file_attachments(file_id);
+CREATE INDEX IF NOT EXISTS idx_file_attachments_attachment_id ON file_attachments(attachment_id);
@@ -0,0 +1,15 @@
+-- A directory's curated arrangement: preferred sort and pinned entries
+CREATE TABLE IF NOT EXISTS directory_arrangements (
+    directory_id TEXT PRIMARY KEY,
+    sort TEXT,
+    updated_at TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS directory_pins (
+    directory_id TEXT NOT NULL,
+    entry_id TEXT NOT NULL,
+    position BIGINT NOT NULL,
+    PRIMARY KEY (directory_id, entry_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_directory_pins_entry_id ON directory_pins(entry_id);
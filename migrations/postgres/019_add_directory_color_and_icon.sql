@@ -0,0 +1,3 @@
+-- Optional color label and icon clients show for a directory
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS color TEXT;
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS icon TEXT;
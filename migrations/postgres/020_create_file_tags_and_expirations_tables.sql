@@ -0,0 +1,16 @@
+-- Free-form tags on files
+CREATE TABLE IF NOT EXISTS file_tags (
+    file_id TEXT NOT NULL,
+    tag TEXT NOT NULL,
+    PRIMARY KEY (file_id, tag)
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_tags_tag ON file_tags(tag);
+
+-- When a file is deleted automatically by the scheduler
+CREATE TABLE IF NOT EXISTS file_expirations (
+    file_id TEXT PRIMARY KEY,
+    expires_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_expirations_expires_at ON file_expirations(expires_at);
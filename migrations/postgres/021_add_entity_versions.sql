@@ -0,0 +1,4 @@
+-- Version of each file and directory, raised by every change so clients can
+-- send it back in If-Match and avoid overwriting each other's edits
+ALTER TABLE files ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS version BIGINT NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- Terms a recipient must accept before a share's file is served, and which
+-- version of them was accepted for each download
+ALTER TABLE shares ADD COLUMN IF NOT EXISTS terms TEXT;
+ALTER TABLE file_access_log ADD COLUMN IF NOT EXISTS accepted_terms TEXT;
@@ -0,0 +1,23 @@
+-- Guest upload links into a directory, opened at /u/<token> without
+-- authentication, and who sent each file through one
+CREATE TABLE IF NOT EXISTS upload_links (
+    id TEXT PRIMARY KEY,
+    token TEXT NOT NULL UNIQUE,
+    directory_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    deadline TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_upload_links_directory_id ON upload_links(directory_id);
+
+CREATE TABLE IF NOT EXISTS upload_submissions (
+    id TEXT PRIMARY KEY,
+    upload_link_id TEXT NOT NULL,
+    file_id TEXT NOT NULL,
+    sender_name TEXT NOT NULL,
+    sender_email TEXT,
+    submitted_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_upload_submissions_upload_link_id ON upload_submissions(upload_link_id);
+CREATE INDEX IF NOT EXISTS idx_upload_submissions_file_id ON upload_submissions(file_id);
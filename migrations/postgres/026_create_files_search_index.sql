@@ -0,0 +1,8 @@
+-- Trigram indexes over file names and descriptions, so searches for any
+-- part of them (LOWER(...) LIKE '%part%') don't scan the files table.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS idx_files_original_filename_trgm
+    ON files USING GIN (LOWER(original_filename) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_files_description_trgm
+    ON files USING GIN (LOWER(description) gin_trgm_ops);
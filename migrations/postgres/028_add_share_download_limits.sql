@@ -0,0 +1,4 @@
+-- How many times a share's file may be downloaded before the link stops
+-- working (unlimited when NULL), and how many times it has been
+ALTER TABLE shares ADD COLUMN IF NOT EXISTS max_downloads BIGINT;
+ALTER TABLE shares ADD COLUMN IF NOT EXISTS download_count BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,11 @@
+-- Upload-only links into a directory, posted to at /drop/<token> without
+-- authentication; they grant neither listing nor downloading
+CREATE TABLE IF NOT EXISTS drop_links (
+    id TEXT PRIMARY KEY,
+    token TEXT NOT NULL UNIQUE,
+    directory_id TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    expires_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_drop_links_directory_id ON drop_links(directory_id);
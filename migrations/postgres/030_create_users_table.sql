@@ -0,0 +1,13 @@
+-- Accounts that log in with a password and receive a JWT; usernames are
+-- stored lowercased
+CREATE TABLE IF NOT EXISTS users (
+    id TEXT PRIMARY KEY,
+    username TEXT NOT NULL UNIQUE,
+    password_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+-- The user who uploaded a file, when the upload was authenticated
+ALTER TABLE files ADD COLUMN IF NOT EXISTS owner_id TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_files_owner_id ON files(owner_id);
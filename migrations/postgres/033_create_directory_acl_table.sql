@@ -0,0 +1,12 @@
+-- Per-directory access control: the role each user has in a directory and
+-- everything under it. Directories without entries on their path are open
+-- to everyone.
+CREATE TABLE IF NOT EXISTS directory_acl (
+    directory_id TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    role TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (directory_id, user_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_directory_acl_user_id ON directory_acl(user_id);
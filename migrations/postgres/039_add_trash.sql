@@ -0,0 +1,11 @@
+-- Deleted files and directories go to the trash until they are restored or
+-- purged. `trashed` hides an item, including everything inside a trashed
+-- directory; `deleted_at` is set on the items that were deleted themselves,
+-- which are the ones the trash lists.
+ALTER TABLE files ADD COLUMN IF NOT EXISTS trashed BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE files ADD COLUMN IF NOT EXISTS deleted_at TEXT;
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS trashed BIGINT NOT NULL DEFAULT 0;
+ALTER TABLE directories ADD COLUMN IF NOT EXISTS deleted_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_files_deleted_at ON files(deleted_at);
+CREATE INDEX IF NOT EXISTS idx_directories_deleted_at ON directories(deleted_at);
@@ -0,0 +1,15 @@
+-- Earlier content of files that had a new version uploaded. A version's
+-- chunks, if it was stored as chunks, are kept under its own ID.
+CREATE TABLE IF NOT EXISTS file_versions (
+    id TEXT PRIMARY KEY,
+    file_id TEXT NOT NULL,
+    filename TEXT NOT NULL,
+    file_size BIGINT NOT NULL,
+    mime_type TEXT,
+    storage_path TEXT NOT NULL,
+    sha256 TEXT,
+    uploaded_at TEXT NOT NULL,
+    replaced_at TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_versions_file_id ON file_versions(file_id, replaced_at);
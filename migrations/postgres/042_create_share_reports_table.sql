@@ -0,0 +1,22 @@
+-- Reports of abusive content from recipients of public share links, the
+-- moderation queue admins work through. reporter_network is the reporter's
+-- truncated address, so each network counts once towards taking a share
+-- down.
+CREATE TABLE IF NOT EXISTS share_reports (
+    id TEXT PRIMARY KEY,
+    share_id TEXT NOT NULL,
+    share_kind TEXT NOT NULL,
+    target_id TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    details TEXT,
+    reporter_network TEXT NOT NULL,
+    reported_at TEXT NOT NULL,
+    resolved_at TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_share_reports_share_id ON share_reports(share_id, resolved_at);
+CREATE INDEX IF NOT EXISTS idx_share_reports_reported_at ON share_reports(reported_at);
+
+-- Set when enough reports took a share down, until an admin dismisses them
+ALTER TABLE shares ADD COLUMN IF NOT EXISTS disabled_at TEXT;
+ALTER TABLE directory_shares ADD COLUMN IF NOT EXISTS disabled_at TEXT;
@@ -0,0 +1,20 @@
+-- Public links to a hand-picked set of files, from any directories, opened
+-- at /s/<token> as a list of the files with a download of all of them.
+CREATE TABLE IF NOT EXISTS bundle_shares (
+    id TEXT PRIMARY KEY,
+    token TEXT NOT NULL UNIQUE,
+    title TEXT,
+    created_at TEXT NOT NULL,
+    expires_at TEXT,
+    disabled_at TEXT
+);
+
+-- The files of a bundle, in the order they were given
+CREATE TABLE IF NOT EXISTS bundle_share_files (
+    bundle_id TEXT NOT NULL,
+    file_id TEXT NOT NULL,
+    position BIGINT NOT NULL,
+    PRIMARY KEY (bundle_id, file_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_bundle_share_files_file_id ON bundle_share_files(file_id);
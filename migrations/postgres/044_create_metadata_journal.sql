@@ -0,0 +1,99 @@
+-- Every change to the metadata of files and directories, written by
+-- triggers in the same transaction as the change itself, so
+-- `fileshare_rust restore-to` can put the metadata back as it was at an
+-- earlier time. `old_row` holds the restorable columns before the change
+-- and is NULL when the change created the row; `tag` is set on entries for
+-- file_tags. `recorded_at` is always UTC with milliseconds, so entries
+-- compare in time order as text.
+CREATE TABLE IF NOT EXISTS metadata_journal (
+    id BIGSERIAL PRIMARY KEY,
+    recorded_at TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    row_id TEXT NOT NULL,
+    tag TEXT,
+    operation TEXT NOT NULL,
+    old_row TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_metadata_journal_recorded_at ON metadata_journal(recorded_at);
+
+CREATE OR REPLACE FUNCTION metadata_journal_record() RETURNS trigger AS $$
+DECLARE
+    entry_row_id TEXT;
+    entry_tag TEXT;
+    entry_old_row TEXT;
+BEGIN
+    IF TG_TABLE_NAME = 'files' THEN
+        IF TG_OP = 'INSERT' THEN
+            entry_row_id := NEW.id;
+        ELSE
+            entry_row_id := OLD.id;
+            entry_old_row := json_build_object(
+                'original_filename', OLD.original_filename,
+                'description', OLD.description,
+                'parent_directory_id', OLD.parent_directory_id,
+                'storage_path', OLD.storage_path,
+                'sha256', OLD.sha256,
+                'trashed', OLD.trashed,
+                'deleted_at', OLD.deleted_at)::TEXT;
+        END IF;
+    ELSIF TG_TABLE_NAME = 'directories' THEN
+        IF TG_OP = 'INSERT' THEN
+            entry_row_id := NEW.id;
+        ELSE
+            entry_row_id := OLD.id;
+            entry_old_row := json_build_object(
+                'name', OLD.name,
+                'parent_id', OLD.parent_id,
+                'created_at', OLD.created_at,
+                'color', OLD.color,
+                'icon', OLD.icon,
+                'trashed', OLD.trashed,
+                'deleted_at', OLD.deleted_at)::TEXT;
+        END IF;
+    ELSIF TG_TABLE_NAME = 'file_tags' THEN
+        IF TG_OP = 'INSERT' THEN
+            entry_row_id := NEW.file_id;
+            entry_tag := NEW.tag;
+        ELSE
+            entry_row_id := OLD.file_id;
+            entry_tag := OLD.tag;
+            entry_old_row := json_build_object('tag', OLD.tag)::TEXT;
+        END IF;
+    ELSE
+        IF TG_OP = 'INSERT' THEN
+            entry_row_id := NEW.file_id;
+        ELSE
+            entry_row_id := OLD.file_id;
+            entry_old_row := json_build_object('expires_at', OLD.expires_at)::TEXT;
+        END IF;
+    END IF;
+    INSERT INTO metadata_journal (recorded_at, table_name, row_id, tag, operation, old_row)
+    VALUES (
+        to_char(clock_timestamp() AT TIME ZONE 'UTC', 'YYYY-MM-DD"T"HH24:MI:SS.MS"Z"'),
+        TG_TABLE_NAME, entry_row_id, entry_tag, lower(TG_OP), entry_old_row);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DROP TRIGGER IF EXISTS files_journal ON files;
+CREATE TRIGGER files_journal
+AFTER INSERT OR DELETE OR UPDATE OF original_filename, description, parent_directory_id,
+    storage_path, sha256, trashed, deleted_at ON files
+FOR EACH ROW EXECUTE FUNCTION metadata_journal_record();
+
+DROP TRIGGER IF EXISTS directories_journal ON directories;
+CREATE TRIGGER directories_journal
+AFTER INSERT OR DELETE OR UPDATE OF name, parent_id, color, icon, trashed, deleted_at
+ON directories
+FOR EACH ROW EXECUTE FUNCTION metadata_journal_record();
+
+DROP TRIGGER IF EXISTS file_tags_journal ON file_tags;
+CREATE TRIGGER file_tags_journal
+AFTER INSERT OR DELETE ON file_tags
+FOR EACH ROW EXECUTE FUNCTION metadata_journal_record();
+
+DROP TRIGGER IF EXISTS file_expirations_journal ON file_expirations;
+CREATE TRIGGER file_expirations_journal
+AFTER INSERT OR DELETE OR UPDATE OF expires_at ON file_expirations
+FOR EACH ROW EXECUTE FUNCTION metadata_journal_record();
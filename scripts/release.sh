@@ -0,0 +1,31 @@
+#!/usr/bin/env bash
+# Builds the release artifacts: the server binary, the OpenAPI document and
+# the Python client generated from it. Everything ends up in dist/.
+#
+# Requires `openapi-python-client` and `build` (pip install openapi-python-client build).
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+DIST=dist
+CLIENT_DIR=clients/python/fileshare-client
+
+mkdir -p "$DIST"
+
+echo "==> Building server"
+cargo build --release --workspace
+cp target/release/fileshare_rust "$DIST/"
+
+echo "==> Generating OpenAPI document"
+./target/release/fileshare_rust generate-openapi "$DIST/openapi.json"
+
+echo "==> Generating Python client"
+openapi-python-client generate \
+    --path "$DIST/openapi.json" \
+    --config clients/python/config.yaml \
+    --output-path "$CLIENT_DIR" \
+    --overwrite
+
+echo "==> Building Python client"
+python -m build "$CLIENT_DIR" --outdir "$DIST"
+
+echo "Release artifacts written to $DIST/"
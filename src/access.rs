@@ -0,0 +1,56 @@
+use axum::http::{header, HeaderMap};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, SocketAddr};
+
+/// Longest user agent kept in the access log.
+const MAX_USER_AGENT_LEN: usize = 256;
+
+/// Who fetched a file, reduced to what the access log is allowed to keep.
+#[derive(Debug, Clone, Default)]
+pub struct AccessContext {
+    /// Client address with the host part zeroed (see [`truncate_ip`]).
+    pub client_ip: Option<String>,
+    pub user_agent: Option<String>,
+    /// Share the file was reached through, if any.
+    pub share_id: Option<String>,
+    /// Digest of the share terms accepted before the download.
+    pub accepted_terms: Option<String>,
+}
+
+impl AccessContext {
+    pub fn from_request(addr: SocketAddr, headers: &HeaderMap) -> Self {
+        let user_agent = headers
+            .get(header::USER_AGENT)
+            .and_then(|v| v.to_str().ok())
+            .map(|ua| ua.chars().take(MAX_USER_AGENT_LEN).collect());
+        Self {
+            client_ip: Some(truncate_ip(addr.ip())),
+            user_agent,
+            share_id: None,
+            accepted_terms: None,
+        }
+    }
+}
+
+/// Drops the host part of an address so it identifies a network rather
+/// than a person: IPv4 is cut to /24 and IPv6 to /48.
+pub fn truncate_ip(ip: IpAddr) -> String {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{}.{}.{}.0", a, b, c)
+        }
+        IpAddr::V6(v6) => {
+            let s = v6.segments();
+            format!("{:x}:{:x}:{:x}::", s[0], s[1], s[2])
+        }
+    }
+}
+
+/// Short digest identifying one version of a share's terms, so the access
+/// log records what was accepted even after the terms are edited.
+pub fn terms_digest(terms: &str) -> String {
+    let mut digest = hex::encode(Sha256::digest(terms.as_bytes()));
+    digest.truncate(16);
+    digest
+}
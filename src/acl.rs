@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+
+/// What a user may do in a directory. Each role includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Role {
+    /// List, download and watch.
+    Read,
+    /// Also upload, create, rename, move and delete files and folders.
+    Write,
+    /// Also delete or move the directory itself, share it and manage its
+    /// access list.
+    Owner,
+}
+
+impl Role {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "read" => Some(Role::Read),
+            "write" => Some(Role::Write),
+            "owner" => Some(Role::Owner),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::Read => "read",
+            Role::Write => "write",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AclError {
+    #[error("Log in to access this directory")]
+    LoginRequired,
+    #[error("You need {} access to this directory", .0.as_str())]
+    Forbidden(Role),
+}
+
+/// The directory access lists as one request sees them. A directory with an
+/// entry on itself or any ancestor is restricted to the users listed there,
+/// each with the highest role granted along the way; every other directory,
+/// and the root, stays open to everyone.
+#[derive(Debug, Clone, Default)]
+pub struct Access {
+    user_id: Option<String>,
+    parents: HashMap<String, Option<String>>,
+    /// Directory ID to the role of each user listed on it.
+    entries: HashMap<String, HashMap<String, Role>>,
+}
+
+impl Access {
+    pub fn new(
+        user_id: Option<String>,
+        parents: HashMap<String, Option<String>>,
+        entries: HashMap<String, HashMap<String, Role>>,
+    ) -> Self {
+        Self {
+            user_id,
+            parents,
+            entries,
+        }
+    }
+
+    /// Whether `directory_id` or one of its ancestors has an access list.
+    pub fn is_restricted(&self, directory_id: Option<&str>) -> bool {
+        self.ancestry(directory_id)
+            .any(|id| self.entries.contains_key(id))
+    }
+
+    /// The user's role in `directory_id`, or `None` when they have no access
+    /// to it. Everyone owns unrestricted directories.
+    pub fn role(&self, directory_id: Option<&str>) -> Option<Role> {
+        self.role_of(self.user_id.as_deref(), directory_id)
+    }
+
+    pub fn allows(&self, directory_id: Option<&str>, role: Role) -> bool {
+        self.role(directory_id)
+            .is_some_and(|granted| granted >= role)
+    }
+
+    /// Like `allows`, for `user_id` rather than the user this was loaded
+    /// for, so one load serves checks for many users.
+    pub fn allows_user(&self, user_id: &str, directory_id: Option<&str>, role: Role) -> bool {
+        self.role_of(Some(user_id), directory_id)
+            .is_some_and(|granted| granted >= role)
+    }
+
+    fn role_of(&self, user_id: Option<&str>, directory_id: Option<&str>) -> Option<Role> {
+        if !self.is_restricted(directory_id) {
+            return Some(Role::Owner);
+        }
+        let user_id = user_id?;
+        self.ancestry(directory_id)
+            .filter_map(|id| self.entries.get(id)?.get(user_id).copied())
+            .max()
+    }
+
+    pub fn check(&self, directory_id: Option<&str>, role: Role) -> Result<(), AclError> {
+        if self.allows(directory_id, role) {
+            Ok(())
+        } else if self.user_id.is_none() {
+            Err(AclError::LoginRequired)
+        } else {
+            Err(AclError::Forbidden(role))
+        }
+    }
+
+    /// Like `check`, and also for every directory beneath `directory_id`
+    /// with an access list of its own, for operations that take the whole
+    /// subtree along: under an unrestricted directory, a restricted one
+    /// mustn't be deleted, moved or copied out by users it doesn't list.
+    pub fn check_subtree(&self, directory_id: &str, role: Role) -> Result<(), AclError> {
+        self.check(Some(directory_id), role)?;
+        for listed in self.entries.keys() {
+            if self
+                .ancestry(Some(listed))
+                .skip(1)
+                .any(|id| id == directory_id)
+            {
+                self.check(Some(listed), role)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// `directory_id` and its ancestors, nearest first. Stops at directories
+    /// it doesn't know, such as mirrored ones, and guards against cycles.
+    fn ancestry<'a>(&'a self, directory_id: Option<&'a str>) -> impl Iterator<Item = &'a str> {
+        let mut next = directory_id;
+        let mut remaining = self.parents.len() + 1;
+        std::iter::from_fn(move || {
+            let id = next.filter(|_| remaining > 0)?;
+            remaining -= 1;
+            next = self.parents.get(id).and_then(|parent| parent.as_deref());
+            Some(id)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `top` is unrestricted and holds `a`, which lists alice as a reader
+    /// and bob as owner; `a` holds `b`, where alice may also write, and `b`
+    /// holds `c`, which lists carol.
+    fn access(user_id: Option<&str>) -> Access {
+        let parents = [
+            ("top", None),
+            ("a", Some("top")),
+            ("b", Some("a")),
+            ("c", Some("b")),
+        ]
+        .into_iter()
+        .map(|(id, parent)| (id.to_string(), parent.map(str::to_string)))
+        .collect();
+        let entries = [
+            ("a", vec![("alice", Role::Read), ("bob", Role::Owner)]),
+            ("b", vec![("alice", Role::Write)]),
+            ("c", vec![("carol", Role::Read)]),
+        ]
+        .into_iter()
+        .map(|(id, users)| {
+            let users = users
+                .into_iter()
+                .map(|(user, role)| (user.to_string(), role))
+                .collect();
+            (id.to_string(), users)
+        })
+        .collect();
+        Access::new(user_id.map(str::to_string), parents, entries)
+    }
+
+    #[test]
+    fn unrestricted_directories_are_open_to_everyone() {
+        for user_id in [None, Some("alice"), Some("dave")] {
+            let access = access(user_id);
+            assert_eq!(access.role(None), Some(Role::Owner));
+            assert_eq!(access.role(Some("top")), Some(Role::Owner));
+            // Unknown directories, such as mirrored ones, too.
+            assert_eq!(access.role(Some("elsewhere")), Some(Role::Owner));
+        }
+    }
+
+    #[test]
+    fn roles_are_inherited_at_the_highest_granted_along_the_ancestry() {
+        let alice = access(Some("alice"));
+        assert_eq!(alice.role(Some("a")), Some(Role::Read));
+        assert_eq!(alice.role(Some("b")), Some(Role::Write));
+        assert_eq!(alice.role(Some("c")), Some(Role::Write));
+
+        let bob = access(Some("bob"));
+        assert_eq!(bob.role(Some("c")), Some(Role::Owner));
+
+        let carol = access(Some("carol"));
+        assert_eq!(carol.role(Some("b")), None);
+        assert_eq!(carol.role(Some("c")), Some(Role::Read));
+        assert!(carol.allows_user("alice", Some("b"), Role::Write));
+        assert!(!carol.allows_user("alice", Some("b"), Role::Owner));
+    }
+
+    #[test]
+    fn restricted_directories_refuse_anonymous_and_unlisted_users() {
+        let anonymous = access(None);
+        assert_eq!(anonymous.role(Some("a")), None);
+        assert!(matches!(
+            anonymous.check(Some("a"), Role::Read),
+            Err(AclError::LoginRequired)
+        ));
+
+        let dave = access(Some("dave"));
+        assert!(matches!(
+            dave.check(Some("c"), Role::Read),
+            Err(AclError::Forbidden(Role::Read))
+        ));
+        assert!(matches!(
+            access(Some("alice")).check(Some("a"), Role::Write),
+            Err(AclError::Forbidden(Role::Write))
+        ));
+    }
+
+    #[test]
+    fn subtree_checks_cover_restricted_directories_beneath() {
+        // Everyone may delete `top` itself, but not the lists beneath it.
+        let dave = access(Some("dave"));
+        assert!(dave.check(Some("top"), Role::Owner).is_ok());
+        assert!(dave.check_subtree("top", Role::Owner).is_err());
+
+        let bob = access(Some("bob"));
+        assert!(bob.check_subtree("top", Role::Owner).is_ok());
+        let alice = access(Some("alice"));
+        assert!(alice.check_subtree("b", Role::Write).is_ok());
+        assert!(alice.check_subtree("a", Role::Write).is_err());
+        // carol can read `c` but not `b` above it.
+        let carol = access(Some("carol"));
+        assert!(carol.check_subtree("c", Role::Read).is_ok());
+        assert!(carol.check_subtree("b", Role::Read).is_err());
+    }
+
+    #[test]
+    fn ancestry_stops_at_cycles() {
+        let parents = HashMap::from([
+            ("x".to_string(), Some("y".to_string())),
+            ("y".to_string(), Some("x".to_string())),
+        ]);
+        let entries = HashMap::from([(
+            "y".to_string(),
+            HashMap::from([("alice".to_string(), Role::Read)]),
+        )]);
+        let access = Access::new(Some("alice".to_string()), parents, entries);
+
+        assert_eq!(access.ancestry(Some("x")).count(), 3);
+        assert_eq!(access.role(Some("x")), Some(Role::Read));
+        assert!(access.check_subtree("x", Role::Read).is_ok());
+        assert!(access.check_subtree("x", Role::Write).is_err());
+    }
+}
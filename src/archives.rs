@@ -0,0 +1,115 @@
+use crate::events::ChangeEvent;
+use crate::models::ArchiveJob;
+use crate::storage::FileStorage;
+use crate::zip;
+use futures_util::StreamExt;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tracing::{error, info, warn};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Picks up archive jobs a restart interrupted, building them again from
+/// the start.
+pub fn spawn(storage: FileStorage) {
+    tokio::spawn(async move {
+        match storage.pending_archive_jobs().await {
+            Ok(jobs) => {
+                for job in jobs {
+                    info!(
+                        "Resuming archive of directory {} (job {})",
+                        job.directory_id, job.id
+                    );
+                    start(storage.clone(), job);
+                }
+            }
+            Err(e) => error!("Failed to load pending archive jobs: {}", e),
+        }
+    });
+}
+
+/// Builds a job's archive in the background and records the outcome,
+/// telling watchers of the directory once it is done.
+pub fn start(storage: FileStorage, job: ArchiveJob) {
+    tokio::spawn(async move {
+        let outcome = build(&storage, &job).await.map_err(|e| {
+            warn!("Failed to build archive {}: {}", job.id, e);
+            e.to_string()
+        });
+        let message = match &outcome {
+            Ok(_) => format!("Archive {} is ready to download", job.file_name),
+            Err(_) => format!("Archive {} could not be built", job.file_name),
+        };
+        match storage.finish_archive_job(&job.id, outcome).await {
+            Ok(true) => {}
+            // Cancelled while it was being built.
+            Ok(false) => {
+                let _ = fs::remove_file(storage.archive_path(&job.id)).await;
+                return;
+            }
+            Err(e) => {
+                error!("Failed to record archive job {}: {}", job.id, e);
+                return;
+            }
+        }
+        storage.events().publish(ChangeEvent::JobFinished {
+            job: "archive".to_string(),
+            parent_directory_id: Some(job.directory_id.clone()),
+            message,
+        });
+    });
+}
+
+/// Writes the ZIP archive of the job's directory, as the user who asked for
+/// it may read it now, to a `.part` file that takes its final name once
+/// complete, encrypted like stored files. Returns the archive's size.
+async fn build(storage: &FileStorage, job: &ArchiveJob) -> Result<i64, BoxError> {
+    let access = storage.access(job.user_id.as_deref()).await?;
+    let entries = storage.archive_entries(&job.directory_id, &access).await?;
+    let content_size: u64 = entries.iter().map(zip::ArchiveEntry::content_size).sum();
+    let headroom = storage.disk_headroom();
+    headroom.check(content_size)?;
+    info!(
+        "Building archive {} of {} entries ({} bytes)",
+        job.id,
+        entries.len(),
+        content_size
+    );
+
+    let path = storage.archive_path(&job.id);
+    let partial = path.with_extension("zip.part");
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).await?;
+    }
+    let mut file = fs::File::create(&partial).await?;
+    let mut encryptor = storage.encryptor();
+    let mut size: u64 = 0;
+    let written: Result<(), BoxError> = async {
+        let mut archive = zip::archive(storage.clone(), entries);
+        while let Some(chunk) = archive.next().await {
+            let chunk = chunk?;
+            size += chunk.len() as u64;
+            headroom.check(size)?;
+            match &mut encryptor {
+                Some(encryptor) => {
+                    for sealed in encryptor.update(&chunk)? {
+                        file.write_all(&sealed).await?;
+                    }
+                }
+                None => file.write_all(&chunk).await?,
+            }
+        }
+        if let Some(encryptor) = encryptor {
+            file.write_all(&encryptor.finish()?).await?;
+        }
+        file.sync_all().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = fs::remove_file(&partial).await;
+        return Err(e);
+    }
+    fs::rename(&partial, &path).await?;
+    Ok(size as i64)
+}
@@ -0,0 +1,347 @@
+use crate::events::ChangeEvent;
+use crate::models::{AudioMetadata, FileMetadata};
+use crate::storage::FileStorage;
+use chrono::Utc;
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{DecoderOptions, CODEC_TYPE_NULL};
+use symphonia::core::errors::Error as SymphoniaError;
+use symphonia::core::formats::FormatOptions;
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey, Tag};
+use symphonia::core::probe::Hint;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{info, warn};
+
+/// Number of peaks a waveform is reduced to; enough for a full-width player
+/// on most screens.
+pub const WAVEFORM_PEAKS: usize = 1000;
+
+const AUDIO_EXTENSIONS: &[&str] = &[
+    "mp3", "flac", "wav", "ogg", "oga", "m4a", "aac", "alac", "mka", "caf",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum AudioError {
+    #[error("Failed to read file: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to read file: {0}")]
+    Source(String),
+    #[error("Unsupported or corrupt audio: {0}")]
+    Decode(#[from] SymphoniaError),
+    #[error("File contains no audio track")]
+    NoTrack,
+}
+
+/// What analyzing an audio file produced.
+#[derive(Debug)]
+pub struct AudioAnalysis {
+    pub metadata: AudioMetadata,
+    pub peaks: Vec<u8>,
+}
+
+pub fn is_audio(metadata: &FileMetadata) -> bool {
+    let by_mime = metadata
+        .mime_type
+        .as_deref()
+        .is_some_and(|mime| mime.to_ascii_lowercase().starts_with("audio/"));
+    let by_extension = Path::new(&metadata.original_filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| AUDIO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+    by_mime || by_extension
+}
+
+/// Starts analyzing audio files in the background as they are created.
+pub fn spawn(storage: FileStorage) {
+    let mut events = storage.events().subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Audio analysis fell behind and missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let (ChangeEvent::FileCreated { file_id, .. }
+            | ChangeEvent::FileUpdated { file_id, .. }) = &event.change
+            else {
+                continue;
+            };
+            let metadata = match storage.get_file_metadata(file_id).await {
+                Ok(Some(metadata)) if is_audio(&metadata) => metadata,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Failed to look up {} for audio analysis: {}", file_id, e);
+                    continue;
+                }
+            };
+            if let Err(e) = analyze_and_store(&storage, &metadata).await {
+                warn!("Failed to analyze audio file {}: {}", file_id, e);
+            }
+        }
+    });
+}
+
+/// Analyzes an audio file and saves the result, replacing any earlier one.
+pub async fn analyze_and_store(
+    storage: &FileStorage,
+    metadata: &FileMetadata,
+) -> Result<AudioAnalysis, Box<dyn std::error::Error + Send + Sync>> {
+    let analysis = analyze(storage, metadata).await?;
+    storage.save_audio_analysis(&analysis).await?;
+    info!(
+        "Analyzed audio file {} ({:.1}s)",
+        metadata.id, analysis.metadata.duration_secs
+    );
+    Ok(analysis)
+}
+
+/// Reads duration, stream properties, tags and waveform peaks of a stored
+/// audio file. Chunked files are staged to a temporary file first, since
+/// some containers need to seek.
+pub async fn analyze(
+    storage: &FileStorage,
+    metadata: &FileMetadata,
+) -> Result<AudioAnalysis, AudioError> {
+    let (path, staged) = match storage.local_content_path(metadata).await {
+        Ok(Some(path)) => (path, false),
+        Ok(None) => (stage(storage, metadata).await?, true),
+        Err(e) => return Err(AudioError::Source(e.to_string())),
+    };
+
+    let file_id = metadata.id.clone();
+    let file_size = metadata.file_size;
+    let extension = Path::new(&metadata.original_filename)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_ascii_lowercase);
+    let mime_type = metadata.mime_type.clone();
+    let decode_path = path.clone();
+    let result = tokio::task::spawn_blocking(move || {
+        decode(
+            &decode_path,
+            &file_id,
+            file_size,
+            extension.as_deref(),
+            mime_type.as_deref(),
+        )
+    })
+    .await
+    .map_err(|e| AudioError::Source(e.to_string()))?;
+
+    if staged {
+        let _ = fs::remove_file(&path).await;
+    }
+    result
+}
+
+async fn stage(storage: &FileStorage, metadata: &FileMetadata) -> Result<PathBuf, AudioError> {
+    let dir = storage.upload_dir().join("analysis");
+    fs::create_dir_all(&dir).await?;
+    let path = dir.join(format!("{}.{}", metadata.id, uuid::Uuid::new_v4()));
+
+    let mut content = storage
+        .open_content(metadata)
+        .await
+        .map_err(|e| AudioError::Source(e.to_string()))?;
+    let mut file = fs::File::create(&path).await?;
+    let written: Result<(), AudioError> = async {
+        while let Some(chunk) = content.next().await {
+            file.write_all(&chunk?).await?;
+        }
+        file.flush().await?;
+        Ok(())
+    }
+    .await;
+    if let Err(e) = written {
+        let _ = fs::remove_file(&path).await;
+        return Err(e);
+    }
+    Ok(path)
+}
+
+fn decode(
+    path: &Path,
+    file_id: &str,
+    file_size: i64,
+    extension: Option<&str>,
+    mime_type: Option<&str>,
+) -> Result<AudioAnalysis, AudioError> {
+    let source = MediaSourceStream::new(Box::new(std::fs::File::open(path)?), Default::default());
+    let mut hint = Hint::new();
+    if let Some(extension) = extension {
+        hint.with_extension(extension);
+    }
+    if let Some(mime_type) = mime_type {
+        hint.mime_type(mime_type);
+    }
+    let mut probed = symphonia::default::get_probe().format(
+        &hint,
+        source,
+        &FormatOptions::default(),
+        &MetadataOptions::default(),
+    )?;
+
+    // ID3v2 tags sit in front of the stream and are found by the probe;
+    // container tags (Vorbis comments, MP4 atoms) come from the format.
+    let mut tags: Vec<Tag> = probed
+        .metadata
+        .get()
+        .and_then(|m| m.current().map(|rev| rev.tags().to_vec()))
+        .unwrap_or_default();
+    if let Some(rev) = probed.format.metadata().current() {
+        tags.extend_from_slice(rev.tags());
+    }
+
+    let format = &mut probed.format;
+    let track = format
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(AudioError::NoTrack)?;
+    let track_id = track.id;
+    let codec = symphonia::default::get_codecs()
+        .get_codec(track.codec_params.codec)
+        .map(|c| c.short_name.to_string());
+    let mut sample_rate = track.codec_params.sample_rate;
+    let mut channels = track.codec_params.channels.map(|c| c.count());
+    let mut decoder =
+        symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut peaks = PeakCollector::new(WAVEFORM_PEAKS);
+    let mut samples: Option<SampleBuffer<f32>> = None;
+    let mut frames: u64 = 0;
+    loop {
+        let packet = match format.next_packet() {
+            Ok(packet) => packet,
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+                break
+            }
+            Err(SymphoniaError::ResetRequired) => break,
+            Err(e) => return Err(e.into()),
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+
+        let decoded = match decoder.decode(&packet) {
+            Ok(decoded) => decoded,
+            // A damaged frame is skipped rather than failing the whole file.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let spec = *decoded.spec();
+        sample_rate.get_or_insert(spec.rate);
+        let channel_count = *channels.get_or_insert(spec.channels.count());
+        if decoded.frames() == 0 {
+            continue;
+        }
+
+        let buffer = match &mut samples {
+            Some(buffer) if buffer.capacity() >= decoded.capacity() * channel_count => buffer,
+            _ => samples.insert(SampleBuffer::new(decoded.capacity() as u64, spec)),
+        };
+        buffer.copy_interleaved_ref(decoded);
+        for frame in buffer.samples().chunks(channel_count.max(1)) {
+            peaks.push(frame.iter().fold(0f32, |max, s| max.max(s.abs())));
+        }
+        frames += (buffer.samples().len() / channel_count.max(1)) as u64;
+    }
+
+    let duration_secs = match sample_rate {
+        Some(rate) if rate > 0 => frames as f64 / rate as f64,
+        _ => 0.0,
+    };
+    let bitrate = (duration_secs > 0.0).then(|| (file_size as f64 * 8.0 / duration_secs) as i64);
+    let tag = |key: StandardTagKey| {
+        tags.iter()
+            .find(|t| t.std_key == Some(key))
+            .map(|t| t.value.to_string().trim().to_string())
+            .filter(|v| !v.is_empty())
+    };
+
+    Ok(AudioAnalysis {
+        metadata: AudioMetadata {
+            file_id: file_id.to_string(),
+            duration_secs,
+            bitrate,
+            sample_rate: sample_rate.map(i64::from),
+            channels: channels.map(|c| c as i64),
+            codec,
+            title: tag(StandardTagKey::TrackTitle),
+            artist: tag(StandardTagKey::Artist),
+            album: tag(StandardTagKey::Album),
+            genre: tag(StandardTagKey::Genre),
+            year: tag(StandardTagKey::Date),
+            track_number: tag(StandardTagKey::TrackNumber),
+            analyzed_at: Utc::now().to_rfc3339(),
+        },
+        peaks: peaks.finish(),
+    })
+}
+
+/// Tracks the loudest sample of each window of frames without knowing the
+/// track length up front: whenever twice the wanted number of windows has
+/// been collected, neighbours are merged and the window length doubles.
+struct PeakCollector {
+    count: usize,
+    window: u64,
+    windows: Vec<f32>,
+    current: f32,
+    frames_in_window: u64,
+}
+
+impl PeakCollector {
+    fn new(count: usize) -> Self {
+        Self {
+            count,
+            window: 1,
+            windows: Vec::with_capacity(count * 2),
+            current: 0.0,
+            frames_in_window: 0,
+        }
+    }
+
+    fn push(&mut self, amplitude: f32) {
+        self.current = self.current.max(amplitude);
+        self.frames_in_window += 1;
+        if self.frames_in_window < self.window {
+            return;
+        }
+        self.windows.push(self.current);
+        self.current = 0.0;
+        self.frames_in_window = 0;
+
+        if self.windows.len() >= self.count * 2 {
+            self.windows = self
+                .windows
+                .chunks(2)
+                .map(|pair| pair.iter().fold(0f32, |max, p| max.max(*p)))
+                .collect();
+            self.window *= 2;
+        }
+    }
+
+    /// Merges the windows into at most `count` peaks scaled to 0–255.
+    fn finish(mut self) -> Vec<u8> {
+        if self.frames_in_window > 0 {
+            self.windows.push(self.current);
+        }
+        let len = self.windows.len();
+        let buckets = len.min(self.count);
+        (0..buckets)
+            .map(|i| {
+                let slice = &self.windows[i * len / buckets..(i + 1) * len / buckets];
+                let peak = slice.iter().fold(0f32, |max, p| max.max(*p));
+                (peak.clamp(0.0, 1.0) * 255.0).round() as u8
+            })
+            .collect()
+    }
+}
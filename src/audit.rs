@@ -0,0 +1,322 @@
+use crate::access::AccessContext;
+use crate::config::Config;
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::path::{Path, PathBuf};
+use tokio::fs::OpenOptions;
+use tokio::io::AsyncWriteExt;
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tracing::{error, info, warn};
+
+const SYSLOG_DEFAULT_PORT: u16 = 514;
+/// Syslog severity of exported records: informational.
+const SYSLOG_SEVERITY: u8 = 6;
+const FACILITIES: [(&str, u8); 20] = [
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+/// A download, as exported to SIEM systems. Carries the same client
+/// details the access log keeps.
+#[derive(Debug, Clone)]
+pub struct DownloadAudit {
+    pub occurred_at: DateTime<Utc>,
+    pub file_id: String,
+    pub file_name: String,
+    pub file_size: i64,
+    pub access: AccessContext,
+}
+
+/// In-process fan-out of download records to the audit exporter and to
+/// share access notifications. Kept apart from the change events, which are
+/// public and don't carry client details.
+#[derive(Clone)]
+pub struct AuditLog {
+    sender: broadcast::Sender<DownloadAudit>,
+}
+
+impl AuditLog {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, record: DownloadAudit) {
+        // Nobody listening just means export is disabled.
+        let _ = self.sender.send(record);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<DownloadAudit> {
+        self.sender.subscribe()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum AuditConfigError {
+    #[error("AUDIT_SYSLOG_URL must be udp://host[:port] or tcp://host[:port], got {0}")]
+    Url(String),
+    #[error("AUDIT_SYSLOG_FACILITY is not a syslog facility: {0}")]
+    Facility(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Udp,
+    Tcp,
+}
+
+/// Where download records are exported to, from `AUDIT_SYSLOG_URL`,
+/// `AUDIT_SYSLOG_FACILITY` and `AUDIT_CEF_FILE`.
+#[derive(Debug, Clone)]
+pub struct ExportOptions {
+    syslog: Option<(Transport, String)>,
+    facility: u8,
+    cef_file: Option<PathBuf>,
+}
+
+impl ExportOptions {
+    pub fn from_config(config: &Config) -> Result<Self, AuditConfigError> {
+        let syslog = config
+            .audit_syslog_url
+            .as_deref()
+            .map(parse_url)
+            .transpose()?;
+        let facility = FACILITIES
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(config.audit_syslog_facility.trim()))
+            .map(|(_, code)| *code)
+            .ok_or_else(|| AuditConfigError::Facility(config.audit_syslog_facility.clone()))?;
+        Ok(Self {
+            syslog,
+            facility,
+            cef_file: config.audit_cef_file.clone(),
+        })
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.syslog.is_some() || self.cef_file.is_some()
+    }
+}
+
+fn parse_url(url: &str) -> Result<(Transport, String), AuditConfigError> {
+    let invalid = || AuditConfigError::Url(url.to_string());
+    let (scheme, rest) = url.split_once("://").ok_or_else(invalid)?;
+    let transport = match scheme.to_ascii_lowercase().as_str() {
+        "udp" => Transport::Udp,
+        "tcp" => Transport::Tcp,
+        _ => return Err(invalid()),
+    };
+    let host = rest.trim_end_matches('/');
+    if host.is_empty() || host.contains('/') {
+        return Err(invalid());
+    }
+    // Bracketed IPv6 literals contain colons of their own.
+    let has_port = match host.rfind(']') {
+        Some(end) => host[end..].contains(':'),
+        None => host.contains(':'),
+    };
+    let addr = if has_port {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, SYSLOG_DEFAULT_PORT)
+    };
+    Ok((transport, addr))
+}
+
+/// Exports every download as a CEF record, to a syslog collector and/or
+/// appended to a file, alongside the access log kept in the database.
+pub fn spawn(config: &Config, audit: &AuditLog) -> Result<(), AuditConfigError> {
+    let options = ExportOptions::from_config(config)?;
+    if !options.is_enabled() {
+        return Ok(());
+    }
+    if let Some((_, addr)) = &options.syslog {
+        info!("Exporting download audit records to syslog at {}", addr);
+    }
+    if let Some(path) = &options.cef_file {
+        info!("Writing download audit records to {:?}", path);
+    }
+
+    let mut records = audit.subscribe();
+    tokio::spawn(async move {
+        let mut syslog = options
+            .syslog
+            .map(|(transport, addr)| SyslogSender::new(transport, addr));
+        loop {
+            let record = match records.recv().await {
+                Ok(record) => record,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Audit export fell behind and missed {} records", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let line = cef(&record);
+            if let Some(path) = &options.cef_file {
+                if let Err(e) = append_line(path, &line).await {
+                    error!("Failed to write audit record to {:?}: {}", path, e);
+                }
+            }
+            if let Some(syslog) = &mut syslog {
+                let message = syslog_message(options.facility, &record, &line);
+                if let Err(e) = syslog.send(&message).await {
+                    error!("Failed to send audit record to syslog: {}", e);
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+async fn append_line(path: &Path, line: &str) -> std::io::Result<()> {
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await?;
+    file.write_all(format!("{}\n", line).as_bytes()).await?;
+    file.flush().await
+}
+
+/// Sends syslog messages, reconnecting a TCP connection that has dropped.
+struct SyslogSender {
+    transport: Transport,
+    addr: String,
+    udp: Option<UdpSocket>,
+    tcp: Option<TcpStream>,
+}
+
+impl SyslogSender {
+    fn new(transport: Transport, addr: String) -> Self {
+        Self {
+            transport,
+            addr,
+            udp: None,
+            tcp: None,
+        }
+    }
+
+    async fn send(&mut self, message: &str) -> std::io::Result<()> {
+        match self.transport {
+            Transport::Udp => {
+                if self.udp.is_none() {
+                    let target = tokio::net::lookup_host(&self.addr)
+                        .await?
+                        .next()
+                        .ok_or_else(|| {
+                            std::io::Error::new(
+                                std::io::ErrorKind::NotFound,
+                                "No address for syslog host",
+                            )
+                        })?;
+                    let local = if target.is_ipv6() {
+                        "[::]:0"
+                    } else {
+                        "0.0.0.0:0"
+                    };
+                    let socket = UdpSocket::bind(local).await?;
+                    socket.connect(target).await?;
+                    self.udp = Some(socket);
+                }
+                if let Some(socket) = &self.udp {
+                    socket.send(message.as_bytes()).await?;
+                }
+                Ok(())
+            }
+            Transport::Tcp => {
+                // Octet-counting framing (RFC 6587), so messages may contain
+                // newlines.
+                let frame = format!("{} {}", message.len(), message);
+                if let Some(stream) = &mut self.tcp {
+                    if stream.write_all(frame.as_bytes()).await.is_ok() {
+                        return Ok(());
+                    }
+                }
+                let mut stream = TcpStream::connect(&self.addr).await?;
+                stream.write_all(frame.as_bytes()).await?;
+                self.tcp = Some(stream);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Wraps a CEF record in an RFC 5424 syslog message.
+fn syslog_message(facility: u8, record: &DownloadAudit, cef: &str) -> String {
+    format!(
+        "<{}>1 {} - fileshare_rust {} file_download - {}",
+        facility * 8 + SYSLOG_SEVERITY,
+        record
+            .occurred_at
+            .to_rfc3339_opts(SecondsFormat::Millis, true),
+        std::process::id(),
+        cef
+    )
+}
+
+/// Formats a download as an ArcSight Common Event Format record.
+pub fn cef(record: &DownloadAudit) -> String {
+    let mut extension = vec![
+        ("rt", record.occurred_at.timestamp_millis().to_string()),
+        ("act", "download".to_string()),
+        ("fileId", record.file_id.clone()),
+        ("fname", record.file_name.clone()),
+        ("fsize", record.file_size.to_string()),
+    ];
+    if let Some(ip) = &record.access.client_ip {
+        extension.push(("src", ip.clone()));
+    }
+    if let Some(user_agent) = &record.access.user_agent {
+        extension.push(("requestClientApplication", user_agent.clone()));
+    }
+    if let Some(share_id) = &record.access.share_id {
+        extension.push(("cs1Label", "shareId".to_string()));
+        extension.push(("cs1", share_id.clone()));
+    }
+    if let Some(digest) = &record.access.accepted_terms {
+        extension.push(("cs2Label", "acceptedTerms".to_string()));
+        extension.push(("cs2", digest.clone()));
+    }
+    let extension: Vec<String> = extension
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key, escape_extension(&value)))
+        .collect();
+
+    format!(
+        "CEF:0|fileshare_rust|fileshare_rust|{}|file_download|File downloaded|3|{}",
+        escape_header(env!("CARGO_PKG_VERSION")),
+        extension.join(" ")
+    )
+}
+
+fn escape_header(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('|', "\\|")
+}
+
+fn escape_extension(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('=', "\\=")
+        .replace('\r', "\\r")
+        .replace('\n', "\\n")
+}
@@ -0,0 +1,274 @@
+use crate::config::Config;
+use crate::models::{ErrorResponse, User};
+use crate::storage::FileStorage;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use axum::{
+    extract::{Request, State},
+    http::{header, Method, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{DecodingKey, EncodingKey, Header, Validation};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::sync::Arc;
+use tracing::info;
+
+const SECRET_FILE_NAME: &str = ".jwt_secret";
+
+/// Paths that take changes on credentials other than a login, which
+/// public read-only mode leaves open to anonymous requests: signing in
+/// itself, and share, upload and drop links, whose token is the credential.
+const OWN_CREDENTIAL_PREFIXES: [&str; 4] = ["/api/auth/", "/s/", "/u/", "/drop/"];
+
+/// Endpoints for admins only, such as quarantine review, which no read is
+/// let through anonymously.
+const ADMIN_PREFIX: &str = "/api/admin/";
+
+/// Shortest and longest passwords accepted at registration.
+pub const MIN_PASSWORD_LEN: usize = 8;
+pub const MAX_PASSWORD_LEN: usize = 1024;
+
+/// Who a request was made by, attached to requests that carry a valid
+/// token. Handlers take it as `Option<Extension<AuthUser>>`, `None` being
+/// an anonymous request.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: String,
+    /// Whether the user is listed in `ADMIN_USERS`.
+    pub admin: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Claims {
+    sub: String,
+    name: String,
+    iat: i64,
+    exp: i64,
+    /// The tenant the token was issued by; absent for the default tenant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
+}
+
+/// Issues and checks the HS256 JWTs users sign in with.
+#[derive(Clone)]
+pub struct Tokens {
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    ttl: Duration,
+    tenant: Option<String>,
+    admins: Arc<[String]>,
+}
+
+impl Tokens {
+    /// Uses `JWT_SECRET` when set, otherwise a random secret persisted in
+    /// the upload directory so sessions survive restarts.
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let secret = match &config.jwt_secret {
+            Some(secret) => secret.as_bytes().to_vec(),
+            None => {
+                fs::create_dir_all(&config.upload_dir)?;
+                let secret_path = config.upload_dir.join(SECRET_FILE_NAME);
+                match fs::read_to_string(&secret_path)
+                    .ok()
+                    .and_then(|s| hex::decode(s.trim()).ok())
+                {
+                    Some(secret) => secret,
+                    None => {
+                        let mut secret = vec![0u8; 32];
+                        rand::thread_rng().fill_bytes(&mut secret);
+                        fs::write(&secret_path, hex::encode(&secret))?;
+                        info!("Generated JWT secret at {:?}", secret_path);
+                        secret
+                    }
+                }
+            }
+        };
+        Ok(Self {
+            encoding: EncodingKey::from_secret(&secret),
+            decoding: DecodingKey::from_secret(&secret),
+            ttl: Duration::seconds(config.jwt_ttl_secs as i64),
+            tenant: None,
+            admins: config.admin_users.clone().into(),
+        })
+    }
+
+    /// Tokens that only the tenant `name` accepts, even where tenants share
+    /// `JWT_SECRET`.
+    pub fn for_tenant(self, name: &str) -> Self {
+        Self {
+            tenant: Some(name.to_string()),
+            ..self
+        }
+    }
+
+    /// A token for `user` and the time it expires.
+    pub fn issue(
+        &self,
+        user: &User,
+    ) -> Result<(String, DateTime<Utc>), jsonwebtoken::errors::Error> {
+        let now = Utc::now();
+        let expires_at = now + self.ttl;
+        let claims = Claims {
+            sub: user.id.clone(),
+            name: user.username.clone(),
+            iat: now.timestamp(),
+            exp: expires_at.timestamp(),
+            aud: self.tenant.clone(),
+        };
+        let token = jsonwebtoken::encode(&Header::default(), &claims, &self.encoding)?;
+        Ok((token, expires_at))
+    }
+
+    /// The user a token was issued to, unless it is forged or expired.
+    pub fn verify(&self, token: &str) -> Option<AuthUser> {
+        let mut validation = Validation::default();
+        if let Some(tenant) = &self.tenant {
+            validation.set_audience(&[tenant]);
+            validation.set_required_spec_claims(&["exp", "aud"]);
+        }
+        let data = jsonwebtoken::decode::<Claims>(token, &self.decoding, &validation).ok()?;
+        Some(AuthUser {
+            admin: self.is_admin(&data.claims.name),
+            id: data.claims.sub,
+        })
+    }
+
+    /// Whether the account named `username` is listed in `ADMIN_USERS`.
+    pub fn is_admin(&self, username: &str) -> bool {
+        self.admins.iter().any(|admin| admin == username)
+    }
+}
+
+/// Hashes a password with Argon2id, off the async runtime since it is
+/// deliberately slow.
+pub async fn hash_password(password: String) -> io::Result<String> {
+    tokio::task::spawn_blocking(move || {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let salt = SaltString::encode_b64(&salt).map_err(io::Error::other)?;
+        Argon2::default()
+            .hash_password(password.as_bytes(), &salt)
+            .map(|hash| hash.to_string())
+            .map_err(io::Error::other)
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+pub async fn verify_password(password: String, hash: String) -> bool {
+    tokio::task::spawn_blocking(move || {
+        PasswordHash::new(&hash)
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false)
+    })
+    .await
+    .unwrap_or(false)
+}
+
+/// Usernames are 3 to 64 letters, digits, dots, dashes and underscores.
+pub fn valid_username(username: &str) -> bool {
+    (3..=64).contains(&username.len())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+}
+
+/// Attaches the [`AuthUser`] of a bearer JWT to the request. Requests
+/// without one go through anonymously, as do bearer values that aren't
+/// JWTs, such as the quick upload API key; a JWT that is forged or
+/// expired is refused with `401`. In public read-only mode, anonymous
+/// requests that would change something are refused with `401` here, so
+/// no handler has to check for it.
+pub async fn authenticate(
+    State(storage): State<FileStorage>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .map(str::trim)
+        .filter(|token| token.split('.').count() == 3);
+    if let Some(token) = token {
+        match storage.tokens().verify(token) {
+            Some(user) => {
+                request.extensions_mut().insert(user);
+            }
+            None => {
+                return (
+                    StatusCode::UNAUTHORIZED,
+                    Json(ErrorResponse {
+                        error: "Invalid or expired token".to_string(),
+                    }),
+                )
+                    .into_response();
+            }
+        }
+    }
+    if storage.public_read_only()
+        && request.extensions().get::<AuthUser>().is_none()
+        && !allowed_anonymously(&storage, &request)
+    {
+        return (
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Log in to make changes".to_string(),
+            }),
+        )
+            .into_response();
+    }
+    next.run(request).await
+}
+
+/// Whether public read-only mode lets an anonymous request through: any
+/// read outside the admin endpoints, and changes made with credentials of
+/// their own, including quick uploads once they need the API key.
+fn allowed_anonymously(storage: &FileStorage, request: &Request) -> bool {
+    let path = request.uri().path();
+    if path.starts_with(ADMIN_PREFIX) {
+        return false;
+    }
+    if matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    ) {
+        return true;
+    }
+    OWN_CREDENTIAL_PREFIXES
+        .iter()
+        .any(|prefix| path.starts_with(prefix))
+        || (path == "/api/quick" && storage.quick_upload_api_key().is_some())
+}
+
+/// Refuses requests to the admin and policy endpoints it is layered on
+/// unless they come from a user listed in `ADMIN_USERS`.
+pub async fn require_admin(
+    user: Option<Extension<AuthUser>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let refusal = match user {
+        Some(Extension(user)) if user.admin => return next.run(request).await,
+        Some(_) => (StatusCode::FORBIDDEN, "Only admins can do this"),
+        None => (StatusCode::UNAUTHORIZED, "Log in as an admin to do this"),
+    };
+    (
+        refusal.0,
+        Json(ErrorResponse {
+            error: refusal.1.to_string(),
+        }),
+    )
+        .into_response()
+}
@@ -0,0 +1,71 @@
+use crate::config::Config;
+use crate::models::Capabilities;
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const GIT_COMMIT: &str = env!("FILESHARE_GIT_COMMIT");
+pub const BUILD_DATE: &str = env!("FILESHARE_BUILD_DATE");
+
+pub const STORAGE_BACKEND: &str = "local";
+
+/// Optional features switched on in this instance's configuration.
+pub fn enabled_features(config: &Config) -> Vec<&'static str> {
+    [
+        ("chunk_dedup", config.chunk_dedup),
+        ("case_insensitive_names", config.case_insensitive_names),
+        ("windows_compatible_names", config.windows_compatible_names),
+        ("mirror", config.mirror_dir.is_some()),
+        ("watch_external_changes", config.watch_external_changes),
+        ("email_notifications", config.smtp_url.is_some()),
+        ("web_push", true),
+        ("mqtt", config.mqtt_url.is_some()),
+        ("format_conversion", config.image_convert_command.is_some()),
+        ("thumbnails", config.thumbnail_command.is_some()),
+        ("sentry", config.sentry_dsn.is_some()),
+        (
+            "quota",
+            config.quota_soft_limit.is_some() || config.quota_hard_limit.is_some(),
+        ),
+        (
+            "audit_export",
+            config.audit_syslog_url.is_some() || config.audit_cef_file.is_some(),
+        ),
+        ("tenants", !config.tenants.is_empty()),
+        ("public_read_only", config.public_read_only),
+        ("cdn", config.cdn_base_url.is_some()),
+        (
+            "encryption_at_rest",
+            config.encryption_key.is_some() || config.encryption_key_file.is_some(),
+        ),
+    ]
+    .into_iter()
+    .filter(|(_, enabled)| *enabled)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+pub fn capabilities(config: &Config) -> Capabilities {
+    Capabilities {
+        shares: true,
+        thumbnails: config.thumbnail_command.is_some(),
+        webdav: false,
+        chunked_uploads: true,
+        tar_ingest: true,
+        links: true,
+        change_events: true,
+        web_push: true,
+        email_notifications: config.smtp_url.is_some(),
+        mqtt: config.mqtt_url.is_some(),
+        chat_integrations: true,
+        clipboard: true,
+        audio_waveforms: true,
+        format_conversion: config.image_convert_command.is_some(),
+        mirror: config.mirror_dir.is_some(),
+        chunk_dedup: config.chunk_dedup,
+        case_insensitive_names: config.case_insensitive_names,
+        windows_compatible_names: config.windows_compatible_names,
+        user_accounts: true,
+        registration: !config.disable_registration,
+        oidc: config.oidc_issuer_url.is_some(),
+        public_read_only: config.public_read_only,
+    }
+}
@@ -0,0 +1,181 @@
+use crate::config::Config;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use reqwest::Url;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::traits::PublicKeyParts;
+use rsa::{Pkcs1v15Sign, RsaPrivateKey};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::sync::Arc;
+
+/// Path prefix the CDN fetches shared files from on this server.
+pub const ORIGIN_PREFIX: &str = "/cdn";
+
+/// Smallest RSA key CloudFront accepts.
+const MIN_KEY_BITS: usize = 2048;
+
+#[derive(Debug, thiserror::Error)]
+pub enum CdnConfigError {
+    #[error("CDN_BASE_URL must be an http or https URL, got {0}")]
+    BaseUrl(String),
+    #[error("CDN_PROVIDER must be cloudfront or fastly, got {0:?}")]
+    Provider(String),
+    #[error("CDN_KEY_ID is required for CloudFront")]
+    KeyId,
+    #[error("CDN_PRIVATE_KEY_FILE is required for CloudFront")]
+    KeyFile,
+    #[error("Failed to read CDN_PRIVATE_KEY_FILE: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("CDN_PRIVATE_KEY_FILE must hold a PEM RSA private key of at least 2048 bits")]
+    Key,
+    #[error("CDN_SIGNING_SECRET is required for Fastly")]
+    Secret,
+}
+
+/// A CDN provider's scheme for URLs that only work until a given time.
+pub trait UrlSigner: Send + Sync {
+    /// `url` with what the CDN needs to serve it until `expires`, a Unix
+    /// time, and refuse it afterwards.
+    fn sign(&self, url: Url, expires: i64) -> Url;
+}
+
+/// CloudFront signed URLs with a canned policy, signed with the private key
+/// of a public key in the distribution's trusted key group.
+pub struct CloudFront {
+    key_id: String,
+    key: RsaPrivateKey,
+}
+
+impl CloudFront {
+    pub fn new(key_id: String, pem: &str) -> Result<Self, CdnConfigError> {
+        let key = RsaPrivateKey::from_pkcs1_pem(pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs8_pem(pem))
+            .map_err(|_| CdnConfigError::Key)?;
+        if key.size() * 8 < MIN_KEY_BITS {
+            return Err(CdnConfigError::Key);
+        }
+        Ok(Self { key_id, key })
+    }
+}
+
+impl UrlSigner for CloudFront {
+    fn sign(&self, mut url: Url, expires: i64) -> Url {
+        let policy = format!(
+            r#"{{"Statement":[{{"Resource":"{}","Condition":{{"DateLessThan":{{"AWS:EpochTime":{}}}}}}}]}}"#,
+            url, expires
+        );
+        let digest = Sha1::digest(policy.as_bytes());
+        let signature = self
+            .key
+            .sign(Pkcs1v15Sign::new::<Sha1>(), &digest)
+            .expect("keys of 2048 bits or more sign SHA-1 digests");
+        // CloudFront's URL-safe variant of base64.
+        let signature: String = STANDARD
+            .encode(signature)
+            .chars()
+            .map(|c| match c {
+                '+' => '-',
+                '=' => '_',
+                '/' => '~',
+                c => c,
+            })
+            .collect();
+        // Set as is: form encoding would escape the `~` CloudFront expects.
+        url.set_query(Some(&format!(
+            "Expires={}&Signature={}&Key-Pair-Id={}",
+            expires, signature, self.key_id
+        )));
+        url
+    }
+}
+
+/// Fastly URL tokens, `token=<expires>_<signature>` with the hex-encoded
+/// HMAC-SHA256 of the path followed by the expiry time, checked at the edge
+/// by a VCL snippet sharing the secret.
+pub struct Fastly {
+    secret: Vec<u8>,
+}
+
+impl Fastly {
+    pub fn new(secret: &str) -> Self {
+        Self {
+            secret: secret.as_bytes().to_vec(),
+        }
+    }
+}
+
+impl UrlSigner for Fastly {
+    fn sign(&self, mut url: Url, expires: i64) -> Url {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC accepts keys of any length");
+        mac.update(format!("{}{}", url.path(), expires).as_bytes());
+        let signature = hex::encode(mac.finalize().into_bytes());
+        url.query_pairs_mut()
+            .append_pair("token", &format!("{}_{}", expires, signature));
+        url
+    }
+}
+
+/// A CDN that downloads of public shares are handed to, with URLs signed
+/// for its provider.
+#[derive(Clone)]
+pub struct Cdn {
+    base_url: String,
+    ttl_secs: u64,
+    signer: Arc<dyn UrlSigner>,
+}
+
+impl Cdn {
+    /// The CDN configured with `CDN_BASE_URL`, or `None` when unset.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, CdnConfigError> {
+        let Some(base_url) = &config.cdn_base_url else {
+            return Ok(None);
+        };
+        match Url::parse(base_url) {
+            Ok(url) if matches!(url.scheme(), "http" | "https") && url.query().is_none() => {}
+            _ => return Err(CdnConfigError::BaseUrl(base_url.clone())),
+        }
+
+        let signer: Arc<dyn UrlSigner> = match config.cdn_provider.as_str() {
+            "cloudfront" => {
+                let key_id = config.cdn_key_id.clone().ok_or(CdnConfigError::KeyId)?;
+                let key_file = config
+                    .cdn_private_key_file
+                    .as_ref()
+                    .ok_or(CdnConfigError::KeyFile)?;
+                Arc::new(CloudFront::new(key_id, &fs::read_to_string(key_file)?)?)
+            }
+            "fastly" => {
+                let secret = config
+                    .cdn_signing_secret
+                    .as_deref()
+                    .ok_or(CdnConfigError::Secret)?;
+                Arc::new(Fastly::new(secret))
+            }
+            other => return Err(CdnConfigError::Provider(other.to_string())),
+        };
+        Ok(Some(Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            ttl_secs: config.cdn_url_ttl_secs,
+            signer,
+        }))
+    }
+
+    /// A signed CDN URL for the shared file with `token`, valid for the
+    /// configured time but not past `not_after`.
+    pub fn share_url(&self, token: &str, not_after: Option<DateTime<Utc>>) -> Url {
+        let ttl = chrono::Duration::seconds(self.ttl_secs.min(i32::MAX as u64) as i64);
+        let expires = match not_after {
+            Some(not_after) => not_after.min(Utc::now() + ttl),
+            None => Utc::now() + ttl,
+        };
+        let url = Url::parse(&format!("{}{}/{}", self.base_url, ORIGIN_PREFIX, token))
+            .expect("CDN_BASE_URL was checked at startup");
+        self.signer.sign(url, expires.timestamp())
+    }
+}
@@ -0,0 +1,492 @@
+use crate::config::Config;
+use crate::db::{self, DbPool};
+use crate::audit;
+use crate::cdn::Cdn;
+use crate::encryption::Cipher;
+use crate::file_io;
+use crate::i18n;
+use crate::listen;
+use crate::maintenance::MaintenanceWindow;
+use crate::pages;
+use crate::mqtt;
+use crate::push::PushSender;
+use crate::quota;
+use crate::security;
+use crate::templates::DirectoryTemplate;
+use crate::tenants;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, Tokio1Executor};
+use rumqttc::{AsyncClient, Event, Packet};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// Free space in the upload directory below which the check warns.
+const MIN_FREE_BYTES: u64 = 1024 * 1024 * 1024;
+
+/// How long to wait for an external service before reporting it unreachable.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Ok,
+    Warn,
+    Fail,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckResult {
+    pub name: &'static str,
+    pub status: CheckStatus,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CheckReport {
+    pub passed: bool,
+    pub checks: Vec<CheckResult>,
+}
+
+impl CheckReport {
+    fn new(checks: Vec<CheckResult>) -> Self {
+        Self {
+            passed: checks.iter().all(|c| c.status != CheckStatus::Fail),
+            checks,
+        }
+    }
+
+    /// Writes every result to the log, at a level matching its status.
+    pub fn log(&self) {
+        for check in &self.checks {
+            match check.status {
+                CheckStatus::Ok | CheckStatus::Skipped => {
+                    info!("Self-check {}: {}", check.name, check.detail)
+                }
+                CheckStatus::Warn => warn!("Self-check {}: {}", check.name, check.detail),
+                CheckStatus::Fail => error!("Self-check {}: {}", check.name, check.detail),
+            }
+        }
+    }
+
+    pub fn print(&self) {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Ok => "ok",
+                CheckStatus::Warn => "WARN",
+                CheckStatus::Fail => "FAIL",
+                CheckStatus::Skipped => "skip",
+            };
+            println!("[{:>4}] {:<12} {}", status, check.name, check.detail);
+        }
+        println!();
+        println!(
+            "{}",
+            if self.passed {
+                "All checks passed"
+            } else {
+                "Some checks failed"
+            }
+        );
+    }
+}
+
+fn result(name: &'static str, status: CheckStatus, detail: impl Into<String>) -> CheckResult {
+    CheckResult {
+        name,
+        status,
+        detail: detail.into(),
+    }
+}
+
+/// Runs `fileshare check`: prints the report (as JSON with `--json`) and
+/// returns the process exit code.
+pub async fn run_command(config: &Config, json: bool) -> i32 {
+    let report = match db::connect(&config.database_url).await {
+        Ok(pool) => run(config, &pool).await,
+        Err(e) => CheckReport::new(vec![result(
+            "database",
+            CheckStatus::Fail,
+            format!("Cannot open {}: {}", db::redacted_url(&config.database_url), e),
+        )]),
+    };
+
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report is serializable")
+        );
+    } else {
+        report.print();
+    }
+    if report.passed {
+        0
+    } else {
+        1
+    }
+}
+
+/// Checks configuration, the database, the upload directory and every
+/// configured external service. Unreachable external services only warn, so
+/// a flaky mail server doesn't keep the server from starting.
+pub async fn run(config: &Config, pool: &DbPool) -> CheckReport {
+    CheckReport::new(vec![
+        check_config(config),
+        check_database(config, pool).await,
+        check_migrations(pool).await,
+        check_upload_dir(&config.upload_dir).await,
+        check_free_space(&config.upload_dir),
+        check_smtp(config).await,
+        check_mqtt(config).await,
+    ])
+}
+
+fn check_config(config: &Config) -> CheckResult {
+    let mut problems = Vec::new();
+
+    for (key, dir) in [
+        ("IMPORT_DIR", &config.import_dir),
+        ("MIRROR_DIR", &config.mirror_dir),
+    ] {
+        if let Some(dir) = dir {
+            if !dir.is_dir() {
+                problems.push(format!("{} {:?} is not a directory", key, dir));
+            }
+        }
+    }
+    if config.vapid_private_key.is_some() {
+        if let Err(e) = PushSender::from_config(config) {
+            problems.push(format!("VAPID_PRIVATE_KEY: {}", e));
+        }
+    }
+    if let Err(e) = config.smtp_from.parse::<Mailbox>() {
+        problems.push(format!("SMTP_FROM: {}", e));
+    }
+    if let Err(e) = mqtt::options(config) {
+        problems.push(format!("MQTT_URL: {}", e));
+    }
+    if let Err(e) = audit::ExportOptions::from_config(config) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = Cdn::from_config(config) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = Cipher::from_config(config) {
+        problems.push(e.to_string());
+    }
+    if let Err(e) = listen::addresses(config) {
+        problems.push(e.to_string());
+    }
+    if let Some(url) = &config.public_url {
+        if let Err(e) = reqwest::Url::parse(url) {
+            problems.push(format!("PUBLIC_URL: {}", e));
+        }
+    }
+    if !pages::is_css_color(&config.branding_accent_color) {
+        problems.push(format!(
+            "BRANDING_ACCENT_COLOR must be #rgb or #rrggbb, got {}",
+            config.branding_accent_color
+        ));
+    }
+    if let Some(url) = &config.branding_logo_url {
+        if !url.starts_with('/') {
+            if let Err(e) = reqwest::Url::parse(url) {
+                problems.push(format!("BRANDING_LOGO_URL: {}", e));
+            }
+        }
+    }
+    if !i18n::is_supported(&config.default_language) {
+        problems.push(format!(
+            "DEFAULT_LANGUAGE must be one of: {}, got {}",
+            i18n::languages().join(", "),
+            config.default_language
+        ));
+    }
+    for (key, value) in [
+        ("ARCHIVE_MAX_EXTRACTED_BYTES", config.archive_max_extracted_bytes),
+        ("ARCHIVE_MAX_ENTRIES", config.archive_max_entries),
+        ("ARCHIVE_MAX_DEPTH", config.archive_max_depth as u64),
+        ("ARCHIVE_MAX_RATIO", config.archive_max_ratio),
+    ] {
+        if value == 0 {
+            problems.push(format!("{} must be greater than 0", key));
+        }
+    }
+    for content_type in &config.inline_content_types {
+        if !security::ACTIVE_CONTENT_TYPES.contains(&content_type.as_str()) {
+            problems.push(format!(
+                "INLINE_CONTENT_TYPES may only list: {}, got {}",
+                security::ACTIVE_CONTENT_TYPES.join(", "),
+                content_type
+            ));
+        }
+    }
+    if config.jwt_secret.as_ref().is_some_and(|secret| secret.len() < 32) {
+        problems.push("JWT_SECRET must be at least 32 characters".to_string());
+    }
+    if config.jwt_ttl_secs < 60 {
+        problems.push("JWT_TTL_SECS must be at least 60".to_string());
+    }
+    for (key, url) in [
+        ("OIDC_ISSUER_URL", &config.oidc_issuer_url),
+        ("OIDC_REDIRECT_URL", &config.oidc_redirect_url),
+        ("OIDC_POST_LOGIN_REDIRECT", &config.oidc_post_login_redirect),
+    ] {
+        if let Some(Err(e)) = url.as_deref().map(reqwest::Url::parse) {
+            problems.push(format!("{}: {}", key, e));
+        }
+    }
+    if config.oidc_issuer_url.is_some() && config.oidc_client_id.is_none() {
+        problems.push("OIDC_CLIENT_ID is required with OIDC_ISSUER_URL".to_string());
+    }
+    if !config.oidc_scopes.split_whitespace().any(|scope| scope == "openid") {
+        problems.push("OIDC_SCOPES must include openid".to_string());
+    }
+    if let Some(window) = &config.db_maintenance_window {
+        match MaintenanceWindow::parse(window) {
+            Ok(window) if window.length().num_seconds() < config.scheduler_interval_secs as i64 => {
+                problems.push(format!(
+                    "DB_MAINTENANCE_WINDOW {} is shorter than SCHEDULER_INTERVAL_SECS, so runs \
+                     could miss it",
+                    window
+                ))
+            }
+            Ok(_) => {}
+            Err(e) => problems.push(format!("DB_MAINTENANCE_WINDOW: {}", e)),
+        }
+    }
+    if let Err(e) = tenants::parse(config) {
+        problems.push(e.to_string());
+    }
+    for template in [
+        DirectoryTemplate::for_tenants(config),
+        DirectoryTemplate::for_users(config),
+    ] {
+        if let Err(e) = template {
+            problems.push(e.to_string());
+        }
+    }
+    if config.upload_session_ttl_secs < 60 {
+        problems.push("UPLOAD_SESSION_TTL_SECS must be at least 60".to_string());
+    }
+    if config.upload_write_queue == 0 {
+        problems.push("UPLOAD_WRITE_QUEUE must be at least 1".to_string());
+    }
+    if config.transfer_concurrency == 0 {
+        problems.push("TRANSFER_CONCURRENCY must be at least 1".to_string());
+    }
+    if config.interactive_concurrency == 0 {
+        problems.push("INTERACTIVE_CONCURRENCY must be at least 1".to_string());
+    }
+    if config.thumbnail_workers == 0 {
+        problems.push("THUMBNAIL_WORKERS must be at least 1".to_string());
+    }
+    if !file_io::BACKENDS.contains(&config.io_backend.as_str()) {
+        problems.push(format!(
+            "IO_BACKEND must be one of: {}, got {}",
+            file_io::BACKENDS.join(", "),
+            config.io_backend
+        ));
+    }
+    for (key, size) in [
+        ("READ_BUFFER_SIZE", config.read_buffer_size),
+        ("WRITE_BUFFER_SIZE", config.write_buffer_size),
+    ] {
+        if !(file_io::MIN_BUFFER_SIZE..=file_io::MAX_BUFFER_SIZE).contains(&size) {
+            problems.push(format!(
+                "{} must be between {} and {} bytes, got {}",
+                key,
+                file_io::MIN_BUFFER_SIZE,
+                file_io::MAX_BUFFER_SIZE,
+                size
+            ));
+        }
+    }
+    for extension in &config.risky_extensions {
+        if !extension.chars().all(|c| c.is_ascii_alphanumeric()) {
+            problems.push(format!(
+                "RISKY_EXTENSIONS entries must be letters and digits, got {}",
+                extension
+            ));
+        }
+    }
+    if let Some(dsn) = &config.sentry_dsn {
+        if let Err(e) = dsn.parse::<sentry::types::Dsn>() {
+            problems.push(format!("SENTRY_DSN: {}", e));
+        }
+    }
+
+    if problems.is_empty() {
+        result("config", CheckStatus::Ok, "Configuration is valid")
+    } else {
+        result("config", CheckStatus::Fail, problems.join("; "))
+    }
+}
+
+async fn check_database(config: &Config, pool: &DbPool) -> CheckResult {
+    match sqlx::query("SELECT 1").execute(pool).await {
+        Ok(_) => result(
+            "database",
+            CheckStatus::Ok,
+            format!("Connected to {}", db::redacted_url(&config.database_url)),
+        ),
+        Err(e) => result(
+            "database",
+            CheckStatus::Fail,
+            format!("Query failed: {}", e),
+        ),
+    }
+}
+
+async fn check_migrations(pool: &DbPool) -> CheckResult {
+    match db::pending_migrations(pool).await {
+        Ok(pending) if pending.is_empty() => {
+            let version = db::MIGRATIONS.last().map_or(0, |m| m.version);
+            result(
+                "migrations",
+                CheckStatus::Ok,
+                format!("Schema is at version {}", version),
+            )
+        }
+        Ok(pending) => {
+            let names: Vec<String> = pending
+                .iter()
+                .map(|m| format!("{:03}_{}", m.version, m.name))
+                .collect();
+            result(
+                "migrations",
+                CheckStatus::Warn,
+                format!(
+                    "{} pending, applied on next start: {}",
+                    names.len(),
+                    names.join(", ")
+                ),
+            )
+        }
+        Err(e) => result("migrations", CheckStatus::Fail, e.to_string()),
+    }
+}
+
+async fn check_upload_dir(dir: &Path) -> CheckResult {
+    let probe = dir.join(format!(".check-{}", Uuid::new_v4()));
+    let outcome = async {
+        tokio::fs::create_dir_all(dir).await?;
+        tokio::fs::write(&probe, b"check").await?;
+        tokio::fs::remove_file(&probe).await
+    }
+    .await;
+
+    match outcome {
+        Ok(()) => result(
+            "upload_dir",
+            CheckStatus::Ok,
+            format!("{:?} is writable", dir),
+        ),
+        Err(e) => result(
+            "upload_dir",
+            CheckStatus::Fail,
+            format!("{:?} is not writable: {}", dir, e),
+        ),
+    }
+}
+
+fn check_free_space(dir: &Path) -> CheckResult {
+    match quota::free_space(dir) {
+        Some(free) => {
+            let status = if free < MIN_FREE_BYTES {
+                CheckStatus::Warn
+            } else {
+                CheckStatus::Ok
+            };
+            let gib = free as f64 / (1024.0 * 1024.0 * 1024.0);
+            result(
+                "free_space",
+                status,
+                format!("{:.1} GiB free in {:?}", gib, dir),
+            )
+        }
+        None => result(
+            "free_space",
+            CheckStatus::Skipped,
+            "Free space is unknown on this platform",
+        ),
+    }
+}
+
+async fn check_smtp(config: &Config) -> CheckResult {
+    let Some(url) = &config.smtp_url else {
+        return result("smtp", CheckStatus::Skipped, "SMTP_URL is not set");
+    };
+    let transport: AsyncSmtpTransport<Tokio1Executor> =
+        match AsyncSmtpTransport::<Tokio1Executor>::from_url(url) {
+            Ok(builder) => builder.build(),
+            Err(e) => {
+                return result(
+                    "smtp",
+                    CheckStatus::Fail,
+                    format!("Invalid SMTP_URL: {}", e),
+                )
+            }
+        };
+
+    match tokio::time::timeout(CONNECT_TIMEOUT, transport.test_connection()).await {
+        Ok(Ok(true)) => result("smtp", CheckStatus::Ok, "Mail server is reachable"),
+        Ok(Ok(false)) => result(
+            "smtp",
+            CheckStatus::Warn,
+            "Mail server did not accept the connection",
+        ),
+        Ok(Err(e)) => result(
+            "smtp",
+            CheckStatus::Warn,
+            format!("Mail server is unreachable: {}", e),
+        ),
+        Err(_) => result(
+            "smtp",
+            CheckStatus::Warn,
+            "Timed out connecting to the mail server",
+        ),
+    }
+}
+
+async fn check_mqtt(config: &Config) -> CheckResult {
+    let options = match mqtt::options(config) {
+        Ok(Some(options)) => options,
+        Ok(None) => return result("mqtt", CheckStatus::Skipped, "MQTT_URL is not set"),
+        Err(e) => {
+            return result(
+                "mqtt",
+                CheckStatus::Fail,
+                format!("Invalid MQTT_URL: {}", e),
+            )
+        }
+    };
+
+    let (_client, mut eventloop) = AsyncClient::new(options, 1);
+    let connect = async {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => return Ok(()),
+                Ok(_) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    };
+
+    match tokio::time::timeout(CONNECT_TIMEOUT, connect).await {
+        Ok(Ok(())) => result("mqtt", CheckStatus::Ok, "Broker accepted the connection"),
+        Ok(Err(e)) => result(
+            "mqtt",
+            CheckStatus::Warn,
+            format!("Broker is unreachable: {}", e),
+        ),
+        Err(_) => result(
+            "mqtt",
+            CheckStatus::Warn,
+            "Timed out connecting to the broker",
+        ),
+    }
+}
@@ -0,0 +1,101 @@
+use axum::body::{Body, Bytes, HttpBody};
+use axum::http::HeaderMap;
+use futures_util::Stream;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Header, or trailer, carrying the hex SHA-256 of a file's content: sent
+/// on downloads, and checked on uploads that come with one.
+pub const HEADER: &str = "x-checksum-sha256";
+
+/// The SHA-256 a client vouches for an upload's content having, which the
+/// upload must match to be kept.
+#[derive(Debug, Clone, Default)]
+pub enum ExpectedChecksum {
+    /// Nothing to check against.
+    #[default]
+    None,
+    /// Sent after the body, in its trailers.
+    Trailer(Trailers),
+    /// Known from the request, e.g. the final call of an upload session.
+    Given(String),
+}
+
+impl ExpectedChecksum {
+    /// The checksum to compare with, in lowercase hex, once the whole body
+    /// has been read.
+    pub fn value(&self) -> Option<String> {
+        let sha256 = match self {
+            ExpectedChecksum::None => None,
+            ExpectedChecksum::Trailer(trailers) => trailers.checksum(),
+            ExpectedChecksum::Given(sha256) => Some(sha256.clone()),
+        };
+        sha256.map(|sha256| sha256.trim().to_ascii_lowercase())
+    }
+
+    /// Whether `sha256`, in lowercase hex, is what the client expects; true
+    /// when it named nothing.
+    pub fn matches(&self, sha256: &str) -> bool {
+        self.value().is_none_or(|expected| expected == sha256)
+    }
+}
+
+/// Trailers of a request body, available once the body has been read.
+#[derive(Debug, Clone, Default)]
+pub struct Trailers(Arc<Mutex<Option<HeaderMap>>>);
+
+impl Trailers {
+    pub fn checksum(&self) -> Option<String> {
+        let trailers = self.0.lock().unwrap();
+        trailers
+            .as_ref()?
+            .get(HEADER)?
+            .to_str()
+            .ok()
+            .map(str::to_string)
+    }
+}
+
+/// A request body as a stream of its data, keeping the trailers that
+/// follow it, which `Body::into_data_stream` drops.
+pub struct TrailedBody {
+    body: Body,
+    trailers: Trailers,
+}
+
+impl TrailedBody {
+    pub fn new(body: Body) -> (Self, Trailers) {
+        let trailers = Trailers::default();
+        (
+            Self {
+                body,
+                trailers: trailers.clone(),
+            },
+            trailers,
+        )
+    }
+}
+
+impl Stream for TrailedBody {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            let frame = match Pin::new(&mut self.body).poll_frame(cx) {
+                Poll::Ready(Some(Ok(frame))) => frame,
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            };
+            match frame.into_data() {
+                Ok(data) => return Poll::Ready(Some(Ok(data))),
+                Err(frame) => {
+                    if let Ok(trailers) = frame.into_trailers() {
+                        *self.trailers.0.lock().unwrap() = Some(trailers);
+                    }
+                }
+            }
+        }
+    }
+}
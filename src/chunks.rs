@@ -0,0 +1,61 @@
+use crate::encryption::{self, Cipher};
+use fastcdc::v2020::StreamCDC;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MIN_CHUNK_SIZE: u32 = 256 * 1024;
+const AVG_CHUNK_SIZE: u32 = 1024 * 1024;
+const MAX_CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+
+/// One content-defined chunk of a file, as produced by [`split_into_chunks`].
+pub struct ChunkRef {
+    pub hash: String,
+    pub offset: i64,
+    pub length: i64,
+}
+
+/// Location of a chunk inside the chunk store, sharded by hash prefix.
+pub fn chunk_path(chunk_dir: &Path, hash: &str) -> PathBuf {
+    chunk_dir.join(&hash[..2]).join(hash)
+}
+
+/// Splits `source` with FastCDC and writes every chunk not yet present in
+/// `chunk_dir`, encrypted with `cipher` when given. Chunks are named after
+/// the hash of their plain content either way. Blocking; run it on the
+/// blocking thread pool.
+pub fn split_into_chunks(
+    source: &Path,
+    chunk_dir: &Path,
+    cipher: Option<&Cipher>,
+) -> io::Result<Vec<ChunkRef>> {
+    let content = encryption::open_blocking(source, cipher)?;
+    let chunker = StreamCDC::new(content, MIN_CHUNK_SIZE, AVG_CHUNK_SIZE, MAX_CHUNK_SIZE);
+    let mut refs = Vec::new();
+
+    for chunk in chunker {
+        let chunk = chunk.map_err(|e| io::Error::other(e.to_string()))?;
+        let hash = hex::encode(Sha256::digest(&chunk.data));
+        let path = chunk_path(chunk_dir, &hash);
+
+        if !path.exists() {
+            fs::create_dir_all(path.parent().expect("chunk paths have a parent"))?;
+            // Write under a temporary name so concurrent uploads never see a partial chunk.
+            let tmp = path.with_extension(format!("tmp-{}", uuid::Uuid::new_v4()));
+            match cipher {
+                Some(cipher) => fs::write(&tmp, cipher.encrypt(&chunk.data)?)?,
+                None => fs::write(&tmp, &chunk.data)?,
+            }
+            fs::rename(&tmp, &path)?;
+        }
+
+        refs.push(ChunkRef {
+            hash,
+            offset: chunk.offset as i64,
+            length: chunk.length as i64,
+        });
+    }
+
+    Ok(refs)
+}
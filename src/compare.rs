@@ -0,0 +1,108 @@
+use crate::models::{CompareResponse, ComparedFile, ManifestEntry, TreeEntry};
+use std::collections::BTreeMap;
+
+/// A file on one side of a comparison.
+#[derive(Debug, Clone, Default)]
+pub struct Version {
+    pub file_id: Option<String>,
+    pub sha256: Option<String>,
+    pub file_size: Option<i64>,
+}
+
+impl Version {
+    /// Whether both sides are known to hold the same content. Without a hash
+    /// on one side they can't be shown to, so the file counts as changed.
+    fn same_content(&self, other: &Version) -> bool {
+        match (&self.sha256, &other.sha256) {
+            (Some(a), Some(b)) => a.eq_ignore_ascii_case(b),
+            _ => false,
+        }
+    }
+}
+
+/// The files of a recursive listing by path; directories and links aren't
+/// compared.
+pub fn from_tree(entries: Vec<TreeEntry>) -> BTreeMap<String, Version> {
+    entries
+        .into_iter()
+        .filter_map(|entry| match entry {
+            TreeEntry::File { path, file } => Some((
+                path,
+                Version {
+                    file_id: Some(file.id),
+                    sha256: file.sha256,
+                    file_size: Some(file.file_size),
+                },
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Checks a client's manifest and indexes it by path, with leading and
+/// trailing slashes dropped.
+pub fn from_manifest(entries: Vec<ManifestEntry>) -> Result<BTreeMap<String, Version>, String> {
+    let mut files = BTreeMap::new();
+    for entry in entries {
+        let path = entry.path.trim_matches('/').to_string();
+        if path.is_empty()
+            || path
+                .split('/')
+                .any(|part| part.is_empty() || part == "." || part == "..")
+        {
+            return Err(format!("Invalid manifest path {:?}", entry.path));
+        }
+        if entry.sha256.len() != 64 || !entry.sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(format!("sha256 of {} must be 64 hex digits", path));
+        }
+        let version = Version {
+            file_id: None,
+            sha256: Some(entry.sha256.to_ascii_lowercase()),
+            file_size: entry.file_size,
+        };
+        if files.insert(path.clone(), version).is_some() {
+            return Err(format!("{} is listed more than once", path));
+        }
+    }
+    Ok(files)
+}
+
+/// What was added to, removed from and changed in `current` relative to
+/// `base`, each sorted by path.
+pub fn compare(
+    mut current: BTreeMap<String, Version>,
+    base: BTreeMap<String, Version>,
+) -> CompareResponse {
+    let mut response = CompareResponse {
+        added: Vec::new(),
+        removed: Vec::new(),
+        changed: Vec::new(),
+        unchanged: 0,
+    };
+    for (path, before) in base {
+        match current.remove(&path) {
+            Some(now) if now.same_content(&before) => response.unchanged += 1,
+            Some(now) => response.changed.push(compared(path, now, before)),
+            None => response
+                .removed
+                .push(compared(path, Version::default(), before)),
+        }
+    }
+    response.added = current
+        .into_iter()
+        .map(|(path, now)| compared(path, now, Version::default()))
+        .collect();
+    response
+}
+
+fn compared(path: String, now: Version, before: Version) -> ComparedFile {
+    ComparedFile {
+        path,
+        file_id: now.file_id,
+        sha256: now.sha256,
+        file_size: now.file_size,
+        base_file_id: before.file_id,
+        base_sha256: before.sha256,
+        base_file_size: before.file_size,
+    }
+}
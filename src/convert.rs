@@ -0,0 +1,230 @@
+use crate::models::FileMetadata;
+use crate::storage::ContentStream;
+use futures_util::StreamExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tracing::{info, warn};
+
+/// Longest a single conversion may run before it is abandoned.
+const CONVERT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Source formats browsers and most image viewers can't open, which the
+/// converter is allowed to turn into a common format.
+const CONVERTIBLE_MIME_TYPES: &[&str] = &[
+    "image/heic",
+    "image/heif",
+    "image/heic-sequence",
+    "image/heif-sequence",
+    "image/avif",
+    "image/tiff",
+    "image/bmp",
+    "image/x-adobe-dng",
+    "image/webp",
+];
+const CONVERTIBLE_EXTENSIONS: &[&str] =
+    &["heic", "heif", "avif", "tif", "tiff", "bmp", "dng", "webp"];
+
+/// A format files can be converted to on download.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetFormat {
+    pub name: &'static str,
+    pub mime_type: &'static str,
+    pub extension: &'static str,
+}
+
+pub const TARGET_FORMATS: &[TargetFormat] = &[
+    TargetFormat {
+        name: "jpeg",
+        mime_type: "image/jpeg",
+        extension: "jpg",
+    },
+    TargetFormat {
+        name: "png",
+        mime_type: "image/png",
+        extension: "png",
+    },
+];
+
+impl TargetFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = if name == "jpg" { "jpeg" } else { name };
+        TARGET_FORMATS.iter().copied().find(|f| f.name == name)
+    }
+
+    /// `name` with its extension swapped for this format's.
+    pub fn rename(&self, name: &str) -> String {
+        let stem = Path::new(name)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| name.to_string());
+        format!("{}.{}", stem, self.extension)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConvertError {
+    #[error("Failed to prepare conversion: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to read file: {0}")]
+    Source(String),
+    #[error("Image converter failed: {0}")]
+    Failed(String),
+    #[error("Image conversion timed out")]
+    TimedOut,
+}
+
+/// Converts stored images with an external program such as ImageMagick,
+/// libheif's `heif-convert` or libvips, caching each result on disk.
+#[derive(Debug, Clone)]
+pub struct Converter {
+    /// Program and arguments; `{input}` and `{output}` are replaced by paths.
+    command: Vec<String>,
+    cache_dir: PathBuf,
+}
+
+impl Converter {
+    /// Parses a command template such as `magick {input} {output}`. A bare
+    /// program name gets the input and output paths appended.
+    pub fn new(template: &str, cache_dir: PathBuf) -> Option<Self> {
+        let mut command: Vec<String> = template.split_whitespace().map(String::from).collect();
+        if command.is_empty() {
+            return None;
+        }
+        if !command.iter().any(|arg| arg.contains("{input}")) {
+            command.push("{input}".to_string());
+            command.push("{output}".to_string());
+        }
+        Some(Self { command, cache_dir })
+    }
+
+    pub fn is_convertible(metadata: &FileMetadata) -> bool {
+        let by_mime = metadata.mime_type.as_deref().is_some_and(|mime| {
+            CONVERTIBLE_MIME_TYPES.contains(&mime.to_ascii_lowercase().as_str())
+        });
+        let by_extension = Path::new(&metadata.original_filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| CONVERTIBLE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        by_mime || by_extension
+    }
+
+    fn cache_path(&self, file_id: &str, format: TargetFormat) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}.{}", file_id, format.extension))
+    }
+
+    /// Path of the converted file, converting `content` first unless a
+    /// cached result exists.
+    pub async fn convert(
+        &self,
+        metadata: &FileMetadata,
+        content: ContentStream,
+        format: TargetFormat,
+    ) -> Result<PathBuf, ConvertError> {
+        let cached = self.cache_path(&metadata.id, format);
+        if fs::try_exists(&cached).await? {
+            return Ok(cached);
+        }
+
+        fs::create_dir_all(&self.cache_dir).await?;
+        let source_extension = Path::new(&metadata.original_filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("bin");
+        let nonce = uuid::Uuid::new_v4();
+        let input = self
+            .cache_dir
+            .join(format!("{}.{}.{}", metadata.id, nonce, source_extension));
+        let output = self
+            .cache_dir
+            .join(format!("{}.{}.{}", metadata.id, nonce, format.extension));
+
+        let result = run(&self.command, content, &input, &output, &[]).await;
+        let _ = fs::remove_file(&input).await;
+        match result {
+            Ok(()) => {
+                fs::rename(&output, &cached).await?;
+                info!("Converted {} to {}", metadata.id, format.name);
+                Ok(cached)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&output).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops cached conversions of a deleted file.
+    pub async fn forget(&self, file_id: &str) {
+        for format in TARGET_FORMATS {
+            let path = self.cache_path(file_id, *format);
+            if let Err(e) = fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to remove cached conversion {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+/// Writes `content` to `input` and runs `command` on it, with `{input}`,
+/// `{output}` and each of `replacements` substituted in its arguments.
+/// Succeeds once the command exits cleanly having written `output`.
+pub async fn run(
+    command: &[String],
+    mut content: ContentStream,
+    input: &Path,
+    output: &Path,
+    replacements: &[(&str, &str)],
+) -> Result<(), ConvertError> {
+    let mut file = fs::File::create(input).await?;
+    while let Some(chunk) = content.next().await {
+        let chunk = chunk.map_err(|e| ConvertError::Source(e.to_string()))?;
+        file.write_all(&chunk).await?;
+    }
+    file.flush().await?;
+
+    let program = &command[0];
+    let args: Vec<String> = command[1..]
+        .iter()
+        .map(|arg| {
+            let arg = arg
+                .replace("{input}", &input.to_string_lossy())
+                .replace("{output}", &output.to_string_lossy());
+            replacements
+                .iter()
+                .fold(arg, |arg, (from, to)| arg.replace(from, to))
+        })
+        .collect();
+    let child = Command::new(program)
+        .args(&args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .output();
+
+    let result = tokio::time::timeout(CONVERT_TIMEOUT, child)
+        .await
+        .map_err(|_| ConvertError::TimedOut)??;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(ConvertError::Failed(format!(
+            "{} exited with {}: {}",
+            program,
+            result.status,
+            stderr.trim()
+        )));
+    }
+    if !fs::try_exists(output).await? {
+        return Err(ConvertError::Failed(format!(
+            "{} produced no output",
+            program
+        )));
+    }
+    Ok(())
+}
@@ -0,0 +1,50 @@
+use crate::config::Config;
+use crate::models::CostEstimate;
+
+const BYTES_PER_GB: f64 = 1_000_000_000.0;
+/// Length of the month costs are projected over.
+const DAYS_PER_MONTH: f64 = 30.0;
+
+/// Prices used to turn storage and download volumes into cost estimates.
+#[derive(Debug, Clone)]
+pub struct CostRates {
+    pub storage_per_gb_month: f64,
+    pub egress_per_gb: f64,
+    pub currency: String,
+}
+
+impl CostRates {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            storage_per_gb_month: config.storage_cost_per_gb_month,
+            egress_per_gb: config.egress_cost_per_gb,
+            currency: config.cost_currency.clone(),
+        }
+    }
+
+    /// Estimates a month's cost of keeping `stored_bytes` and of serving
+    /// downloads at the rate of `downloaded_bytes` per `window_days`.
+    pub fn estimate(
+        &self,
+        stored_bytes: i64,
+        downloaded_bytes: i64,
+        window_days: u64,
+    ) -> CostEstimate {
+        let egress_bytes =
+            (downloaded_bytes as f64 * DAYS_PER_MONTH / window_days.max(1) as f64).round();
+        let storage_cost =
+            round_cents(stored_bytes as f64 / BYTES_PER_GB * self.storage_per_gb_month);
+        let egress_cost = round_cents(egress_bytes / BYTES_PER_GB * self.egress_per_gb);
+        CostEstimate {
+            stored_bytes,
+            egress_bytes: egress_bytes as i64,
+            storage_cost,
+            egress_cost,
+            total_cost: round_cents(storage_cost + egress_cost),
+        }
+    }
+}
+
+fn round_cents(amount: f64) -> f64 {
+    (amount * 100.0).round() / 100.0
+}
@@ -1,31 +1,584 @@
-use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
-use sqlx::{Pool, Sqlite};
+use chrono::Utc;
+use sqlx::any::{install_default_drivers, AnyArguments, AnyPoolOptions, AnyTypeInfo, AnyValueRef};
+use sqlx::error::BoxDynError;
+use sqlx::sqlite::SqliteConnectOptions;
+use sqlx::{Any, AnyConnection, Arguments, Decode, Encode, Pool, Type, TypeInfo, ValueRef};
+use std::collections::HashSet;
+use std::fmt::Write;
+use std::path::PathBuf;
 use std::str::FromStr;
 use tracing::info;
 
-pub type DbPool = Pool<Sqlite>;
+/// A pool on either backend. Queries use `$1`-style placeholders, which
+/// SQLite accepts too, and stick to SQL both understand.
+pub type DbPool = Pool<Any>;
+pub type DbConnection = AnyConnection;
 
-pub async fn init_db(database_url: &str) -> Result<DbPool, sqlx::Error> {
-    info!("Initializing database connection...");
+/// The database behind `DATABASE_URL`, told apart by its scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Sqlite,
+    Postgres,
+}
 
-    let options = SqliteConnectOptions::from_str(database_url)?
-        .create_if_missing(true);
+impl Backend {
+    pub fn from_url(database_url: &str) -> Option<Self> {
+        let scheme = database_url.split(':').next()?.to_ascii_lowercase();
+        match scheme.as_str() {
+            "sqlite" => Some(Backend::Sqlite),
+            "postgres" | "postgresql" => Some(Backend::Postgres),
+            _ => None,
+        }
+    }
 
-    let pool = SqlitePoolOptions::new()
-        .max_connections(5)
-        .connect_with(options)
-        .await?;
+    pub fn name(self) -> &'static str {
+        match self {
+            Backend::Sqlite => "sqlite",
+            Backend::Postgres => "postgres",
+        }
+    }
+}
+
+/// A column that may be NULL. The `Any` driver never reports a value as
+/// NULL, so a plain `Option<T>` fails to decode one; this looks at the
+/// value's type instead. `Option` fields of rows read it through
+/// `#[sqlx(try_from = "Nullable<T>")]`.
+pub struct Nullable<T>(pub Option<T>);
+
+impl<T: Type<Any>> Type<Any> for Nullable<T> {
+    fn type_info() -> AnyTypeInfo {
+        T::type_info()
+    }
+
+    fn compatible(ty: &AnyTypeInfo) -> bool {
+        is_null(ty) || T::compatible(ty)
+    }
+}
+
+impl<'r, T: Decode<'r, Any>> Decode<'r, Any> for Nullable<T> {
+    fn decode(value: AnyValueRef<'r>) -> Result<Self, BoxDynError> {
+        if is_null(&value.type_info()) {
+            return Ok(Nullable(None));
+        }
+        T::decode(value).map(|value| Nullable(Some(value)))
+    }
+}
+
+impl<T> From<Nullable<T>> for Option<T> {
+    fn from(value: Nullable<T>) -> Self {
+        value.0
+    }
+}
+
+fn is_null(ty: &AnyTypeInfo) -> bool {
+    ty.name() == "NULL"
+}
+
+/// SQL assembled at runtime. Unlike `sqlx::QueryBuilder`, which writes `?`
+/// for `Any`, it numbers placeholders the way PostgreSQL needs them.
+pub struct NumberedQuery<'q> {
+    sql: String,
+    args: AnyArguments<'q>,
+    bound: usize,
+}
+
+impl<'q> NumberedQuery<'q> {
+    pub fn new(sql: &str) -> Self {
+        Self {
+            sql: sql.to_string(),
+            args: AnyArguments::default(),
+            bound: 0,
+        }
+    }
+
+    pub fn push(&mut self, sql: &str) -> &mut Self {
+        self.sql.push_str(sql);
+        self
+    }
+
+    pub fn push_bind<T>(&mut self, value: T) -> &mut Self
+    where
+        T: 'q + Send + Encode<'q, Any> + Type<Any>,
+    {
+        self.args.add(value);
+        self.bound += 1;
+        let _ = write!(self.sql, "${}", self.bound);
+        self
+    }
+
+    /// The SQL and its arguments, for `sqlx::query_with` and friends.
+    pub fn into_parts(self) -> (String, AnyArguments<'q>) {
+        (self.sql, self.args)
+    }
+}
+
+/// A schema change, applied once and recorded in `schema_migrations`.
+/// Each has a SQLite and a PostgreSQL version of the same schema.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+    pub postgres_sql: &'static str,
+}
+
+impl Migration {
+    pub fn sql_for(&self, backend: Backend) -> &'static str {
+        match backend {
+            Backend::Sqlite => self.sql,
+            Backend::Postgres => self.postgres_sql,
+        }
+    }
+}
+
+/// Every migration, in the order it must be applied.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "create_files_table",
+        sql: include_str!("../migrations/001_create_files_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/001_create_files_table.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "create_directories_table",
+        sql: include_str!("../migrations/002_create_directories_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/002_create_directories_table.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "create_upload_receipts_table",
+        sql: include_str!("../migrations/003_create_upload_receipts_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/003_create_upload_receipts_table.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "create_chunk_tables",
+        sql: include_str!("../migrations/004_create_chunk_tables.sql"),
+        postgres_sql: include_str!("../migrations/postgres/004_create_chunk_tables.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "index_files_storage_path",
+        sql: include_str!("../migrations/005_index_files_storage_path.sql"),
+        postgres_sql: include_str!("../migrations/postgres/005_index_files_storage_path.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "create_links_table",
+        sql: include_str!("../migrations/006_create_links_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/006_create_links_table.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "create_retention_policies_table",
+        sql: include_str!("../migrations/007_create_retention_policies_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/007_create_retention_policies_table.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "create_file_access_stats_table",
+        sql: include_str!("../migrations/008_create_file_access_stats_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/008_create_file_access_stats_table.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "create_file_access_log_table",
+        sql: include_str!("../migrations/009_create_file_access_log_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/009_create_file_access_log_table.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "create_notification_tables",
+        sql: include_str!("../migrations/010_create_notification_tables.sql"),
+        postgres_sql: include_str!("../migrations/postgres/010_create_notification_tables.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "create_push_subscriptions_table",
+        sql: include_str!("../migrations/011_create_push_subscriptions_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/011_create_push_subscriptions_table.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "create_directory_integrations_table",
+        sql: include_str!("../migrations/012_create_directory_integrations_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/012_create_directory_integrations_table.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "create_upload_sessions_table",
+        sql: include_str!("../migrations/013_create_upload_sessions_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/013_create_upload_sessions_table.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "create_clipboard_entries_table",
+        sql: include_str!("../migrations/014_create_clipboard_entries_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/014_create_clipboard_entries_table.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "create_shares_table",
+        sql: include_str!("../migrations/015_create_shares_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/015_create_shares_table.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "create_audio_metadata_table",
+        sql: include_str!("../migrations/016_create_audio_metadata_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/016_create_audio_metadata_table.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "create_file_attachments_table",
+        sql: include_str!("../migrations/017_create_file_attachments_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/017_create_file_attachments_table.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "create_directory_arrangement_tables",
+        sql: include_str!("../migrations/018_create_directory_arrangement_tables.sql"),
+        postgres_sql: include_str!("../migrations/postgres/018_create_directory_arrangement_tables.sql"),
+    },
+    Migration {
+        version: 19,
+        name: "add_directory_color_and_icon",
+        sql: include_str!("../migrations/019_add_directory_color_and_icon.sql"),
+        postgres_sql: include_str!("../migrations/postgres/019_add_directory_color_and_icon.sql"),
+    },
+    Migration {
+        version: 20,
+        name: "create_file_tags_and_expirations_tables",
+        sql: include_str!("../migrations/020_create_file_tags_and_expirations_tables.sql"),
+        postgres_sql: include_str!("../migrations/postgres/020_create_file_tags_and_expirations_tables.sql"),
+    },
+    Migration {
+        version: 21,
+        name: "add_entity_versions",
+        sql: include_str!("../migrations/021_add_entity_versions.sql"),
+        postgres_sql: include_str!("../migrations/postgres/021_add_entity_versions.sql"),
+    },
+    Migration {
+        version: 22,
+        name: "create_quota_grace_periods_table",
+        sql: include_str!("../migrations/022_create_quota_grace_periods_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/022_create_quota_grace_periods_table.sql"),
+    },
+    Migration {
+        version: 23,
+        name: "add_share_terms",
+        sql: include_str!("../migrations/023_add_share_terms.sql"),
+        postgres_sql: include_str!("../migrations/postgres/023_add_share_terms.sql"),
+    },
+    Migration {
+        version: 24,
+        name: "create_directory_shares_table",
+        sql: include_str!("../migrations/024_create_directory_shares_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/024_create_directory_shares_table.sql"),
+    },
+    Migration {
+        version: 25,
+        name: "create_upload_links_tables",
+        sql: include_str!("../migrations/025_create_upload_links_tables.sql"),
+        postgres_sql: include_str!("../migrations/postgres/025_create_upload_links_tables.sql"),
+    },
+    Migration {
+        version: 26,
+        name: "create_files_search_index",
+        sql: include_str!("../migrations/026_create_files_search_index.sql"),
+        postgres_sql: include_str!("../migrations/postgres/026_create_files_search_index.sql"),
+    },
+    Migration {
+        version: 27,
+        name: "create_quarantine_tables",
+        sql: include_str!("../migrations/027_create_quarantine_tables.sql"),
+        postgres_sql: include_str!("../migrations/postgres/027_create_quarantine_tables.sql"),
+    },
+    Migration {
+        version: 28,
+        name: "add_share_download_limits",
+        sql: include_str!("../migrations/028_add_share_download_limits.sql"),
+        postgres_sql: include_str!("../migrations/postgres/028_add_share_download_limits.sql"),
+    },
+    Migration {
+        version: 29,
+        name: "create_drop_links_table",
+        sql: include_str!("../migrations/029_create_drop_links_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/029_create_drop_links_table.sql"),
+    },
+    Migration {
+        version: 30,
+        name: "create_users_table",
+        sql: include_str!("../migrations/030_create_users_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/030_create_users_table.sql"),
+    },
+    Migration {
+        version: 31,
+        name: "create_oidc_identities_table",
+        sql: include_str!("../migrations/031_create_oidc_identities_table.sql"),
+        postgres_sql: include_str!(
+            "../migrations/postgres/031_create_oidc_identities_table.sql"
+        ),
+    },
+    Migration {
+        version: 32,
+        name: "add_upload_session_expiry",
+        sql: include_str!("../migrations/032_add_upload_session_expiry.sql"),
+        postgres_sql: include_str!("../migrations/postgres/032_add_upload_session_expiry.sql"),
+    },
+    Migration {
+        version: 33,
+        name: "create_directory_acl_table",
+        sql: include_str!("../migrations/033_create_directory_acl_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/033_create_directory_acl_table.sql"),
+    },
+    Migration {
+        version: 34,
+        name: "create_thumbnail_policies_table",
+        sql: include_str!("../migrations/034_create_thumbnail_policies_table.sql"),
+        postgres_sql: include_str!(
+            "../migrations/postgres/034_create_thumbnail_policies_table.sql"
+        ),
+    },
+    Migration {
+        version: 35,
+        name: "add_file_checksums",
+        sql: include_str!("../migrations/035_add_file_checksums.sql"),
+        postgres_sql: include_str!("../migrations/postgres/035_add_file_checksums.sql"),
+    },
+    Migration {
+        version: 36,
+        name: "create_blobs_table",
+        sql: include_str!("../migrations/036_create_blobs_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/036_create_blobs_table.sql"),
+    },
+    Migration {
+        version: 37,
+        name: "create_archive_jobs_table",
+        sql: include_str!("../migrations/037_create_archive_jobs_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/037_create_archive_jobs_table.sql"),
+    },
+    Migration {
+        version: 38,
+        name: "create_segment_checksums_table",
+        sql: include_str!("../migrations/038_create_segment_checksums_table.sql"),
+        postgres_sql: include_str!(
+            "../migrations/postgres/038_create_segment_checksums_table.sql"
+        ),
+    },
+    Migration {
+        version: 39,
+        name: "add_trash",
+        sql: include_str!("../migrations/039_add_trash.sql"),
+        postgres_sql: include_str!("../migrations/postgres/039_add_trash.sql"),
+    },
+    Migration {
+        version: 40,
+        name: "create_file_versions_table",
+        sql: include_str!("../migrations/040_create_file_versions_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/040_create_file_versions_table.sql"),
+    },
+    Migration {
+        version: 41,
+        name: "create_integration_deliveries_table",
+        sql: include_str!("../migrations/041_create_integration_deliveries_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/041_create_integration_deliveries_table.sql"),
+    },
+    Migration {
+        version: 42,
+        name: "create_share_reports_table",
+        sql: include_str!("../migrations/042_create_share_reports_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/042_create_share_reports_table.sql"),
+    },
+    Migration {
+        version: 43,
+        name: "create_bundle_shares_table",
+        sql: include_str!("../migrations/043_create_bundle_shares_table.sql"),
+        postgres_sql: include_str!("../migrations/postgres/043_create_bundle_shares_table.sql"),
+    },
+    Migration {
+        version: 44,
+        name: "create_metadata_journal",
+        sql: include_str!("../migrations/044_create_metadata_journal.sql"),
+        postgres_sql: include_str!("../migrations/postgres/044_create_metadata_journal.sql"),
+    },
+];
+
+pub async fn init_db(database_url: &str) -> Result<DbPool, sqlx::Error> {
+    info!("Initializing database connection...");
+    let pool = connect(database_url).await?;
 
     // Run migrations
     info!("Running database migrations...");
-    sqlx::query(include_str!("../migrations/001_create_files_table.sql"))
-        .execute(&pool)
-        .await?;
-
-    sqlx::query(include_str!("../migrations/002_create_directories_table.sql"))
-        .execute(&pool)
-        .await?;
+    migrate(&pool, database_url).await?;
 
     info!("Database initialized successfully");
     Ok(pool)
 }
+
+/// Applies every pending migration, each in its own transaction, after
+/// backing up an existing database. Returns the backup's path, if one was made.
+pub async fn migrate(pool: &DbPool, database_url: &str) -> Result<Option<PathBuf>, sqlx::Error> {
+    let backend = backend(database_url)?;
+    let pending = pending_migrations(pool).await?;
+    let Some(last) = pending.last() else {
+        return Ok(None);
+    };
+
+    let backup = match backend {
+        Backend::Sqlite => backup_before_migrating(pool, database_url, last.version).await?,
+        // PostgreSQL servers are backed up with their own tools.
+        Backend::Postgres => None,
+    };
+    if let Some(path) = &backup {
+        info!("Backed up database to {:?} before migrating", path);
+    }
+
+    for migration in pending {
+        info!("Applying migration {:03} {}", migration.version, migration.name);
+        let mut tx = pool.begin().await?;
+        sqlx::raw_sql(migration.sql_for(backend)).execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO schema_migrations (version, name, applied_at) VALUES ($1, $2, $3)")
+            .bind(migration.version)
+            .bind(migration.name)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+    }
+    Ok(backup)
+}
+
+/// The backend `database_url` points at, or an error for an unknown scheme
+/// or a backend this build doesn't include.
+pub fn backend(database_url: &str) -> Result<Backend, sqlx::Error> {
+    match Backend::from_url(database_url) {
+        Some(Backend::Postgres) if !cfg!(feature = "postgres") => Err(sqlx::Error::Configuration(
+            "PostgreSQL support is not compiled in; build with --features postgres".into(),
+        )),
+        Some(backend) => Ok(backend),
+        None => Err(sqlx::Error::Configuration(
+            format!("DATABASE_URL must start with sqlite: or postgres://, got {}", database_url)
+                .into(),
+        )),
+    }
+}
+
+/// `database_url` with its password masked, for logs and status output.
+pub fn redacted_url(database_url: &str) -> String {
+    let Some((scheme, rest)) = database_url.split_once("://") else {
+        return database_url.to_string();
+    };
+    let authority_end = rest.find('/').unwrap_or(rest.len());
+    match rest[..authority_end].rfind('@') {
+        Some(at) => match rest[..at].split_once(':') {
+            Some((user, _)) => format!("{}://{}:***{}", scheme, user, &rest[at..]),
+            None => database_url.to_string(),
+        },
+        None => database_url.to_string(),
+    }
+}
+
+/// Opens the database, creating a SQLite file if needed, without migrating it.
+pub async fn connect(database_url: &str) -> Result<DbPool, sqlx::Error> {
+    install_default_drivers();
+    // Any connections take their options from the URL alone.
+    let option = match backend(database_url)? {
+        Backend::Sqlite if database_url.contains(":memory:") => None,
+        Backend::Sqlite => Some(("mode", "rwc")),
+        // The Any driver binds NULL as an integer, so a cached statement
+        // first prepared with a NULL argument would reject text when reused.
+        Backend::Postgres => Some(("statement-cache-capacity", "0")),
+    };
+    let url = match option {
+        Some((key, value)) if !database_url.contains(&format!("{}=", key)) => {
+            let separator = if database_url.contains('?') { '&' } else { '?' };
+            format!("{}{}{}={}", database_url, separator, key, value)
+        }
+        _ => database_url.to_string(),
+    };
+
+    AnyPoolOptions::new().max_connections(5).connect(&url).await
+}
+
+/// Migrations not yet recorded as applied. Databases created before
+/// migrations were tracked simply re-run them all; each one is idempotent.
+pub async fn pending_migrations(pool: &DbPool) -> Result<Vec<&'static Migration>, sqlx::Error> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL
+        )
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    let applied: HashSet<i64> = sqlx::query_scalar("SELECT version FROM schema_migrations")
+        .fetch_all(pool)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|m| !applied.contains(&m.version))
+        .collect())
+}
+
+/// Whether `table` exists in the database `database_url` points at.
+pub async fn table_exists(
+    pool: &DbPool,
+    database_url: &str,
+    table: &str,
+) -> Result<bool, sqlx::Error> {
+    let sql = match backend(database_url)? {
+        Backend::Sqlite => "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = $1",
+        Backend::Postgres => {
+            "SELECT COUNT(*) FROM information_schema.tables \
+             WHERE table_schema = current_schema() AND table_name = $1"
+        }
+    };
+    let count: i64 = sqlx::query_scalar(sql).bind(table).fetch_one(pool).await?;
+    Ok(count > 0)
+}
+
+/// Copies an existing SQLite database next to itself before it is migrated,
+/// as `<file>.pre-<version>-<timestamp>.bak`. Returns `None` for new or
+/// in-memory databases, which have nothing worth keeping.
+async fn backup_before_migrating(
+    pool: &DbPool,
+    database_url: &str,
+    target_version: i64,
+) -> Result<Option<PathBuf>, sqlx::Error> {
+    let path = SqliteConnectOptions::from_str(database_url)?.get_filename().into_owned();
+    if path.as_os_str() == ":memory:" || !path.exists() {
+        return Ok(None);
+    }
+
+    let existing_tables: i64 = sqlx::query_scalar(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name != 'schema_migrations'",
+    )
+    .fetch_one(pool)
+    .await?;
+    if existing_tables == 0 {
+        return Ok(None);
+    }
+
+    let mut backup = path.clone().into_os_string();
+    backup.push(format!(
+        ".pre-{:03}-{}.bak",
+        target_version,
+        Utc::now().format("%Y%m%d%H%M%S")
+    ));
+    let backup = PathBuf::from(backup);
+
+    // VACUUM INTO writes a consistent copy even while other connections are open.
+    sqlx::query("VACUUM INTO $1")
+        .bind(backup.to_string_lossy().into_owned())
+        .execute(pool)
+        .await?;
+    Ok(Some(backup))
+}
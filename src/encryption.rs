@@ -0,0 +1,403 @@
+use crate::config::Config;
+use crate::file_io::FileIo;
+use crate::storage::ContentStream;
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use axum::body::Bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures_util::{stream, StreamExt};
+use rand::RngCore;
+use std::fs;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+/// Starts every file stored encrypted, followed by the file's nonce prefix.
+/// Files without it are read as they are, so content stored before
+/// encryption was turned on stays readable.
+const MAGIC: &[u8; 8] = b"\x89FSENC1\n";
+
+/// Random per file; each segment's nonce appends the segment's index and
+/// whether it is the last (the STREAM construction), so segments can't be
+/// reordered, dropped or cut off at the end unnoticed.
+const NONCE_PREFIX_LEN: usize = 7;
+
+const HEADER_LEN: u64 = (MAGIC.len() + NONCE_PREFIX_LEN) as u64;
+
+/// Plaintext bytes per segment, each sealed on its own so ranges can be
+/// read without decrypting the file from the start.
+const SEGMENT_SIZE: usize = 64 * 1024;
+
+const TAG_LEN: u64 = 16;
+
+const SEALED_SEGMENT_LEN: u64 = SEGMENT_SIZE as u64 + TAG_LEN;
+
+#[derive(Debug, thiserror::Error)]
+pub enum EncryptionConfigError {
+    #[error("Set only one of ENCRYPTION_KEY and ENCRYPTION_KEY_FILE")]
+    BothKeys,
+    #[error("Failed to read ENCRYPTION_KEY_FILE: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0} must hold a 256-bit key, as 64 hex digits or in base64")]
+    Key(&'static str),
+}
+
+/// AES-256-GCM for file content at rest. Content is stored as a header and
+/// a sequence of 64 KiB segments, each with its own authentication tag.
+#[derive(Clone)]
+pub struct Cipher(Arc<Aes256Gcm>);
+
+impl Cipher {
+    /// The key set with `ENCRYPTION_KEY` or `ENCRYPTION_KEY_FILE`, or
+    /// `None` when neither is set and content is stored in the clear.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, EncryptionConfigError> {
+        let (encoded, source) = match (&config.encryption_key, &config.encryption_key_file) {
+            (Some(_), Some(_)) => return Err(EncryptionConfigError::BothKeys),
+            (Some(key), None) => (key.clone(), "ENCRYPTION_KEY"),
+            (None, Some(path)) => (fs::read_to_string(path)?, "ENCRYPTION_KEY_FILE"),
+            (None, None) => return Ok(None),
+        };
+        let encoded = encoded.trim();
+        let key = hex::decode(encoded)
+            .ok()
+            .or_else(|| STANDARD.decode(encoded).ok())
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or(EncryptionConfigError::Key(source))?;
+        Ok(Some(Self(Arc::new(Aes256Gcm::new(
+            &Key::<Aes256Gcm>::from(key),
+        )))))
+    }
+
+    /// Starts encrypting one file's content.
+    pub fn encryptor(&self) -> Encryptor {
+        let mut prefix = [0u8; NONCE_PREFIX_LEN];
+        rand::thread_rng().fill_bytes(&mut prefix);
+        Encryptor {
+            cipher: self.clone(),
+            prefix,
+            index: 0,
+            pending: Vec::with_capacity(SEGMENT_SIZE),
+        }
+    }
+
+    /// Encrypts content held in memory as a whole.
+    pub fn encrypt(&self, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        let mut encryptor = self.encryptor();
+        let mut sealed = Vec::with_capacity(plaintext.len() + plaintext.len() / 1024 + 64);
+        for segment in encryptor.update(plaintext)? {
+            sealed.extend_from_slice(&segment);
+        }
+        sealed.extend_from_slice(&encryptor.finish()?);
+        Ok(sealed)
+    }
+
+    fn seal(&self, prefix: &[u8], index: u32, last: bool, plaintext: &[u8]) -> io::Result<Vec<u8>> {
+        self.0
+            .encrypt(&nonce(prefix, index, last), plaintext)
+            .map_err(|_| io::Error::other("encryption failed"))
+    }
+
+    fn open(&self, prefix: &[u8], index: u64, last: bool, sealed: &[u8]) -> io::Result<Vec<u8>> {
+        let index = u32::try_from(index).map_err(|_| corrupt())?;
+        self.0
+            .decrypt(&nonce(prefix, index, last), sealed)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "encrypted content failed authentication; it was altered or the key is wrong",
+                )
+            })
+    }
+}
+
+fn nonce(prefix: &[u8], index: u32, last: bool) -> Nonce<aes_gcm::aead::consts::U12> {
+    let mut nonce = [0u8; 12];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..11].copy_from_slice(&index.to_be_bytes());
+    nonce[11] = u8::from(last);
+    Nonce::from(nonce)
+}
+
+fn corrupt() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        "encrypted content is truncated or corrupt",
+    )
+}
+
+/// Encrypts one file's content as it is written, a piece at a time.
+pub struct Encryptor {
+    cipher: Cipher,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    index: u32,
+    pending: Vec<u8>,
+}
+
+impl Encryptor {
+    /// Takes the next piece of content, returning what is ready to be
+    /// written: the header, then each segment it completes. A full segment
+    /// is only sealed once more content follows, since the last one is
+    /// sealed differently.
+    pub fn update(&mut self, mut data: &[u8]) -> io::Result<Vec<Bytes>> {
+        let mut sealed = Vec::new();
+        while !data.is_empty() {
+            if self.pending.len() == SEGMENT_SIZE {
+                let segment =
+                    std::mem::replace(&mut self.pending, Vec::with_capacity(SEGMENT_SIZE));
+                sealed.push(self.seal(&segment, false)?);
+            }
+            let take = (SEGMENT_SIZE - self.pending.len()).min(data.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+        }
+        Ok(sealed)
+    }
+
+    /// Seals the last segment, which may be empty, returning what is left to
+    /// write.
+    pub fn finish(mut self) -> io::Result<Bytes> {
+        let segment = std::mem::take(&mut self.pending);
+        self.seal(&segment, true)
+    }
+
+    fn seal(&mut self, segment: &[u8], last: bool) -> io::Result<Bytes> {
+        let mut sealed = Vec::with_capacity(HEADER_LEN as usize + segment.len() + TAG_LEN as usize);
+        if self.index == 0 {
+            sealed.extend_from_slice(MAGIC);
+            sealed.extend_from_slice(&self.prefix);
+        }
+        sealed.extend(self.cipher.seal(&self.prefix, self.index, last, segment)?);
+        self.index = self
+            .index
+            .checked_add(1)
+            .ok_or_else(|| io::Error::other("file too large to encrypt"))?;
+        Ok(Bytes::from(sealed))
+    }
+}
+
+/// Where the segments of an encrypted file lie.
+#[derive(Debug, Clone, Copy)]
+struct Segments {
+    count: u64,
+    /// Stored bytes after the header.
+    sealed_len: u64,
+    /// Size of the content.
+    size: u64,
+}
+
+impl Segments {
+    fn of(stored_len: u64) -> io::Result<Self> {
+        let sealed_len = stored_len
+            .checked_sub(HEADER_LEN)
+            .filter(|len| *len >= TAG_LEN)
+            .ok_or_else(corrupt)?;
+        let count = sealed_len.div_ceil(SEALED_SEGMENT_LEN);
+        let size = sealed_len
+            .checked_sub(count * TAG_LEN)
+            .ok_or_else(corrupt)?;
+        Ok(Self {
+            count,
+            sealed_len,
+            size,
+        })
+    }
+
+    fn offset(&self, index: u64) -> u64 {
+        HEADER_LEN + index * SEALED_SEGMENT_LEN
+    }
+
+    fn sealed_len(&self, index: u64) -> u64 {
+        if index + 1 < self.count {
+            SEALED_SEGMENT_LEN
+        } else {
+            self.sealed_len - index * SEALED_SEGMENT_LEN
+        }
+    }
+
+    fn is_last(&self, index: u64) -> bool {
+        index + 1 == self.count
+    }
+}
+
+/// The nonce prefix of the file at `path` if it is stored encrypted.
+async fn read_header(path: &Path) -> io::Result<Option<[u8; NONCE_PREFIX_LEN]>> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    (&mut file)
+        .take(HEADER_LEN)
+        .read_to_end(&mut header)
+        .await?;
+    Ok(prefix_of(&header))
+}
+
+fn prefix_of(header: &[u8]) -> Option<[u8; NONCE_PREFIX_LEN]> {
+    let prefix = header.strip_prefix(MAGIC.as_slice())?;
+    prefix.try_into().ok()
+}
+
+/// Whether the file at `path` is stored encrypted.
+pub async fn is_encrypted(path: &Path) -> io::Result<bool> {
+    Ok(read_header(path).await?.is_some())
+}
+
+fn missing_key(path: &Path) -> io::Error {
+    io::Error::other(format!(
+        "{:?} is stored encrypted, but no ENCRYPTION_KEY is set",
+        path
+    ))
+}
+
+/// Streams `length` bytes of the content stored at `path` from byte `start`,
+/// or all of it from there, decrypting it if it is stored encrypted. Only
+/// the segments overlapping the range are read.
+pub async fn read(
+    io: &FileIo,
+    cipher: Option<&Cipher>,
+    path: PathBuf,
+    start: u64,
+    length: Option<u64>,
+) -> io::Result<ContentStream> {
+    let Some(prefix) = read_header(&path).await? else {
+        return io.read(path, start, length).await;
+    };
+    let cipher = cipher.ok_or_else(|| missing_key(&path))?.clone();
+    let segments = Segments::of(tokio::fs::metadata(&path).await?.len())?;
+    let end = length.map_or(segments.size, |length| {
+        start.saturating_add(length).min(segments.size)
+    });
+    if start >= end {
+        return Ok(stream::empty().boxed());
+    }
+
+    let (first, last) = (start / SEGMENT_SIZE as u64, (end - 1) / SEGMENT_SIZE as u64);
+    let offset = segments.offset(first);
+    let sealed = io
+        .read(
+            path,
+            offset,
+            Some(segments.offset(last) + segments.sealed_len(last) - offset),
+        )
+        .await?;
+    let mut state = Decrypting {
+        sealed,
+        cipher,
+        prefix,
+        segments,
+        index: first,
+        buffer: Vec::new(),
+        skip: (start % SEGMENT_SIZE as u64) as usize,
+        remaining: end - start,
+    };
+    // Opened up front, so a wrong key fails the request rather than cutting
+    // off a download whose headers were already sent.
+    let opened = state.next_segment().await?;
+    let rest = stream::unfold(state, |mut state| async move {
+        if state.remaining == 0 {
+            return None;
+        }
+        let next = state.next_segment().await;
+        if next.is_err() {
+            state.remaining = 0;
+        }
+        Some((next, state))
+    });
+    Ok(stream::once(async { Ok(opened) }).chain(rest).boxed())
+}
+
+struct Decrypting {
+    sealed: ContentStream,
+    cipher: Cipher,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    segments: Segments,
+    index: u64,
+    buffer: Vec<u8>,
+    /// Bytes of the next segment before the range.
+    skip: usize,
+    remaining: u64,
+}
+
+impl Decrypting {
+    async fn next_segment(&mut self) -> io::Result<Bytes> {
+        let len = self.segments.sealed_len(self.index) as usize;
+        while self.buffer.len() < len {
+            match self.sealed.next().await {
+                Some(chunk) => self.buffer.extend_from_slice(&chunk?),
+                None => return Err(corrupt()),
+            }
+        }
+        let rest = self.buffer.split_off(len);
+        let sealed = std::mem::replace(&mut self.buffer, rest);
+        let plaintext = Bytes::from(self.cipher.open(
+            &self.prefix,
+            self.index,
+            self.segments.is_last(self.index),
+            &sealed,
+        )?);
+        let from = self.skip.min(plaintext.len());
+        let to = (from as u64 + self.remaining).min(plaintext.len() as u64) as usize;
+        self.skip = 0;
+        self.remaining -= (to - from) as u64;
+        self.index += 1;
+        Ok(plaintext.slice(from..to))
+    }
+}
+
+/// Opens the content stored at `path` for reading from the start,
+/// decrypting it if it is stored encrypted. Blocking; run it on the
+/// blocking thread pool.
+pub fn open_blocking(path: &Path, cipher: Option<&Cipher>) -> io::Result<Box<dyn Read + Send>> {
+    let mut file = fs::File::open(path)?;
+    let mut header = Vec::with_capacity(HEADER_LEN as usize);
+    (&mut file).take(HEADER_LEN).read_to_end(&mut header)?;
+    let Some(prefix) = prefix_of(&header) else {
+        file.seek(SeekFrom::Start(0))?;
+        return Ok(Box::new(file));
+    };
+    let cipher = cipher.ok_or_else(|| missing_key(path))?.clone();
+    let segments = Segments::of(file.metadata()?.len())?;
+    Ok(Box::new(DecryptingReader {
+        file: io::BufReader::with_capacity(SEALED_SEGMENT_LEN as usize, file),
+        cipher,
+        prefix,
+        segments,
+        index: 0,
+        plaintext: Vec::new(),
+        position: 0,
+    }))
+}
+
+struct DecryptingReader {
+    file: io::BufReader<fs::File>,
+    cipher: Cipher,
+    prefix: [u8; NONCE_PREFIX_LEN],
+    segments: Segments,
+    index: u64,
+    plaintext: Vec<u8>,
+    position: usize,
+}
+
+impl Read for DecryptingReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position == self.plaintext.len() {
+            if self.index == self.segments.count {
+                return Ok(0);
+            }
+            let mut sealed = vec![0u8; self.segments.sealed_len(self.index) as usize];
+            self.file.read_exact(&mut sealed).map_err(|_| corrupt())?;
+            self.plaintext = self.cipher.open(
+                &self.prefix,
+                self.index,
+                self.segments.is_last(self.index),
+                &sealed,
+            )?;
+            self.position = 0;
+            self.index += 1;
+        }
+        let read = buf.len().min(self.plaintext.len() - self.position);
+        buf[..read].copy_from_slice(&self.plaintext[self.position..self.position + read]);
+        self.position += read;
+        Ok(read)
+    }
+}
@@ -0,0 +1,204 @@
+use chrono::Utc;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use ts_rs::TS;
+use utoipa::ToSchema;
+use uuid::Uuid;
+
+/// A change to the file tree, published for API mutations and for changes
+/// picked up from watched directories alike, plus completion of server jobs
+/// and storage quota warnings.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ChangeEvent {
+    FileCreated {
+        file_id: String,
+        name: String,
+        parent_directory_id: Option<String>,
+    },
+    FileDeleted {
+        file_id: String,
+        parent_directory_id: Option<String>,
+    },
+    FileMoved {
+        file_id: String,
+        parent_directory_id: Option<String>,
+    },
+    /// A new version of a file's content was uploaded, or an earlier one
+    /// restored.
+    FileUpdated {
+        file_id: String,
+        name: String,
+        parent_directory_id: Option<String>,
+    },
+    DirectoryCreated {
+        directory_id: String,
+        name: String,
+        parent_id: Option<String>,
+    },
+    DirectoryDeleted {
+        directory_id: String,
+        parent_id: Option<String>,
+    },
+    DirectoryMoved {
+        directory_id: String,
+        parent_id: Option<String>,
+    },
+    FileRenamed {
+        file_id: String,
+        name: String,
+        parent_directory_id: Option<String>,
+    },
+    DirectoryRenamed {
+        directory_id: String,
+        name: String,
+        parent_id: Option<String>,
+    },
+    LinkCreated {
+        link_id: String,
+        name: String,
+        parent_directory_id: Option<String>,
+    },
+    LinkDeleted {
+        link_id: String,
+        parent_directory_id: Option<String>,
+    },
+    /// A file was flagged and is hidden until an admin reviews it; clients
+    /// treat it like a deletion.
+    FileQuarantined {
+        file_id: String,
+        parent_directory_id: Option<String>,
+    },
+    /// A quarantined file was released and is visible again.
+    FileReleased {
+        file_id: String,
+        name: String,
+        parent_directory_id: Option<String>,
+    },
+    /// A long-running job such as an archive ingest has completed.
+    JobFinished {
+        job: String,
+        parent_directory_id: Option<String>,
+        message: String,
+    },
+    /// Stored files have gone past the soft quota; uploads are refused
+    /// after `grace_ends_at` unless space is freed.
+    QuotaExceeded {
+        #[ts(type = "number")]
+        used_bytes: u64,
+        #[ts(type = "number")]
+        soft_limit: u64,
+        grace_ends_at: String,
+    },
+}
+
+impl ChangeEvent {
+    /// The directory the change happened in, `Some(None)` being the root;
+    /// `None` for events that aren't about the file tree.
+    pub fn directory_id(&self) -> Option<Option<&str>> {
+        match self {
+            Self::FileCreated {
+                parent_directory_id,
+                ..
+            }
+            | Self::FileDeleted {
+                parent_directory_id,
+                ..
+            }
+            | Self::FileMoved {
+                parent_directory_id,
+                ..
+            }
+            | Self::FileUpdated {
+                parent_directory_id,
+                ..
+            }
+            | Self::FileRenamed {
+                parent_directory_id,
+                ..
+            }
+            | Self::LinkCreated {
+                parent_directory_id,
+                ..
+            }
+            | Self::LinkDeleted {
+                parent_directory_id,
+                ..
+            }
+            | Self::FileQuarantined {
+                parent_directory_id,
+                ..
+            }
+            | Self::FileReleased {
+                parent_directory_id,
+                ..
+            }
+            | Self::JobFinished {
+                parent_directory_id,
+                ..
+            } => Some(parent_directory_id.as_deref()),
+            Self::DirectoryCreated { parent_id, .. }
+            | Self::DirectoryDeleted { parent_id, .. }
+            | Self::DirectoryMoved { parent_id, .. }
+            | Self::DirectoryRenamed { parent_id, .. } => Some(parent_id.as_deref()),
+            Self::QuotaExceeded { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct Event {
+    pub id: String,
+    pub occurred_at: String,
+    #[serde(flatten)]
+    pub change: ChangeEvent,
+}
+
+/// Version of the JSON posted to webhook integrations. Fields may be added
+/// within a version; renaming or removing one, or changing its meaning,
+/// bumps it.
+pub const WEBHOOK_SCHEMA_VERSION: u32 = 1;
+
+/// An event as posted to a webhook integration.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct WebhookPayload {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+impl WebhookPayload {
+    pub fn new(event: Event) -> Self {
+        Self {
+            schema_version: WEBHOOK_SCHEMA_VERSION,
+            event,
+        }
+    }
+}
+
+/// In-process fan-out of change events to any number of subscribers.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: broadcast::Sender<Event>,
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity);
+        Self { sender }
+    }
+
+    pub fn publish(&self, change: ChangeEvent) {
+        let event = Event {
+            id: Uuid::new_v4().to_string(),
+            occurred_at: Utc::now().to_rfc3339(),
+            change,
+        };
+        // Sending only fails when nobody is subscribed, which is fine.
+        let _ = self.sender.send(event);
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.sender.subscribe()
+    }
+}
@@ -0,0 +1,222 @@
+use crate::config::Config;
+use crate::models::DiskBenchmark;
+use crate::storage::ContentStream;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+use crate::uring;
+use axum::body::Bytes;
+use futures_util::StreamExt;
+use rand::RngCore;
+use std::io::{self, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, BufWriter};
+use tokio::sync::mpsc;
+use tokio_util::io::ReaderStream;
+use tracing::warn;
+
+/// Values of `IO_BACKEND`.
+pub const BACKENDS: [&str; 2] = ["standard", "io_uring"];
+
+/// Bounds of `READ_BUFFER_SIZE` and `WRITE_BUFFER_SIZE`.
+pub const MIN_BUFFER_SIZE: usize = 4 * 1024;
+pub const MAX_BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+/// Size of the pieces a benchmark hands the writer, about what an upload
+/// arrives in.
+const BENCHMARK_CHUNK: usize = 64 * 1024;
+
+/// How stored file content is read and written: through tokio's blocking
+/// thread pool, or, on Linux builds with the `io-uring` feature, through
+/// io_uring, which saves a thread hop and syscalls per chunk on fast disks.
+/// Either way the disk sees reads and writes of the configured buffer sizes.
+#[derive(Debug, Clone)]
+pub struct FileIo {
+    read_buffer: usize,
+    write_buffer: usize,
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    uring: Option<uring::Driver>,
+}
+
+impl FileIo {
+    /// The backend `IO_BACKEND` asks for, or the standard one where io_uring
+    /// isn't available.
+    pub fn from_config(config: &Config) -> Self {
+        let io = Self {
+            read_buffer: config.read_buffer_size,
+            write_buffer: config.write_buffer_size,
+            #[cfg(all(target_os = "linux", feature = "io-uring"))]
+            uring: None,
+        };
+        if config.io_backend != "io_uring" {
+            return io;
+        }
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            match uring::Driver::start() {
+                Ok(driver) => {
+                    tracing::info!("Reading and writing file content through io_uring");
+                    return Self {
+                        uring: Some(driver),
+                        ..io
+                    };
+                }
+                Err(e) => warn!("io_uring is unavailable ({}); using standard file I/O", e),
+            }
+        }
+        #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+        warn!("io_uring support is not compiled in; using standard file I/O");
+        io
+    }
+
+    /// The same backend with other buffer sizes.
+    pub fn with_buffers(&self, read_buffer: usize, write_buffer: usize) -> Self {
+        let mut io = self.clone();
+        io.read_buffer = read_buffer;
+        io.write_buffer = write_buffer;
+        io
+    }
+
+    pub fn backend(&self) -> &'static str {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            if self.uring.is_some() {
+                return "io_uring";
+            }
+        }
+        "standard"
+    }
+
+    pub fn read_buffer(&self) -> usize {
+        self.read_buffer
+    }
+
+    pub fn write_buffer(&self) -> usize {
+        self.write_buffer
+    }
+
+    /// Streams `length` bytes of the file at `path` from byte `start`, or
+    /// all of it from there.
+    pub async fn read(
+        &self,
+        path: PathBuf,
+        start: u64,
+        length: Option<u64>,
+    ) -> io::Result<ContentStream> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            if let Some(driver) = &self.uring {
+                let chunks = driver.read(path, start, length, self.read_buffer).await?;
+                return Ok(tokio_stream::wrappers::ReceiverStream::new(chunks).boxed());
+            }
+        }
+
+        let mut file = File::open(path).await?;
+        if start > 0 {
+            file.seek(SeekFrom::Start(start)).await?;
+        }
+        Ok(match length {
+            Some(length) => {
+                ReaderStream::with_capacity(file.take(length), self.read_buffer).boxed()
+            }
+            None => ReaderStream::with_capacity(file, self.read_buffer).boxed(),
+        })
+    }
+
+    /// Writes the chunks received into `file`, calling `dequeued` with the
+    /// size of each chunk taken off the queue. On an error, the chunks
+    /// still queued are taken off and dropped.
+    pub async fn write(
+        &self,
+        file: File,
+        mut chunks: mpsc::Receiver<Bytes>,
+        dequeued: impl Fn(usize) + Send + 'static,
+    ) -> io::Result<()> {
+        #[cfg(all(target_os = "linux", feature = "io-uring"))]
+        {
+            if let Some(driver) = &self.uring {
+                let file = file.into_std().await;
+                return driver
+                    .write(file, chunks, Box::new(dequeued), self.write_buffer)
+                    .await;
+            }
+        }
+
+        let mut file = BufWriter::with_capacity(self.write_buffer, file);
+        let mut written = Ok(());
+        while let Some(chunk) = chunks.recv().await {
+            dequeued(chunk.len());
+            if let Err(e) = file.write_all(&chunk).await {
+                written = Err(e);
+                break;
+            }
+        }
+        if written.is_ok() {
+            return file.flush().await;
+        }
+        chunks.close();
+        while let Ok(chunk) = chunks.try_recv() {
+            dequeued(chunk.len());
+        }
+        written
+    }
+
+    /// Writes `size` bytes of random data to a scratch file in `dir`, syncs
+    /// it, reads it back and removes it, timing both directions.
+    pub async fn benchmark(&self, dir: &Path, size: u64) -> io::Result<DiskBenchmark> {
+        let path = dir.join(format!(".benchmark-{}", uuid::Uuid::new_v4()));
+        let measured = self.measure(&path, size).await;
+        let _ = tokio::fs::remove_file(&path).await;
+        let (write_secs, read_secs) = measured?;
+
+        let mib = size as f64 / (1024.0 * 1024.0);
+        Ok(DiskBenchmark {
+            io_backend: self.backend().to_string(),
+            size,
+            read_buffer_size: self.read_buffer as u64,
+            write_buffer_size: self.write_buffer as u64,
+            write_secs,
+            read_secs,
+            write_mib_per_sec: mib / write_secs.max(f64::EPSILON),
+            read_mib_per_sec: mib / read_secs.max(f64::EPSILON),
+        })
+    }
+
+    async fn measure(&self, path: &Path, size: u64) -> io::Result<(f64, f64)> {
+        let mut block = vec![0u8; 1024 * 1024];
+        rand::thread_rng().fill_bytes(&mut block);
+        let block = Bytes::from(block);
+
+        let started = Instant::now();
+        let (tx, rx) = mpsc::channel(16);
+        let writer = self.write(File::create(path).await?, rx, |_| {});
+        let feeder = async move {
+            let mut remaining = size;
+            for offset in (0..block.len()).step_by(BENCHMARK_CHUNK).cycle() {
+                let len = (BENCHMARK_CHUNK as u64).min(remaining) as usize;
+                if len == 0 || tx.send(block.slice(offset..offset + len)).await.is_err() {
+                    return;
+                }
+                remaining -= len as u64;
+            }
+        };
+        let (written, ()) = tokio::join!(writer, feeder);
+        written?;
+        File::open(path).await?.sync_all().await?;
+        let write_secs = started.elapsed().as_secs_f64();
+
+        let started = Instant::now();
+        let mut content = self.read(path.to_path_buf(), 0, None).await?;
+        let mut read = 0u64;
+        while let Some(chunk) = content.next().await {
+            read += chunk?.len() as u64;
+        }
+        if read != size {
+            return Err(io::Error::other(format!(
+                "read back {} of {} bytes",
+                read, size
+            )));
+        }
+        Ok((write_secs, started.elapsed().as_secs_f64()))
+    }
+}
@@ -1,38 +1,496 @@
+use crate::access::{self, AccessContext};
+use crate::acl::{Access, AclError, Role};
+use crate::archives;
+use crate::audio::{self, AudioAnalysis};
+use crate::auth::{self, AuthUser};
+use crate::build_info;
+use crate::checksum::{self, ExpectedChecksum, TrailedBody};
+use crate::compare;
+use crate::convert::{Converter, TargetFormat, TARGET_FORMATS};
+use crate::events::ChangeEvent;
+use crate::file_io;
+use crate::ingest::{self, IngestError};
+use crate::maintenance::MaintenanceError;
+use crate::integrations;
 use crate::models::{
-    BulkDeleteRequest, BulkDeleteResponse, CreateDirectoryRequest, CreateDirectoryResponse,
-    DeleteResponse, DirectoryResponse, ErrorResponse, FileResponse, ListFilesResponse,
-    MoveDirectoryRequest, MoveFileRequest, UploadResponse,
+    AccessLogResponse, ArchiveJob, ArchiveJobResponse, ArchiveLimitExceeded, AudioMetadata,
+    BatchOperation, BatchRequest, BatchResponse, Branding, BulkCopyResponse, BulkDeleteRequest,
+    BulkDeleteResponse, BulkItemResult, BulkMoveResponse, BulkTransferRequest, BulkUpdateRequest,
+    BulkUpdateResponse, BundleShare, BundleShareResponse, CapabilitiesResponse, ClipboardEntry,
+    CompareRequest, CompareResponse, CostReport, CreateAttachmentRequest, CreateBundleShareRequest,
+    CreateDirectoryIntegrationRequest, CreateDirectoryRequest, CreateDirectoryResponse,
+    CreateDropLinkRequest, CreateLinkRequest, CreatePushSubscriptionRequest,
+    CreateRetentionPolicyRequest, CreateShareRequest, CreateThumbnailPolicyRequest,
+    CreateUploadLinkRequest, CreateUploadSessionRequest, Credentials, DatabaseMaintenance,
+    DatabaseStats, DeleteResponse, Directory, DirectoryAclEntry, DirectoryAclResponse,
+    DirectoryArrangement, DirectoryIntegration, DirectoryResponse, DirectoryShare,
+    DirectoryShareResponse, DiskBenchmark, DiskBenchmarkRequest, DownloadConfirmationRequired,
+    DropLink, DropLinkResponse, DropReceipt, DroppedFile, ErrorResponse, FileAttachment,
+    FileMetadata, FileReportResponse, FileResponse, FileVersion, IngestResponse, LinkResponse,
+    ListFilesResponse, ListTreeResponse, MoveDirectoryRequest, MoveDirectoryToRequest,
+    MoveFileRequest, NotificationPreferences, PushSubscription, QuarantineDecision,
+    QuarantineEntry, QuarantineFileRequest, QuarantineReviewRequest, QuarantinedFileResponse,
+    QuotaStatus, ReceiptResponse, ReportShareRequest, ReportShareResponse,
+    ResolveShareReportRequest, RetentionPolicy, SearchMatch, SearchResponse, SegmentManifest,
+    SetClipboardRequest, SetDirectoryAclRequest, SetShareTermsRequest, Share, ShareReport,
+    ShareResponse, ShareTerms, ShareTermsRequired, ShareXUploader, SubmissionsReport,
+    ThumbnailPolicy, TokenResponse, TrashItem, UpdateDirectoryArrangementRequest,
+    UpdateFileRequest, UpdateNotificationPreferencesRequest, UploadCleanupStats, UploadLink,
+    UploadLinkInfo, UploadLinkResponse, UploadResponse, UploadSession, UploadSessionResponse,
+    UsageNode, User, UserErasureReport, UserResponse, VapidPublicKeyResponse, VersionResponse,
+    WatchFolderRequest, WatchedFolder, WaveformResponse, WebhookReplayResponse, WriteQueueStats,
 };
-use crate::storage::FileStorage;
+use crate::mirror;
+use crate::notifications::{DELIVERY_DIGEST, DELIVERY_IMMEDIATE};
+use crate::oidc::OidcError;
+use crate::pages;
+use crate::push;
+use crate::quota;
+use crate::readme::{self, ReadmeFormat};
+use crate::sorting::{self, DirectorySort, SortOrder};
+use crate::storage::{
+    BatchError, ContentStream, FileFilter, FileOrder, FileReport, FileSortKey, FileStorage,
+    NameTaken, SavedUpload, UploadError, ARCHIVE_READY,
+};
+use crate::tenants;
+use crate::thumbnails::{ThumbnailSize, Thumbnailer, THUMBNAIL_SIZES};
+use crate::upload_sessions::{self, NetworkProfile};
+use crate::zip;
 use axum::{
     body::Body,
-    extract::{Multipart, Path, Query, State},
-    http::{header, StatusCode},
-    response::{IntoResponse, Response},
-    Json,
+    extract::{ConnectInfo, Multipart, Path, Query, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{Event as SseEvent, KeepAlive, Sse},
+        Html, IntoResponse, Redirect, Response,
+    },
+    Extension, Json,
 };
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use futures_util::{Stream, StreamExt};
 use serde::Deserialize;
-use tokio::fs::File;
-use tokio::io::AsyncWriteExt;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio_stream::wrappers::BroadcastStream;
 use tokio_util::io::ReaderStream;
-use tracing::{error, info};
+use tracing::{error, info, warn};
+use utoipa::IntoParams;
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 pub struct ListQuery {
     pub parent_directory_id: Option<String>,
+    /// List the whole subtree instead of direct children only.
+    #[serde(default)]
+    pub recursive: bool,
+    pub offset: Option<usize>,
+    pub limit: Option<usize>,
+    /// Sort names naturally (`file2` before `file10`) instead of by upload time.
+    /// Either value overrides the listed directory's own sort.
+    pub natural: Option<bool>,
+    /// Overrides `LIST_DIRECTORIES_FIRST` for recursive listings.
+    pub directories_first: Option<bool>,
+    /// Exact MIME type or a `type/*` wildcard; also accepted as `mime_type`.
+    #[serde(alias = "mime_type")]
+    pub mime: Option<String>,
+    pub min_size: Option<i64>,
+    pub before: Option<String>,
+    pub after: Option<String>,
+    /// Only files whose name contains this, ignoring case.
+    pub name_contains: Option<String>,
+    /// Only files uploaded by the user with this ID.
+    pub uploader: Option<String>,
+    /// Only files with this tag.
+    pub tag: Option<String>,
+    /// `name`, `size` or `uploaded_at`. Overrides `natural` and the listed
+    /// directory's own sort and pins; ignored by recursive listings.
+    pub sort_by: Option<String>,
+    /// `asc` or `desc`; defaults to `asc` for `name` and `desc` otherwise.
+    pub order: Option<String>,
+}
+
+impl ListQuery {
+    fn sort_order(&self, storage: &FileStorage) -> SortOrder {
+        let default = storage.sort_order();
+        SortOrder {
+            natural: self.natural.unwrap_or(default.natural),
+            directories_first: self.directories_first.unwrap_or(default.directories_first),
+        }
+    }
+
+    fn file_filter(&self) -> Result<FileFilter, (StatusCode, Json<ErrorResponse>)> {
+        Ok(FileFilter {
+            mime: self.mime.clone().filter(|m| !m.is_empty()),
+            min_size: self.min_size,
+            before: self.before.as_deref().map(parse_timestamp).transpose()?,
+            after: self.after.as_deref().map(parse_timestamp).transpose()?,
+            name_contains: self.name_contains.clone().filter(|n| !n.is_empty()),
+            uploader: self.uploader.clone().filter(|u| !u.is_empty()),
+            tag: self
+                .tag
+                .as_deref()
+                .map(|t| t.trim().to_lowercase())
+                .filter(|t| !t.is_empty()),
+        })
+    }
+
+    /// The order asked for with `sort_by` and `order`, or `None` to keep the
+    /// default.
+    fn explicit_order(&self) -> Result<Option<FileOrder>, (StatusCode, Json<ErrorResponse>)> {
+        let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+        if self.sort_by.is_none() && self.order.is_none() {
+            return Ok(None);
+        }
+        let key = match self.sort_by.as_deref() {
+            Some(name) => FileSortKey::from_name(name).ok_or_else(|| {
+                bad_request(format!(
+                    "Invalid sort_by '{}': expected name, size or uploaded_at",
+                    name
+                ))
+            })?,
+            None => FileSortKey::UploadedAt,
+        };
+        let descending = match self.order.as_deref() {
+            Some("asc") => false,
+            Some("desc") => true,
+            Some(other) => {
+                return Err(bad_request(format!(
+                    "Invalid order '{}': expected asc or desc",
+                    other
+                )))
+            }
+            None => key != FileSortKey::Name,
+        };
+        Ok(Some(FileOrder { key, descending }))
+    }
+}
+
+/// The directory sort matching an explicit file order, so directories and
+/// links are listed the same way as the files.
+fn directory_sort(order: FileOrder) -> DirectorySort {
+    match (order.key, order.descending) {
+        (FileSortKey::Name, false) => DirectorySort::Name,
+        (FileSortKey::Name, true) => DirectorySort::NameDesc,
+        (FileSortKey::Size, false) => DirectorySort::Smallest,
+        (FileSortKey::Size, true) => DirectorySort::Largest,
+        (FileSortKey::UploadedAt, false) => DirectorySort::Oldest,
+        (FileSortKey::UploadedAt, true) => DirectorySort::Newest,
+    }
+}
+
+/// Accepts an RFC 3339 timestamp or a plain `YYYY-MM-DD` date (midnight UTC)
+/// and returns it in the RFC 3339 UTC form used for stored timestamps.
+fn parse_timestamp(value: &str) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    let parsed = DateTime::parse_from_rfc3339(value)
+        .map(|t| t.with_timezone(&Utc))
+        .or_else(|_| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map(|d| d.and_time(NaiveTime::MIN).and_utc())
+        })
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("Invalid timestamp '{}': expected RFC 3339 or YYYY-MM-DD", value),
+                }),
+            )
+        })?;
+    Ok(parsed.to_rfc3339())
+}
+
+const DEFAULT_TREE_PAGE_SIZE: usize = 1000;
+const MAX_TREE_PAGE_SIZE: usize = 10_000;
+
+/// Rejects mutations that touch mirrored entries, which are served straight
+/// from the host filesystem.
+fn ensure_writable<'a>(
+    ids: impl IntoIterator<Item = &'a str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    if ids.into_iter().any(mirror::is_mirror_id) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: mirror::READ_ONLY_MESSAGE.to_string(),
+            }),
+        ));
+    }
+    Ok(())
+}
+
+fn acl_error(e: AclError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        AclError::LoginRequired => StatusCode::UNAUTHORIZED,
+        AclError::Forbidden(_) => StatusCode::FORBIDDEN,
+    };
+    (
+        status,
+        Json(ErrorResponse {
+            error: e.to_string(),
+        }),
+    )
+}
+
+/// Maps a failed storage write: `409` when a sibling already goes by the
+/// name it was to give an entry, otherwise `status` with `context` leading
+/// the message.
+fn write_error(
+    context: &str,
+    status: StatusCode,
+) -> impl Fn(Box<dyn std::error::Error + Send + Sync>) -> (StatusCode, Json<ErrorResponse>) + '_ {
+    move |e| {
+        if let Some(taken) = e.downcast_ref::<NameTaken>() {
+            return (
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: taken.to_string(),
+                }),
+            );
+        }
+        error!("{}: {}", context, e);
+        (
+            status,
+            Json(ErrorResponse {
+                error: format!("{}: {}", context, e),
+            }),
+        )
+    }
+}
+
+/// The directory access lists as the request's user sees them.
+async fn load_access(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+) -> Result<Access, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = user.as_ref().map(|Extension(user)| user.id.as_str());
+    storage.access(user_id).await.map_err(|e| {
+        error!("Failed to load access lists: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })
+}
+
+/// Refuses the request unless its user has `role` in `directory_id`.
+async fn require_role(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    directory_id: Option<&str>,
+    role: Role,
+) -> Result<Access, (StatusCode, Json<ErrorResponse>)> {
+    let access = load_access(storage, user).await?;
+    access.check(directory_id, role).map_err(acl_error)?;
+    Ok(access)
+}
+
+/// Refuses the request unless its user has `role` in the directory holding
+/// `file_id`. Unknown files pass, for the handler to report as not found.
+async fn require_file_role(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    file_id: &str,
+    role: Role,
+) -> Result<Access, (StatusCode, Json<ErrorResponse>)> {
+    let access = load_access(storage, user).await?;
+    let metadata = storage.get_file_metadata(file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if let Some(metadata) = metadata {
+        access
+            .check(metadata.parent_directory_id.as_deref(), role)
+            .map_err(acl_error)?;
+    }
+    Ok(access)
+}
+
+/// Refuses the request unless `access` allows `role` in the directory
+/// holding each of `file_ids`. Unknown files pass, for the handler to
+/// report.
+async fn check_file_roles<'a>(
+    storage: &FileStorage,
+    access: &Access,
+    file_ids: impl IntoIterator<Item = &'a str>,
+    role: Role,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    for file_id in file_ids {
+        let metadata = storage.get_file_metadata(file_id).await.map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+        if let Some(metadata) = metadata {
+            access
+                .check(metadata.parent_directory_id.as_deref(), role)
+                .map_err(acl_error)?;
+        }
+    }
+    Ok(())
+}
+
+/// Refuses changes to a share unless the request's user could create it:
+/// write access to a shared file's directory, or ownership of a shared
+/// directory. Unknown shares pass, for the handler to report as not found.
+async fn require_share_role(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    share_id: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let scope = storage.share_scope(share_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    for (directory_id, whole_directory) in scope {
+        let role = if whole_directory {
+            Role::Owner
+        } else {
+            Role::Write
+        };
+        require_role(storage, user, directory_id.as_deref(), role).await?;
+    }
+    Ok(())
+}
+
+/// A JSON response carrying the `ETag` of the file or directory it describes.
+type Tagged<T> = ([(header::HeaderName, String); 1], Json<T>);
+
+fn tagged<T>(version: i64, body: T) -> Tagged<T> {
+    ([(header::ETAG, entity_tag(version))], Json(body))
+}
+
+fn entity_tag(version: i64) -> String {
+    format!("\"{}\"", version)
+}
+
+/// A `Content-Disposition` value naming `filename`. Stored names may hold
+/// quotes, control characters or non-ASCII text, none of which fit a quoted
+/// string, so `filename` gets an ASCII stand-in and `filename*` the exact
+/// name, percent-encoded as RFC 6266 and RFC 5987 describe.
+fn content_disposition(disposition: &str, filename: &str) -> String {
+    let fallback: String = filename
+        .chars()
+        .map(|c| match c {
+            ' '..='~' if c != '"' && c != '\\' => c,
+            _ => '_',
+        })
+        .collect();
+    let mut encoded = String::new();
+    for byte in filename.bytes() {
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    format!(
+        "{}; filename=\"{}\"; filename*=UTF-8''{}",
+        disposition, fallback, encoded
+    )
+}
+
+/// Finishes a streamed response, answering `500` instead of panicking
+/// should one of its headers turn out invalid.
+fn finish_response(
+    response: axum::http::response::Builder,
+    body: Body,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    response.body(body).map_err(|e| {
+        error!("Failed to build response: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to build response: {}", e),
+            }),
+        )
+    })
+}
+
+/// Requires a change to name the current version of what it changes in
+/// `If-Match`, so an edit based on a stale copy is refused instead of
+/// silently overwriting someone else's.
+fn check_if_match(
+    headers: &HeaderMap,
+    version: i64,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(value) = headers.get(header::IF_MATCH) else {
+        return Err((
+            StatusCode::PRECONDITION_REQUIRED,
+            Json(ErrorResponse {
+                error: "An If-Match header with the ETag from the last fetch is required"
+                    .to_string(),
+            }),
+        ));
+    };
+    let current = entity_tag(version);
+    let matches = value
+        .to_str()
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .any(|tag| tag == "*" || tag == current);
+    if !matches {
+        return Err(version_conflict());
+    }
+    Ok(())
+}
+
+fn version_conflict() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::CONFLICT,
+        Json(ErrorResponse {
+            error: "Changed by someone else since it was fetched; fetch it again and retry"
+                .to_string(),
+        }),
+    )
+}
+
+/// A file's MIME type, guessed from its name when the uploader sent none or
+/// only `application/octet-stream`.
+fn effective_mime_type(metadata: &FileMetadata) -> String {
+    match metadata.mime_type.as_deref() {
+        Some(mime) if mime != "application/octet-stream" => mime.to_string(),
+        _ => mime_guess::from_path(&metadata.original_filename)
+            .first_or_octet_stream()
+            .to_string(),
+    }
 }
 
 // Upload file handler
 pub async fn upload_file(
     State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    headers: HeaderMap,
     mut multipart: Multipart,
 ) -> Result<Json<UploadResponse>, (StatusCode, Json<ErrorResponse>)> {
     let mut original_filename = String::new();
     let mut mime_type: Option<String> = None;
     let mut description: Option<String> = None;
     let mut parent_directory_id: Option<String> = None;
-    // (file_id, file_path, stored_filename, file_size)
-    let mut upload_info: Option<(String, std::path::PathBuf, String, i64)> = None;
+    let mut upload_info: Option<SavedUpload> = None;
+    // Checked as the file is written when the header or the `checksum`
+    // field comes before it, or once it is stored when the field follows.
+    let mut expected = match headers.get(checksum::HEADER) {
+        Some(sha256) => ExpectedChecksum::Given(sha256.to_str().unwrap_or_default().to_string()),
+        None => ExpectedChecksum::None,
+    };
 
     while let Some(mut field) = multipart
         .next_field()
@@ -51,55 +509,59 @@ pub async fn upload_file(
 
         match field_name.as_str() {
             "file" => {
-                original_filename = field.file_name().unwrap_or("unnamed").to_string();
+                original_filename = storage
+                    .name_policy()
+                    .normalize(field.file_name().unwrap_or("unnamed"));
                 mime_type = field.content_type().map(|s| s.to_string());
 
-                let (file_id, file_path, stored_filename) =
-                    storage.prepare_upload_path(&original_filename);
-
-                let mut disk_file = tokio::fs::File::create(&file_path).await.map_err(|e| {
-                    error!("Failed to create upload file: {}", e);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ErrorResponse {
-                            error: format!("Failed to create upload file: {}", e),
-                        }),
-                    )
-                })?;
-
-                let mut total_bytes: i64 = 0;
-                while let Some(chunk) = field.chunk().await.map_err(|e| {
-                    error!("Failed to read file chunk: {}", e);
-                    (
-                        StatusCode::BAD_REQUEST,
-                        Json(ErrorResponse {
-                            error: format!("Failed to read file data: {}", e),
-                        }),
-                    )
-                })? {
-                    disk_file.write_all(&chunk).await.map_err(|e| {
-                        error!("Failed to write file chunk: {}", e);
+                storage
+                    .name_policy()
+                    .validate(&original_filename)
+                    .map_err(|e| {
                         (
-                            StatusCode::INTERNAL_SERVER_ERROR,
+                            StatusCode::BAD_REQUEST,
                             Json(ErrorResponse {
-                                error: format!("Failed to write file data: {}", e),
+                                error: format!("Invalid filename: {}", e),
                             }),
                         )
                     })?;
-                    total_bytes += chunk.len() as i64;
+                // With the directory known before the file, refuse before
+                // storing anything; otherwise it's checked once all fields
+                // are read.
+                if parent_directory_id.is_some() {
+                    ensure_writable(parent_directory_id.as_deref())?;
+                    require_role(&storage, &user, parent_directory_id.as_deref(), Role::Write)
+                        .await?;
                 }
 
-                disk_file.flush().await.map_err(|e| {
-                    error!("Failed to flush upload file: {}", e);
-                    (
-                        StatusCode::INTERNAL_SERVER_ERROR,
-                        Json(ErrorResponse {
-                            error: format!("Failed to finalize file: {}", e),
-                        }),
-                    )
-                })?;
+                let saved = storage
+                    .save_file(&original_filename, &mut field, &expected)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to store upload: {}", e);
+                        let status = match e {
+                            UploadError::Body(_) | UploadError::InvalidChunk(_) => {
+                                StatusCode::BAD_REQUEST
+                            }
+                            UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                                StatusCode::INSUFFICIENT_STORAGE
+                            }
+                            UploadError::ChecksumMismatch { .. } => {
+                                StatusCode::UNPROCESSABLE_ENTITY
+                            }
+                            UploadError::Io(_) | UploadError::Database(_) => {
+                                StatusCode::INTERNAL_SERVER_ERROR
+                            }
+                        };
+                        (
+                            status,
+                            Json(ErrorResponse {
+                                error: e.to_string(),
+                            }),
+                        )
+                    })?;
 
-                upload_info = Some((file_id, file_path, stored_filename, total_bytes));
+                upload_info = Some(saved);
             }
             "description" => {
                 let text = field.text().await.map_err(|e| {
@@ -127,80 +589,195 @@ pub async fn upload_file(
                     parent_directory_id = Some(text);
                 }
             }
+            "checksum" => {
+                let text = field.text().await.map_err(|e| {
+                    error!("Failed to read checksum: {}", e);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Failed to read checksum: {}", e),
+                        }),
+                    )
+                })?;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                expected = ExpectedChecksum::Given(text);
+                if let Some(saved) = &upload_info {
+                    if !expected.matches(&saved.sha256) {
+                        let _ = tokio::fs::remove_file(&saved.file_path).await;
+                        let e = UploadError::ChecksumMismatch {
+                            expected: expected.value().unwrap_or_default(),
+                            actual: saved.sha256.clone(),
+                        };
+                        return Err((
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            Json(ErrorResponse {
+                                error: e.to_string(),
+                            }),
+                        ));
+                    }
+                }
+            }
             _ => {}
         }
     }
 
-    let (file_id, file_path, stored_filename, file_size) =
-        upload_info.ok_or_else(|| {
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: "No file provided".to_string(),
-                }),
-            )
-        })?;
+    let saved = upload_info.ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No file provided".to_string(),
+            }),
+        )
+    })?;
+    let sha256 = saved.sha256.clone();
+
+    let allowed = match ensure_writable(parent_directory_id.as_deref()) {
+        Ok(()) => require_role(&storage, &user, parent_directory_id.as_deref(), Role::Write)
+            .await
+            .map(|_| ()),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = allowed {
+        let _ = tokio::fs::remove_file(&saved.file_path).await;
+        return Err(e);
+    }
 
     let metadata = storage
         .record_file_metadata(
-            file_id,
+            saved,
             original_filename,
-            stored_filename,
-            file_path,
-            file_size,
             mime_type,
             description,
             parent_directory_id,
+            user.map(|Extension(user)| user.id),
         )
         .await
-        .map_err(|e| {
-            error!("Failed to save file metadata: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to save file: {}", e),
-                }),
-            )
-        })?;
+        .map_err(write_error(
+            "Failed to save file",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+
+    let receipt = storage.issue_receipt(&metadata, &sha256).await.map_err(|e| {
+        error!("Failed to issue upload receipt: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to issue upload receipt: {}", e),
+            }),
+        )
+    })?;
 
     info!("File uploaded successfully: {}", metadata.id);
 
     Ok(Json(UploadResponse {
         success: true,
         file: metadata.into(),
+        receipt,
         message: "File uploaded successfully".to_string(),
     }))
 }
 
-// Download file handler
-pub async fn download_file(
+// Bulk ingest handler: unpacks a streamed tar or tar.gz body
+pub async fn ingest_tar(
     State(storage): State<FileStorage>,
-    Path(file_id): Path<String>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<ListQuery>,
+    body: Body,
 ) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
-    let metadata = storage
-        .get_file_metadata(&file_id)
-        .await
-        .map_err(|e| {
-            error!("Database error: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Database error: {}", e),
-                }),
-            )
-        })?;
+    ensure_writable(query.parent_directory_id.as_deref())?;
+    require_role(
+        &storage,
+        &user,
+        query.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
 
-    let metadata = metadata.ok_or_else(|| {
-        (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "File not found".to_string(),
-            }),
-        )
-    })?;
+    let result = ingest::ingest_tar(
+        &storage,
+        body.into_data_stream(),
+        query.parent_directory_id.clone(),
+        user.map(|Extension(user)| user.id),
+    )
+    .await;
+    let report = match result {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Failed to ingest archive: {}", e);
+            let message = e.to_string();
+            let status = match e {
+                IngestError::Archive(_) => StatusCode::BAD_REQUEST,
+                IngestError::QuotaExceeded(_) => StatusCode::INSUFFICIENT_STORAGE,
+                IngestError::LimitExceeded {
+                    limit,
+                    max,
+                    suspicious_file_ids,
+                } => {
+                    return Ok((
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        Json(ArchiveLimitExceeded {
+                            error: message,
+                            limit: limit.name().to_string(),
+                            max,
+                            suspicious_file_ids,
+                        }),
+                    )
+                        .into_response());
+                }
+                IngestError::Storage(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            return Err((status, Json(ErrorResponse { error: message })));
+        }
+    };
+
+    info!(
+        "Ingested {} files from archive ({} entries skipped)",
+        report.files.len(),
+        report.skipped.len()
+    );
+    storage.events().publish(ChangeEvent::JobFinished {
+        job: "tar_ingest".to_string(),
+        parent_directory_id: query.parent_directory_id.clone(),
+        message: format!(
+            "Archive ingest finished: {} files added, {} entries skipped",
+            report.files.len(),
+            report.skipped.len()
+        ),
+    });
+
+    Ok(Json(IngestResponse {
+        success: true,
+        message: format!("Ingested {} files", report.files.len()),
+        files: report.files.into_iter().map(Into::into).collect(),
+        skipped: report.skipped,
+    })
+    .into_response())
+}
+
+fn upload_session_response(session: UploadSession, received_chunks: Vec<i64>) -> UploadSessionResponse {
+    UploadSessionResponse {
+        total_chunks: upload_sessions::total_chunks(session.file_size, session.chunk_size),
+        id: session.id,
+        filename: session.filename,
+        file_size: session.file_size,
+        network_profile: session.network_profile,
+        chunk_size: session.chunk_size,
+        parallelism: session.parallelism,
+        received_chunks,
+        created_at: session.created_at,
+        expires_at: session.expires_at,
+    }
+}
 
-    let file_path = storage
-        .get_file_path(&file_id)
+/// Looks up an upload session, turning a missing one into a 404.
+async fn find_upload_session(
+    storage: &FileStorage,
+    session_id: &str,
+) -> Result<UploadSession, (StatusCode, Json<ErrorResponse>)> {
+    storage
+        .get_upload_session(session_id)
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
@@ -215,225 +792,274 @@ pub async fn download_file(
             (
                 StatusCode::NOT_FOUND,
                 Json(ErrorResponse {
-                    error: "File not found".to_string(),
+                    error: "Upload session not found".to_string(),
                 }),
             )
-        })?;
+        })
+}
 
-    let file = File::open(&file_path).await.map_err(|e| {
-        error!("Failed to open file: {}", e);
+// Chunked upload session handlers
+pub async fn create_upload_session(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(mut payload): Json<CreateUploadSessionRequest>,
+) -> Result<Json<UploadSessionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: message }),
+        )
+    };
+
+    payload.filename = storage.name_policy().normalize(&payload.filename);
+    storage
+        .name_policy()
+        .validate(&payload.filename)
+        .map_err(|e| bad_request(format!("Invalid filename: {}", e)))?;
+    if payload.file_size < 0 {
+        return Err(bad_request("file_size must not be negative".to_string()));
+    }
+    ensure_writable(payload.parent_directory_id.as_deref())?;
+    require_role(
+        &storage,
+        &user,
+        payload.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+
+    let profile = match payload.network_profile.as_deref() {
+        None => NetworkProfile::default(),
+        Some(name) => NetworkProfile::from_name(name).ok_or_else(|| {
+            bad_request(format!(
+                "network_profile must be one of: {}",
+                NetworkProfile::NAMES.join(", ")
+            ))
+        })?,
+    };
+
+    // Refuse up front what the quota or the disk won't allow, rather than
+    // after every chunk has been sent.
+    let quota = storage.quota_status().await.map_err(|e| {
+        error!("Database error: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: format!("Failed to open file: {}", e),
+                error: format!("Database error: {}", e),
             }),
         )
     })?;
-
-    let stream = ReaderStream::new(file);
-    let body = Body::from_stream(stream);
-
-    let content_type = metadata
-        .mime_type
-        .unwrap_or_else(|| "application/octet-stream".to_string());
-
-    Ok(Response::builder()
-        .status(StatusCode::OK)
-        .header(header::CONTENT_TYPE, content_type)
-        .header(
-            header::CONTENT_DISPOSITION,
-            format!("attachment; filename=\"{}\"", metadata.original_filename),
+    quota::check_upload(&quota, payload.file_size as u64).map_err(|message| {
+        (
+            StatusCode::INSUFFICIENT_STORAGE,
+            Json(ErrorResponse { error: message }),
         )
-        .body(body)
-        .unwrap())
-}
-
-// List all files and directories handler
-pub async fn list_files(
-    State(storage): State<FileStorage>,
-    Query(query): Query<ListQuery>,
-) -> Result<Json<ListFilesResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let files = storage
-        .list_files(query.parent_directory_id.clone())
-        .await
+    })?;
+    storage
+        .headroom(quota.used_bytes)
+        .check(payload.file_size as u64)
         .map_err(|e| {
-            error!("Failed to list files: {}", e);
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::INSUFFICIENT_STORAGE,
                 Json(ErrorResponse {
-                    error: format!("Failed to list files: {}", e),
+                    error: e.to_string(),
                 }),
             )
         })?;
 
-    let directories = storage
-        .list_directories(query.parent_directory_id)
+    let session = storage
+        .create_upload_session(payload, profile)
         .await
         .map_err(|e| {
-            error!("Failed to list directories: {}", e);
+            error!("Failed to create upload session: {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
-                    error: format!("Failed to list directories: {}", e),
+                    error: format!("Database error: {}", e),
                 }),
             )
         })?;
 
-    // Get stats for each directory
-    let mut directory_responses = Vec::new();
-    for dir in directories {
-        let (file_count, total_size) = storage.get_directory_stats(&dir.id).await.map_err(|e| {
-            error!("Failed to get directory stats: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(ErrorResponse {
-                    error: format!("Failed to get directory stats: {}", e),
-                }),
-            )
-        })?;
-
-        directory_responses.push(DirectoryResponse {
-            id: dir.id,
-            name: dir.name,
-            parent_id: dir.parent_id,
-            created_at: dir.created_at,
-            updated_at: dir.updated_at,
-            file_count,
-            total_size,
-        });
-    }
+    Ok(Json(upload_session_response(session, Vec::new())))
+}
 
-    let total = files.len() + directory_responses.len();
-    let file_responses: Vec<FileResponse> = files.into_iter().map(|f| f.into()).collect();
+pub async fn get_upload_session(
+    State(storage): State<FileStorage>,
+    Path(session_id): Path<String>,
+) -> Result<Json<UploadSessionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let session = find_upload_session(&storage, &session_id).await?;
+    let received = storage.received_chunks(&session_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
 
-    Ok(Json(ListFilesResponse {
-        files: file_responses,
-        directories: directory_responses,
-        total,
-    }))
+    Ok(Json(upload_session_response(session, received)))
 }
 
-// Get file metadata handler
-pub async fn get_file_info(
+pub async fn upload_session_chunk(
     State(storage): State<FileStorage>,
-    Path(file_id): Path<String>,
-) -> Result<Json<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let metadata = storage
-        .get_file_metadata(&file_id)
+    Path((session_id, index)): Path<(String, i64)>,
+    body: Body,
+) -> Result<Json<UploadSessionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let mut session = find_upload_session(&storage, &session_id).await?;
+    let (body, trailers) = TrailedBody::new(body);
+    let size = storage
+        .save_session_chunk(&session, index, body, &ExpectedChecksum::Trailer(trailers))
         .await
         .map_err(|e| {
-            error!("Database error: {}", e);
+            error!("Failed to store chunk {} of upload session {}: {}", index, session_id, e);
+            let status = match e {
+                UploadError::Body(_) | UploadError::InvalidChunk(_) => StatusCode::BAD_REQUEST,
+                UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                    StatusCode::INSUFFICIENT_STORAGE
+                }
+                UploadError::ChecksumMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+                UploadError::Io(_) | UploadError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                status,
                 Json(ErrorResponse {
-                    error: format!("Database error: {}", e),
+                    error: e.to_string(),
                 }),
             )
         })?;
+    let expires_at = storage
+        .record_session_chunk(&session_id, index, size)
+        .await
+        .map_err(db_error)?;
+    session.expires_at = Some(expires_at);
 
-    let metadata = metadata.ok_or_else(|| {
-        (
-            StatusCode::NOT_FOUND,
-            Json(ErrorResponse {
-                error: "File not found".to_string(),
-            }),
-        )
-    })?;
-
-    Ok(Json(metadata.into()))
+    let received = storage.received_chunks(&session_id).await.map_err(db_error)?;
+    Ok(Json(upload_session_response(session, received)))
 }
 
-// Delete file handler
-pub async fn delete_file(
+pub async fn complete_upload_session(
     State(storage): State<FileStorage>,
-    Path(file_id): Path<String>,
-) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let deleted = storage.delete_file(&file_id).await.map_err(|e| {
-        error!("Failed to delete file: {}", e);
+    user: Option<Extension<AuthUser>>,
+    Path(session_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<UploadResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: format!("Failed to delete file: {}", e),
+                error: format!("Database error: {}", e),
             }),
         )
-    })?;
+    };
 
-    if deleted {
-        info!("File deleted: {}", file_id);
-        Ok(Json(DeleteResponse {
-            success: true,
-            message: "File deleted successfully".to_string(),
-        }))
-    } else {
-        Err((
-            StatusCode::NOT_FOUND,
+    let session = find_upload_session(&storage, &session_id).await?;
+    let received = storage.received_chunks(&session_id).await.map_err(db_error)?;
+    let total = upload_sessions::total_chunks(session.file_size, session.chunk_size);
+    let missing: Vec<String> = (0..total)
+        .filter(|index| received.binary_search(index).is_err())
+        .map(|index| index.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err((
+            StatusCode::CONFLICT,
             Json(ErrorResponse {
-                error: "File not found".to_string(),
+                error: format!("Missing chunks: {}", missing.join(", ")),
             }),
-        ))
+        ));
     }
-}
-
-// Health check handler
-pub async fn health_check() -> impl IntoResponse {
-    Json(serde_json::json!({
-        "status": "healthy",
-        "service": "file-transfer-api"
-    }))
-}
+    ensure_writable(session.parent_directory_id.as_deref())?;
+    require_role(
+        &storage,
+        &user,
+        session.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
 
-// Create directory handler
-pub async fn create_directory(
-    State(storage): State<FileStorage>,
-    Json(payload): Json<CreateDirectoryRequest>,
-) -> Result<Json<CreateDirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let directory = storage
-        .create_directory(&payload.name, payload.parent_id)
+    // The whole file's checksum, which clients only know once every chunk
+    // has been sent.
+    let expected = match headers.get(checksum::HEADER) {
+        Some(sha256) => ExpectedChecksum::Given(sha256.to_str().unwrap_or_default().to_string()),
+        None => ExpectedChecksum::None,
+    };
+    let saved = storage
+        .assemble_upload_session(&session, &expected)
         .await
         .map_err(|e| {
-            error!("Failed to create directory: {}", e);
+            error!("Failed to assemble upload session {}: {}", session_id, e);
+            let status = match e {
+                UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                    StatusCode::INSUFFICIENT_STORAGE
+                }
+                UploadError::ChecksumMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+                _ => StatusCode::INTERNAL_SERVER_ERROR,
+            };
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                status,
                 Json(ErrorResponse {
-                    error: format!("Failed to create directory: {}", e),
+                    error: e.to_string(),
                 }),
             )
         })?;
+    let sha256 = saved.sha256.clone();
 
-    let (file_count, total_size) = storage.get_directory_stats(&directory.id).await.map_err(|e| {
-        error!("Failed to get directory stats: {}", e);
+    let metadata = storage
+        .record_file_metadata(
+            saved,
+            session.filename.clone(),
+            session.mime_type.clone(),
+            session.description.clone(),
+            session.parent_directory_id.clone(),
+            user.map(|Extension(user)| user.id),
+        )
+        .await
+        .map_err(write_error(
+            "Failed to save file",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+
+    let receipt = storage.issue_receipt(&metadata, &sha256).await.map_err(|e| {
+        error!("Failed to issue upload receipt: {}", e);
         (
             StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: format!("Failed to get directory stats: {}", e),
+                error: format!("Failed to issue upload receipt: {}", e),
             }),
         )
     })?;
 
-    info!("Directory created: {}", directory.id);
+    storage.delete_upload_session(&session_id).await.map_err(db_error)?;
+    info!(
+        "Upload session {} completed as file {}",
+        session_id, metadata.id
+    );
 
-    Ok(Json(CreateDirectoryResponse {
+    Ok(Json(UploadResponse {
         success: true,
-        directory: DirectoryResponse {
-            id: directory.id,
-            name: directory.name,
-            parent_id: directory.parent_id,
-            created_at: directory.created_at,
-            updated_at: directory.updated_at,
-            file_count,
-            total_size,
-        },
-        message: "Directory created successfully".to_string(),
+        file: metadata.into(),
+        receipt,
+        message: "File uploaded successfully".to_string(),
     }))
 }
 
-// Get directory info handler
-pub async fn get_directory_info(
+pub async fn delete_upload_session(
     State(storage): State<FileStorage>,
-    Path(dir_id): Path<String>,
-) -> Result<Json<DirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let directory = storage
-        .get_directory(&dir_id)
+    Path(session_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let deleted = storage
+        .delete_upload_session(&session_id)
         .await
         .map_err(|e| {
             error!("Database error: {}", e);
@@ -443,83 +1069,62 @@ pub async fn get_directory_info(
                     error: format!("Database error: {}", e),
                 }),
             )
-        })?
-        .ok_or_else(|| {
-            (
-                StatusCode::NOT_FOUND,
-                Json(ErrorResponse {
-                    error: "Directory not found".to_string(),
-                }),
-            )
         })?;
 
-    let (file_count, total_size) = storage.get_directory_stats(&dir_id).await.map_err(|e| {
-        error!("Failed to get directory stats: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to get directory stats: {}", e),
-            }),
-        )
-    })?;
-
-    Ok(Json(DirectoryResponse {
-        id: directory.id,
-        name: directory.name,
-        parent_id: directory.parent_id,
-        created_at: directory.created_at,
-        updated_at: directory.updated_at,
-        file_count,
-        total_size,
-    }))
-}
-
-// Delete directory handler
-pub async fn delete_directory(
-    State(storage): State<FileStorage>,
-    Path(dir_id): Path<String>,
-) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let deleted = storage.delete_directory(&dir_id).await.map_err(|e| {
-        error!("Failed to delete directory: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to delete directory: {}", e),
-            }),
-        )
-    })?;
-
     if deleted {
-        info!("Directory deleted: {}", dir_id);
         Ok(Json(DeleteResponse {
             success: true,
-            message: "Directory deleted successfully".to_string(),
+            message: "Upload session cancelled".to_string(),
         }))
     } else {
         Err((
             StatusCode::NOT_FOUND,
             Json(ErrorResponse {
-                error: "Directory not found".to_string(),
+                error: "Upload session not found".to_string(),
             }),
         ))
     }
 }
 
-// Move file handler
-pub async fn move_file(
+// Download file handler
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DownloadQuery {
+    /// Convert the image before sending it: `jpeg` (or `jpg`) or `png`.
+    pub format: Option<String>,
+}
+
+pub async fn download_file(
     State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
     Path(file_id): Path<String>,
-    Json(payload): Json<MoveFileRequest>,
-) -> Result<Json<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Query(query): Query<DownloadQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let target = match query.format.as_deref().map(str::to_ascii_lowercase) {
+        None => None,
+        Some(name) => Some(TargetFormat::from_name(&name).ok_or_else(|| {
+            let names: Vec<&str> = TARGET_FORMATS.iter().map(|f| f.name).collect();
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("format must be one of: {}", names.join(", ")),
+                }),
+            )
+        })?),
+    };
+
+
     let metadata = storage
-        .move_file(&file_id, payload.parent_directory_id)
+        .get_file_metadata(&file_id)
         .await
         .map_err(|e| {
-            error!("Failed to move file: {}", e);
+            error!("Database error: {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
-                    error: format!("Failed to move file: {}", e),
+                    error: format!("Database error: {}", e),
                 }),
             )
         })?;
@@ -532,119 +1137,7226 @@ pub async fn move_file(
             }),
         )
     })?;
+    require_role(
+        &storage,
+        &user,
+        metadata.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
 
-    info!("File moved: {}", file_id);
-    Ok(Json(metadata.into()))
-}
+    // Files already in the requested format are sent as they are.
+    let target = target.filter(|format| metadata.mime_type.as_deref() != Some(format.mime_type));
 
-// Move directory handler
-pub async fn move_directory(
-    State(storage): State<FileStorage>,
-    Path(dir_id): Path<String>,
-    Json(payload): Json<MoveDirectoryRequest>,
-) -> Result<Json<DirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
-    let directory = storage
-        .move_directory(&dir_id, payload.parent_id)
-        .await
-        .map_err(|e| {
-            error!("Failed to move directory: {}", e);
-            (
-                StatusCode::BAD_REQUEST,
-                Json(ErrorResponse {
-                    error: format!("Failed to move directory: {}", e),
-                }),
-            )
-        })?;
+    // Conversions are produced on the fly, so only original content can be
+    // sent in parts.
+    let size = metadata.file_size.max(0) as u64;
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) if target.is_none() && if_range_matches(&headers, metadata.version) => {
+            parse_range(value, size)
+        }
+        _ => RangeRequest::Full,
+    };
 
-    let directory = directory.ok_or_else(|| {
+    let open_error = |e: Box<dyn std::error::Error + Send + Sync>| {
+        error!("Failed to open file: {}", e);
         (
-            StatusCode::NOT_FOUND,
+            StatusCode::INTERNAL_SERVER_ERROR,
             Json(ErrorResponse {
-                error: "Directory not found".to_string(),
+                error: format!("Failed to open file: {}", e),
             }),
         )
-    })?;
-
-    let (file_count, total_size) = storage
-        .get_directory_stats(&dir_id)
-        .await
-        .map_err(|e| {
-            error!("Failed to get directory stats: {}", e);
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let stream = match range {
+        RangeRequest::Full => storage.open_content(&metadata).await.map_err(open_error)?,
+        RangeRequest::Partial { start, end } => storage
+            .open_content_range(&metadata, start, end - start + 1)
+            .await
+            .map_err(open_error)?,
+        RangeRequest::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
                 Json(ErrorResponse {
-                    error: format!("Failed to get directory stats: {}", e),
+                    error: format!("Range is outside the file's {} bytes", size),
                 }),
             )
-        })?;
+                .into_response());
+        }
+    };
 
-    info!("Directory moved: {}", dir_id);
-    Ok(Json(DirectoryResponse {
-        id: directory.id,
-        name: directory.name,
-        parent_id: directory.parent_id,
-        created_at: directory.created_at,
-        updated_at: directory.updated_at,
-        file_count,
-        total_size,
-    }))
+    let (body, content_type, filename) = match target {
+        None => (
+            Body::from_stream(stream),
+            effective_mime_type(&metadata),
+            metadata.original_filename.clone(),
+        ),
+        Some(format) => {
+            let converted = convert_download(&storage, &metadata, stream, format).await?;
+            (
+                converted,
+                format.mime_type.to_string(),
+                format.rename(&metadata.original_filename),
+            )
+        }
+    };
+
+    // A player seeking through a file makes many requests; only the one
+    // starting at the beginning counts as a download.
+    if !matches!(range, RangeRequest::Partial { start, .. } if start > 0) {
+        let access = AccessContext::from_request(addr, &headers);
+        if let Err(e) = storage.record_download(&metadata, &access).await {
+            error!("Failed to record download of {}: {}", metadata.id, e);
+        }
+    }
+
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("attachment", &filename),
+        );
+    if target.is_none() {
+        response = response
+            .header(header::ACCEPT_RANGES, "bytes")
+            .header(header::ETAG, entity_tag(metadata.version));
+        if let Some(sha256) = &metadata.sha256 {
+            response = response.header(checksum::HEADER, sha256);
+        }
+    }
+    response = match range {
+        RangeRequest::Partial { start, end } => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(header::CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, size))
+            .header(header::CONTENT_LENGTH, end - start + 1),
+        _ if target.is_none() => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size),
+        _ => response.status(StatusCode::OK),
+    };
+
+    finish_response(response, body)
 }
 
-// List recent files handler
-#[derive(Debug, Deserialize)]
-pub struct RecentQuery {
-    pub limit: Option<i64>,
+/// What a `Range` header asks of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeRequest {
+    /// No usable range; the whole file is sent.
+    Full,
+    /// Bytes `start..=end`, already clamped to the file.
+    Partial { start: u64, end: u64 },
+    /// The range lies entirely past the end of the file.
+    Unsatisfiable,
 }
 
-pub async fn list_recent_files(
-    State(storage): State<FileStorage>,
-    Query(query): Query<RecentQuery>,
-) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
-    let limit = query.limit.unwrap_or(20).min(100);
-    let files = storage.list_recent_files(limit).await.map_err(|e| {
-        error!("Failed to list recent files: {}", e);
-        (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            Json(ErrorResponse {
-                error: format!("Failed to list recent files: {}", e),
-            }),
-        )
-    })?;
-    let responses: Vec<FileResponse> = files.into_iter().map(|f| f.into()).collect();
-    let total = responses.len();
-    Ok(Json(serde_json::json!({ "files": responses, "total": total })))
+/// Parses a single `bytes=` range against a file of `size` bytes. Headers
+/// with other units, syntax errors or several ranges are ignored, which
+/// sends the whole file as the spec allows.
+fn parse_range(value: &str, size: u64) -> RangeRequest {
+    let Some(spec) = value.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let Some((first, last)) = spec.split_once('-').filter(|_| !spec.contains(',')) else {
+        return RangeRequest::Full;
+    };
+    let (first, last) = (first.trim(), last.trim());
+
+    if first.is_empty() {
+        // A suffix range: the last `n` bytes.
+        return match last.parse::<u64>() {
+            Ok(n) if n > 0 && size > 0 => RangeRequest::Partial {
+                start: size.saturating_sub(n),
+                end: size - 1,
+            },
+            Ok(_) => RangeRequest::Unsatisfiable,
+            Err(_) => RangeRequest::Full,
+        };
+    }
+    let Ok(start) = first.parse::<u64>() else {
+        return RangeRequest::Full;
+    };
+    let end = match last.parse::<u64>() {
+        Ok(end) if end >= start => end,
+        Err(_) if last.is_empty() => u64::MAX,
+        _ => return RangeRequest::Full,
+    };
+    if start >= size {
+        return RangeRequest::Unsatisfiable;
+    }
+    RangeRequest::Partial {
+        start,
+        end: end.min(size - 1),
+    }
 }
 
-// Bulk delete handler
-pub async fn bulk_delete(
-    State(storage): State<FileStorage>,
-    Json(payload): Json<BulkDeleteRequest>,
+#[cfg(test)]
+mod parse_range_tests {
+    use super::{parse_range, RangeRequest};
+
+    fn partial(start: u64, end: u64) -> RangeRequest {
+        RangeRequest::Partial { start, end }
+    }
+
+    #[test]
+    fn bounded_ranges_are_clamped_to_the_file() {
+        assert_eq!(parse_range("bytes=0-499", 1000), partial(0, 499));
+        assert_eq!(parse_range(" bytes=10 - 20 ", 1000), partial(10, 20));
+        assert_eq!(parse_range("bytes=900-5000", 1000), partial(900, 999));
+    }
+
+    #[test]
+    fn suffix_ranges_take_the_last_bytes() {
+        assert_eq!(parse_range("bytes=-100", 1000), partial(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000), partial(0, 999));
+        assert_eq!(parse_range("bytes=-0", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-10", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn open_ended_ranges_run_to_the_end() {
+        assert_eq!(parse_range("bytes=500-", 1000), partial(500, 999));
+        assert_eq!(parse_range("bytes=0-", 1), partial(0, 0));
+    }
+
+    #[test]
+    fn ranges_starting_past_the_end_are_unsatisfiable() {
+        assert_eq!(
+            parse_range("bytes=1000-", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(
+            parse_range("bytes=1000-2000", 1000),
+            RangeRequest::Unsatisfiable
+        );
+        assert_eq!(parse_range("bytes=0-", 0), RangeRequest::Unsatisfiable);
+    }
+
+    #[test]
+    fn unusable_headers_send_the_whole_file() {
+        assert_eq!(parse_range("bytes=0-99,200-299", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=-", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=500-100", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=a-b", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("bytes=-x", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-99", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("", 1000), RangeRequest::Full);
+    }
+}
+
+/// Whether a range request's `If-Range` still names the current content.
+/// Dates never match, since downloads carry an ETag but no Last-Modified.
+fn if_range_matches(headers: &HeaderMap, version: i64) -> bool {
+    match headers.get(header::IF_RANGE) {
+        None => true,
+        Some(value) => value.to_str().is_ok_and(|v| v.trim() == entity_tag(version)),
+    }
+}
+
+/// Whether `If-None-Match` names the current content, so a cache's copy is
+/// confirmed with `304 Not Modified` instead of sending it again.
+fn if_none_match_matches(headers: &HeaderMap, version: i64) -> bool {
+    let tag = entity_tag(version);
+    headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .any(|value| value == "*" || value.trim_start_matches("W/") == tag)
+}
+
+/// How browsers, and a CDN or proxy cache in front of the server, may keep
+/// a response.
+#[derive(Debug, Clone, Copy)]
+enum Caching {
+    /// Any cache, for up to `max_age` seconds before revalidating.
+    Public { max_age: u64 },
+    /// Only the requester's browser, for content behind an access list.
+    Private { max_age: u64 },
+    /// Not at all, so that every request reaches the server.
+    NoStore,
+}
+
+impl Caching {
+    /// Caching for content of a public link, never kept past the link's
+    /// expiry.
+    fn public_until(storage: &FileStorage, expires_at: Option<&str>) -> Self {
+        let remaining = expires_at
+            .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+            .map(|time| (time.with_timezone(&Utc) - Utc::now()).num_seconds().max(0) as u64);
+        Caching::Public {
+            max_age: storage
+                .cache_max_age_secs()
+                .min(remaining.unwrap_or(u64::MAX)),
+        }
+    }
+
+    fn header_value(self) -> String {
+        match self {
+            Caching::Public { max_age: 0 } => "public, no-cache".to_string(),
+            Caching::Public { max_age } => format!("public, max-age={}", max_age),
+            Caching::Private { max_age: 0 } => "private, no-cache".to_string(),
+            Caching::Private { max_age } => format!("private, max-age={}", max_age),
+            Caching::NoStore => "no-store".to_string(),
+        }
+    }
+}
+
+/// A file as public links send it: whole, in the requested range while
+/// `If-Range` still matches, or as `304 Not Modified` when the cache's copy
+/// is current. Files that mustn't be cached are always sent whole, so that
+/// each download is seen. Also returns whether the response counts as a
+/// download.
+async fn public_file_response(
+    storage: &FileStorage,
+    metadata: &FileMetadata,
+    headers: &HeaderMap,
+    disposition: &str,
+    caching: Caching,
+) -> Result<(Response, bool), (StatusCode, Json<ErrorResponse>)> {
+    let cacheable = !matches!(caching, Caching::NoStore);
+    if cacheable && if_none_match_matches(headers, metadata.version) {
+        let response = (
+            StatusCode::NOT_MODIFIED,
+            [
+                (header::ETAG, entity_tag(metadata.version)),
+                (header::CACHE_CONTROL, caching.header_value()),
+            ],
+        )
+            .into_response();
+        return Ok((response, false));
+    }
+
+    let size = metadata.file_size.max(0) as u64;
+    let range = match headers.get(header::RANGE).and_then(|v| v.to_str().ok()) {
+        Some(value) if cacheable && if_range_matches(headers, metadata.version) => {
+            parse_range(value, size)
+        }
+        _ => RangeRequest::Full,
+    };
+    let open_error = |e: Box<dyn std::error::Error + Send + Sync>| {
+        error!("Failed to open file: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to open file: {}", e),
+            }),
+        )
+    };
+    let stream = match range {
+        RangeRequest::Full => storage.open_content(metadata).await.map_err(open_error)?,
+        RangeRequest::Partial { start, end } => storage
+            .open_content_range(metadata, start, end - start + 1)
+            .await
+            .map_err(open_error)?,
+        RangeRequest::Unsatisfiable => {
+            let response = (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                Json(ErrorResponse {
+                    error: format!("Range is outside the file's {} bytes", size),
+                }),
+            )
+                .into_response();
+            return Ok((response, false));
+        }
+    };
+
+    let content_type = metadata
+        .mime_type
+        .clone()
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let mut response = Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition(disposition, &metadata.original_filename),
+        )
+        .header(header::ETAG, entity_tag(metadata.version))
+        .header(header::CACHE_CONTROL, caching.header_value());
+    if cacheable {
+        response = response.header(header::ACCEPT_RANGES, "bytes");
+    }
+    if let Some(sha256) = &metadata.sha256 {
+        response = response.header(checksum::HEADER, sha256);
+    }
+    response = match range {
+        RangeRequest::Partial { start, end } => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, size),
+            )
+            .header(header::CONTENT_LENGTH, end - start + 1),
+        _ => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size),
+    };
+
+    // As with downloads, only the request starting at the beginning counts.
+    let counts = !matches!(range, RangeRequest::Partial { start, .. } if start > 0);
+    Ok((
+        finish_response(response, Body::from_stream(stream))?,
+        counts,
+    ))
+}
+
+/// Converts a download to `format` through the configured converter,
+/// returning the body of the (possibly cached) result.
+async fn convert_download(
+    storage: &FileStorage,
+    metadata: &FileMetadata,
+    content: ContentStream,
+    format: TargetFormat,
+) -> Result<Body, (StatusCode, Json<ErrorResponse>)> {
+    let converter = storage.converter().ok_or_else(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ErrorResponse {
+                error: "Format conversion is not enabled on this server".to_string(),
+            }),
+        )
+    })?;
+    if !Converter::is_convertible(metadata) {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ErrorResponse {
+                error: format!(
+                    "{} can't be converted to {}",
+                    metadata.original_filename, format.name
+                ),
+            }),
+        ));
+    }
+
+    let internal_error = |e: String| {
+        error!("Failed to convert {}: {}", metadata.id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to convert file: {}", e),
+            }),
+        )
+    };
+    let path = converter
+        .convert(metadata, content, format)
+        .await
+        .map_err(|e| internal_error(e.to_string()))?;
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| internal_error(e.to_string()))?;
+    Ok(Body::from_stream(ReaderStream::new(file)))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuickUploadQuery {
+    /// Name to store the image under; generated from the time if absent.
+    pub filename: Option<String>,
+}
+
+/// Base URL for links handed out to clients: `PUBLIC_URL` when set,
+/// otherwise the scheme and host the request came in on.
+fn external_base_url(storage: &FileStorage, headers: &HeaderMap) -> String {
+    if let Some(url) = storage.public_url() {
+        return url.to_string();
+    }
+    let header_value = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let scheme = header_value("x-forwarded-proto").unwrap_or("http");
+    let host = header_value("x-forwarded-host")
+        .or_else(|| header_value(header::HOST.as_str()))
+        .unwrap_or("localhost");
+    format!("{}://{}", scheme, host)
+}
+
+/// Rejects quick-upload requests without the configured API key, given as
+/// `Authorization: Bearer <key>` or, for `key`, as a query parameter.
+fn check_quick_upload_key(
+    storage: &FileStorage,
+    headers: &HeaderMap,
+    key: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(expected) = storage.quick_upload_api_key() else {
+        return Ok(());
+    };
+    let provided = key.or_else(|| {
+        headers
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+    });
+    if provided == Some(expected) {
+        return Ok(());
+    }
+    Err((
+        StatusCode::UNAUTHORIZED,
+        Json(ErrorResponse {
+            error: "Missing or invalid API key".to_string(),
+        }),
+    ))
+}
+
+// Screenshot quick-upload handler: stores a raw image body in the quick
+// upload directory and answers with a public short link as plain text
+pub async fn quick_upload(
+    State(storage): State<FileStorage>,
+    Query(query): Query<QuickUploadQuery>,
+    headers: HeaderMap,
+    body: Body,
+) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    check_quick_upload_key(&storage, &headers, None)?;
+
+    let internal_error = |message: String| {
+        error!("{}", message);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: message }),
+        )
+    };
+
+    let mime_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or(v).trim().to_ascii_lowercase())
+        .filter(|mime| mime.starts_with("image/"))
+        .ok_or_else(|| {
+            (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                Json(ErrorResponse {
+                    error: "Body must be an image with an image/* Content-Type".to_string(),
+                }),
+            )
+        })?;
+
+    let filename = match query.filename.filter(|name| !name.is_empty()) {
+        Some(name) => name,
+        None => {
+            let extension = mime_guess::get_mime_extensions_str(&mime_type)
+                .and_then(|extensions| extensions.first())
+                .unwrap_or(&"png");
+            format!(
+                "Screenshot {}.{}",
+                Utc::now().format("%Y-%m-%d %H-%M-%S"),
+                extension
+            )
+        }
+    };
+    let filename = storage.name_policy().normalize(&filename);
+    storage.name_policy().validate(&filename).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid filename: {}", e),
+            }),
+        )
+    })?;
+
+    let path: Vec<String> = storage
+        .quick_upload_dir()
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| storage.name_policy().normalize(segment))
+        .collect();
+    let parent_directory_id = storage
+        .ensure_directory_path(None, &path)
+        .await
+        .map_err(|e| internal_error(format!("Failed to create quick upload directory: {}", e)))?;
+
+    let (body, trailers) = TrailedBody::new(body);
+    let saved = storage
+        .save_file(&filename, body, &ExpectedChecksum::Trailer(trailers))
+        .await
+        .map_err(|e| {
+            error!("Failed to store quick upload: {}", e);
+            let status = match e {
+                UploadError::Body(_) | UploadError::InvalidChunk(_) => StatusCode::BAD_REQUEST,
+                UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                    StatusCode::INSUFFICIENT_STORAGE
+                }
+                UploadError::ChecksumMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+                UploadError::Io(_) | UploadError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            (
+                status,
+                Json(ErrorResponse {
+                    error: e.to_string(),
+                }),
+            )
+        })?;
+    let sha256 = saved.sha256.clone();
+
+    let metadata = storage
+        .record_file_metadata(saved, filename, Some(mime_type), None, parent_directory_id, None)
+        .await
+        .map_err(write_error(
+            "Failed to save file",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+    storage
+        .issue_receipt(&metadata, &sha256)
+        .await
+        .map_err(|e| internal_error(format!("Failed to issue upload receipt: {}", e)))?;
+    let share = storage
+        .create_share(&metadata.id, None, None)
+        .await
+        .map_err(|e| internal_error(format!("Failed to create share: {}", e)))?;
+
+    info!("Quick upload {} shared as {}", metadata.id, share.token);
+    Ok(format!("{}/s/{}", external_base_url(&storage, &headers), share.token))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShareXQuery {
+    /// The quick-upload API key, required when `QUICK_UPLOAD_API_KEY` is set.
+    pub key: Option<String>,
+}
+
+// ShareX uploader handler: a ready-made `.sxcu` file for `/api/quick`
+pub async fn sharex_uploader(
+    State(storage): State<FileStorage>,
+    Query(query): Query<ShareXQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    check_quick_upload_key(&storage, &headers, query.key.as_deref())?;
+
+    let base_url = external_base_url(&storage, &headers);
+    let host = base_url.split("://").nth(1).unwrap_or(&base_url).to_string();
+    let mut request_headers = BTreeMap::new();
+    if let Some(key) = storage.quick_upload_api_key() {
+        request_headers.insert("Authorization".to_string(), format!("Bearer {}", key));
+    }
+    if let Some(key) = headers
+        .get(tenants::API_KEY_HEADER)
+        .and_then(|v| v.to_str().ok())
+    {
+        request_headers.insert("X-Api-Key".to_string(), key.to_string());
+    }
+
+    let uploader = ShareXUploader {
+        version: "15.0.0".to_string(),
+        name: format!("Fileshare ({})", host),
+        destination_type: "ImageUploader".to_string(),
+        request_method: "POST".to_string(),
+        request_url: format!("{}/api/quick", base_url),
+        parameters: BTreeMap::from([("filename".to_string(), "{filename}".to_string())]),
+        headers: request_headers,
+        body: "Binary".to_string(),
+        url: "{response}".to_string(),
+    };
+
+    Ok((
+        [(
+            header::CONTENT_DISPOSITION,
+            "attachment; filename=\"fileshare.sxcu\"",
+        )],
+        Json(uploader),
+    )
+        .into_response())
+}
+
+const MAX_SHARE_TERMS_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ShareQuery {
+    /// Digest of the share's terms, confirming the recipient accepted them.
+    pub accept_terms: Option<String>,
+    /// For directory shares: the directory to list, beneath the shared one.
+    pub dir: Option<String>,
+    /// For directory shares: the file to download, beneath the shared directory.
+    pub file: Option<String>,
+    /// Confirms downloading a file type listed in `RISKY_EXTENSIONS`.
+    #[serde(default)]
+    pub confirm_download: bool,
+    /// For bundle shares: downloads all the files as one ZIP archive.
+    #[serde(default)]
+    pub archive: bool,
+}
+
+// Public share handler: serves the shared file inline, or the index of a
+// shared directory, without authentication
+pub async fn open_share(
+    State(storage): State<FileStorage>,
+    Path(token): Path<String>,
+    Query(query): Query<ShareQuery>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+) -> Response {
+    let mut response = serve_share(storage, token, query, addr, headers)
+        .await
+        .into_response();
+    // Only shared files are fit for caching. Pages and errors depend on the
+    // browser's language and on whether it asks for HTML.
+    if !response.headers().contains_key(header::CACHE_CONTROL) {
+        let headers = response.headers_mut();
+        headers.insert(header::CACHE_CONTROL, HeaderValue::from_static("no-store"));
+        headers.append(
+            header::VARY,
+            HeaderValue::from_static("accept, accept-language"),
+        );
+    }
+    response
+}
+
+async fn serve_share(
+    storage: FileStorage,
+    token: String,
+    query: ShareQuery,
+    addr: SocketAddr,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_found = || public_error(&storage, &headers, StatusCode::NOT_FOUND, "share-not-found");
+
+    let Some(share) = storage.get_share_by_token(&token).await.map_err(db_error)? else {
+        if let Some(share) = storage
+            .get_directory_share_by_token(&token)
+            .await
+            .map_err(db_error)?
+        {
+            return open_directory_share(storage, share, query, addr, headers).await;
+        }
+        let Some(share) = storage
+            .get_bundle_share_by_token(&token)
+            .await
+            .map_err(db_error)?
+        else {
+            return Ok(not_found());
+        };
+        return open_bundle_share(storage, share, query, addr, headers).await;
+    };
+    let Some(metadata) = storage
+        .get_file_metadata(&share.file_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Ok(not_found());
+    };
+
+    let cdn = storage.cdn().filter(|_| served_by_cdn(&share));
+
+    // Shares with terms only serve the file once the current version of
+    // them has been accepted.
+    let accepted_terms = match share.terms {
+        Some(terms) => {
+            let digest = access::terms_digest(&terms);
+            if query.accept_terms.as_deref() != Some(digest.as_str()) {
+                let accept_url = format!("/s/{}?accept_terms={}", share.token, digest);
+                let locale = storage.i18n().negotiate(&headers);
+                if accepts_html(&headers) {
+                    let page = pages::terms_page(
+                        storage.branding(),
+                        &locale,
+                        &metadata.original_filename,
+                        &terms,
+                        &accept_url,
+                    );
+                    return Ok((StatusCode::FORBIDDEN, Html(page)).into_response());
+                }
+                return Ok((
+                    StatusCode::FORBIDDEN,
+                    Json(ShareTermsRequired {
+                        error: locale.text("share-terms-required"),
+                        terms,
+                        terms_digest: digest,
+                        accept_url,
+                    }),
+                )
+                    .into_response());
+            }
+            Some(digest)
+        }
+        None => None,
+    };
+
+    if !query.confirm_download {
+        let confirm_url = match &accepted_terms {
+            Some(digest) => format!(
+                "/s/{}?accept_terms={}&confirm_download=true",
+                share.token, digest
+            ),
+            None => format!("/s/{}?confirm_download=true", share.token),
+        };
+        if let Some(warning) = download_warning(&storage, &headers, &metadata, &confirm_url) {
+            return Ok(warning);
+        }
+    }
+
+    let (response, counts) = match cdn {
+        // The CDN fetches the file from `/cdn/:token`; handing the
+        // recipient over to it is what counts as the download.
+        Some(cdn) => {
+            let expires_at = share
+                .expires_at
+                .as_deref()
+                .and_then(|time| DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&Utc));
+            let url = cdn.share_url(&share.token, expires_at);
+            (
+                Redirect::temporary(url.as_str()).into_response(),
+                !headers.contains_key(header::RANGE),
+            )
+        }
+        None => {
+            // Caches would hide downloads from a share's download limit.
+            let caching = match share.max_downloads {
+                Some(_) => Caching::NoStore,
+                None => Caching::public_until(&storage, share.expires_at.as_deref()),
+            };
+            public_file_response(&storage, &metadata, &headers, "inline", caching).await?
+        }
+    };
+    if !counts {
+        return Ok(response);
+    }
+    // Another download may have used up the share's last one since it was
+    // looked up.
+    if !storage
+        .consume_share_download(&share.id)
+        .await
+        .map_err(db_error)?
+    {
+        return Ok(not_found());
+    }
+
+    let access = AccessContext {
+        share_id: Some(share.id),
+        accepted_terms,
+        ..AccessContext::from_request(addr, &headers)
+    };
+    if let Err(e) = storage.record_download(&metadata, &access).await {
+        error!("Failed to record download of {}: {}", metadata.id, e);
+    }
+    Ok(response)
+}
+
+/// Whether a share's downloads may go through the CDN. Shares with terms or
+/// a download limit have to see every download, which a CDN's cache would
+/// hide.
+fn served_by_cdn(share: &Share) -> bool {
+    share.terms.is_none() && share.max_downloads.is_none()
+}
+
+/// What a recipient may report a share for.
+const REPORT_REASONS: [&str; 6] = [
+    "spam",
+    "malware",
+    "copyright",
+    "illegal",
+    "harassment",
+    "other",
+];
+const MAX_REPORT_DETAILS_LEN: usize = 2000;
+
+/// Lets a recipient flag what a share link serves as abusive. Reports go
+/// to the moderation queue; once they come from `SHARE_REPORT_THRESHOLD`
+/// different networks, the link is taken down until an admin reviews them.
+pub async fn report_share(
+    State(storage): State<FileStorage>,
+    Path(token): Path<String>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    headers: HeaderMap,
+    Json(payload): Json<ReportShareRequest>,
+) -> Result<Json<ReportShareResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let locale = storage.i18n().negotiate(&headers);
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let reason = payload.reason.trim().to_ascii_lowercase();
+    if !REPORT_REASONS.contains(&reason.as_str()) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: locale.message(
+                    "share-report-invalid-reason",
+                    &[("reasons", REPORT_REASONS.join(", ").into())],
+                ),
+            }),
+        ));
+    }
+    let details = payload
+        .details
+        .map(|d| {
+            d.trim()
+                .chars()
+                .take(MAX_REPORT_DETAILS_LEN)
+                .collect::<String>()
+        })
+        .filter(|d| !d.is_empty());
+
+    let target = match storage.get_share_by_token(&token).await.map_err(db_error)? {
+        Some(share) => Some((share.id, "file", share.file_id)),
+        None => storage
+            .get_directory_share_by_token(&token)
+            .await
+            .map_err(db_error)?
+            .map(|share| (share.id, "directory", share.directory_id)),
+    };
+    let target = match target {
+        Some(target) => Some(target),
+        None => storage
+            .get_bundle_share_by_token(&token)
+            .await
+            .map_err(db_error)?
+            .map(|share| (share.id.clone(), "bundle", share.id)),
+    };
+    let Some((share_id, share_kind, target_id)) = target else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: locale.text("share-not-found"),
+            }),
+        ));
+    };
+
+    let report = ReportShareRequest { reason, details };
+    let network = access::truncate_ip(addr.ip());
+    let disabled = storage
+        .report_share(&share_id, share_kind, &target_id, &report, &network)
+        .await
+        .map_err(db_error)?;
+    if disabled {
+        warn!(
+            "Share {} taken down after reports from {} networks",
+            share_id,
+            storage.share_report_threshold()
+        );
+    } else {
+        info!("Share {} reported for {}", share_id, report.reason);
+    }
+
+    Ok(Json(ReportShareResponse {
+        success: true,
+        message: locale.text("share-report-received"),
+    }))
+}
+
+/// Sends a shared file to the CDN that share links redirect to. Only shares
+/// the CDN may serve are found here, and fetches aren't counted: the
+/// redirect to the CDN was.
+pub async fn open_cdn_origin(
+    State(storage): State<FileStorage>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_found = || public_error(&storage, &headers, StatusCode::NOT_FOUND, "share-not-found");
+
+    if storage.cdn().is_none() {
+        return Ok(not_found());
+    }
+    let Some(share) = storage
+        .get_share_by_token(&token)
+        .await
+        .map_err(db_error)?
+        .filter(served_by_cdn)
+    else {
+        return Ok(not_found());
+    };
+    let Some(metadata) = storage
+        .get_file_metadata(&share.file_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Ok(not_found());
+    };
+
+    let caching = Caching::public_until(&storage, share.expires_at.as_deref());
+    let (response, _) =
+        public_file_response(&storage, &metadata, &headers, "inline", caching).await?;
+    Ok(response)
+}
+
+/// Whether a request comes from a browser, which gets an HTML page rather
+/// than JSON.
+fn accepts_html(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|accept| accept.contains("text/html"))
+}
+
+/// The interstitial to answer with instead of a shared file whose type
+/// could run on the recipient's machine, until they open `confirm_url`.
+fn download_warning(
+    storage: &FileStorage,
+    headers: &HeaderMap,
+    metadata: &FileMetadata,
+    confirm_url: &str,
+) -> Option<Response> {
+    let extension = storage
+        .download_warnings()
+        .risky_extension(&metadata.original_filename)?;
+    let locale = storage.i18n().negotiate(headers);
+    if accepts_html(headers) {
+        let page = pages::download_warning_page(
+            storage.branding(),
+            &locale,
+            &metadata.original_filename,
+            &extension,
+            confirm_url,
+        );
+        return Some((StatusCode::FORBIDDEN, Html(page)).into_response());
+    }
+    Some(
+        (
+            StatusCode::FORBIDDEN,
+            Json(DownloadConfirmationRequired {
+                error: locale.text("download-confirmation-required"),
+                extension,
+                confirm_url: confirm_url.to_string(),
+            }),
+        )
+            .into_response(),
+    )
+}
+
+/// A localized error for a public link: an HTML page for browsers, JSON for
+/// everything else.
+fn public_error(
+    storage: &FileStorage,
+    headers: &HeaderMap,
+    status: StatusCode,
+    message_id: &str,
+) -> Response {
+    let locale = storage.i18n().negotiate(headers);
+    let message = locale.text(message_id);
+    if accepts_html(headers) {
+        let page = pages::error_page(storage.branding(), &locale, &message);
+        return (status, Html(page)).into_response();
+    }
+    (status, Json(ErrorResponse { error: message })).into_response()
+}
+
+/// Serves a directory share: the index of the shared directory or of one
+/// beneath it, or a file from anywhere in the tree as an attachment.
+async fn open_directory_share(
+    storage: FileStorage,
+    share: DirectoryShare,
+    query: ShareQuery,
+    addr: SocketAddr,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_found = || public_error(&storage, &headers, StatusCode::NOT_FOUND, "not-found");
+
+    if let Some(file_id) = query.file.as_deref() {
+        let Some(metadata) = storage.get_file_metadata(file_id).await.map_err(db_error)? else {
+            return Ok(not_found());
+        };
+        // Only files beneath the shared directory can be reached.
+        let inside = match metadata.parent_directory_id.as_deref() {
+            Some(parent_id) => storage
+                .directory_chain(&share.directory_id, parent_id)
+                .await
+                .map_err(db_error)?
+                .is_some(),
+            None => false,
+        };
+        if !inside {
+            return Ok(not_found());
+        }
+        if !query.confirm_download {
+            let confirm_url = format!("/s/{}?file={}&confirm_download=true", share.token, file_id);
+            if let Some(warning) = download_warning(&storage, &headers, &metadata, &confirm_url) {
+                return Ok(warning);
+            }
+        }
+
+        let caching = Caching::public_until(&storage, share.expires_at.as_deref());
+        let (response, counts) =
+            public_file_response(&storage, &metadata, &headers, "attachment", caching).await?;
+        if counts {
+            let access = AccessContext {
+                share_id: Some(share.id),
+                ..AccessContext::from_request(addr, &headers)
+            };
+            if let Err(e) = storage.record_download(&metadata, &access).await {
+                error!("Failed to record download of {}: {}", metadata.id, e);
+            }
+        }
+        return Ok(response);
+    }
+
+    let dir_id = query.dir.as_deref().unwrap_or(&share.directory_id);
+    let Some(chain) = storage
+        .directory_chain(&share.directory_id, dir_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Ok(not_found());
+    };
+    let mut directories = storage
+        .list_directories(Some(dir_id.to_string()))
+        .await
+        .map_err(db_error)?;
+    let mut files = storage
+        .list_files(Some(dir_id.to_string()))
+        .await
+        .map_err(db_error)?;
+    directories.sort_by_key(|d| d.name.to_lowercase());
+    files.sort_by_key(|f| f.original_filename.to_lowercase());
+
+    let page = pages::directory_index(
+        storage.branding(),
+        &storage.i18n().negotiate(&headers),
+        &share.token,
+        &chain,
+        &directories,
+        &files,
+    );
+    Ok(Html(page).into_response())
+}
+
+async fn open_bundle_share(
+    storage: FileStorage,
+    share: BundleShare,
+    query: ShareQuery,
+    addr: SocketAddr,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let files = storage.bundle_files(&share.id).await.map_err(db_error)?;
+    let access = AccessContext {
+        share_id: Some(share.id.clone()),
+        ..AccessContext::from_request(addr, &headers)
+    };
+
+    if let Some(file_id) = query.file.as_deref() {
+        let Some(metadata) = files.into_iter().find(|f| f.id == file_id) else {
+            return Ok(public_error(
+                &storage,
+                &headers,
+                StatusCode::NOT_FOUND,
+                "not-found",
+            ));
+        };
+        if !query.confirm_download {
+            let confirm_url = format!("/s/{}?file={}&confirm_download=true", share.token, file_id);
+            if let Some(warning) = download_warning(&storage, &headers, &metadata, &confirm_url) {
+                return Ok(warning);
+            }
+        }
+
+        let caching = Caching::public_until(&storage, share.expires_at.as_deref());
+        let (response, counts) =
+            public_file_response(&storage, &metadata, &headers, "attachment", caching).await?;
+        if counts {
+            if let Err(e) = storage.record_download(&metadata, &access).await {
+                error!("Failed to record download of {}: {}", metadata.id, e);
+            }
+        }
+        return Ok(response);
+    }
+
+    if query.archive {
+        let mut names = HashSet::new();
+        let mut entries = Vec::new();
+        for file in files {
+            if let Err(e) = storage.record_download(&file, &access).await {
+                error!("Failed to record download of {}: {}", file.id, e);
+            }
+            entries.push(zip::ArchiveEntry {
+                path: distinct_name(&mut names, &file.original_filename),
+                modified: file.uploaded_at.clone(),
+                content: zip::ArchiveContent::File(Box::new(file)),
+            });
+        }
+        info!(
+            "Streaming bundle share {} as a ZIP archive of {} files",
+            share.id,
+            entries.len()
+        );
+        return finish_response(
+            Response::builder()
+                .status(StatusCode::OK)
+                .header(header::CONTENT_TYPE, "application/zip")
+                .header(
+                    header::CONTENT_DISPOSITION,
+                    content_disposition("attachment", &format!("{}.zip", share.token)),
+                ),
+            Body::from_stream(zip::archive(storage.clone(), entries)),
+        );
+    }
+
+    let page = pages::bundle_index(
+        storage.branding(),
+        &storage.i18n().negotiate(&headers),
+        &share.token,
+        share.title.as_deref(),
+        &files,
+    );
+    Ok(Html(page).into_response())
+}
+
+/// `name`, numbered like `report (2).pdf` if it is among `taken` already,
+/// so files of the same name don't overwrite each other in an archive.
+fn distinct_name(taken: &mut HashSet<String>, name: &str) -> String {
+    let (stem, extension) = match name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => (stem, format!(".{}", extension)),
+        _ => (name, String::new()),
+    };
+    let mut candidate = name.to_string();
+    let mut n = 2;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{} ({}){}", stem, n, extension);
+        n += 1;
+    }
+    candidate
+}
+
+/// Parses an RFC 3339 time that has to lie ahead, normalized to UTC.
+fn future_time(value: &str, what: &str) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+    let time = DateTime::parse_from_rfc3339(value)
+        .map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("{} must be an RFC 3339 timestamp", what),
+                }),
+            )
+        })?
+        .with_timezone(&Utc);
+    if time <= Utc::now() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("{} must be in the future", what),
+            }),
+        ));
+    }
+    Ok(time.to_rfc3339())
+}
+
+fn share_response(storage: &FileStorage, headers: &HeaderMap, share: Share) -> ShareResponse {
+    let expired = share
+        .expires_at
+        .as_deref()
+        .and_then(|t| DateTime::parse_from_rfc3339(t).ok())
+        .is_some_and(|t| t <= Utc::now());
+    ShareResponse {
+        url: format!("{}/s/{}", external_base_url(storage, headers), share.token),
+        id: share.id,
+        file_id: share.file_id,
+        created_at: share.created_at,
+        expires_at: share.expires_at,
+        expired,
+        max_downloads: share.max_downloads,
+        download_count: share.download_count,
+        terms: share.terms,
+        disabled_at: share.disabled_at,
+    }
+}
+
+// Share file handler: creates a public link to a file, optionally expiring
+pub async fn share_file(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateShareRequest>,
+) -> Result<Json<ShareResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if mirror::is_mirror_id(&file_id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Mirrored files can't be shared".to_string(),
+            }),
+        ));
+    }
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .map(|expires_at| future_time(expires_at, "Expiry"))
+        .transpose()?;
+    if payload.max_downloads.is_some_and(|max| max < 1) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "max_downloads must be at least 1".to_string(),
+            }),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let Some(metadata) = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        ));
+    };
+    require_role(
+        &storage,
+        &user,
+        metadata.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+
+    let share = storage
+        .create_share(&file_id, expires_at, payload.max_downloads)
+        .await
+        .map_err(db_error)?;
+    info!("File {} shared as {}", file_id, share.token);
+
+    Ok(Json(share_response(&storage, &headers, share)))
+}
+
+// List file shares handler: every link to a file, expired ones included
+pub async fn list_file_shares(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<ShareResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let Some(metadata) = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        ));
+    };
+    require_role(
+        &storage,
+        &user,
+        metadata.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+
+    let shares = storage.list_shares(&file_id).await.map_err(db_error)?;
+    Ok(Json(
+        shares
+            .into_iter()
+            .map(|share| share_response(&storage, &headers, share))
+            .collect(),
+    ))
+}
+
+// Revoke share handler: removes a file, directory or bundle share, so its
+// link stops resolving
+pub async fn revoke_share(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(share_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_share_role(&storage, &user, &share_id).await?;
+
+    let revoked = storage.revoke_share(&share_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if !revoked {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Share not found".to_string(),
+            }),
+        ));
+    }
+
+    info!("Share {} revoked", share_id);
+    Ok(Json(DeleteResponse {
+        success: true,
+        message: "Share revoked".to_string(),
+    }))
+}
+
+// Share directory handler: publishes a read-only index of a directory tree
+pub async fn share_directory(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<DirectoryShareResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if mirror::is_mirror_id(&dir_id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Mirrored directories can't be shared".to_string(),
+            }),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+
+    let share = storage
+        .create_directory_share(&dir_id)
+        .await
+        .map_err(db_error)?;
+    info!("Directory {} shared as {}", dir_id, share.token);
+
+    Ok(Json(DirectoryShareResponse {
+        url: format!("{}/s/{}", external_base_url(&storage, &headers), share.token),
+        id: share.id,
+        directory_id: share.directory_id,
+        created_at: share.created_at,
+        expires_at: share.expires_at,
+    }))
+}
+
+/// Files a single bundle share may hold.
+const MAX_BUNDLE_FILES: usize = 100;
+const MAX_BUNDLE_TITLE_LEN: usize = 200;
+
+// Share bundle handler: publishes one link to a set of files from any
+// directories, with a list page and a download of them all
+pub async fn share_bundle(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateBundleShareRequest>,
+) -> Result<Json<BundleShareResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+    let mut file_ids: Vec<String> = Vec::new();
+    for file_id in payload.file_ids {
+        if !file_ids.contains(&file_id) {
+            file_ids.push(file_id);
+        }
+    }
+    if file_ids.is_empty() {
+        return Err(bad_request(
+            "file_ids must name at least one file".to_string(),
+        ));
+    }
+    if file_ids.len() > MAX_BUNDLE_FILES {
+        return Err(bad_request(format!(
+            "A bundle can hold at most {} files",
+            MAX_BUNDLE_FILES
+        )));
+    }
+    if file_ids.iter().any(|id| mirror::is_mirror_id(id)) {
+        return Err(bad_request("Mirrored files can't be shared".to_string()));
+    }
+    let title = payload
+        .title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    if title
+        .as_ref()
+        .is_some_and(|t| t.chars().count() > MAX_BUNDLE_TITLE_LEN)
+    {
+        return Err(bad_request(format!(
+            "Title must be at most {} characters",
+            MAX_BUNDLE_TITLE_LEN
+        )));
+    }
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .map(|expires_at| future_time(expires_at, "Expiry"))
+        .transpose()?;
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    for file_id in &file_ids {
+        let Some(metadata) = storage.get_file_metadata(file_id).await.map_err(db_error)? else {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: format!("File {} not found", file_id),
+                }),
+            ));
+        };
+        require_role(
+            &storage,
+            &user,
+            metadata.parent_directory_id.as_deref(),
+            Role::Write,
+        )
+        .await?;
+    }
+
+    let share = storage
+        .create_bundle_share(&file_ids, title, expires_at)
+        .await
+        .map_err(db_error)?;
+    info!("{} files shared as bundle {}", file_ids.len(), share.token);
+
+    let url = format!(
+        "{}/s/{}",
+        external_base_url(&storage, &headers),
+        share.token
+    );
+    Ok(Json(BundleShareResponse {
+        archive_url: format!("{}?archive=true", url),
+        url,
+        id: share.id,
+        file_ids,
+        title: share.title,
+        created_at: share.created_at,
+        expires_at: share.expires_at,
+    }))
+}
+
+const MAX_SENDER_NAME_LEN: usize = 200;
+const MAX_SENDER_EMAIL_LEN: usize = 254;
+
+/// Whether an upload link's deadline has passed.
+fn upload_link_closed(link: &UploadLink) -> bool {
+    link.deadline
+        .as_deref()
+        .and_then(|deadline| DateTime::parse_from_rfc3339(deadline).ok())
+        .is_some_and(|deadline| deadline <= Utc::now())
+}
+
+/// A loose check that catches typos, not a full RFC 5322 parser.
+fn is_email_address(email: &str) -> bool {
+    email.len() <= MAX_SENDER_EMAIL_LEN
+        && !email.chars().any(char::is_whitespace)
+        && email
+            .split_once('@')
+            .is_some_and(|(local, domain)| !local.is_empty() && domain.contains('.'))
+}
+
+// Create upload link handler: lets guests send files into a directory,
+// each with their name, until an optional deadline
+pub async fn create_upload_link(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateUploadLinkRequest>,
+) -> Result<Json<UploadLinkResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([dir_id.as_str()])?;
+
+    let deadline = payload
+        .deadline
+        .as_deref()
+        .map(|deadline| future_time(deadline, "Deadline"))
+        .transpose()?;
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+
+    let link = storage
+        .create_upload_link(&dir_id, deadline)
+        .await
+        .map_err(db_error)?;
+    info!("Upload link {} created for directory {}", link.token, dir_id);
+
+    Ok(Json(UploadLinkResponse {
+        url: format!("{}/u/{}", external_base_url(&storage, &headers), link.token),
+        id: link.id,
+        directory_id: link.directory_id,
+        created_at: link.created_at,
+        deadline: link.deadline,
+    }))
+}
+
+/// Looks up an open upload link and the directory it uploads into, or the
+/// public error to answer with.
+async fn find_upload_link(
+    storage: &FileStorage,
+    headers: &HeaderMap,
+    token: &str,
+) -> Result<Result<(UploadLink, Directory), Response>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_found = || public_error(storage, headers, StatusCode::NOT_FOUND, "upload-link-not-found");
+
+    let Some(link) = storage
+        .get_upload_link_by_token(token)
+        .await
+        .map_err(db_error)?
+    else {
+        return Ok(Err(not_found()));
+    };
+    let Some(directory) = storage
+        .get_directory(&link.directory_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Ok(Err(not_found()));
+    };
+    if upload_link_closed(&link) {
+        return Ok(Err(public_error(
+            storage,
+            headers,
+            StatusCode::GONE,
+            "upload-deadline-passed",
+        )));
+    }
+    Ok(Ok((link, directory)))
+}
+
+// Public upload link handler: the form guests send their file with
+pub async fn open_upload_link(
+    State(storage): State<FileStorage>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let (link, directory) = match find_upload_link(&storage, &headers, &token).await? {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+
+    if accepts_html(&headers) {
+        let page = pages::upload_page(
+            storage.branding(),
+            &storage.i18n().negotiate(&headers),
+            &directory.name,
+            link.deadline.as_deref(),
+            &format!("/u/{}", link.token),
+        );
+        return Ok(Html(page).into_response());
+    }
+    Ok(Json(UploadLinkInfo {
+        directory_name: directory.name,
+        deadline: link.deadline,
+    })
+    .into_response())
+}
+
+// Public upload handler: stores a guest's file in the link's directory and
+// records who sent it. The sender fields have to come before the file.
+pub async fn submit_upload(
+    State(storage): State<FileStorage>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let (link, directory) = match find_upload_link(&storage, &headers, &token).await? {
+        Ok(found) => found,
+        Err(response) => return Ok(response),
+    };
+    let bad_request = |message_id| public_error(&storage, &headers, StatusCode::BAD_REQUEST, message_id);
+
+    let mut sender_name = String::new();
+    let mut sender_email: Option<String> = None;
+    let mut upload: Option<(SavedUpload, String, Option<String>)> = None;
+
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read multipart field: {}", e);
+                return Ok(bad_request("upload-failed"));
+            }
+        };
+        match field.name().unwrap_or("") {
+            "sender_name" | "sender_email" => {
+                let is_name = field.name() == Some("sender_name");
+                let Ok(text) = field.text().await else {
+                    return Ok(bad_request("upload-failed"));
+                };
+                let text = text.trim().to_string();
+                if is_name {
+                    sender_name = text;
+                } else if !text.is_empty() {
+                    sender_email = Some(text);
+                }
+            }
+            "file" if upload.is_none() => {
+                if sender_name.is_empty() || sender_name.chars().count() > MAX_SENDER_NAME_LEN {
+                    return Ok(bad_request("upload-sender-required"));
+                }
+                if sender_email.as_deref().is_some_and(|email| !is_email_address(email)) {
+                    return Ok(bad_request("upload-invalid-email"));
+                }
+
+                let original_filename = storage
+                    .name_policy()
+                    .normalize(field.file_name().unwrap_or("unnamed"));
+                if storage.name_policy().validate(&original_filename).is_err() {
+                    return Ok(bad_request("upload-failed"));
+                }
+                let mime_type = field.content_type().map(|s| s.to_string());
+                let saved = match storage
+                    .save_file(&original_filename, &mut field, &ExpectedChecksum::None)
+                    .await
+                {
+                    Ok(saved) => saved,
+                    Err(e) => {
+                        error!("Failed to store guest upload: {}", e);
+                        let status = match e {
+                            UploadError::Body(_) | UploadError::InvalidChunk(_) => {
+                                StatusCode::BAD_REQUEST
+                            }
+                            UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                                StatusCode::INSUFFICIENT_STORAGE
+                            }
+                            UploadError::ChecksumMismatch { .. } => {
+                                StatusCode::UNPROCESSABLE_ENTITY
+                            }
+                            UploadError::Io(_) | UploadError::Database(_) => {
+                                StatusCode::INTERNAL_SERVER_ERROR
+                            }
+                        };
+                        return Ok(public_error(&storage, &headers, status, "upload-failed"));
+                    }
+                };
+                upload = Some((saved, original_filename, mime_type));
+            }
+            _ => {}
+        }
+    }
+
+    let Some((saved, original_filename, mime_type)) = upload else {
+        return Ok(bad_request("upload-no-file"));
+    };
+    let metadata = storage
+        .record_file_metadata(
+            saved,
+            original_filename,
+            mime_type,
+            None,
+            Some(directory.id.clone()),
+            None,
+        )
+        .await
+        .map_err(write_error(
+            "Failed to save file",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+    let submission = storage
+        .record_submission(&link, &metadata, &sender_name, sender_email.as_deref())
+        .await
+        .map_err(|e| {
+            error!("Failed to record submission: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to record submission: {}", e),
+                }),
+            )
+        })?;
+    info!(
+        "File {} submitted through upload link {} by {}",
+        metadata.id, link.id, submission.sender_name
+    );
+
+    if accepts_html(&headers) {
+        let page = pages::upload_received_page(
+            storage.branding(),
+            &storage.i18n().negotiate(&headers),
+            &submission.original_filename,
+            &format!("/u/{}", link.token),
+        );
+        return Ok(Html(page).into_response());
+    }
+    Ok(Json(submission).into_response())
+}
+
+fn drop_link_response(
+    storage: &FileStorage,
+    headers: &HeaderMap,
+    link: DropLink,
+) -> DropLinkResponse {
+    DropLinkResponse {
+        url: format!("{}/drop/{}", external_base_url(storage, headers), link.token),
+        id: link.id,
+        directory_id: link.directory_id,
+        created_at: link.created_at,
+        expires_at: link.expires_at,
+    }
+}
+
+// Create drop link handler: lets anyone with the link post files into a
+// directory, without seeing what is in it
+pub async fn create_drop_link(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<CreateDropLinkRequest>,
+) -> Result<Json<DropLinkResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([dir_id.as_str()])?;
+    let expires_at = payload
+        .expires_at
+        .as_deref()
+        .map(|expires_at| future_time(expires_at, "Expiry"))
+        .transpose()?;
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+
+    let link = storage
+        .create_drop_link(&dir_id, expires_at)
+        .await
+        .map_err(db_error)?;
+    info!("Drop link {} created for directory {}", link.token, dir_id);
+
+    Ok(Json(drop_link_response(&storage, &headers, link)))
+}
+
+// List drop links handler: a directory's drop links, expired ones included
+pub async fn list_drop_links(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    headers: HeaderMap,
+) -> Result<Json<Vec<DropLinkResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+
+    let links = storage.list_drop_links(&dir_id).await.map_err(db_error)?;
+    Ok(Json(
+        links
+            .into_iter()
+            .map(|link| drop_link_response(&storage, &headers, link))
+            .collect(),
+    ))
+}
+
+// Public drop handler: stores every `file` field of a multipart body in the
+// link's directory. The answer describes what was received, but nothing
+// that would let the sender find or list files.
+pub async fn drop_files(
+    State(storage): State<FileStorage>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let internal_error = |message: String| {
+        error!("{}", message);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse { error: message }),
+        )
+    };
+    let not_found = || public_error(&storage, &headers, StatusCode::NOT_FOUND, "upload-link-not-found");
+    let bad_request = |message_id| public_error(&storage, &headers, StatusCode::BAD_REQUEST, message_id);
+
+    let Some(link) = storage
+        .get_drop_link_by_token(&token)
+        .await
+        .map_err(|e| internal_error(format!("Database error: {}", e)))?
+    else {
+        return Ok(not_found());
+    };
+    if storage
+        .get_directory(&link.directory_id)
+        .await
+        .map_err(|e| internal_error(format!("Database error: {}", e)))?
+        .is_none()
+    {
+        return Ok(not_found());
+    }
+
+    let mut files = Vec::new();
+    loop {
+        let mut field = match multipart.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) => {
+                error!("Failed to read multipart field: {}", e);
+                return Ok(bad_request("upload-failed"));
+            }
+        };
+        if field.name() != Some("file") {
+            continue;
+        }
+
+        let original_filename = storage
+            .name_policy()
+            .normalize(field.file_name().unwrap_or("unnamed"));
+        if storage.name_policy().validate(&original_filename).is_err() {
+            return Ok(bad_request("upload-failed"));
+        }
+        let mime_type = field.content_type().map(|s| s.to_string());
+        let saved = match storage
+            .save_file(&original_filename, &mut field, &ExpectedChecksum::None)
+            .await
+        {
+            Ok(saved) => saved,
+            Err(e) => {
+                error!("Failed to store dropped file: {}", e);
+                let status = match e {
+                    UploadError::Body(_) | UploadError::InvalidChunk(_) => StatusCode::BAD_REQUEST,
+                    UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                        StatusCode::INSUFFICIENT_STORAGE
+                    }
+                    UploadError::ChecksumMismatch { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+                    UploadError::Io(_) | UploadError::Database(_) => {
+                        StatusCode::INTERNAL_SERVER_ERROR
+                    }
+                };
+                return Ok(public_error(&storage, &headers, status, "upload-failed"));
+            }
+        };
+        let sha256 = saved.sha256.clone();
+
+        let metadata = storage
+            .record_file_metadata(
+                saved,
+                original_filename,
+                mime_type,
+                None,
+                Some(link.directory_id.clone()),
+                None,
+            )
+            .await
+            .map_err(write_error(
+                "Failed to save file",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))?;
+        storage
+            .issue_receipt(&metadata, &sha256)
+            .await
+            .map_err(|e| internal_error(format!("Failed to issue upload receipt: {}", e)))?;
+        info!("File {} dropped through link {}", metadata.id, link.id);
+
+        files.push(DroppedFile {
+            name: metadata.original_filename,
+            size: metadata.file_size,
+            sha256,
+        });
+    }
+
+    if files.is_empty() {
+        return Ok(bad_request("upload-no-file"));
+    }
+    Ok(Json(DropReceipt { files }).into_response())
+}
+
+// Submissions report handler: who sent what through an upload link
+pub async fn list_submissions(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(link_id): Path<String>,
+) -> Result<Json<SubmissionsReport>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let link = storage
+        .get_upload_link(&link_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Upload link not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(&storage, &user, Some(&link.directory_id), Role::Owner).await?;
+    let submissions = storage.list_submissions(&link.id).await.map_err(db_error)?;
+
+    let senders: BTreeSet<(String, Option<String>)> = submissions
+        .iter()
+        .map(|s| {
+            (
+                s.sender_name.to_lowercase(),
+                s.sender_email.as_deref().map(str::to_lowercase),
+            )
+        })
+        .collect();
+
+    Ok(Json(SubmissionsReport {
+        closed: upload_link_closed(&link),
+        upload_link_id: link.id,
+        directory_id: link.directory_id,
+        deadline: link.deadline,
+        sender_count: senders.len() as i64,
+        submissions,
+    }))
+}
+
+// Set share terms handler: gates a share behind terms its recipients accept
+pub async fn set_share_terms(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(share_id): Path<String>,
+    Json(payload): Json<SetShareTermsRequest>,
+) -> Result<Json<ShareTerms>, (StatusCode, Json<ErrorResponse>)> {
+    let terms = payload
+        .terms
+        .as_deref()
+        .map(str::trim)
+        .filter(|terms| !terms.is_empty());
+    if terms.is_some_and(|terms| terms.len() > MAX_SHARE_TERMS_LEN) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Terms must be at most {} bytes", MAX_SHARE_TERMS_LEN),
+            }),
+        ));
+    }
+    require_share_role(&storage, &user, &share_id).await?;
+
+    let share = storage
+        .set_share_terms(&share_id, terms)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Share not found".to_string(),
+                }),
+            )
+        })?;
+
+    info!(
+        "Terms of share {} {}",
+        share.id,
+        if share.terms.is_some() { "set" } else { "cleared" }
+    );
+    Ok(Json(ShareTerms {
+        terms_digest: share.terms.as_deref().map(access::terms_digest),
+        share_id: share.id,
+        terms: share.terms,
+    }))
+}
+
+// List all files and directories handler
+pub async fn list_files(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<ListQuery>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let access = require_role(
+        &storage,
+        &user,
+        query.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+    if query.recursive {
+        return list_tree(storage, query, access)
+            .await
+            .map(IntoResponse::into_response);
+    }
+    let order = query.sort_order(&storage);
+    let filter = query.file_filter()?;
+    let explicit_order = query.explicit_order()?;
+
+    let mut files = storage
+        .list_files_matching(
+            query.parent_directory_id.clone(),
+            &filter,
+            explicit_order.unwrap_or_default(),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to list files: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to list files: {}", e),
+                }),
+            )
+        })?;
+
+    let mut links = storage
+        .list_links(query.parent_directory_id.clone())
+        .await
+        .map_err(|e| {
+            error!("Failed to list links: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to list links: {}", e),
+                }),
+            )
+        })?;
+
+    let mut directories = storage
+        .list_directories(query.parent_directory_id.clone())
+        .await
+        .map_err(|e| {
+            error!("Failed to list directories: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to list directories: {}", e),
+                }),
+            )
+        })?;
+    directories.retain(|dir| access.allows(Some(&dir.id), Role::Read));
+
+    let arrangement = match query.parent_directory_id.as_deref() {
+        Some(dir_id) => storage
+            .get_directory_arrangement(dir_id)
+            .await
+            .map_err(|e| {
+                error!("Database error: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Database error: {}", e),
+                    }),
+                )
+            })?,
+        None => None,
+    };
+    // A `natural` choice in the request wins over the directory's own sort.
+    let curated_sort = arrangement
+        .as_ref()
+        .and_then(|a| a.sort.as_deref())
+        .and_then(DirectorySort::from_name)
+        .filter(|_| query.natural.is_none());
+
+    if let Some(explicit) = explicit_order {
+        directory_sort(explicit).sort_links(&mut links);
+    } else if let Some(sort) = curated_sort {
+        sort.sort_files(&mut files);
+        sort.sort_links(&mut links);
+    } else if order.natural {
+        files.sort_by(|a, b| order.compare_names(&a.original_filename, &b.original_filename));
+        directories.sort_by(|a, b| order.compare_names(&a.name, &b.name));
+        links.sort_by(|a, b| order.compare_names(&a.name, &b.name));
+    }
+
+    // Get stats for each directory
+    let mut directory_responses = Vec::new();
+    for dir in directories {
+        let (file_count, total_size) = storage.get_directory_stats(&dir.id).await.map_err(|e| {
+            error!("Failed to get directory stats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to get directory stats: {}", e),
+                }),
+            )
+        })?;
+
+        directory_responses.push(DirectoryResponse {
+            id: dir.id,
+            name: dir.name,
+            parent_id: dir.parent_id,
+            created_at: dir.created_at,
+            updated_at: dir.updated_at,
+            color: dir.color,
+            icon: dir.icon,
+            version: dir.version,
+            file_count,
+            total_size,
+            readme: None,
+        });
+    }
+
+    if let Some(sort) = explicit_order.map(directory_sort).or(curated_sort) {
+        sort.sort_directories(&mut directory_responses);
+    }
+    if let Some(arrangement) = arrangement.as_ref().filter(|_| explicit_order.is_none()) {
+        sorting::pin_first(&mut files, &arrangement.pinned, |f| &f.id);
+        sorting::pin_first(&mut directory_responses, &arrangement.pinned, |d| &d.id);
+        sorting::pin_first(&mut links, &arrangement.pinned, |l| &l.id);
+    }
+
+    let total = files.len() + directory_responses.len() + links.len();
+    let file_responses: Vec<FileResponse> = files.into_iter().map(|f| f.into()).collect();
+
+    Ok(Json(ListFilesResponse {
+        files: file_responses,
+        directories: directory_responses,
+        links: links.into_iter().map(|l| l.into()).collect(),
+        total,
+        arrangement,
+    })
+    .into_response())
+}
+
+// Recursive listing: the flattened subtree with relative paths, one page at a time
+async fn list_tree(
+    storage: FileStorage,
+    query: ListQuery,
+    access: Access,
+) -> Result<Json<ListTreeResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let order = query.sort_order(&storage);
+    let filter = query.file_filter()?;
+    let mut entries = storage
+        .list_tree(query.parent_directory_id, &filter, &access)
+        .await
+        .map_err(|e| {
+            error!("Failed to list directory tree: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to list directory tree: {}", e),
+                }),
+            )
+        })?;
+
+    order.sort_tree(&mut entries);
+
+    let offset = query.offset.unwrap_or(0);
+    let limit = query
+        .limit
+        .unwrap_or(DEFAULT_TREE_PAGE_SIZE)
+        .clamp(1, MAX_TREE_PAGE_SIZE);
+    let total = entries.len();
+
+    Ok(Json(ListTreeResponse {
+        entries: entries.into_iter().skip(offset).take(limit).collect(),
+        total,
+        offset,
+        limit,
+    }))
+}
+
+/// Files a client's manifest may list.
+const MAX_MANIFEST_ENTRIES: usize = 100_000;
+
+// Compare handler: what was added, removed and changed in a directory tree
+// relative to another directory, or to the files a client holds
+pub async fn compare_directories(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(payload): Json<CompareRequest>,
+) -> Result<Json<CompareResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: error.to_string(),
+            }),
+        )
+    };
+    if payload.base_directory_id.is_some() == payload.manifest.is_some() {
+        return Err(bad_request(
+            "Exactly one of base_directory_id and manifest must be given",
+        ));
+    }
+    if payload
+        .manifest
+        .as_ref()
+        .is_some_and(|m| m.len() > MAX_MANIFEST_ENTRIES)
+    {
+        return Err(bad_request(&format!(
+            "A manifest can list at most {} files",
+            MAX_MANIFEST_ENTRIES
+        )));
+    }
+
+    let current = compared_tree(&storage, &user, payload.directory_id).await?;
+    let base = match payload.base_directory_id {
+        Some(base_id) => compared_tree(&storage, &user, base_id).await?,
+        None => compare::from_manifest(payload.manifest.unwrap_or_default())
+            .map_err(|error| bad_request(&error))?,
+    };
+    Ok(Json(compare::compare(current, base)))
+}
+
+/// The files beneath a directory being compared, by relative path.
+async fn compared_tree(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    dir_id: String,
+) -> Result<BTreeMap<String, compare::Version>, (StatusCode, Json<ErrorResponse>)> {
+    let exists = storage.get_directory(&dir_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if exists.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: format!("Directory {} not found", dir_id),
+            }),
+        ));
+    }
+    let access = require_role(storage, user, Some(&dir_id), Role::Read).await?;
+    let entries = storage
+        .list_tree(Some(dir_id), &FileFilter::default(), &access)
+        .await
+        .map_err(|e| {
+            error!("Failed to list directory tree: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to list directory tree: {}", e),
+                }),
+            )
+        })?;
+    Ok(compare::from_tree(entries))
+}
+
+// Get file metadata handler
+pub async fn get_file_info(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+) -> Result<Tagged<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let metadata = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    let metadata = metadata.ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        )
+    })?;
+    require_role(
+        &storage,
+        &user,
+        metadata.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+
+    let attachments = storage.list_file_attachments(&file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    let tags = storage.list_file_tags(&file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    let expires_at = storage.get_file_expiration(&file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    let mut response = FileResponse::from(metadata);
+    response.attachments = Some(attachments);
+    response.tags = Some(tags);
+    response.expires_at = expires_at;
+    Ok(tagged(response.version, response))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SegmentsQuery {
+    /// How many segments to split the file into, 1 to 64; 4 by default.
+    /// Small files get fewer, as segments are at least 64 KiB.
+    pub parts: Option<u32>,
+}
+
+const DEFAULT_SEGMENT_PARTS: u32 = 4;
+const MAX_SEGMENT_PARTS: u32 = 64;
+
+/// Byte ranges and their checksums for downloading a file in parallel
+/// parts, each of which can be verified on its own.
+pub async fn get_file_segments(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    Query(query): Query<SegmentsQuery>,
+) -> Result<Json<SegmentManifest>, (StatusCode, Json<ErrorResponse>)> {
+    let parts = query.parts.unwrap_or(DEFAULT_SEGMENT_PARTS);
+    if !(1..=MAX_SEGMENT_PARTS).contains(&parts) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("parts must be between 1 and {}", MAX_SEGMENT_PARTS),
+            }),
+        ));
+    }
+
+    let metadata = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(
+        &storage,
+        &user,
+        metadata.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+
+    let (segment_size, segments) =
+        storage
+            .segment_manifest(&metadata, parts)
+            .await
+            .map_err(|e| {
+                error!("Failed to checksum segments of {}: {}", file_id, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Failed to read file: {}", e),
+                    }),
+                )
+            })?;
+    Ok(Json(SegmentManifest {
+        file_id,
+        file_size: metadata.file_size,
+        sha256: metadata.sha256,
+        etag: entity_tag(metadata.version),
+        segment_size,
+        segments,
+    }))
+}
+
+/// Attachment kinds a player shows as `<track>` elements; browsers only
+/// play WebVTT, so these must be `.vtt` files.
+const TRACK_ATTACHMENT_KINDS: [&str; 3] = ["subtitles", "captions", "chapters"];
+const ATTACHMENT_KIND_POSTER: &str = "poster";
+
+pub async fn create_file_attachment(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    Json(mut payload): Json<CreateAttachmentRequest>,
+) -> Result<Json<FileAttachment>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: message }),
+        )
+    };
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_found = |message: &str| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: message.to_string(),
+            }),
+        )
+    };
+
+    ensure_writable([file_id.as_str(), payload.attachment_id.as_str()])?;
+
+    let is_track = TRACK_ATTACHMENT_KINDS.contains(&payload.kind.as_str());
+    if !is_track && payload.kind != ATTACHMENT_KIND_POSTER {
+        return Err(bad_request(format!(
+            "kind must be one of: {}, {}",
+            TRACK_ATTACHMENT_KINDS.join(", "),
+            ATTACHMENT_KIND_POSTER
+        )));
+    }
+    if payload.attachment_id == file_id {
+        return Err(bad_request("A file can't be attached to itself".to_string()));
+    }
+    payload.language = payload
+        .language
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty());
+    if let Some(language) = &payload.language {
+        if language.len() > 35 || !language.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        {
+            return Err(bad_request(
+                "language must be a BCP 47 tag such as en or pt-BR".to_string(),
+            ));
+        }
+    }
+    payload.label = payload
+        .label
+        .map(|l| l.trim().to_string())
+        .filter(|l| !l.is_empty());
+
+    let file = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| not_found("File not found"))?;
+    require_role(
+        &storage,
+        &user,
+        file.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+    let file_mime = effective_mime_type(&file);
+    if !file_mime.starts_with("video/") && !file_mime.starts_with("audio/") {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ErrorResponse {
+                error: "Attachments can only be added to video and audio files".to_string(),
+            }),
+        ));
+    }
+
+    let attachment = storage
+        .get_file_metadata(&payload.attachment_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| not_found("Attachment file not found"))?;
+    require_role(
+        &storage,
+        &user,
+        attachment.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+    let attachment_mime = effective_mime_type(&attachment);
+    if is_track && attachment_mime != "text/vtt" {
+        return Err(bad_request(format!(
+            "{} attachments must be WebVTT (.vtt) files",
+            payload.kind
+        )));
+    }
+    if payload.kind == ATTACHMENT_KIND_POSTER && !attachment_mime.starts_with("image/") {
+        return Err(bad_request("poster attachments must be images".to_string()));
+    }
+
+    let existing = storage
+        .list_file_attachments(&file_id)
+        .await
+        .map_err(db_error)?;
+    if existing
+        .iter()
+        .any(|a| a.attachment_id == payload.attachment_id && a.kind == payload.kind)
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: format!(
+                    "{} is already attached as {}",
+                    attachment.original_filename, payload.kind
+                ),
+            }),
+        ));
+    }
+
+    let created = storage
+        .create_file_attachment(&file_id, payload)
+        .await
+        .map_err(db_error)?;
+
+    info!(
+        "Attached {} to file {} as {}",
+        created.attachment_id, file_id, created.kind
+    );
+    Ok(Json(created))
+}
+
+pub async fn delete_file_attachment(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((file_id, attachment_id)): Path<(String, String)>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_file_role(&storage, &user, &file_id, Role::Write).await?;
+
+    let deleted = storage
+        .delete_file_attachment(&file_id, &attachment_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    if deleted {
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Attachment removed successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Attachment not found".to_string(),
+            }),
+        ))
+    }
+}
+
+// Get upload receipt handler
+pub async fn get_file_receipt(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+) -> Result<Json<ReceiptResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_file_role(&storage, &user, &file_id, Role::Read).await?;
+
+    let (receipt, signature_valid) = storage
+        .get_receipt(&file_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Receipt not found".to_string(),
+                }),
+            )
+        })?;
+
+    Ok(Json(ReceiptResponse {
+        receipt,
+        signature_valid,
+    }))
+}
+
+/// Analyzes an audio file on request, for files uploaded before analysis
+/// existed or that the background job hasn't reached yet.
+async fn analyze_audio_now(
+    storage: &FileStorage,
+    file_id: &str,
+) -> Result<AudioAnalysis, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let metadata = storage
+        .get_file_metadata(file_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+    if !audio::is_audio(&metadata) {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ErrorResponse {
+                error: format!("{} is not an audio file", metadata.original_filename),
+            }),
+        ));
+    }
+
+    let analysis = audio::analyze(storage, &metadata).await.map_err(|e| {
+        error!("Failed to analyze audio file {}: {}", file_id, e);
+        (
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })?;
+    storage
+        .save_audio_analysis(&analysis)
+        .await
+        .map_err(db_error)?;
+    Ok(analysis)
+}
+
+pub async fn get_audio_metadata(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+) -> Result<Json<AudioMetadata>, (StatusCode, Json<ErrorResponse>)> {
+    require_file_role(&storage, &user, &file_id, Role::Read).await?;
+
+    let stored = storage.get_audio_metadata(&file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    match stored {
+        Some(metadata) => Ok(Json(metadata)),
+        None => Ok(Json(analyze_audio_now(&storage, &file_id).await?.metadata)),
+    }
+}
+
+pub async fn get_waveform(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+) -> Result<Json<WaveformResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_file_role(&storage, &user, &file_id, Role::Read).await?;
+
+    let stored = storage.get_audio_peaks(&file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    let (duration_secs, peaks) = match stored {
+        Some(stored) => stored,
+        None => {
+            let analysis = analyze_audio_now(&storage, &file_id).await?;
+            (analysis.metadata.duration_secs, analysis.peaks)
+        }
+    };
+    Ok(Json(WaveformResponse {
+        file_id,
+        duration_secs,
+        peaks,
+    }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ThumbnailQuery {
+    /// `small` (128px), `medium` (256px, the default), `large` (512px) or
+    /// `preview` (1280px).
+    pub size: Option<String>,
+}
+
+fn thumbnail_sizes(
+    names: impl IntoIterator<Item = impl AsRef<str>>,
+) -> Result<Vec<ThumbnailSize>, (StatusCode, Json<ErrorResponse>)> {
+    names
+        .into_iter()
+        .map(|name| {
+            ThumbnailSize::from_name(&name.as_ref().to_ascii_lowercase()).ok_or_else(|| {
+                let names: Vec<&str> = THUMBNAIL_SIZES.iter().map(|s| s.name).collect();
+                (
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: format!("size must be one of: {}", names.join(", ")),
+                    }),
+                )
+            })
+        })
+        .collect()
+}
+
+/// A JPEG thumbnail of an image, rendered on first request unless a
+/// thumbnail policy had it rendered on upload.
+pub async fn get_file_thumbnail(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    Query(query): Query<ThumbnailQuery>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let thumbnailer = storage.thumbnailer().cloned().ok_or_else(|| {
+        (
+            StatusCode::NOT_IMPLEMENTED,
+            Json(ErrorResponse {
+                error: "Thumbnails are not enabled on this server".to_string(),
+            }),
+        )
+    })?;
+    let size = thumbnail_sizes([query.size.as_deref().unwrap_or("medium")])?[0];
+    let access = require_file_role(&storage, &user, &file_id, Role::Read).await?;
+
+    let metadata = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+    if !Thumbnailer::is_thumbnailable(&metadata) {
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Json(ErrorResponse {
+                error: format!("{} is not an image", metadata.original_filename),
+            }),
+        ));
+    }
+
+    // Thumbnails behind an access list are only kept by the viewer's browser.
+    let restricted = access.is_restricted(metadata.parent_directory_id.as_deref());
+    let max_age = storage.cache_max_age_secs();
+    let caching = if restricted {
+        Caching::Private { max_age }
+    } else {
+        Caching::Public { max_age }
+    };
+    let mut cache_headers = HeaderMap::new();
+    cache_headers.insert(
+        header::ETAG,
+        HeaderValue::from_str(&entity_tag(metadata.version)).unwrap(),
+    );
+    cache_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_str(&caching.header_value()).unwrap(),
+    );
+    if restricted {
+        cache_headers.insert(header::VARY, HeaderValue::from_static("authorization"));
+    }
+    if if_none_match_matches(&headers, metadata.version) {
+        return Ok((StatusCode::NOT_MODIFIED, cache_headers).into_response());
+    }
+
+    let internal_error = |e: String| {
+        error!("Failed to render thumbnail of {}: {}", metadata.id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to render thumbnail: {}", e),
+            }),
+        )
+    };
+    let path = thumbnailer
+        .generate(&storage, &metadata, size)
+        .await
+        .map_err(|e| internal_error(e.to_string()))?;
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|e| internal_error(e.to_string()))?;
+    Ok((
+        [(header::CONTENT_TYPE, "image/jpeg")],
+        cache_headers,
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+// Delete file handler
+pub async fn delete_file(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([file_id.as_str()])?;
+    require_file_role(&storage, &user, &file_id, Role::Write).await?;
+
+    let deleted = storage.delete_file(&file_id).await.map_err(|e| {
+        error!("Failed to delete file: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to delete file: {}", e),
+            }),
+        )
+    })?;
+
+    if deleted {
+        info!("File deleted: {}", file_id);
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "File deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        ))
+    }
+}
+
+/// Looks up a file for its version endpoints and checks the caller holds
+/// `role` in its directory.
+async fn versioned_file(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    file_id: &str,
+    role: Role,
+) -> Result<FileMetadata, (StatusCode, Json<ErrorResponse>)> {
+    let metadata = storage
+        .get_file_metadata(file_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(storage, user, metadata.parent_directory_id.as_deref(), role).await?;
+    Ok(metadata)
+}
+
+/// Looks up an earlier version of a file.
+async fn find_file_version(
+    storage: &FileStorage,
+    file_id: &str,
+    version_id: &str,
+) -> Result<FileVersion, (StatusCode, Json<ErrorResponse>)> {
+    storage
+        .get_file_version(file_id, version_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Version not found".to_string(),
+                }),
+            )
+        })
+}
+
+/// Uploads new content for an existing file. What it replaces is kept as an
+/// earlier version, up to `FILE_VERSION_RETENTION` of them.
+pub async fn upload_file_version(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    mut multipart: Multipart,
+) -> Result<Tagged<UploadResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([file_id.as_str()])?;
+    let current = versioned_file(&storage, &user, &file_id, Role::Write).await?;
+    check_if_match(&headers, current.version)?;
+    if !storage.is_uploaded(&current) {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "Files registered in place from disk can't have versions".to_string(),
+            }),
+        ));
+    }
+
+    let mut mime_type: Option<String> = None;
+    let mut upload_info: Option<SavedUpload> = None;
+    let mut expected = match headers.get(checksum::HEADER) {
+        Some(sha256) => ExpectedChecksum::Given(sha256.to_str().unwrap_or_default().to_string()),
+        None => ExpectedChecksum::None,
+    };
+
+    while let Some(mut field) = multipart.next_field().await.map_err(|e| {
+        error!("Failed to read multipart field: {}", e);
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Failed to read multipart data: {}", e),
+            }),
+        )
+    })? {
+        match field.name().unwrap_or("") {
+            "file" => {
+                mime_type = field.content_type().map(|s| s.to_string());
+                let saved = storage
+                    .save_file(&current.original_filename, &mut field, &expected)
+                    .await
+                    .map_err(|e| {
+                        error!("Failed to store upload: {}", e);
+                        let status = match e {
+                            UploadError::Body(_) | UploadError::InvalidChunk(_) => {
+                                StatusCode::BAD_REQUEST
+                            }
+                            UploadError::QuotaExceeded(_) | UploadError::StorageFull(_) => {
+                                StatusCode::INSUFFICIENT_STORAGE
+                            }
+                            UploadError::ChecksumMismatch { .. } => {
+                                StatusCode::UNPROCESSABLE_ENTITY
+                            }
+                            UploadError::Io(_) | UploadError::Database(_) => {
+                                StatusCode::INTERNAL_SERVER_ERROR
+                            }
+                        };
+                        (
+                            status,
+                            Json(ErrorResponse {
+                                error: e.to_string(),
+                            }),
+                        )
+                    })?;
+                upload_info = Some(saved);
+            }
+            "checksum" => {
+                let text = field.text().await.map_err(|e| {
+                    error!("Failed to read checksum: {}", e);
+                    (
+                        StatusCode::BAD_REQUEST,
+                        Json(ErrorResponse {
+                            error: format!("Failed to read checksum: {}", e),
+                        }),
+                    )
+                })?;
+                if text.trim().is_empty() {
+                    continue;
+                }
+                expected = ExpectedChecksum::Given(text);
+                if let Some(saved) = &upload_info {
+                    if !expected.matches(&saved.sha256) {
+                        let _ = tokio::fs::remove_file(&saved.file_path).await;
+                        let e = UploadError::ChecksumMismatch {
+                            expected: expected.value().unwrap_or_default(),
+                            actual: saved.sha256.clone(),
+                        };
+                        return Err((
+                            StatusCode::UNPROCESSABLE_ENTITY,
+                            Json(ErrorResponse {
+                                error: e.to_string(),
+                            }),
+                        ));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let saved = upload_info.ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "No file provided".to_string(),
+            }),
+        )
+    })?;
+    let sha256 = saved.sha256.clone();
+
+    let metadata = storage
+        .record_file_version(&current, saved, mime_type)
+        .await
+        .map_err(|e| {
+            error!("Failed to save file version: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to save file: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(version_conflict)?;
+
+    let receipt = storage
+        .issue_receipt(&metadata, &sha256)
+        .await
+        .map_err(|e| {
+            error!("Failed to issue upload receipt: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to issue upload receipt: {}", e),
+                }),
+            )
+        })?;
+
+    info!("New version of file {} uploaded", metadata.id);
+    Ok(tagged(
+        metadata.version,
+        UploadResponse {
+            success: true,
+            file: metadata.into(),
+            receipt,
+            message: "New version uploaded successfully".to_string(),
+        },
+    ))
+}
+
+/// Earlier versions of a file, newest first.
+pub async fn list_file_versions(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+) -> Result<Json<Vec<FileVersion>>, (StatusCode, Json<ErrorResponse>)> {
+    versioned_file(&storage, &user, &file_id, Role::Read).await?;
+
+    let versions = storage.list_file_versions(&file_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    Ok(Json(versions))
+}
+
+pub async fn download_file_version(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((file_id, version_id)): Path<(String, String)>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let file = versioned_file(&storage, &user, &file_id, Role::Read).await?;
+    let version = find_file_version(&storage, &file_id, &version_id).await?;
+    let content = version.as_content(&file);
+
+    let stream = storage.open_content(&content).await.map_err(|e| {
+        error!("Failed to open file: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to open file: {}", e),
+            }),
+        )
+    })?;
+
+    let mut response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, effective_mime_type(&content))
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("attachment", &content.original_filename),
+        )
+        .header(header::CONTENT_LENGTH, content.file_size.max(0));
+    if let Some(sha256) = &content.sha256 {
+        response = response.header(checksum::HEADER, sha256);
+    }
+    finish_response(response, Body::from_stream(stream))
+}
+
+/// Makes an earlier version the file's content again. The content it
+/// replaces becomes a version in its place.
+pub async fn restore_file_version(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((file_id, version_id)): Path<(String, String)>,
+    headers: HeaderMap,
+) -> Result<Tagged<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([file_id.as_str()])?;
+    let current = versioned_file(&storage, &user, &file_id, Role::Write).await?;
+    check_if_match(&headers, current.version)?;
+    let version = find_file_version(&storage, &file_id, &version_id).await?;
+
+    let metadata = storage
+        .restore_file_version(&current, &version)
+        .await
+        .map_err(|e| {
+            error!("Failed to restore file version: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to restore version: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(version_conflict)?;
+
+    info!("File {} restored to version {}", file_id, version_id);
+    Ok(tagged(metadata.version, metadata.into()))
+}
+
+// Health check handler
+pub async fn health_check() -> impl IntoResponse {
+    Json(serde_json::json!({
+        "status": "healthy",
+        "service": "file-transfer-api"
+    }))
+}
+
+pub async fn get_capabilities(State(storage): State<FileStorage>) -> Json<CapabilitiesResponse> {
+    Json(CapabilitiesResponse {
+        features: storage.capabilities().clone(),
+        max_upload_size: None,
+    })
+}
+
+fn account_error(
+    status: StatusCode,
+    error: impl Into<String>,
+) -> (StatusCode, Json<ErrorResponse>) {
+    (
+        status,
+        Json(ErrorResponse {
+            error: error.into(),
+        }),
+    )
+}
+
+// Accounts: sign up with a username and password
+pub async fn register(
+    State(storage): State<FileStorage>,
+    Json(payload): Json<Credentials>,
+) -> Result<Json<UserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if !storage.registration_enabled() {
+        return Err(account_error(StatusCode::FORBIDDEN, "Registration is disabled"));
+    }
+    if !auth::valid_username(&payload.username) {
+        return Err(account_error(
+            StatusCode::BAD_REQUEST,
+            "Usernames must be 3 to 64 letters, digits, dots, dashes or underscores",
+        ));
+    }
+    let password_len = payload.password.chars().count();
+    if !(auth::MIN_PASSWORD_LEN..=auth::MAX_PASSWORD_LEN).contains(&password_len) {
+        return Err(account_error(
+            StatusCode::BAD_REQUEST,
+            format!(
+                "Passwords must be {} to {} characters",
+                auth::MIN_PASSWORD_LEN,
+                auth::MAX_PASSWORD_LEN
+            ),
+        ));
+    }
+
+    let password_hash = auth::hash_password(payload.password).await.map_err(|e| {
+        error!("Failed to hash password: {}", e);
+        account_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to create account")
+    })?;
+    let user = storage
+        .create_user(&payload.username, password_hash)
+        .await
+        .map_err(|e| {
+            error!("Failed to create user: {}", e);
+            account_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to create account: {}", e),
+            )
+        })?
+        .ok_or_else(|| account_error(StatusCode::CONFLICT, "Username is already taken"))?;
+    if let Err(e) = storage.create_home_directory(&user).await {
+        warn!("Failed to create home directory for {}: {}", user.username, e);
+    }
+
+    Ok(Json(user_response(&storage, user)))
+}
+
+// Accounts: exchange a username and password for a token
+pub async fn login(
+    State(storage): State<FileStorage>,
+    Json(payload): Json<Credentials>,
+) -> Result<Json<TokenResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let invalid = || account_error(StatusCode::UNAUTHORIZED, "Invalid username or password");
+
+    let user = storage
+        .get_user_by_username(&payload.username)
+        .await
+        .map_err(|e| {
+            error!("Failed to look up user: {}", e);
+            account_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to log in: {}", e),
+            )
+        })?
+        .ok_or_else(invalid)?;
+    if !auth::verify_password(payload.password, user.password_hash.clone()).await {
+        return Err(invalid());
+    }
+
+    let (token, expires_at) = storage.tokens().issue(&user).map_err(|e| {
+        error!("Failed to issue token: {}", e);
+        account_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to log in")
+    })?;
+    info!("User {} logged in", user.id);
+    Ok(Json(TokenResponse {
+        token,
+        token_type: "Bearer".to_string(),
+        expires_at: expires_at.to_rfc3339(),
+        user: user_response(&storage, user),
+    }))
+}
+
+// Accounts: who the request's token belongs to
+pub async fn current_user(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+) -> Result<Json<UserResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let not_signed_in = || account_error(StatusCode::UNAUTHORIZED, "Not logged in");
+    let Some(Extension(user)) = user else {
+        return Err(not_signed_in());
+    };
+
+    let user = storage
+        .get_user(&user.id)
+        .await
+        .map_err(|e| {
+            error!("Failed to look up user: {}", e);
+            account_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to look up user: {}", e),
+            )
+        })?
+        .ok_or_else(not_signed_in)?;
+    Ok(Json(user_response(&storage, user)))
+}
+
+fn user_response(storage: &FileStorage, user: User) -> UserResponse {
+    let is_admin = storage.tokens().is_admin(&user.username);
+    UserResponse::new(user, is_admin)
+}
+
+fn oidc_error(e: OidcError) -> (StatusCode, Json<ErrorResponse>) {
+    let status = match e {
+        OidcError::UnknownLogin => StatusCode::BAD_REQUEST,
+        OidcError::TooManyLogins => StatusCode::SERVICE_UNAVAILABLE,
+        OidcError::Provider(_) => StatusCode::BAD_GATEWAY,
+        OidcError::InvalidToken(_) => StatusCode::UNAUTHORIZED,
+    };
+    if status != StatusCode::BAD_REQUEST {
+        error!("OIDC login failed: {}", e);
+    }
+    account_error(status, e.to_string())
+}
+
+fn oidc_not_configured() -> (StatusCode, Json<ErrorResponse>) {
+    account_error(StatusCode::NOT_FOUND, "Single sign-on is not configured")
+}
+
+// Accounts: send the browser to the OIDC provider to log in
+pub async fn oidc_login(
+    State(storage): State<FileStorage>,
+    headers: HeaderMap,
+) -> Result<Redirect, (StatusCode, Json<ErrorResponse>)> {
+    let oidc = storage.oidc().ok_or_else(oidc_not_configured)?;
+    let redirect_uri = match oidc.redirect_url() {
+        Some(url) => url.to_string(),
+        None => format!(
+            "{}/auth/oidc/callback",
+            external_base_url(&storage, &headers)
+        ),
+    };
+    let url = oidc.start_login(redirect_uri).await.map_err(oidc_error)?;
+    Ok(Redirect::to(&url))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct OidcCallbackQuery {
+    pub code: Option<String>,
+    pub state: Option<String>,
+    /// Set by the provider instead of `code` when the login failed.
+    pub error: Option<String>,
+    pub error_description: Option<String>,
+}
+
+// Accounts: the OIDC provider sends the browser back here after login
+pub async fn oidc_callback(
+    State(storage): State<FileStorage>,
+    Query(query): Query<OidcCallbackQuery>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let oidc = storage.oidc().ok_or_else(oidc_not_configured)?;
+    if let Some(error) = query.error {
+        let message = match query.error_description {
+            Some(description) => format!("Login failed: {} ({})", description, error),
+            None => format!("Login failed: {}", error),
+        };
+        return Err(account_error(StatusCode::UNAUTHORIZED, message));
+    }
+    let (Some(code), Some(state)) = (query.code, query.state) else {
+        return Err(account_error(
+            StatusCode::BAD_REQUEST,
+            "code and state are required",
+        ));
+    };
+
+    let identity = oidc.finish_login(&code, &state).await.map_err(oidc_error)?;
+    let user = storage
+        .user_for_oidc_identity(
+            &identity.issuer,
+            &identity.subject,
+            identity.name_hint.as_deref(),
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to map OIDC identity to a user: {}", e);
+            account_error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("Failed to log in: {}", e),
+            )
+        })?;
+
+    let (token, expires_at) = storage.tokens().issue(&user).map_err(|e| {
+        error!("Failed to issue token: {}", e);
+        account_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to log in")
+    })?;
+    info!("User {} logged in through {}", user.id, identity.issuer);
+    if let Some(url) = oidc.post_login_url(&token, expires_at) {
+        return Ok(Redirect::to(&url).into_response());
+    }
+    Ok(Json(TokenResponse {
+        token,
+        token_type: "Bearer".to_string(),
+        expires_at: expires_at.to_rfc3339(),
+        user: user_response(&storage, user),
+    })
+    .into_response())
+}
+
+pub async fn get_branding(State(storage): State<FileStorage>) -> Json<Branding> {
+    Json(storage.branding().clone())
+}
+
+pub async fn get_version(
+    State(storage): State<FileStorage>,
+) -> Result<Json<VersionResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let schema_version = storage.schema_version().await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(VersionResponse {
+        version: build_info::VERSION.to_string(),
+        git_commit: build_info::GIT_COMMIT.to_string(),
+        build_date: build_info::BUILD_DATE.to_string(),
+        database_backend: storage.database_backend().name().to_string(),
+        storage_backend: build_info::STORAGE_BACKEND.to_string(),
+        features: storage.features().iter().map(|f| f.to_string()).collect(),
+        schema_version,
+    }))
+}
+
+// Create directory handler
+pub async fn create_directory(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(mut payload): Json<CreateDirectoryRequest>,
+) -> Result<Json<CreateDirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    payload.name = storage.name_policy().normalize(&payload.name);
+    ensure_writable(payload.parent_id.as_deref())?;
+    require_role(&storage, &user, payload.parent_id.as_deref(), Role::Write).await?;
+    storage.name_policy().validate(&payload.name).map_err(|e| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!("Invalid directory name: {}", e),
+            }),
+        )
+    })?;
+
+    let directory = storage
+        .create_directory(&payload.name, payload.parent_id)
+        .await
+        .map_err(write_error(
+            "Failed to create directory",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+
+    let (file_count, total_size) = storage.get_directory_stats(&directory.id).await.map_err(|e| {
+        error!("Failed to get directory stats: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to get directory stats: {}", e),
+            }),
+        )
+    })?;
+
+    info!("Directory created: {}", directory.id);
+
+    Ok(Json(CreateDirectoryResponse {
+        success: true,
+        directory: DirectoryResponse {
+            id: directory.id,
+            name: directory.name,
+            parent_id: directory.parent_id,
+            created_at: directory.created_at,
+            updated_at: directory.updated_at,
+            color: directory.color,
+            icon: directory.icon,
+            version: directory.version,
+            file_count,
+            total_size,
+            readme: None,
+        },
+        message: "Directory created successfully".to_string(),
+    }))
+}
+
+// Get directory info handler
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct DirectoryInfoQuery {
+    /// Include the directory's `README.md` as `html` (sanitized) or raw `markdown`.
+    pub readme: Option<String>,
+}
+
+pub async fn get_directory_info(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    Query(query): Query<DirectoryInfoQuery>,
+) -> Result<Tagged<DirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let readme_format = match query.readme.as_deref() {
+        None => None,
+        Some(name) => Some(ReadmeFormat::from_name(name).ok_or_else(|| {
+            (
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!("readme must be one of: {}", ReadmeFormat::NAMES.join(", ")),
+                }),
+            )
+        })?),
+    };
+
+    let directory = storage
+        .get_directory(&dir_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Directory not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(&storage, &user, Some(&dir_id), Role::Read).await?;
+
+    let (file_count, total_size) = storage.get_directory_stats(&dir_id).await.map_err(|e| {
+        error!("Failed to get directory stats: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to get directory stats: {}", e),
+            }),
+        )
+    })?;
+
+    let readme = match readme_format {
+        None => None,
+        Some(format) => readme::load(&storage, &dir_id, format).await.map_err(|e| {
+            error!("Failed to read README of directory {}: {}", dir_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to read README: {}", e),
+                }),
+            )
+        })?,
+    };
+
+    Ok(tagged(
+        directory.version,
+        DirectoryResponse {
+            id: directory.id,
+            name: directory.name,
+            parent_id: directory.parent_id,
+            created_at: directory.created_at,
+            updated_at: directory.updated_at,
+            color: directory.color,
+            icon: directory.icon,
+            version: directory.version,
+            file_count,
+            total_size,
+            readme,
+        },
+    ))
+}
+
+// Download a directory and everything beneath it as a ZIP archive
+pub async fn download_directory(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    if mirror::is_mirror_id(&dir_id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Mirrored directories can't be downloaded as an archive".to_string(),
+            }),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let directory = storage
+        .get_directory(&dir_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Directory not found".to_string(),
+                }),
+            )
+        })?;
+    let access = require_role(&storage, &user, Some(&dir_id), Role::Read).await?;
+    let entries = storage
+        .archive_entries(&dir_id, &access)
+        .await
+        .map_err(db_error)?;
+
+    info!(
+        "Streaming directory {} as a ZIP archive of {} entries",
+        dir_id,
+        entries.len()
+    );
+    finish_response(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/zip")
+            .header(
+                header::CONTENT_DISPOSITION,
+                content_disposition("attachment", &format!("{}.zip", directory.name)),
+            ),
+        Body::from_stream(zip::archive(storage.clone(), entries)),
+    )
+}
+
+fn archive_job_response(
+    storage: &FileStorage,
+    headers: &HeaderMap,
+    job: ArchiveJob,
+) -> ArchiveJobResponse {
+    let download_url = (job.status == ARCHIVE_READY)
+        .then(|| format!("{}/z/{}", external_base_url(storage, headers), job.ticket));
+    ArchiveJobResponse {
+        id: job.id,
+        directory_id: job.directory_id,
+        status: job.status,
+        file_name: job.file_name,
+        file_size: job.file_size,
+        error: job.error,
+        download_url,
+        created_at: job.created_at,
+        finished_at: job.finished_at,
+        expires_at: job.expires_at,
+    }
+}
+
+/// Looks up an archive job, which only the user who started it can see.
+async fn find_archive_job(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    job_id: &str,
+) -> Result<ArchiveJob, (StatusCode, Json<ErrorResponse>)> {
+    let user_id = user.as_ref().map(|Extension(user)| user.id.as_str());
+    storage
+        .get_archive_job(job_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .filter(|job| job.user_id.is_none() || job.user_id.as_deref() == user_id)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Archive job not found".to_string(),
+                }),
+            )
+        })
+}
+
+// Start building a directory's ZIP archive in the background, for
+// directories too large to stream in one response
+pub async fn create_archive_job(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    headers: HeaderMap,
+    Path(dir_id): Path<String>,
+) -> Result<(StatusCode, Json<ArchiveJobResponse>), (StatusCode, Json<ErrorResponse>)> {
+    if mirror::is_mirror_id(&dir_id) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "Mirrored directories can't be downloaded as an archive".to_string(),
+            }),
+        ));
+    }
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let directory = storage
+        .get_directory(&dir_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Directory not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(&storage, &user, Some(&dir_id), Role::Read).await?;
+
+    let user_id = user.map(|Extension(user)| user.id);
+    let job = storage
+        .create_archive_job(&directory, user_id)
+        .await
+        .map_err(db_error)?;
+    info!("Queued archive of directory {} (job {})", dir_id, job.id);
+    archives::start(storage.clone(), job.clone());
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(archive_job_response(&storage, &headers, job)),
+    ))
+}
+
+pub async fn get_archive_job(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    headers: HeaderMap,
+    Path(job_id): Path<String>,
+) -> Result<Json<ArchiveJobResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let job = find_archive_job(&storage, &user, &job_id).await?;
+    Ok(Json(archive_job_response(&storage, &headers, job)))
+}
+
+pub async fn delete_archive_job(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(job_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    find_archive_job(&storage, &user, &job_id).await?;
+    storage.delete_archive_job(&job_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    Ok(Json(DeleteResponse {
+        success: true,
+        message: "Archive deleted".to_string(),
+    }))
+}
+
+// Download a finished directory archive through its ticket, which works
+// without signing in until the archive expires. Ranges let an interrupted
+// download resume.
+pub async fn download_archive(
+    State(storage): State<FileStorage>,
+    Path(ticket): Path<String>,
+    headers: HeaderMap,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let job = storage
+        .get_archive_job_by_ticket(&ticket)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .filter(|job| {
+            job.status == ARCHIVE_READY
+                && DateTime::parse_from_rfc3339(&job.expires_at).is_ok_and(|t| t > Utc::now())
+        })
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Archive not found or expired".to_string(),
+                }),
+            )
+        })?;
+
+    let size = job.file_size.unwrap_or(0).max(0) as u64;
+    let range = headers
+        .get(header::RANGE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| parse_range(value, size))
+        .unwrap_or(RangeRequest::Full);
+    let (start, length) = match range {
+        RangeRequest::Full => (0, None),
+        RangeRequest::Partial { start, end } => (start, Some(end - start + 1)),
+        RangeRequest::Unsatisfiable => {
+            return Ok((
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                [(header::CONTENT_RANGE, format!("bytes */{}", size))],
+                Json(ErrorResponse {
+                    error: format!("Range is outside the archive's {} bytes", size),
+                }),
+            )
+                .into_response());
+        }
+    };
+    let stream = storage
+        .open_archive(&job.id, start, length)
+        .await
+        .map_err(|e| {
+            error!("Failed to open archive {}: {}", job.id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to open archive: {}", e),
+                }),
+            )
+        })?;
+
+    let response = Response::builder()
+        .header(header::CONTENT_TYPE, "application/zip")
+        .header(
+            header::CONTENT_DISPOSITION,
+            content_disposition("attachment", &job.file_name),
+        )
+        .header(header::ACCEPT_RANGES, "bytes");
+    let response = match range {
+        RangeRequest::Partial { start, end } => response
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", start, end, size),
+            )
+            .header(header::CONTENT_LENGTH, end - start + 1),
+        _ => response
+            .status(StatusCode::OK)
+            .header(header::CONTENT_LENGTH, size),
+    };
+    finish_response(response, Body::from_stream(stream))
+}
+
+// Delete directory handler
+pub async fn delete_directory(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([dir_id.as_str()])?;
+    load_access(&storage, &user)
+        .await?
+        .check_subtree(&dir_id, Role::Owner)
+        .map_err(acl_error)?;
+
+    let deleted = storage.delete_directory(&dir_id).await.map_err(|e| {
+        error!("Failed to delete directory: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to delete directory: {}", e),
+            }),
+        )
+    })?;
+
+    if deleted {
+        info!("Directory deleted: {}", dir_id);
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Directory deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ))
+    }
+}
+
+/// Whether `access` may see and restore a trashed item: the same role it
+/// took to delete it.
+fn may_restore(access: &Access, item: &TrashItem) -> Result<(), AclError> {
+    if item.kind == "directory" {
+        access.check_subtree(&item.id, Role::Owner)
+    } else {
+        access.check(item.parent_id.as_deref(), Role::Write)
+    }
+}
+
+// List deleted files and directories that can still be restored
+pub async fn list_trash(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+) -> Result<Json<Vec<TrashItem>>, (StatusCode, Json<ErrorResponse>)> {
+    let access = load_access(&storage, &user).await?;
+    let mut items = storage.list_trash().await.map_err(|e| {
+        error!("Failed to list trash: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to list trash: {}", e),
+            }),
+        )
+    })?;
+    items.retain(|item| may_restore(&access, item).is_ok());
+    Ok(Json(items))
+}
+
+// Restore a file or directory from the trash to where it was deleted from
+pub async fn restore_from_trash(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(id): Path<String>,
+) -> Result<Json<TrashItem>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_in_trash = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Item not found in the trash".to_string(),
+            }),
+        )
+    };
+
+    let item = storage
+        .get_trash_item(&id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(not_in_trash)?;
+    may_restore(&load_access(&storage, &user).await?, &item).map_err(acl_error)?;
+
+    if let Some(parent_id) = &item.parent_id {
+        if storage
+            .get_directory(parent_id)
+            .await
+            .map_err(db_error)?
+            .is_none()
+        {
+            return Err((
+                StatusCode::CONFLICT,
+                Json(ErrorResponse {
+                    error: "The directory it was deleted from is in the trash; restore that first"
+                        .to_string(),
+                }),
+            ));
+        }
+    }
+
+    if !storage
+        .restore_from_trash(&item)
+        .await
+        .map_err(write_error(
+            "Failed to restore from trash",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?
+    {
+        return Err(not_in_trash());
+    }
+    Ok(Json(item))
+}
+
+// Move file handler
+pub async fn move_file(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<MoveFileRequest>,
+) -> Result<Tagged<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([file_id.as_str()].into_iter().chain(payload.parent_directory_id.as_deref()))?;
+
+    let current = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+    let access = require_role(
+        &storage,
+        &user,
+        current.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+    access
+        .check(payload.parent_directory_id.as_deref(), Role::Write)
+        .map_err(acl_error)?;
+    if let Some(parent_id) = payload.parent_directory_id.as_deref() {
+        let parent = storage.get_directory(parent_id).await.map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+        if parent.is_none() {
+            return Err((
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Target directory not found".to_string(),
+                }),
+            ));
+        }
+    }
+    check_if_match(&headers, current.version)?;
+
+    let metadata = storage
+        .move_file(&file_id, payload.parent_directory_id, current.version)
+        .await
+        .map_err(write_error(
+            "Failed to move file",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+
+    let metadata = metadata.ok_or_else(version_conflict)?;
+
+    info!("File moved: {}", file_id);
+    Ok(tagged(metadata.version, metadata.into()))
+}
+
+// Update file handler: changes a file's description, tags and expiry, and
+// moves it, in one call
+pub async fn update_file(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    headers: HeaderMap,
+    Json(mut payload): Json<UpdateFileRequest>,
+) -> Result<Tagged<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+    ensure_writable(
+        [file_id.as_str()].into_iter().chain(
+            payload
+                .parent_directory_id
+                .as_ref()
+                .and_then(|p| p.as_deref()),
+        ),
+    )?;
+
+    if payload.parent_directory_id.is_none()
+        && payload.description.is_none()
+        && payload.tags.is_none()
+        && payload.expires_at.is_none()
+    {
+        return Err(bad_request(
+            "Nothing to update; set parent_directory_id, description, tags or expires_at"
+                .to_string(),
+        ));
+    }
+    if let Some(tags) = &mut payload.tags {
+        *tags = normalize_tags(tags).map_err(bad_request)?;
+    }
+    if let Some(Some(expires_at)) = &mut payload.expires_at {
+        *expires_at = future_time(expires_at, "Expiry")?;
+    }
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let mut metadata = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+    let access = require_role(
+        &storage,
+        &user,
+        metadata.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+    if let Some(parent_id) = &payload.parent_directory_id {
+        access
+            .check(parent_id.as_deref(), Role::Write)
+            .map_err(acl_error)?;
+        if let Some(parent_id) = parent_id.as_deref() {
+            if storage
+                .get_directory(parent_id)
+                .await
+                .map_err(db_error)?
+                .is_none()
+            {
+                return Err((
+                    StatusCode::NOT_FOUND,
+                    Json(ErrorResponse {
+                        error: "Target directory not found".to_string(),
+                    }),
+                ));
+            }
+        }
+    }
+    check_if_match(&headers, metadata.version)?;
+
+    if let Some(parent_id) = payload.parent_directory_id.take() {
+        metadata = storage
+            .move_file(&file_id, parent_id, metadata.version)
+            .await
+            .map_err(write_error(
+                "Failed to move file",
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ))?
+            .ok_or_else(version_conflict)?;
+    }
+    if payload.description.is_some() || payload.tags.is_some() || payload.expires_at.is_some() {
+        metadata = storage
+            .update_file_metadata(&file_id, &payload, metadata.version)
+            .await
+            .map_err(db_error)?
+            .ok_or_else(version_conflict)?;
+    }
+
+    let tags = storage.list_file_tags(&file_id).await.map_err(db_error)?;
+    let expires_at = storage
+        .get_file_expiration(&file_id)
+        .await
+        .map_err(db_error)?;
+    let mut response = FileResponse::from(metadata);
+    response.tags = Some(tags);
+    response.expires_at = expires_at;
+    info!("File updated: {}", file_id);
+    Ok(tagged(response.version, response))
+}
+
+/// Color labels a directory can carry; clients map each to their palette.
+const DIRECTORY_COLORS: [&str; 9] = [
+    "red", "orange", "yellow", "green", "teal", "blue", "purple", "pink", "gray",
+];
+/// Icons a directory can show in place of the plain folder.
+const DIRECTORY_ICONS: [&str; 16] = [
+    "folder", "star", "heart", "home", "work", "document", "photo", "music", "video", "code",
+    "archive", "download", "cloud", "lock", "book", "game",
+];
+
+// Move directory handler
+pub async fn move_directory(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<MoveDirectoryRequest>,
+) -> Result<Tagged<DirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable(
+        [dir_id.as_str()]
+            .into_iter()
+            .chain(payload.parent_id.as_ref().and_then(|p| p.as_deref())),
+    )?;
+
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let not_found = || {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        )
+    };
+
+    for (field, value, allowed) in [
+        ("color", &payload.color, &DIRECTORY_COLORS[..]),
+        ("icon", &payload.icon, &DIRECTORY_ICONS[..]),
+    ] {
+        if let Some(Some(value)) = value {
+            if !allowed.contains(&value.as_str()) {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    Json(ErrorResponse {
+                        error: format!("{} must be one of: {}", field, allowed.join(", ")),
+                    }),
+                ));
+            }
+        }
+    }
+
+    let mut directory = storage
+        .get_directory(&dir_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(not_found)?;
+    // Moving a directory can take it out from under its access list, so it
+    // takes ownership; changing its appearance only takes write access.
+    let access = load_access(&storage, &user).await?;
+    match &payload.parent_id {
+        Some(parent_id) => {
+            access
+                .check_subtree(&dir_id, Role::Owner)
+                .map_err(acl_error)?;
+            access
+                .check(parent_id.as_deref(), Role::Write)
+                .map_err(acl_error)?;
+        }
+        None => access
+            .check(Some(&dir_id), Role::Write)
+            .map_err(acl_error)?,
+    }
+    if let Some(parent_id) = &payload.parent_id {
+        check_move_target(&storage, parent_id.as_deref()).await?;
+    }
+    check_if_match(&headers, directory.version)?;
+
+    if let Some(parent_id) = payload.parent_id {
+        directory = relocate_directory(&storage, &directory, parent_id).await?;
+    }
+
+    if payload.color.is_some() || payload.icon.is_some() {
+        let color = payload.color.unwrap_or(directory.color);
+        let icon = payload.icon.unwrap_or(directory.icon);
+        directory = storage
+            .set_directory_appearance(
+                &dir_id,
+                color.as_deref(),
+                icon.as_deref(),
+                directory.version,
+            )
+            .await
+            .map_err(db_error)?
+            .ok_or_else(version_conflict)?;
+    }
+
+    let (file_count, total_size) = storage
+        .get_directory_stats(&dir_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to get directory stats: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to get directory stats: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(tagged(
+        directory.version,
+        DirectoryResponse {
+            id: directory.id,
+            name: directory.name,
+            parent_id: directory.parent_id,
+            created_at: directory.created_at,
+            updated_at: directory.updated_at,
+            color: directory.color,
+            icon: directory.icon,
+            version: directory.version,
+            file_count,
+            total_size,
+            readme: None,
+        },
+    ))
+}
+
+/// Refuses moving into a directory that doesn't exist or is in the trash.
+async fn check_move_target(
+    storage: &FileStorage,
+    parent_id: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(parent_id) = parent_id else {
+        return Ok(());
+    };
+    let parent = storage.get_directory(parent_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if parent.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Target directory not found".to_string(),
+            }),
+        ));
+    }
+    Ok(())
+}
+
+/// Moves a directory that is at `directory.version` into `parent_id`.
+/// Moves next to a sibling with its name, or into the directory itself or
+/// beneath it, are refused by storage.
+async fn relocate_directory(
+    storage: &FileStorage,
+    directory: &Directory,
+    parent_id: Option<String>,
+) -> Result<Directory, (StatusCode, Json<ErrorResponse>)> {
+    let moved = storage
+        .move_directory(&directory.id, parent_id, directory.version)
+        .await
+        .map_err(write_error(
+            "Failed to move directory",
+            StatusCode::BAD_REQUEST,
+        ))?
+        .ok_or_else(version_conflict)?;
+    info!("Directory moved: {}", directory.id);
+    Ok(moved)
+}
+
+// Move directory handler: moves a directory and everything beneath it into
+// another directory
+pub async fn move_directory_to(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<MoveDirectoryToRequest>,
+) -> Result<Tagged<DirectoryResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable(
+        [dir_id.as_str()]
+            .into_iter()
+            .chain(payload.parent_id.as_deref()),
+    )?;
+
+    let directory = storage
+        .get_directory(&dir_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Directory not found".to_string(),
+                }),
+            )
+        })?;
+    // The directory can end up out from under its access list, so moving
+    // it takes ownership of everything in it.
+    let access = load_access(&storage, &user).await?;
+    access
+        .check_subtree(&dir_id, Role::Owner)
+        .map_err(acl_error)?;
+    access
+        .check(payload.parent_id.as_deref(), Role::Write)
+        .map_err(acl_error)?;
+    check_move_target(&storage, payload.parent_id.as_deref()).await?;
+    check_if_match(&headers, directory.version)?;
+
+    let directory = relocate_directory(&storage, &directory, payload.parent_id).await?;
+    let (file_count, total_size) = storage.get_directory_stats(&dir_id).await.map_err(|e| {
+        error!("Failed to get directory stats: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to get directory stats: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(tagged(
+        directory.version,
+        DirectoryResponse {
+            id: directory.id,
+            name: directory.name,
+            parent_id: directory.parent_id,
+            created_at: directory.created_at,
+            updated_at: directory.updated_at,
+            color: directory.color,
+            icon: directory.icon,
+            version: directory.version,
+            file_count,
+            total_size,
+            readme: None,
+        },
+    ))
+}
+
+// List recent files handler
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct RecentQuery {
+    pub limit: Option<i64>,
+}
+
+pub async fn list_recent_files(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<RecentQuery>,
+) -> Result<Json<serde_json::Value>, (StatusCode, Json<ErrorResponse>)> {
+    let limit = query.limit.unwrap_or(20).min(100);
+    let access = load_access(&storage, &user).await?;
+    let mut files = storage.list_recent_files(limit).await.map_err(|e| {
+        error!("Failed to list recent files: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to list recent files: {}", e),
+            }),
+        )
+    })?;
+    files.retain(|f| access.allows(f.parent_directory_id.as_deref(), Role::Read));
+    let responses: Vec<FileResponse> = files.into_iter().map(|f| f.into()).collect();
+    let total = responses.len();
+    Ok(Json(serde_json::json!({ "files": responses, "total": total })))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Part of the file name or description to look for, ignoring case.
+    pub q: Option<String>,
+    /// Most matches to return (default 50, at most 500).
+    pub limit: Option<i64>,
+}
+
+// Search files in every directory by name and description
+pub async fn search_files(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<SearchQuery>,
+) -> Result<Json<SearchResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let text = query.q.as_deref().map(str::trim).unwrap_or_default();
+    if text.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "q must not be empty".to_string(),
+            }),
+        ));
+    }
+    let search_error = |e: sqlx::Error| {
+        error!("Failed to search files: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to search files: {}", e),
+            }),
+        )
+    };
+
+    let access = load_access(&storage, &user).await?;
+    let mut files = storage
+        .search_files(text, query.limit.unwrap_or(50).clamp(1, 500))
+        .await
+        .map_err(search_error)?;
+    files.retain(|f| access.allows(f.parent_directory_id.as_deref(), Role::Read));
+    let directory_ids: Vec<String> = files
+        .iter()
+        .filter_map(|f| f.parent_directory_id.clone())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let paths = storage
+        .directory_paths(&directory_ids)
+        .await
+        .map_err(search_error)?;
+
+    let matches: Vec<SearchMatch> = files
+        .into_iter()
+        .map(|file| {
+            let directory_path = file
+                .parent_directory_id
+                .as_ref()
+                .and_then(|id| paths.get(id).cloned())
+                .unwrap_or_else(|| "/".to_string());
+            SearchMatch {
+                file: file.into(),
+                directory_path,
+            }
+        })
+        .collect();
+    Ok(Json(SearchResponse {
+        query: text.to_string(),
+        total: matches.len(),
+        matches,
+    }))
+}
+
+// Bulk delete handler
+pub async fn bulk_delete(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(payload): Json<BulkDeleteRequest>,
 ) -> Result<Json<BulkDeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable(
+        payload
+            .file_ids
+            .iter()
+            .chain(payload.directory_ids.iter())
+            .map(String::as_str),
+    )?;
+    let access = load_access(&storage, &user).await?;
+    check_file_roles(
+        &storage,
+        &access,
+        payload.file_ids.iter().map(String::as_str),
+        Role::Write,
+    )
+    .await?;
+    for dir_id in &payload.directory_ids {
+        access
+            .check_subtree(dir_id, Role::Owner)
+            .map_err(acl_error)?;
+    }
+
     let (deleted_files, deleted_directories) = storage
         .bulk_delete(payload.file_ids, payload.directory_ids)
         .await
         .map_err(|e| {
-            error!("Failed to bulk delete: {}", e);
+            error!("Failed to bulk delete: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to bulk delete: {}", e),
+                }),
+            )
+        })?;
+
+    info!(
+        "Bulk delete completed: {} files, {} directories",
+        deleted_files, deleted_directories
+    );
+
+    Ok(Json(BulkDeleteResponse {
+        success: true,
+        deleted_files,
+        deleted_directories,
+        message: format!(
+            "Deleted {} files and {} directories",
+            deleted_files, deleted_directories
+        ),
+    }))
+}
+
+/// Checks the target of a bulk move or copy exists.
+async fn ensure_bulk_target(
+    storage: &FileStorage,
+    target: Option<&str>,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let Some(target) = target else {
+        return Ok(());
+    };
+    let directory = storage.get_directory(target).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if directory.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Target directory not found".to_string(),
+            }),
+        ));
+    }
+    Ok(())
+}
+
+fn count_done(results: &[BulkItemResult], kind: &str) -> usize {
+    results
+        .iter()
+        .filter(|r| r.success && r.kind == kind)
+        .count()
+}
+
+pub async fn bulk_move(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(payload): Json<BulkTransferRequest>,
+) -> Result<Json<BulkMoveResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable(
+        payload
+            .file_ids
+            .iter()
+            .chain(payload.directory_ids.iter())
+            .map(String::as_str)
+            .chain(payload.target_directory_id.as_deref()),
+    )?;
+    ensure_bulk_target(&storage, payload.target_directory_id.as_deref()).await?;
+    let access = require_role(
+        &storage,
+        &user,
+        payload.target_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+    check_file_roles(
+        &storage,
+        &access,
+        payload.file_ids.iter().map(String::as_str),
+        Role::Write,
+    )
+    .await?;
+    for dir_id in &payload.directory_ids {
+        access
+            .check_subtree(dir_id, Role::Owner)
+            .map_err(acl_error)?;
+    }
+
+    let results = storage
+        .bulk_move(
+            payload.file_ids,
+            payload.directory_ids,
+            payload.target_directory_id,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to bulk move: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to bulk move: {}", e),
+                }),
+            )
+        })?;
+
+    let moved_files = count_done(&results, "file");
+    let moved_directories = count_done(&results, "directory");
+    let success = results.iter().all(|r| r.success);
+    info!(
+        "Bulk move completed: {} files, {} directories",
+        moved_files, moved_directories
+    );
+
+    Ok(Json(BulkMoveResponse {
+        success,
+        moved_files,
+        moved_directories,
+        message: if success {
+            format!(
+                "Moved {} files and {} directories",
+                moved_files, moved_directories
+            )
+        } else {
+            "Nothing was moved; see results for the items that failed".to_string()
+        },
+        results,
+    }))
+}
+
+pub async fn bulk_copy(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(payload): Json<BulkTransferRequest>,
+) -> Result<Json<BulkCopyResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable(payload.target_directory_id.as_deref())?;
+    ensure_bulk_target(&storage, payload.target_directory_id.as_deref()).await?;
+    let access = require_role(
+        &storage,
+        &user,
+        payload.target_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+    check_file_roles(
+        &storage,
+        &access,
+        payload.file_ids.iter().map(String::as_str),
+        Role::Read,
+    )
+    .await?;
+    for dir_id in &payload.directory_ids {
+        access
+            .check_subtree(dir_id, Role::Read)
+            .map_err(acl_error)?;
+    }
+
+    let results = storage
+        .bulk_copy(
+            payload.file_ids,
+            payload.directory_ids,
+            payload.target_directory_id,
+        )
+        .await
+        .map_err(write_error(
+            "Failed to bulk copy",
+            StatusCode::INTERNAL_SERVER_ERROR,
+        ))?;
+
+    let copied_files = count_done(&results, "file");
+    let copied_directories = count_done(&results, "directory");
+    let success = results.iter().all(|r| r.success);
+    info!(
+        "Bulk copy completed: {} files, {} directories",
+        copied_files, copied_directories
+    );
+
+    Ok(Json(BulkCopyResponse {
+        success,
+        copied_files,
+        copied_directories,
+        message: if success {
+            format!(
+                "Copied {} files and {} directories",
+                copied_files, copied_directories
+            )
+        } else {
+            "Nothing was copied; see results for the items that failed".to_string()
+        },
+        results,
+    }))
+}
+
+const MAX_BATCH_OPERATIONS: usize = 1000;
+
+/// Refuses a batch unless its user may make every change in it. Parents
+/// created by earlier steps pass, having been checked as those steps.
+async fn check_batch_roles(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    operations: &[BatchOperation],
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let access = load_access(storage, user).await?;
+    let check_parent = |parent_id: &Option<String>| match parent_id.as_deref() {
+        Some(step) if step.starts_with('$') => Ok(()),
+        parent_id => access.check(parent_id, Role::Write).map_err(acl_error),
+    };
+    for operation in operations {
+        match operation {
+            BatchOperation::Mkdir { parent_id, .. } => check_parent(parent_id)?,
+            BatchOperation::Move {
+                kind,
+                id,
+                parent_id,
+                ..
+            } => {
+                check_parent(parent_id)?;
+                match kind.as_str() {
+                    "directory" => access.check_subtree(id, Role::Owner).map_err(acl_error)?,
+                    _ => check_file_roles(storage, &access, [id.as_str()], Role::Write).await?,
+                }
+            }
+            BatchOperation::Rename { kind, id, .. } => match kind.as_str() {
+                "directory" => access.check(Some(id), Role::Write).map_err(acl_error)?,
+                _ => check_file_roles(storage, &access, [id.as_str()], Role::Write).await?,
+            },
+            BatchOperation::Delete { kind, id } => match kind.as_str() {
+                "directory" => access.check_subtree(id, Role::Owner).map_err(acl_error)?,
+                _ => check_file_roles(storage, &access, [id.as_str()], Role::Write).await?,
+            },
+        }
+    }
+    Ok(())
+}
+
+pub async fn apply_batch(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(payload): Json<BatchRequest>,
+) -> Result<Json<BatchResponse>, (StatusCode, Json<ErrorResponse>)> {
+    if payload.operations.len() > MAX_BATCH_OPERATIONS {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!(
+                    "A batch can have at most {} operations",
+                    MAX_BATCH_OPERATIONS
+                ),
+            }),
+        ));
+    }
+    ensure_writable(payload.operations.iter().flat_map(|operation| {
+        let (id, parent_id) = match operation {
+            BatchOperation::Mkdir { parent_id, .. } => (None, parent_id.as_deref()),
+            BatchOperation::Move { id, parent_id, .. } => (Some(id.as_str()), parent_id.as_deref()),
+            BatchOperation::Rename { id, .. } | BatchOperation::Delete { id, .. } => {
+                (Some(id.as_str()), None)
+            }
+        };
+        id.into_iter().chain(parent_id)
+    }))?;
+    check_batch_roles(&storage, &user, &payload.operations).await?;
+
+    match storage.apply_batch(&payload.operations).await {
+        Ok(results) => {
+            info!("Batch applied: {} operations", results.len());
+            Ok(Json(BatchResponse {
+                success: true,
+                message: format!("Applied {} operations", results.len()),
+                results,
+                failed_operation: None,
+                error: None,
+            }))
+        }
+        Err(BatchError::Rejected { index, message }) => Ok(Json(BatchResponse {
+            success: false,
+            results: Vec::new(),
+            failed_operation: Some(index),
+            error: Some(message),
+            message: format!("Operation {} failed; nothing was changed", index),
+        })),
+        Err(e) => {
+            error!("Failed to apply batch: {}", e);
+            Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to apply batch: {}", e),
+                }),
+            ))
+        }
+    }
+}
+
+const MAX_TAG_LEN: usize = 64;
+
+/// Tags are compared case-insensitively, so they are stored lowercased,
+/// each once.
+fn normalize_tags(tags: &[String]) -> Result<Vec<String>, String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_lowercase();
+        if tag.is_empty() || tag.chars().count() > MAX_TAG_LEN || tag.chars().any(char::is_control)
+        {
+            return Err(format!(
+                "Tags must be 1 to {} characters without control characters",
+                MAX_TAG_LEN
+            ));
+        }
+        if !normalized.contains(&tag) {
+            normalized.push(tag);
+        }
+    }
+    Ok(normalized)
+}
+
+pub async fn bulk_update(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(mut payload): Json<BulkUpdateRequest>,
+) -> Result<Json<BulkUpdateResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+
+    ensure_writable(payload.file_ids.iter().map(String::as_str))?;
+    let access = load_access(&storage, &user).await?;
+    check_file_roles(
+        &storage,
+        &access,
+        payload.file_ids.iter().map(String::as_str),
+        Role::Write,
+    )
+    .await?;
+
+    if payload.add_tags.is_none()
+        && payload.remove_tags.is_none()
+        && payload.description.is_none()
+        && payload.expires_at.is_none()
+    {
+        return Err(bad_request(
+            "Nothing to update; set add_tags, remove_tags, description or expires_at".to_string(),
+        ));
+    }
+
+    for tags in [&mut payload.add_tags, &mut payload.remove_tags]
+        .into_iter()
+        .flatten()
+    {
+        *tags = normalize_tags(tags).map_err(bad_request)?;
+    }
+    if let (Some(add), Some(remove)) = (&payload.add_tags, &payload.remove_tags) {
+        if let Some(tag) = add.iter().find(|tag| remove.contains(tag)) {
+            return Err(bad_request(format!(
+                "Tag '{}' can't be both added and removed",
+                tag
+            )));
+        }
+    }
+    if let Some(Some(expires_at)) = &mut payload.expires_at {
+        let parsed = DateTime::parse_from_rfc3339(expires_at).map_err(|_| {
+            bad_request(
+                "expires_at must be an RFC 3339 time such as 2024-12-31T23:00:00Z".to_string(),
+            )
+        })?;
+        *expires_at = parsed.with_timezone(&Utc).to_rfc3339();
+    }
+
+    let results = storage.bulk_update_files(&payload).await.map_err(|e| {
+        error!("Failed to bulk update: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to bulk update: {}", e),
+            }),
+        )
+    })?;
+
+    let updated_files = count_done(&results, "file");
+    let success = results.iter().all(|r| r.success);
+    info!("Bulk update completed: {} files", updated_files);
+
+    Ok(Json(BulkUpdateResponse {
+        success,
+        updated_files,
+        message: if success {
+            format!("Updated {} files", updated_files)
+        } else {
+            "Nothing was updated; see results for the files that failed".to_string()
+        },
+        results,
+    }))
+}
+
+// Create link handler
+pub async fn create_link(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(mut payload): Json<CreateLinkRequest>,
+) -> Result<Json<LinkResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+
+    payload.name = storage.name_policy().normalize(&payload.name);
+    storage
+        .name_policy()
+        .validate(&payload.name)
+        .map_err(|e| bad_request(format!("Invalid link name: {}", e)))?;
+    ensure_writable(payload.parent_directory_id.as_deref())?;
+    let access = require_role(
+        &storage,
+        &user,
+        payload.parent_directory_id.as_deref(),
+        Role::Write,
+    )
+    .await?;
+
+    match (&payload.target_url, &payload.target_file_id) {
+        (Some(url), None) => {
+            if !(url.starts_with("http://") || url.starts_with("https://")) {
+                return Err(bad_request("target_url must be an http(s) URL".to_string()));
+            }
+        }
+        (None, Some(file_id)) => {
+            let target = storage.get_file_metadata(file_id).await.map_err(|e| {
+                error!("Database error: {}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    Json(ErrorResponse {
+                        error: format!("Database error: {}", e),
+                    }),
+                )
+            })?;
+            let Some(target) = target else {
+                return Err(bad_request("target_file_id does not exist".to_string()));
+            };
+            access
+                .check(target.parent_directory_id.as_deref(), Role::Read)
+                .map_err(acl_error)?;
+        }
+        _ => {
+            return Err(bad_request(
+                "Exactly one of target_url or target_file_id is required".to_string(),
+            ))
+        }
+    }
+
+    let link = storage
+        .create_link(
+            &payload.name,
+            payload.target_url,
+            payload.target_file_id,
+            payload.parent_directory_id,
+            payload.description,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to create link: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to create link: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(link.into()))
+}
+
+// Get link info handler
+pub async fn get_link_info(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(link_id): Path<String>,
+) -> Result<Json<LinkResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let link = storage
+        .get_link(&link_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Link not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(
+        &storage,
+        &user,
+        link.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+
+    Ok(Json(link.into()))
+}
+
+// Follow link handler: redirects to the external URL or the target file's download
+pub async fn open_link(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(link_id): Path<String>,
+) -> Result<Redirect, (StatusCode, Json<ErrorResponse>)> {
+    let link = storage
+        .get_link(&link_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Link not found".to_string(),
+                }),
+            )
+        })?;
+    require_role(
+        &storage,
+        &user,
+        link.parent_directory_id.as_deref(),
+        Role::Read,
+    )
+    .await?;
+
+    match (link.target_url, link.target_file_id) {
+        (Some(url), _) => Ok(Redirect::temporary(&url)),
+        (None, Some(file_id)) => Ok(Redirect::temporary(&format!(
+            "/api/files/{}/download",
+            file_id
+        ))),
+        (None, None) => Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Link has no target".to_string(),
+            }),
+        )),
+    }
+}
+
+// Delete link handler
+pub async fn delete_link(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(link_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let link = storage.get_link(&link_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if let Some(link) = link {
+        require_role(
+            &storage,
+            &user,
+            link.parent_directory_id.as_deref(),
+            Role::Write,
+        )
+        .await?;
+    }
+
+    let deleted = storage.delete_link(&link_id).await.map_err(|e| {
+        error!("Failed to delete link: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to delete link: {}", e),
+            }),
+        )
+    })?;
+
+    if deleted {
+        info!("Link deleted: {}", link_id);
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Link deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Link not found".to_string(),
+            }),
+        ))
+    }
+}
+
+// Change event stream handler (Server-Sent Events)
+pub async fn stream_events(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+) -> Result<Sse<impl Stream<Item = Result<SseEvent, Infallible>>>, (StatusCode, Json<ErrorResponse>)>
+{
+    // Changes in directories the subscriber can't read are left out, as
+    // access stood when the stream was opened.
+    let access = Arc::new(load_access(&storage, &user).await?);
+    // Subscribers that fall behind simply miss the events they lagged on.
+    let stream = BroadcastStream::new(storage.events().subscribe()).filter_map(move |event| {
+        let access = access.clone();
+        async move {
+            let event = event.ok()?;
+            if let Some(directory_id) = event.change.directory_id() {
+                if !access.allows(directory_id, Role::Read) {
+                    return None;
+                }
+            }
+            SseEvent::default().json_data(&event).ok().map(Ok)
+        }
+    });
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+// Retention policy handlers
+pub async fn create_retention_policy(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Json(payload): Json<CreateRetentionPolicyRequest>,
+) -> Result<Json<RetentionPolicy>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: &str| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: error.to_string(),
+            }),
+        )
+    };
+
+    if payload.pattern.is_empty() {
+        return Err(bad_request("pattern must not be empty"));
+    }
+    if payload.keep_last < 1 {
+        return Err(bad_request("keep_last must be at least 1"));
+    }
+    ensure_writable(payload.directory_id.as_deref())?;
+
+    if let Some(dir_id) = &payload.directory_id {
+        let exists = storage.get_directory(dir_id).await.map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+        if exists.is_none() {
+            return Err(bad_request("directory_id does not exist"));
+        }
+    }
+    // The policy deletes files throughout the directory
+    require_role(&storage, &user, payload.directory_id.as_deref(), Role::Owner).await?;
+
+    let policy = storage
+        .create_retention_policy(payload.directory_id, &payload.pattern, payload.keep_last)
+        .await
+        .map_err(|e| {
+            error!("Failed to create retention policy: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to create retention policy: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(policy))
+}
+
+pub async fn list_retention_policies(
+    State(storage): State<FileStorage>,
+) -> Result<Json<Vec<RetentionPolicy>>, (StatusCode, Json<ErrorResponse>)> {
+    let policies = storage.list_retention_policies().await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(policies))
+}
+
+pub async fn delete_retention_policy(
+    State(storage): State<FileStorage>,
+    Path(policy_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let deleted = storage
+        .delete_retention_policy(&policy_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete retention policy: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to delete retention policy: {}", e),
+                }),
+            )
+        })?;
+
+    if deleted {
+        info!("Retention policy deleted: {}", policy_id);
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Retention policy deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Retention policy not found".to_string(),
+            }),
+        ))
+    }
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UsageTreeQuery {
+    pub depth: Option<usize>,
+}
+
+// Admin: recursive storage usage per directory, for treemap visualizations
+pub async fn usage_tree(
+    State(storage): State<FileStorage>,
+    Query(query): Query<UsageTreeQuery>,
+) -> Result<Json<UsageNode>, (StatusCode, Json<ErrorResponse>)> {
+    let tree = storage
+        .usage_tree(query.depth.unwrap_or(3))
+        .await
+        .map_err(|e| {
+            error!("Failed to compute usage tree: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to compute usage tree: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(tree))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct CostReportQuery {
+    /// Days of download history to base egress on; defaults to 30, or the
+    /// access log retention if that is shorter.
+    pub days: Option<u64>,
+    pub limit: Option<usize>,
+}
+
+// Thumbnail policy handlers
+pub async fn create_thumbnail_policy(
+    State(storage): State<FileStorage>,
+    Json(payload): Json<CreateThumbnailPolicyRequest>,
+) -> Result<Json<ThumbnailPolicy>, (StatusCode, Json<ErrorResponse>)> {
+    let sizes = match &payload.sizes {
+        Some(names) if names.is_empty() => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "sizes must not be empty".to_string(),
+                }),
+            ));
+        }
+        Some(names) => thumbnail_sizes(names)?,
+        None => THUMBNAIL_SIZES.to_vec(),
+    };
+    ensure_writable(payload.directory_id.as_deref())?;
+
+    if let Some(dir_id) = &payload.directory_id {
+        let exists = storage.get_directory(dir_id).await.map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+        if exists.is_none() {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "directory_id does not exist".to_string(),
+                }),
+            ));
+        }
+    }
+
+    let policy = storage
+        .create_thumbnail_policy(
+            payload.directory_id,
+            payload.recursive.unwrap_or(false),
+            &sizes,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to create thumbnail policy: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to create thumbnail policy: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(policy))
+}
+
+pub async fn list_thumbnail_policies(
+    State(storage): State<FileStorage>,
+) -> Result<Json<Vec<ThumbnailPolicy>>, (StatusCode, Json<ErrorResponse>)> {
+    let policies = storage.list_thumbnail_policies().await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(policies))
+}
+
+pub async fn delete_thumbnail_policy(
+    State(storage): State<FileStorage>,
+    Path(policy_id): Path<String>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let deleted = storage
+        .delete_thumbnail_policy(&policy_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete thumbnail policy: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to delete thumbnail policy: {}", e),
+                }),
+            )
+        })?;
+
+    if deleted {
+        info!("Thumbnail policy deleted: {}", policy_id);
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Thumbnail policy deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Thumbnail policy not found".to_string(),
+            }),
+        ))
+    }
+}
+
+// Admin: estimated monthly storage and egress cost per directory and share
+pub async fn cost_report(
+    State(storage): State<FileStorage>,
+    Query(query): Query<CostReportQuery>,
+) -> Result<Json<CostReport>, (StatusCode, Json<ErrorResponse>)> {
+    let retention = storage.access_log_retention_days();
+    let days = query.days.unwrap_or(retention.min(30));
+    if days == 0 || days > retention {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: format!(
+                    "days must be between 1 and {} (the access log retention period)",
+                    retention
+                ),
+            }),
+        ));
+    }
+
+    let report = storage
+        .cost_report(days, query.limit.unwrap_or(100))
+        .await
+        .map_err(|e| {
+            error!("Failed to estimate storage costs: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to estimate storage costs: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(report))
+}
+
+// Admin: everything stored about a user, for data subject access requests,
+// as a ZIP archive of `export.json` and the content of the user's files
+pub async fn export_user_data(
+    State(storage): State<FileStorage>,
+    Path(user_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let (export, files) = storage.export_user_data(&user_id).await.map_err(|e| {
+        error!("Failed to export data of user {}: {}", user_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to export user data: {}", e),
+            }),
+        )
+    })?;
+    let manifest = serde_json::to_vec_pretty(&export).map_err(|e| {
+        error!("Failed to serialize data of user {}: {}", user_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to export user data: {}", e),
+            }),
+        )
+    })?;
+
+    let mut entries = vec![zip::ArchiveEntry {
+        path: "export.json".to_string(),
+        modified: export.exported_at.clone(),
+        content: zip::ArchiveContent::Data(manifest.into()),
+    }];
+    for file in files {
+        entries.push(zip::ArchiveEntry {
+            path: format!("files/{}/{}", file.id, file.original_filename),
+            modified: file.uploaded_at.clone(),
+            content: zip::ArchiveContent::File(Box::new(file)),
+        });
+    }
+
+    info!(
+        "Exporting data of user {} with {} files",
+        user_id,
+        entries.len() - 1
+    );
+    finish_response(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/zip")
+            .header(
+                header::CONTENT_DISPOSITION,
+                content_disposition("attachment", &format!("user-{}.zip", user_id)),
+            ),
+        Body::from_stream(zip::archive(storage.clone(), entries)),
+    )
+}
+
+// Admin: erase everything stored about a user
+pub async fn erase_user_data(
+    State(storage): State<FileStorage>,
+    Path(user_id): Path<String>,
+) -> Result<Json<UserErasureReport>, (StatusCode, Json<ErrorResponse>)> {
+    let report = storage.erase_user_data(&user_id).await.map_err(|e| {
+        error!("Failed to erase data of user {}: {}", user_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to erase user data: {}", e),
+            }),
+        )
+    })?;
+
+    info!("Erased data of user {}", user_id);
+    Ok(Json(report))
+}
+
+// Admin: total storage use against the soft and hard quota
+pub async fn get_quota(
+    State(storage): State<FileStorage>,
+) -> Result<Json<QuotaStatus>, (StatusCode, Json<ErrorResponse>)> {
+    let status = storage.quota_status().await.map_err(|e| {
+        error!("Failed to check storage quota: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to check storage quota: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(status))
+}
+
+// Admin: upload data received but not yet on disk, to spot a slow disk
+pub async fn get_write_queue(State(storage): State<FileStorage>) -> Json<WriteQueueStats> {
+    Json(storage.write_queue().stats())
+}
+
+// Admin: database size and dead space
+pub async fn get_database_stats(
+    State(storage): State<FileStorage>,
+) -> Result<Json<DatabaseStats>, (StatusCode, Json<ErrorResponse>)> {
+    storage.database_stats().await.map(Json).map_err(|e| {
+        error!("Failed to read database stats: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to read database stats: {}", e),
+            }),
+        )
+    })
+}
+
+// Admin: checkpoint, VACUUM and ANALYZE the database now
+pub async fn run_database_maintenance(
+    State(storage): State<FileStorage>,
+) -> Result<Json<DatabaseMaintenance>, (StatusCode, Json<ErrorResponse>)> {
+    storage.maintain_database(true).await.map(Json).map_err(|e| {
+        let status = match e {
+            MaintenanceError::AlreadyRunning => StatusCode::CONFLICT,
+            MaintenanceError::Database(_) => {
+                error!("Database maintenance failed: {}", e);
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        };
+        (
+            status,
+            Json(ErrorResponse {
+                error: e.to_string(),
+            }),
+        )
+    })
+}
+
+// Admin: disk space reclaimed from abandoned and interrupted uploads
+pub async fn get_upload_cleanup(State(storage): State<FileStorage>) -> Json<UploadCleanupStats> {
+    Json(storage.upload_cleanup_stats())
+}
+
+/// Most bytes a disk benchmark may write.
+const MAX_BENCHMARK_SIZE: u64 = 16 * 1024 * 1024 * 1024;
+
+// Admin: sequential throughput of the upload volume, to tune buffer sizes
+pub async fn disk_benchmark(
+    State(storage): State<FileStorage>,
+    Json(request): Json<DiskBenchmarkRequest>,
+) -> Result<Json<DiskBenchmark>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |error: String| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error }));
+
+    let size = request.size.unwrap_or(256 * 1024 * 1024);
+    if !(1024 * 1024..=MAX_BENCHMARK_SIZE).contains(&size) {
+        return Err(bad_request(format!(
+            "Size must be between 1 MiB and {} bytes",
+            MAX_BENCHMARK_SIZE
+        )));
+    }
+    let io = storage.file_io();
+    let read_buffer = request.read_buffer_size.unwrap_or(io.read_buffer());
+    let write_buffer = request.write_buffer_size.unwrap_or(io.write_buffer());
+    for buffer in [read_buffer, write_buffer] {
+        if !(file_io::MIN_BUFFER_SIZE..=file_io::MAX_BUFFER_SIZE).contains(&buffer) {
+            return Err(bad_request(format!(
+                "Buffer sizes must be between {} and {} bytes",
+                file_io::MIN_BUFFER_SIZE,
+                file_io::MAX_BUFFER_SIZE
+            )));
+        }
+    }
+
+    let result = io
+        .with_buffers(read_buffer, write_buffer)
+        .benchmark(storage.upload_dir(), size)
+        .await
+        .map_err(|e| {
+            error!("Disk benchmark failed: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Disk benchmark failed: {}", e),
+                }),
+            )
+        })?;
+    info!(
+        "Disk benchmark: {:.0} MiB/s write, {:.0} MiB/s read",
+        result.write_mib_per_sec, result.read_mib_per_sec
+    );
+    Ok(Json(result))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ReportQuery {
+    pub limit: Option<i64>,
+}
+
+// Admin: largest / least-accessed / never-downloaded file reports
+pub async fn file_report(
+    State(storage): State<FileStorage>,
+    Path(report_name): Path<String>,
+    Query(query): Query<ReportQuery>,
+) -> Result<Json<FileReportResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let report = FileReport::from_name(&report_name).ok_or_else(|| {
+        (
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: format!("Unknown report: {}", report_name),
+            }),
+        )
+    })?;
+
+    let files = storage
+        .file_report(report, query.limit.unwrap_or(50).clamp(1, 1000))
+        .await
+        .map_err(|e| {
+            error!("Failed to build {} report: {}", report_name, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to build report: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(FileReportResponse {
+        report: report_name,
+        files: files.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Most entries of the audit trail returned for one quarantined file.
+const MAX_FILE_DECISIONS: i64 = 1000;
+
+fn not_in_quarantine() -> (StatusCode, Json<ErrorResponse>) {
+    (
+        StatusCode::NOT_FOUND,
+        Json(ErrorResponse {
+            error: "File is not in quarantine".to_string(),
+        }),
+    )
+}
+
+async fn find_quarantine_entry(
+    storage: &FileStorage,
+    file_id: &str,
+) -> Result<QuarantineEntry, (StatusCode, Json<ErrorResponse>)> {
+    storage
+        .get_quarantine_entry(file_id)
+        .await
+        .map_err(|e| {
+            error!("Failed to look up quarantined file {}: {}", file_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(not_in_quarantine)
+}
+
+// Admin: list quarantined files
+pub async fn list_quarantine(
+    State(storage): State<FileStorage>,
+) -> Result<Json<Vec<QuarantinedFileResponse>>, (StatusCode, Json<ErrorResponse>)> {
+    let entries = storage.list_quarantine().await.map_err(|e| {
+        error!("Failed to list quarantined files: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to list quarantined files: {}", e),
+            }),
+        )
+    })?;
+    Ok(Json(entries.into_iter().map(Into::into).collect()))
+}
+
+// Admin: open reports against share links
+pub async fn list_share_reports(
+    State(storage): State<FileStorage>,
+) -> Result<Json<Vec<ShareReport>>, (StatusCode, Json<ErrorResponse>)> {
+    let reports = storage.list_share_reports().await.map_err(|e| {
+        error!("Failed to list share reports: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to list share reports: {}", e),
+            }),
+        )
+    })?;
+    Ok(Json(reports))
+}
+
+// Admin: dismisses the reports against a share, or revokes it
+pub async fn resolve_share_report(
+    State(storage): State<FileStorage>,
+    Path(report_id): Path<String>,
+    Json(payload): Json<ResolveShareReportRequest>,
+) -> Result<Json<ShareReport>, (StatusCode, Json<ErrorResponse>)> {
+    let revoke = match payload.action.as_str() {
+        "dismiss" => false,
+        "revoke" => true,
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: "action must be dismiss or revoke".to_string(),
+                }),
+            ))
+        }
+    };
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let report = storage
+        .get_share_report(&report_id)
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Report not found".to_string(),
+                }),
+            )
+        })?;
+    if report.resolved_at.is_some() {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "The report was already resolved".to_string(),
+            }),
+        ));
+    }
+
+    let resolved = storage
+        .resolve_share_reports(&report.share_id, revoke)
+        .await
+        .map_err(db_error)?;
+    info!(
+        "Resolved {} reports against share {} ({})",
+        resolved, report.share_id, payload.action
+    );
+
+    let report = storage
+        .get_share_report(&report_id)
+        .await
+        .map_err(db_error)?
+        .unwrap_or(report);
+    Ok(Json(report))
+}
+
+// Admin: put a file flagged by a scanner or moderator in quarantine
+pub async fn quarantine_file(
+    State(storage): State<FileStorage>,
+    Json(payload): Json<QuarantineFileRequest>,
+) -> Result<Json<QuarantinedFileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([payload.file_id.as_str()])?;
+    let source = payload.source.trim();
+    if source.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "source must not be empty".to_string(),
+            }),
+        ));
+    }
+    let db_error = |e: sqlx::Error| {
+        error!("Failed to quarantine {}: {}", payload.file_id, e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to quarantine file: {}", e),
+            }),
+        )
+    };
+
+    if storage
+        .get_quarantine_entry(&payload.file_id)
+        .await
+        .map_err(db_error)?
+        .is_some()
+    {
+        return Err((
+            StatusCode::CONFLICT,
+            Json(ErrorResponse {
+                error: "File is already in quarantine".to_string(),
+            }),
+        ));
+    }
+    let entry = storage
+        .quarantine_file(&payload.file_id, source, payload.reason.as_deref())
+        .await
+        .map_err(db_error)?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "File not found".to_string(),
+                }),
+            )
+        })?;
+
+    info!(
+        "File {} quarantined by {}: {}",
+        entry.file.id,
+        entry.source,
+        entry.reason.as_deref().unwrap_or("no reason given")
+    );
+    Ok(Json(entry.into()))
+}
+
+// Admin: inspect a quarantined file and its audit trail
+pub async fn get_quarantined_file(
+    State(storage): State<FileStorage>,
+    Path(file_id): Path<String>,
+) -> Result<Json<QuarantinedFileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let entry = find_quarantine_entry(&storage, &file_id).await?;
+    let decisions = storage
+        .quarantine_decisions(Some(&file_id), MAX_FILE_DECISIONS)
+        .await
+        .map_err(|e| {
+            error!("Failed to read quarantine decisions of {}: {}", file_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(QuarantinedFileResponse {
+        decisions: Some(decisions),
+        ..entry.into()
+    }))
+}
+
+// Admin: download a quarantined file to review it
+pub async fn download_quarantined_file(
+    State(storage): State<FileStorage>,
+    Path(file_id): Path<String>,
+) -> Result<Response, (StatusCode, Json<ErrorResponse>)> {
+    let entry = find_quarantine_entry(&storage, &file_id).await?;
+    let stream = storage.open_content(&entry.file).await.map_err(|e| {
+        error!("Failed to open file: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Failed to open file: {}", e),
+            }),
+        )
+    })?;
+
+    // Whatever flagged the file, it is never given a type a browser would
+    // render.
+    finish_response(
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "application/octet-stream")
+            .header(header::CONTENT_LENGTH, entry.file.file_size)
+            .header(
+                header::CONTENT_DISPOSITION,
+                content_disposition("attachment", &entry.file.original_filename),
+            ),
+        Body::from_stream(stream),
+    )
+}
+
+// Admin: release a quarantined file, making it visible again
+pub async fn release_quarantined_file(
+    State(storage): State<FileStorage>,
+    Path(file_id): Path<String>,
+    Json(payload): Json<QuarantineReviewRequest>,
+) -> Result<Json<FileResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let entry = find_quarantine_entry(&storage, &file_id).await?;
+    storage
+        .release_file(&entry, payload.reviewer.as_deref(), payload.reason.as_deref())
+        .await
+        .map_err(|e| {
+            error!("Failed to release {}: {}", file_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to release file: {}", e),
+                }),
+            )
+        })?;
+
+    info!("File {} released from quarantine", file_id);
+    Ok(Json(entry.file.into()))
+}
+
+// Admin: delete a quarantined file for good
+pub async fn purge_quarantined_file(
+    State(storage): State<FileStorage>,
+    Path(file_id): Path<String>,
+    Json(payload): Json<QuarantineReviewRequest>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let entry = find_quarantine_entry(&storage, &file_id).await?;
+    storage
+        .purge_file(entry, payload.reviewer.as_deref(), payload.reason.as_deref())
+        .await
+        .map_err(|e| {
+            error!("Failed to purge {}: {}", file_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to purge file: {}", e),
+                }),
+            )
+        })?;
+
+    info!("File {} purged from quarantine", file_id);
+    Ok(Json(DeleteResponse {
+        success: true,
+        message: "File purged successfully".to_string(),
+    }))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct QuarantineLogQuery {
+    /// Most decisions to return, newest first (default 100, at most 1000).
+    pub limit: Option<i64>,
+}
+
+// Admin: audit trail of quarantine decisions
+pub async fn quarantine_log(
+    State(storage): State<FileStorage>,
+    Query(query): Query<QuarantineLogQuery>,
+) -> Result<Json<Vec<QuarantineDecision>>, (StatusCode, Json<ErrorResponse>)> {
+    let decisions = storage
+        .quarantine_decisions(None, query.limit.unwrap_or(100).clamp(1, 1000))
+        .await
+        .map_err(|e| {
+            error!("Failed to read quarantine decisions: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+    Ok(Json(decisions))
+}
+
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct AccessLogQuery {
+    pub limit: Option<i64>,
+}
+
+// Recent download history of a file
+pub async fn get_file_access_log(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(file_id): Path<String>,
+    Query(query): Query<AccessLogQuery>,
+) -> Result<Json<AccessLogResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let Some(metadata) = storage
+        .get_file_metadata(&file_id)
+        .await
+        .map_err(db_error)?
+    else {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "File not found".to_string(),
+            }),
+        ));
+    };
+    // The log tells who fetched the file from where, so only the uploader
+    // and the directory's owners get to see it
+    let access = load_access(&storage, &user).await?;
+    let uploaded_by_user = match (&user, &metadata.owner_id) {
+        (Some(Extension(user)), Some(owner_id)) => &user.id == owner_id,
+        _ => false,
+    };
+    if !uploaded_by_user {
+        access
+            .check(metadata.parent_directory_id.as_deref(), Role::Owner)
+            .map_err(acl_error)?;
+    }
+
+    let entries = storage
+        .access_log(&file_id, query.limit.unwrap_or(100).clamp(1, 1000))
+        .await
+        .map_err(db_error)?;
+
+    Ok(Json(AccessLogResponse {
+        file_id,
+        entries,
+        retention_days: storage.access_log_retention_days(),
+    }))
+}
+
+/// Refuses requests about `user_id`'s notification settings, watched
+/// folders or push subscriptions unless they come from that user or an
+/// admin.
+fn require_self(
+    user: &Option<Extension<AuthUser>>,
+    user_id: &str,
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    match user {
+        Some(Extension(user)) if user.id == user_id || user.admin => Ok(()),
+        Some(_) => Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "You can only manage your own notifications".to_string(),
+            }),
+        )),
+        None => Err((
+            StatusCode::UNAUTHORIZED,
+            Json(ErrorResponse {
+                error: "Log in to manage notifications".to_string(),
+            }),
+        )),
+    }
+}
+
+// Notification preference handlers
+pub async fn get_notification_preferences(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<NotificationPreferences>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let prefs = storage
+        .get_notification_preferences(&user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .unwrap_or_else(|| NotificationPreferences::defaults(&user_id));
+
+    Ok(Json(prefs))
+}
+
+pub async fn update_notification_preferences(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<UpdateNotificationPreferencesRequest>,
+) -> Result<Json<NotificationPreferences>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let mut prefs = storage
+        .get_notification_preferences(&user_id)
+        .await
+        .map_err(db_error)?
+        .unwrap_or_else(|| NotificationPreferences::defaults(&user_id));
+
+    if let Some(delivery) = payload.delivery {
+        if delivery != DELIVERY_IMMEDIATE && delivery != DELIVERY_DIGEST {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!(
+                        "delivery must be '{}' or '{}'",
+                        DELIVERY_IMMEDIATE, DELIVERY_DIGEST
+                    ),
+                }),
+            ));
+        }
+        prefs.delivery = delivery;
+    }
+    if let Some(email) = payload.email {
+        prefs.email = Some(email).filter(|e| !e.is_empty());
+    }
+    if let Some(on_share_access) = payload.on_share_access {
+        prefs.on_share_access = on_share_access;
+    }
+    if let Some(on_new_files) = payload.on_new_files {
+        prefs.on_new_files = on_new_files;
+    }
+    prefs.updated_at = Utc::now().to_rfc3339();
+
+    storage
+        .save_notification_preferences(&prefs)
+        .await
+        .map_err(db_error)?;
+
+    info!("Notification preferences updated for {}", user_id);
+    Ok(Json(prefs))
+}
+
+pub async fn list_watched_folders(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Vec<WatchedFolder>>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let folders = storage.list_watched_folders(&user_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(folders))
+}
+
+pub async fn watch_folder(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<WatchFolderRequest>,
+) -> Result<Json<WatchedFolder>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    if storage
+        .get_directory(&payload.directory_id)
+        .await
+        .map_err(db_error)?
+        .is_none()
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    // Notifications name the files they're about, so the watcher has to be
+    // able to read the folder.
+    let watcher_access = storage.access(Some(&user_id)).await.map_err(db_error)?;
+    if !watcher_access.allows(Some(&payload.directory_id), Role::Read) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            Json(ErrorResponse {
+                error: "That user can't read this directory".to_string(),
+            }),
+        ));
+    }
+
+    let watch = storage
+        .watch_folder(&user_id, &payload.directory_id)
+        .await
+        .map_err(db_error)?;
+
+    Ok(Json(watch))
+}
+
+pub async fn unwatch_folder(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((user_id, directory_id)): Path<(String, String)>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let removed = storage
+        .unwatch_folder(&user_id, &directory_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    if removed {
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Folder is no longer watched".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Folder is not watched".to_string(),
+            }),
+        ))
+    }
+}
+
+// Directory integration handlers
+pub async fn list_directory_integrations(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+) -> Result<Json<Vec<DirectoryIntegration>>, (StatusCode, Json<ErrorResponse>)> {
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+
+    let integrations = storage
+        .list_directory_integrations(&dir_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(integrations))
+}
+
+pub async fn create_directory_integration(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    Json(payload): Json<CreateDirectoryIntegrationRequest>,
+) -> Result<Json<DirectoryIntegration>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: message }),
+        )
+    };
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    if !integrations::KINDS.contains(&payload.kind.as_str()) {
+        return Err(bad_request(format!(
+            "kind must be one of: {}",
+            integrations::KINDS.join(", ")
+        )));
+    }
+    if payload.kind == integrations::KIND_MATRIX
+        && (payload.room_id.is_none() || payload.access_token.is_none())
+    {
+        return Err(bad_request(
+            "Matrix integrations require room_id and access_token".to_string(),
+        ));
+    }
+
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    if user.is_none() {
+        return Err(acl_error(AclError::LoginRequired));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+    push::check_url(&payload.url).await.map_err(bad_request)?;
+
+    let integration = storage
+        .create_directory_integration(&dir_id, payload)
+        .await
+        .map_err(db_error)?;
+
+    info!(
+        "Added {} integration {} to directory {}",
+        integration.kind, integration.id, dir_id
+    );
+    Ok(Json(integration))
+}
+
+pub async fn delete_directory_integration(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((dir_id, integration_id)): Path<(String, String)>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_role(&storage, &user, Some(&dir_id), Role::Owner).await?;
+
+    let deleted = storage
+        .delete_directory_integration(&dir_id, &integration_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    if deleted {
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Integration deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Integration not found".to_string(),
+            }),
+        ))
+    }
+}
+
+// Admin: sends an integration's failed deliveries again
+pub async fn replay_webhook(
+    State(storage): State<FileStorage>,
+    Path(integration_id): Path<String>,
+) -> Result<Json<WebhookReplayResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let integration = storage
+        .get_directory_integration(&integration_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Integration not found".to_string(),
+                }),
+            )
+        })?;
+
+    let outcome = integrations::replay_failed(&storage, &integration)
+        .await
+        .map_err(|e| {
+            error!("Failed to replay deliveries to {}: {}", integration_id, e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to replay deliveries: {}", e),
+                }),
+            )
+        })?;
+
+    info!(
+        "Replayed {} deliveries to integration {}, {} still failing",
+        outcome.replayed, integration_id, outcome.failed
+    );
+    Ok(Json(outcome))
+}
+
+// Directory access list handlers
+async fn directory_acl_response(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    directory_id: String,
+) -> Result<DirectoryAclResponse, (StatusCode, Json<ErrorResponse>)> {
+    let access = load_access(storage, user).await?;
+    let entries = storage
+        .list_directory_acl(&directory_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+    Ok(DirectoryAclResponse {
+        restricted: access.is_restricted(Some(&directory_id)),
+        your_role: access
+            .role(Some(&directory_id))
+            .map(|role| role.as_str().to_string()),
+        directory_id,
+        entries,
+    })
+}
+
+/// Finds the directory whose access list is being changed, after checking
+/// the request's user owns it.
+async fn find_acl_directory(
+    storage: &FileStorage,
+    user: &Option<Extension<AuthUser>>,
+    dir_id: &str,
+) -> Result<(Directory, Access), (StatusCode, Json<ErrorResponse>)> {
+    ensure_writable([dir_id])?;
+    if user.is_none() {
+        return Err(acl_error(AclError::LoginRequired));
+    }
+    let directory = storage
+        .get_directory(dir_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 Json(ErrorResponse {
-                    error: format!("Failed to bulk delete: {}", e),
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Directory not found".to_string(),
                 }),
             )
         })?;
+    let access = require_role(storage, user, Some(dir_id), Role::Owner).await?;
+    Ok((directory, access))
+}
+
+/// A directory's own access list must keep an owner, unless an ancestor's
+/// list covers it.
+fn ensure_acl_owner(
+    access: &Access,
+    directory: &Directory,
+    remaining: &[DirectoryAclEntry],
+) -> Result<(), (StatusCode, Json<ErrorResponse>)> {
+    let owned = remaining
+        .iter()
+        .any(|entry| entry.role == Role::Owner.as_str());
+    if remaining.is_empty() || owned || access.is_restricted(directory.parent_id.as_deref()) {
+        return Ok(());
+    }
+    Err((
+        StatusCode::CONFLICT,
+        Json(ErrorResponse {
+            error: "That would leave the directory's access list without an owner".to_string(),
+        }),
+    ))
+}
+
+pub async fn get_directory_acl(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+) -> Result<Json<DirectoryAclResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let directory = storage.get_directory(&dir_id).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+    if directory.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Read).await?;
+
+    Ok(Json(directory_acl_response(&storage, &user, dir_id).await?))
+}
+
+pub async fn set_directory_acl(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((dir_id, user_id)): Path<(String, String)>,
+    Json(payload): Json<SetDirectoryAclRequest>,
+) -> Result<Json<DirectoryAclResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+    let role = Role::from_name(&payload.role).ok_or_else(|| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse {
+                error: "role must be one of: read, write, owner".to_string(),
+            }),
+        )
+    })?;
+
+    let (directory, access) = find_acl_directory(&storage, &user, &dir_id).await?;
+    if storage
+        .get_user(&user_id)
+        .await
+        .map_err(db_error)?
+        .is_none()
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "User not found".to_string(),
+            }),
+        ));
+    }
+
+    let mut remaining = storage
+        .list_directory_acl(&dir_id)
+        .await
+        .map_err(db_error)?;
+    remaining.retain(|entry| entry.user_id != user_id);
+    if role != Role::Owner {
+        ensure_acl_owner(&access, &directory, &remaining)?;
+    }
+
+    // The first entry restricts the directory, so whoever adds it keeps
+    // ownership rather than locking themselves out.
+    if let Some(Extension(caller)) = &user {
+        if !access.is_restricted(Some(&dir_id)) && caller.id != user_id {
+            storage
+                .set_directory_acl(&dir_id, &caller.id, Role::Owner)
+                .await
+                .map_err(db_error)?;
+        }
+    }
+    storage
+        .set_directory_acl(&dir_id, &user_id, role)
+        .await
+        .map_err(db_error)?;
 
+    Ok(Json(directory_acl_response(&storage, &user, dir_id).await?))
+}
+
+pub async fn delete_directory_acl(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((dir_id, user_id)): Path<(String, String)>,
+) -> Result<Json<DirectoryAclResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    let (directory, access) = find_acl_directory(&storage, &user, &dir_id).await?;
+    let mut remaining = storage
+        .list_directory_acl(&dir_id)
+        .await
+        .map_err(db_error)?;
+    remaining.retain(|entry| entry.user_id != user_id);
+    ensure_acl_owner(&access, &directory, &remaining)?;
+
+    if !storage
+        .delete_directory_acl(&dir_id, &user_id)
+        .await
+        .map_err(db_error)?
+    {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "User is not on this directory's access list".to_string(),
+            }),
+        ));
+    }
     info!(
-        "Bulk delete completed: {} files, {} directories",
-        deleted_files, deleted_directories
+        "Removed user {} from the access list of directory {}",
+        user_id, dir_id
     );
 
-    Ok(Json(BulkDeleteResponse {
+    Ok(Json(directory_acl_response(&storage, &user, dir_id).await?))
+}
+
+/// Most entries a directory may pin.
+const MAX_PINNED_ENTRIES: usize = 100;
+
+pub async fn get_directory_arrangement(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+) -> Result<Json<DirectoryArrangement>, (StatusCode, Json<ErrorResponse>)> {
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Read).await?;
+
+    let arrangement = storage
+        .get_directory_arrangement(&dir_id)
+        .await
+        .map_err(db_error)?
+        .unwrap_or(DirectoryArrangement {
+            directory_id: dir_id,
+            sort: None,
+            pinned: Vec::new(),
+            updated_at: None,
+        });
+    Ok(Json(arrangement))
+}
+
+pub async fn set_directory_arrangement(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(dir_id): Path<String>,
+    Json(payload): Json<UpdateDirectoryArrangementRequest>,
+) -> Result<Json<DirectoryArrangement>, (StatusCode, Json<ErrorResponse>)> {
+    let bad_request = |message: String| {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(ErrorResponse { error: message }),
+        )
+    };
+    let db_error = |e: sqlx::Error| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    };
+
+    ensure_writable([dir_id.as_str()])?;
+
+    if let Some(sort) = payload.sort.as_deref() {
+        if DirectorySort::from_name(sort).is_none() {
+            return Err(bad_request(format!(
+                "sort must be one of: {}",
+                DirectorySort::NAMES.join(", ")
+            )));
+        }
+    }
+    let mut pinned: Vec<String> = Vec::with_capacity(payload.pinned.len());
+    for id in payload.pinned {
+        if !pinned.contains(&id) {
+            pinned.push(id);
+        }
+    }
+    if pinned.len() > MAX_PINNED_ENTRIES {
+        return Err(bad_request(format!(
+            "At most {} entries can be pinned",
+            MAX_PINNED_ENTRIES
+        )));
+    }
+
+    if storage.get_directory(&dir_id).await.map_err(db_error)?.is_none() {
+        return Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Directory not found".to_string(),
+            }),
+        ));
+    }
+    require_role(&storage, &user, Some(&dir_id), Role::Write).await?;
+
+    // Only direct children can be pinned.
+    if !pinned.is_empty() {
+        let parent = Some(dir_id.clone());
+        let mut children: Vec<String> = storage
+            .list_files(parent.clone())
+            .await
+            .map_err(db_error)?
+            .into_iter()
+            .map(|f| f.id)
+            .collect();
+        children.extend(
+            storage
+                .list_directories(parent.clone())
+                .await
+                .map_err(db_error)?
+                .into_iter()
+                .map(|d| d.id),
+        );
+        children.extend(
+            storage
+                .list_links(parent)
+                .await
+                .map_err(db_error)?
+                .into_iter()
+                .map(|l| l.id),
+        );
+        let foreign: Vec<&str> = pinned
+            .iter()
+            .filter(|id| !children.contains(id))
+            .map(String::as_str)
+            .collect();
+        if !foreign.is_empty() {
+            return Err(bad_request(format!(
+                "Not in this directory: {}",
+                foreign.join(", ")
+            )));
+        }
+    }
+
+    let arrangement = storage
+        .set_directory_arrangement(&dir_id, payload.sort, pinned)
+        .await
+        .map_err(db_error)?;
+
+    info!(
+        "Arranged directory {}: sort {:?}, {} pinned",
+        dir_id,
+        arrangement.sort,
+        arrangement.pinned.len()
+    );
+    Ok(Json(arrangement))
+}
+
+/// Largest text accepted by the clipboard, in bytes.
+const CLIPBOARD_MAX_BYTES: usize = 64 * 1024;
+/// Longest lifetime a clipboard entry may ask for.
+const CLIPBOARD_MAX_TTL_SECS: u64 = 24 * 60 * 60;
+/// Shortest LAN token accepted, so clipboards can't be found by guessing.
+const CLIPBOARD_MIN_TOKEN_LEN: usize = 8;
+
+/// Whose clipboard to use: one shared by every device that knows the same
+/// LAN token, or without a token the signed-in user's own.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ClipboardQuery {
+    pub token: Option<String>,
+}
+
+impl ClipboardQuery {
+    fn owner(
+        &self,
+        user: &Option<Extension<AuthUser>>,
+    ) -> Result<String, (StatusCode, Json<ErrorResponse>)> {
+        match (self.token.as_deref(), user) {
+            (Some(token), _) if token.len() >= CLIPBOARD_MIN_TOKEN_LEN => {
+                Ok(format!("token:{}", token))
+            }
+            (Some(_), _) => Err((
+                StatusCode::BAD_REQUEST,
+                Json(ErrorResponse {
+                    error: format!(
+                        "token must be at least {} characters",
+                        CLIPBOARD_MIN_TOKEN_LEN
+                    ),
+                }),
+            )),
+            (None, Some(Extension(user))) => Ok(format!("user:{}", user.id)),
+            (None, None) => Err((
+                StatusCode::UNAUTHORIZED,
+                Json(ErrorResponse {
+                    error: "Log in or give a token to use the clipboard".to_string(),
+                }),
+            )),
+        }
+    }
+}
+
+// Clipboard handlers
+pub async fn get_clipboard(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<ClipboardQuery>,
+) -> Result<Json<ClipboardEntry>, (StatusCode, Json<ErrorResponse>)> {
+    let owner = query.owner(&user)?;
+    storage
+        .get_clipboard(&owner)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?
+        .map(Json)
+        .ok_or_else(|| {
+            (
+                StatusCode::NOT_FOUND,
+                Json(ErrorResponse {
+                    error: "Clipboard is empty".to_string(),
+                }),
+            )
+        })
+}
+
+pub async fn set_clipboard(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<ClipboardQuery>,
+    Json(payload): Json<SetClipboardRequest>,
+) -> Result<Json<ClipboardEntry>, (StatusCode, Json<ErrorResponse>)> {
+    let owner = query.owner(&user)?;
+    if payload.text.len() > CLIPBOARD_MAX_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            Json(ErrorResponse {
+                error: format!("Clipboard text is limited to {} bytes", CLIPBOARD_MAX_BYTES),
+            }),
+        ));
+    }
+    let ttl_secs = payload
+        .ttl_secs
+        .unwrap_or(storage.clipboard_ttl_secs())
+        .clamp(1, CLIPBOARD_MAX_TTL_SECS);
+
+    let entry = storage
+        .set_clipboard(&owner, payload.text, ttl_secs)
+        .await
+        .map_err(|e| {
+            error!("Failed to set clipboard: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(entry))
+}
+
+pub async fn clear_clipboard(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Query(query): Query<ClipboardQuery>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    let owner = query.owner(&user)?;
+    storage.clear_clipboard(&owner).await.map_err(|e| {
+        error!("Database error: {}", e);
+        (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(ErrorResponse {
+                error: format!("Database error: {}", e),
+            }),
+        )
+    })?;
+
+    Ok(Json(DeleteResponse {
         success: true,
-        deleted_files,
-        deleted_directories,
-        message: format!(
-            "Deleted {} files and {} directories",
-            deleted_files, deleted_directories
-        ),
+        message: "Clipboard cleared".to_string(),
     }))
 }
+
+// Web Push handlers
+pub async fn get_vapid_public_key(State(storage): State<FileStorage>) -> Json<VapidPublicKeyResponse> {
+    Json(VapidPublicKeyResponse {
+        public_key: storage.push().public_key(),
+    })
+}
+
+pub async fn list_push_subscriptions(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(user_id): Path<String>,
+) -> Result<Json<Vec<PushSubscription>>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let subscriptions = storage
+        .list_push_subscriptions(&user_id)
+        .await
+        .map_err(|e| {
+            error!("Database error: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Database error: {}", e),
+                }),
+            )
+        })?;
+
+    Ok(Json(subscriptions))
+}
+
+pub async fn create_push_subscription(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path(user_id): Path<String>,
+    Json(payload): Json<CreatePushSubscriptionRequest>,
+) -> Result<Json<PushSubscription>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    push::check_endpoint(&payload.endpoint)
+        .await
+        .map_err(|error| (StatusCode::BAD_REQUEST, Json(ErrorResponse { error })))?;
+
+    let subscription = storage
+        .save_push_subscription(
+            &user_id,
+            &payload.endpoint,
+            &payload.keys.p256dh,
+            &payload.keys.auth,
+        )
+        .await
+        .map_err(|e| {
+            error!("Failed to save push subscription: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to save push subscription: {}", e),
+                }),
+            )
+        })?;
+
+    info!("Push subscription {} saved for {}", subscription.id, user_id);
+    Ok(Json(subscription))
+}
+
+pub async fn delete_push_subscription(
+    State(storage): State<FileStorage>,
+    user: Option<Extension<AuthUser>>,
+    Path((user_id, id)): Path<(String, String)>,
+) -> Result<Json<DeleteResponse>, (StatusCode, Json<ErrorResponse>)> {
+    require_self(&user, &user_id)?;
+    let deleted = storage
+        .delete_push_subscription(&user_id, &id)
+        .await
+        .map_err(|e| {
+            error!("Failed to delete push subscription: {}", e);
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                Json(ErrorResponse {
+                    error: format!("Failed to delete push subscription: {}", e),
+                }),
+            )
+        })?;
+
+    if deleted {
+        Ok(Json(DeleteResponse {
+            success: true,
+            message: "Push subscription deleted successfully".to_string(),
+        }))
+    } else {
+        Err((
+            StatusCode::NOT_FOUND,
+            Json(ErrorResponse {
+                error: "Push subscription not found".to_string(),
+            }),
+        ))
+    }
+}
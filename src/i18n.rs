@@ -0,0 +1,161 @@
+use crate::config::Config;
+use axum::http::{header, HeaderMap};
+use fluent::concurrent::FluentBundle;
+use fluent::{FluentArgs, FluentResource, FluentValue};
+use std::sync::Arc;
+use tracing::warn;
+use unic_langid::LanguageIdentifier;
+
+/// Languages server-generated text is available in, with their
+/// translations. The first is the fallback for missing messages.
+const LOCALES: [(&str, &str); 4] = [
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+    ("fr", include_str!("../locales/fr.ftl")),
+    ("es", include_str!("../locales/es.ftl")),
+];
+
+pub fn is_supported(language: &str) -> bool {
+    LOCALES
+        .iter()
+        .any(|(code, _)| code.eq_ignore_ascii_case(language.trim()))
+}
+
+pub fn languages() -> Vec<&'static str> {
+    LOCALES.iter().map(|(code, _)| *code).collect()
+}
+
+/// Translations of public pages, error messages and notifications, picked
+/// per request from `Accept-Language` or else `DEFAULT_LANGUAGE`.
+#[derive(Clone)]
+pub struct Localizer {
+    bundles: Arc<Vec<FluentBundle<FluentResource>>>,
+    default: usize,
+}
+
+impl Localizer {
+    pub fn from_config(config: &Config) -> Self {
+        let bundles = LOCALES
+            .iter()
+            .map(|(code, source)| {
+                let language: LanguageIdentifier =
+                    code.parse().expect("Locale codes are valid language tags");
+                let resource = FluentResource::try_new(source.to_string()).unwrap_or_else(
+                    |(resource, errors)| {
+                        warn!("Errors in the {} translations: {:?}", code, errors);
+                        resource
+                    },
+                );
+                let mut bundle = FluentBundle::new_concurrent(vec![language]);
+                // Isolation marks would end up in plain-text emails.
+                bundle.set_use_isolating(false);
+                if let Err(errors) = bundle.add_resource(resource) {
+                    warn!("Errors in the {} translations: {:?}", code, errors);
+                }
+                bundle
+            })
+            .collect();
+        let default = LOCALES
+            .iter()
+            .position(|(code, _)| code.eq_ignore_ascii_case(config.default_language.trim()))
+            .unwrap_or(0);
+        Self {
+            bundles: Arc::new(bundles),
+            default,
+        }
+    }
+
+    /// The instance's default language, for text not sent in reply to a
+    /// request, such as emails.
+    pub fn default_locale(&self) -> Locale<'_> {
+        self.locale(self.default)
+    }
+
+    /// The best language for a request, by the weights in its
+    /// `Accept-Language` header.
+    pub fn negotiate(&self, headers: &HeaderMap) -> Locale<'_> {
+        let Some(accept) = headers
+            .get(header::ACCEPT_LANGUAGE)
+            .and_then(|v| v.to_str().ok())
+        else {
+            return self.default_locale();
+        };
+
+        let mut ranges: Vec<(&str, f32)> = accept
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let tag = parts.next()?.trim();
+                let quality = parts
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.trim().parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (!tag.is_empty() && quality > 0.0).then_some((tag, quality))
+            })
+            .collect();
+        // Stable, so equally weighted ranges keep the client's order.
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        for (tag, _) in ranges {
+            if tag == "*" {
+                return self.default_locale();
+            }
+            let primary = tag.split(['-', '_']).next().unwrap_or(tag);
+            if let Some(index) = LOCALES
+                .iter()
+                .position(|(code, _)| code.eq_ignore_ascii_case(primary))
+            {
+                return self.locale(index);
+            }
+        }
+        self.default_locale()
+    }
+
+    fn locale(&self, index: usize) -> Locale<'_> {
+        Locale {
+            language: LOCALES[index].0,
+            bundle: &self.bundles[index],
+            fallback: &self.bundles[0],
+        }
+    }
+}
+
+/// One language's translations.
+pub struct Locale<'a> {
+    language: &'static str,
+    bundle: &'a FluentBundle<FluentResource>,
+    fallback: &'a FluentBundle<FluentResource>,
+}
+
+impl Locale<'_> {
+    /// Language code, e.g. for the `lang` attribute of a page.
+    pub fn language(&self) -> &'static str {
+        self.language
+    }
+
+    pub fn text(&self, id: &str) -> String {
+        self.message(id, &[])
+    }
+
+    /// Formats message `id` with `args`, falling back to English when it
+    /// isn't translated, and to the ID itself when it doesn't exist.
+    pub fn message(&self, id: &str, args: &[(&str, FluentValue)]) -> String {
+        let mut fluent_args = FluentArgs::new();
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+        for bundle in [self.bundle, self.fallback] {
+            let Some(pattern) = bundle.get_message(id).and_then(|m| m.value()) else {
+                continue;
+            };
+            let mut errors = Vec::new();
+            let text = bundle.format_pattern(pattern, Some(&fluent_args), &mut errors);
+            if !errors.is_empty() {
+                warn!("Errors formatting message {}: {:?}", id, errors);
+            }
+            return text.into_owned();
+        }
+        warn!("Missing translation for message {}", id);
+        id.to_string()
+    }
+}
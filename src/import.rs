@@ -0,0 +1,161 @@
+use crate::storage::{FileStorage, NameTaken};
+use chrono::{DateTime, Utc};
+use std::path::{Component, Path, PathBuf};
+use tokio::fs;
+use tracing::{info, warn};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub directories_created: usize,
+    pub files_imported: usize,
+    pub files_skipped: usize,
+}
+
+/// Walks an existing folder tree and registers its directories and files in
+/// the database, leaving the bytes where they are. Files that are already
+/// registered are skipped, so running the import again only adds new files.
+pub async fn import_tree(storage: &FileStorage, root: &Path) -> Result<ImportReport, BoxError> {
+    let root = fs::canonicalize(root).await?;
+    let mut report = ImportReport::default();
+    import_into(storage, &root, None, &mut report).await?;
+
+    info!(
+        "Import finished: {} directories created, {} files imported, {} skipped",
+        report.directories_created, report.files_imported, report.files_skipped
+    );
+    Ok(report)
+}
+
+/// Imports the contents of `dir` beneath the database directory `parent_id`.
+pub async fn import_into(
+    storage: &FileStorage,
+    dir: &Path,
+    parent_id: Option<String>,
+    report: &mut ImportReport,
+) -> Result<(), BoxError> {
+    let upload_dir = fs::canonicalize(storage.upload_dir()).await.ok();
+    let mut pending: Vec<(PathBuf, Option<String>)> = vec![(dir.to_path_buf(), parent_id)];
+
+    while let Some((dir_path, parent_id)) = pending.pop() {
+        let mut entries = fs::read_dir(&dir_path).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            let file_type = entry.file_type().await?;
+            if file_type.is_symlink() || upload_dir.as_deref() == Some(path.as_path()) {
+                continue;
+            }
+
+            let name = storage
+                .name_policy()
+                .normalize(&entry.file_name().to_string_lossy());
+            if let Err(e) = storage.name_policy().validate(&name) {
+                warn!("Skipping {:?}: {}", path, e);
+                report.files_skipped += 1;
+                continue;
+            }
+
+            if file_type.is_dir() {
+                let directory = match storage
+                    .find_child_directory(parent_id.as_deref(), &name)
+                    .await?
+                {
+                    Some(existing) => existing,
+                    None => match storage.create_directory(&name, parent_id.clone()).await {
+                        Ok(created) => {
+                            report.directories_created += 1;
+                            created
+                        }
+                        Err(e) if e.is::<NameTaken>() => {
+                            warn!("Skipping {:?}: {}", path, e);
+                            report.files_skipped += 1;
+                            continue;
+                        }
+                        Err(e) => return Err(e),
+                    },
+                };
+                pending.push((path, Some(directory.id)));
+            } else if file_type.is_file() {
+                if import_file(storage, &path, &name, parent_id.clone()).await? {
+                    report.files_imported += 1;
+                } else {
+                    report.files_skipped += 1;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Registers a single file unless it is already known. Returns whether it was added.
+pub async fn import_file(
+    storage: &FileStorage,
+    path: &Path,
+    name: &str,
+    parent_id: Option<String>,
+) -> Result<bool, BoxError> {
+    if storage.find_file_by_storage_path(path).await?.is_some() {
+        return Ok(false);
+    }
+
+    let meta = fs::metadata(path).await?;
+    let modified_at = meta
+        .modified()
+        .map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now())
+        .to_rfc3339();
+    let registered = storage
+        .register_existing_file(path, name.to_string(), meta.len() as i64, modified_at, parent_id)
+        .await?;
+    if registered.is_none() {
+        warn!("Skipping {:?}: another entry there already has its name", path);
+    }
+    Ok(registered.is_some())
+}
+
+/// Names of the path components of `path` below `root`, normalized the way
+/// imported names are.
+fn relative_names(storage: &FileStorage, root: &Path, path: &Path) -> Option<Vec<String>> {
+    let relative = path.strip_prefix(root).ok()?;
+    relative
+        .components()
+        .map(|c| match c {
+            Component::Normal(name) => {
+                Some(storage.name_policy().normalize(&name.to_string_lossy()))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Returns the database directory mirroring `dir`, creating any missing
+/// directories along the way. `None` stands for the import root itself.
+pub async fn ensure_directory(
+    storage: &FileStorage,
+    root: &Path,
+    dir: &Path,
+) -> Result<Option<String>, BoxError> {
+    let names = relative_names(storage, root, dir).ok_or("Path is outside the import root")?;
+    storage.ensure_directory_path(None, &names).await
+}
+
+/// Looks up the database directory mirroring `dir` without creating anything.
+pub async fn find_directory(
+    storage: &FileStorage,
+    root: &Path,
+    dir: &Path,
+) -> Result<Option<String>, BoxError> {
+    let Some(names) = relative_names(storage, root, dir) else {
+        return Ok(None);
+    };
+    let mut parent_id: Option<String> = None;
+    for name in names {
+        match storage.find_child_directory(parent_id.as_deref(), &name).await? {
+            Some(directory) => parent_id = Some(directory.id),
+            None => return Ok(None),
+        }
+    }
+    Ok(parent_id)
+}
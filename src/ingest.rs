@@ -0,0 +1,302 @@
+use crate::checksum::ExpectedChecksum;
+use crate::config::Config;
+use crate::models::FileMetadata;
+use crate::storage::{FileStorage, NameTaken, UploadError};
+use async_compression::tokio::bufread::GzipDecoder;
+use axum::body::Bytes;
+use futures_util::{Stream, StreamExt};
+use std::io;
+use std::path::{Component, Path};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio_tar::{Archive, EntryType};
+use tokio_util::io::{ReaderStream, StreamReader};
+use tracing::warn;
+
+/// First two bytes of every gzip stream.
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// Bytes unpacked before the ratio limit applies, so small archives of
+/// very compressible files aren't refused.
+const RATIO_GRACE_BYTES: u64 = 16 * 1024 * 1024;
+/// Quarantine source of files unpacked from archives that went past their
+/// limits.
+const QUARANTINE_SOURCE: &str = "archive_limits";
+
+/// Limits on unpacking an archive, from `ARCHIVE_MAX_EXTRACTED_BYTES`,
+/// `ARCHIVE_MAX_ENTRIES`, `ARCHIVE_MAX_DEPTH` and `ARCHIVE_MAX_RATIO`, so a
+/// decompression bomb is refused before it fills the disk.
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveLimits {
+    pub max_extracted_bytes: u64,
+    pub max_entries: u64,
+    pub max_depth: usize,
+    /// Unpacked bytes per byte received, for compressed archives.
+    pub max_ratio: u64,
+}
+
+impl ArchiveLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_extracted_bytes: config.archive_max_extracted_bytes,
+            max_entries: config.archive_max_entries,
+            max_depth: config.archive_max_depth,
+            max_ratio: config.archive_max_ratio,
+        }
+    }
+
+    fn max(&self, limit: ArchiveLimit) -> u64 {
+        match limit {
+            ArchiveLimit::ExtractedSize => self.max_extracted_bytes,
+            ArchiveLimit::Entries => self.max_entries,
+            ArchiveLimit::Depth => self.max_depth as u64,
+            ArchiveLimit::Ratio => self.max_ratio,
+        }
+    }
+}
+
+/// The limit an archive went past.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveLimit {
+    ExtractedSize,
+    Entries,
+    Depth,
+    Ratio,
+}
+
+impl ArchiveLimit {
+    pub fn name(self) -> &'static str {
+        match self {
+            ArchiveLimit::ExtractedSize => "extracted_size",
+            ArchiveLimit::Entries => "entries",
+            ArchiveLimit::Depth => "depth",
+            ArchiveLimit::Ratio => "ratio",
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum IngestError {
+    /// The request body isn't a readable tar (or tar.gz) stream.
+    #[error("Invalid archive: {0}")]
+    Archive(String),
+    #[error("{0}")]
+    QuotaExceeded(String),
+    /// The archive went past one of the [`ArchiveLimits`]. Files unpacked
+    /// before that are kept, marked suspicious.
+    #[error("Archive exceeds its {} limit of {max}", limit.name())]
+    LimitExceeded {
+        limit: ArchiveLimit,
+        max: u64,
+        suspicious_file_ids: Vec<String>,
+    },
+    #[error("{0}")]
+    Storage(#[from] Box<dyn std::error::Error + Send + Sync>),
+}
+
+impl From<UploadError> for IngestError {
+    fn from(e: UploadError) -> Self {
+        match e {
+            UploadError::Body(msg) | UploadError::InvalidChunk(msg) => IngestError::Archive(msg),
+            UploadError::QuotaExceeded(msg) => IngestError::QuotaExceeded(msg),
+            UploadError::StorageFull(e) => IngestError::QuotaExceeded(e.to_string()),
+            e @ UploadError::ChecksumMismatch { .. } => IngestError::Archive(e.to_string()),
+            UploadError::Io(e) => IngestError::Storage(e.into()),
+            UploadError::Database(e) => IngestError::Storage(e.into()),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct IngestReport {
+    pub files: Vec<FileMetadata>,
+    /// Archive paths that were not unpacked, e.g. symlinks or invalid names.
+    pub skipped: Vec<String>,
+}
+
+/// Unpacks a tar or gzip-compressed tar stream beneath `parent_id` as it
+/// arrives. Each regular file goes through the normal upload path, so it is
+/// hashed, chunked and receipted like any other upload; directories named in
+/// the archive are created (or reused) along the way. Unpacking stops at
+/// the first of the storage's [`ArchiveLimits`] the archive goes past.
+/// Files are owned by `owner_id`, if given.
+pub async fn ingest_tar<S, E>(
+    storage: &FileStorage,
+    body: S,
+    parent_id: Option<String>,
+    owner_id: Option<String>,
+) -> Result<IngestReport, IngestError>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + Unpin + 'static,
+    E: std::fmt::Display,
+{
+    let received = Arc::new(AtomicU64::new(0));
+    let counter = received.clone();
+    let mut reader = BufReader::new(StreamReader::new(body.map(move |chunk| {
+        let chunk = chunk.map_err(|e| io::Error::other(e.to_string()))?;
+        counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        Ok::<_, io::Error>(chunk)
+    })));
+    let is_gzip = reader
+        .fill_buf()
+        .await
+        .map_err(|e| IngestError::Archive(e.to_string()))?
+        .starts_with(&GZIP_MAGIC);
+
+    let source: Box<dyn AsyncRead + Send + Unpin> = if is_gzip {
+        Box::new(GzipDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+    unpack(storage, Archive::new(source), parent_id, owner_id, &received).await
+}
+
+async fn unpack<R: AsyncRead + Send + Unpin>(
+    storage: &FileStorage,
+    mut archive: Archive<R>,
+    parent_id: Option<String>,
+    owner_id: Option<String>,
+    received: &AtomicU64,
+) -> Result<IngestReport, IngestError> {
+    let limits = storage.archive_limits();
+    let mut report = IngestReport::default();
+    let mut entry_count: u64 = 0;
+    let mut extracted: u64 = 0;
+    let mut entries = archive
+        .entries()
+        .map_err(|e| IngestError::Archive(e.to_string()))?;
+
+    while let Some(entry) = entries.next().await {
+        let entry = entry.map_err(|e| IngestError::Archive(e.to_string()))?;
+        let path = entry
+            .path()
+            .map_err(|e| IngestError::Archive(e.to_string()))?
+            .to_string_lossy()
+            .to_string();
+
+        entry_count += 1;
+        if entry_count > limits.max_entries {
+            return Err(limit_exceeded(storage, report, ArchiveLimit::Entries).await);
+        }
+        if Path::new(&path).components().count() > limits.max_depth {
+            return Err(limit_exceeded(storage, report, ArchiveLimit::Depth).await);
+        }
+
+        let Some(mut names) = entry_names(storage, Path::new(&path)) else {
+            report.skipped.push(path);
+            continue;
+        };
+
+        match entry.header().entry_type() {
+            EntryType::Directory => {
+                storage.ensure_directory_path(parent_id.clone(), &names).await?;
+            }
+            EntryType::Regular | EntryType::Continuous => {
+                let Some(name) = names.pop() else {
+                    report.skipped.push(path);
+                    continue;
+                };
+                // The header's size is what the entry unpacks to, so the
+                // total can be checked before anything is written.
+                let size = entry
+                    .header()
+                    .size()
+                    .map_err(|e| IngestError::Archive(e.to_string()))?;
+                if extracted.saturating_add(size) > limits.max_extracted_bytes {
+                    return Err(limit_exceeded(storage, report, ArchiveLimit::ExtractedSize).await);
+                }
+
+                let dir_id = storage.ensure_directory_path(parent_id.clone(), &names).await?;
+                let mut over_ratio = false;
+                let content = ReaderStream::new(entry).map(|chunk| {
+                    let chunk = chunk?;
+                    extracted += chunk.len() as u64;
+                    let allowed = received
+                        .load(Ordering::Relaxed)
+                        .saturating_mul(limits.max_ratio);
+                    if extracted > RATIO_GRACE_BYTES && extracted > allowed {
+                        over_ratio = true;
+                        return Err(io::Error::other("Decompression ratio limit exceeded"));
+                    }
+                    Ok(chunk)
+                });
+                let saved = match storage.save_file(&name, content, &ExpectedChecksum::None).await {
+                    Ok(saved) => saved,
+                    Err(_) if over_ratio => {
+                        return Err(limit_exceeded(storage, report, ArchiveLimit::Ratio).await);
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                let sha256 = saved.sha256.clone();
+                let mime_type = mime_guess::from_path(&name).first().map(|m| m.to_string());
+                let metadata = match storage
+                    .record_file_metadata(saved, name, mime_type, None, dir_id, owner_id.clone())
+                    .await
+                {
+                    Ok(metadata) => metadata,
+                    Err(e) if e.is::<NameTaken>() => {
+                        report.skipped.push(path);
+                        continue;
+                    }
+                    Err(e) => return Err(e.into()),
+                };
+                storage
+                    .issue_receipt(&metadata, &sha256)
+                    .await
+                    .map_err(|e| IngestError::Storage(e.into()))?;
+                report.files.push(metadata);
+            }
+            _ => report.skipped.push(path),
+        }
+    }
+
+    Ok(report)
+}
+
+/// Gives up on an archive at `limit`. The files unpacked so far came from a
+/// likely decompression bomb, so they are put in quarantine.
+async fn limit_exceeded(
+    storage: &FileStorage,
+    report: IngestReport,
+    limit: ArchiveLimit,
+) -> IngestError {
+    warn!(
+        "Archive exceeds its {} limit; quarantining {} unpacked files",
+        limit.name(),
+        report.files.len()
+    );
+    let reason = format!("Unpacked from an archive that exceeds its {} limit", limit.name());
+    for file in &report.files {
+        if let Err(e) = storage
+            .quarantine_file(&file.id, QUARANTINE_SOURCE, Some(&reason))
+            .await
+        {
+            warn!("Failed to quarantine {}: {}", file.id, e);
+        }
+    }
+    IngestError::LimitExceeded {
+        limit,
+        max: storage.archive_limits().max(limit),
+        suspicious_file_ids: report.files.into_iter().map(|file| file.id).collect(),
+    }
+}
+
+/// Splits an archive path into validated names (empty for the archive root),
+/// or `None` if any component is absolute, `..`, or not allowed by the name
+/// policy.
+fn entry_names(storage: &FileStorage, path: &Path) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => {
+                let name = storage.name_policy().normalize(&name.to_string_lossy());
+                storage.name_policy().validate(&name).ok()?;
+                names.push(name);
+            }
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(names)
+}
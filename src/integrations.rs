@@ -0,0 +1,248 @@
+use crate::config::Config;
+use crate::events::{ChangeEvent, Event, WebhookPayload};
+use crate::models::{DirectoryIntegration, Share, WebhookReplayResponse};
+use crate::push;
+use crate::storage::FileStorage;
+use chrono::{DateTime, Utc};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Url};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const KIND_SLACK: &str = "slack";
+pub const KIND_DISCORD: &str = "discord";
+pub const KIND_MATRIX: &str = "matrix";
+pub const KIND_WEBHOOK: &str = "webhook";
+pub const KINDS: [&str; 4] = [KIND_SLACK, KIND_DISCORD, KIND_MATRIX, KIND_WEBHOOK];
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Posts a chat message to every chat integration configured on a directory
+/// when a new file lands in it, and every change in it to its webhooks.
+#[derive(Clone)]
+pub struct Integrations {
+    storage: FileStorage,
+    client: Client,
+    /// Base URL for share links in messages; links are left out when unset.
+    public_url: Option<String>,
+}
+
+impl Integrations {
+    pub fn from_config(config: &Config, storage: FileStorage) -> Result<Self, BoxError> {
+        Ok(Self {
+            storage,
+            client: client()?,
+            public_url: config
+                .public_url
+                .as_ref()
+                .map(|u| u.trim_end_matches('/').to_string()),
+        })
+    }
+
+    /// Starts reacting to change events in the background.
+    pub fn spawn(&self) {
+        let integrations = self.clone();
+        let mut events = self.storage.events().subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Integrations fell behind and missed {} events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                if let Some(Some(dir_id)) = event.change.directory_id() {
+                    if let Err(e) = integrations.announce(dir_id, &event).await {
+                        error!("Failed to run directory integrations: {}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn announce(&self, dir_id: &str, event: &Event) -> Result<(), BoxError> {
+        let targets = self.storage.list_directory_integrations(dir_id).await?;
+        if targets.is_empty() {
+            return Ok(());
+        }
+
+        let message = match &event.change {
+            ChangeEvent::FileCreated { file_id, name, .. }
+                if targets.iter().any(|i| i.kind != KIND_WEBHOOK) =>
+            {
+                Some(self.new_file_message(dir_id, file_id, name).await?)
+            }
+            _ => None,
+        };
+
+        for integration in &targets {
+            let payload = match (integration.kind.as_str(), &message) {
+                (KIND_WEBHOOK, _) => serde_json::to_value(WebhookPayload::new(event.clone()))?,
+                (kind, Some(message)) => chat_message(kind, message),
+                _ => continue,
+            };
+            let outcome = send(&self.client, integration, &payload).await;
+            if let Err(e) = &outcome {
+                warn!(
+                    "Failed to post to {} integration {}: {}",
+                    integration.kind, integration.id, e
+                );
+            }
+            self.storage
+                .record_integration_delivery(
+                    &integration.id,
+                    &event.id,
+                    &payload.to_string(),
+                    outcome.err().map(|e| e.to_string()),
+                )
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn new_file_message(
+        &self,
+        dir_id: &str,
+        file_id: &str,
+        name: &str,
+    ) -> Result<String, BoxError> {
+        let dir_name = self
+            .storage
+            .get_directory(dir_id)
+            .await?
+            .map(|d| d.name)
+            .unwrap_or_else(|| dir_id.to_string());
+        let mut message = format!("New file in {}: {}", dir_name, name);
+        if let Some(base) = &self.public_url {
+            let share = self.share_for(file_id).await?;
+            message.push_str(&format!("\n{}/s/{}", base, share.token));
+        }
+        Ok(message)
+    }
+
+    /// A share of the file for everyone in the channel to open: one it
+    /// already has that hasn't expired, been taken down or got a download
+    /// limit, or else a new one.
+    async fn share_for(&self, file_id: &str) -> Result<Share, BoxError> {
+        let now = Utc::now();
+        let existing = self
+            .storage
+            .list_shares(file_id)
+            .await?
+            .into_iter()
+            .find(|share| {
+                share.disabled_at.is_none()
+                    && share.max_downloads.is_none()
+                    && share.expires_at.as_deref().is_none_or(|t| {
+                        DateTime::parse_from_rfc3339(t).is_ok_and(|t| t > now)
+                    })
+            });
+        match existing {
+            Some(share) => Ok(share),
+            None => Ok(self.storage.create_share(file_id, None, None).await?),
+        }
+    }
+}
+
+/// Sends an integration's deliveries that failed, and are still kept, once
+/// more, oldest first.
+pub async fn replay_failed(
+    storage: &FileStorage,
+    integration: &DirectoryIntegration,
+) -> Result<WebhookReplayResponse, BoxError> {
+    let client = client()?;
+    let failed = storage
+        .failed_integration_deliveries(&integration.id)
+        .await?;
+
+    let mut delivered = 0;
+    for (delivery_id, payload) in &failed {
+        let outcome = send(&client, integration, &serde_json::from_str(payload)?).await;
+        if outcome.is_ok() {
+            delivered += 1;
+        }
+        storage
+            .retried_integration_delivery(delivery_id, outcome.err().map(|e| e.to_string()))
+            .await?;
+    }
+
+    let replayed = failed.len() as u64;
+    Ok(WebhookReplayResponse {
+        replayed,
+        delivered,
+        failed: replayed - delivered,
+    })
+}
+
+/// The body of a chat message for a kind of chat integration.
+fn chat_message(kind: &str, message: &str) -> Value {
+    match kind {
+        KIND_DISCORD => json!({ "content": message }),
+        KIND_MATRIX => json!({ "msgtype": "m.text", "body": message }),
+        _ => json!({ "text": message }),
+    }
+}
+
+/// The HTTP client for integration requests. Redirects aren't followed, as
+/// they could lead past the check on the integration's URL.
+fn client() -> Result<Client, BoxError> {
+    Ok(Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(Policy::none())
+        .build()?)
+}
+
+/// Posts `payload` to an integration, once its URL still resolves to public
+/// addresses only.
+async fn send(
+    client: &Client,
+    integration: &DirectoryIntegration,
+    payload: &Value,
+) -> Result<(), BoxError> {
+    push::check_url(&integration.url).await?;
+    let request = match integration.kind.as_str() {
+        KIND_SLACK | KIND_DISCORD | KIND_WEBHOOK => client.post(&integration.url),
+        KIND_MATRIX => {
+            let room_id = integration.room_id.as_deref().ok_or("missing room_id")?;
+            let token = integration
+                .access_token
+                .as_deref()
+                .ok_or("missing access_token")?;
+            client
+                .put(matrix_send_url(&integration.url, room_id)?)
+                .bearer_auth(token)
+        }
+        other => return Err(format!("unknown integration kind {}", other).into()),
+    };
+    request.json(payload).send().await?.error_for_status()?;
+    Ok(())
+}
+
+/// The client-server API endpoint for sending a message to `room_id`, with a
+/// fresh transaction ID so retries by the homeserver are deduplicated.
+fn matrix_send_url(homeserver: &str, room_id: &str) -> Result<Url, BoxError> {
+    let mut url = Url::parse(homeserver)?;
+    let txn_id = Uuid::new_v4().to_string();
+    url.path_segments_mut()
+        .map_err(|_| "homeserver URL cannot be a base")?
+        .pop_if_empty()
+        .extend([
+            "_matrix",
+            "client",
+            "v3",
+            "rooms",
+            room_id,
+            "send",
+            "m.room.message",
+            &txn_id,
+        ]);
+    Ok(url)
+}
@@ -0,0 +1,432 @@
+use crate::config::Config;
+use crate::db::{self, DbPool, Nullable};
+use crate::tenants;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::{Any, Transaction};
+use std::collections::{HashMap, HashSet};
+
+/// The format triggers write `recorded_at` in, which sorts as text.
+pub const RECORDED_AT_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+/// What putting the metadata back to an earlier time did, or would do.
+#[derive(Debug, Default)]
+pub struct Restore {
+    /// Files and directories created since, moved to the trash.
+    pub trashed: u64,
+    /// Files and directories whose name, place, description or trash state
+    /// was put back.
+    pub reverted: u64,
+    /// Directories deleted for good since, created again.
+    pub recreated: u64,
+    /// Files deleted for good since; their content is gone with them.
+    pub lost: u64,
+    /// Files whose content was replaced since. Their metadata is put back,
+    /// the content stays as it is and can be restored from their versions.
+    pub content_changed: u64,
+    /// Tags and expiry dates put back.
+    pub tags: u64,
+    pub expirations: u64,
+}
+
+/// The columns of a file `restore_to` puts back, as the triggers record them.
+#[derive(Debug, Deserialize)]
+struct FileRow {
+    original_filename: String,
+    description: Option<String>,
+    parent_directory_id: Option<String>,
+    storage_path: String,
+    sha256: Option<String>,
+    trashed: i64,
+    deleted_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct DirectoryRow {
+    name: String,
+    parent_id: Option<String>,
+    created_at: String,
+    color: Option<String>,
+    icon: Option<String>,
+    trashed: i64,
+    deleted_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExpirationRow {
+    expires_at: String,
+}
+
+/// The first change to a row after the restore point, whose `old_row` is
+/// the row as it was then, or `None` if the row didn't exist yet.
+struct Entry {
+    table_name: String,
+    row_id: String,
+    tag: Option<String>,
+    old_row: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum JournalError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Journal entry for {table} {id} can't be read: {source}")]
+    Entry {
+        table: String,
+        id: String,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+/// Puts the metadata of files and directories back to what it was at `at`,
+/// undoing every change journaled since in one transaction. Only the first
+/// change to each row after `at` matters, since it recorded the row as it
+/// was then. The restore is journaled like any other change, so it can be
+/// undone the same way. With `apply` false the transaction is rolled back
+/// and the report tells what would have happened.
+pub async fn restore_to(
+    pool: &DbPool,
+    at: DateTime<Utc>,
+    apply: bool,
+) -> Result<Restore, JournalError> {
+    let mut tx = pool.begin().await?;
+    let rows: Vec<(String, String, Nullable<String>, Nullable<String>)> = sqlx::query_as(
+        "SELECT table_name, row_id, tag, old_row FROM metadata_journal \
+         WHERE recorded_at > $1 ORDER BY id",
+    )
+    .bind(at.format(RECORDED_AT_FORMAT).to_string())
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let mut seen = HashSet::new();
+    let mut entries = Vec::new();
+    for (table_name, row_id, Nullable(tag), Nullable(old_row)) in rows {
+        if seen.insert((table_name.clone(), row_id.clone(), tag.clone())) {
+            entries.push(Entry {
+                table_name,
+                row_id,
+                tag,
+                old_row,
+            });
+        }
+    }
+
+    let now = Utc::now().to_rfc3339();
+    let mut restore = Restore::default();
+    let mut deleted_directories = HashMap::new();
+    for entry in entries.iter().filter(|e| e.table_name == "directories") {
+        match parse::<DirectoryRow>(entry)? {
+            None => restore.trashed += trash(&mut tx, "directories", &entry.row_id, &now).await?,
+            Some(row) => {
+                let result = sqlx::query(
+                    "UPDATE directories SET name = $1, parent_id = $2, color = $3, icon = $4, \
+                     trashed = $5, deleted_at = $6, updated_at = $7, version = version + 1 \
+                     WHERE id = $8",
+                )
+                .bind(&row.name)
+                .bind(&row.parent_id)
+                .bind(&row.color)
+                .bind(&row.icon)
+                .bind(row.trashed)
+                .bind(&row.deleted_at)
+                .bind(&now)
+                .bind(&entry.row_id)
+                .execute(&mut *tx)
+                .await?;
+                if result.rows_affected() == 0 {
+                    deleted_directories.insert(entry.row_id.clone(), row);
+                } else {
+                    restore.reverted += 1;
+                }
+            }
+        }
+    }
+    restore.recreated = recreate_directories(&mut tx, deleted_directories, &now).await?;
+
+    for entry in entries.iter().filter(|e| e.table_name == "files") {
+        let Some(row) = parse::<FileRow>(entry)? else {
+            restore.trashed += trash(&mut tx, "files", &entry.row_id, &now).await?;
+            continue;
+        };
+        let current: Option<(String, Nullable<String>)> =
+            sqlx::query_as("SELECT storage_path, sha256 FROM files WHERE id = $1")
+                .bind(&entry.row_id)
+                .fetch_optional(&mut *tx)
+                .await?;
+        let Some((storage_path, Nullable(sha256))) = current else {
+            restore.lost += 1;
+            continue;
+        };
+        if storage_path != row.storage_path || sha256 != row.sha256 {
+            restore.content_changed += 1;
+        }
+        sqlx::query(
+            "UPDATE files SET original_filename = $1, description = $2, \
+             parent_directory_id = $3, trashed = $4, deleted_at = $5, version = version + 1 \
+             WHERE id = $6",
+        )
+        .bind(&row.original_filename)
+        .bind(&row.description)
+        .bind(&row.parent_directory_id)
+        .bind(row.trashed)
+        .bind(&row.deleted_at)
+        .bind(&entry.row_id)
+        .execute(&mut *tx)
+        .await?;
+        restore.reverted += 1;
+    }
+
+    for entry in entries.iter().filter(|e| e.table_name == "file_tags") {
+        let Some(tag) = &entry.tag else { continue };
+        let result = if entry.old_row.is_none() {
+            sqlx::query("DELETE FROM file_tags WHERE file_id = $1 AND tag = $2")
+                .bind(&entry.row_id)
+                .bind(tag)
+                .execute(&mut *tx)
+                .await?
+        } else {
+            sqlx::query(
+                "INSERT INTO file_tags (file_id, tag) \
+                 SELECT id, $1 FROM files WHERE id = $2 ON CONFLICT DO NOTHING",
+            )
+            .bind(tag)
+            .bind(&entry.row_id)
+            .execute(&mut *tx)
+            .await?
+        };
+        restore.tags += result.rows_affected();
+    }
+
+    for entry in entries
+        .iter()
+        .filter(|e| e.table_name == "file_expirations")
+    {
+        let result = match parse::<ExpirationRow>(entry)? {
+            None => {
+                sqlx::query("DELETE FROM file_expirations WHERE file_id = $1")
+                    .bind(&entry.row_id)
+                    .execute(&mut *tx)
+                    .await?
+            }
+            Some(row) => {
+                sqlx::query(
+                    "INSERT INTO file_expirations (file_id, expires_at) \
+                     SELECT id, $1 FROM files WHERE id = $2 \
+                     ON CONFLICT (file_id) DO UPDATE SET expires_at = excluded.expires_at",
+                )
+                .bind(&row.expires_at)
+                .bind(&entry.row_id)
+                .execute(&mut *tx)
+                .await?
+            }
+        };
+        restore.expirations += result.rows_affected();
+    }
+
+    if apply {
+        tx.commit().await?;
+    } else {
+        tx.rollback().await?;
+    }
+    Ok(restore)
+}
+
+fn parse<T: serde::de::DeserializeOwned>(entry: &Entry) -> Result<Option<T>, JournalError> {
+    entry
+        .old_row
+        .as_deref()
+        .map(serde_json::from_str)
+        .transpose()
+        .map_err(|source| JournalError::Entry {
+            table: entry.table_name.clone(),
+            id: entry.row_id.clone(),
+            source,
+        })
+}
+
+/// Moves a file or directory created after the restore point to the trash,
+/// where it can still be restored from. Returns how many were moved.
+async fn trash(
+    tx: &mut Transaction<'static, Any>,
+    table: &str,
+    id: &str,
+    now: &str,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(&format!(
+        "UPDATE {} SET trashed = 1, deleted_at = $1, version = version + 1 \
+         WHERE id = $2 AND trashed = 0",
+        table
+    ))
+    .bind(now)
+    .bind(id)
+    .execute(&mut **tx)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Creates directories deleted for good after the restore point again,
+/// parents before their children. Directories whose parent is gone and
+/// not among them are put at the root.
+async fn recreate_directories(
+    tx: &mut Transaction<'static, Any>,
+    mut pending: HashMap<String, DirectoryRow>,
+    now: &str,
+) -> Result<u64, sqlx::Error> {
+    let mut recreated = 0;
+    while !pending.is_empty() {
+        let ready: Vec<String> = pending
+            .iter()
+            .filter(|(_, row)| {
+                row.parent_id
+                    .as_ref()
+                    .is_none_or(|parent| !pending.contains_key(parent))
+            })
+            .map(|(id, _)| id.clone())
+            .collect();
+        // Whatever is left waits on itself through a loop of parents
+        let ready = if ready.is_empty() {
+            pending.keys().take(1).cloned().collect()
+        } else {
+            ready
+        };
+        for id in ready {
+            let Some(mut row) = pending.remove(&id) else {
+                continue;
+            };
+            if let Some(parent) = &row.parent_id {
+                let exists: Option<(String,)> =
+                    sqlx::query_as("SELECT id FROM directories WHERE id = $1")
+                        .bind(parent)
+                        .fetch_optional(&mut **tx)
+                        .await?;
+                if exists.is_none() {
+                    row.parent_id = None;
+                }
+            }
+            sqlx::query(
+                "INSERT INTO directories \
+                 (id, name, parent_id, created_at, updated_at, color, icon, trashed, deleted_at) \
+                 VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+            )
+            .bind(&id)
+            .bind(&row.name)
+            .bind(&row.parent_id)
+            .bind(&row.created_at)
+            .bind(now)
+            .bind(&row.color)
+            .bind(&row.icon)
+            .bind(row.trashed)
+            .bind(&row.deleted_at)
+            .execute(&mut **tx)
+            .await?;
+            recreated += 1;
+        }
+    }
+    Ok(recreated)
+}
+
+/// `fileshare_rust restore-to --timestamp <RFC 3339> [--plan]`: puts the
+/// metadata of the instance and every tenant back to what it was at the
+/// given time. Returns the process exit code.
+pub async fn run_command(config: &Config, timestamp: Option<&str>, plan_only: bool) -> i32 {
+    let Some(timestamp) = timestamp else {
+        eprintln!("Usage: fileshare_rust restore-to --timestamp <RFC 3339 time> [--plan]");
+        return 1;
+    };
+    let at = match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(at) => at.with_timezone(&Utc),
+        Err(e) => {
+            eprintln!("Invalid timestamp {:?}: {}", timestamp, e);
+            return 1;
+        }
+    };
+    let now = Utc::now();
+    if at > now {
+        eprintln!("{} is in the future", timestamp);
+        return 1;
+    }
+    let oldest = now - chrono::Duration::days(config.metadata_journal_retention_days as i64);
+    if at < oldest {
+        eprintln!(
+            "The journal only goes back {} days (METADATA_JOURNAL_RETENTION_DAYS), to {}",
+            config.metadata_journal_retention_days,
+            oldest.to_rfc3339()
+        );
+        return 1;
+    }
+
+    let specs = match tenants::parse(config) {
+        Ok(specs) => specs,
+        Err(e) => {
+            eprintln!("Invalid tenant configuration: {}", e);
+            return 1;
+        }
+    };
+    let pool = match db::init_db(&config.database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!(
+                "Cannot open {}: {}",
+                db::redacted_url(&config.database_url),
+                e
+            );
+            return 1;
+        }
+    };
+
+    let mut stores = vec![(None, pool.clone())];
+    for spec in specs {
+        match tenants::init_db(&pool, config, &spec.name).await {
+            Ok(tenant_pool) => stores.push((Some(spec.name.clone()), tenant_pool)),
+            Err(e) => {
+                eprintln!("Cannot open the database of tenant {}: {}", spec.name, e);
+                return 1;
+            }
+        }
+    }
+
+    let mut failed = false;
+    for (tenant, pool) in stores {
+        let label = match &tenant {
+            Some(name) => format!("Tenant {}", name),
+            None => "Instance".to_string(),
+        };
+        match restore_to(&pool, at, !plan_only).await {
+            Ok(restore) => {
+                println!(
+                    "{}: {} {} item(s) back, {} new item(s) to the trash, {} directory(ies) \
+                     recreated, {} tag(s) and {} expiry date(s) back",
+                    label,
+                    if plan_only { "would put" } else { "put" },
+                    restore.reverted,
+                    restore.trashed,
+                    restore.recreated,
+                    restore.tags,
+                    restore.expirations
+                );
+                if restore.lost > 0 {
+                    println!(
+                        "{}: {} file(s) were deleted for good since and can't be restored",
+                        label, restore.lost
+                    );
+                }
+                if restore.content_changed > 0 {
+                    println!(
+                        "{}: {} file(s) have new content since; restore it from their versions",
+                        label, restore.content_changed
+                    );
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", label, e);
+                failed = true;
+            }
+        }
+    }
+    if plan_only && !failed {
+        println!();
+        println!("Run without --plan to restore.");
+    }
+    i32::from(failed)
+}
@@ -0,0 +1,282 @@
+use crate::config::Config;
+use crate::db::{self, DbPool, Nullable};
+use crate::tenants;
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+
+/// Where a stored file named `name` goes: two levels of directories named
+/// after its first four characters, e.g. `uploads/ab/cd/abcd1234…`, so no
+/// directory holds more than a fraction of the files. Names are hex SHA-256
+/// hashes for deduplicated content and UUIDs otherwise.
+pub fn shard_path(upload_dir: &Path, name: &str) -> PathBuf {
+    upload_dir.join(&name[..2]).join(&name[2..4]).join(name)
+}
+
+/// Creates the shard directories a stored file goes in.
+pub async fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(dir) => fs::create_dir_all(dir).await,
+        None => Ok(()),
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LayoutError {
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+    #[error("Failed to move {path:?}: {source}")]
+    Io {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+}
+
+/// What moving files into the sharded layout did.
+#[derive(Debug, Default)]
+pub struct Relayout {
+    /// Stored files moved to the path of their hash.
+    pub moved: u64,
+    /// Stored files dropped for an existing blob with the same content.
+    pub deduplicated: u64,
+    /// Recorded files whose bytes weren't found, left as they are.
+    pub missing: u64,
+}
+
+/// A stored file directly in the upload directory, with the files using it.
+struct FlatFile {
+    file_size: i64,
+    sha256: Option<String>,
+    references: i64,
+}
+
+/// Stored files still directly in `upload_dir`, as uploads were kept before
+/// the sharded layout. Chunked files and files kept outside the upload
+/// directory aren't moved.
+async fn flat_files(
+    pool: &DbPool,
+    upload_dir: &Path,
+) -> Result<BTreeMap<String, FlatFile>, sqlx::Error> {
+    let rows: Vec<(String, i64, Nullable<String>)> = sqlx::query_as(
+        "SELECT storage_path, file_size, sha256 FROM files \
+         WHERE id NOT IN (SELECT file_id FROM file_chunks)",
+    )
+    .fetch_all(pool)
+    .await?;
+    let mut flat = BTreeMap::new();
+    for (storage_path, file_size, Nullable(sha256)) in rows {
+        if Path::new(&storage_path).parent() != Some(upload_dir) {
+            continue;
+        }
+        let file = flat.entry(storage_path).or_insert(FlatFile {
+            file_size,
+            sha256: None,
+            references: 0,
+        });
+        file.references += 1;
+        file.sha256 = file.sha256.take().or(sha256);
+    }
+    Ok(flat)
+}
+
+/// Moves the stored files of one database into the sharded layout, under
+/// their SHA-256 as blobs shared by every file with the same content.
+/// Files are linked into place before the database points at them and
+/// only removed afterwards, so an interrupted run can be started again.
+pub async fn relayout(pool: &DbPool, upload_dir: &Path) -> Result<Relayout, LayoutError> {
+    let mut report = Relayout::default();
+    for (storage_path, file) in flat_files(pool, upload_dir).await? {
+        let path = PathBuf::from(&storage_path);
+        let io_error = |source| LayoutError::Io {
+            path: path.clone(),
+            source,
+        };
+        if !fs::try_exists(&path).await.map_err(io_error)? {
+            report.missing += 1;
+            continue;
+        }
+
+        let own_blob: Option<String> =
+            sqlx::query_scalar("SELECT sha256 FROM blobs WHERE storage_path = $1")
+                .bind(&storage_path)
+                .fetch_optional(pool)
+                .await?;
+        let sha256 = match own_blob.clone().or(file.sha256) {
+            Some(sha256) => sha256,
+            None => hash_file(&path).await.map_err(io_error)?,
+        };
+        let existing: Option<String> = sqlx::query_scalar(
+            "SELECT storage_path FROM blobs WHERE sha256 = $1 AND storage_path <> $2 AND ref_count > 0",
+        )
+        .bind(&sha256)
+        .bind(&storage_path)
+        .fetch_optional(pool)
+        .await?;
+
+        let mut tx = pool.begin().await?;
+        let target = match existing {
+            Some(existing) => {
+                sqlx::query("UPDATE blobs SET ref_count = ref_count + $1 WHERE sha256 = $2")
+                    .bind(file.references)
+                    .bind(&sha256)
+                    .execute(&mut *tx)
+                    .await?;
+                report.deduplicated += 1;
+                PathBuf::from(existing)
+            }
+            None => {
+                let target = shard_path(upload_dir, &sha256);
+                link(&path, &target).await.map_err(io_error)?;
+                if own_blob.is_some() {
+                    sqlx::query("UPDATE blobs SET storage_path = $1 WHERE storage_path = $2")
+                        .bind(target.to_string_lossy().to_string())
+                        .bind(&storage_path)
+                        .execute(&mut *tx)
+                        .await?;
+                } else {
+                    sqlx::query("DELETE FROM blobs WHERE sha256 = $1")
+                        .bind(&sha256)
+                        .execute(&mut *tx)
+                        .await?;
+                    sqlx::query(
+                        "INSERT INTO blobs (sha256, storage_path, file_size, ref_count, created_at) \
+                         VALUES ($1, $2, $3, $4, $5)",
+                    )
+                    .bind(&sha256)
+                    .bind(target.to_string_lossy().to_string())
+                    .bind(file.file_size)
+                    .bind(file.references)
+                    .bind(Utc::now().to_rfc3339())
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                report.moved += 1;
+                target
+            }
+        };
+        let name = target
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_else(|| sha256.clone());
+        sqlx::query(
+            "UPDATE files SET storage_path = $1, filename = $2, sha256 = COALESCE(sha256, $3) \
+             WHERE storage_path = $4",
+        )
+        .bind(target.to_string_lossy().to_string())
+        .bind(name)
+        .bind(&sha256)
+        .bind(&storage_path)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        fs::remove_file(&path).await.map_err(io_error)?;
+    }
+    Ok(report)
+}
+
+/// Puts the bytes at `path` at `target` too, replacing whatever an earlier,
+/// interrupted run left there.
+async fn link(path: &Path, target: &Path) -> io::Result<()> {
+    create_parent(target).await?;
+    match fs::remove_file(target).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    if fs::hard_link(path, target).await.is_err() {
+        fs::copy(path, target).await?;
+    }
+    Ok(())
+}
+
+async fn hash_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buffer).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// Runs `fileshare migrate-layout`: moves the stored files of the instance
+/// and every tenant from the flat upload directory into the sharded layout,
+/// or with `plan_only` counts what would be moved. Meant to be run while
+/// the server is stopped. Returns the process exit code.
+pub async fn run_command(config: &Config, plan_only: bool) -> i32 {
+    let specs = match tenants::parse(config) {
+        Ok(specs) => specs,
+        Err(e) => {
+            eprintln!("Invalid tenant configuration: {}", e);
+            return 1;
+        }
+    };
+    let pool = match db::init_db(&config.database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!(
+                "Cannot open {}: {}",
+                db::redacted_url(&config.database_url),
+                e
+            );
+            return 1;
+        }
+    };
+
+    let mut stores = vec![(None, pool.clone(), config.upload_dir.clone())];
+    for spec in specs {
+        match tenants::init_db(&pool, config, &spec.name).await {
+            Ok(tenant_pool) => stores.push((
+                Some(spec.name.clone()),
+                tenant_pool,
+                tenants::config_for(config, &spec.name).upload_dir,
+            )),
+            Err(e) => {
+                eprintln!("Cannot open the database of tenant {}: {}", spec.name, e);
+                return 1;
+            }
+        }
+    }
+
+    let mut failed = false;
+    for (tenant, pool, upload_dir) in stores {
+        let label = match &tenant {
+            Some(name) => format!("Tenant {}", name),
+            None => "Instance".to_string(),
+        };
+        if plan_only {
+            match flat_files(&pool, &upload_dir).await {
+                Ok(flat) => println!("{}: {} stored file(s) to move", label, flat.len()),
+                Err(e) => {
+                    eprintln!("{}: cannot list stored files: {}", label, e);
+                    failed = true;
+                }
+            }
+            continue;
+        }
+        match relayout(&pool, &upload_dir).await {
+            Ok(report) => println!(
+                "{}: moved {} stored file(s), merged {} duplicate(s), {} missing",
+                label, report.moved, report.deduplicated, report.missing
+            ),
+            Err(e) => {
+                eprintln!("{}: {}", label, e);
+                failed = true;
+            }
+        }
+    }
+    if plan_only && !failed {
+        println!();
+        println!("Run without --plan to move them; stop the server first.");
+    }
+    i32::from(failed)
+}
@@ -0,0 +1,109 @@
+use crate::config::Config;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io::ErrorKind;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::net::TcpListener;
+use tracing::warn;
+
+/// Connections a listener queues before they are accepted.
+const BACKLOG: i32 = 1024;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ListenError {
+    #[error(
+        "BIND_ADDRESSES entry {0:?} is not an IP address, [IPv6 address] or either with :port"
+    )]
+    Address(String),
+    #[error("{0} is listed more than once in BIND_ADDRESSES")]
+    Duplicate(SocketAddr),
+    #[error("Failed to listen on {addr}: {source}{}", hint(.source))]
+    Bind {
+        addr: SocketAddr,
+        source: std::io::Error,
+    },
+}
+
+fn hint(e: &std::io::Error) -> &'static str {
+    match e.kind() {
+        ErrorKind::AddrInUse => " (is another server running on this port?)",
+        ErrorKind::PermissionDenied => " (ports below 1024 need extra privileges)",
+        ErrorKind::AddrNotAvailable => " (no interface of this machine has that address)",
+        _ => "",
+    }
+}
+
+/// The addresses in `BIND_ADDRESSES`, with `PORT` for entries that don't
+/// name a port. `None` when unset, for [`bind`] to pick a default.
+pub fn addresses(config: &Config) -> Result<Option<Vec<SocketAddr>>, ListenError> {
+    if config.bind_addresses.is_empty() {
+        return Ok(None);
+    }
+    let mut addrs: Vec<SocketAddr> = Vec::new();
+    for entry in &config.bind_addresses {
+        let addr = parse(entry, config.port).ok_or_else(|| ListenError::Address(entry.clone()))?;
+        if addrs.contains(&addr) {
+            return Err(ListenError::Duplicate(addr));
+        }
+        addrs.push(addr);
+    }
+    Ok(Some(addrs))
+}
+
+fn parse(entry: &str, port: u16) -> Option<SocketAddr> {
+    if let Ok(addr) = entry.parse::<SocketAddr>() {
+        return Some(addr);
+    }
+    let ip = entry
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(entry);
+    ip.parse::<IpAddr>()
+        .ok()
+        .map(|ip| SocketAddr::new(ip, port))
+}
+
+/// Opens a listener on every configured address. Without `BIND_ADDRESSES`,
+/// listens on `[::]`, which takes IPv4 connections too, falling back to
+/// `0.0.0.0` on hosts without IPv6.
+pub async fn bind(config: &Config) -> Result<Vec<TcpListener>, ListenError> {
+    let Some(addrs) = addresses(config)? else {
+        let any_v6 = SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), config.port);
+        return match listen(any_v6, false) {
+            Ok(listener) => Ok(vec![listener]),
+            Err(e) if !matches!(e.kind(), ErrorKind::AddrInUse | ErrorKind::PermissionDenied) => {
+                warn!("Can't listen on {} ({}); listening on IPv4 only", any_v6, e);
+                let any_v4 = SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), config.port);
+                let listener = listen(any_v4, false).map_err(|source| ListenError::Bind {
+                    addr: any_v4,
+                    source,
+                })?;
+                Ok(vec![listener])
+            }
+            Err(source) => Err(ListenError::Bind {
+                addr: any_v6,
+                source,
+            }),
+        };
+    };
+
+    // `[::]` also takes IPv4 connections unless told otherwise, which
+    // would clash with an IPv4 address listed beside it.
+    let has_v4 = addrs.iter().any(SocketAddr::is_ipv4);
+    addrs
+        .into_iter()
+        .map(|addr| listen(addr, has_v4).map_err(|source| ListenError::Bind { addr, source }))
+        .collect()
+}
+
+fn listen(addr: SocketAddr, only_v6: bool) -> std::io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(only_v6)?;
+    }
+    #[cfg(not(windows))]
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(BACKLOG)?;
+    TcpListener::from_std(socket.into())
+}
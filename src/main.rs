@@ -1,15 +1,75 @@
+mod access;
+mod acl;
+mod archives;
+mod audio;
+mod audit;
+mod auth;
+mod build_info;
+mod cdn;
+mod check;
+mod checksum;
+mod chunks;
+mod compare;
+mod config;
+mod convert;
+mod costs;
 mod db;
+mod encryption;
+mod events;
+mod file_io;
 mod handlers;
+mod i18n;
+mod import;
+mod integrations;
+mod ingest;
+mod journal;
+mod layout;
+mod listen;
+mod maintenance;
+mod migrate;
+mod mirror;
 mod models;
+mod mqtt;
+mod names;
+mod notifications;
+mod oidc;
+mod openapi;
+mod pages;
+mod priority;
+mod push;
+mod quota;
+mod readme;
+mod receipts;
+mod retention;
+mod scheduler;
+mod security;
+mod sorting;
 mod storage;
+mod templates;
+mod tenants;
+mod thumbnails;
+mod typegen;
+mod upload_sessions;
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring;
+mod watcher;
+mod write_queue;
+mod zip;
 
 use axum::{
     extract::DefaultBodyLimit,
-    routing::{delete, get, patch, post},
+    http::{header, HeaderName},
+    middleware,
+    routing::{delete, get, patch, post, put},
     Router,
 };
-use std::env;
-use std::path::PathBuf;
+use futures_util::future;
+use std::collections::HashMap;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 use tower_http::trace::TraceLayer;
 use tracing::info;
@@ -17,6 +77,65 @@ use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 #[tokio::main]
 async fn main() {
+    // Load environment variables
+    dotenv::dotenv().ok();
+
+    // Configuration
+    let config = config::Config::from_env();
+
+    // Subcommands run instead of the server.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        None | Some("serve") => {}
+        Some("check") => {
+            let json = args.iter().any(|a| a == "--json");
+            std::process::exit(check::run_command(&config, json).await);
+        }
+        Some("migrate") => {
+            let plan_only = args.iter().any(|a| a == "--plan");
+            std::process::exit(migrate::run_command(&config, plan_only).await);
+        }
+        Some("migrate-layout") => {
+            let plan_only = args.iter().any(|a| a == "--plan");
+            std::process::exit(layout::run_command(&config, plan_only).await);
+        }
+        Some("restore-to") => {
+            let timestamp = args
+                .iter()
+                .position(|a| a == "--timestamp")
+                .and_then(|i| args.get(i + 1))
+                .map(String::as_str);
+            let plan_only = args.iter().any(|a| a == "--plan");
+            std::process::exit(journal::run_command(&config, timestamp, plan_only).await);
+        }
+        Some("generate-types") => {
+            std::process::exit(typegen::run_command(args.get(1).map(String::as_str)));
+        }
+        Some("generate-openapi") => {
+            std::process::exit(openapi::run_command(args.get(1).map(String::as_str)));
+        }
+        Some(other) => {
+            eprintln!("Unknown command: {}", other);
+            eprintln!(
+                "Usage: fileshare_rust [serve | check [--json] | migrate [--plan] | migrate-layout [--plan] | restore-to --timestamp TIME [--plan] | generate-types [FILE] | generate-openapi [FILE]]"
+            );
+            std::process::exit(2);
+        }
+    }
+
+    // Report panics and error-level log events to Sentry when configured.
+    // Kept alive for the lifetime of the server so queued events are flushed.
+    let sentry_guard = config.sentry_dsn.as_deref().map(|dsn| {
+        sentry::init((
+            dsn,
+            sentry::ClientOptions {
+                release: sentry::release_name!(),
+                environment: config.sentry_environment.clone().map(Into::into),
+                ..Default::default()
+            },
+        ))
+    });
+
     // Initialize tracing
     tracing_subscriber::registry()
         .with(
@@ -24,72 +143,386 @@ async fn main() {
                 .unwrap_or_else(|_| "fileshare_rust=debug,tower_http=debug,axum=trace".into()),
         )
         .with(tracing_subscriber::fmt::layer())
+        .with(sentry_guard.is_some().then(sentry::integrations::tracing::layer))
         .init();
 
-    // Load environment variables
-    dotenv::dotenv().ok();
-
-    // Configuration
-    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:./files.db".to_string());
-    let upload_dir = PathBuf::from(
-        env::var("UPLOAD_DIR").unwrap_or_else(|_| "./uploads".to_string())
-    );
-    let port = env::var("PORT")
-        .unwrap_or_else(|_| "3000".to_string())
-        .parse::<u16>()
-        .expect("PORT must be a valid number");
-
     info!("Initializing file transfer service...");
-    info!("Database: {}", database_url);
-    info!("Upload directory: {:?}", upload_dir);
-    info!("Port: {}", port);
+    info!("Database: {}", db::redacted_url(&config.database_url));
+    info!("Upload directory: {:?}", config.upload_dir);
+    info!("Port: {}", config.port);
+    if sentry_guard.is_some() {
+        info!("Reporting errors to Sentry");
+    }
+    if let Some(mirror_dir) = &config.mirror_dir {
+        info!("Mirroring {:?} read-only", mirror_dir);
+    }
+    if config.windows_compatible_names {
+        info!("Windows-compatible name validation enabled");
+    }
 
     // Initialize database
-    let pool = db::init_db(&database_url)
+    let pool = db::init_db(&config.database_url)
         .await
         .expect("Failed to initialize database");
 
+    let report = check::run(&config, &pool).await;
+    report.log();
+    if !report.passed {
+        tracing::error!("Self-check failed; run `fileshare_rust check` for details");
+        std::process::exit(1);
+    }
+
     // Initialize file storage
-    let storage = storage::FileStorage::new(upload_dir, pool);
+    let signer = receipts::ReceiptSigner::from_config(&config)
+        .expect("Failed to load receipt signing key");
+    let push = push::PushSender::from_config(&config).expect("Failed to load VAPID key");
+    let tokens = auth::Tokens::from_config(&config).expect("Failed to load JWT secret");
+    let oidc = oidc::Oidc::from_config(&config).expect("Failed to set up OIDC login");
+    let cdn = cdn::Cdn::from_config(&config).expect("Invalid CDN configuration");
+    let storage = storage::FileStorage::new(
+        &config,
+        pool.clone(),
+        signer.clone(),
+        push.clone(),
+        tokens,
+        oidc,
+        cdn.clone(),
+    );
     storage.init().await.expect("Failed to initialize storage");
+    let tenant_template = templates::DirectoryTemplate::for_tenants(&config)
+        .expect("Invalid TENANT_DIRECTORY_TEMPLATE");
+    templates::set_up_new_storage(&storage, &tenant_template)
+        .await
+        .expect("Failed to create template directories");
+
+    if let Some(import_dir) = &config.import_dir {
+        info!("Importing existing files from {:?}", import_dir);
+        import::import_tree(&storage, import_dir)
+            .await
+            .expect("Failed to import existing files");
+    }
+
+    // Kept alive for the lifetime of the server.
+    let _watchers = if config.watch_external_changes {
+        watcher::spawn(storage.clone(), config.import_dir.as_deref())
+            .expect("Failed to watch external directories")
+    } else {
+        Vec::new()
+    };
+
+    spawn_background(&config, &storage);
+
+    let routes = routes();
+    let mut tenant_routes = HashMap::new();
+    for spec in tenants::parse(&config).expect("Invalid tenant configuration") {
+        let tenant_config = tenants::config_for(&config, &spec.name);
+        let pool = tenants::init_db(&pool, &config, &spec.name)
+            .await
+            .expect("Failed to initialize tenant database");
+        let tokens = auth::Tokens::from_config(&tenant_config)
+            .expect("Failed to load JWT secret")
+            .for_tenant(&spec.name);
+        let tenant_storage = storage::FileStorage::new(
+            &tenant_config,
+            pool,
+            signer.clone(),
+            push.clone(),
+            tokens,
+            None,
+            cdn.clone(),
+        );
+        tenant_storage
+            .init()
+            .await
+            .expect("Failed to initialize tenant storage");
+        templates::set_up_new_storage(&tenant_storage, &tenant_template)
+            .await
+            .expect("Failed to create template directories");
+        spawn_background(&tenant_config, &tenant_storage);
+        info!("Serving tenant {}", spec.name);
+        let router = router(&routes, tenant_storage.clone());
+        tenant_routes.insert(
+            spec.name,
+            tenants::Tenant::new(tenant_storage, router, spec.api_key),
+        );
+    }
 
     // Configure CORS for React frontend
     let cors = CorsLayer::new()
         .allow_origin(Any)
         .allow_methods(Any)
-        .allow_headers(Any);
+        .allow_headers(Any)
+        .expose_headers([
+            header::ETAG,
+            header::ACCEPT_RANGES,
+            header::CONTENT_RANGE,
+            HeaderName::from_static(quota::WARNING_HEADER),
+            HeaderName::from_static(checksum::HEADER),
+        ]);
 
     // Build router
+    let default = tenants::Tenant::new(storage.clone(), router(&routes, storage), None);
+    let tenants = tenants::Tenants::new(&config, default, tenant_routes);
     let app = Router::new()
+        .fallback(tenants::route)
+        .layer(middleware::from_fn_with_state(
+            priority::Budgets::from_config(&config),
+            priority::schedule,
+        ))
+        .layer(DefaultBodyLimit::disable())
+        .layer(cors)
+        .layer(TraceLayer::new_for_http())
+        .with_state(Arc::new(tenants));
+
+    let listeners = listen::bind(&config).await.unwrap_or_else(|e| {
+        tracing::error!("{}", e);
+        std::process::exit(1);
+    });
+
+    info!("File transfer service is ready!");
+    let servers = listeners.into_iter().map(|listener| {
+        if let Ok(addr) = listener.local_addr() {
+            info!("API available at http://{}", addr);
+        }
+        axum::serve(listener, app.clone().into_make_service_with_connect_info::<SocketAddr>())
+            .into_future()
+    });
+    future::try_join_all(servers).await.expect("Server failed to start");
+}
+
+/// Every route of the API, for each tenant's storage to be served with.
+fn routes() -> Router<storage::FileStorage> {
+    Router::new()
         .route("/health", get(handlers::health_check))
+        .route("/api/version", get(handlers::get_version))
+        .route("/api/capabilities", get(handlers::get_capabilities))
+        .route("/api/auth/register", post(handlers::register))
+        .route("/api/auth/login", post(handlers::login))
+        .route("/api/auth/me", get(handlers::current_user))
+        .route("/auth/oidc/login", get(handlers::oidc_login))
+        .route("/auth/oidc/callback", get(handlers::oidc_callback))
+        .route("/api/branding", get(handlers::get_branding))
         .route("/api/files", get(handlers::list_files))
         .route("/api/files", post(handlers::upload_file))
         .route("/api/files/recent", get(handlers::list_recent_files))
+        .route("/api/search", get(handlers::search_files))
+        .route("/api/compare", post(handlers::compare_directories))
         .route("/api/files/:id", get(handlers::get_file_info))
         .route("/api/files/:id/download", get(handlers::download_file))
+        .route("/api/files/:id/segments", get(handlers::get_file_segments))
+        .route("/api/files/:id/receipt", get(handlers::get_file_receipt))
+        .route("/api/files/:id/access-log", get(handlers::get_file_access_log))
+        .route("/api/files/:id/audio", get(handlers::get_audio_metadata))
+        .route("/api/files/:id/waveform", get(handlers::get_waveform))
+        .route("/api/files/:id/thumbnail", get(handlers::get_file_thumbnail))
+        .route("/api/files/:id/share", post(handlers::share_file))
+        .route("/api/files/:id/shares", get(handlers::list_file_shares))
+        .route("/api/files/:id/attachments", post(handlers::create_file_attachment))
+        .route(
+            "/api/files/:id/attachments/:attachment_id",
+            delete(handlers::delete_file_attachment),
+        )
+        .route(
+            "/api/files/:id/versions",
+            get(handlers::list_file_versions).post(handlers::upload_file_version),
+        )
+        .route(
+            "/api/files/:id/versions/:version_id/download",
+            get(handlers::download_file_version),
+        )
+        .route(
+            "/api/files/:id/versions/:version_id/restore",
+            post(handlers::restore_file_version),
+        )
         .route("/api/files/:id", delete(handlers::delete_file))
-        .route("/api/files/:id", patch(handlers::move_file))
+        .route("/api/files/:id", patch(handlers::update_file))
+        .route("/api/files/:id/move", post(handlers::move_file))
+        .route("/api/uploads", post(handlers::create_upload_session))
+        .route("/api/uploads/:id", get(handlers::get_upload_session))
+        .route("/api/uploads/:id", delete(handlers::delete_upload_session))
+        .route("/api/uploads/:id/chunks/:index", put(handlers::upload_session_chunk))
+        .route("/api/uploads/:id/complete", post(handlers::complete_upload_session))
         .route("/api/directories", post(handlers::create_directory))
         .route("/api/directories/:id", get(handlers::get_directory_info))
         .route("/api/directories/:id", delete(handlers::delete_directory))
         .route("/api/directories/:id", patch(handlers::move_directory))
+        .route("/api/directories/:id/move", post(handlers::move_directory_to))
+        .route("/api/directories/:id/download", get(handlers::download_directory))
+        .route("/api/directories/:id/archive", post(handlers::create_archive_job))
+        .route("/api/archives/:id", get(handlers::get_archive_job))
+        .route("/api/archives/:id", delete(handlers::delete_archive_job))
+        .route("/api/directories/:id/share", post(handlers::share_directory))
+        .route("/api/directories/:id/upload-links", post(handlers::create_upload_link))
+        .route("/api/upload-links/:id/submissions", get(handlers::list_submissions))
+        .route("/api/directories/:id/drop-links", get(handlers::list_drop_links))
+        .route("/api/directories/:id/drop-links", post(handlers::create_drop_link))
+        .route(
+            "/api/directories/:id/arrangement",
+            get(handlers::get_directory_arrangement),
+        )
+        .route(
+            "/api/directories/:id/arrangement",
+            put(handlers::set_directory_arrangement),
+        )
+        .route(
+            "/api/directories/:id/integrations",
+            get(handlers::list_directory_integrations),
+        )
+        .route(
+            "/api/directories/:id/integrations",
+            post(handlers::create_directory_integration),
+        )
+        .route(
+            "/api/directories/:id/integrations/:integration_id",
+            delete(handlers::delete_directory_integration),
+        )
+        .route("/api/directories/:id/acl", get(handlers::get_directory_acl))
+        .route(
+            "/api/directories/:id/acl/:user_id",
+            put(handlers::set_directory_acl),
+        )
+        .route(
+            "/api/directories/:id/acl/:user_id",
+            delete(handlers::delete_directory_acl),
+        )
+        .route("/api/links", post(handlers::create_link))
+        .route("/api/links/:id", get(handlers::get_link_info))
+        .route("/api/links/:id", delete(handlers::delete_link))
+        .route("/api/links/:id/open", get(handlers::open_link))
+        .route("/api/ingest/tar", post(handlers::ingest_tar))
+        .route("/api/quick", post(handlers::quick_upload))
+        .route("/s/:token", get(handlers::open_share))
+        .route("/s/:token/report", post(handlers::report_share))
+        .route("/cdn/:token", get(handlers::open_cdn_origin))
+        .route("/z/:ticket", get(handlers::download_archive))
+        .route("/u/:token", get(handlers::open_upload_link))
+        .route("/u/:token", post(handlers::submit_upload))
+        .route("/drop/:token", post(handlers::drop_files))
+        .route("/api/shares/bundles", post(handlers::share_bundle))
+        .route("/api/shares/:id", delete(handlers::revoke_share))
+        .route("/api/shares/:id/terms", put(handlers::set_share_terms))
+        .route("/api/integrations/sharex", get(handlers::sharex_uploader))
+        .route(
+            "/api/users/:user_id/notification-preferences",
+            get(handlers::get_notification_preferences),
+        )
+        .route(
+            "/api/users/:user_id/notification-preferences",
+            put(handlers::update_notification_preferences),
+        )
+        .route("/api/users/:user_id/watched-folders", get(handlers::list_watched_folders))
+        .route("/api/users/:user_id/watched-folders", post(handlers::watch_folder))
+        .route(
+            "/api/users/:user_id/watched-folders/:directory_id",
+            delete(handlers::unwatch_folder),
+        )
+        .route("/api/clipboard", get(handlers::get_clipboard))
+        .route("/api/clipboard", put(handlers::set_clipboard))
+        .route("/api/clipboard", delete(handlers::clear_clipboard))
+        .route("/api/push/public-key", get(handlers::get_vapid_public_key))
+        .route(
+            "/api/users/:user_id/push-subscriptions",
+            get(handlers::list_push_subscriptions),
+        )
+        .route(
+            "/api/users/:user_id/push-subscriptions",
+            post(handlers::create_push_subscription),
+        )
+        .route(
+            "/api/users/:user_id/push-subscriptions/:id",
+            delete(handlers::delete_push_subscription),
+        )
+        .route("/api/trash", get(handlers::list_trash))
+        .route("/api/trash/:id/restore", post(handlers::restore_from_trash))
         .route("/api/bulk-delete", post(handlers::bulk_delete))
-        .layer(DefaultBodyLimit::disable())
-        .layer(cors)
-        .layer(TraceLayer::new_for_http())
-        .with_state(storage);
+        .route("/api/bulk-move", post(handlers::bulk_move))
+        .route("/api/bulk-copy", post(handlers::bulk_copy))
+        .route("/api/bulk-update", post(handlers::bulk_update))
+        .route("/api/batch", post(handlers::apply_batch))
+        .route("/api/events", get(handlers::stream_events))
+        .merge(admin_routes())
+}
 
-    let addr = format!("[::]:{}", port);
-    info!("Server starting on {}", addr);
+/// The admin and policy endpoints, for users listed in `ADMIN_USERS` only.
+fn admin_routes() -> Router<storage::FileStorage> {
+    Router::new()
+        .route("/api/retention-policies", get(handlers::list_retention_policies))
+        .route("/api/retention-policies", post(handlers::create_retention_policy))
+        .route("/api/retention-policies/:id", delete(handlers::delete_retention_policy))
+        .route("/api/thumbnail-policies", get(handlers::list_thumbnail_policies))
+        .route("/api/thumbnail-policies", post(handlers::create_thumbnail_policy))
+        .route("/api/thumbnail-policies/:id", delete(handlers::delete_thumbnail_policy))
+        .route("/api/admin/usage-tree", get(handlers::usage_tree))
+        .route("/api/admin/quota", get(handlers::get_quota))
+        .route("/api/admin/write-queue", get(handlers::get_write_queue))
+        .route("/api/admin/upload-cleanup", get(handlers::get_upload_cleanup))
+        .route("/api/admin/database", get(handlers::get_database_stats))
+        .route("/api/admin/database/maintenance", post(handlers::run_database_maintenance))
+        .route("/api/admin/disk-benchmark", post(handlers::disk_benchmark))
+        .route("/api/admin/cost-report", get(handlers::cost_report))
+        .route("/api/admin/users/:user_id/export", post(handlers::export_user_data))
+        .route("/api/admin/users/:user_id/erase", post(handlers::erase_user_data))
+        .route("/api/admin/reports/:report", get(handlers::file_report))
+        .route("/api/admin/quarantine", get(handlers::list_quarantine))
+        .route("/api/admin/quarantine", post(handlers::quarantine_file))
+        .route("/api/admin/quarantine/:file_id", get(handlers::get_quarantined_file))
+        .route(
+            "/api/admin/quarantine/:file_id/content",
+            get(handlers::download_quarantined_file),
+        )
+        .route(
+            "/api/admin/quarantine/:file_id/release",
+            post(handlers::release_quarantined_file),
+        )
+        .route(
+            "/api/admin/quarantine/:file_id/purge",
+            post(handlers::purge_quarantined_file),
+        )
+        .route("/api/admin/quarantine-log", get(handlers::quarantine_log))
+        .route("/api/admin/moderation", get(handlers::list_share_reports))
+        .route("/api/admin/moderation/:id/resolve", post(handlers::resolve_share_report))
+        .route("/api/admin/webhooks/:id/replay", post(handlers::replay_webhook))
+        .route_layer(middleware::from_fn(auth::require_admin))
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr)
-        .await
-        .expect("Failed to bind to address");
+/// The API serving one tenant's storage. Its middleware wraps the router
+/// as a whole: layering every route of it anew for each tenant is slow.
+fn router(routes: &Router<storage::FileStorage>, storage: storage::FileStorage) -> Router {
+    let api = ServiceBuilder::new()
+        .layer(middleware::from_fn_with_state(storage.clone(), security::headers))
+        .layer(middleware::from_fn_with_state(
+            storage.clone(),
+            quota::warning_header,
+        ))
+        .layer(middleware::from_fn_with_state(storage.clone(), auth::authenticate))
+        .service(routes.clone().with_state(storage));
+    Router::new().fallback_service(api)
+}
 
-    info!("File transfer service is ready!");
-    info!("API available at http://{}", addr);
+/// Starts the background work of one tenant's storage: notifications,
+/// integrations, audio analysis, event and audit export, and the scheduler.
+fn spawn_background(config: &config::Config, storage: &storage::FileStorage) {
+    let notifier = notifications::Notifier::from_config(config, storage.clone())
+        .expect("Failed to configure notifications");
+    notifier.spawn();
 
-    axum::serve(listener, app)
-        .await
-        .expect("Server failed to start");
+    integrations::Integrations::from_config(config, storage.clone())
+        .expect("Failed to configure directory integrations")
+        .spawn();
+
+    audio::spawn(storage.clone());
+
+    archives::spawn(storage.clone());
+
+    thumbnails::spawn(storage.clone());
+
+    mqtt::spawn(config, storage.events()).expect("Invalid MQTT_URL");
+
+    audit::spawn(config, storage.audit()).expect("Invalid audit export configuration");
+
+    scheduler::spawn(
+        storage.clone(),
+        notifier,
+        Duration::from_secs(config.scheduler_interval_secs.max(1)),
+    );
 }
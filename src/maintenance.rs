@@ -0,0 +1,257 @@
+use crate::config::Config;
+use crate::db::{Backend, DbPool};
+use crate::models::{DatabaseMaintenance, DatabaseStats};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
+use sqlx::sqlite::SqliteConnectOptions;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tracing::info;
+
+/// Share of a SQLite file that has to be free pages before a scheduled run
+/// rewrites it with VACUUM, which needs as much free disk as the file takes.
+const VACUUM_MIN_FREE_RATIO: f64 = 0.1;
+
+/// The time of day, in UTC, in which scheduled maintenance may run, as
+/// `HH:MM-HH:MM`. It may span midnight, as in `23:00-01:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MaintenanceWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl MaintenanceWindow {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let invalid = || format!("expected HH:MM-HH:MM, got {}", value);
+        let (start, end) = value.split_once('-').ok_or_else(invalid)?;
+        let time = |t: &str| NaiveTime::parse_from_str(t.trim(), "%H:%M").map_err(|_| invalid());
+        let window = Self {
+            start: time(start)?,
+            end: time(end)?,
+        };
+        if window.start == window.end {
+            return Err(format!("{} is empty", value));
+        }
+        Ok(window)
+    }
+
+    pub fn length(&self) -> Duration {
+        let length = self.end - self.start;
+        if length < Duration::zero() {
+            length + Duration::days(1)
+        } else {
+            length
+        }
+    }
+
+    /// When the window containing `now` opened, or `None` outside it.
+    fn opened_at(&self, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let today = now.date_naive().and_time(self.start).and_utc();
+        let opened = if today <= now {
+            today
+        } else {
+            today - Duration::days(1)
+        };
+        (now < opened + self.length()).then_some(opened)
+    }
+}
+
+impl std::fmt::Display for MaintenanceWindow {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
+/// Keeps the database compact: checkpoints SQLite's write-ahead log on
+/// every scheduler run, and once per maintenance window runs `ANALYZE` and,
+/// when enough of the file is free pages, `VACUUM`. On PostgreSQL it runs
+/// `VACUUM (ANALYZE)` in the window instead, alongside autovacuum.
+#[derive(Debug, Clone)]
+pub struct DbMaintenance {
+    backend: Backend,
+    /// The SQLite database file; `None` for in-memory and PostgreSQL databases.
+    sqlite_path: Option<PathBuf>,
+    window: Option<MaintenanceWindow>,
+    last_run: Arc<Mutex<Option<DatabaseMaintenance>>>,
+    running: Arc<tokio::sync::Mutex<()>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum MaintenanceError {
+    #[error("Database maintenance is already running")]
+    AlreadyRunning,
+    #[error(transparent)]
+    Database(#[from] sqlx::Error),
+}
+
+impl DbMaintenance {
+    pub fn from_config(config: &Config, backend: Backend) -> Self {
+        let sqlite_path = match backend {
+            Backend::Sqlite => SqliteConnectOptions::from_str(&config.database_url)
+                .ok()
+                .map(|options| options.get_filename().to_path_buf())
+                .filter(|path| path.as_os_str() != ":memory:"),
+            Backend::Postgres => None,
+        };
+        Self {
+            backend,
+            sqlite_path,
+            window: config
+                .db_maintenance_window
+                .as_deref()
+                .and_then(|window| MaintenanceWindow::parse(window).ok()),
+            last_run: Arc::default(),
+            running: Arc::default(),
+        }
+    }
+
+    pub fn last_run(&self) -> Option<DatabaseMaintenance> {
+        self.last_run.lock().unwrap().clone()
+    }
+
+    /// Whether `now` is in the maintenance window and this window hasn't
+    /// had its run yet.
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        let Some(opened) = self.window.and_then(|window| window.opened_at(now)) else {
+            return false;
+        };
+        let last_run = self.last_run.lock().unwrap();
+        !last_run.as_ref().is_some_and(|run| {
+            DateTime::parse_from_rfc3339(&run.started_at).is_ok_and(|started| started >= opened)
+        })
+    }
+
+    /// Moves the pages in SQLite's write-ahead log back into the database
+    /// file and truncates the log, which otherwise only shrinks when no
+    /// reader is active. Nothing to do elsewhere.
+    pub async fn checkpoint(&self, pool: &DbPool) -> Result<(), sqlx::Error> {
+        if self.backend == Backend::Sqlite {
+            sqlx::query("PRAGMA wal_checkpoint(TRUNCATE)")
+                .fetch_optional(pool)
+                .await?;
+        }
+        Ok(())
+    }
+
+    /// Runs maintenance now. Scheduled runs only VACUUM a SQLite file with
+    /// enough free pages to be worth rewriting; `force_vacuum` always does.
+    pub async fn run(
+        &self,
+        pool: &DbPool,
+        force_vacuum: bool,
+    ) -> Result<DatabaseMaintenance, MaintenanceError> {
+        let _running = self
+            .running
+            .try_lock()
+            .map_err(|_| MaintenanceError::AlreadyRunning)?;
+        let started_at = Utc::now();
+        let started = Instant::now();
+        let before = self.stats(pool).await?;
+        let mut steps = Vec::new();
+
+        match self.backend {
+            Backend::Sqlite => {
+                self.checkpoint(pool).await?;
+                steps.push("wal_checkpoint".to_string());
+                if force_vacuum || before.fragmentation >= VACUUM_MIN_FREE_RATIO {
+                    sqlx::raw_sql("VACUUM").execute(pool).await?;
+                    steps.push("vacuum".to_string());
+                }
+                sqlx::raw_sql("ANALYZE").execute(pool).await?;
+                steps.push("analyze".to_string());
+                // Compacts the log VACUUM wrote.
+                self.checkpoint(pool).await?;
+            }
+            Backend::Postgres => {
+                sqlx::raw_sql("VACUUM (ANALYZE)").execute(pool).await?;
+                steps.push("vacuum".to_string());
+                steps.push("analyze".to_string());
+            }
+        }
+
+        let after = self.stats(pool).await?;
+        let run = DatabaseMaintenance {
+            started_at: started_at.to_rfc3339(),
+            duration_secs: started.elapsed().as_secs_f64(),
+            steps,
+            size_before: before.size_bytes + before.wal_bytes.unwrap_or(0),
+            size_after: after.size_bytes + after.wal_bytes.unwrap_or(0),
+        };
+        info!(
+            "Database maintenance ({}) took {:.1}s; {} bytes before, {} after",
+            run.steps.join(", "),
+            run.duration_secs,
+            run.size_before,
+            run.size_after
+        );
+        *self.last_run.lock().unwrap() = Some(run.clone());
+        Ok(run)
+    }
+
+    /// How large the database is and how much of it is dead space.
+    pub async fn stats(&self, pool: &DbPool) -> Result<DatabaseStats, sqlx::Error> {
+        let mut stats = DatabaseStats {
+            backend: self.backend.name().to_string(),
+            size_bytes: 0,
+            free_bytes: None,
+            wal_bytes: None,
+            dead_rows: None,
+            fragmentation: 0.0,
+            journal_mode: None,
+            maintenance_window: self.window.map(|window| window.to_string()),
+            last_maintenance: self.last_run(),
+        };
+        match self.backend {
+            Backend::Sqlite => {
+                let page_size: i64 = sqlx::query_scalar("PRAGMA page_size")
+                    .fetch_one(pool)
+                    .await?;
+                let page_count: i64 = sqlx::query_scalar("PRAGMA page_count")
+                    .fetch_one(pool)
+                    .await?;
+                let free_pages: i64 = sqlx::query_scalar("PRAGMA freelist_count")
+                    .fetch_one(pool)
+                    .await?;
+                stats.size_bytes = (page_size * page_count) as u64;
+                stats.free_bytes = Some((page_size * free_pages) as u64);
+                stats.fragmentation = free_pages as f64 / page_count.max(1) as f64;
+                stats.journal_mode = Some(
+                    sqlx::query_scalar("PRAGMA journal_mode")
+                        .fetch_one(pool)
+                        .await?,
+                );
+                if let Some(path) = &self.sqlite_path {
+                    let mut wal = path.clone().into_os_string();
+                    wal.push("-wal");
+                    stats.wal_bytes = Some(
+                        tokio::fs::metadata(PathBuf::from(wal))
+                            .await
+                            .map_or(0, |m| m.len()),
+                    );
+                }
+            }
+            Backend::Postgres => {
+                let size: i64 = sqlx::query_scalar("SELECT pg_database_size(current_database())")
+                    .fetch_one(pool)
+                    .await?;
+                let (live, dead): (i64, i64) = sqlx::query_as(
+                    "SELECT CAST(COALESCE(SUM(n_live_tup), 0) AS BIGINT), \
+                     CAST(COALESCE(SUM(n_dead_tup), 0) AS BIGINT) FROM pg_stat_user_tables \
+                     WHERE schemaname = current_schema()",
+                )
+                .fetch_one(pool)
+                .await?;
+                stats.size_bytes = size as u64;
+                stats.dead_rows = Some(dead as u64);
+                stats.fragmentation = dead as f64 / (live + dead).max(1) as f64;
+            }
+        }
+        Ok(stats)
+    }
+}
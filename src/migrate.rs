@@ -0,0 +1,163 @@
+use crate::config::Config;
+use crate::db::{self, DbPool, Migration};
+use serde::Serialize;
+
+/// A table a pending migration will touch, with its current size.
+#[derive(Debug, Serialize)]
+pub struct AffectedTable {
+    pub table: String,
+    /// What the migration does to it, e.g. `create_table` or `alter_table`.
+    pub operation: &'static str,
+    /// Rows currently in the table, or `None` if it doesn't exist yet.
+    pub rows: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PlannedMigration {
+    pub version: i64,
+    pub name: &'static str,
+    pub affected: Vec<AffectedTable>,
+}
+
+/// Runs `fileshare migrate`: prints the pending migrations and, unless
+/// `plan_only`, applies them after backing up the database. Returns the
+/// process exit code.
+pub async fn run_command(config: &Config, plan_only: bool) -> i32 {
+    let pool = match db::connect(&config.database_url).await {
+        Ok(pool) => pool,
+        Err(e) => {
+            eprintln!("Cannot open {}: {}", db::redacted_url(&config.database_url), e);
+            return 1;
+        }
+    };
+    let planned = match plan(&pool, &config.database_url).await {
+        Ok(planned) => planned,
+        Err(e) => {
+            eprintln!("Cannot read the migration status: {}", e);
+            return 1;
+        }
+    };
+
+    if planned.is_empty() {
+        println!("No pending migrations");
+        return 0;
+    }
+    println!("{} pending migration(s):", planned.len());
+    for migration in &planned {
+        println!("  {:03} {}", migration.version, migration.name);
+        for table in &migration.affected {
+            let rows = match table.rows {
+                Some(rows) => format!("{} existing rows", rows),
+                None => "new table".to_string(),
+            };
+            println!("        {:<14} {:<36} {}", table.operation, table.table, rows);
+        }
+    }
+
+    if plan_only {
+        println!();
+        match db::Backend::from_url(&config.database_url) {
+            Some(db::Backend::Postgres) => {
+                println!("Run without --plan to apply; back up the database with pg_dump first.")
+            }
+            _ => println!("Run without --plan to apply; the database is backed up first."),
+        }
+        return 0;
+    }
+
+    match db::migrate(&pool, &config.database_url).await {
+        Ok(backup) => {
+            if let Some(path) = backup {
+                println!("Backed up the database to {:?}", path);
+            }
+            println!("Applied {} migration(s)", planned.len());
+            0
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            1
+        }
+    }
+}
+
+/// Pending migrations with the tables each one touches and how many rows
+/// those tables hold now, as a rough measure of how long it will take.
+pub async fn plan(pool: &DbPool, database_url: &str) -> Result<Vec<PlannedMigration>, sqlx::Error> {
+    let mut planned = Vec::new();
+    for migration in db::pending_migrations(pool).await? {
+        let mut affected = Vec::new();
+        for (operation, table) in statements(migration) {
+            affected.push(AffectedTable {
+                rows: row_count(pool, database_url, &table).await?,
+                table,
+                operation,
+            });
+        }
+        planned.push(PlannedMigration {
+            version: migration.version,
+            name: migration.name,
+            affected,
+        });
+    }
+    Ok(planned)
+}
+
+async fn row_count(
+    pool: &DbPool,
+    database_url: &str,
+    table: &str,
+) -> Result<Option<i64>, sqlx::Error> {
+    if !db::table_exists(pool, database_url, table).await? {
+        return Ok(None);
+    }
+    // The name was just confirmed to be an existing table.
+    let count = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM \"{}\"", table))
+        .fetch_one(pool)
+        .await?;
+    Ok(Some(count))
+}
+
+/// The operation and target table of each statement in a migration.
+fn statements(migration: &Migration) -> Vec<(&'static str, String)> {
+    let without_comments: String = migration
+        .sql
+        .lines()
+        .map(|line| line.split("--").next().unwrap_or(""))
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    without_comments
+        .split(';')
+        .filter_map(|statement| {
+            let words: Vec<&str> = statement.split_whitespace().collect();
+            let upper: Vec<String> = words.iter().map(|w| w.to_ascii_uppercase()).collect();
+            let keyword = |i: usize| upper.get(i).map(String::as_str);
+            let name_after = |i: usize| -> Option<String> {
+                let mut i = i;
+                if keyword(i) == Some("IF") {
+                    i += if keyword(i + 1) == Some("NOT") { 3 } else { 2 };
+                }
+                let name = words.get(i)?.split('(').next()?;
+                Some(name.trim_matches(|c| c == '"' || c == '`').to_string())
+            };
+
+            match (keyword(0)?, keyword(1)) {
+                ("CREATE", Some("TABLE")) => Some(("create_table", name_after(2)?)),
+                ("CREATE", Some("VIRTUAL")) => Some(("create_table", name_after(3)?)),
+                ("CREATE", Some("INDEX" | "UNIQUE")) => {
+                    let on = upper.iter().position(|w| w == "ON")?;
+                    Some(("create_index", name_after(on + 1)?))
+                }
+                ("ALTER", Some("TABLE")) => Some(("alter_table", name_after(2)?)),
+                ("DROP", Some("TABLE")) => Some(("drop_table", name_after(2)?)),
+                ("UPDATE", _) => Some(("update", name_after(1)?)),
+                ("DELETE", Some("FROM")) => Some(("delete", name_after(2)?)),
+                ("INSERT", _) => {
+                    let into = upper.iter().position(|w| w == "INTO")?;
+                    Some(("insert", name_after(into + 1)?))
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
@@ -0,0 +1,195 @@
+use crate::models::{Directory, FileMetadata};
+use chrono::{DateTime, Utc};
+use std::fs::Metadata;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+use tokio::fs;
+
+/// Prefix of every file and directory ID served from the mirror.
+pub const ID_PREFIX: &str = "mirror-";
+
+pub const READ_ONLY_MESSAGE: &str = "Mirrored files and directories are read-only";
+
+pub fn is_mirror_id(id: &str) -> bool {
+    id.starts_with(ID_PREFIX)
+}
+
+/// A host directory exposed read-only through the regular list/download
+/// APIs. Nothing is stored in the database: metadata comes from `stat`, and
+/// IDs encode the path relative to the mirror root.
+#[derive(Debug, Clone)]
+pub struct Mirror {
+    root: PathBuf,
+    name: String,
+}
+
+impl Mirror {
+    pub fn new(root: PathBuf, name: Option<String>) -> Self {
+        let name = name.unwrap_or_else(|| {
+            root.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_else(|| "Mirror".to_string())
+        });
+        Self { root, name }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// ID of the entry at `path`, which must lie inside `root` as given.
+    pub fn id_for_path(root: &Path, path: &Path) -> Option<String> {
+        path.strip_prefix(root).ok().map(Self::encode_id)
+    }
+
+    fn encode_id(relative: &Path) -> String {
+        format!("{}{}", ID_PREFIX, hex::encode(relative.to_string_lossy().as_bytes()))
+    }
+
+    /// Maps an ID back to a relative path, rejecting anything that could
+    /// escape the mirror root.
+    fn decode_id(id: &str) -> Option<PathBuf> {
+        let bytes = hex::decode(id.strip_prefix(ID_PREFIX)?).ok()?;
+        let relative = PathBuf::from(String::from_utf8(bytes).ok()?);
+        relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+            .then_some(relative)
+    }
+
+    /// Stats `relative`, treating anything that resolves outside the mirror
+    /// root (e.g. through a symlinked directory) as missing.
+    async fn stat(&self, relative: &Path) -> io::Result<Option<Metadata>> {
+        let canonical = match fs::canonicalize(self.root.join(relative)).await {
+            Ok(path) => path,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        if !canonical.starts_with(fs::canonicalize(&self.root).await?) {
+            return Ok(None);
+        }
+        fs::metadata(&canonical).await.map(Some)
+    }
+
+    fn parent_id(relative: &Path) -> Option<String> {
+        relative.parent().map(Self::encode_id)
+    }
+
+    fn to_file(&self, relative: &Path, meta: &Metadata) -> FileMetadata {
+        let name = relative
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+        FileMetadata {
+            id: Self::encode_id(relative),
+            filename: name.clone(),
+            mime_type: mime_guess::from_path(&name).first().map(|m| m.to_string()),
+            original_filename: name,
+            file_size: meta.len() as i64,
+            storage_path: self.root.join(relative).to_string_lossy().to_string(),
+            uploaded_at: timestamp(meta.modified()),
+            description: None,
+            parent_directory_id: Self::parent_id(relative),
+            version: 1,
+            owner_id: None,
+            sha256: None,
+        }
+    }
+
+    fn to_directory(&self, relative: &Path, meta: &Metadata) -> Directory {
+        let name = match relative.file_name() {
+            Some(n) => n.to_string_lossy().to_string(),
+            None => self.name.clone(),
+        };
+        let modified = timestamp(meta.modified());
+        Directory {
+            id: Self::encode_id(relative),
+            name,
+            parent_id: Self::parent_id(relative),
+            created_at: meta
+                .created()
+                .map(|t| timestamp(Ok(t)))
+                .unwrap_or_else(|_| modified.clone()),
+            updated_at: modified,
+            color: None,
+            icon: None,
+            version: 1,
+        }
+    }
+
+    /// The directory entry shown at the top level for the mirror itself.
+    pub async fn root_directory(&self) -> io::Result<Directory> {
+        let meta = fs::metadata(&self.root).await?;
+        Ok(self.to_directory(Path::new(""), &meta))
+    }
+
+    pub async fn get_file(&self, id: &str) -> io::Result<Option<FileMetadata>> {
+        let Some(relative) = Self::decode_id(id) else {
+            return Ok(None);
+        };
+        Ok(self
+            .stat(&relative)
+            .await?
+            .filter(|meta| meta.is_file())
+            .map(|meta| self.to_file(&relative, &meta)))
+    }
+
+    pub async fn get_directory(&self, id: &str) -> io::Result<Option<Directory>> {
+        let Some(relative) = Self::decode_id(id) else {
+            return Ok(None);
+        };
+        Ok(self
+            .stat(&relative)
+            .await?
+            .filter(|meta| meta.is_dir())
+            .map(|meta| self.to_directory(&relative, &meta)))
+    }
+
+    /// Lists the regular files and directories directly inside `dir_id`.
+    /// Symlinks are skipped so the mirror can't be used to reach outside its root.
+    pub async fn list(&self, dir_id: &str) -> io::Result<(Vec<FileMetadata>, Vec<Directory>)> {
+        let mut files = Vec::new();
+        let mut directories = Vec::new();
+        let Some(relative) = Self::decode_id(dir_id) else {
+            return Ok((files, directories));
+        };
+
+        if !self.stat(&relative).await?.is_some_and(|meta| meta.is_dir()) {
+            return Ok((files, directories));
+        }
+
+        let mut entries = fs::read_dir(self.root.join(&relative)).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let meta = entry.metadata().await?;
+            let child = relative.join(entry.file_name());
+            if entry.file_type().await?.is_symlink() {
+                continue;
+            }
+            if meta.is_dir() {
+                directories.push(self.to_directory(&child, &meta));
+            } else if meta.is_file() {
+                files.push(self.to_file(&child, &meta));
+            }
+        }
+
+        files.sort_by(|a, b| b.uploaded_at.cmp(&a.uploaded_at));
+        directories.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok((files, directories))
+    }
+
+    /// File count and total size of the files directly inside `dir_id`.
+    pub async fn stats(&self, dir_id: &str) -> io::Result<(i64, i64)> {
+        let (files, _) = self.list(dir_id).await?;
+        Ok((
+            files.len() as i64,
+            files.iter().map(|f| f.file_size).sum(),
+        ))
+    }
+}
+
+fn timestamp(time: io::Result<SystemTime>) -> String {
+    time.map(DateTime::<Utc>::from)
+        .unwrap_or_else(|_| Utc::now())
+        .to_rfc3339()
+}
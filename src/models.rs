@@ -1,4 +1,8 @@
+use crate::db::Nullable;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use ts_rs::TS;
+use utoipa::ToSchema;
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct FileMetadata {
@@ -6,32 +10,82 @@ pub struct FileMetadata {
     pub filename: String,
     pub original_filename: String,
     pub file_size: i64,
+    #[sqlx(try_from = "Nullable<String>")]
     pub mime_type: Option<String>,
     pub storage_path: String,
     pub uploaded_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
     pub description: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
     pub parent_directory_id: Option<String>,
+    pub version: i64,
+    /// The user who uploaded the file, when the upload was authenticated.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub owner_id: Option<String>,
+    /// Hex SHA-256 of the content, hashed as it was uploaded; unknown for
+    /// files registered in place or changed on disk since.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub sha256: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, TS, ToSchema)]
 pub struct Directory {
     pub id: String,
     pub name: String,
+    #[sqlx(try_from = "Nullable<String>")]
     pub parent_id: Option<String>,
     pub created_at: String,
     pub updated_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub color: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub icon: Option<String>,
+    /// Raised by every change; sent as the `ETag` and expected back in
+    /// `If-Match` when changing the directory.
+    #[ts(type = "number")]
+    pub version: i64,
+}
+
+/// One entry of a chunked file's chunk map.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FileChunk {
+    pub chunk_hash: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct FileResponse {
     pub id: String,
     pub filename: String,
     pub original_filename: String,
+    #[ts(type = "number")]
     pub file_size: i64,
     pub mime_type: Option<String>,
     pub uploaded_at: String,
     pub description: Option<String>,
     pub parent_directory_id: Option<String>,
+    /// Raised by every change; sent as the `ETag` and expected back in
+    /// `If-Match` when changing the file.
+    #[ts(type = "number")]
+    pub version: i64,
+    /// ID of the user who uploaded the file; unset for anonymous uploads.
+    pub owner_id: Option<String>,
+    /// Hex SHA-256 of the content, also sent as `X-Checksum-SHA256` on
+    /// download; unset for files registered in place from disk.
+    pub sha256: Option<String>,
+    /// Subtitle tracks, posters and other attached files; only included when
+    /// a single file is requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub attachments: Option<Vec<FileAttachment>>,
+    /// The file's tags; only included when a single file is requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub tags: Option<Vec<String>>,
+    /// When the file will be deleted automatically; only included when a
+    /// single file is requested and it is set to expire.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub expires_at: Option<String>,
 }
 
 impl From<FileMetadata> for FileResponse {
@@ -45,19 +99,49 @@ impl From<FileMetadata> for FileResponse {
             uploaded_at: metadata.uploaded_at,
             description: metadata.description,
             parent_directory_id: metadata.parent_directory_id,
+            version: metadata.version,
+            owner_id: metadata.owner_id,
+            sha256: metadata.sha256,
+            attachments: None,
+            tags: None,
+            expires_at: None,
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct DirectoryResponse {
     pub id: String,
     pub name: String,
     pub parent_id: Option<String>,
     pub created_at: String,
     pub updated_at: String,
+    pub color: Option<String>,
+    pub icon: Option<String>,
+    /// Raised by every change; sent as the `ETag` and expected back in
+    /// `If-Match` when changing the directory.
+    #[ts(type = "number")]
+    pub version: i64,
+    #[ts(type = "number")]
     pub file_count: i64,
+    #[ts(type = "number")]
     pub total_size: i64,
+    /// The directory's `README.md`; only included when requested with
+    /// `GET /api/directories/:id?readme=`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub readme: Option<DirectoryReadme>,
+}
+
+/// A directory's README as raw markdown or sanitized HTML.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DirectoryReadme {
+    pub file_id: String,
+    /// `html` or `markdown`.
+    pub format: String,
+    pub content: String,
+    /// Whether the README was too long and has been cut off.
+    pub truncated: bool,
 }
 
 impl From<Directory> for DirectoryResponse {
@@ -68,70 +152,1839 @@ impl From<Directory> for DirectoryResponse {
             parent_id: directory.parent_id,
             created_at: directory.created_at,
             updated_at: directory.updated_at,
+            color: directory.color,
+            icon: directory.icon,
+            version: directory.version,
             file_count: 0,
             total_size: 0,
+            readme: None,
         }
     }
 }
 
-#[derive(Debug, Serialize)]
+/// A listing entry whose content lives elsewhere: an external URL or
+/// another stored file.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, TS, ToSchema)]
+pub struct Link {
+    pub id: String,
+    pub name: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub target_url: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub target_file_id: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub parent_directory_id: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub description: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct LinkResponse {
+    /// Always `"link"`, so clients can tell links from files in listings.
+    #[serde(rename = "type")]
+    #[ts(type = "\"link\"")]
+    pub entry_type: &'static str,
+    pub id: String,
+    pub name: String,
+    pub target_url: Option<String>,
+    pub target_file_id: Option<String>,
+    pub parent_directory_id: Option<String>,
+    pub description: Option<String>,
+    pub created_at: String,
+}
+
+impl From<Link> for LinkResponse {
+    fn from(link: Link) -> Self {
+        Self {
+            entry_type: "link",
+            id: link.id,
+            name: link.name,
+            target_url: link.target_url,
+            target_file_id: link.target_file_id,
+            parent_directory_id: link.parent_directory_id,
+            description: link.description,
+            created_at: link.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateLinkRequest {
+    pub name: String,
+    #[ts(optional)]
+    pub target_url: Option<String>,
+    #[ts(optional)]
+    pub target_file_id: Option<String>,
+    #[ts(optional)]
+    pub parent_directory_id: Option<String>,
+    #[ts(optional)]
+    pub description: Option<String>,
+}
+
+/// Keeps only the `keep_last` most recently uploaded files whose names match
+/// `pattern` in a directory; older matches are deleted by the scheduler.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, TS, ToSchema)]
+pub struct RetentionPolicy {
+    pub id: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub directory_id: Option<String>,
+    pub pattern: String,
+    #[ts(type = "number")]
+    pub keep_last: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateRetentionPolicyRequest {
+    #[ts(optional)]
+    pub directory_id: Option<String>,
+    pub pattern: String,
+    #[ts(type = "number")]
+    pub keep_last: i64,
+}
+
+/// Pre-generates thumbnails of the given sizes for images uploaded to a
+/// directory, and with `recursive` to any directory beneath it.
+#[derive(Debug, Clone, Serialize, Deserialize, TS, ToSchema)]
+pub struct ThumbnailPolicy {
+    pub id: String,
+    pub directory_id: Option<String>,
+    pub recursive: bool,
+    pub sizes: Vec<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateThumbnailPolicyRequest {
+    #[ts(optional)]
+    pub directory_id: Option<String>,
+    #[ts(optional)]
+    pub recursive: Option<bool>,
+    /// Size names; all sizes when absent.
+    #[ts(optional)]
+    pub sizes: Option<Vec<String>>,
+}
+
+/// Integrity attestation issued when an upload completes.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow, TS, ToSchema)]
+pub struct UploadReceipt {
+    pub file_id: String,
+    pub sha256: String,
+    #[ts(type = "number")]
+    pub file_size: i64,
+    pub received_at: String,
+    pub scan_status: String,
+    pub signature_algorithm: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ReceiptResponse {
+    #[serde(flatten)]
+    pub receipt: UploadReceipt,
+    pub signature_valid: bool,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct UploadResponse {
     pub success: bool,
     pub file: FileResponse,
+    pub receipt: UploadReceipt,
+    pub message: String,
+}
+
+/// Why unpacking an archive was given up part way through.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ArchiveLimitExceeded {
+    pub error: String,
+    /// `extracted_size`, `entries`, `depth` or `ratio`.
+    pub limit: String,
+    #[ts(type = "number")]
+    pub max: u64,
+    /// Files unpacked before the limit was reached. They are kept, with
+    /// `suspicious` as the scan status of their receipts.
+    pub suspicious_file_ids: Vec<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct IngestResponse {
+    pub success: bool,
+    pub files: Vec<FileResponse>,
+    pub skipped: Vec<String>,
     pub message: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct ErrorResponse {
     pub error: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct DeleteResponse {
     pub success: bool,
     pub message: String,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct ListFilesResponse {
     pub files: Vec<FileResponse>,
     pub directories: Vec<DirectoryResponse>,
+    pub links: Vec<LinkResponse>,
+    pub total: usize,
+    /// The listed directory's sort and pins, if its curator set any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub arrangement: Option<DirectoryArrangement>,
+}
+
+/// One entry of a recursive listing. `path` is relative to the listed
+/// directory, with `/` separators.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TreeEntry {
+    File {
+        path: String,
+        #[serde(flatten)]
+        file: FileResponse,
+    },
+    Directory {
+        path: String,
+        #[serde(flatten)]
+        directory: Directory,
+    },
+    Link {
+        path: String,
+        #[serde(flatten)]
+        link: Link,
+    },
+}
+
+impl TreeEntry {
+    pub fn path(&self) -> &str {
+        match self {
+            TreeEntry::File { path, .. }
+            | TreeEntry::Directory { path, .. }
+            | TreeEntry::Link { path, .. } => path,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ListTreeResponse {
+    pub entries: Vec<TreeEntry>,
+    /// Number of entries in the whole subtree, not just this page.
     pub total: usize,
+    pub offset: usize,
+    pub limit: usize,
+}
+
+/// A file a client holds, described by its path relative to the compared
+/// directory and its content hash.
+#[derive(Debug, Clone, Deserialize, TS, ToSchema)]
+pub struct ManifestEntry {
+    /// Path relative to the directory, with `/` separators.
+    pub path: String,
+    /// Hex SHA-256 of the content.
+    pub sha256: String,
+    #[ts(optional, type = "number")]
+    pub file_size: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CompareRequest {
+    /// The directory as it is now.
+    pub directory_id: String,
+    /// Another directory to compare against, such as an earlier delivery.
+    #[ts(optional)]
+    pub base_directory_id: Option<String>,
+    /// The files a client holds, to compare against instead of a directory.
+    #[ts(optional)]
+    pub manifest: Option<Vec<ManifestEntry>>,
+}
+
+/// A file that differs between the compared directory and its base. The
+/// fields of the side it is missing from are `null`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ComparedFile {
+    pub path: String,
+    /// The file in the compared directory.
+    pub file_id: Option<String>,
+    pub sha256: Option<String>,
+    #[ts(type = "number | null")]
+    pub file_size: Option<i64>,
+    /// The file in the base directory; always `null` for a manifest.
+    pub base_file_id: Option<String>,
+    pub base_sha256: Option<String>,
+    #[ts(type = "number | null")]
+    pub base_file_size: Option<i64>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct CompareResponse {
+    /// Files only in the compared directory.
+    pub added: Vec<ComparedFile>,
+    /// Files only in the base.
+    pub removed: Vec<ComparedFile>,
+    /// Files at the same path whose content differs.
+    pub changed: Vec<ComparedFile>,
+    /// How many files are the same on both sides.
+    pub unchanged: usize,
+}
+
+/// A directory's recursive storage usage, for treemap-style reports.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UsageNode {
+    /// `None` for the root.
+    pub id: Option<String>,
+    pub name: String,
+    /// Size of every file in this directory and all of its descendants.
+    #[ts(type = "number")]
+    pub total_size: i64,
+    #[ts(type = "number")]
+    pub file_count: i64,
+    /// Subdirectories, largest first. Empty once the requested depth is reached.
+    pub children: Vec<UsageNode>,
+}
+
+/// A file together with its download statistics.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct FileUsage {
+    #[sqlx(flatten)]
+    pub file: FileMetadata,
+    pub download_count: i64,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub last_downloaded_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct FileUsageResponse {
+    #[serde(flatten)]
+    pub file: FileResponse,
+    #[ts(type = "number")]
+    pub download_count: i64,
+    pub last_downloaded_at: Option<String>,
+}
+
+impl From<FileUsage> for FileUsageResponse {
+    fn from(usage: FileUsage) -> Self {
+        Self {
+            file: usage.file.into(),
+            download_count: usage.download_count,
+            last_downloaded_at: usage.last_downloaded_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct FileReportResponse {
+    pub report: String,
+    pub files: Vec<FileUsageResponse>,
+}
+
+/// A file found by `GET /api/search`, with the path of its directory.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct SearchMatch {
+    pub file: FileResponse,
+    /// Path of the file's directory from the root, such as `/Photos/2024`;
+    /// `/` for files at the root.
+    pub directory_path: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct SearchResponse {
+    pub query: String,
+    pub matches: Vec<SearchMatch>,
+    pub total: usize,
+}
+
+/// One download of a file, as shown to its owner.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct AccessLogEntry {
+    pub accessed_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub share_id: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub client_ip: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub user_agent: Option<String>,
+    /// Digest of the share terms the recipient accepted, if any.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub accepted_terms: Option<String>,
+}
+
+/// How public pages (share pages, directory indexes) are branded.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct Branding {
+    pub logo_url: Option<String>,
+    /// `#rgb` or `#rrggbb`.
+    pub accent_color: String,
+    pub footer_text: Option<String>,
+}
+
+/// Optional features available on this instance, so clients can adapt their
+/// UI without probing endpoints.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct Capabilities {
+    pub shares: bool,
+    pub thumbnails: bool,
+    pub webdav: bool,
+    pub chunked_uploads: bool,
+    pub tar_ingest: bool,
+    pub links: bool,
+    pub change_events: bool,
+    pub web_push: bool,
+    pub email_notifications: bool,
+    pub mqtt: bool,
+    pub chat_integrations: bool,
+    pub clipboard: bool,
+    pub audio_waveforms: bool,
+    pub format_conversion: bool,
+    pub mirror: bool,
+    pub chunk_dedup: bool,
+    pub case_insensitive_names: bool,
+    pub windows_compatible_names: bool,
+    pub user_accounts: bool,
+    /// Whether `POST /api/auth/register` accepts new accounts.
+    pub registration: bool,
+    /// Whether `GET /auth/oidc/login` offers single sign-on.
+    pub oidc: bool,
+    /// Whether anyone may list and download but only signed-in users may
+    /// make changes.
+    pub public_read_only: bool,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct CapabilitiesResponse {
+    pub features: Capabilities,
+    /// Largest accepted upload in bytes; `null` means no limit.
+    pub max_upload_size: Option<u64>,
+}
+
+/// How far uploads are ahead of the disk, from `GET /api/admin/write-queue`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct WriteQueueStats {
+    /// Uploads currently being written.
+    #[ts(type = "number")]
+    pub active_writers: u64,
+    /// Chunks received from clients and not yet written, across uploads.
+    #[ts(type = "number")]
+    pub queued_chunks: u64,
+    #[ts(type = "number")]
+    pub queued_bytes: u64,
+    /// Chunks one upload may have queued before it waits for the disk.
+    #[ts(type = "number")]
+    pub capacity_chunks: u64,
+    /// Times since startup an upload had to wait for the disk to catch up.
+    #[ts(type = "number")]
+    pub disk_waits: u64,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct DiskBenchmarkRequest {
+    /// Bytes to write and read back; 256 MiB if omitted. Reads of a file
+    /// just written may come from the page cache, so use more than the
+    /// server's free memory to measure the disk itself.
+    #[ts(optional, type = "number")]
+    pub size: Option<u64>,
+    /// Buffer sizes to try instead of `READ_BUFFER_SIZE` and
+    /// `WRITE_BUFFER_SIZE`.
+    #[ts(optional, type = "number")]
+    pub read_buffer_size: Option<usize>,
+    #[ts(optional, type = "number")]
+    pub write_buffer_size: Option<usize>,
 }
 
-#[derive(Debug, Deserialize)]
+/// Sequential throughput of the upload directory's volume, from
+/// `POST /api/admin/disk-benchmark`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DiskBenchmark {
+    /// `standard` or `io_uring`.
+    pub io_backend: String,
+    #[ts(type = "number")]
+    pub size: u64,
+    #[ts(type = "number")]
+    pub read_buffer_size: u64,
+    #[ts(type = "number")]
+    pub write_buffer_size: u64,
+    /// Time to write the data and sync it to disk.
+    pub write_secs: f64,
+    pub read_secs: f64,
+    pub write_mib_per_sec: f64,
+    pub read_mib_per_sec: f64,
+}
+
+/// Total size of stored files measured against the storage quota.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct QuotaStatus {
+    #[ts(type = "number")]
+    pub used_bytes: u64,
+    /// Past this, uploads come with warnings; `null` when not set.
+    #[ts(type = "number | null")]
+    pub soft_limit: Option<u64>,
+    /// Past this, no upload is accepted; `null` when not set.
+    #[ts(type = "number | null")]
+    pub hard_limit: Option<u64>,
+    pub over_soft_limit: bool,
+    /// When uploads stop being accepted, while over the soft limit.
+    pub grace_ends_at: Option<String>,
+    pub accepting_uploads: bool,
+}
+
+/// Estimated monthly cost of storing and serving a set of files.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct CostEstimate {
+    #[ts(type = "number")]
+    pub stored_bytes: i64,
+    /// Bytes downloaded during the measured window, scaled to a 30-day month.
+    #[ts(type = "number")]
+    pub egress_bytes: i64,
+    pub storage_cost: f64,
+    pub egress_cost: f64,
+    pub total_cost: f64,
+}
+
+/// Cost estimate for a directory, including everything beneath it.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DirectoryCost {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    #[ts(type = "number")]
+    pub file_count: i64,
+    pub estimate: CostEstimate,
+}
+
+/// Cost estimate for a share: the shared file's storage, and the downloads
+/// made through the share.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ShareCost {
+    pub share_id: String,
+    pub file_id: String,
+    pub file_name: String,
+    pub estimate: CostEstimate,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct CostReport {
+    pub currency: String,
+    pub storage_cost_per_gb_month: f64,
+    pub egress_cost_per_gb: f64,
+    /// Days of download history the egress figures are based on.
+    #[ts(type = "number")]
+    pub window_days: u64,
+    pub total: CostEstimate,
+    /// Directories, most expensive first.
+    pub directories: Vec<DirectoryCost>,
+    /// Shares, most expensive first.
+    pub shares: Vec<ShareCost>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct VersionResponse {
+    pub version: String,
+    pub git_commit: String,
+    pub build_date: String,
+    pub database_backend: String,
+    pub storage_backend: String,
+    /// Optional features enabled in this instance's configuration.
+    pub features: Vec<String>,
+    /// Version of the newest applied database migration.
+    #[ts(type = "number")]
+    pub schema_version: i64,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct AccessLogResponse {
+    pub file_id: String,
+    pub entries: Vec<AccessLogEntry>,
+    /// Entries older than this many days have been deleted.
+    #[ts(type = "number")]
+    pub retention_days: u64,
+}
+
+/// How a user wants to be told about activity.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct NotificationPreferences {
+    pub user_id: String,
+    pub email: Option<String>,
+    /// Notify when someone opens one of the user's shares.
+    pub on_share_access: bool,
+    /// Notify when files are added to a watched folder.
+    pub on_new_files: bool,
+    /// `"immediate"` or `"digest"`.
+    pub delivery: String,
+    pub updated_at: String,
+}
+
+impl NotificationPreferences {
+    /// Settings for a user who hasn't saved any: immediate notifications
+    /// about watched folders only.
+    pub fn defaults(user_id: &str) -> Self {
+        Self {
+            user_id: user_id.to_string(),
+            email: None,
+            on_share_access: false,
+            on_new_files: true,
+            delivery: "immediate".to_string(),
+            updated_at: chrono::Utc::now().to_rfc3339(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct UpdateNotificationPreferencesRequest {
+    #[ts(optional)]
+    pub email: Option<String>,
+    #[ts(optional)]
+    pub on_share_access: Option<bool>,
+    #[ts(optional)]
+    pub on_new_files: Option<bool>,
+    #[ts(optional)]
+    pub delivery: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct WatchedFolder {
+    pub user_id: String,
+    pub directory_id: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct WatchFolderRequest {
+    pub directory_id: String,
+}
+
+/// A user's role in a directory and everything under it.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct DirectoryAclEntry {
+    pub directory_id: String,
+    pub user_id: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub username: Option<String>,
+    /// `read`, `write` or `owner`.
+    pub role: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DirectoryAclResponse {
+    pub directory_id: String,
+    /// Whether this directory or one of its ancestors has an access list;
+    /// unrestricted directories are open to everyone.
+    pub restricted: bool,
+    /// The caller's role here, inherited from ancestors if not listed on
+    /// this directory; `null` without access.
+    pub your_role: Option<String>,
+    /// The entries on this directory itself, not the inherited ones.
+    pub entries: Vec<DirectoryAclEntry>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct SetDirectoryAclRequest {
+    /// `read`, `write` or `owner`.
+    pub role: String,
+}
+
+/// A browser's Web Push subscription. The keys are only needed to encrypt
+/// payloads, so they aren't echoed back.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct PushSubscription {
+    pub id: String,
+    pub user_id: String,
+    pub endpoint: String,
+    #[serde(skip_serializing)]
+    #[ts(skip)]
+    pub p256dh: String,
+    #[serde(skip_serializing)]
+    #[ts(skip)]
+    pub auth: String,
+    pub created_at: String,
+}
+
+/// A notification held back for a user's next digest.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct PendingNotification {
+    pub message: String,
+    pub created_at: String,
+}
+
+/// A single sign-on identity linked to a local user.
+#[derive(Debug, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    pub created_at: String,
+}
+
+/// Everything stored under a user's ID, for a data subject access request.
+/// Sent as `export.json` in a ZIP archive, next to the content of each of
+/// `files` at `files/<id>/<original_filename>`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UserDataExport {
+    pub user_id: String,
+    pub exported_at: String,
+    /// `None` for IDs with no account, such as one already erased.
+    pub user: Option<UserResponse>,
+    pub oidc_identities: Vec<OidcIdentity>,
+    /// Access list entries naming the user.
+    pub directory_access: Vec<DirectoryAclEntry>,
+    pub archive_jobs: Vec<ArchiveJob>,
+    /// Files the user uploaded, including any in the trash.
+    pub files: Vec<FileResponse>,
+    pub notification_preferences: Option<NotificationPreferences>,
+    pub watched_folders: Vec<WatchedFolder>,
+    pub pending_notifications: Vec<PendingNotification>,
+    pub push_subscriptions: Vec<PushSubscription>,
+    /// The user's clipboard entry, even if it has expired.
+    pub clipboard: Option<ClipboardEntry>,
+}
+
+/// Rows removed, per table, when erasing a user's data.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UserErasureReport {
+    pub user_id: String,
+    pub erased_at: String,
+    #[ts(type = "number")]
+    pub notification_preferences: u64,
+    #[ts(type = "number")]
+    pub watched_folders: u64,
+    #[ts(type = "number")]
+    pub pending_notifications: u64,
+    #[ts(type = "number")]
+    pub push_subscriptions: u64,
+    #[ts(type = "number")]
+    pub clipboard_entries: u64,
+    /// Files the user uploaded, deleted with their content, versions,
+    /// shares and receipts.
+    #[ts(type = "number")]
+    pub files: u64,
+    /// Journal entries for those files, which hold their earlier names.
+    #[ts(type = "number")]
+    pub metadata_journal: u64,
+    #[ts(type = "number")]
+    pub archive_jobs: u64,
+    #[ts(type = "number")]
+    pub directory_acl: u64,
+    #[ts(type = "number")]
+    pub oidc_identities: u64,
+    #[ts(type = "number")]
+    pub users: u64,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct PushSubscriptionKeys {
+    pub p256dh: String,
+    pub auth: String,
+}
+
+/// The browser's `PushSubscription` JSON, as returned by `subscription.toJSON()`.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreatePushSubscriptionRequest {
+    pub endpoint: String,
+    pub keys: PushSubscriptionKeys,
+}
+
+/// A chat integration that announces new files in a directory, or a webhook
+/// that receives every change in it. For Slack and Discord `url` is the
+/// incoming webhook; for Matrix it is the homeserver.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct DirectoryIntegration {
+    pub id: String,
+    pub directory_id: String,
+    pub kind: String,
+    pub url: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub room_id: Option<String>,
+    #[serde(skip_serializing)]
+    #[ts(skip)]
+    #[sqlx(try_from = "Nullable<String>")]
+    pub access_token: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateDirectoryIntegrationRequest {
+    pub kind: String,
+    pub url: String,
+    /// Matrix only: the room to post to, e.g. `!abc123:example.org`.
+    #[ts(optional)]
+    pub room_id: Option<String>,
+    /// Matrix only: access token of the posting user.
+    #[ts(optional)]
+    pub access_token: Option<String>,
+}
+
+/// Outcome of sending an integration's failed deliveries again.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct WebhookReplayResponse {
+    #[ts(type = "number")]
+    pub replayed: u64,
+    #[ts(type = "number")]
+    pub delivered: u64,
+    #[ts(type = "number")]
+    pub failed: u64,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct VapidPublicKeyResponse {
+    pub public_key: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
 pub struct CreateDirectoryRequest {
     pub name: String,
+    #[ts(optional)]
     pub parent_id: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct CreateDirectoryResponse {
     pub success: bool,
     pub directory: DirectoryResponse,
     pub message: String,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, TS, ToSchema)]
 pub struct BulkDeleteRequest {
     pub file_ids: Vec<String>,
     pub directory_ids: Vec<String>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, TS, ToSchema)]
 pub struct MoveFileRequest {
+    /// Directory to move the file into; `null` or left out moves it to the
+    /// root.
+    #[ts(optional)]
     pub parent_directory_id: Option<String>,
 }
 
-#[derive(Debug, Deserialize)]
-pub struct MoveDirectoryRequest {
+/// Changes to a file; fields left out are kept as they are.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct UpdateFileRequest {
+    /// Directory to move the file into; `null` moves it to the root.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub parent_directory_id: Option<Option<String>>,
+    /// New description; `null` removes it.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub description: Option<Option<String>>,
+    /// The file's tags, replacing the ones it has; `[]` removes them all.
+    #[ts(optional)]
+    pub tags: Option<Vec<String>>,
+    /// RFC 3339 time after which the file is deleted; `null` keeps it.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub expires_at: Option<Option<String>>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct MoveDirectoryToRequest {
+    /// Directory to move the directory into; `null` or left out moves it to
+    /// the root.
+    #[ts(optional)]
     pub parent_id: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+/// Changes to a directory; fields left out are kept as they are.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct MoveDirectoryRequest {
+    /// New parent directory; `null` moves the directory to the root.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub parent_id: Option<Option<String>>,
+    /// Color label, one of `red`, `orange`, `yellow`, `green`, `teal`,
+    /// `blue`, `purple`, `pink` or `gray`; `null` removes it.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub color: Option<Option<String>>,
+    /// Icon name, such as `star` or `music`; `null` removes it.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub icon: Option<Option<String>>,
+}
+
+/// Reads a field that may be left out, `null` or set, so a request can tell
+/// "keep" (`None`) apart from "clear" (`Some(None)`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::<T>::deserialize(deserializer).map(Some)
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
 pub struct BulkDeleteResponse {
     pub success: bool,
     pub deleted_files: usize,
     pub deleted_directories: usize,
     pub message: String,
 }
+
+/// Files and directories to move or copy into one directory.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct BulkTransferRequest {
+    pub file_ids: Vec<String>,
+    pub directory_ids: Vec<String>,
+    /// Directory to move or copy into; `null` for the root.
+    #[ts(optional)]
+    pub target_directory_id: Option<String>,
+}
+
+/// What a bulk operation did with one of the requested items.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BulkItemResult {
+    pub id: String,
+    /// `file` or `directory`.
+    pub kind: String,
+    pub success: bool,
+    /// ID of the copy; only set by bulk copy.
+    pub new_id: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BulkMoveResponse {
+    pub success: bool,
+    pub moved_files: usize,
+    pub moved_directories: usize,
+    pub results: Vec<BulkItemResult>,
+    pub message: String,
+}
+
+/// Changes to apply to every listed file; fields left out are kept as they
+/// are.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct BulkUpdateRequest {
+    pub file_ids: Vec<String>,
+    #[ts(optional)]
+    pub add_tags: Option<Vec<String>>,
+    #[ts(optional)]
+    pub remove_tags: Option<Vec<String>>,
+    /// New description; `null` removes it.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub description: Option<Option<String>>,
+    /// RFC 3339 time after which the files are deleted; `null` keeps them.
+    #[serde(default, deserialize_with = "present")]
+    #[ts(optional)]
+    pub expires_at: Option<Option<String>>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BulkUpdateResponse {
+    pub success: bool,
+    pub updated_files: usize,
+    pub results: Vec<BulkItemResult>,
+    pub message: String,
+}
+
+/// One step of a batch. Wherever a step takes a directory ID, `$N` refers
+/// to the directory created by the earlier `mkdir` step at index `N`.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum BatchOperation {
+    Mkdir {
+        name: String,
+        #[ts(optional)]
+        parent_id: Option<String>,
+    },
+    Move {
+        /// `file` or `directory`.
+        kind: String,
+        id: String,
+        /// New parent directory; `null` for the root.
+        #[ts(optional)]
+        parent_id: Option<String>,
+        /// Only move the entry if it is still at this version.
+        #[ts(optional, type = "number")]
+        version: Option<i64>,
+    },
+    Rename {
+        /// `file` or `directory`.
+        kind: String,
+        id: String,
+        name: String,
+        /// Only rename the entry if it is still at this version.
+        #[ts(optional, type = "number")]
+        version: Option<i64>,
+    },
+    Delete {
+        /// `file` or `directory`.
+        kind: String,
+        id: String,
+    },
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct BatchRequest {
+    pub operations: Vec<BatchOperation>,
+}
+
+/// What one step of a batch did.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BatchOperationResult {
+    pub index: usize,
+    /// `mkdir`, `move`, `rename` or `delete`.
+    pub op: String,
+    /// The entry the step changed, or the directory `mkdir` created.
+    pub id: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BatchResponse {
+    pub success: bool,
+    /// One entry per step when the batch was applied; empty otherwise.
+    pub results: Vec<BatchOperationResult>,
+    /// Index of the step that failed, when one did.
+    pub failed_operation: Option<usize>,
+    pub error: Option<String>,
+    pub message: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BulkCopyResponse {
+    pub success: bool,
+    pub copied_files: usize,
+    pub copied_directories: usize,
+    pub results: Vec<BulkItemResult>,
+    pub message: String,
+}
+
+/// A chunked upload in progress, as stored.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UploadSession {
+    pub id: String,
+    pub filename: String,
+    pub file_size: i64,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub mime_type: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub description: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub parent_directory_id: Option<String>,
+    pub network_profile: String,
+    pub chunk_size: i64,
+    pub parallelism: i64,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub expires_at: Option<String>,
+}
+
+/// Starts a chunked upload. `network_profile` (`wired`, `wifi`, `cellular`
+/// or `constrained`, default `wifi`) decides the chunk size and parallelism;
+/// `chunk_size` and `parallelism` are preferences kept within its limits.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateUploadSessionRequest {
+    pub filename: String,
+    #[ts(type = "number")]
+    pub file_size: i64,
+    #[ts(optional)]
+    pub mime_type: Option<String>,
+    #[ts(optional)]
+    pub description: Option<String>,
+    #[ts(optional)]
+    pub parent_directory_id: Option<String>,
+    #[ts(optional)]
+    pub network_profile: Option<String>,
+    #[ts(optional, type = "number")]
+    pub chunk_size: Option<i64>,
+    #[ts(optional, type = "number")]
+    pub parallelism: Option<i64>,
+}
+
+/// The negotiated settings of an upload session and which chunks have
+/// arrived, so an interrupted client can resume with the missing ones.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UploadSessionResponse {
+    pub id: String,
+    pub filename: String,
+    #[ts(type = "number")]
+    pub file_size: i64,
+    pub network_profile: String,
+    #[ts(type = "number")]
+    pub chunk_size: i64,
+    #[ts(type = "number")]
+    pub parallelism: i64,
+    #[ts(type = "number")]
+    pub total_chunks: i64,
+    #[ts(type = "Array<number>")]
+    pub received_chunks: Vec<i64>,
+    pub created_at: String,
+    /// When the session is discarded unless another chunk arrives first.
+    pub expires_at: Option<String>,
+}
+
+/// What upload cleanup has reclaimed since startup, from
+/// `GET /api/admin/upload-cleanup`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UploadCleanupStats {
+    /// Seconds a session may go without a chunk before it is discarded.
+    #[ts(type = "number")]
+    pub session_ttl_secs: u64,
+    /// Cleanup passes since startup, one at startup and one per scheduler run.
+    #[ts(type = "number")]
+    pub runs: u64,
+    /// Abandoned upload sessions discarded, with their chunks.
+    #[ts(type = "number")]
+    pub sessions_expired: u64,
+    /// Chunk directories found without a session and removed.
+    #[ts(type = "number")]
+    pub orphaned_directories: u64,
+    /// `.part` files of interrupted uploads removed.
+    #[ts(type = "number")]
+    pub temp_files_removed: u64,
+    /// Disk space freed by all of the above.
+    #[ts(type = "number")]
+    pub bytes_reclaimed: u64,
+    pub last_run_at: Option<String>,
+}
+
+/// A directory archive built in the background, as stored.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct ArchiveJob {
+    pub id: String,
+    pub directory_id: String,
+    /// Whose access the archive was built with; `None` without sign-in.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub user_id: Option<String>,
+    pub status: String,
+    #[serde(skip_serializing)]
+    #[ts(skip)]
+    pub ticket: String,
+    pub file_name: String,
+    #[sqlx(try_from = "Nullable<i64>")]
+    #[ts(type = "number | null")]
+    pub file_size: Option<i64>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub error: Option<String>,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub finished_at: Option<String>,
+    pub expires_at: String,
+}
+
+/// A directory archive being built for download, from
+/// `POST /api/directories/:id/archive`. Poll it until `status` is `ready`,
+/// then download it from `download_url`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ArchiveJobResponse {
+    pub id: String,
+    pub directory_id: String,
+    /// `pending` while the archive is built, then `ready` or `failed`.
+    pub status: String,
+    /// Name the archive downloads as, e.g. `Photos.zip`.
+    pub file_name: String,
+    /// Size of the finished archive in bytes.
+    #[ts(type = "number | null")]
+    pub file_size: Option<i64>,
+    /// Why building the archive failed.
+    pub error: Option<String>,
+    /// Link to the finished archive, which works without signing in until
+    /// `expires_at` and supports `Range` requests for resuming.
+    pub download_url: Option<String>,
+    pub created_at: String,
+    pub finished_at: Option<String>,
+    /// When the archive and this job are removed.
+    pub expires_at: String,
+}
+
+/// How to download a file in parallel parts, from
+/// `GET /api/files/:id/segments`. Fetch each segment with a `Range` request
+/// and check it against its own checksum.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct SegmentManifest {
+    pub file_id: String,
+    #[ts(type = "number")]
+    pub file_size: i64,
+    /// Hex SHA-256 of the whole file, when known.
+    pub sha256: Option<String>,
+    /// The file's `ETag`; send it in `If-Range` so a file changed in the
+    /// meantime comes back whole instead of as mismatched parts.
+    pub etag: String,
+    /// Length of every segment but the last, a multiple of 64 KiB.
+    #[ts(type = "number")]
+    pub segment_size: i64,
+    pub segments: Vec<FileSegment>,
+}
+
+/// One part of a [`SegmentManifest`].
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct FileSegment {
+    pub index: u32,
+    /// First byte of the segment.
+    #[ts(type = "number")]
+    pub start: i64,
+    /// Last byte of the segment, inclusive, as in a `Range` header.
+    #[ts(type = "number")]
+    pub end: i64,
+    #[ts(type = "number")]
+    pub length: i64,
+    /// Hex SHA-256 of the segment's bytes.
+    pub sha256: String,
+}
+
+/// Earlier content of a file, kept when a new version was uploaded.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct FileVersion {
+    pub id: String,
+    pub file_id: String,
+    #[serde(skip_serializing)]
+    #[ts(skip)]
+    pub filename: String,
+    #[ts(type = "number")]
+    pub file_size: i64,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub mime_type: Option<String>,
+    #[serde(skip_serializing)]
+    #[ts(skip)]
+    pub storage_path: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub sha256: Option<String>,
+    /// When this content was uploaded.
+    pub uploaded_at: String,
+    /// When a newer version took its place.
+    pub replaced_at: String,
+}
+
+impl FileVersion {
+    /// The version's content in the shape of the file it belongs to, so it
+    /// can be opened or released like the file's own.
+    pub fn as_content(&self, file: &FileMetadata) -> FileMetadata {
+        FileMetadata {
+            id: self.id.clone(),
+            filename: self.filename.clone(),
+            file_size: self.file_size,
+            mime_type: self.mime_type.clone(),
+            storage_path: self.storage_path.clone(),
+            uploaded_at: self.uploaded_at.clone(),
+            sha256: self.sha256.clone(),
+            ..file.clone()
+        }
+    }
+}
+
+/// A file or directory in the trash, from `GET /api/trash`. Everything
+/// inside a trashed directory goes with it and comes back when it is
+/// restored.
+#[derive(Debug, Clone, Serialize, TS, ToSchema, sqlx::FromRow)]
+pub struct TrashItem {
+    pub id: String,
+    /// `file` or `directory`.
+    pub kind: String,
+    pub name: String,
+    /// The directory the item is restored to; `None` for the root.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub parent_id: Option<String>,
+    /// Size of a file; `None` for a directory.
+    #[ts(type = "number | null")]
+    #[sqlx(try_from = "Nullable<i64>")]
+    pub file_size: Option<i64>,
+    pub deleted_at: String,
+    /// When the item is deleted for good unless it is restored first.
+    #[sqlx(skip)]
+    pub purge_at: String,
+}
+
+/// One database maintenance run.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct DatabaseMaintenance {
+    pub started_at: String,
+    pub duration_secs: f64,
+    /// What ran, in order: `wal_checkpoint`, `vacuum`, `analyze`.
+    pub steps: Vec<String>,
+    /// Bytes the database took before and after, including SQLite's
+    /// write-ahead log.
+    #[ts(type = "number")]
+    pub size_before: u64,
+    #[ts(type = "number")]
+    pub size_after: u64,
+}
+
+/// Size and dead space of the database, from `GET /api/admin/database`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DatabaseStats {
+    /// `sqlite` or `postgres`.
+    pub backend: String,
+    #[ts(type = "number")]
+    pub size_bytes: u64,
+    /// SQLite: bytes of free pages inside the file, which only VACUUM
+    /// returns to the disk.
+    #[ts(type = "number | null")]
+    pub free_bytes: Option<u64>,
+    /// SQLite: size of the write-ahead log next to the file.
+    #[ts(type = "number | null")]
+    pub wal_bytes: Option<u64>,
+    /// PostgreSQL: rows deleted or updated but not yet vacuumed.
+    #[ts(type = "number | null")]
+    pub dead_rows: Option<u64>,
+    /// Share of the database that is dead space, from 0 to 1: free pages on
+    /// SQLite, dead rows on PostgreSQL.
+    pub fragmentation: f64,
+    /// SQLite: `delete`, `wal` and so on.
+    pub journal_mode: Option<String>,
+    /// `DB_MAINTENANCE_WINDOW`, in UTC; `null` when scheduled maintenance is off.
+    pub maintenance_window: Option<String>,
+    /// The latest run since startup, scheduled or requested.
+    pub last_maintenance: Option<DatabaseMaintenance>,
+}
+
+/// A text snippet shared between devices through `/api/clipboard`.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct ClipboardEntry {
+    pub text: String,
+    pub created_at: String,
+    pub expires_at: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct SetClipboardRequest {
+    pub text: String,
+    /// Seconds until the entry expires; defaults to `CLIPBOARD_TTL_SECS`.
+    #[ts(optional, type = "number")]
+    pub ttl_secs: Option<u64>,
+}
+
+/// A public link to one file, opened at `/s/<token>`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct Share {
+    pub id: String,
+    pub token: String,
+    pub file_id: String,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub expires_at: Option<String>,
+    /// Text the recipient has to accept before the file is served.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub terms: Option<String>,
+    /// Downloads after which the link stops working; unlimited if `None`.
+    #[sqlx(try_from = "Nullable<i64>")]
+    pub max_downloads: Option<i64>,
+    pub download_count: i64,
+    /// When reports from recipients took the link down; it doesn't resolve
+    /// until an admin dismisses them.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub disabled_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateShareRequest {
+    /// RFC 3339 time after which the link stops resolving; open-ended if
+    /// omitted.
+    #[ts(optional)]
+    pub expires_at: Option<String>,
+    /// Downloads after which the link stops resolving, e.g. `1` for a
+    /// one-time link; unlimited if omitted.
+    #[ts(optional, type = "number")]
+    pub max_downloads: Option<i64>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ShareResponse {
+    pub id: String,
+    pub file_id: String,
+    /// Public URL of the share, which names neither the file nor its id.
+    pub url: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+    /// Whether the link has expired and no longer resolves.
+    pub expired: bool,
+    #[ts(type = "number | null")]
+    pub max_downloads: Option<i64>,
+    /// Downloads made through the link; once it reaches `max_downloads`,
+    /// the link no longer resolves.
+    #[ts(type = "number")]
+    pub download_count: i64,
+    pub terms: Option<String>,
+    /// When recipients' reports took the link down, pending review by an
+    /// admin; it doesn't resolve meanwhile.
+    pub disabled_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct SetShareTermsRequest {
+    /// Terms the recipient has to accept; `null` or empty removes the gate.
+    #[ts(optional)]
+    pub terms: Option<String>,
+}
+
+/// The terms a share is gated behind.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ShareTerms {
+    pub share_id: String,
+    pub terms: Option<String>,
+    /// Identifies this version of the terms; accepting it is recorded in the
+    /// access log.
+    pub terms_digest: Option<String>,
+}
+
+/// Returned instead of the file when a share's terms haven't been accepted.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ShareTermsRequired {
+    pub error: String,
+    pub terms: String,
+    pub terms_digest: String,
+    /// Opening this URL accepts the terms and downloads the file.
+    pub accept_url: String,
+}
+
+/// Returned instead of a file whose type could run on the recipient's
+/// machine, until they confirm they want it.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DownloadConfirmationRequired {
+    pub error: String,
+    /// The file's extension, lowercased and without the dot.
+    pub extension: String,
+    /// Opening this URL confirms the warning and downloads the file.
+    pub confirm_url: String,
+}
+
+/// A public, read-only index of a directory and everything beneath it,
+/// opened at `/s/<token>`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DirectoryShare {
+    pub id: String,
+    pub token: String,
+    pub directory_id: String,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DirectoryShareResponse {
+    pub id: String,
+    pub directory_id: String,
+    /// Public URL of the directory's index page.
+    pub url: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+}
+
+/// A public link to a set of files picked from any directories, opened at
+/// `/s/<token>` as a list of them with a download of them all.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct BundleShare {
+    pub id: String,
+    pub token: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub title: Option<String>,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateBundleShareRequest {
+    /// The files to share, listed on the share's page in this order.
+    pub file_ids: Vec<String>,
+    /// Heading of the share's page; a count of the files if omitted.
+    #[ts(optional)]
+    pub title: Option<String>,
+    /// RFC 3339 time after which the link stops resolving; open-ended if
+    /// omitted.
+    #[ts(optional)]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct BundleShareResponse {
+    pub id: String,
+    pub file_ids: Vec<String>,
+    pub title: Option<String>,
+    /// Public URL of the list of files.
+    pub url: String,
+    /// Public URL of a ZIP archive of all the files.
+    pub archive_url: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+}
+
+/// A guest upload link into a directory, opened at `/u/<token>`, which
+/// stops accepting files at its deadline.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct UploadLink {
+    pub id: String,
+    pub token: String,
+    pub directory_id: String,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub deadline: Option<String>,
+}
+
+/// An upload-only link into a directory, posted to at `/drop/<token>`.
+/// Unlike upload links it asks nothing of the sender, and unlike shares it
+/// grants neither listing nor downloading.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct DropLink {
+    pub id: String,
+    pub token: String,
+    pub directory_id: String,
+    pub created_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateDropLinkRequest {
+    /// RFC 3339 time after which the link stops accepting files;
+    /// open-ended if omitted.
+    #[ts(optional)]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DropLinkResponse {
+    pub id: String,
+    pub directory_id: String,
+    /// URL to post files to.
+    pub url: String,
+    pub created_at: String,
+    pub expires_at: Option<String>,
+}
+
+/// A file received through a drop link, described without its ID, which
+/// would let the sender look it up.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DroppedFile {
+    pub name: String,
+    #[ts(type = "number")]
+    pub size: i64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct DropReceipt {
+    pub files: Vec<DroppedFile>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateUploadLinkRequest {
+    /// RFC 3339 time after which uploads are refused; open-ended if omitted.
+    #[ts(optional)]
+    pub deadline: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UploadLinkResponse {
+    pub id: String,
+    pub directory_id: String,
+    /// Public URL of the upload form.
+    pub url: String,
+    pub created_at: String,
+    pub deadline: Option<String>,
+}
+
+/// What a guest sees of an upload link before sending a file.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UploadLinkInfo {
+    pub directory_name: String,
+    pub deadline: Option<String>,
+}
+
+/// A file in quarantine, with what flagged it.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct QuarantineEntry {
+    #[sqlx(flatten)]
+    pub file: FileMetadata,
+    pub source: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub reason: Option<String>,
+    pub quarantined_at: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct QuarantinedFileResponse {
+    #[serde(flatten)]
+    pub file: FileResponse,
+    /// What flagged the file, such as `av`, `dlp`, `moderation` or
+    /// `archive_limits`.
+    pub source: String,
+    pub reason: Option<String>,
+    pub quarantined_at: String,
+    /// Decisions about the file, oldest first; only included when a single
+    /// file is requested.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[ts(optional)]
+    pub decisions: Option<Vec<QuarantineDecision>>,
+}
+
+impl From<QuarantineEntry> for QuarantinedFileResponse {
+    fn from(entry: QuarantineEntry) -> Self {
+        Self {
+            file: entry.file.into(),
+            source: entry.source,
+            reason: entry.reason,
+            quarantined_at: entry.quarantined_at,
+            decisions: None,
+        }
+    }
+}
+
+/// Flags a file, taking it out of sight until an admin reviews it.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct QuarantineFileRequest {
+    pub file_id: String,
+    /// What flagged the file, such as `av`, `dlp` or `moderation`.
+    pub source: String,
+    #[ts(optional)]
+    pub reason: Option<String>,
+}
+
+/// An admin's decision to release or purge a quarantined file.
+#[derive(Debug, Default, Deserialize, TS, ToSchema)]
+pub struct QuarantineReviewRequest {
+    /// Who made the decision.
+    #[ts(optional)]
+    pub reviewer: Option<String>,
+    #[ts(optional)]
+    pub reason: Option<String>,
+}
+
+/// A recipient's report of abusive content behind a share link.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct ReportShareRequest {
+    /// `spam`, `malware`, `copyright`, `illegal`, `harassment` or `other`.
+    pub reason: String,
+    /// Anything the moderators should know, up to 2000 characters.
+    #[ts(optional)]
+    pub details: Option<String>,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct ReportShareResponse {
+    pub success: bool,
+    pub message: String,
+}
+
+/// An entry of the moderation queue: a report against a share link.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct ShareReport {
+    pub id: String,
+    pub share_id: String,
+    /// `file`, `directory` or `bundle`.
+    pub share_kind: String,
+    /// The shared file or directory; for a bundle, the share itself.
+    pub target_id: String,
+    pub reason: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub details: Option<String>,
+    pub reported_at: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub resolved_at: Option<String>,
+}
+
+/// An admin's decision on the reports against a share.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct ResolveShareReportRequest {
+    /// `dismiss` puts the share back up if reports took it down; `revoke`
+    /// deletes the share for good.
+    pub action: String,
+}
+
+/// An entry of the quarantine audit trail.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct QuarantineDecision {
+    pub id: String,
+    pub file_id: String,
+    pub original_filename: String,
+    /// `quarantine`, `release` or `purge`.
+    pub action: String,
+    /// The source that flagged the file, or the reviewer who released or
+    /// purged it.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub actor: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub reason: Option<String>,
+    pub decided_at: String,
+}
+
+/// A file sent through an upload link, with who sent it.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct UploadSubmission {
+    pub id: String,
+    pub file_id: String,
+    pub original_filename: String,
+    pub file_size: i64,
+    pub sender_name: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub sender_email: Option<String>,
+    pub submitted_at: String,
+}
+
+/// Everything sent through an upload link, oldest first.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct SubmissionsReport {
+    pub upload_link_id: String,
+    pub directory_id: String,
+    pub deadline: Option<String>,
+    /// Whether the deadline has passed.
+    pub closed: bool,
+    /// Distinct senders, by name and email.
+    pub sender_count: i64,
+    pub submissions: Vec<UploadSubmission>,
+}
+
+/// A ShareX custom uploader definition (`.sxcu`) that sends screenshots to
+/// `/api/quick` and copies the returned link.
+#[derive(Debug, Serialize, TS, ToSchema)]
+#[serde(rename_all = "PascalCase")]
+pub struct ShareXUploader {
+    pub version: String,
+    pub name: String,
+    pub destination_type: String,
+    pub request_method: String,
+    #[serde(rename = "RequestURL")]
+    pub request_url: String,
+    pub parameters: BTreeMap<String, String>,
+    pub headers: BTreeMap<String, String>,
+    pub body: String,
+    #[serde(rename = "URL")]
+    pub url: String,
+}
+
+/// Properties and tags read from an audio file.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct AudioMetadata {
+    pub file_id: String,
+    pub duration_secs: f64,
+    /// Average bitrate in bits per second.
+    #[ts(type = "number | null")]
+    #[sqlx(try_from = "Nullable<i64>")]
+    pub bitrate: Option<i64>,
+    #[ts(type = "number | null")]
+    #[sqlx(try_from = "Nullable<i64>")]
+    pub sample_rate: Option<i64>,
+    #[ts(type = "number | null")]
+    #[sqlx(try_from = "Nullable<i64>")]
+    pub channels: Option<i64>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub codec: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub title: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub artist: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub album: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub genre: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub year: Option<String>,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub track_number: Option<String>,
+    pub analyzed_at: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct WaveformResponse {
+    pub file_id: String,
+    pub duration_secs: f64,
+    /// Loudest sample of each equal slice of the track, scaled to 0–255.
+    pub peaks: Vec<u8>,
+}
+
+/// A related file attached to a video or audio file, such as a WebVTT
+/// subtitle track or a poster image.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow, TS, ToSchema)]
+pub struct FileAttachment {
+    pub id: String,
+    pub file_id: String,
+    /// ID of the attached file, downloadable like any other file.
+    pub attachment_id: String,
+    /// `subtitles`, `captions`, `chapters` or `poster`.
+    pub kind: String,
+    /// Language of a text track as a BCP 47 tag, e.g. `en` or `pt-BR`.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub language: Option<String>,
+    /// Name shown in the player's track menu.
+    #[sqlx(try_from = "Nullable<String>")]
+    pub label: Option<String>,
+    pub filename: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    pub mime_type: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct CreateAttachmentRequest {
+    pub attachment_id: String,
+    pub kind: String,
+    #[ts(optional)]
+    pub language: Option<String>,
+    #[ts(optional)]
+    pub label: Option<String>,
+}
+
+/// How a directory's curator wants it shown to everyone: a preferred sort
+/// order and entries pinned to the top.
+#[derive(Debug, Clone, Serialize, TS, ToSchema)]
+pub struct DirectoryArrangement {
+    pub directory_id: String,
+    /// `newest`, `oldest`, `name`, `name_desc`, `largest` or `smallest`;
+    /// `null` keeps the server's default order.
+    pub sort: Option<String>,
+    /// IDs of pinned files, directories and links, in display order.
+    pub pinned: Vec<String>,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct UpdateDirectoryArrangementRequest {
+    #[ts(optional)]
+    pub sort: Option<String>,
+    /// Entry IDs in the order they should be pinned; `[]` unpins everything.
+    pub pinned: Vec<String>,
+}
+
+/// An account that logs in with a password.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct User {
+    pub id: String,
+    /// Lowercased, so names differing only in case are the same account.
+    pub username: String,
+    /// Argon2 hash in PHC string format.
+    pub password_hash: String,
+    pub created_at: String,
+}
+
+/// Body of `POST /api/auth/register` and `POST /api/auth/login`.
+#[derive(Debug, Deserialize, TS, ToSchema)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct UserResponse {
+    pub id: String,
+    pub username: String,
+    pub created_at: String,
+    /// Whether the user is listed in `ADMIN_USERS` and may use the admin and
+    /// policy endpoints.
+    pub is_admin: bool,
+}
+
+impl UserResponse {
+    pub fn new(user: User, is_admin: bool) -> Self {
+        Self {
+            id: user.id,
+            username: user.username,
+            created_at: user.created_at,
+            is_admin,
+        }
+    }
+}
+
+/// A signed-in session, from `POST /api/auth/login`.
+#[derive(Debug, Serialize, TS, ToSchema)]
+pub struct TokenResponse {
+    /// JWT to send as `Authorization: Bearer <token>`.
+    pub token: String,
+    /// Always `Bearer`.
+    pub token_type: String,
+    pub expires_at: String,
+    pub user: UserResponse,
+}
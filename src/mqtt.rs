@@ -0,0 +1,82 @@
+use crate::config::Config;
+use crate::events::EventBus;
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use std::time::Duration;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+/// How long to wait before reconnecting after the broker connection drops.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Republishes every change event to an MQTT broker as JSON, on
+/// `<MQTT_TOPIC>/<event type>` (e.g. `fileshare/events/file_created`), so
+/// home-automation setups can react to new uploads.
+pub fn spawn(config: &Config, events: &EventBus) -> Result<(), rumqttc::OptionError> {
+    let Some(options) = options(config)? else {
+        return Ok(());
+    };
+    let (client, mut eventloop) = AsyncClient::new(options, 64);
+    let topic = config.mqtt_topic.trim_end_matches('/').to_string();
+    info!("Publishing change events to MQTT topic {}/#", topic);
+
+    // The event loop drives the connection and must be polled for anything
+    // to be sent; polling again after an error reconnects.
+    tokio::spawn(async move {
+        loop {
+            if let Err(e) = eventloop.poll().await {
+                warn!("MQTT connection error: {}", e);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+            }
+        }
+    });
+
+    let mut events = events.subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("MQTT publisher fell behind and missed {} events", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let payload = match serde_json::to_value(&event) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    error!("Failed to serialize event: {}", e);
+                    continue;
+                }
+            };
+            let event_type = payload["type"].as_str().unwrap_or("unknown");
+            let event_topic = format!("{}/{}", topic, event_type);
+            if let Err(e) = client
+                .publish(event_topic, QoS::AtLeastOnce, false, payload.to_string())
+                .await
+            {
+                error!("Failed to publish event to MQTT: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
+
+/// Connection options for `MQTT_URL`, or `None` when publishing is disabled.
+pub fn options(config: &Config) -> Result<Option<MqttOptions>, rumqttc::OptionError> {
+    config
+        .mqtt_url
+        .as_deref()
+        .map(|url| MqttOptions::parse_url(with_client_id(url)))
+        .transpose()
+}
+
+/// Brokers require a client ID; default to one unique to this process.
+fn with_client_id(url: &str) -> String {
+    if url.contains("client_id=") {
+        return url.to_string();
+    }
+    let separator = if url.contains('?') { '&' } else { '?' };
+    format!("{}{}client_id=fileshare-{}", url, separator, std::process::id())
+}
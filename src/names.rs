@@ -0,0 +1,93 @@
+use crate::config::Config;
+use unicode_normalization::UnicodeNormalization;
+
+const MAX_NAME_BYTES: usize = 255;
+
+/// Device names reserved by Windows, with or without an extension.
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+const WINDOWS_FORBIDDEN_CHARS: &[char] = &['<', '>', ':', '"', '/', '\\', '|', '?', '*'];
+
+/// Rules applied to file and directory names coming in through the API.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NamePolicy {
+    pub windows_compatible: bool,
+    pub case_insensitive: bool,
+}
+
+impl NamePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            windows_compatible: config.windows_compatible_names,
+            case_insensitive: config.case_insensitive_names,
+        }
+    }
+
+    /// Brings a client-supplied name into canonical (NFC) form, so the same
+    /// name typed on macOS (NFD) and Linux (NFC) is stored identically.
+    pub fn normalize(&self, name: &str) -> String {
+        name.nfc().collect()
+    }
+
+    /// Returns true if two names refer to the same entry under this policy.
+    pub fn same_name(&self, a: &str, b: &str) -> bool {
+        let (a, b) = (self.normalize(a), self.normalize(b));
+        if self.case_insensitive {
+            a.to_lowercase() == b.to_lowercase()
+        } else {
+            a == b
+        }
+    }
+
+    /// Validates a single path component (a file or directory name).
+    pub fn validate(&self, name: &str) -> Result<(), String> {
+        if name.trim().is_empty() {
+            return Err("Name must not be empty".to_string());
+        }
+        if name == "." || name == ".." {
+            return Err(format!("'{}' is not a valid name", name));
+        }
+        if name.len() > MAX_NAME_BYTES {
+            return Err(format!("Name must be at most {} bytes", MAX_NAME_BYTES));
+        }
+        if name.contains('/') || name.contains('\0') {
+            return Err("Name must not contain '/' or NUL characters".to_string());
+        }
+
+        if self.windows_compatible {
+            validate_windows_name(name)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_windows_name(name: &str) -> Result<(), String> {
+    if let Some(c) = name
+        .chars()
+        .find(|c| WINDOWS_FORBIDDEN_CHARS.contains(c) || c.is_ascii_control())
+    {
+        return Err(format!(
+            "Name contains the character {:?}, which is not allowed on Windows",
+            c
+        ));
+    }
+
+    if name.ends_with('.') || name.ends_with(' ') {
+        return Err("Name must not end with a dot or a space on Windows".to_string());
+    }
+
+    // "CON", "con.txt" and "Com1.tar.gz" are all reserved.
+    let stem = name.split('.').next().unwrap_or(name).trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|reserved| reserved.eq_ignore_ascii_case(stem))
+    {
+        return Err(format!("'{}' is a reserved name on Windows", name));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,329 @@
+use crate::audit::DownloadAudit;
+use crate::config::Config;
+use crate::events::ChangeEvent;
+use crate::models::{NotificationPreferences, PendingNotification};
+use crate::push::{self, PushPayload};
+use crate::storage::FileStorage;
+use lettre::message::Mailbox;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use std::collections::BTreeMap;
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{error, info, warn};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+pub const DELIVERY_IMMEDIATE: &str = "immediate";
+pub const DELIVERY_DIGEST: &str = "digest";
+
+#[derive(Clone)]
+struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+/// Turns change events into user notifications according to each user's
+/// preferences. Without `SMTP_URL`, emails are only logged.
+#[derive(Clone)]
+pub struct Notifier {
+    storage: FileStorage,
+    mailer: Option<Mailer>,
+}
+
+impl Notifier {
+    pub fn from_config(config: &Config, storage: FileStorage) -> Result<Self, BoxError> {
+        let mailer = match &config.smtp_url {
+            Some(url) => Some(Mailer {
+                transport: AsyncSmtpTransport::<Tokio1Executor>::from_url(url)?.build(),
+                from: config.smtp_from.parse()?,
+            }),
+            None => None,
+        };
+        Ok(Self { storage, mailer })
+    }
+
+    /// Starts reacting to change events and share downloads in the
+    /// background.
+    pub fn spawn(&self) {
+        let notifier = self.clone();
+        let mut downloads = self.storage.audit().subscribe();
+        tokio::spawn(async move {
+            loop {
+                let record = match downloads.recv().await {
+                    Ok(record) => record,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Notifier fell behind and missed {} downloads", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+                if record.access.share_id.is_none() {
+                    continue;
+                }
+                if let Err(e) = notifier.share_accessed(&record).await {
+                    error!("Failed to send share access notification: {}", e);
+                }
+            }
+        });
+
+        let notifier = self.clone();
+        let mut events = self.storage.events().subscribe();
+        tokio::spawn(async move {
+            loop {
+                let event = match events.recv().await {
+                    Ok(event) => event,
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("Notifier fell behind and missed {} events", missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => break,
+                };
+
+                let result = match &event.change {
+                    ChangeEvent::FileCreated {
+                        name,
+                        parent_directory_id: Some(dir_id),
+                        ..
+                    } => notifier.new_file(dir_id, name).await,
+                    ChangeEvent::JobFinished {
+                        parent_directory_id: Some(dir_id),
+                        message,
+                        ..
+                    } => notifier.job_finished(dir_id, message).await,
+                    ChangeEvent::QuotaExceeded {
+                        used_bytes,
+                        soft_limit,
+                        grace_ends_at,
+                    } => {
+                        notifier
+                            .quota_exceeded(*used_bytes, *soft_limit, grace_ends_at)
+                            .await
+                    }
+                    _ => Ok(()),
+                };
+                if let Err(e) = result {
+                    error!("Failed to send notifications: {}", e);
+                }
+            }
+        });
+    }
+
+    async fn new_file(&self, dir_id: &str, name: &str) -> Result<(), BoxError> {
+        let watchers = self.storage.folder_watchers(dir_id).await?;
+        if watchers.is_empty() {
+            return Ok(());
+        }
+
+        let dir_name = self
+            .storage
+            .get_directory(dir_id)
+            .await?
+            .map(|d| d.name)
+            .unwrap_or_else(|| dir_id.to_string());
+        let locale = self.storage.i18n().default_locale();
+        let subject = locale.message(
+            "new-file-subject",
+            &[("directory", dir_name.as_str().into())],
+        );
+        let message = locale.message(
+            "new-file-message",
+            &[
+                ("name", name.into()),
+                ("directory", dir_name.as_str().into()),
+            ],
+        );
+
+        for prefs in watchers.iter().filter(|p| p.on_new_files) {
+            if let Err(e) = self.notify(prefs, &subject, &message).await {
+                error!("Failed to notify {}: {}", prefs.user_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the user who uploaded a file downloaded through a share, if
+    /// they turned on `on_share_access`.
+    async fn share_accessed(&self, record: &DownloadAudit) -> Result<(), BoxError> {
+        let Some(owner_id) = self
+            .storage
+            .get_file_metadata(&record.file_id)
+            .await?
+            .and_then(|metadata| metadata.owner_id)
+        else {
+            return Ok(());
+        };
+        let Some(prefs) = self
+            .storage
+            .get_notification_preferences(&owner_id)
+            .await?
+            .filter(|prefs| prefs.on_share_access)
+        else {
+            return Ok(());
+        };
+
+        let locale = self.storage.i18n().default_locale();
+        let subject = locale.message(
+            "share-access-subject",
+            &[("name", record.file_name.as_str().into())],
+        );
+        let message = locale.message(
+            "share-access-message",
+            &[
+                ("name", record.file_name.as_str().into()),
+                (
+                    "network",
+                    record.access.client_ip.as_deref().unwrap_or("?").into(),
+                ),
+            ],
+        );
+        self.notify(&prefs, &subject, &message).await
+    }
+
+    /// Tells everyone watching `dir_id` that a job working on it is done.
+    async fn job_finished(&self, dir_id: &str, message: &str) -> Result<(), BoxError> {
+        let subject = self
+            .storage
+            .i18n()
+            .default_locale()
+            .text("job-finished-subject");
+        for prefs in self.storage.folder_watchers(dir_id).await? {
+            if let Err(e) = self.notify(&prefs, &subject, message).await {
+                error!("Failed to notify {}: {}", prefs.user_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Warns everyone who has set up notifications that storage is over its
+    /// soft quota, since there are no per-user quotas to single anyone out.
+    async fn quota_exceeded(
+        &self,
+        used_bytes: u64,
+        soft_limit: u64,
+        grace_ends_at: &str,
+    ) -> Result<(), BoxError> {
+        let locale = self.storage.i18n().default_locale();
+        let subject = locale.text("quota-exceeded-subject");
+        let message = locale.message(
+            "quota-exceeded-message",
+            &[
+                ("used", used_bytes.to_string().into()),
+                ("limit", soft_limit.to_string().into()),
+                ("grace_ends_at", grace_ends_at.into()),
+            ],
+        );
+        for prefs in self.storage.list_notification_preferences().await? {
+            if let Err(e) = self.notify(&prefs, &subject, &message).await {
+                error!("Failed to notify {}: {}", prefs.user_id, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Pushes `message` to the user's browsers, and emails it right away or
+    /// queues it for the next digest depending on their delivery preference.
+    pub async fn notify(
+        &self,
+        prefs: &NotificationPreferences,
+        subject: &str,
+        message: &str,
+    ) -> Result<(), BoxError> {
+        self.send_push(&prefs.user_id, subject, message).await?;
+
+        if prefs.delivery == DELIVERY_DIGEST {
+            self.storage.queue_notification(&prefs.user_id, message).await?;
+            return Ok(());
+        }
+        self.send_email(prefs, subject, message).await
+    }
+
+    /// Sends every user with queued notifications a single digest email.
+    /// A digest that can't be sent goes back in the queue for the next run.
+    pub async fn send_digests(&self) -> Result<(), BoxError> {
+        let mut by_user: BTreeMap<String, Vec<PendingNotification>> = BTreeMap::new();
+        for (user_id, notification) in self.storage.take_pending_notifications().await? {
+            by_user.entry(user_id).or_default().push(notification);
+        }
+
+        for (user_id, notifications) in by_user {
+            if let Err(e) = self.send_digest(&user_id, &notifications).await {
+                error!("Failed to send digest to {}: {}", user_id, e);
+                if let Err(e) = self
+                    .storage
+                    .requeue_notifications(&user_id, &notifications)
+                    .await
+                {
+                    error!("Failed to requeue notifications for {}: {}", user_id, e);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_digest(
+        &self,
+        user_id: &str,
+        notifications: &[PendingNotification],
+    ) -> Result<(), BoxError> {
+        let prefs = self
+            .storage
+            .get_notification_preferences(user_id)
+            .await?
+            .unwrap_or_else(|| NotificationPreferences::defaults(user_id));
+        let body = notifications
+            .iter()
+            .map(|n| format!("- {}", n.message))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let subject = self
+            .storage
+            .i18n()
+            .default_locale()
+            .message("digest-subject", &[("count", notifications.len().into())]);
+        self.send_email(&prefs, &subject, &body).await
+    }
+
+    async fn send_push(&self, user_id: &str, title: &str, body: &str) -> Result<(), BoxError> {
+        let payload = PushPayload { title, body };
+        for subscription in self.storage.list_push_subscriptions(user_id).await? {
+            // Checked again, as the host may resolve elsewhere by now
+            if let Err(e) = push::check_endpoint(&subscription.endpoint).await {
+                warn!("Not pushing to {}: {}", subscription.endpoint, e);
+                continue;
+            }
+            match self.storage.push().send(&subscription, &payload).await {
+                Ok(()) => {}
+                Err(e) if push::is_expired(&e) => {
+                    info!("Removing expired push subscription {}", subscription.id);
+                    self.storage
+                        .delete_push_subscription(user_id, &subscription.id)
+                        .await?;
+                }
+                Err(e) => warn!("Failed to push to {}: {}", subscription.endpoint, e),
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_email(
+        &self,
+        prefs: &NotificationPreferences,
+        subject: &str,
+        body: &str,
+    ) -> Result<(), BoxError> {
+        let Some(to) = prefs.email.as_deref() else {
+            return Ok(());
+        };
+        let Some(mailer) = &self.mailer else {
+            info!("Notification for {} <{}>: {}: {}", prefs.user_id, to, subject, body);
+            return Ok(());
+        };
+
+        let email = Message::builder()
+            .from(mailer.from.clone())
+            .to(to.parse()?)
+            .subject(subject)
+            .body(body.to_string())?;
+        mailer.transport.send(email).await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,309 @@
+use crate::config::Config;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, SecondsFormat, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use rand::RngCore;
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::OnceCell;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a user has at the provider between starting and finishing a
+/// login.
+const LOGIN_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Logins in progress at once; more are refused until some finish or time
+/// out, so the pending set can't be grown without bound.
+const MAX_PENDING_LOGINS: usize = 10_000;
+
+#[derive(Debug, thiserror::Error)]
+pub enum OidcError {
+    #[error("Unknown or expired login; start again")]
+    UnknownLogin,
+    #[error("Too many logins in progress; try again later")]
+    TooManyLogins,
+    #[error("Identity provider error: {0}")]
+    Provider(String),
+    #[error("Invalid ID token: {0}")]
+    InvalidToken(String),
+}
+
+impl From<reqwest::Error> for OidcError {
+    fn from(e: reqwest::Error) -> Self {
+        OidcError::Provider(e.to_string())
+    }
+}
+
+/// The parts of the provider's discovery document a login needs.
+#[derive(Debug, Deserialize)]
+struct Discovery {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenExchange {
+    id_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdClaims {
+    sub: String,
+    nonce: Option<String>,
+    preferred_username: Option<String>,
+    email: Option<String>,
+}
+
+struct PendingLogin {
+    started: Instant,
+    nonce: String,
+    verifier: String,
+    redirect_uri: String,
+}
+
+/// Who the provider says logged in.
+#[derive(Debug)]
+pub struct OidcIdentity {
+    pub issuer: String,
+    pub subject: String,
+    /// Preferred username or email, to name a new local account after.
+    pub name_hint: Option<String>,
+}
+
+/// Single sign-on through an OpenID Connect provider such as Keycloak or
+/// Auth0, with the authorization code flow and PKCE. The provider is
+/// discovered on the first login, so the server starts even while it is
+/// unreachable.
+#[derive(Clone)]
+pub struct Oidc {
+    client: Client,
+    issuer_url: String,
+    client_id: String,
+    client_secret: Option<String>,
+    redirect_url: Option<String>,
+    scopes: String,
+    post_login_redirect: Option<String>,
+    discovery: Arc<OnceCell<Discovery>>,
+    pending: Arc<Mutex<HashMap<String, PendingLogin>>>,
+}
+
+impl Oidc {
+    /// `None` unless `OIDC_ISSUER_URL` is set.
+    pub fn from_config(config: &Config) -> Result<Option<Self>, BoxError> {
+        let Some(issuer_url) = &config.oidc_issuer_url else {
+            return Ok(None);
+        };
+        let client_id = config
+            .oidc_client_id
+            .clone()
+            .ok_or("OIDC_CLIENT_ID is required with OIDC_ISSUER_URL")?;
+        Ok(Some(Self {
+            client: Client::builder().timeout(REQUEST_TIMEOUT).build()?,
+            issuer_url: issuer_url.trim_end_matches('/').to_string(),
+            client_id,
+            client_secret: config.oidc_client_secret.clone(),
+            redirect_url: config.oidc_redirect_url.clone(),
+            scopes: config.oidc_scopes.clone(),
+            post_login_redirect: config.oidc_post_login_redirect.clone(),
+            discovery: Arc::default(),
+            pending: Arc::default(),
+        }))
+    }
+
+    /// The configured callback URL, if it isn't derived from the request.
+    pub fn redirect_url(&self) -> Option<&str> {
+        self.redirect_url.as_deref()
+    }
+
+    /// `OIDC_POST_LOGIN_REDIRECT` with a new token in its fragment, which
+    /// the browser keeps out of server logs and `Referer` headers.
+    pub fn post_login_url(&self, token: &str, expires_at: DateTime<Utc>) -> Option<String> {
+        let mut url = Url::parse(self.post_login_redirect.as_deref()?).ok()?;
+        url.set_fragment(Some(&format!(
+            "token={}&token_type=Bearer&expires_at={}",
+            token,
+            expires_at.to_rfc3339_opts(SecondsFormat::Secs, true)
+        )));
+        Some(url.to_string())
+    }
+
+    async fn discovery(&self) -> Result<&Discovery, OidcError> {
+        self.discovery
+            .get_or_try_init(|| async {
+                let url = format!("{}/.well-known/openid-configuration", self.issuer_url);
+                let discovery = self
+                    .client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<Discovery>()
+                    .await?;
+                Ok(discovery)
+            })
+            .await
+    }
+
+    /// Starts a login, returning the provider URL to send the browser to.
+    /// The provider sends it back to `redirect_uri`.
+    pub async fn start_login(&self, redirect_uri: String) -> Result<String, OidcError> {
+        let discovery = self.discovery().await?;
+        let (state, nonce, verifier) = (random_token(), random_token(), random_token());
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+
+        let url = Url::parse_with_params(
+            &discovery.authorization_endpoint,
+            [
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("redirect_uri", redirect_uri.as_str()),
+                ("scope", self.scopes.as_str()),
+                ("state", state.as_str()),
+                ("nonce", nonce.as_str()),
+                ("code_challenge", challenge.as_str()),
+                ("code_challenge_method", "S256"),
+            ],
+        )
+        .map_err(|e| OidcError::Provider(format!("Invalid authorization endpoint: {}", e)))?;
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, login| login.started.elapsed() < LOGIN_TIMEOUT);
+        if pending.len() >= MAX_PENDING_LOGINS {
+            return Err(OidcError::TooManyLogins);
+        }
+        pending.insert(
+            state,
+            PendingLogin {
+                started: Instant::now(),
+                nonce,
+                verifier,
+                redirect_uri,
+            },
+        );
+        Ok(url.to_string())
+    }
+
+    /// Finishes the login `state` names by exchanging `code` for an ID
+    /// token and checking it.
+    pub async fn finish_login(&self, code: &str, state: &str) -> Result<OidcIdentity, OidcError> {
+        let login = self
+            .pending
+            .lock()
+            .unwrap()
+            .remove(state)
+            .filter(|login| login.started.elapsed() < LOGIN_TIMEOUT)
+            .ok_or(OidcError::UnknownLogin)?;
+        let discovery = self.discovery().await?;
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", login.redirect_uri.as_str()),
+            ("client_id", self.client_id.as_str()),
+            ("code_verifier", login.verifier.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            form.push(("client_secret", secret));
+        }
+        let response = self
+            .client
+            .post(&discovery.token_endpoint)
+            .form(&form)
+            .send()
+            .await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(OidcError::Provider(format!(
+                "Token exchange failed with {}: {}",
+                status, body
+            )));
+        }
+        let tokens = response.json::<TokenExchange>().await?;
+
+        let claims = self.verify_id_token(discovery, &tokens.id_token).await?;
+        if claims.nonce.as_deref() != Some(login.nonce.as_str()) {
+            return Err(OidcError::InvalidToken("nonce does not match".to_string()));
+        }
+        Ok(OidcIdentity {
+            issuer: discovery.issuer.clone(),
+            subject: claims.sub,
+            name_hint: claims.preferred_username.or(claims.email),
+        })
+    }
+
+    async fn verify_id_token(
+        &self,
+        discovery: &Discovery,
+        id_token: &str,
+    ) -> Result<IdClaims, OidcError> {
+        let invalid = |e: jsonwebtoken::errors::Error| OidcError::InvalidToken(e.to_string());
+        let header = jsonwebtoken::decode_header(id_token).map_err(invalid)?;
+
+        let key = match header.alg {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+                let secret = self.client_secret.as_deref().ok_or_else(|| {
+                    OidcError::InvalidToken("HMAC-signed without a client secret".to_string())
+                })?;
+                DecodingKey::from_secret(secret.as_bytes())
+            }
+            _ => {
+                let keys = self
+                    .client
+                    .get(&discovery.jwks_uri)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<JwkSet>()
+                    .await?;
+                let jwk = match &header.kid {
+                    Some(kid) => keys.find(kid),
+                    None => keys.keys.first(),
+                }
+                .ok_or_else(|| OidcError::InvalidToken("signing key not found".to_string()))?;
+                DecodingKey::from_jwk(jwk).map_err(invalid)?
+            }
+        };
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_audience(&[&self.client_id]);
+        validation.set_issuer(&[&discovery.issuer]);
+        let data =
+            jsonwebtoken::decode::<IdClaims>(id_token, &key, &validation).map_err(invalid)?;
+        Ok(data.claims)
+    }
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// A local username for a new single sign-on account: the provider's hint
+/// reduced to the characters usernames allow, or `user` without one.
+pub fn username_base(hint: Option<&str>) -> String {
+    let hint = hint.unwrap_or_default();
+    let hint = hint.split('@').next().unwrap_or_default();
+    let mut name: String = hint
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .take(56)
+        .collect::<String>()
+        .to_lowercase();
+    if name.len() < 3 {
+        name = "user".to_string();
+    }
+    name
+}
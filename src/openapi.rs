@@ -0,0 +1,1650 @@
+use crate::build_info;
+use crate::events::{ChangeEvent, Event, WebhookPayload};
+use crate::handlers::{
+    AccessLogQuery, ClipboardQuery, CostReportQuery, DirectoryInfoQuery, DownloadQuery, ListQuery,
+    OidcCallbackQuery, QuarantineLogQuery, QuickUploadQuery, RecentQuery, ReportQuery, SearchQuery,
+    SegmentsQuery, ShareQuery, ShareXQuery, ThumbnailQuery, UsageTreeQuery,
+};
+use crate::models::*;
+use utoipa::openapi::path::{
+    HttpMethod, OperationBuilder, Parameter, ParameterBuilder, ParameterIn, PathItem,
+};
+use utoipa::openapi::request_body::{RequestBody, RequestBodyBuilder};
+use utoipa::openapi::schema::{ArrayBuilder, ComponentsBuilder, ObjectBuilder, Type};
+use utoipa::openapi::{
+    Content, InfoBuilder, KnownFormat, OpenApi, OpenApiBuilder, PathsBuilder, Ref, RefOr, Required,
+    Response, ResponseBuilder, Schema, SchemaFormat,
+};
+use utoipa::{IntoParams, ToSchema};
+
+/// Registers every listed type as a component schema.
+macro_rules! schemas {
+    ($($model:ty),* $(,)?) => {
+        ComponentsBuilder::new()$(.schema_from::<$model>())*
+    };
+}
+
+/// The OpenAPI document describing every route of the API, from which the
+/// Python client is generated. New routes and models need to be added here
+/// to be included.
+pub fn generate() -> OpenApi {
+    let endpoints = vec![
+        // Instance information
+        (
+            HttpMethod::Get,
+            "/health",
+            op("Service", "health_check", "Health check")
+                .returns(ok_json(ObjectBuilder::new().into())),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/version",
+            op("Service", "get_version", "Version and build information")
+                .returns(json::<VersionResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/capabilities",
+            op(
+                "Service",
+                "get_capabilities",
+                "Optional features enabled on this instance",
+            )
+            .returns(json::<CapabilitiesResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/auth/register",
+            op("Accounts", "register", "Create an account")
+                .request_body(Some(body::<Credentials>()))
+                .returns(json::<UserResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/auth/login",
+            op("Accounts", "login", "Exchange a username and password for a token")
+                .request_body(Some(body::<Credentials>()))
+                .returns(json::<TokenResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/auth/me",
+            op("Accounts", "current_user", "The account the request's token belongs to")
+                .returns(json::<UserResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/auth/oidc/login",
+            op("Accounts", "oidc_login", "Log in through the OIDC provider")
+                .response_with("303", "Redirect to the provider's login page")
+                .response_with("404", "Single sign-on is not configured"),
+        ),
+        (
+            HttpMethod::Get,
+            "/auth/oidc/callback",
+            op("Accounts", "oidc_callback", "Finish logging in through the OIDC provider")
+                .parameters(Some(query::<OidcCallbackQuery>()))
+                .returns(json::<TokenResponse>())
+                .response_with("303", "Redirect to OIDC_POST_LOGIN_REDIRECT with the token"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/branding",
+            op(
+                "Service",
+                "get_branding",
+                "Logo, accent color and footer of public pages",
+            )
+            .returns(json::<Branding>()),
+        ),
+        // Files
+        (
+            HttpMethod::Get,
+            "/api/files",
+            op(
+                "Files",
+                "list_files",
+                "List a directory; `recursive=true` returns a ListTreeResponse instead",
+            )
+            .parameters(Some(query::<ListQuery>()))
+            .returns(json::<ListFilesResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/files",
+            op("Files", "upload_file", "Upload a file")
+                .parameters(Some([checksum_header()]))
+                .request_body(Some(multipart()))
+                .returns(json::<UploadResponse>())
+                .response_with("422", CHECKSUM_MISMATCH),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/recent",
+            op("Files", "list_recent_files", "Recently uploaded files")
+                .parameters(Some(query::<RecentQuery>()))
+                .returns(ok_json(ObjectBuilder::new().into())),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/search",
+            op("Files", "search_files", "Search files by name and description")
+                .parameters(Some(query::<SearchQuery>()))
+                .returns(json::<SearchResponse>())
+                .response_with("400", "The query is empty"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/compare",
+            op(
+                "Directories",
+                "compare_directories",
+                "Compare a directory tree with another directory or a client's manifest",
+            )
+            .request_body(Some(body::<CompareRequest>()))
+            .returns(json::<CompareResponse>())
+            .response_with("404", "One of the directories doesn't exist"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id",
+            op("Files", "get_file_info", "Get file metadata").returns(json::<FileResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/download",
+            op("Files", "download_file", "Download a file")
+                .parameters(Some(
+                    query::<DownloadQuery>().into_iter().chain(range_headers()),
+                ))
+                .returns(binary())
+                .response_with("206", "The requested range of the file")
+                .response_with("416", "The range lies past the end of the file"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/segments",
+            op("Files", "get_file_segments", "Get a manifest for parallel segmented download")
+                .parameters(Some(query::<SegmentsQuery>()))
+                .returns(json::<SegmentManifest>())
+                .response_with("400", "parts is outside 1 to 64"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/receipt",
+            op("Files", "get_file_receipt", "Get the signed upload receipt")
+                .returns(json::<ReceiptResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/access-log",
+            op(
+                "Files",
+                "get_file_access_log",
+                "Recent download history of a file",
+            )
+            .parameters(Some(query::<AccessLogQuery>()))
+            .returns(json::<AccessLogResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/audio",
+            op(
+                "Files",
+                "get_audio_metadata",
+                "Duration, bitrate and tags of an audio file",
+            )
+            .returns(json::<AudioMetadata>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/waveform",
+            op("Files", "get_waveform", "Waveform peaks of an audio file")
+                .returns(json::<WaveformResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/thumbnail",
+            op("Files", "get_file_thumbnail", "JPEG thumbnail of an image")
+                .parameters(Some(
+                    query::<ThumbnailQuery>().into_iter().chain([if_none_match()]),
+                ))
+                .returns(binary())
+                .response_with("304", "The cached copy is current")
+                .response_with("415", "The file is not an image")
+                .response_with("501", "Thumbnails are not enabled on this server"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/files/:id/share",
+            op("Shares", "share_file", "Create a public link to a file, optionally expiring or limited to a number of downloads")
+                .request_body(Some(body::<CreateShareRequest>()))
+                .returns(json::<ShareResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/shares",
+            op("Shares", "list_file_shares", "List a file's share links, expired ones included")
+                .returns(json_list::<ShareResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/files/:id/attachments",
+            op(
+                "Files",
+                "create_file_attachment",
+                "Attach a subtitle track or poster",
+            )
+            .request_body(Some(body::<CreateAttachmentRequest>()))
+            .returns(json::<FileAttachment>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/files/:id/attachments/:attachment_id",
+            op("Files", "delete_file_attachment", "Remove an attachment")
+                .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/files/:id",
+            op("Files", "delete_file", "Move a file to the trash").returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Patch,
+            "/api/files/:id",
+            op(
+                "Files",
+                "update_file",
+                "Change a file's description, tags or expiry, or move it",
+            )
+            .parameters(Some([if_match()]))
+            .request_body(Some(body::<UpdateFileRequest>()))
+            .returns(json::<FileResponse>())
+            .response_with("404", "The file or the target directory doesn't exist"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/files/:id/move",
+            op("Files", "move_file", "Move a file to another directory")
+                .parameters(Some([if_match()]))
+                .request_body(Some(body::<MoveFileRequest>()))
+                .returns(json::<FileResponse>())
+                .response_with("404", "The file or the target directory doesn't exist"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/versions",
+            op("Files", "list_file_versions", "List earlier versions of a file, newest first")
+                .returns(json_list::<FileVersion>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/files/:id/versions",
+            op("Files", "upload_file_version", "Upload new content for a file, keeping the current content as a version")
+                .parameters(Some([if_match(), checksum_header()]))
+                .request_body(Some(version_form()))
+                .returns(json::<UploadResponse>())
+                .response_with("409", "The file changed since it was fetched, or it is registered in place from disk")
+                .response_with("422", CHECKSUM_MISMATCH),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/files/:id/versions/:version_id/download",
+            op("Files", "download_file_version", "Download an earlier version of a file")
+                .returns(binary()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/files/:id/versions/:version_id/restore",
+            op("Files", "restore_file_version", "Make an earlier version the file's content again")
+                .parameters(Some([if_match()]))
+                .returns(json::<FileResponse>())
+                .response_with("409", "The file changed since it was fetched"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/trash",
+            op("Files", "list_trash", "List deleted files and directories")
+                .returns(json_list::<TrashItem>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/trash/:id/restore",
+            op("Files", "restore_from_trash", "Restore a file or directory from the trash")
+                .returns(json::<TrashItem>())
+                .response_with("404", "The item is not in the trash")
+                .response_with("409", "Its directory is in the trash, or the name is taken"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/bulk-delete",
+            op(
+                "Files",
+                "bulk_delete",
+                "Delete several files and directories",
+            )
+            .request_body(Some(body::<BulkDeleteRequest>()))
+            .returns(json::<BulkDeleteResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/bulk-move",
+            op(
+                "Files",
+                "bulk_move",
+                "Move several files and directories at once",
+            )
+            .request_body(Some(body::<BulkTransferRequest>()))
+            .returns(json::<BulkMoveResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/bulk-copy",
+            op(
+                "Files",
+                "bulk_copy",
+                "Copy several files and directories at once",
+            )
+            .request_body(Some(body::<BulkTransferRequest>()))
+            .returns(json::<BulkCopyResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/bulk-update",
+            op(
+                "Files",
+                "bulk_update",
+                "Change tags, description or expiry of several files",
+            )
+            .request_body(Some(body::<BulkUpdateRequest>()))
+            .returns(json::<BulkUpdateResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/batch",
+            op(
+                "Files",
+                "apply_batch",
+                "Apply mkdir, move, rename and delete steps all or nothing",
+            )
+            .request_body(Some(body::<BatchRequest>()))
+            .returns(json::<BatchResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/ingest/tar",
+            op(
+                "Files",
+                "ingest_tar",
+                "Unpack a tar or tar.gz body into a directory",
+            )
+            .parameters(Some(query::<ListQuery>()))
+            .request_body(Some(octet_stream()))
+            .returns(json::<IngestResponse>())
+            .response_with(
+                "422",
+                "ArchiveLimitExceeded: the archive went past one of the ARCHIVE_MAX_* limits",
+            ),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/quick",
+            op(
+                "Files",
+                "quick_upload",
+                "Upload a raw image and get a public short link back",
+            )
+            .parameters(Some(query::<QuickUploadQuery>()))
+            .request_body(Some(raw_body("image/*")))
+            .returns(plain_text("Public link to the image"))
+            .response_with("422", CHECKSUM_MISMATCH),
+        ),
+        (
+            HttpMethod::Get,
+            "/s/:token",
+            op(
+                "Shares",
+                "open_share",
+                "Open a public share: a file, or the index of a directory",
+            )
+                .parameters(Some(
+                    query::<ShareQuery>()
+                        .into_iter()
+                        .chain(range_headers())
+                        .chain([if_none_match()]),
+                ))
+                .returns(binary())
+                .response_with("206", "The requested range of the file")
+                .response_with("304", "The cached copy is current")
+                .response_with("416", "The range lies past the end of the file")
+                .response_with(
+                    "307",
+                    "Redirect to a signed CDN URL for the file, when a CDN is configured",
+                )
+                .response_with(
+                    "403",
+                    "ShareTermsRequired (or an HTML page for browsers) until the share's terms are accepted, \
+                     or DownloadConfirmationRequired until a risky file type is confirmed",
+                ),
+        ),
+        (
+            HttpMethod::Post,
+            "/s/:token/report",
+            op("Shares", "report_share", "Report abusive content behind a share link")
+                .request_body(Some(body::<ReportShareRequest>()))
+                .returns(json::<ReportShareResponse>())
+                .response_with("400", "The reason is not one of the accepted ones"),
+        ),
+        (
+            HttpMethod::Get,
+            "/cdn/:token",
+            op(
+                "Shares",
+                "open_cdn_origin",
+                "Fetch a shared file for the CDN share links redirect to",
+            )
+            .parameters(Some(range_headers().into_iter().chain([if_none_match()])))
+            .returns(binary())
+            .response_with("206", "The requested range of the file")
+            .response_with("304", "The cached copy is current")
+            .response_with("416", "The range lies past the end of the file"),
+        ),
+        (
+            HttpMethod::Get,
+            "/u/:token",
+            op(
+                "Shares",
+                "open_upload_link",
+                "Open a guest upload link: an upload form for browsers",
+            )
+            .returns(json::<UploadLinkInfo>())
+            .response_with("410", "The link's deadline has passed"),
+        ),
+        (
+            HttpMethod::Post,
+            "/u/:token",
+            op(
+                "Shares",
+                "submit_upload",
+                "Send a file through a guest upload link",
+            )
+            .request_body(Some(guest_upload_form()))
+            .returns(json::<UploadSubmission>())
+            .response_with("410", "The link's deadline has passed"),
+        ),
+        (
+            HttpMethod::Post,
+            "/drop/:token",
+            op("Shares", "drop_files", "Send files through an upload-only drop link")
+                .request_body(Some(drop_form()))
+                .returns(json::<DropReceipt>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/shares/bundles",
+            op(
+                "Shares",
+                "share_bundle",
+                "Publish one link to a set of files, with a list page and a ZIP of them all",
+            )
+            .request_body(Some(body::<CreateBundleShareRequest>()))
+            .returns(json::<BundleShareResponse>())
+            .response_with("404", "One of the files doesn't exist"),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/shares/:id",
+            op("Shares", "revoke_share", "Revoke a file, directory or bundle share, or a drop link")
+                .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Put,
+            "/api/shares/:id/terms",
+            op(
+                "Shares",
+                "set_share_terms",
+                "Require recipients to accept terms before downloading",
+            )
+            .request_body(Some(body::<SetShareTermsRequest>()))
+            .returns(json::<ShareTerms>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/integrations/sharex",
+            op(
+                "Files",
+                "sharex_uploader",
+                "ShareX custom uploader for the quick-upload endpoint",
+            )
+            .parameters(Some(query::<ShareXQuery>()))
+            .returns(json::<ShareXUploader>()),
+        ),
+        // Chunked uploads
+        (
+            HttpMethod::Post,
+            "/api/uploads",
+            op(
+                "Uploads",
+                "create_upload_session",
+                "Start a chunked upload sized for the client's network",
+            )
+            .request_body(Some(body::<CreateUploadSessionRequest>()))
+            .returns(json::<UploadSessionResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/uploads/:id",
+            op("Uploads", "get_upload_session", "Get an upload session")
+                .returns(json::<UploadSessionResponse>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/uploads/:id",
+            op(
+                "Uploads",
+                "delete_upload_session",
+                "Cancel an upload session",
+            )
+            .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Put,
+            "/api/uploads/:id/chunks/:index",
+            op(
+                "Uploads",
+                "upload_session_chunk",
+                "Upload or retry one chunk",
+            )
+            .request_body(Some(octet_stream()))
+            .returns(json::<UploadSessionResponse>())
+            .response_with("422", CHECKSUM_MISMATCH),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/uploads/:id/complete",
+            op(
+                "Uploads",
+                "complete_upload_session",
+                "Assemble the chunks into a file",
+            )
+            .parameters(Some([checksum_header()]))
+            .returns(json::<UploadResponse>())
+            .response_with("422", CHECKSUM_MISMATCH),
+        ),
+        // Directories
+        (
+            HttpMethod::Post,
+            "/api/directories",
+            op("Directories", "create_directory", "Create a directory")
+                .request_body(Some(body::<CreateDirectoryRequest>()))
+                .returns(json::<CreateDirectoryResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/directories/:id",
+            op(
+                "Directories",
+                "get_directory_info",
+                "Get directory metadata",
+            )
+            .parameters(Some(query::<DirectoryInfoQuery>()))
+            .returns(json::<DirectoryResponse>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/directories/:id",
+            op(
+                "Directories",
+                "delete_directory",
+                "Move a directory and its contents to the trash",
+            )
+            .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Patch,
+            "/api/directories/:id",
+            op(
+                "Directories",
+                "update_directory",
+                "Move a directory or set its color and icon",
+            )
+            .parameters(Some([if_match()]))
+            .request_body(Some(body::<MoveDirectoryRequest>()))
+            .returns(json::<DirectoryResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/directories/:id/move",
+            op(
+                "Directories",
+                "move_directory",
+                "Move a directory and everything in it into another directory",
+            )
+            .parameters(Some([if_match()]))
+            .request_body(Some(body::<MoveDirectoryToRequest>()))
+            .returns(json::<DirectoryResponse>())
+            .response_with("400", "The target is the directory itself or beneath it")
+            .response_with("404", "The directory or the target doesn't exist")
+            .response_with("409", "The target has a directory of the same name"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/directories/:id/download",
+            op(
+                "Directories",
+                "download_directory",
+                "Download a directory as a ZIP archive",
+            )
+            .returns(binary()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/directories/:id/archive",
+            op(
+                "Directories",
+                "create_archive_job",
+                "Build a directory's ZIP archive in the background, to download by ticket",
+            )
+            .returns(("202", json::<ArchiveJobResponse>().1)),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/archives/:id",
+            op("Directories", "get_archive_job", "Check on a directory archive job")
+                .returns(json::<ArchiveJobResponse>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/archives/:id",
+            op(
+                "Directories",
+                "delete_archive_job",
+                "Cancel a directory archive job or delete its archive",
+            )
+            .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/z/:ticket",
+            op(
+                "Directories",
+                "download_archive",
+                "Download a finished directory archive by its ticket",
+            )
+            .parameters(Some(range_headers().into_iter().take(1)))
+            .returns(binary())
+            .response_with("206", "The requested range of the archive")
+            .response_with("416", "The range lies past the end of the archive"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/directories/:id/share",
+            op(
+                "Shares",
+                "share_directory",
+                "Publish a read-only HTML index of a directory tree",
+            )
+            .returns(json::<DirectoryShareResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/directories/:id/upload-links",
+            op(
+                "Shares",
+                "create_upload_link",
+                "Create a guest upload link into a directory, with an optional deadline",
+            )
+            .request_body(Some(body::<CreateUploadLinkRequest>()))
+            .returns(json::<UploadLinkResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/directories/:id/drop-links",
+            op("Shares", "list_drop_links", "List a directory's drop links, expired ones included")
+                .returns(json_list::<DropLinkResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/directories/:id/drop-links",
+            op(
+                "Shares",
+                "create_drop_link",
+                "Create an upload-only link into a directory, without listing or download rights",
+            )
+            .request_body(Some(body::<CreateDropLinkRequest>()))
+            .returns(json::<DropLinkResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/upload-links/:id/submissions",
+            op(
+                "Shares",
+                "list_submissions",
+                "Report of the files sent through an upload link and who sent them",
+            )
+            .returns(json::<SubmissionsReport>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/directories/:id/arrangement",
+            op(
+                "Directories",
+                "get_directory_arrangement",
+                "Get a directory's sort order and pins",
+            )
+            .returns(json::<DirectoryArrangement>()),
+        ),
+        (
+            HttpMethod::Put,
+            "/api/directories/:id/arrangement",
+            op(
+                "Directories",
+                "set_directory_arrangement",
+                "Set a directory's sort order and pins",
+            )
+            .request_body(Some(body::<UpdateDirectoryArrangementRequest>()))
+            .returns(json::<DirectoryArrangement>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/directories/:id/integrations",
+            op(
+                "Directories",
+                "list_directory_integrations",
+                "List a directory's chat integrations",
+            )
+            .returns(json_list::<DirectoryIntegration>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/directories/:id/integrations",
+            op(
+                "Directories",
+                "create_directory_integration",
+                "Post new files in a directory to a chat service",
+            )
+            .request_body(Some(body::<CreateDirectoryIntegrationRequest>()))
+            .returns(json::<DirectoryIntegration>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/directories/:id/integrations/:integration_id",
+            op(
+                "Directories",
+                "delete_directory_integration",
+                "Remove a chat integration",
+            )
+            .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/directories/:id/acl",
+            op(
+                "Directories",
+                "get_directory_acl",
+                "List who may access a directory",
+            )
+            .returns(json::<DirectoryAclResponse>()),
+        ),
+        (
+            HttpMethod::Put,
+            "/api/directories/:id/acl/:user_id",
+            op(
+                "Directories",
+                "set_directory_acl",
+                "Give a user a role in a directory",
+            )
+            .request_body(Some(body::<SetDirectoryAclRequest>()))
+            .returns(json::<DirectoryAclResponse>())
+            .response_with("409", "The access list would be left without an owner"),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/directories/:id/acl/:user_id",
+            op(
+                "Directories",
+                "delete_directory_acl",
+                "Remove a user from a directory's access list",
+            )
+            .returns(json::<DirectoryAclResponse>())
+            .response_with("409", "The access list would be left without an owner"),
+        ),
+        // Links
+        (
+            HttpMethod::Post,
+            "/api/links",
+            op("Links", "create_link", "Create a link entry")
+                .request_body(Some(body::<CreateLinkRequest>()))
+                .returns(json::<LinkResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/links/:id",
+            op("Links", "get_link_info", "Get link details").returns(json::<LinkResponse>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/links/:id",
+            op("Links", "delete_link", "Delete a link").returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/links/:id/open",
+            op("Links", "open_link", "Follow a link")
+                .response_with("303", "Redirect to the link's target"),
+        ),
+        // Retention and reports
+        (
+            HttpMethod::Get,
+            "/api/retention-policies",
+            op(
+                "Retention",
+                "list_retention_policies",
+                "List retention policies",
+            )
+            .returns(json_list::<RetentionPolicy>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/retention-policies",
+            op(
+                "Retention",
+                "create_retention_policy",
+                "Create a retention policy",
+            )
+            .request_body(Some(body::<CreateRetentionPolicyRequest>()))
+            .returns(json::<RetentionPolicy>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/retention-policies/:id",
+            op(
+                "Retention",
+                "delete_retention_policy",
+                "Delete a retention policy",
+            )
+            .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/thumbnail-policies",
+            op("Thumbnails", "list_thumbnail_policies", "List thumbnail policies")
+                .returns(json_list::<ThumbnailPolicy>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/thumbnail-policies",
+            op("Thumbnails", "create_thumbnail_policy", "Pre-generate thumbnails of uploads to a directory")
+                .request_body(Some(body::<CreateThumbnailPolicyRequest>()))
+                .returns(json::<ThumbnailPolicy>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/thumbnail-policies/:id",
+            op("Thumbnails", "delete_thumbnail_policy", "Delete a thumbnail policy")
+                .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/usage-tree",
+            op(
+                "Admin",
+                "usage_tree",
+                "Recursive storage usage per directory",
+            )
+            .parameters(Some(query::<UsageTreeQuery>()))
+            .returns(json::<UsageNode>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/quota",
+            op("Admin", "get_quota", "Storage use against the quota")
+                .returns(json::<QuotaStatus>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/write-queue",
+            op("Admin", "get_write_queue", "Upload data received but not yet written to disk")
+                .returns(json::<WriteQueueStats>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/upload-cleanup",
+            op("Admin", "get_upload_cleanup", "Disk space reclaimed from abandoned uploads")
+                .returns(json::<UploadCleanupStats>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/database",
+            op("Admin", "get_database_stats", "Database size and dead space")
+                .returns(json::<DatabaseStats>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/database/maintenance",
+            op(
+                "Admin",
+                "run_database_maintenance",
+                "Checkpoint, VACUUM and ANALYZE the database now",
+            )
+            .returns(json::<DatabaseMaintenance>())
+            .response_with("409", "Maintenance is already running"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/disk-benchmark",
+            op("Admin", "disk_benchmark", "Measure sequential throughput of the upload volume")
+                .request_body(Some(body::<DiskBenchmarkRequest>()))
+                .returns(json::<DiskBenchmark>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/cost-report",
+            op(
+                "Admin",
+                "cost_report",
+                "Estimated monthly storage and egress cost",
+            )
+            .parameters(Some(query::<CostReportQuery>()))
+            .returns(json::<CostReport>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/users/:user_id/export",
+            op(
+                "Admin",
+                "export_user_data",
+                "Export everything stored about a user as a ZIP archive of export.json (a UserDataExport) and their files",
+            )
+            .returns(binary()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/users/:user_id/erase",
+            op(
+                "Admin",
+                "erase_user_data",
+                "Erase everything stored about a user",
+            )
+            .returns(json::<UserErasureReport>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/reports/:report",
+            op(
+                "Admin",
+                "file_report",
+                "Largest, least-accessed or never-downloaded files",
+            )
+            .parameters(Some(query::<ReportQuery>()))
+            .returns(json::<FileReportResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/quarantine",
+            op("Admin", "list_quarantine", "List quarantined files")
+                .returns(json_list::<QuarantinedFileResponse>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/quarantine",
+            op("Admin", "quarantine_file", "Put a flagged file in quarantine")
+                .request_body(Some(body::<QuarantineFileRequest>()))
+                .returns(json::<QuarantinedFileResponse>())
+                .response_with("404", "No such file")
+                .response_with("409", "The file is already in quarantine"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/quarantine/:file_id",
+            op("Admin", "get_quarantined_file", "Inspect a quarantined file and its audit trail")
+                .returns(json::<QuarantinedFileResponse>())
+                .response_with("404", "The file is not in quarantine"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/quarantine/:file_id/content",
+            op("Admin", "download_quarantined_file", "Download a quarantined file to review it")
+                .returns(binary())
+                .response_with("404", "The file is not in quarantine"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/quarantine/:file_id/release",
+            op("Admin", "release_quarantined_file", "Release a quarantined file")
+                .request_body(Some(body::<QuarantineReviewRequest>()))
+                .returns(json::<FileResponse>())
+                .response_with("404", "The file is not in quarantine"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/quarantine/:file_id/purge",
+            op("Admin", "purge_quarantined_file", "Delete a quarantined file for good")
+                .request_body(Some(body::<QuarantineReviewRequest>()))
+                .returns(json::<DeleteResponse>())
+                .response_with("404", "The file is not in quarantine"),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/quarantine-log",
+            op("Admin", "quarantine_log", "Audit trail of quarantine decisions")
+                .parameters(Some(query::<QuarantineLogQuery>()))
+                .returns(json_list::<QuarantineDecision>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/admin/moderation",
+            op("Admin", "list_share_reports", "Open reports against share links, oldest first")
+                .returns(json_list::<ShareReport>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/moderation/:id/resolve",
+            op("Admin", "resolve_share_report", "Dismiss the reports against a share, or revoke it")
+                .request_body(Some(body::<ResolveShareReportRequest>()))
+                .returns(json::<ShareReport>())
+                .response_with("409", "The report was already resolved"),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/admin/webhooks/:id/replay",
+            op("Admin", "replay_webhook", "Send an integration's failed deliveries again")
+                .returns(json::<WebhookReplayResponse>()),
+        ),
+        // Notifications
+        (
+            HttpMethod::Get,
+            "/api/users/:user_id/notification-preferences",
+            op(
+                "Notifications",
+                "get_notification_preferences",
+                "Get a user's notification settings",
+            )
+            .returns(json::<NotificationPreferences>()),
+        ),
+        (
+            HttpMethod::Put,
+            "/api/users/:user_id/notification-preferences",
+            op(
+                "Notifications",
+                "update_notification_preferences",
+                "Update a user's notification settings",
+            )
+            .request_body(Some(body::<UpdateNotificationPreferencesRequest>()))
+            .returns(json::<NotificationPreferences>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/users/:user_id/watched-folders",
+            op(
+                "Notifications",
+                "list_watched_folders",
+                "List folders a user watches",
+            )
+            .returns(json_list::<WatchedFolder>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/users/:user_id/watched-folders",
+            op(
+                "Notifications",
+                "watch_folder",
+                "Watch a folder for new files",
+            )
+            .request_body(Some(body::<WatchFolderRequest>()))
+            .returns(json::<WatchedFolder>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/users/:user_id/watched-folders/:directory_id",
+            op("Notifications", "unwatch_folder", "Stop watching a folder")
+                .returns(json::<DeleteResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/push/public-key",
+            op(
+                "Notifications",
+                "get_vapid_public_key",
+                "VAPID public key for Web Push subscriptions",
+            )
+            .returns(json::<VapidPublicKeyResponse>()),
+        ),
+        (
+            HttpMethod::Get,
+            "/api/users/:user_id/push-subscriptions",
+            op(
+                "Notifications",
+                "list_push_subscriptions",
+                "List a user's push subscriptions",
+            )
+            .returns(json_list::<PushSubscription>()),
+        ),
+        (
+            HttpMethod::Post,
+            "/api/users/:user_id/push-subscriptions",
+            op(
+                "Notifications",
+                "create_push_subscription",
+                "Register a browser push subscription",
+            )
+            .request_body(Some(body::<CreatePushSubscriptionRequest>()))
+            .returns(json::<PushSubscription>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/users/:user_id/push-subscriptions/:id",
+            op(
+                "Notifications",
+                "delete_push_subscription",
+                "Remove a push subscription",
+            )
+            .returns(json::<DeleteResponse>()),
+        ),
+        // Clipboard
+        (
+            HttpMethod::Get,
+            "/api/clipboard",
+            op(
+                "Clipboard",
+                "get_clipboard",
+                "Get a user's or LAN token's clipboard",
+            )
+            .parameters(Some(query::<ClipboardQuery>()))
+            .returns(json::<ClipboardEntry>()),
+        ),
+        (
+            HttpMethod::Put,
+            "/api/clipboard",
+            op(
+                "Clipboard",
+                "set_clipboard",
+                "Replace the clipboard with a short-lived text",
+            )
+            .parameters(Some(query::<ClipboardQuery>()))
+            .request_body(Some(body::<SetClipboardRequest>()))
+            .returns(json::<ClipboardEntry>()),
+        ),
+        (
+            HttpMethod::Delete,
+            "/api/clipboard",
+            op("Clipboard", "clear_clipboard", "Clear the clipboard")
+                .parameters(Some(query::<ClipboardQuery>()))
+                .returns(json::<DeleteResponse>()),
+        ),
+        // Change events
+        (
+            HttpMethod::Get,
+            "/api/events",
+            op(
+                "Events",
+                "stream_events",
+                "Server-Sent Events stream of changes",
+            )
+            .response_with("200", "`text/event-stream` of ChangeEvent objects"),
+        ),
+    ];
+
+    let mut paths = PathsBuilder::new();
+    for (method, path, operation) in endpoints {
+        let operation = operation
+            .parameters(Some(path_parameters(path)))
+            .response("default", error_response())
+            .build();
+        paths = paths.path(openapi_path(path), PathItem::new(method, operation));
+    }
+
+    let components = schemas![
+        FileResponse,
+        UploadReceipt,
+        UploadResponse,
+        ReceiptResponse,
+        IngestResponse,
+        ArchiveLimitExceeded,
+        ListFilesResponse,
+        TreeEntry,
+        ListTreeResponse,
+        ManifestEntry,
+        CompareRequest,
+        ComparedFile,
+        CompareResponse,
+        MoveFileRequest,
+        UpdateFileRequest,
+        CreateUploadSessionRequest,
+        UploadSessionResponse,
+        Directory,
+        DirectoryResponse,
+        DirectoryReadme,
+        CreateDirectoryRequest,
+        CreateDirectoryResponse,
+        MoveDirectoryRequest,
+        MoveDirectoryToRequest,
+        Link,
+        LinkResponse,
+        CreateLinkRequest,
+        BulkDeleteRequest,
+        BulkDeleteResponse,
+        BulkTransferRequest,
+        BulkItemResult,
+        BulkMoveResponse,
+        BulkCopyResponse,
+        BulkUpdateRequest,
+        BulkUpdateResponse,
+        BatchOperation,
+        BatchRequest,
+        BatchOperationResult,
+        BatchResponse,
+        DeleteResponse,
+        ErrorResponse,
+        RetentionPolicy,
+        CreateRetentionPolicyRequest,
+        ThumbnailPolicy,
+        CreateThumbnailPolicyRequest,
+        UsageNode,
+        QuotaStatus,
+        WriteQueueStats,
+        UploadCleanupStats,
+        ArchiveJobResponse,
+        SegmentManifest,
+        FileSegment,
+        FileVersion,
+        WebhookReplayResponse,
+        ReportShareRequest,
+        ReportShareResponse,
+        ShareReport,
+        ResolveShareReportRequest,
+        TrashItem,
+        DatabaseStats,
+        DatabaseMaintenance,
+        DiskBenchmarkRequest,
+        DiskBenchmark,
+        CostEstimate,
+        DirectoryCost,
+        ShareCost,
+        CostReport,
+        FileUsageResponse,
+        FileReportResponse,
+        SearchMatch,
+        SearchResponse,
+        AccessLogEntry,
+        AccessLogResponse,
+        NotificationPreferences,
+        UpdateNotificationPreferencesRequest,
+        WatchedFolder,
+        WatchFolderRequest,
+        PendingNotification,
+        OidcIdentity,
+        ArchiveJob,
+        UserDataExport,
+        UserErasureReport,
+        PushSubscription,
+        PushSubscriptionKeys,
+        CreatePushSubscriptionRequest,
+        VapidPublicKeyResponse,
+        DirectoryIntegration,
+        CreateDirectoryIntegrationRequest,
+        DirectoryArrangement,
+        UpdateDirectoryArrangementRequest,
+        DirectoryAclEntry,
+        DirectoryAclResponse,
+        SetDirectoryAclRequest,
+        CreateShareRequest,
+        ShareResponse,
+        SetShareTermsRequest,
+        ShareTerms,
+        DirectoryShareResponse,
+        CreateBundleShareRequest,
+        BundleShareResponse,
+        CreateUploadLinkRequest,
+        UploadLinkResponse,
+        UploadLinkInfo,
+        UploadSubmission,
+        CreateDropLinkRequest,
+        DropLinkResponse,
+        DroppedFile,
+        DropReceipt,
+        SubmissionsReport,
+        QuarantinedFileResponse,
+        QuarantineFileRequest,
+        QuarantineReviewRequest,
+        QuarantineDecision,
+        ShareTermsRequired,
+        DownloadConfirmationRequired,
+        ShareXUploader,
+        AudioMetadata,
+        WaveformResponse,
+        FileAttachment,
+        CreateAttachmentRequest,
+        ClipboardEntry,
+        SetClipboardRequest,
+        Capabilities,
+        Branding,
+        CapabilitiesResponse,
+        VersionResponse,
+        Credentials,
+        UserResponse,
+        TokenResponse,
+        ChangeEvent,
+        Event,
+        WebhookPayload,
+    ];
+
+    OpenApiBuilder::new()
+        .info(
+            InfoBuilder::new()
+                .title("Fileshare API")
+                .version(build_info::VERSION)
+                .description(Some(
+                    "File transfer backend: uploads, downloads, directories and links.",
+                )),
+        )
+        .paths(paths)
+        .components(Some(components.build()))
+        .build()
+}
+
+/// Runs `fileshare generate-openapi [FILE]`, writing to stdout when no file
+/// is given. Returns the process exit code.
+pub fn run_command(output: Option<&str>) -> i32 {
+    let spec = match generate().to_pretty_json() {
+        Ok(spec) => spec + "\n",
+        Err(e) => {
+            eprintln!("Failed to serialize the OpenAPI document: {}", e);
+            return 1;
+        }
+    };
+    let Some(path) = output else {
+        print!("{}", spec);
+        return 0;
+    };
+
+    match std::fs::write(path, spec) {
+        Ok(()) => {
+            eprintln!("Wrote OpenAPI document to {}", path);
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path, e);
+            1
+        }
+    }
+}
+
+/// Lets each endpoint in the table read as a single builder expression.
+trait OperationExt {
+    fn returns(self, response: (&str, Response)) -> Self;
+    fn response_with(self, code: &str, description: &str) -> Self;
+}
+
+impl OperationExt for OperationBuilder {
+    fn returns(self, (code, response): (&str, Response)) -> Self {
+        self.response(code, response)
+    }
+
+    fn response_with(self, code: &str, description: &str) -> Self {
+        self.response(
+            code,
+            ResponseBuilder::new().description(description).build(),
+        )
+    }
+}
+
+fn op(tag: &str, operation_id: &str, summary: &str) -> OperationBuilder {
+    OperationBuilder::new()
+        .tag(tag)
+        .operation_id(Some(operation_id))
+        .summary(Some(summary))
+}
+
+/// Converts axum's `:param` segments to OpenAPI's `{param}`.
+fn openapi_path(path: &str) -> String {
+    path.split('/')
+        .map(|segment| match segment.strip_prefix(':') {
+            Some(name) => format!("{{{}}}", name),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn path_parameters(path: &str) -> Vec<Parameter> {
+    path.split('/')
+        .filter_map(|segment| segment.strip_prefix(':'))
+        .map(|name| {
+            ParameterBuilder::new()
+                .name(name)
+                .parameter_in(ParameterIn::Path)
+                .required(Required::True)
+                .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+                .build()
+        })
+        .collect()
+}
+
+/// The `If-Match` header changes of a file or directory require.
+fn if_match() -> Parameter {
+    ParameterBuilder::new()
+        .name("If-Match")
+        .parameter_in(ParameterIn::Header)
+        .required(Required::True)
+        .description(Some(
+            "`ETag` from the last fetch; a stale one is refused with 409",
+        ))
+        .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+        .build()
+}
+
+/// The optional `Range` and `If-Range` headers of a download.
+fn range_headers() -> [Parameter; 2] {
+    let header = |name: &str, description: &str| {
+        ParameterBuilder::new()
+            .name(name)
+            .parameter_in(ParameterIn::Header)
+            .required(Required::False)
+            .description(Some(description))
+            .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+            .build()
+    };
+    [
+        header("Range", "A single byte range, e.g. `bytes=0-1023`"),
+        header(
+            "If-Range",
+            "`ETag` of an earlier response; the range is ignored if the file has changed",
+        ),
+    ]
+}
+
+/// Response to an upload whose content doesn't match the checksum sent
+/// with it.
+const CHECKSUM_MISMATCH: &str =
+    "ErrorResponse: the content doesn't match the X-Checksum-SHA256 sent with it";
+
+/// The optional `X-Checksum-SHA256` header an upload is checked against.
+fn checksum_header() -> Parameter {
+    ParameterBuilder::new()
+        .name("X-Checksum-SHA256")
+        .parameter_in(ParameterIn::Header)
+        .required(Required::False)
+        .description(Some(
+            "Hex SHA-256 of the whole file; the file is refused with 422 if it doesn't match",
+        ))
+        .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+        .build()
+}
+
+/// The optional `If-None-Match` header caches revalidate with.
+fn if_none_match() -> Parameter {
+    ParameterBuilder::new()
+        .name("If-None-Match")
+        .parameter_in(ParameterIn::Header)
+        .required(Required::False)
+        .description(Some(
+            "`ETag` of a cached copy; answered with 304 while it is current",
+        ))
+        .schema(Some(ObjectBuilder::new().schema_type(Type::String)))
+        .build()
+}
+
+fn query<T: IntoParams>() -> Vec<Parameter> {
+    T::into_params(|| Some(ParameterIn::Query))
+}
+
+fn schema_ref<T: ToSchema>() -> RefOr<Schema> {
+    Ref::from_schema_name(T::name()).into()
+}
+
+fn ok_json(schema: RefOr<Schema>) -> (&'static str, Response) {
+    (
+        "200",
+        ResponseBuilder::new()
+            .description("Success")
+            .content("application/json", Content::new(Some(schema)))
+            .build(),
+    )
+}
+
+fn json<T: ToSchema>() -> (&'static str, Response) {
+    ok_json(schema_ref::<T>())
+}
+
+fn json_list<T: ToSchema>() -> (&'static str, Response) {
+    ok_json(ArrayBuilder::new().items(schema_ref::<T>()).into())
+}
+
+fn binary() -> (&'static str, Response) {
+    (
+        "200",
+        ResponseBuilder::new()
+            .description("File contents")
+            .content(
+                "application/octet-stream",
+                Content::new(Some(binary_schema())),
+            )
+            .build(),
+    )
+}
+
+fn error_response() -> Response {
+    ResponseBuilder::new()
+        .description("Error")
+        .content(
+            "application/json",
+            Content::new(Some(schema_ref::<ErrorResponse>())),
+        )
+        .build()
+}
+
+fn body<T: ToSchema>() -> RequestBody {
+    RequestBodyBuilder::new()
+        .content("application/json", Content::new(Some(schema_ref::<T>())))
+        .required(Some(Required::True))
+        .build()
+}
+
+fn multipart() -> RequestBody {
+    let form = ObjectBuilder::new()
+        .property("file", binary_schema())
+        .required("file")
+        .property(
+            "description",
+            ObjectBuilder::new().schema_type(Type::String),
+        )
+        .property(
+            "parent_directory_id",
+            ObjectBuilder::new().schema_type(Type::String),
+        )
+        .property(
+            "checksum",
+            ObjectBuilder::new()
+                .schema_type(Type::String)
+                .description(Some(
+                    "Hex SHA-256 of the file, like the X-Checksum-SHA256 header; may come \
+                     before or after the file",
+                )),
+        );
+    RequestBodyBuilder::new()
+        .content("multipart/form-data", Content::new(Some(form)))
+        .required(Some(Required::True))
+        .build()
+}
+
+/// New content for an existing file; it keeps its name and directory.
+fn version_form() -> RequestBody {
+    let form = ObjectBuilder::new()
+        .property("file", binary_schema())
+        .required("file")
+        .property(
+            "checksum",
+            ObjectBuilder::new()
+                .schema_type(Type::String)
+                .description(Some(
+                    "Hex SHA-256 of the file, like the X-Checksum-SHA256 header; may come \
+                     before or after the file",
+                )),
+        );
+    RequestBodyBuilder::new()
+        .content("multipart/form-data", Content::new(Some(form)))
+        .required(Some(Required::True))
+        .build()
+}
+
+/// The form sent to a guest upload link; the sender fields come first.
+fn guest_upload_form() -> RequestBody {
+    let form = ObjectBuilder::new()
+        .property(
+            "sender_name",
+            ObjectBuilder::new().schema_type(Type::String),
+        )
+        .required("sender_name")
+        .property(
+            "sender_email",
+            ObjectBuilder::new().schema_type(Type::String),
+        )
+        .property("file", binary_schema())
+        .required("file");
+    RequestBodyBuilder::new()
+        .content("multipart/form-data", Content::new(Some(form)))
+        .required(Some(Required::True))
+        .build()
+}
+
+/// The form posted to a drop link: one or more `file` fields.
+fn drop_form() -> RequestBody {
+    let form = ObjectBuilder::new()
+        .property("file", ArrayBuilder::new().items(binary_schema()))
+        .required("file");
+    RequestBodyBuilder::new()
+        .content("multipart/form-data", Content::new(Some(form)))
+        .required(Some(Required::True))
+        .build()
+}
+
+fn octet_stream() -> RequestBody {
+    raw_body("application/octet-stream")
+}
+
+fn raw_body(content_type: &str) -> RequestBody {
+    RequestBodyBuilder::new()
+        .content(content_type, Content::new(Some(binary_schema())))
+        .required(Some(Required::True))
+        .build()
+}
+
+fn plain_text(description: &str) -> (&'static str, Response) {
+    (
+        "200",
+        ResponseBuilder::new()
+            .description(description)
+            .content(
+                "text/plain",
+                Content::new(Some(ObjectBuilder::new().schema_type(Type::String))),
+            )
+            .build(),
+    )
+}
+
+fn binary_schema() -> ObjectBuilder {
+    ObjectBuilder::new()
+        .schema_type(Type::String)
+        .format(Some(SchemaFormat::KnownFormat(KnownFormat::Binary)))
+}
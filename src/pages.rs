@@ -0,0 +1,352 @@
+use crate::config::Config;
+use crate::i18n::Locale;
+use crate::models::{Branding, Directory, FileMetadata};
+use chrono::DateTime;
+
+/// Branding of public pages, from `BRANDING_LOGO_URL`,
+/// `BRANDING_ACCENT_COLOR` and `BRANDING_FOOTER_TEXT`.
+pub fn branding(config: &Config) -> Branding {
+    Branding {
+        logo_url: config.branding_logo_url.clone(),
+        accent_color: config.branding_accent_color.trim().to_string(),
+        footer_text: config.branding_footer_text.clone(),
+    }
+}
+
+/// Whether `value` is a `#rgb` or `#rrggbb` color, the only form the accent
+/// color may take since it goes into the page's stylesheet.
+pub fn is_css_color(value: &str) -> bool {
+    value
+        .trim()
+        .strip_prefix('#')
+        .is_some_and(|hex| matches!(hex.len(), 3 | 6) && hex.chars().all(|c| c.is_ascii_hexdigit()))
+}
+
+/// Escapes text for use in HTML element content and quoted attributes.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Wraps already-escaped body markup in a minimal standalone page, with
+/// the instance's logo, accent color and footer.
+fn page(branding: &Branding, locale: &Locale, title: &str, body: &str) -> String {
+    let header = match &branding.logo_url {
+        Some(url) => format!(
+            r#"<header><img class="logo" src="{}" alt=""></header>"#,
+            escape_html(url)
+        ),
+        None => String::new(),
+    };
+    let footer = match &branding.footer_text {
+        Some(text) => format!("<footer>{}</footer>", escape_html(text)),
+        None => String::new(),
+    };
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="{lang}">
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>{title}</title>
+<style>
+body {{ font-family: system-ui, sans-serif; max-width: 48rem; margin: 2rem auto; padding: 0 1rem; color: #222; }}
+a {{ color: {accent}; }}
+.logo {{ max-height: 3rem; }}
+footer {{ margin-top: 2rem; padding-top: 1rem; border-top: 1px solid #eee; color: #555; font-size: 0.875rem; }}
+.terms {{ white-space: pre-wrap; border: 1px solid #ccc; border-radius: 4px; padding: 1rem; max-height: 60vh; overflow: auto; }}
+table {{ width: 100%; border-collapse: collapse; }}
+th, td {{ text-align: left; padding: 0.25rem 0.5rem; border-bottom: 1px solid #eee; }}
+td.size, td.date {{ white-space: nowrap; color: #555; }}
+.button {{ display: inline-block; margin-top: 1rem; padding: 0.5rem 1rem; background: {accent}; color: #fff; border: none; border-radius: 4px; font: inherit; text-decoration: none; cursor: pointer; }}
+label {{ display: block; margin-top: 1rem; }}
+label input {{ display: block; margin-top: 0.25rem; }}
+</style>
+</head>
+<body>
+{header}
+{body}
+{footer}
+</body>
+</html>
+"#,
+        lang = locale.language(),
+        title = escape_html(title),
+        accent = branding.accent_color,
+        header = header,
+        body = body,
+        footer = footer
+    )
+}
+
+/// The interstitial shown in place of a shared file until its terms are
+/// accepted.
+pub fn terms_page(
+    branding: &Branding,
+    locale: &Locale,
+    file_name: &str,
+    terms: &str,
+    accept_url: &str,
+) -> String {
+    let body = format!(
+        r#"<h1>{name}</h1>
+<p>{intro}</p>
+<div class="terms">{terms}</div>
+<a class="button" href="{accept_url}">{accept}</a>"#,
+        name = escape_html(file_name),
+        intro = escape_html(&locale.text("terms-intro")),
+        terms = escape_html(terms),
+        accept_url = escape_html(accept_url),
+        accept = escape_html(&locale.text("terms-accept"))
+    );
+    page(branding, locale, file_name, &body)
+}
+
+/// The interstitial shown in place of a shared file whose type could run
+/// on the recipient's machine, until they confirm the download.
+pub fn download_warning_page(
+    branding: &Branding,
+    locale: &Locale,
+    file_name: &str,
+    extension: &str,
+    confirm_url: &str,
+) -> String {
+    let warning = locale.message("download-warning", &[("extension", extension.into())]);
+    let body = format!(
+        r#"<h1>{name}</h1>
+<p>{warning}</p>
+<a class="button" href="{confirm_url}">{confirm}</a>"#,
+        name = escape_html(file_name),
+        warning = escape_html(&warning),
+        confirm_url = escape_html(confirm_url),
+        confirm = escape_html(&locale.text("download-confirm"))
+    );
+    page(branding, locale, file_name, &body)
+}
+
+/// A page showing a single error message, for browsers opening public links.
+pub fn error_page(branding: &Branding, locale: &Locale, message: &str) -> String {
+    let title = locale.text("error-title");
+    let body = format!(
+        "<h1>{}</h1>\n<p>{}</p>",
+        escape_html(&title),
+        escape_html(message)
+    );
+    page(branding, locale, &title, &body)
+}
+
+/// The form guests send a file through an upload link with, giving their
+/// name and optionally their email first. Works without JavaScript.
+pub fn upload_page(
+    branding: &Branding,
+    locale: &Locale,
+    directory_name: &str,
+    deadline: Option<&str>,
+    action: &str,
+) -> String {
+    let heading = locale.message("upload-heading", &[("directory", directory_name.into())]);
+    let deadline = match deadline {
+        Some(deadline) => format!(
+            "<p>{}</p>\n",
+            escape_html(&locale.message(
+                "upload-deadline",
+                &[("deadline", format_date(deadline).into())]
+            ))
+        ),
+        None => String::new(),
+    };
+    let body = format!(
+        r#"<h1>{heading}</h1>
+{deadline}<form method="post" action="{action}" enctype="multipart/form-data">
+<label>{name}<input type="text" name="sender_name" required maxlength="200"></label>
+<label>{email}<input type="email" name="sender_email" maxlength="254"></label>
+<label>{file}<input type="file" name="file" required></label>
+<button class="button" type="submit">{submit}</button>
+</form>"#,
+        heading = escape_html(&heading),
+        deadline = deadline,
+        action = escape_html(action),
+        name = escape_html(&locale.text("upload-name")),
+        email = escape_html(&locale.text("upload-email")),
+        file = escape_html(&locale.text("upload-file")),
+        submit = escape_html(&locale.text("upload-submit"))
+    );
+    page(branding, locale, &heading, &body)
+}
+
+/// Confirms a guest's file arrived, with a way back to the form.
+pub fn upload_received_page(
+    branding: &Branding,
+    locale: &Locale,
+    file_name: &str,
+    form_url: &str,
+) -> String {
+    let message = locale.message("upload-received", &[("name", file_name.into())]);
+    let body = format!(
+        r#"<h1>{message}</h1>
+<a class="button" href="{form_url}">{another}</a>"#,
+        message = escape_html(&message),
+        form_url = escape_html(form_url),
+        another = escape_html(&locale.text("upload-another"))
+    );
+    page(branding, locale, &message, &body)
+}
+
+/// A classic autoindex of one directory of a directory share: a breadcrumb
+/// from the shared directory down to `chain`'s last entry, then its
+/// subdirectories and files with sizes and dates. Works without JavaScript.
+pub fn directory_index(
+    branding: &Branding,
+    locale: &Locale,
+    token: &str,
+    chain: &[Directory],
+    directories: &[Directory],
+    files: &[FileMetadata],
+) -> String {
+    let dir_url = |index: usize, id: &str| {
+        if index == 0 {
+            format!("/s/{}", token)
+        } else {
+            format!("/s/{}?dir={}", token, id)
+        }
+    };
+
+    let breadcrumb: Vec<String> = chain
+        .iter()
+        .enumerate()
+        .map(|(index, dir)| {
+            if index + 1 == chain.len() {
+                escape_html(&dir.name)
+            } else {
+                format!(
+                    r#"<a href="{}">{}</a>"#,
+                    escape_html(&dir_url(index, &dir.id)),
+                    escape_html(&dir.name)
+                )
+            }
+        })
+        .collect();
+
+    let mut rows = Vec::new();
+    if chain.len() > 1 {
+        let parent = &chain[chain.len() - 2];
+        rows.push(format!(
+            r#"<tr><td><a href="{}">../</a></td><td class="size">-</td><td class="date"></td></tr>"#,
+            escape_html(&dir_url(chain.len() - 2, &parent.id))
+        ));
+    }
+    for dir in directories {
+        rows.push(format!(
+            r#"<tr><td><a href="{}">{}/</a></td><td class="size">-</td><td class="date">{}</td></tr>"#,
+            escape_html(&dir_url(chain.len(), &dir.id)),
+            escape_html(&dir.name),
+            format_date(&dir.updated_at)
+        ));
+    }
+    for file in files {
+        rows.push(format!(
+            r#"<tr><td><a href="{}">{}</a></td><td class="size">{}</td><td class="date">{}</td></tr>"#,
+            escape_html(&format!("/s/{}?file={}", token, file.id)),
+            escape_html(&file.original_filename),
+            format_size(file.file_size),
+            format_date(&file.uploaded_at)
+        ));
+    }
+
+    let body = format!(
+        r#"<h1>{heading} {breadcrumb}</h1>
+<table>
+<thead><tr><th>{name}</th><th>{size}</th><th>{modified}</th></tr></thead>
+<tbody>
+{rows}
+</tbody>
+</table>"#,
+        heading = escape_html(&locale.text("index-heading")),
+        breadcrumb = breadcrumb.join(" / "),
+        name = escape_html(&locale.text("column-name")),
+        size = escape_html(&locale.text("column-size")),
+        modified = escape_html(&locale.text("column-modified")),
+        rows = rows.join("\n")
+    );
+    let title = chain.last().map(|dir| dir.name.as_str()).unwrap_or("Index");
+    page(branding, locale, title, &body)
+}
+
+/// The list of files of a bundle share, each downloadable on its own, with
+/// a button downloading them all as one archive. Works without JavaScript.
+pub fn bundle_index(
+    branding: &Branding,
+    locale: &Locale,
+    token: &str,
+    title: Option<&str>,
+    files: &[FileMetadata],
+) -> String {
+    let rows: Vec<String> = files
+        .iter()
+        .map(|file| {
+            format!(
+                r#"<tr><td><a href="{}">{}</a></td><td class="size">{}</td><td class="date">{}</td></tr>"#,
+                escape_html(&format!("/s/{}?file={}", token, file.id)),
+                escape_html(&file.original_filename),
+                format_size(file.file_size),
+                format_date(&file.uploaded_at)
+            )
+        })
+        .collect();
+
+    let heading = match title {
+        Some(title) => title.to_string(),
+        None => locale.message("bundle-heading", &[("count", files.len().into())]),
+    };
+    let body = format!(
+        r#"<h1>{heading}</h1>
+<table>
+<thead><tr><th>{name}</th><th>{size}</th><th>{modified}</th></tr></thead>
+<tbody>
+{rows}
+</tbody>
+</table>
+<a class="button" href="{archive_url}">{download_all}</a>"#,
+        heading = escape_html(&heading),
+        name = escape_html(&locale.text("column-name")),
+        size = escape_html(&locale.text("column-size")),
+        modified = escape_html(&locale.text("column-modified")),
+        rows = rows.join("\n"),
+        archive_url = escape_html(&format!("/s/{}?archive=true", token)),
+        download_all = escape_html(&locale.text("bundle-download-all"))
+    );
+    page(branding, locale, &heading, &body)
+}
+
+/// Formats a byte count with binary units, e.g. `1.5 MiB`.
+fn format_size(bytes: i64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Formats an RFC 3339 timestamp to the minute, in UTC.
+fn format_date(timestamp: &str) -> String {
+    match DateTime::parse_from_rfc3339(timestamp) {
+        Ok(date) => date.naive_utc().format("%Y-%m-%d %H:%M").to_string(),
+        Err(_) => escape_html(timestamp),
+    }
+}
@@ -0,0 +1,73 @@
+use crate::config::Config;
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::Method,
+    middleware::Next,
+    response::Response,
+};
+use futures_util::StreamExt;
+use std::sync::Arc;
+use tokio::sync::Semaphore;
+
+/// Separate budgets of requests in progress for file transfers, which run
+/// for as long as the file takes, and for the rest of the API. Transfers
+/// past their budget wait their turn, so a few multi-GB uploads or
+/// downloads can't slow down the metadata requests a UI is waiting on.
+#[derive(Debug, Clone)]
+pub struct Budgets {
+    transfers: Arc<Semaphore>,
+    interactive: Arc<Semaphore>,
+}
+
+impl Budgets {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            transfers: Arc::new(Semaphore::new(config.transfer_concurrency.max(1))),
+            interactive: Arc::new(Semaphore::new(config.interactive_concurrency.max(1))),
+        }
+    }
+}
+
+/// Whether a request streams file content in or out: uploads, chunks,
+/// assembling chunked uploads, downloads and public shares.
+fn is_transfer(method: &Method, path: &str) -> bool {
+    let last = path.trim_end_matches('/').rsplit('/').next().unwrap_or_default();
+    match *method {
+        Method::GET => path.starts_with("/s/") || matches!(last, "download" | "content"),
+        Method::POST => {
+            matches!(path, "/api/files" | "/api/quick" | "/api/ingest/tar")
+                || path.starts_with("/u/")
+                || path.starts_with("/drop/")
+                || (path.starts_with("/api/uploads/") && last == "complete")
+        }
+        Method::PUT => path.starts_with("/api/uploads/") && path.contains("/chunks/"),
+        _ => false,
+    }
+}
+
+/// Runs each request within its budget. A transfer's share is held until
+/// its response body has been sent, since downloads stream after the
+/// handler returns.
+pub async fn schedule(State(budgets): State<Budgets>, request: Request, next: Next) -> Response {
+    if !is_transfer(request.method(), request.uri().path()) {
+        let _permit = budgets
+            .interactive
+            .acquire()
+            .await
+            .expect("budget semaphores are never closed");
+        return next.run(request).await;
+    }
+
+    let permit = budgets
+        .transfers
+        .acquire_owned()
+        .await
+        .expect("budget semaphores are never closed");
+    let (parts, body) = next.run(request).await.into_parts();
+    let body = body.into_data_stream().map(move |chunk| {
+        let _held = &permit;
+        chunk
+    });
+    Response::from_parts(parts, Body::from_stream(body))
+}
@@ -0,0 +1,188 @@
+use crate::config::Config;
+use crate::models::PushSubscription;
+use axum::http::Uri;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use tracing::info;
+use web_push::{
+    ContentEncoding, HyperWebPushClient, PartialVapidSignatureBuilder, SubscriptionInfo,
+    VapidSignatureBuilder, WebPushClient, WebPushError, WebPushMessageBuilder,
+};
+
+const KEY_FILE_NAME: &str = ".vapid_private_key";
+
+/// What the service worker receives; it decides how to display it.
+#[derive(Debug, Serialize)]
+pub struct PushPayload<'a> {
+    pub title: &'a str,
+    pub body: &'a str,
+}
+
+/// Sends Web Push notifications signed with the server's VAPID key.
+#[derive(Clone)]
+pub struct PushSender {
+    vapid: PartialVapidSignatureBuilder,
+    subject: String,
+    client: HyperWebPushClient,
+}
+
+impl PushSender {
+    /// Uses `VAPID_PRIVATE_KEY` when set, otherwise a key generated on first
+    /// start and kept in the upload directory, so browser subscriptions
+    /// survive restarts.
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        let invalid = |_| io::Error::new(io::ErrorKind::InvalidData, "Invalid VAPID private key");
+        let vapid = match &config.vapid_private_key {
+            Some(key) => VapidSignatureBuilder::from_base64_no_sub(key).map_err(invalid)?,
+            None => Self::load_or_generate_key(config)?,
+        };
+
+        Ok(Self {
+            vapid,
+            subject: config.vapid_subject.clone(),
+            client: HyperWebPushClient::new(),
+        })
+    }
+
+    fn load_or_generate_key(config: &Config) -> io::Result<PartialVapidSignatureBuilder> {
+        fs::create_dir_all(&config.upload_dir)?;
+        let key_path = config.upload_dir.join(KEY_FILE_NAME);
+        if let Ok(existing) = fs::read_to_string(&key_path) {
+            if let Ok(vapid) = VapidSignatureBuilder::from_base64_no_sub(existing.trim()) {
+                return Ok(vapid);
+            }
+        }
+
+        // Almost every 32-byte string is a valid P-256 private key; retry the
+        // rare one that isn't.
+        loop {
+            let mut key = [0u8; 32];
+            rand::thread_rng().fill_bytes(&mut key);
+            let encoded = URL_SAFE_NO_PAD.encode(key);
+            if let Ok(vapid) = VapidSignatureBuilder::from_base64_no_sub(&encoded) {
+                fs::write(&key_path, &encoded)?;
+                info!("Generated VAPID key at {:?}", key_path);
+                return Ok(vapid);
+            }
+        }
+    }
+
+    /// The `applicationServerKey` browsers need to subscribe.
+    pub fn public_key(&self) -> String {
+        URL_SAFE_NO_PAD.encode(self.vapid.get_public_key())
+    }
+
+    pub async fn send(
+        &self,
+        subscription: &PushSubscription,
+        payload: &PushPayload<'_>,
+    ) -> Result<(), WebPushError> {
+        let info = SubscriptionInfo::new(
+            subscription.endpoint.as_str(),
+            subscription.p256dh.as_str(),
+            subscription.auth.as_str(),
+        );
+        let mut signature = self.vapid.clone().add_sub_info(&info);
+        signature.add_claim("sub", self.subject.as_str());
+
+        let content = serde_json::to_vec(payload).unwrap_or_default();
+        let mut message = WebPushMessageBuilder::new(&info);
+        message.set_payload(ContentEncoding::Aes128Gcm, &content);
+        message.set_vapid_signature(signature.build()?);
+        self.client.send(message.build()?).await
+    }
+}
+
+/// True when the push service says the subscription no longer exists.
+pub fn is_expired(error: &WebPushError) -> bool {
+    matches!(
+        error,
+        WebPushError::EndpointNotValid(_) | WebPushError::EndpointNotFound(_)
+    )
+}
+
+/// Checks that a subscription endpoint is an https URL whose host resolves
+/// to public addresses only. Push services are on the internet, and anything
+/// else would let a subscription make the server send requests into its own
+/// network, such as a cloud metadata service.
+pub async fn check_endpoint(endpoint: &str) -> Result<(), String> {
+    let uri: Uri = endpoint
+        .parse()
+        .map_err(|_| "endpoint must be an https URL".to_string())?;
+    let host = match (uri.scheme_str(), uri.host()) {
+        (Some("https"), Some(host)) => host,
+        _ => return Err("endpoint must be an https URL".to_string()),
+    };
+    let public = resolves_publicly(host, uri.port_u16().unwrap_or(443))
+        .await
+        .map_err(|e| format!("Failed to resolve the endpoint's host: {}", e))?;
+    if !public {
+        return Err("endpoint must be on a public host".to_string());
+    }
+    Ok(())
+}
+
+/// Like `check_endpoint`, for the http(s) URLs directory integrations post
+/// to.
+pub async fn check_url(url: &str) -> Result<(), String> {
+    let uri: Uri = url
+        .parse()
+        .map_err(|_| "url must be an http(s) URL".to_string())?;
+    let (host, default_port) = match (uri.scheme_str(), uri.host()) {
+        (Some("https"), Some(host)) => (host, 443),
+        (Some("http"), Some(host)) => (host, 80),
+        _ => return Err("url must be an http(s) URL".to_string()),
+    };
+    let public = resolves_publicly(host, uri.port_u16().unwrap_or(default_port))
+        .await
+        .map_err(|e| format!("Failed to resolve the URL's host: {}", e))?;
+    if !public {
+        return Err("url must be on a public host".to_string());
+    }
+    Ok(())
+}
+
+/// Whether `host` resolves, and only to public addresses.
+async fn resolves_publicly(host: &str, port: u16) -> io::Result<bool> {
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let addresses: Vec<IpAddr> = tokio::net::lookup_host((host, port))
+        .await?
+        .map(|address| address.ip())
+        .collect();
+    Ok(!addresses.is_empty() && addresses.into_iter().all(is_public))
+}
+
+/// False for loopback, private, link-local, shared (CGNAT), unspecified,
+/// broadcast, documentation and multicast addresses.
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                || first == 0
+                || (first == 100 && (second & 0xc0) == 64))
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || (first & 0xfe00) == 0xfc00
+                    || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
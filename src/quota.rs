@@ -0,0 +1,214 @@
+use crate::config::Config;
+use crate::models::QuotaStatus;
+use crate::storage::FileStorage;
+use axum::{
+    extract::{Request, State},
+    http::{HeaderValue, Method},
+    middleware::Next,
+    response::Response,
+};
+use chrono::{DateTime, Duration, Utc};
+use std::path::Path;
+use tracing::error;
+
+/// Row of `quota_grace_periods` tracking the instance-wide quota.
+pub const INSTANCE_SCOPE: &str = "instance";
+
+/// Header carrying a warning on responses while storage is over its soft
+/// quota.
+pub const WARNING_HEADER: &str = "x-quota-warning";
+
+/// Limits on the total size of stored files. Past the soft limit, uploads
+/// are still accepted, with warnings, until the grace period runs out;
+/// nothing that would take storage past the hard limit is accepted.
+#[derive(Debug, Clone, Copy)]
+pub struct QuotaPolicy {
+    pub soft_limit: Option<u64>,
+    pub hard_limit: Option<u64>,
+    pub grace_period: Duration,
+}
+
+impl QuotaPolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            soft_limit: config.quota_soft_limit,
+            hard_limit: config.quota_hard_limit,
+            grace_period: Duration::seconds(config.quota_grace_period_secs as i64),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.soft_limit.is_some() || self.hard_limit.is_some()
+    }
+
+    pub fn is_over_soft_limit(&self, used_bytes: u64) -> bool {
+        self.soft_limit.is_some_and(|limit| used_bytes > limit)
+    }
+
+    /// Where `used_bytes` stands, given when storage went over the soft
+    /// limit.
+    pub fn status(&self, used_bytes: u64, over_since: Option<DateTime<Utc>>) -> QuotaStatus {
+        let grace_ends_at = over_since.map(|since| since + self.grace_period);
+        let grace_expired = grace_ends_at.is_some_and(|ends| Utc::now() >= ends);
+        let under_hard = self.hard_limit.is_none_or(|limit| used_bytes < limit);
+        QuotaStatus {
+            used_bytes,
+            soft_limit: self.soft_limit,
+            hard_limit: self.hard_limit,
+            over_soft_limit: over_since.is_some(),
+            grace_ends_at: grace_ends_at.map(|ends| ends.to_rfc3339()),
+            accepting_uploads: under_hard && !grace_expired,
+        }
+    }
+}
+
+/// Why an upload can't be stored, whatever the quota says.
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum StorageFull {
+    #[error("Storage is full: {used_bytes} of the {limit} bytes allowed are in use")]
+    TotalLimit { used_bytes: u64, limit: u64 },
+    #[error(
+        "Storage is full: {free_bytes} bytes of disk space left, {reserved} of which are kept free"
+    )]
+    DiskSpace { free_bytes: u64, reserved: u64 },
+}
+
+/// What the upload directory can hold: at most `MAX_TOTAL_STORAGE` in
+/// total, and never so much that the disk has less than `MIN_FREE_DISK`
+/// left.
+#[derive(Debug, Clone, Copy)]
+pub struct Capacity {
+    pub max_total: Option<u64>,
+    pub min_free: u64,
+}
+
+impl Capacity {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            max_total: config.max_total_storage,
+            min_free: config.min_free_disk,
+        }
+    }
+
+    /// Room for an upload to `dir` while `used_bytes` are stored, measuring
+    /// the disk's free space once for the whole upload.
+    pub fn headroom(&self, dir: &Path, used_bytes: u64) -> Headroom {
+        Headroom {
+            capacity: *self,
+            used_bytes,
+            free_bytes: free_space(dir),
+        }
+    }
+}
+
+/// Room left for one upload, measured before it starts.
+#[derive(Debug, Clone, Copy)]
+pub struct Headroom {
+    capacity: Capacity,
+    used_bytes: u64,
+    free_bytes: Option<u64>,
+}
+
+impl Headroom {
+    /// Refuses an upload once `incoming` bytes of it no longer fit, rather
+    /// than letting the disk fill up while it is written.
+    pub fn check(&self, incoming: u64) -> Result<(), StorageFull> {
+        if let Some(limit) = self.capacity.max_total {
+            if self.used_bytes >= limit || self.used_bytes + incoming > limit {
+                return Err(StorageFull::TotalLimit {
+                    used_bytes: self.used_bytes,
+                    limit,
+                });
+            }
+        }
+        if let Some(free_bytes) = self.free_bytes {
+            let reserved = self.capacity.min_free;
+            if free_bytes <= reserved || incoming > free_bytes - reserved {
+                return Err(StorageFull::DiskSpace {
+                    free_bytes,
+                    reserved,
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Free space on the filesystem holding `dir`, if the platform tells.
+#[cfg(unix)]
+pub fn free_space(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat: libc::statvfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `path` is NUL-terminated and `stat` is a valid out-pointer.
+    if unsafe { libc::statvfs(path.as_ptr(), &mut stat) } != 0 {
+        return None;
+    }
+    #[allow(clippy::unnecessary_cast)]
+    Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(not(unix))]
+pub fn free_space(_dir: &Path) -> Option<u64> {
+    None
+}
+
+/// Refuses an upload of `incoming` bytes that the quota doesn't allow.
+pub fn check_upload(status: &QuotaStatus, incoming: u64) -> Result<(), String> {
+    if let Some(limit) = status.hard_limit {
+        if status.used_bytes >= limit || status.used_bytes + incoming > limit {
+            return Err(format!(
+                "Upload would exceed the storage quota: {} of {} bytes used",
+                status.used_bytes, limit
+            ));
+        }
+    }
+    if !status.accepting_uploads {
+        return Err(format!(
+            "Storage is over its soft quota of {} bytes and the grace period ended at {}; delete files to upload again",
+            status.soft_limit.unwrap_or_default(),
+            status.grace_ends_at.as_deref().unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+/// What to tell a client while storage is over its soft quota.
+pub fn warning(status: &QuotaStatus) -> Option<String> {
+    let grace_ends_at = status.grace_ends_at.as_deref()?;
+    Some(format!(
+        "Storage is over its soft quota ({} of {} bytes used); uploads will be refused after {}",
+        status.used_bytes,
+        status.soft_limit.unwrap_or_default(),
+        grace_ends_at
+    ))
+}
+
+/// Adds the quota warning to successful changes while storage is over its
+/// soft quota. Checking after each change is also what notices storage
+/// going over, or back under, the soft limit.
+pub async fn warning_header(
+    State(storage): State<FileStorage>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let is_change = !matches!(
+        *request.method(),
+        Method::GET | Method::HEAD | Method::OPTIONS
+    );
+    let mut response = next.run(request).await;
+    if !is_change || !response.status().is_success() || !storage.quota().is_enabled() {
+        return response;
+    }
+
+    match storage.quota_status().await {
+        Ok(status) => {
+            if let Some(value) = warning(&status).and_then(|w| HeaderValue::from_str(&w).ok()) {
+                response.headers_mut().insert(WARNING_HEADER, value);
+            }
+        }
+        Err(e) => error!("Failed to check storage quota: {}", e),
+    }
+    response
+}
@@ -0,0 +1,132 @@
+use crate::models::DirectoryReadme;
+use crate::storage::FileStorage;
+use futures_util::StreamExt;
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Name of the file shown as a directory's README, matched case-insensitively.
+const README_NAME: &str = "README.md";
+/// Longest README included in a response; longer ones are cut off.
+const README_MAX_BYTES: usize = 256 * 1024;
+/// URL schemes links and images may use; relative URLs are always allowed.
+const SAFE_URL_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadmeFormat {
+    Html,
+    Markdown,
+}
+
+impl ReadmeFormat {
+    pub const NAMES: &'static [&'static str] = &["html", "markdown"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "html" => Some(Self::Html),
+            "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Html => "html",
+            Self::Markdown => "markdown",
+        }
+    }
+}
+
+/// Loads the README of a directory, if it has one, as raw markdown or
+/// rendered HTML.
+pub async fn load(
+    storage: &FileStorage,
+    dir_id: &str,
+    format: ReadmeFormat,
+) -> Result<Option<DirectoryReadme>, Box<dyn std::error::Error + Send + Sync>> {
+    let files = storage.list_files(Some(dir_id.to_string())).await?;
+    let Some(file) = files
+        .into_iter()
+        .find(|f| f.original_filename.eq_ignore_ascii_case(README_NAME))
+    else {
+        return Ok(None);
+    };
+
+    let mut content = storage.open_content(&file).await?;
+    let mut bytes = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = content.next().await {
+        let chunk = chunk?;
+        let room = README_MAX_BYTES - bytes.len();
+        if chunk.len() > room {
+            bytes.extend_from_slice(&chunk[..room]);
+            truncated = true;
+            break;
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    // A cut may split a UTF-8 sequence; the lossy conversion drops it.
+    let markdown = String::from_utf8_lossy(&bytes);
+    let markdown = markdown.trim_end_matches(char::REPLACEMENT_CHARACTER);
+
+    Ok(Some(DirectoryReadme {
+        file_id: file.id,
+        format: format.as_str().to_string(),
+        content: match format {
+            ReadmeFormat::Html => render_html(markdown),
+            ReadmeFormat::Markdown => markdown.to_string(),
+        },
+        truncated,
+    }))
+}
+
+/// Renders markdown to HTML that is safe to insert into a page: raw HTML
+/// in the source is escaped rather than passed through, and links or
+/// images with a scheme such as `javascript:` lose their target.
+pub fn render_html(markdown: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let events = Parser::new_ext(markdown, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Link {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Link {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => Event::Start(Tag::Image {
+            link_type,
+            dest_url: safe_url(dest_url),
+            title,
+            id,
+        }),
+        event => event,
+    });
+
+    let mut output = String::new();
+    html::push_html(&mut output, events);
+    output
+}
+
+fn safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    // A scheme ends at the first ':' that comes before any '/', '?' or '#'.
+    let scheme = url
+        .split(['/', '?', '#'])
+        .next()
+        .and_then(|head| head.split_once(':'))
+        .map(|(scheme, _)| scheme.trim().to_ascii_lowercase());
+    match scheme {
+        Some(scheme) if !SAFE_URL_SCHEMES.contains(&scheme.as_str()) => CowStr::Borrowed(""),
+        _ => url,
+    }
+}
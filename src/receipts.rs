@@ -0,0 +1,84 @@
+use crate::config::Config;
+use crate::models::UploadReceipt;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use tracing::info;
+
+pub const SIGNATURE_ALGORITHM: &str = "HMAC-SHA256";
+
+/// Scan status recorded on receipts while no scanner is configured.
+pub const SCAN_STATUS_NOT_SCANNED: &str = "not_scanned";
+
+/// Scan status of files put in quarantine, such as those unpacked from an
+/// archive that went past its limits.
+pub const SCAN_STATUS_SUSPICIOUS: &str = "suspicious";
+
+/// Scan status of files an admin has reviewed and released from
+/// quarantine.
+pub const SCAN_STATUS_RELEASED: &str = "released";
+
+const KEY_FILE_NAME: &str = ".receipt_signing_key";
+
+/// Signs upload receipts so they can later be checked for tampering.
+#[derive(Clone)]
+pub struct ReceiptSigner {
+    key: Vec<u8>,
+}
+
+impl ReceiptSigner {
+    /// Uses `RECEIPT_SIGNING_KEY` when set, otherwise a random key persisted
+    /// in the upload directory so receipts stay verifiable across restarts.
+    pub fn from_config(config: &Config) -> io::Result<Self> {
+        if let Some(key) = &config.receipt_signing_key {
+            return Ok(Self {
+                key: key.as_bytes().to_vec(),
+            });
+        }
+
+        fs::create_dir_all(&config.upload_dir)?;
+        let key_path = config.upload_dir.join(KEY_FILE_NAME);
+        if let Ok(existing) = fs::read_to_string(&key_path) {
+            if let Ok(key) = hex::decode(existing.trim()) {
+                return Ok(Self { key });
+            }
+        }
+
+        let mut key = vec![0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        fs::write(&key_path, hex::encode(&key))?;
+        info!("Generated receipt signing key at {:?}", key_path);
+        Ok(Self { key })
+    }
+
+    pub fn sign(&self, receipt: &UploadReceipt) -> String {
+        hex::encode(self.mac(receipt).finalize().into_bytes())
+    }
+
+    /// Checks a stored receipt's signature against its current contents.
+    pub fn verify(&self, receipt: &UploadReceipt) -> bool {
+        match hex::decode(&receipt.signature) {
+            Ok(signature) => self.mac(receipt).verify_slice(&signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+
+    fn mac(&self, receipt: &UploadReceipt) -> Hmac<Sha256> {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any length");
+        mac.update(
+            format!(
+                "{}\n{}\n{}\n{}\n{}",
+                receipt.file_id,
+                receipt.sha256,
+                receipt.file_size,
+                receipt.received_at,
+                receipt.scan_status
+            )
+            .as_bytes(),
+        );
+        mac
+    }
+}
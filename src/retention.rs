@@ -0,0 +1,72 @@
+use crate::models::RetentionPolicy;
+use crate::storage::FileStorage;
+use tracing::info;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Applies a keep-last-N policy: of the files in the policy's directory
+/// whose names match its pattern, all but the `keep_last` most recent
+/// uploads are deleted. Returns the number of files removed.
+pub async fn apply_policy(storage: &FileStorage, policy: &RetentionPolicy) -> Result<usize, BoxError> {
+    let mut matching: Vec<_> = storage
+        .list_files(policy.directory_id.clone())
+        .await?
+        .into_iter()
+        .filter(|f| pattern_matches(&policy.pattern, &f.original_filename))
+        .collect();
+    // RFC 3339 timestamps sort chronologically as strings.
+    matching.sort_by(|a, b| b.uploaded_at.cmp(&a.uploaded_at));
+
+    let mut deleted = 0;
+    for file in matching.iter().skip(policy.keep_last.max(0) as usize) {
+        if storage.delete_file(&file.id).await? {
+            deleted += 1;
+        }
+    }
+
+    if deleted > 0 {
+        info!(
+            "Retention policy {} removed {} files matching {:?}",
+            policy.id, deleted, policy.pattern
+        );
+    }
+    Ok(deleted)
+}
+
+/// Shell-style wildcard match: `*` matches any run of characters and `?`
+/// exactly one. A pattern without wildcards is treated as a prefix, so
+/// `nightly-` matches every nightly build.
+pub fn pattern_matches(pattern: &str, name: &str) -> bool {
+    if !pattern.contains(['*', '?']) {
+        return name.starts_with(pattern);
+    }
+
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match backtrack {
+                // Let the last `*` swallow one more character and retry.
+                Some((star_p, star_n)) => {
+                    backtrack = Some((star_p, star_n + 1));
+                    p = star_p + 1;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
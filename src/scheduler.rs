@@ -0,0 +1,158 @@
+use crate::notifications::Notifier;
+use crate::retention;
+use crate::storage::FileStorage;
+use chrono::Utc;
+use std::time::Duration;
+use tracing::{error, info, warn};
+
+/// Runs periodic maintenance jobs in the background, once at startup and
+/// then every `interval`.
+pub fn spawn(storage: FileStorage, notifier: Notifier, interval: Duration) {
+    info!("Scheduler running every {:?}", interval);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            run_retention_policies(&storage).await;
+            prune_access_log(&storage).await;
+            prune_integration_deliveries(&storage).await;
+            prune_metadata_journal(&storage).await;
+            prune_clipboard(&storage).await;
+            clean_up_uploads(&storage).await;
+            clean_up_archives(&storage).await;
+            delete_expired_files(&storage).await;
+            purge_trash(&storage).await;
+            prune_segment_checksums(&storage).await;
+            check_quota(&storage).await;
+            maintain_database(&storage).await;
+            if let Err(e) = notifier.send_digests().await {
+                error!("Failed to send notification digests: {}", e);
+            }
+        }
+    });
+}
+
+async fn run_retention_policies(storage: &FileStorage) {
+    let policies = match storage.list_retention_policies().await {
+        Ok(policies) => policies,
+        Err(e) => {
+            error!("Failed to load retention policies: {}", e);
+            return;
+        }
+    };
+
+    for policy in &policies {
+        if let Err(e) = retention::apply_policy(storage, policy).await {
+            error!("Failed to apply retention policy {}: {}", policy.id, e);
+        }
+    }
+}
+
+async fn prune_access_log(storage: &FileStorage) {
+    match storage.prune_access_log().await {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} expired access log entries", pruned),
+        Err(e) => error!("Failed to prune access log: {}", e),
+    }
+}
+
+async fn prune_integration_deliveries(storage: &FileStorage) {
+    match storage.prune_integration_deliveries().await {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} expired integration deliveries", pruned),
+        Err(e) => error!("Failed to prune integration deliveries: {}", e),
+    }
+}
+
+async fn prune_metadata_journal(storage: &FileStorage) {
+    match storage.prune_metadata_journal().await {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} expired metadata journal entries", pruned),
+        Err(e) => error!("Failed to prune the metadata journal: {}", e),
+    }
+}
+
+async fn prune_clipboard(storage: &FileStorage) {
+    match storage.prune_clipboard().await {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned {} expired clipboard entries", pruned),
+        Err(e) => error!("Failed to prune clipboard entries: {}", e),
+    }
+}
+
+async fn clean_up_uploads(storage: &FileStorage) {
+    match storage.clean_up_uploads().await {
+        Ok(reclaimed) if reclaimed.is_empty() => {}
+        Ok(reclaimed) => info!(
+            "Cleaned up {} abandoned upload sessions, {} orphaned chunk directories and {} \
+             interrupted uploads, reclaiming {} bytes",
+            reclaimed.sessions,
+            reclaimed.orphaned_directories,
+            reclaimed.temp_files,
+            reclaimed.bytes
+        ),
+        Err(e) => error!("Failed to clean up upload sessions: {}", e),
+    }
+}
+
+async fn clean_up_archives(storage: &FileStorage) {
+    match storage.clean_up_archives().await {
+        Ok(0) => {}
+        Ok(removed) => info!("Removed {} expired directory archives", removed),
+        Err(e) => error!("Failed to clean up directory archives: {}", e),
+    }
+}
+
+async fn delete_expired_files(storage: &FileStorage) {
+    match storage.delete_expired_files().await {
+        Ok(0) => {}
+        Ok(deleted) => info!("Deleted {} expired files", deleted),
+        Err(e) => error!("Failed to delete expired files: {}", e),
+    }
+}
+
+async fn purge_trash(storage: &FileStorage) {
+    match storage.purge_trash().await {
+        Ok(0) => {}
+        Ok(purged) => info!("Purged {} items from the trash", purged),
+        Err(e) => error!("Failed to purge the trash: {}", e),
+    }
+}
+
+async fn prune_segment_checksums(storage: &FileStorage) {
+    match storage.prune_segment_checksums().await {
+        Ok(0) => {}
+        Ok(pruned) => info!("Pruned segment checksums of {} deleted files", pruned),
+        Err(e) => error!("Failed to prune segment checksums: {}", e),
+    }
+}
+
+/// Catches storage going back under the soft quota through deletions the
+/// scheduler made, or the grace period running out, without waiting for
+/// the next upload.
+async fn check_quota(storage: &FileStorage) {
+    if !storage.quota().is_enabled() {
+        return;
+    }
+    match storage.quota_status().await {
+        Ok(status) if !status.accepting_uploads => {
+            warn!("Storage quota reached; uploads are being refused")
+        }
+        Ok(_) => {}
+        Err(e) => error!("Failed to check storage quota: {}", e),
+    }
+}
+
+/// Checkpoints the database on every run, and runs the rest of its
+/// maintenance once per `DB_MAINTENANCE_WINDOW`.
+async fn maintain_database(storage: &FileStorage) {
+    if let Err(e) = storage.checkpoint_database().await {
+        error!("Failed to checkpoint the database: {}", e);
+    }
+    if !storage.db_maintenance().is_due(Utc::now()) {
+        return;
+    }
+    if let Err(e) = storage.maintain_database(false).await {
+        error!("Failed to maintain the database: {}", e);
+    }
+}
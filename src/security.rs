@@ -0,0 +1,133 @@
+use crate::config::Config;
+use crate::storage::FileStorage;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+/// Types a browser would run scripts from if it rendered them, which are
+/// sent as `application/octet-stream` unless `INLINE_CONTENT_TYPES` lists
+/// them.
+pub const ACTIVE_CONTENT_TYPES: [&str; 5] = [
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "text/xml",
+    "application/xml",
+];
+
+/// Policy of uploaded content: nothing it references is loaded beyond
+/// images, media and inline styles, and whatever renders it runs sandboxed,
+/// in an origin of its own, without scripts, forms or plugins.
+const CONTENT_POLICY: &str =
+    "sandbox; default-src 'none'; img-src 'self' data:; media-src 'self'; style-src 'unsafe-inline'";
+
+/// Policy of the public pages, which use inline styles, a logo from
+/// anywhere and forms posting back to themselves, but no scripts.
+const PAGE_POLICY: &str = "default-src 'none'; style-src 'unsafe-inline'; img-src * data:; \
+     form-action 'self'; base-uri 'none'; frame-ancestors 'none'";
+
+/// Which active content types may be sent as they are.
+#[derive(Debug, Clone, Default)]
+pub struct InlinePolicy {
+    allowed: Vec<String>,
+}
+
+impl InlinePolicy {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            allowed: config.inline_content_types.clone(),
+        }
+    }
+
+    /// The type to send content of `content_type` as instead, if it may
+    /// not be sent as it is.
+    pub fn replacement(&self, content_type: &str) -> Option<&'static str> {
+        let essence = essence(content_type);
+        (ACTIVE_CONTENT_TYPES.contains(&essence.as_str()) && !self.allowed.contains(&essence))
+            .then_some("application/octet-stream")
+    }
+}
+
+/// File extensions public shares only serve once the recipient confirms
+/// they want a file that could run on their machine.
+#[derive(Debug, Clone, Default)]
+pub struct DownloadWarnings {
+    extensions: Vec<String>,
+}
+
+impl DownloadWarnings {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            extensions: config.risky_extensions.clone(),
+        }
+    }
+
+    /// The extension of `file_name`, lowercased, if downloading it needs
+    /// confirming.
+    pub fn risky_extension(&self, file_name: &str) -> Option<String> {
+        let (_, extension) = file_name.rsplit_once('.')?;
+        let extension = extension.to_ascii_lowercase();
+        self.extensions.contains(&extension).then_some(extension)
+    }
+}
+
+/// The `type/subtype` of a content type, without parameters.
+fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Keeps uploaded files from running scripts in the service's origin.
+/// Responses carrying file content (anything with a `Content-Disposition`)
+/// are sandboxed and lose active content types; the public pages get a
+/// policy without scripts; and nothing is sniffed as another type.
+pub async fn headers(State(storage): State<FileStorage>, request: Request, next: Next) -> Response {
+    let mut response = next.run(request).await;
+    let headers = response.headers_mut();
+    headers.insert(
+        header::X_CONTENT_TYPE_OPTIONS,
+        HeaderValue::from_static("nosniff"),
+    );
+
+    let content_type = content_type(headers);
+    if headers.contains_key(header::CONTENT_DISPOSITION) {
+        let replacement = content_type
+            .as_deref()
+            .and_then(|t| storage.inline_policy().replacement(t));
+        if let Some(replacement) = replacement {
+            headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(replacement));
+        }
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(CONTENT_POLICY),
+        );
+        headers.insert(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        );
+    } else if content_type.is_some_and(|t| essence(&t) == "text/html") {
+        headers.insert(
+            header::CONTENT_SECURITY_POLICY,
+            HeaderValue::from_static(PAGE_POLICY),
+        );
+        headers.insert(
+            header::REFERRER_POLICY,
+            HeaderValue::from_static("no-referrer"),
+        );
+    }
+    response
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
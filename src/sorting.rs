@@ -0,0 +1,236 @@
+use crate::config::Config;
+use crate::models::{DirectoryResponse, FileMetadata, Link, TreeEntry};
+use std::cmp::Ordering;
+
+/// How listings are ordered. Clients may override either setting per request.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SortOrder {
+    /// Compare runs of digits by value, so `file2` sorts before `file10`.
+    pub natural: bool,
+    /// In recursive listings, put a directory's subdirectories before its files.
+    pub directories_first: bool,
+}
+
+impl SortOrder {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            natural: false,
+            directories_first: config.list_directories_first,
+        }
+    }
+
+    pub fn compare_names(&self, a: &str, b: &str) -> Ordering {
+        if self.natural {
+            natural_cmp(a, b)
+        } else {
+            a.cmp(b)
+        }
+    }
+
+    /// Orders a recursive listing so every directory is followed by its
+    /// contents, with siblings compared by name.
+    pub fn sort_tree(&self, entries: &mut [TreeEntry]) {
+        entries.sort_by(|a, b| {
+            let a_parts: Vec<&str> = a.path().split('/').collect();
+            let b_parts: Vec<&str> = b.path().split('/').collect();
+            for i in 0..a_parts.len().min(b_parts.len()) {
+                if a_parts[i] == b_parts[i] {
+                    continue;
+                }
+                if self.directories_first {
+                    // A component is a directory if more components follow it.
+                    let a_is_dir = i + 1 < a_parts.len() || matches!(a, TreeEntry::Directory { .. });
+                    let b_is_dir = i + 1 < b_parts.len() || matches!(b, TreeEntry::Directory { .. });
+                    if a_is_dir != b_is_dir {
+                        return b_is_dir.cmp(&a_is_dir);
+                    }
+                }
+                return self.compare_names(a_parts[i], b_parts[i]);
+            }
+            a_parts.len().cmp(&b_parts.len())
+        });
+    }
+}
+
+/// A directory's preferred order for its direct contents, chosen by whoever
+/// curates it. Sizes only order files and directories; links keep their
+/// name order under the size sorts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectorySort {
+    Newest,
+    Oldest,
+    Name,
+    NameDesc,
+    Largest,
+    Smallest,
+}
+
+impl DirectorySort {
+    pub const NAMES: &'static [&'static str] = &[
+        "newest",
+        "oldest",
+        "name",
+        "name_desc",
+        "largest",
+        "smallest",
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "newest" => Some(Self::Newest),
+            "oldest" => Some(Self::Oldest),
+            "name" => Some(Self::Name),
+            "name_desc" => Some(Self::NameDesc),
+            "largest" => Some(Self::Largest),
+            "smallest" => Some(Self::Smallest),
+            _ => None,
+        }
+    }
+
+    /// Orders by name, time or size; ties fall back to the name so the
+    /// order is stable between requests.
+    fn compare(&self, a: (&str, &str, i64), b: (&str, &str, i64)) -> Ordering {
+        let (a_name, a_time, a_size) = a;
+        let (b_name, b_time, b_size) = b;
+        let by_name = natural_cmp(a_name, b_name);
+        match self {
+            Self::Newest => b_time.cmp(a_time).then(by_name),
+            Self::Oldest => a_time.cmp(b_time).then(by_name),
+            Self::Name => by_name,
+            Self::NameDesc => by_name.reverse(),
+            Self::Largest => b_size.cmp(&a_size).then(by_name),
+            Self::Smallest => a_size.cmp(&b_size).then(by_name),
+        }
+    }
+
+    pub fn sort_files(&self, files: &mut [FileMetadata]) {
+        files.sort_by(|a, b| {
+            self.compare(
+                (&a.original_filename, &a.uploaded_at, a.file_size),
+                (&b.original_filename, &b.uploaded_at, b.file_size),
+            )
+        });
+    }
+
+    pub fn sort_directories(&self, directories: &mut [DirectoryResponse]) {
+        directories.sort_by(|a, b| {
+            self.compare(
+                (&a.name, &a.created_at, a.total_size),
+                (&b.name, &b.created_at, b.total_size),
+            )
+        });
+    }
+
+    pub fn sort_links(&self, links: &mut [Link]) {
+        links.sort_by(|a, b| {
+            self.compare((&a.name, &a.created_at, 0), (&b.name, &b.created_at, 0))
+        });
+    }
+}
+
+/// Moves pinned entries to the front in pin order, keeping the rest in
+/// their current order.
+pub fn pin_first<T>(entries: &mut [T], pinned: &[String], id: impl Fn(&T) -> &str) {
+    entries.sort_by_key(|entry| {
+        pinned
+            .iter()
+            .position(|p| p == id(entry))
+            .unwrap_or(usize::MAX)
+    });
+}
+
+/// Case-insensitive comparison that treats each run of ASCII digits as a
+/// number. Names that compare equal that way fall back to a plain comparison
+/// so the order stays total.
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a_chars = a.chars().peekable();
+    let mut b_chars = b.chars().peekable();
+
+    loop {
+        match (a_chars.peek().copied(), b_chars.peek().copied()) {
+            (None, None) => return a.cmp(b),
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let x_digits = take_digits(&mut a_chars);
+                let y_digits = take_digits(&mut b_chars);
+                let x_value = x_digits.trim_start_matches('0');
+                let y_value = y_digits.trim_start_matches('0');
+                let ordering = x_value
+                    .len()
+                    .cmp(&y_value.len())
+                    .then_with(|| x_value.cmp(y_value));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a_chars.next();
+                b_chars.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(names: &[&str]) -> Vec<String> {
+        let mut names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        names.sort_by(|a, b| natural_cmp(a, b));
+        names
+    }
+
+    #[test]
+    fn digit_runs_compare_by_value() {
+        assert_eq!(
+            sorted(&["file10", "file2", "file1", "file20"]),
+            ["file1", "file2", "file10", "file20"]
+        );
+        assert_eq!(natural_cmp("v1.10", "v1.9"), Ordering::Greater);
+        // Longer than any integer type holds.
+        assert_eq!(
+            natural_cmp("x123456789012345678901234567890", "x99"),
+            Ordering::Greater
+        );
+    }
+
+    #[test]
+    fn digits_sort_before_letters() {
+        assert_eq!(natural_cmp("file1", "filea"), Ordering::Less);
+        assert_eq!(natural_cmp("2020 report", "annual report"), Ordering::Less);
+        assert_eq!(natural_cmp("file", "file1"), Ordering::Less);
+    }
+
+    #[test]
+    fn leading_zeros_are_ignored_then_break_ties() {
+        assert_eq!(natural_cmp("file01", "file2"), Ordering::Less);
+        assert_eq!(natural_cmp("file010", "file9"), Ordering::Greater);
+        assert_eq!(natural_cmp("file007", "file7"), Ordering::Less);
+        assert_eq!(natural_cmp("file7", "file007"), Ordering::Greater);
+    }
+
+    #[test]
+    fn case_is_ignored_then_breaks_ties() {
+        assert_eq!(
+            sorted(&["banana", "Cherry", "apple", "Banana"]),
+            ["apple", "Banana", "banana", "Cherry"]
+        );
+        assert_eq!(natural_cmp("Report2", "report10"), Ordering::Less);
+        assert_eq!(natural_cmp("File", "file"), Ordering::Less);
+        assert_eq!(natural_cmp("file", "File"), Ordering::Greater);
+    }
+}
@@ -1,20 +1,777 @@
-use crate::db::DbPool;
-use crate::models::{Directory, FileMetadata};
-use chrono::Utc;
+use crate::access::AccessContext;
+use crate::acl::{Access, Role};
+use crate::auth::Tokens;
+use crate::build_info;
+use crate::cdn::Cdn;
+use crate::checksum::ExpectedChecksum;
+use crate::chunks;
+use crate::audio::AudioAnalysis;
+use crate::audit::{AuditLog, DownloadAudit};
+use crate::config::Config;
+use crate::convert::Converter;
+use crate::encryption::{self, Cipher, Encryptor};
+use crate::db::{Backend, DbConnection, DbPool, Nullable, NumberedQuery};
+use crate::events::{ChangeEvent, EventBus};
+use crate::file_io::FileIo;
+use crate::i18n::Localizer;
+use crate::ingest::ArchiveLimits;
+use crate::journal;
+use crate::layout;
+use crate::maintenance::{DbMaintenance, MaintenanceError};
+use crate::models::{
+    AccessLogEntry, ArchiveJob, AudioMetadata, BatchOperation, BatchOperationResult, Branding,
+    BulkItemResult, BulkUpdateRequest, BundleShare, Capabilities, ClipboardEntry, CostReport,
+    CreateAttachmentRequest, CreateDirectoryIntegrationRequest, CreateUploadSessionRequest,
+    DatabaseMaintenance, DatabaseStats, Directory, DirectoryAclEntry, DirectoryArrangement,
+    DirectoryCost, DirectoryIntegration, DirectoryShare, DropLink, FileAttachment, FileChunk,
+    FileMetadata, FileResponse, FileSegment, FileUsage, FileVersion, Link, NotificationPreferences,
+    OidcIdentity, PendingNotification, PushSubscription, QuarantineDecision, QuarantineEntry,
+    QuotaStatus, ReportShareRequest, RetentionPolicy, Share, ShareCost, ShareReport,
+    ThumbnailPolicy, TrashItem, TreeEntry, UpdateFileRequest, UploadCleanupStats, UploadLink,
+    UploadReceipt, UploadSession, UploadSubmission, UsageNode, User, UserDataExport,
+    UserErasureReport, UserResponse, WatchedFolder,
+};
+use crate::mirror::{self, Mirror};
+use crate::names::NamePolicy;
+use crate::oidc::{self, Oidc};
+use crate::pages;
+use crate::push::PushSender;
+use crate::costs::CostRates;
+use crate::quota::{self, Capacity, Headroom, QuotaPolicy, StorageFull};
+use crate::security::{DownloadWarnings, InlinePolicy};
+use crate::receipts::{
+    ReceiptSigner, SCAN_STATUS_NOT_SCANNED, SCAN_STATUS_RELEASED, SCAN_STATUS_SUSPICIOUS,
+    SIGNATURE_ALGORITHM,
+};
+use crate::sorting::SortOrder;
+use crate::templates::DirectoryTemplate;
+use crate::thumbnails::{ThumbnailSize, Thumbnailer};
+use crate::upload_sessions::{self, CleanupCounters, NetworkProfile, Reclaimed};
+use crate::write_queue::WriteQueue;
+use crate::zip::{ArchiveContent, ArchiveEntry};
+use axum::body::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{Stream, StreamExt, TryStreamExt};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::path::{Path, PathBuf};
 use tokio::fs;
-use tracing::info;
+use tracing::{info, warn};
 use uuid::Uuid;
 
+/// A `notification_preferences` row. The flags are stored as integers, which
+/// both database backends read back the same way.
+#[derive(sqlx::FromRow)]
+struct NotificationPreferencesRow {
+    user_id: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    email: Option<String>,
+    on_share_access: i64,
+    on_new_files: i64,
+    delivery: String,
+    updated_at: String,
+}
+
+impl From<NotificationPreferencesRow> for NotificationPreferences {
+    fn from(row: NotificationPreferencesRow) -> Self {
+        Self {
+            user_id: row.user_id,
+            email: row.email,
+            on_share_access: row.on_share_access != 0,
+            on_new_files: row.on_new_files != 0,
+            delivery: row.delivery,
+            updated_at: row.updated_at,
+        }
+    }
+}
+
+/// A `thumbnail_policies` row, with its flag as an integer and its sizes as
+/// a comma-separated list.
+#[derive(sqlx::FromRow)]
+struct ThumbnailPolicyRow {
+    id: String,
+    #[sqlx(try_from = "Nullable<String>")]
+    directory_id: Option<String>,
+    recursive: i64,
+    sizes: String,
+    created_at: String,
+}
+
+impl From<ThumbnailPolicyRow> for ThumbnailPolicy {
+    fn from(row: ThumbnailPolicyRow) -> Self {
+        Self {
+            id: row.id,
+            directory_id: row.directory_id,
+            recursive: row.recursive != 0,
+            sizes: row
+                .sizes
+                .split(',')
+                .filter(|name| !name.is_empty())
+                .map(str::to_string)
+                .collect(),
+            created_at: row.created_at,
+        }
+    }
+}
+
+/// Length of the random token in share links; 22 alphanumerics carry about
+/// 130 bits, too many to guess.
+const SHARE_TOKEN_LEN: usize = 22;
+
+/// States of a directory archive job.
+pub const ARCHIVE_PENDING: &str = "pending";
+pub const ARCHIVE_READY: &str = "ready";
+pub const ARCHIVE_FAILED: &str = "failed";
+
+/// Key of the Postgres advisory lock that directory moves and name checks
+/// take, so they run one at a time; see `lock_directory_tree`.
+const DIRECTORY_TREE_LOCK: i64 = 0x6469_725f_7472_6565;
+
+/// Segments of a download manifest are a multiple of this long, so parts
+/// line up with whole blocks on disk.
+const SEGMENT_ALIGN: u64 = 64 * 1024;
+
+/// Attachments joined with the attached file's name and type.
+const ATTACHMENT_SELECT: &str = r#"
+    SELECT a.id, a.file_id, a.attachment_id, a.kind, a.language, a.label,
+           f.original_filename AS filename, f.mime_type, a.created_at
+    FROM file_attachments a
+    JOIN files f ON f.id = a.attachment_id
+"#;
+
+#[derive(Debug, thiserror::Error)]
+pub enum UploadError {
+    #[error("Failed to read file data: {0}")]
+    Body(String),
+    /// A chunk of an upload session that doesn't fit the session.
+    #[error("{0}")]
+    InvalidChunk(String),
+    #[error("Failed to write file data: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    QuotaExceeded(String),
+    #[error("{0}")]
+    StorageFull(#[from] StorageFull),
+    /// The content isn't what the client said it would be.
+    #[error("Checksum mismatch: expected SHA-256 {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// An entry can't take a name because a sibling already goes by it.
+#[derive(Debug, thiserror::Error)]
+#[error("A {kind} named '{name}' already exists there")]
+pub struct NameTaken {
+    /// `file` or `directory`: what has the name.
+    pub kind: &'static str,
+    pub name: String,
+}
+
+/// Why a batch was not applied.
+#[derive(Debug, thiserror::Error)]
+pub enum BatchError {
+    /// A step can't be applied; nothing was changed.
+    #[error("Operation {index} failed: {message}")]
+    Rejected { index: usize, message: String },
+    #[error("Database error: {0}")]
+    Database(#[from] sqlx::Error),
+}
+
+/// The bytes of a stored file, ready to be sent as a response body.
+pub type ContentStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// A file whose bytes have been written to disk but not yet recorded.
+pub struct SavedUpload {
+    pub file_id: String,
+    pub stored_filename: String,
+    pub file_path: PathBuf,
+    pub file_size: i64,
+    pub sha256: String,
+}
+
+/// Optional conditions on file listings. Every condition that is set must hold.
+#[derive(Debug, Clone, Default)]
+pub struct FileFilter {
+    /// Exact MIME type, or a `type/*` wildcard such as `image/*`.
+    pub mime: Option<String>,
+    pub min_size: Option<i64>,
+    /// Only files uploaded before this RFC 3339 timestamp.
+    pub before: Option<String>,
+    /// Only files uploaded at or after this RFC 3339 timestamp.
+    pub after: Option<String>,
+    /// Case-insensitive part of the name.
+    pub name_contains: Option<String>,
+    /// ID of the user who uploaded the file.
+    pub uploader: Option<String>,
+    /// A tag the file has, lowercased like stored tags.
+    pub tag: Option<String>,
+}
+
+impl FileFilter {
+    fn push_conditions(&self, query: &mut NumberedQuery<'_>) {
+        if let Some(mime) = &self.mime {
+            match mime.strip_suffix("/*") {
+                Some(prefix) => {
+                    query.push(" AND mime_type LIKE ").push_bind(format!("{}/%", prefix));
+                }
+                None => {
+                    query.push(" AND mime_type = ").push_bind(mime.clone());
+                }
+            }
+        }
+        if let Some(min_size) = self.min_size {
+            query.push(" AND file_size >= ").push_bind(min_size);
+        }
+        if let Some(before) = &self.before {
+            query.push(" AND uploaded_at < ").push_bind(before.clone());
+        }
+        if let Some(after) = &self.after {
+            query.push(" AND uploaded_at >= ").push_bind(after.clone());
+        }
+        if let Some(part) = &self.name_contains {
+            query
+                .push(" AND LOWER(original_filename) LIKE ")
+                .push_bind(contains_pattern(part))
+                .push(" ESCAPE '\\'");
+        }
+        if let Some(uploader) = &self.uploader {
+            query.push(" AND owner_id = ").push_bind(uploader.clone());
+        }
+        if let Some(tag) = &self.tag {
+            query
+                .push(" AND id IN (SELECT file_id FROM file_tags WHERE tag = ")
+                .push_bind(tag.clone())
+                .push(")");
+        }
+    }
+
+    /// The same conditions, for listings that don't come from the database.
+    fn matches(&self, file: &FileMetadata) -> bool {
+        let mime_ok = match (&self.mime, &file.mime_type) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(wanted), Some(actual)) => match wanted.strip_suffix("/*") {
+                Some(prefix) => actual.starts_with(&format!("{}/", prefix)),
+                None => actual == wanted,
+            },
+        };
+        mime_ok
+            && self.min_size.is_none_or(|min| file.file_size >= min)
+            && self.before.as_ref().is_none_or(|t| file.uploaded_at < *t)
+            && self.after.as_ref().is_none_or(|t| file.uploaded_at >= *t)
+            && self.name_contains.as_ref().is_none_or(|part| {
+                file.original_filename
+                    .to_lowercase()
+                    .contains(&part.to_lowercase())
+            })
+            && self
+                .uploader
+                .as_ref()
+                .is_none_or(|uploader| file.owner_id.as_ref() == Some(uploader))
+            // Files from elsewhere can't be tagged
+            && self.tag.is_none()
+    }
+}
+
+/// Condition keeping quarantined files out of everything but the admin
+/// quarantine endpoints.
+const NOT_QUARANTINED: &str = "id NOT IN (SELECT file_id FROM quarantined_files)";
+
+/// Condition keeping files and directories in the trash, and everything
+/// inside trashed directories, out of everything but the trash endpoints.
+const NOT_TRASHED: &str = "trashed = 0";
+
+/// A `LIKE` pattern, escaped with `\\`, matching lowercased text that
+/// contains `part`.
+fn contains_pattern(part: &str) -> String {
+    let escaped = part
+        .to_lowercase()
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("%{}%", escaped)
+}
+
+/// What a quarantine decision did, as recorded in the audit trail.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuarantineAction {
+    Quarantine,
+    Release,
+    Purge,
+}
+
+impl QuarantineAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Quarantine => "quarantine",
+            Self::Release => "release",
+            Self::Purge => "purge",
+        }
+    }
+}
+
+/// What file listings can be ordered by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileSortKey {
+    Name,
+    Size,
+    UploadedAt,
+}
+
+impl FileSortKey {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "name" => Some(FileSortKey::Name),
+            "size" => Some(FileSortKey::Size),
+            "uploaded_at" => Some(FileSortKey::UploadedAt),
+            _ => None,
+        }
+    }
+}
+
+/// The order of a file listing; newest first unless asked otherwise. Ties
+/// are broken by name, so pages don't shift between requests.
+#[derive(Debug, Clone, Copy)]
+pub struct FileOrder {
+    pub key: FileSortKey,
+    pub descending: bool,
+}
+
+impl Default for FileOrder {
+    fn default() -> Self {
+        Self {
+            key: FileSortKey::UploadedAt,
+            descending: true,
+        }
+    }
+}
+
+impl FileOrder {
+    fn sql(&self) -> String {
+        let column = match self.key {
+            FileSortKey::Name => "LOWER(original_filename)",
+            FileSortKey::Size => "file_size",
+            FileSortKey::UploadedAt => "uploaded_at",
+        };
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        format!(" ORDER BY {} {}, original_filename, id", column, direction)
+    }
+
+    /// The same order, for listings that don't come from the database.
+    fn sort(&self, files: &mut [FileMetadata]) {
+        files.sort_by(|a, b| {
+            let ordering = match self.key {
+                FileSortKey::Name => a
+                    .original_filename
+                    .to_lowercase()
+                    .cmp(&b.original_filename.to_lowercase()),
+                FileSortKey::Size => a.file_size.cmp(&b.file_size),
+                FileSortKey::UploadedAt => a.uploaded_at.cmp(&b.uploaded_at),
+            };
+            let ordering = if self.descending { ordering.reverse() } else { ordering };
+            ordering
+                .then_with(|| a.original_filename.cmp(&b.original_filename))
+                .then_with(|| a.id.cmp(&b.id))
+        });
+    }
+}
+
+/// Admin reports that help decide what to clean up.
+#[derive(Debug, Clone, Copy)]
+pub enum FileReport {
+    Largest,
+    /// Least recently downloaded; never-downloaded files count from upload time.
+    LeastAccessed,
+    NeverDownloaded,
+}
+
+impl FileReport {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "largest" => Some(FileReport::Largest),
+            "least-accessed" => Some(FileReport::LeastAccessed),
+            "never-downloaded" => Some(FileReport::NeverDownloaded),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct FileStorage {
     upload_dir: PathBuf,
     pool: DbPool,
+    names: NamePolicy,
+    signer: ReceiptSigner,
+    push: PushSender,
+    tokens: Tokens,
+    registration_enabled: bool,
+    public_read_only: bool,
+    /// Laid out in the home directory each new user gets; empty when users
+    /// get none.
+    user_template: DirectoryTemplate,
+    oidc: Option<Oidc>,
+    cdn: Option<Cdn>,
+    /// Encrypts stored file content; `None` when it is stored in the clear.
+    cipher: Option<Cipher>,
+    /// Minimum file size for chunk-level deduplication; `None` when disabled.
+    chunk_dedup_min_size: Option<u64>,
+    mirror: Option<Mirror>,
+    converter: Option<Converter>,
+    thumbnailer: Option<Thumbnailer>,
+    events: EventBus,
+    audit: AuditLog,
+    sort_order: SortOrder,
+    access_log_retention_days: u64,
+    trash_retention_days: u64,
+    file_version_retention: usize,
+    integration_delivery_retention_days: u64,
+    metadata_journal_retention_days: u64,
+    share_report_threshold: u64,
+    cache_max_age_secs: u64,
+    clipboard_ttl_secs: u64,
+    upload_session_ttl_secs: u64,
+    archive_ttl_secs: u64,
+    upload_cleanup: CleanupCounters,
+    db_maintenance: DbMaintenance,
+    quick_upload_dir: String,
+    quick_upload_api_key: Option<String>,
+    public_url: Option<String>,
+    quota: QuotaPolicy,
+    capacity: Capacity,
+    archive_limits: ArchiveLimits,
+    inline_policy: InlinePolicy,
+    download_warnings: DownloadWarnings,
+    file_io: FileIo,
+    write_queue: WriteQueue,
+    costs: CostRates,
+    branding: Branding,
+    i18n: Localizer,
+    features: Vec<&'static str>,
+    capabilities: Capabilities,
+    database_backend: Backend,
 }
 
 impl FileStorage {
-    pub fn new(upload_dir: PathBuf, pool: DbPool) -> Self {
-        Self { upload_dir, pool }
+    pub fn new(
+        config: &Config,
+        pool: DbPool,
+        signer: ReceiptSigner,
+        push: PushSender,
+        tokens: Tokens,
+        oidc: Option<Oidc>,
+        cdn: Option<Cdn>,
+    ) -> Self {
+        let file_io = FileIo::from_config(config);
+        let database_backend = Backend::from_url(&config.database_url).unwrap_or(Backend::Sqlite);
+        Self {
+            upload_dir: config.upload_dir.clone(),
+            pool,
+            names: NamePolicy::from_config(config),
+            signer,
+            push,
+            tokens,
+            registration_enabled: !config.disable_registration,
+            public_read_only: config.public_read_only,
+            user_template: DirectoryTemplate::for_users(config)
+                .expect("Invalid USER_DIRECTORY_TEMPLATE"),
+            oidc,
+            cdn,
+            // A bad key fails the self-check run before storage is set up.
+            cipher: Cipher::from_config(config).expect("Invalid encryption configuration"),
+            chunk_dedup_min_size: config.chunk_dedup.then_some(config.chunk_dedup_min_size),
+            mirror: config
+                .mirror_dir
+                .clone()
+                .map(|dir| Mirror::new(dir, config.mirror_name.clone())),
+            converter: config.image_convert_command.as_deref().and_then(|command| {
+                Converter::new(command, config.upload_dir.join("conversions"))
+            }),
+            thumbnailer: config.thumbnail_command.as_deref().and_then(|command| {
+                Thumbnailer::new(
+                    command,
+                    config.upload_dir.join("thumbnails"),
+                    config.thumbnail_workers,
+                )
+            }),
+            events: EventBus::new(1024),
+            audit: AuditLog::new(256),
+            sort_order: SortOrder::from_config(config),
+            access_log_retention_days: config.access_log_retention_days,
+            trash_retention_days: config.trash_retention_days,
+            file_version_retention: config.file_version_retention,
+            integration_delivery_retention_days: config.integration_delivery_retention_days,
+            metadata_journal_retention_days: config.metadata_journal_retention_days,
+            share_report_threshold: config.share_report_threshold,
+            cache_max_age_secs: config.cache_max_age_secs,
+            clipboard_ttl_secs: config.clipboard_ttl_secs,
+            upload_session_ttl_secs: config.upload_session_ttl_secs,
+            archive_ttl_secs: config.archive_ttl_secs,
+            upload_cleanup: CleanupCounters::default(),
+            db_maintenance: DbMaintenance::from_config(config, database_backend),
+            quick_upload_dir: config.quick_upload_dir.clone(),
+            quick_upload_api_key: config.quick_upload_api_key.clone(),
+            public_url: config.public_url.clone(),
+            quota: QuotaPolicy::from_config(config),
+            capacity: Capacity::from_config(config),
+            archive_limits: ArchiveLimits::from_config(config),
+            inline_policy: InlinePolicy::from_config(config),
+            download_warnings: DownloadWarnings::from_config(config),
+            write_queue: WriteQueue::new(config.upload_write_queue, file_io.clone()),
+            file_io,
+            costs: CostRates::from_config(config),
+            branding: pages::branding(config),
+            i18n: Localizer::from_config(config),
+            features: build_info::enabled_features(config),
+            capabilities: build_info::capabilities(config),
+            database_backend,
+        }
+    }
+
+    pub fn events(&self) -> &EventBus {
+        &self.events
+    }
+
+    pub fn audit(&self) -> &AuditLog {
+        &self.audit
+    }
+
+    pub fn mirror(&self) -> Option<&Mirror> {
+        self.mirror.as_ref()
+    }
+
+    pub fn converter(&self) -> Option<&Converter> {
+        self.converter.as_ref()
+    }
+
+    pub fn thumbnailer(&self) -> Option<&Thumbnailer> {
+        self.thumbnailer.as_ref()
+    }
+
+    pub fn push(&self) -> &PushSender {
+        &self.push
+    }
+
+    pub fn tokens(&self) -> &Tokens {
+        &self.tokens
+    }
+
+    pub fn registration_enabled(&self) -> bool {
+        self.registration_enabled
+    }
+
+    pub fn share_report_threshold(&self) -> u64 {
+        self.share_report_threshold
+    }
+
+    /// Whether anonymous requests may only read.
+    pub fn public_read_only(&self) -> bool {
+        self.public_read_only
+    }
+
+    pub fn oidc(&self) -> Option<&Oidc> {
+        self.oidc.as_ref()
+    }
+
+    pub fn cdn(&self) -> Option<&Cdn> {
+        self.cdn.as_ref()
+    }
+
+    /// Starts encrypting a file's content as it is written, when content is
+    /// stored encrypted.
+    pub fn encryptor(&self) -> Option<Encryptor> {
+        self.cipher.as_ref().map(Cipher::encryptor)
+    }
+
+    /// Streams `length` bytes of the content stored at `path` from byte
+    /// `start`, or all of it from there, decrypted when it is stored
+    /// encrypted.
+    pub async fn read_stored(
+        &self,
+        path: PathBuf,
+        start: u64,
+        length: Option<u64>,
+    ) -> std::io::Result<ContentStream> {
+        encryption::read(&self.file_io, self.cipher.as_ref(), path, start, length).await
+    }
+
+    pub fn name_policy(&self) -> &NamePolicy {
+        &self.names
+    }
+
+    pub fn access_log_retention_days(&self) -> u64 {
+        self.access_log_retention_days
+    }
+
+    pub fn cache_max_age_secs(&self) -> u64 {
+        self.cache_max_age_secs
+    }
+
+    pub fn clipboard_ttl_secs(&self) -> u64 {
+        self.clipboard_ttl_secs
+    }
+
+    pub fn upload_cleanup_stats(&self) -> UploadCleanupStats {
+        self.upload_cleanup.stats(self.upload_session_ttl_secs)
+    }
+
+    pub fn db_maintenance(&self) -> &DbMaintenance {
+        &self.db_maintenance
+    }
+
+    pub async fn checkpoint_database(&self) -> Result<(), sqlx::Error> {
+        self.db_maintenance.checkpoint(&self.pool).await
+    }
+
+    pub async fn maintain_database(
+        &self,
+        force_vacuum: bool,
+    ) -> Result<DatabaseMaintenance, MaintenanceError> {
+        self.db_maintenance.run(&self.pool, force_vacuum).await
+    }
+
+    pub async fn database_stats(&self) -> Result<DatabaseStats, sqlx::Error> {
+        self.db_maintenance.stats(&self.pool).await
+    }
+
+    pub fn quick_upload_dir(&self) -> &str {
+        &self.quick_upload_dir
+    }
+
+    pub fn quick_upload_api_key(&self) -> Option<&str> {
+        self.quick_upload_api_key.as_deref()
+    }
+
+    /// Externally reachable base URL, without a trailing slash, if configured.
+    pub fn public_url(&self) -> Option<&str> {
+        self.public_url.as_deref().map(|url| url.trim_end_matches('/'))
+    }
+
+    pub fn quota(&self) -> &QuotaPolicy {
+        &self.quota
+    }
+
+    /// Room for an upload while `used_bytes` are stored.
+    pub fn headroom(&self, used_bytes: u64) -> Headroom {
+        self.capacity.headroom(&self.upload_dir, used_bytes)
+    }
+
+    pub fn archive_limits(&self) -> ArchiveLimits {
+        self.archive_limits
+    }
+
+    pub fn inline_policy(&self) -> &InlinePolicy {
+        &self.inline_policy
+    }
+
+    pub fn download_warnings(&self) -> &DownloadWarnings {
+        &self.download_warnings
+    }
+
+    pub fn file_io(&self) -> &FileIo {
+        &self.file_io
+    }
+
+    pub fn write_queue(&self) -> &WriteQueue {
+        &self.write_queue
+    }
+
+    pub fn branding(&self) -> &Branding {
+        &self.branding
+    }
+
+    pub fn i18n(&self) -> &Localizer {
+        &self.i18n
+    }
+
+    pub fn database_backend(&self) -> Backend {
+        self.database_backend
+    }
+
+    /// Optional features enabled in this instance's configuration.
+    pub fn features(&self) -> &[&'static str] {
+        &self.features
+    }
+
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// Total size of stored files against the quota. Also notices storage
+    /// going over the soft limit, which starts the grace period and
+    /// publishes a warning, or back under it, which ends the grace period.
+    pub async fn quota_status(&self) -> Result<QuotaStatus, sqlx::Error> {
+        let used: i64 = sqlx::query_scalar(
+            "SELECT CAST(COALESCE(SUM(file_size), 0) AS BIGINT) \
+             FROM (SELECT file_size FROM files UNION ALL SELECT file_size FROM file_versions) stored",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let used = used.max(0) as u64;
+        if !self.quota.is_enabled() {
+            return Ok(self.quota.status(used, None));
+        }
+
+        let started: Option<String> =
+            sqlx::query_scalar("SELECT started_at FROM quota_grace_periods WHERE scope = $1")
+                .bind(quota::INSTANCE_SCOPE)
+                .fetch_optional(&self.pool)
+                .await?;
+        let over_since = match (self.quota.is_over_soft_limit(used), started) {
+            (true, Some(started)) => DateTime::parse_from_rfc3339(&started)
+                .ok()
+                .map(|t| t.with_timezone(&Utc)),
+            (true, None) => {
+                let now = Utc::now();
+                let result = sqlx::query(
+                    "INSERT INTO quota_grace_periods (scope, started_at) VALUES ($1, $2) ON CONFLICT DO NOTHING",
+                )
+                .bind(quota::INSTANCE_SCOPE)
+                .bind(now.to_rfc3339())
+                .execute(&self.pool)
+                .await?;
+                // Only the request that actually started the grace period
+                // announces it.
+                if result.rows_affected() > 0 {
+                    let grace_ends_at = (now + self.quota.grace_period).to_rfc3339();
+                    warn!(
+                        "Storage is over its soft quota ({} bytes used); uploads are refused after {}",
+                        used, grace_ends_at
+                    );
+                    self.events.publish(ChangeEvent::QuotaExceeded {
+                        used_bytes: used,
+                        soft_limit: self.quota.soft_limit.unwrap_or_default(),
+                        grace_ends_at,
+                    });
+                }
+                Some(now)
+            }
+            (false, Some(_)) => {
+                sqlx::query("DELETE FROM quota_grace_periods WHERE scope = $1")
+                    .bind(quota::INSTANCE_SCOPE)
+                    .execute(&self.pool)
+                    .await?;
+                info!("Storage is back under its soft quota");
+                None
+            }
+            (false, None) => None,
+        };
+        Ok(self.quota.status(used, over_since))
+    }
+
+    /// Version of the newest applied database migration.
+    pub async fn schema_version(&self) -> Result<i64, sqlx::Error> {
+        sqlx::query_scalar("SELECT COALESCE(MAX(version), 0) FROM schema_migrations")
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    /// The configured listing order, before any per-request overrides.
+    pub fn sort_order(&self) -> SortOrder {
+        self.sort_order
     }
 
     pub async fn init(&self) -> std::io::Result<()> {
@@ -23,7 +780,7 @@ impl FileStorage {
         Ok(())
     }
 
-    pub fn prepare_upload_path(&self, filename: &str) -> (String, PathBuf, String) {
+    fn prepare_upload_path(&self, filename: &str) -> (String, PathBuf, String) {
         let file_id = Uuid::new_v4().to_string();
         let extension = Path::new(filename)
             .extension()
@@ -34,39 +791,234 @@ impl FileStorage {
         } else {
             format!("{}.{}", file_id, extension)
         };
-        let file_path = self.upload_dir.join(&stored_filename);
+        let file_path = layout::shard_path(&self.upload_dir, &stored_filename);
         (file_id, file_path, stored_filename)
     }
 
+    /// Streams an upload body to disk, hashing it on the way through, and
+    /// stops as soon as it would take storage past the quota or fill the
+    /// disk. The body is
+    /// written to a `.part` file that only takes the final name once it is
+    /// complete and matches the checksum the client expects; an
+    /// interrupted or refused upload leaves nothing behind.
+    pub async fn save_file<S, E>(
+        &self,
+        original_filename: &str,
+        mut body: S,
+        expected: &ExpectedChecksum,
+    ) -> Result<SavedUpload, UploadError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let quota = self.quota_status().await?;
+        quota::check_upload(&quota, 0).map_err(UploadError::QuotaExceeded)?;
+        let headroom = self.headroom(quota.used_bytes);
+        headroom.check(0)?;
+
+        let (file_id, file_path, stored_filename) = self.prepare_upload_path(original_filename);
+        let partial = self.upload_dir.join(format!("{}.part", stored_filename));
+        let file = fs::File::create(&partial).await?;
+        let mut writer = self.write_queue.writer(file, self.encryptor());
+        let mut hasher = Sha256::new();
+        let mut file_size: i64 = 0;
+
+        let received = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| UploadError::Body(e.to_string()))?;
+                let received = file_size as u64 + chunk.len() as u64;
+                quota::check_upload(&quota, received).map_err(UploadError::QuotaExceeded)?;
+                headroom.check(received)?;
+                hasher.update(&chunk);
+                file_size += chunk.len() as i64;
+                writer.write(chunk).await?;
+            }
+            Ok(())
+        }
+        .await;
+        // Whatever was queued is written out before the file is kept or
+        // removed.
+        let written = writer.finish().await.map_err(UploadError::from);
+        let sha256 = hex::encode(hasher.finalize());
+
+        if let Err(e) = received
+            .and(written)
+            .and_then(|()| verify_checksum(expected, &sha256))
+        {
+            let _ = fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        layout::create_parent(&file_path).await?;
+        fs::rename(&partial, &file_path).await?;
+
+        Ok(SavedUpload {
+            file_id,
+            stored_filename,
+            file_path,
+            file_size,
+            sha256,
+        })
+    }
+
     pub async fn record_file_metadata(
         &self,
-        file_id: String,
+        saved: SavedUpload,
         original_filename: String,
-        stored_filename: String,
-        file_path: PathBuf,
-        file_size: i64,
         mime_type: Option<String>,
         description: Option<String>,
         parent_directory_id: Option<String>,
+        owner_id: Option<String>,
     ) -> Result<FileMetadata, Box<dyn std::error::Error + Send + Sync>> {
+        if parent_directory_id.as_deref().is_some_and(mirror::is_mirror_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
         let uploaded_at = Utc::now().to_rfc3339();
 
+        // Big files are deduplicated by chunk instead.
+        let chunked = self
+            .chunk_dedup_min_size
+            .is_some_and(|min| saved.file_size as u64 >= min);
+        let (filename, file_path) = if chunked {
+            (saved.stored_filename, saved.file_path)
+        } else {
+            self.share_blob(saved.stored_filename, saved.file_path, &saved.sha256, saved.file_size)
+                .await?
+        };
+
         let metadata = FileMetadata {
-            id: file_id,
-            filename: stored_filename,
+            id: saved.file_id,
+            filename,
             original_filename,
-            file_size,
+            file_size: saved.file_size,
             mime_type,
             storage_path: file_path.to_string_lossy().to_string(),
             uploaded_at,
             description,
             parent_directory_id,
+            version: 1,
+            owner_id,
+            sha256: Some(saved.sha256),
         };
 
+        if !self.insert_file_row(&metadata).await? {
+            self.release_content(&metadata).await?;
+            return Err(NameTaken {
+                kind: "file",
+                name: metadata.original_filename,
+            }
+            .into());
+        }
+
+        if chunked {
+            self.convert_to_chunks(&metadata).await?;
+        }
+
+        self.events.publish(ChangeEvent::FileCreated {
+            file_id: metadata.id.clone(),
+            name: metadata.original_filename.clone(),
+            parent_directory_id: metadata.parent_directory_id.clone(),
+        });
+        info!("File saved: {} ({})", metadata.original_filename, metadata.id);
+        Ok(metadata)
+    }
+
+    /// Keeps a single copy of each content uploaded, stored under its hash.
+    /// When a blob with the same hash is stored already, the new upload's
+    /// bytes are dropped and the blob referenced instead; otherwise the
+    /// upload is moved into place as the blob. Returns the stored name and
+    /// path the file's content is at.
+    async fn share_blob(
+        &self,
+        stored_filename: String,
+        file_path: PathBuf,
+        sha256: &str,
+        file_size: i64,
+    ) -> Result<(String, PathBuf), Box<dyn std::error::Error + Send + Sync>> {
+        // Blobs down to no references are being removed, so aren't reused.
+        let referenced = sqlx::query(
+            "UPDATE blobs SET ref_count = ref_count + 1 WHERE sha256 = $1 AND file_size = $2 AND ref_count > 0",
+        )
+        .bind(sha256)
+        .bind(file_size)
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if referenced {
+            let existing: String =
+                sqlx::query_scalar("SELECT storage_path FROM blobs WHERE sha256 = $1")
+                    .bind(sha256)
+                    .fetch_one(&self.pool)
+                    .await?;
+            if let Err(e) = fs::remove_file(&file_path).await {
+                warn!("Failed to remove duplicate upload {:?}: {}", file_path, e);
+            }
+            let existing = PathBuf::from(existing);
+            let name = existing
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or(stored_filename);
+            info!("Upload of {} bytes stored as existing blob {:?}", file_size, existing);
+            return Ok((name, existing));
+        }
+
+        // Claimed before the bytes are moved, so that a blob still being
+        // removed isn't replaced under its remover. Another upload of the
+        // same content may have become the blob in the meantime, or be
+        // removing it; this one then keeps its own copy.
+        let blob_path = layout::shard_path(&self.upload_dir, sha256);
+        let claimed = sqlx::query(
+            "INSERT INTO blobs (sha256, storage_path, file_size, ref_count, created_at) \
+             VALUES ($1, $2, $3, 1, $4) ON CONFLICT DO NOTHING",
+        )
+        .bind(sha256)
+        .bind(blob_path.to_string_lossy().to_string())
+        .bind(file_size)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?
+        .rows_affected()
+            > 0;
+        if !claimed {
+            return Ok((stored_filename, file_path));
+        }
+        let moved = async {
+            layout::create_parent(&blob_path).await?;
+            fs::rename(&file_path, &blob_path).await
+        }
+        .await;
+        if let Err(e) = moved {
+            sqlx::query("DELETE FROM blobs WHERE sha256 = $1")
+                .bind(sha256)
+                .execute(&self.pool)
+                .await?;
+            return Err(e.into());
+        }
+        Ok((sha256.to_string(), blob_path))
+    }
+
+    /// Records a new file, unless a sibling already goes by its name.
+    /// Returns whether it was recorded.
+    async fn insert_file_row(&self, metadata: &FileMetadata) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        let taken = self
+            .sibling_named(
+                &mut tx,
+                metadata.parent_directory_id.as_deref(),
+                &metadata.original_filename,
+                None,
+                false,
+            )
+            .await?;
+        if taken.is_some() {
+            return Ok(false);
+        }
         sqlx::query(
             r#"
-            INSERT INTO files (id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id)
-            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+            INSERT INTO files (id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, owner_id, sha256)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
             "#
         )
         .bind(&metadata.id)
@@ -78,292 +1030,5786 @@ impl FileStorage {
         .bind(&metadata.uploaded_at)
         .bind(&metadata.description)
         .bind(&metadata.parent_directory_id)
-        .execute(&self.pool)
+        .bind(&metadata.owner_id)
+        .bind(&metadata.sha256)
+        .execute(&mut *tx)
         .await?;
+        tx.commit().await?;
 
-        info!("File saved: {} ({})", metadata.original_filename, metadata.id);
-        Ok(metadata)
+        Ok(true)
     }
 
-    pub async fn get_file_metadata(
+    /// Registers a file that already exists on disk (outside the upload
+    /// directory) in place, without copying or chunking its bytes. Returns
+    /// `None` when a sibling already goes by its name.
+    pub async fn register_existing_file(
         &self,
-        file_id: &str,
+        path: &Path,
+        name: String,
+        file_size: i64,
+        modified_at: String,
+        parent_directory_id: Option<String>,
     ) -> Result<Option<FileMetadata>, sqlx::Error> {
-        let metadata = sqlx::query_as::<_, FileMetadata>(
-            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id FROM files WHERE id = ?"
-        )
-        .bind(file_id)
-        .fetch_optional(&self.pool)
-        .await?;
-
-        Ok(metadata)
-    }
-
-    pub async fn list_files(&self, parent_directory_id: Option<String>) -> Result<Vec<FileMetadata>, sqlx::Error> {
-        let files = if let Some(dir_id) = parent_directory_id {
-            sqlx::query_as::<_, FileMetadata>(
-                "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id FROM files WHERE parent_directory_id = ? ORDER BY uploaded_at DESC"
-            )
-            .bind(dir_id)
-            .fetch_all(&self.pool)
-            .await?
-        } else {
-            sqlx::query_as::<_, FileMetadata>(
-                "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id FROM files WHERE parent_directory_id IS NULL ORDER BY uploaded_at DESC"
-            )
-            .fetch_all(&self.pool)
-            .await?
+        let metadata = FileMetadata {
+            id: Uuid::new_v4().to_string(),
+            filename: name.clone(),
+            mime_type: mime_guess::from_path(&name).first().map(|m| m.to_string()),
+            original_filename: name,
+            file_size,
+            storage_path: path.to_string_lossy().to_string(),
+            uploaded_at: modified_at,
+            description: None,
+            parent_directory_id,
+            version: 1,
+            owner_id: None,
+            sha256: None,
         };
 
-        Ok(files)
+        if !self.insert_file_row(&metadata).await? {
+            return Ok(None);
+        }
+        self.events.publish(ChangeEvent::FileCreated {
+            file_id: metadata.id.clone(),
+            name: metadata.original_filename.clone(),
+            parent_directory_id: metadata.parent_directory_id.clone(),
+        });
+        Ok(Some(metadata))
     }
 
-    pub async fn delete_file(
+    pub async fn find_file_by_storage_path(
         &self,
-        file_id: &str,
-    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        // Get file metadata first
-        let metadata = self.get_file_metadata(file_id).await?;
+        path: &Path,
+    ) -> Result<Option<FileMetadata>, sqlx::Error> {
+        sqlx::query_as::<_, FileMetadata>(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 FROM files WHERE storage_path = $1 LIMIT 1"
+        )
+        .bind(path.to_string_lossy().to_string())
+        .fetch_optional(&self.pool)
+        .await
+    }
 
-        if let Some(meta) = metadata {
-            // Delete from filesystem
-            let file_path = Path::new(&meta.storage_path);
-            if file_path.exists() {
-                fs::remove_file(file_path).await?;
-                info!("File deleted from filesystem: {:?}", file_path);
-            }
+    /// Refreshes the recorded size of a file whose bytes changed on disk,
+    /// forgetting its checksum.
+    pub async fn update_file_size(&self, file_id: &str, file_size: i64) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "UPDATE files SET file_size = $1, sha256 = NULL, version = version + 1 WHERE id = $2",
+        )
+            .bind(file_size)
+            .bind(file_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
 
-            // Delete from database
-            let result = sqlx::query("DELETE FROM files WHERE id = ?")
-                .bind(file_id)
-                .execute(&self.pool)
-                .await?;
+    pub fn upload_dir(&self) -> &Path {
+        &self.upload_dir
+    }
 
-            Ok(result.rows_affected() > 0)
-        } else {
-            Ok(false)
+    fn chunk_dir(&self) -> PathBuf {
+        self.upload_dir.join("chunks")
+    }
+
+    /// Moves a whole stored file into the chunk store, keeping only chunks
+    /// that aren't already referenced by other files.
+    async fn convert_to_chunks(
+        &self,
+        metadata: &FileMetadata,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let source = PathBuf::from(&metadata.storage_path);
+        let chunk_dir = self.chunk_dir();
+        let cipher = self.cipher.clone();
+        let refs = tokio::task::spawn_blocking(move || {
+            chunks::split_into_chunks(&source, &chunk_dir, cipher.as_ref())
+        })
+        .await??;
+
+        let mut tx = self.pool.begin().await?;
+        for (seq, chunk) in refs.iter().enumerate() {
+            sqlx::query(
+                "INSERT INTO chunks (hash, size, ref_count) VALUES ($1, $2, 1) \
+                 ON CONFLICT(hash) DO UPDATE SET ref_count = chunks.ref_count + 1"
+            )
+            .bind(&chunk.hash)
+            .bind(chunk.length)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query(
+                "INSERT INTO file_chunks (file_id, seq, chunk_hash, chunk_offset, length) VALUES ($1, $2, $3, $4, $5)"
+            )
+            .bind(&metadata.id)
+            .bind(seq as i64)
+            .bind(&chunk.hash)
+            .bind(chunk.offset)
+            .bind(chunk.length)
+            .execute(&mut *tx)
+            .await?;
         }
+        tx.commit().await?;
+
+        fs::remove_file(&metadata.storage_path).await?;
+        info!("File {} stored as {} chunks", metadata.id, refs.len());
+        Ok(())
     }
 
-    pub async fn get_file_path(&self, file_id: &str) -> Result<Option<PathBuf>, sqlx::Error> {
-        let metadata = self.get_file_metadata(file_id).await?;
-        Ok(metadata.map(|m| PathBuf::from(m.storage_path)))
+    async fn file_chunks(&self, file_id: &str) -> Result<Vec<FileChunk>, sqlx::Error> {
+        sqlx::query_as::<_, FileChunk>(
+            "SELECT chunk_hash FROM file_chunks WHERE file_id = $1 ORDER BY seq"
+        )
+        .bind(file_id)
+        .fetch_all(&self.pool)
+        .await
     }
 
-    // Directory management methods
-    pub async fn create_directory(
+    /// Opens a stored file's content, reassembling it from chunks if needed.
+    pub async fn open_content(
         &self,
-        name: &str,
-        parent_id: Option<String>,
-    ) -> Result<Directory, Box<dyn std::error::Error + Send + Sync>> {
-        let dir_id = Uuid::new_v4().to_string();
-        let now = Utc::now().to_rfc3339();
+        metadata: &FileMetadata,
+    ) -> Result<ContentStream, Box<dyn std::error::Error + Send + Sync>> {
+        let file_chunks = self.file_chunks(&metadata.id).await?;
+        if file_chunks.is_empty() {
+            let path = PathBuf::from(&metadata.storage_path);
+            return Ok(self.read_stored(path, 0, None).await?);
+        }
 
-        let directory = Directory {
-            id: dir_id.clone(),
-            name: name.to_string(),
-            parent_id: parent_id.clone(),
-            created_at: now.clone(),
-            updated_at: now.clone(),
-        };
+        let chunk_dir = self.chunk_dir();
+        let paths: Vec<PathBuf> = file_chunks
+            .iter()
+            .map(|c| chunks::chunk_path(&chunk_dir, &c.chunk_hash))
+            .collect();
+        let storage = self.clone();
+        Ok(stream::iter(paths)
+            .then(move |path| {
+                let storage = storage.clone();
+                async move { storage.read_stored(path, 0, None).await }
+            })
+            .try_flatten()
+            .boxed())
+    }
 
-        sqlx::query(
-            r#"
-            INSERT INTO directories (id, name, parent_id, created_at, updated_at)
-            VALUES (?, ?, ?, ?, ?)
-            "#
+    /// Opens `length` bytes of a stored file's content, starting at byte
+    /// `start`. Of a chunked file only the chunks overlapping the range are
+    /// read.
+    pub async fn open_content_range(
+        &self,
+        metadata: &FileMetadata,
+        start: u64,
+        length: u64,
+    ) -> Result<ContentStream, Box<dyn std::error::Error + Send + Sync>> {
+        let layout: Vec<(String, i64, i64)> = sqlx::query_as(
+            "SELECT chunk_hash, chunk_offset, length FROM file_chunks WHERE file_id = $1 ORDER BY seq",
         )
-        .bind(&directory.id)
-        .bind(&directory.name)
-        .bind(&directory.parent_id)
-        .bind(&directory.created_at)
-        .bind(&directory.updated_at)
-        .execute(&self.pool)
+        .bind(&metadata.id)
+        .fetch_all(&self.pool)
         .await?;
+        if layout.is_empty() {
+            let path = PathBuf::from(&metadata.storage_path);
+            return Ok(self.read_stored(path, start, Some(length)).await?);
+        }
 
-        info!("Directory created: {} ({})", name, dir_id);
-        Ok(directory)
+        // (chunk path, bytes to skip in the chunk, bytes to read from it)
+        let end = start + length;
+        let chunk_dir = self.chunk_dir();
+        let parts: Vec<(PathBuf, u64, u64)> = layout
+            .into_iter()
+            .filter_map(|(hash, offset, len)| {
+                let (chunk_start, chunk_end) = (offset as u64, (offset + len) as u64);
+                if chunk_end <= start || chunk_start >= end {
+                    return None;
+                }
+                let skip = start.saturating_sub(chunk_start);
+                let take = end.min(chunk_end) - chunk_start - skip;
+                Some((chunks::chunk_path(&chunk_dir, &hash), skip, take))
+            })
+            .collect();
+        let storage = self.clone();
+        Ok(stream::iter(parts)
+            .then(move |(path, skip, take)| {
+                let storage = storage.clone();
+                async move { storage.read_stored(path, skip, Some(take)).await }
+            })
+            .try_flatten()
+            .boxed())
     }
 
-    pub async fn list_directories(&self, parent_id: Option<String>) -> Result<Vec<Directory>, sqlx::Error> {
-        let directories = if let Some(p_id) = parent_id {
-            sqlx::query_as::<_, Directory>(
-                "SELECT id, name, parent_id, created_at, updated_at FROM directories WHERE parent_id = ? ORDER BY name ASC"
-            )
-            .bind(p_id)
-            .fetch_all(&self.pool)
-            .await?
-        } else {
-            sqlx::query_as::<_, Directory>(
-                "SELECT id, name, parent_id, created_at, updated_at FROM directories WHERE parent_id IS NULL ORDER BY name ASC"
+    /// Splits a file into at most `parts` segments for parallel download,
+    /// with the SHA-256 of each. Checksums are kept per content hash, so
+    /// asking again, or for a copy of the same content, reads nothing.
+    pub async fn segment_manifest(
+        &self,
+        metadata: &FileMetadata,
+        parts: u32,
+    ) -> Result<(i64, Vec<FileSegment>), Box<dyn std::error::Error + Send + Sync>> {
+        let size = metadata.file_size.max(0);
+        let per_part = (size as u64).div_ceil(parts.max(1) as u64);
+        let segment_size = per_part.div_ceil(SEGMENT_ALIGN).max(1) as i64 * SEGMENT_ALIGN as i64;
+        if size == 0 {
+            return Ok((segment_size, Vec::new()));
+        }
+
+        let cached: Option<String> = match &metadata.sha256 {
+            Some(sha256) => sqlx::query_scalar(
+                "SELECT checksums FROM segment_checksums WHERE sha256 = $1 AND segment_size = $2",
             )
-            .fetch_all(&self.pool)
-            .await?
+            .bind(sha256)
+            .bind(segment_size)
+            .fetch_optional(&self.pool)
+            .await?,
+            None => None,
+        };
+        let checksums: Vec<String> = match cached {
+            Some(joined) => joined.split(',').map(str::to_string).collect(),
+            None => {
+                let checksums = self.hash_segments(metadata, segment_size as u64).await?;
+                if let Some(sha256) = &metadata.sha256 {
+                    sqlx::query(
+                        "INSERT INTO segment_checksums (sha256, segment_size, checksums, created_at) \
+                         VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+                    )
+                    .bind(sha256)
+                    .bind(segment_size)
+                    .bind(checksums.join(","))
+                    .bind(Utc::now().to_rfc3339())
+                    .execute(&self.pool)
+                    .await?;
+                }
+                checksums
+            }
         };
 
-        Ok(directories)
+        let segments = checksums
+            .into_iter()
+            .enumerate()
+            .map(|(index, sha256)| {
+                let start = index as i64 * segment_size;
+                let length = segment_size.min(size - start);
+                FileSegment {
+                    index: index as u32,
+                    start,
+                    end: start + length - 1,
+                    length,
+                    sha256,
+                }
+            })
+            .collect();
+        Ok((segment_size, segments))
     }
 
-    pub async fn get_directory(&self, dir_id: &str) -> Result<Option<Directory>, sqlx::Error> {
-        let directory = sqlx::query_as::<_, Directory>(
-            "SELECT id, name, parent_id, created_at, updated_at FROM directories WHERE id = ?"
+    /// Reads a file's content once, hashing each `segment_size` bytes apart.
+    async fn hash_segments(
+        &self,
+        metadata: &FileMetadata,
+        segment_size: u64,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut content = self.open_content(metadata).await?;
+        let mut checksums = Vec::new();
+        let mut hasher = Sha256::new();
+        let mut filled: u64 = 0;
+        while let Some(chunk) = content.next().await {
+            let mut chunk = &chunk?[..];
+            while !chunk.is_empty() {
+                let take = chunk.len().min((segment_size - filled) as usize);
+                hasher.update(&chunk[..take]);
+                filled += take as u64;
+                chunk = &chunk[take..];
+                if filled == segment_size {
+                    checksums.push(hex::encode(hasher.finalize_reset()));
+                    filled = 0;
+                }
+            }
+        }
+        if filled > 0 {
+            checksums.push(hex::encode(hasher.finalize()));
+        }
+        Ok(checksums)
+    }
+
+    /// Forgets segment checksums of content no file has any more.
+    pub async fn prune_segment_checksums(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "DELETE FROM segment_checksums WHERE sha256 NOT IN (SELECT sha256 FROM files WHERE sha256 IS NOT NULL)",
         )
-        .bind(dir_id)
-        .fetch_optional(&self.pool)
+        .execute(&self.pool)
         .await?;
-
-        Ok(directory)
+        Ok(result.rows_affected())
     }
 
-    pub async fn delete_directory(
+    /// Path of a file's content on disk, or `None` when it is stored as
+    /// deduplicated chunks or encrypted and can only be streamed.
+    pub async fn local_content_path(
         &self,
-        dir_id: &str,
-    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
-        // Check if directory exists
-        let directory = self.get_directory(dir_id).await?;
-        if directory.is_none() {
-            return Ok(false);
+        metadata: &FileMetadata,
+    ) -> Result<Option<PathBuf>, Box<dyn std::error::Error + Send + Sync>> {
+        if !self.file_chunks(&metadata.id).await?.is_empty() {
+            return Ok(None);
+        }
+        let path = PathBuf::from(&metadata.storage_path);
+        if encryption::is_encrypted(&path).await? {
+            return Ok(None);
         }
+        Ok(Some(path))
+    }
 
-        // Delete all files in this directory
-        sqlx::query("DELETE FROM files WHERE parent_directory_id = ?")
-            .bind(dir_id)
+    /// Drops a file's reference to the blob at `storage_path`. Returns
+    /// whether the bytes can go: the last reference is gone, or the file
+    /// wasn't stored as a blob.
+    async fn release_blob(&self, storage_path: &str) -> Result<bool, sqlx::Error> {
+        let shared = sqlx::query("UPDATE blobs SET ref_count = ref_count - 1 WHERE storage_path = $1")
+            .bind(storage_path)
             .execute(&self.pool)
-            .await?;
-
-        // Delete the directory (CASCADE will handle subdirectories and their files)
-        let result = sqlx::query("DELETE FROM directories WHERE id = ?")
-            .bind(dir_id)
+            .await?
+            .rows_affected()
+            > 0;
+        if !shared {
+            return Ok(true);
+        }
+        let unreferenced = sqlx::query("DELETE FROM blobs WHERE storage_path = $1 AND ref_count <= 0")
+            .bind(storage_path)
             .execute(&self.pool)
+            .await?
+            .rows_affected()
+            > 0;
+        Ok(unreferenced)
+    }
+
+    /// Frees the bytes behind a file: removes the stored file unless it is a
+    /// blob other files still reference, or drops its chunk references and
+    /// deletes chunks no other file uses. Files registered in place are left
+    /// on disk, as they belong to whoever put them there.
+    async fn release_content(
+        &self,
+        metadata: &FileMetadata,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let file_chunks = self.file_chunks(&metadata.id).await?;
+        if file_chunks.is_empty() {
+            if !self.release_blob(&metadata.storage_path).await? || !self.is_uploaded(metadata) {
+                return Ok(());
+            }
+            let file_path = Path::new(&metadata.storage_path);
+            if file_path.exists() {
+                fs::remove_file(file_path).await?;
+                info!("File deleted from filesystem: {:?}", file_path);
+            }
+            return Ok(());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for chunk in &file_chunks {
+            sqlx::query("UPDATE chunks SET ref_count = ref_count - 1 WHERE hash = $1")
+                .bind(&chunk.chunk_hash)
+                .execute(&mut *tx)
+                .await?;
+        }
+        sqlx::query("DELETE FROM file_chunks WHERE file_id = $1")
+            .bind(&metadata.id)
+            .execute(&mut *tx)
+            .await?;
+        let orphaned: Vec<(String,)> =
+            sqlx::query_as("SELECT hash FROM chunks WHERE ref_count <= 0")
+                .fetch_all(&mut *tx)
+                .await?;
+        sqlx::query("DELETE FROM chunks WHERE ref_count <= 0")
+            .execute(&mut *tx)
             .await?;
+        tx.commit().await?;
 
-        info!("Directory deleted: {}", dir_id);
-        Ok(result.rows_affected() > 0)
+        let chunk_dir = self.chunk_dir();
+        for (hash,) in orphaned {
+            let path = chunks::chunk_path(&chunk_dir, &hash);
+            if let Err(e) = fs::remove_file(&path).await {
+                warn!("Failed to remove chunk {:?}: {}", path, e);
+            }
+        }
+        Ok(())
     }
 
-    pub async fn get_directory_stats(&self, dir_id: &str) -> Result<(i64, i64), sqlx::Error> {
-        // Get file count and total size for a directory
-        let result: Option<(Option<i64>, Option<i64>)> = sqlx::query_as(
-            "SELECT COUNT(*), SUM(file_size) FROM files WHERE parent_directory_id = ?"
+    /// Issues and persists a signed receipt for a freshly recorded upload.
+    pub async fn issue_receipt(
+        &self,
+        metadata: &FileMetadata,
+        sha256: &str,
+    ) -> Result<UploadReceipt, sqlx::Error> {
+        let mut receipt = UploadReceipt {
+            file_id: metadata.id.clone(),
+            sha256: sha256.to_string(),
+            file_size: metadata.file_size,
+            received_at: metadata.uploaded_at.clone(),
+            scan_status: SCAN_STATUS_NOT_SCANNED.to_string(),
+            signature_algorithm: SIGNATURE_ALGORITHM.to_string(),
+            signature: String::new(),
+        };
+        receipt.signature = self.signer.sign(&receipt);
+
+        sqlx::query(
+            r#"
+            INSERT INTO upload_receipts (file_id, sha256, file_size, received_at, scan_status, signature_algorithm, signature)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#
         )
-        .bind(dir_id)
-        .fetch_optional(&self.pool)
+        .bind(&receipt.file_id)
+        .bind(&receipt.sha256)
+        .bind(receipt.file_size)
+        .bind(&receipt.received_at)
+        .bind(&receipt.scan_status)
+        .bind(&receipt.signature_algorithm)
+        .bind(&receipt.signature)
+        .execute(&self.pool)
         .await?;
 
-        match result {
-            Some((Some(count), Some(size))) => Ok((count, size)),
-            Some((Some(count), None)) => Ok((count, 0)),
-            _ => Ok((0, 0)),
-        }
+        Ok(receipt)
     }
 
-    pub async fn move_file(
+    /// Changes the scan status on a file's receipt and signs it again.
+    /// Returns `false` if the file has no receipt.
+    pub async fn set_scan_status(&self, file_id: &str, scan_status: &str) -> Result<bool, sqlx::Error> {
+        let Some((mut receipt, _)) = self.get_receipt(file_id).await? else {
+            return Ok(false);
+        };
+        receipt.scan_status = scan_status.to_string();
+        receipt.signature = self.signer.sign(&receipt);
+
+        sqlx::query("UPDATE upload_receipts SET scan_status = $1, signature = $2 WHERE file_id = $3")
+            .bind(&receipt.scan_status)
+            .bind(&receipt.signature)
+            .bind(file_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(true)
+    }
+
+    /// Returns the stored receipt for a file and whether its signature still checks out.
+    pub async fn get_receipt(
         &self,
         file_id: &str,
-        parent_directory_id: Option<String>,
-    ) -> Result<Option<FileMetadata>, Box<dyn std::error::Error + Send + Sync>> {
-        let result = sqlx::query(
-            "UPDATE files SET parent_directory_id = ? WHERE id = ?"
+    ) -> Result<Option<(UploadReceipt, bool)>, sqlx::Error> {
+        let receipt = sqlx::query_as::<_, UploadReceipt>(
+            "SELECT file_id, sha256, file_size, received_at, scan_status, signature_algorithm, signature FROM upload_receipts WHERE file_id = $1"
         )
-        .bind(&parent_directory_id)
         .bind(file_id)
-        .execute(&self.pool)
+        .fetch_optional(&self.pool)
         .await?;
 
-        if result.rows_affected() == 0 {
-            return Ok(None);
-        }
-
-        let metadata = self.get_file_metadata(file_id).await?;
-        info!("File moved: {} -> {:?}", file_id, parent_directory_id);
-        Ok(metadata)
+        Ok(receipt.map(|r| {
+            let valid = self.signer.verify(&r);
+            (r, valid)
+        }))
     }
 
-    pub async fn move_directory(
+    // Quarantine methods
+
+    /// Puts a file in quarantine, hiding it from everything but the admin
+    /// quarantine endpoints, and marks its receipt suspicious. Returns
+    /// `None` if there is no such file, or it is already in quarantine.
+    pub async fn quarantine_file(
         &self,
-        dir_id: &str,
-        parent_id: Option<String>,
-    ) -> Result<Option<Directory>, Box<dyn std::error::Error + Send + Sync>> {
-        // Prevent moving a directory into itself or one of its descendants
-        if let Some(ref target_id) = parent_id {
-            if target_id == dir_id {
-                return Err("Cannot move a directory into itself".into());
-            }
-            if self.is_ancestor_of(dir_id, target_id).await? {
-                return Err("Cannot move a directory into one of its own subdirectories".into());
-            }
-        }
+        file_id: &str,
+        source: &str,
+        reason: Option<&str>,
+    ) -> Result<Option<QuarantineEntry>, sqlx::Error> {
+        let Some(file) = self.get_file_metadata(file_id).await? else {
+            return Ok(None);
+        };
+        let entry = QuarantineEntry {
+            file,
+            source: source.to_string(),
+            reason: reason.map(str::to_string),
+            quarantined_at: Utc::now().to_rfc3339(),
+        };
 
-        let now = Utc::now().to_rfc3339();
-        let result = sqlx::query(
-            "UPDATE directories SET parent_id = ?, updated_at = ? WHERE id = ?"
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO quarantined_files (file_id, source, reason, quarantined_at) VALUES ($1, $2, $3, $4)",
         )
-        .bind(&parent_id)
-        .bind(&now)
-        .bind(dir_id)
-        .execute(&self.pool)
+        .bind(&entry.file.id)
+        .bind(&entry.source)
+        .bind(&entry.reason)
+        .bind(&entry.quarantined_at)
+        .execute(&mut *tx)
         .await?;
+        record_quarantine_decision(&mut tx, &entry.file, QuarantineAction::Quarantine, Some(source), reason)
+            .await?;
+        tx.commit().await?;
 
-        if result.rows_affected() == 0 {
-            return Ok(None);
-        }
-
-        let directory = self.get_directory(dir_id).await?;
-        info!("Directory moved: {} -> {:?}", dir_id, parent_id);
-        Ok(directory)
+        self.set_scan_status(file_id, SCAN_STATUS_SUSPICIOUS).await?;
+        self.events.publish(ChangeEvent::FileQuarantined {
+            file_id: entry.file.id.clone(),
+            parent_directory_id: entry.file.parent_directory_id.clone(),
+        });
+        Ok(Some(entry))
     }
 
-    /// Returns true if `ancestor_id` is an ancestor of `target_id` (walks up the tree).
-    async fn is_ancestor_of(&self, ancestor_id: &str, target_id: &str) -> Result<bool, sqlx::Error> {
-        let mut current = target_id.to_string();
-        loop {
-            match self.get_directory(&current).await? {
-                None => return Ok(false),
-                Some(dir) => match dir.parent_id {
-                    None => return Ok(false),
-                    Some(pid) => {
-                        if pid == ancestor_id {
-                            return Ok(true);
-                        }
-                        current = pid;
-                    }
-                },
-            }
-        }
+    /// Quarantined files, most recently flagged first.
+    pub async fn list_quarantine(&self) -> Result<Vec<QuarantineEntry>, sqlx::Error> {
+        sqlx::query_as::<_, QuarantineEntry>(
+            "SELECT f.id, f.filename, f.original_filename, f.file_size, f.mime_type, f.storage_path, \
+             f.uploaded_at, f.description, f.parent_directory_id, f.version, f.owner_id, f.sha256, \
+             q.source, q.reason, q.quarantined_at \
+             FROM quarantined_files q JOIN files f ON f.id = q.file_id \
+             ORDER BY q.quarantined_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await
     }
 
-    pub async fn list_recent_files(&self, limit: i64) -> Result<Vec<FileMetadata>, sqlx::Error> {
-        sqlx::query_as::<_, FileMetadata>(
-            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id \
-             FROM files ORDER BY uploaded_at DESC LIMIT ?"
+    pub async fn get_quarantine_entry(&self, file_id: &str) -> Result<Option<QuarantineEntry>, sqlx::Error> {
+        sqlx::query_as::<_, QuarantineEntry>(
+            "SELECT f.id, f.filename, f.original_filename, f.file_size, f.mime_type, f.storage_path, \
+             f.uploaded_at, f.description, f.parent_directory_id, f.version, f.owner_id, f.sha256, \
+             q.source, q.reason, q.quarantined_at \
+             FROM quarantined_files q JOIN files f ON f.id = q.file_id \
+             WHERE q.file_id = $1",
         )
-        .bind(limit)
-        .fetch_all(&self.pool)
+        .bind(file_id)
+        .fetch_optional(&self.pool)
         .await
     }
 
-    pub async fn bulk_delete(
+    /// The quarantine audit trail, newest first, or of one file, oldest
+    /// first.
+    pub async fn quarantine_decisions(
         &self,
-        file_ids: Vec<String>,
-        directory_ids: Vec<String>,
-    ) -> Result<(usize, usize), Box<dyn std::error::Error + Send + Sync>> {
-        let mut deleted_files = 0;
-        let mut deleted_directories = 0;
-
-        // Delete files
-        for file_id in file_ids {
-            if self.delete_file(&file_id).await? {
-                deleted_files += 1;
+        file_id: Option<&str>,
+        limit: i64,
+    ) -> Result<Vec<QuarantineDecision>, sqlx::Error> {
+        let mut query = NumberedQuery::new(
+            "SELECT id, file_id, original_filename, action, actor, reason, decided_at FROM quarantine_decisions",
+        );
+        match file_id {
+            Some(file_id) => {
+                query
+                    .push(" WHERE file_id = ")
+                    .push_bind(file_id.to_string())
+                    .push(" ORDER BY decided_at ASC, id");
+            }
+            None => {
+                query.push(" ORDER BY decided_at DESC, id");
             }
         }
+        query.push(" LIMIT ").push_bind(limit);
 
-        // Delete directories
-        for dir_id in directory_ids {
-            if self.delete_directory(&dir_id).await? {
-                deleted_directories += 1;
-            }
+        let (sql, args) = query.into_parts();
+        sqlx::query_as_with::<_, QuarantineDecision, _>(&sql, args)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Takes a file out of quarantine, making it visible again, and marks
+    /// its receipt released.
+    pub async fn release_file(
+        &self,
+        entry: &QuarantineEntry,
+        reviewer: Option<&str>,
+        reason: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM quarantined_files WHERE file_id = $1")
+            .bind(&entry.file.id)
+            .execute(&mut *tx)
+            .await?;
+        record_quarantine_decision(&mut tx, &entry.file, QuarantineAction::Release, reviewer, reason).await?;
+        tx.commit().await?;
+
+        self.set_scan_status(&entry.file.id, SCAN_STATUS_RELEASED).await?;
+        self.events.publish(ChangeEvent::FileReleased {
+            file_id: entry.file.id.clone(),
+            name: entry.file.original_filename.clone(),
+            parent_directory_id: entry.file.parent_directory_id.clone(),
+        });
+        Ok(())
+    }
+
+    /// Deletes a quarantined file for good. The audit trail keeps the
+    /// decision, and the file's name, after the file is gone.
+    pub async fn purge_file(
+        &self,
+        entry: QuarantineEntry,
+        reviewer: Option<&str>,
+        reason: Option<&str>,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        record_quarantine_decision(
+            &mut *self.pool.acquire().await?,
+            &entry.file,
+            QuarantineAction::Purge,
+            reviewer,
+            reason,
+        )
+        .await?;
+        self.remove_file_and_announce(entry.file).await
+    }
+
+    pub async fn get_file_metadata(
+        &self,
+        file_id: &str,
+    ) -> Result<Option<FileMetadata>, sqlx::Error> {
+        if mirror::is_mirror_id(file_id) {
+            return match &self.mirror {
+                Some(m) => m.get_file(file_id).await.map_err(sqlx::Error::Io),
+                None => Ok(None),
+            };
         }
 
-        Ok((deleted_files, deleted_directories))
+        let metadata = sqlx::query_as::<_, FileMetadata>(&format!(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 \
+             FROM files WHERE id = $1 AND {} AND {}",
+            NOT_QUARANTINED, NOT_TRASHED
+        ))
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(metadata)
+    }
+
+    pub async fn list_files(&self, parent_directory_id: Option<String>) -> Result<Vec<FileMetadata>, sqlx::Error> {
+        self.list_files_matching(parent_directory_id, &FileFilter::default(), FileOrder::default())
+            .await
+    }
+
+    /// Lists the files directly inside a directory that pass `filter`, in `order`.
+    pub async fn list_files_matching(
+        &self,
+        parent_directory_id: Option<String>,
+        filter: &FileFilter,
+        order: FileOrder,
+    ) -> Result<Vec<FileMetadata>, sqlx::Error> {
+        if let Some(dir_id) = parent_directory_id.as_deref().filter(|id| mirror::is_mirror_id(id)) {
+            let mut files: Vec<FileMetadata> = match &self.mirror {
+                Some(m) => m
+                    .list(dir_id)
+                    .await
+                    .map_err(sqlx::Error::Io)?
+                    .0
+                    .into_iter()
+                    .filter(|f| filter.matches(f))
+                    .collect(),
+                None => Vec::new(),
+            };
+            order.sort(&mut files);
+            return Ok(files);
+        }
+
+        let mut query = NumberedQuery::new(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 FROM files WHERE parent_directory_id IS NOT DISTINCT FROM ",
+        );
+        query
+            .push("CAST(")
+            .push_bind(parent_directory_id)
+            .push(" AS TEXT) AND ")
+            .push(NOT_QUARANTINED)
+            .push(" AND ")
+            .push(NOT_TRASHED);
+        filter.push_conditions(&mut query);
+        query.push(&order.sql());
+
+        let (sql, args) = query.into_parts();
+        sqlx::query_as_with::<_, FileMetadata, _>(&sql, args)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// Moves a file to the trash, where it stays until it is restored or
+    /// purged. Returns whether there was such a file outside the trash.
+    pub async fn delete_file(
+        &self,
+        file_id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(file_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let Some(meta) = self.get_file_metadata(file_id).await? else {
+            return Ok(false);
+        };
+        let trashed = sqlx::query("UPDATE files SET trashed = 1, deleted_at = $1 WHERE id = $2 AND trashed = 0")
+            .bind(Utc::now().to_rfc3339())
+            .bind(file_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected()
+            > 0;
+
+        if trashed {
+            self.events.publish(ChangeEvent::FileDeleted {
+                file_id: meta.id,
+                parent_directory_id: meta.parent_directory_id,
+            });
+        }
+        Ok(trashed)
+    }
+
+    /// Deletes a file for good, whether or not it is in the trash.
+    pub async fn delete_file_permanently(
+        &self,
+        file_id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(file_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let meta = sqlx::query_as::<_, FileMetadata>(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 \
+             FROM files WHERE id = $1",
+        )
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        match meta {
+            Some(meta) => self.remove_file_and_announce(meta).await,
+            None => Ok(false),
+        }
+    }
+
+    /// Deletes a file's content, its earlier versions and everything
+    /// recorded about it.
+    async fn remove_file(
+        &self,
+        meta: FileMetadata,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        // Delete from filesystem
+        for version in self.list_file_versions(&meta.id).await? {
+            self.release_version(&meta, &version).await?;
+        }
+        self.release_content(&meta).await?;
+
+        // Delete from database
+        let deleted = delete_file_rows(&mut *self.pool.acquire().await?, &meta.id).await?;
+
+        if let Some(converter) = &self.converter {
+            converter.forget(&meta.id).await;
+        }
+        if let Some(thumbnailer) = &self.thumbnailer {
+            thumbnailer.forget(&meta.id).await;
+        }
+        Ok(deleted)
+    }
+
+    /// Like `remove_file`, telling clients the file is gone.
+    async fn remove_file_and_announce(
+        &self,
+        meta: FileMetadata,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let (file_id, parent_directory_id) = (meta.id.clone(), meta.parent_directory_id.clone());
+        let deleted = self.remove_file(meta).await?;
+        if deleted {
+            self.events.publish(ChangeEvent::FileDeleted {
+                file_id,
+                parent_directory_id,
+            });
+        }
+        Ok(deleted)
+    }
+
+    /// Whether a file's content is kept in the upload directory, rather
+    /// than registered in place where it was found on disk.
+    pub fn is_uploaded(&self, metadata: &FileMetadata) -> bool {
+        Path::new(&metadata.storage_path).starts_with(&self.upload_dir)
+    }
+
+    /// Earlier versions of a file, newest first.
+    pub async fn list_file_versions(&self, file_id: &str) -> Result<Vec<FileVersion>, sqlx::Error> {
+        sqlx::query_as::<_, FileVersion>(
+            "SELECT id, file_id, filename, file_size, mime_type, storage_path, sha256, uploaded_at, replaced_at \
+             FROM file_versions WHERE file_id = $1 ORDER BY replaced_at DESC",
+        )
+        .bind(file_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_file_version(
+        &self,
+        file_id: &str,
+        version_id: &str,
+    ) -> Result<Option<FileVersion>, sqlx::Error> {
+        sqlx::query_as::<_, FileVersion>(
+            "SELECT id, file_id, filename, file_size, mime_type, storage_path, sha256, uploaded_at, replaced_at \
+             FROM file_versions WHERE id = $1 AND file_id = $2",
+        )
+        .bind(version_id)
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Makes a stored upload the new content of a file, if the file is
+    /// still at `current.version`. The content it replaces is kept as an
+    /// earlier version. Returns the updated file, or `None` if it changed
+    /// in the meantime.
+    pub async fn record_file_version(
+        &self,
+        current: &FileMetadata,
+        saved: SavedUpload,
+        mime_type: Option<String>,
+    ) -> Result<Option<FileMetadata>, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(&current.id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        // Stored the same way as a new upload.
+        let chunked = self
+            .chunk_dedup_min_size
+            .is_some_and(|min| saved.file_size as u64 >= min);
+        let (filename, file_path) = if chunked {
+            (saved.stored_filename, saved.file_path)
+        } else {
+            self.share_blob(saved.stored_filename, saved.file_path, &saved.sha256, saved.file_size)
+                .await?
+        };
+        let updated = FileMetadata {
+            filename,
+            file_size: saved.file_size,
+            mime_type: mime_type
+                .filter(|mime| mime != "application/octet-stream")
+                .or_else(|| current.mime_type.clone()),
+            storage_path: file_path.to_string_lossy().to_string(),
+            uploaded_at: Utc::now().to_rfc3339(),
+            version: current.version + 1,
+            sha256: Some(saved.sha256),
+            ..current.clone()
+        };
+
+        let mut tx = self.pool.begin().await?;
+        if !replace_content(&mut tx, current, &updated, &updated.uploaded_at).await? {
+            drop(tx);
+            let unused = FileMetadata {
+                id: saved.file_id,
+                ..updated
+            };
+            self.release_content(&unused).await?;
+            return Ok(None);
+        }
+        tx.commit().await?;
+
+        if chunked {
+            self.convert_to_chunks(&updated).await?;
+        }
+        self.content_replaced(&updated).await?;
+        Ok(Some(updated))
+    }
+
+    /// Makes an earlier version the current content of a file again, if the
+    /// file is still at `current.version`; the content it replaces becomes
+    /// a version in its place. Returns the updated file, or `None` if it
+    /// changed in the meantime.
+    pub async fn restore_file_version(
+        &self,
+        current: &FileMetadata,
+        version: &FileVersion,
+    ) -> Result<Option<FileMetadata>, Box<dyn std::error::Error + Send + Sync>> {
+        let restored = FileMetadata {
+            id: current.id.clone(),
+            version: current.version + 1,
+            ..version.as_content(current)
+        };
+
+        let mut tx = self.pool.begin().await?;
+        if !replace_content(&mut tx, current, &restored, &Utc::now().to_rfc3339()).await? {
+            return Ok(None);
+        }
+        sqlx::query("UPDATE file_chunks SET file_id = $1 WHERE file_id = $2")
+            .bind(&current.id)
+            .bind(&version.id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM file_versions WHERE id = $1")
+            .bind(&version.id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.content_replaced(&restored).await?;
+        Ok(Some(restored))
+    }
+
+    /// Forgets what was derived from a file's previous content, lets go of
+    /// versions past the number kept, and tells clients about the change.
+    async fn content_replaced(&self, file: &FileMetadata) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        sqlx::query("DELETE FROM upload_receipts WHERE file_id = $1")
+            .bind(&file.id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query("DELETE FROM audio_metadata WHERE file_id = $1")
+            .bind(&file.id)
+            .execute(&self.pool)
+            .await?;
+        if let Some(converter) = &self.converter {
+            converter.forget(&file.id).await;
+        }
+        if let Some(thumbnailer) = &self.thumbnailer {
+            thumbnailer.forget(&file.id).await;
+        }
+
+        let versions = self.list_file_versions(&file.id).await?;
+        for version in versions.iter().skip(self.file_version_retention) {
+            self.release_version(file, version).await?;
+        }
+
+        self.events.publish(ChangeEvent::FileUpdated {
+            file_id: file.id.clone(),
+            name: file.original_filename.clone(),
+            parent_directory_id: file.parent_directory_id.clone(),
+        });
+        info!("New content for file {} ({} bytes)", file.id, file.file_size);
+        Ok(())
+    }
+
+    /// Deletes an earlier version of a file and frees its content.
+    async fn release_version(
+        &self,
+        file: &FileMetadata,
+        version: &FileVersion,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        self.release_content(&version.as_content(file)).await?;
+        sqlx::query("DELETE FROM file_versions WHERE id = $1")
+            .bind(&version.id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    // Upload session methods
+    fn session_dir(&self, session_id: &str) -> PathBuf {
+        self.upload_dir.join("sessions").join(session_id)
+    }
+
+    fn upload_session_expiry(&self) -> String {
+        (Utc::now() + chrono::Duration::seconds(self.upload_session_ttl_secs as i64)).to_rfc3339()
+    }
+
+    pub async fn create_upload_session(
+        &self,
+        request: CreateUploadSessionRequest,
+        profile: NetworkProfile,
+    ) -> Result<UploadSession, sqlx::Error> {
+        let plan = upload_sessions::negotiate(profile, request.chunk_size, request.parallelism);
+        let session = UploadSession {
+            id: Uuid::new_v4().to_string(),
+            filename: request.filename,
+            file_size: request.file_size,
+            mime_type: request.mime_type,
+            description: request.description,
+            parent_directory_id: request.parent_directory_id,
+            network_profile: profile.as_str().to_string(),
+            chunk_size: plan.chunk_size,
+            parallelism: plan.parallelism,
+            created_at: Utc::now().to_rfc3339(),
+            expires_at: Some(self.upload_session_expiry()),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO upload_sessions (id, filename, file_size, mime_type, description, parent_directory_id, network_profile, chunk_size, parallelism, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            "#,
+        )
+        .bind(&session.id)
+        .bind(&session.filename)
+        .bind(session.file_size)
+        .bind(&session.mime_type)
+        .bind(&session.description)
+        .bind(&session.parent_directory_id)
+        .bind(&session.network_profile)
+        .bind(session.chunk_size)
+        .bind(session.parallelism)
+        .bind(&session.created_at)
+        .bind(&session.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        info!(
+            "Upload session {} created for {} ({} profile, {} byte chunks)",
+            session.id, session.filename, session.network_profile, session.chunk_size
+        );
+        Ok(session)
+    }
+
+    pub async fn get_upload_session(&self, session_id: &str) -> Result<Option<UploadSession>, sqlx::Error> {
+        sqlx::query_as::<_, UploadSession>(
+            r#"
+            SELECT id, filename, file_size, mime_type, description, parent_directory_id, network_profile, chunk_size, parallelism, created_at, expires_at
+            FROM upload_sessions
+            WHERE id = $1
+            "#,
+        )
+        .bind(session_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Indexes of the chunks received so far, in order.
+    pub async fn received_chunks(&self, session_id: &str) -> Result<Vec<i64>, sqlx::Error> {
+        sqlx::query_scalar(
+            "SELECT chunk_index FROM upload_session_chunks WHERE session_id = $1 ORDER BY chunk_index",
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Writes one chunk of a session to disk. The chunk must be exactly the
+    /// expected size, and fit on the disk; a retried chunk replaces the
+    /// earlier attempt.
+    pub async fn save_session_chunk<S, E>(
+        &self,
+        session: &UploadSession,
+        index: i64,
+        mut body: S,
+        expected_sha256: &ExpectedChecksum,
+    ) -> Result<i64, UploadError>
+    where
+        S: Stream<Item = Result<Bytes, E>> + Unpin,
+        E: std::fmt::Display,
+    {
+        let expected =
+            upload_sessions::expected_chunk_size(session.file_size, session.chunk_size, index)
+                .ok_or_else(|| UploadError::InvalidChunk(format!("Chunk {} is out of range", index)))?;
+        let used = self.quota_status().await?.used_bytes;
+        self.headroom(used).check(expected as u64)?;
+
+        let dir = self.session_dir(&session.id);
+        fs::create_dir_all(&dir).await?;
+        let partial = dir.join(format!("{}.part", index));
+        let file = fs::File::create(&partial).await?;
+        let mut writer = self.write_queue.writer(file, self.encryptor());
+        let mut hasher = Sha256::new();
+        let mut size: i64 = 0;
+
+        let received = async {
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk.map_err(|e| UploadError::Body(e.to_string()))?;
+                size += chunk.len() as i64;
+                if size > expected {
+                    break;
+                }
+                hasher.update(&chunk);
+                writer.write(chunk).await?;
+            }
+            if size != expected {
+                return Err(UploadError::InvalidChunk(format!(
+                    "Chunk {} must be {} bytes",
+                    index, expected
+                )));
+            }
+            Ok(())
+        }
+        .await;
+        let written = writer.finish().await.map_err(UploadError::from);
+
+        if let Err(e) = received
+            .and(written)
+            .and_then(|()| verify_checksum(expected_sha256, &hex::encode(hasher.finalize())))
+        {
+            let _ = fs::remove_file(&partial).await;
+            return Err(e);
+        }
+        fs::rename(&partial, dir.join(index.to_string())).await?;
+        Ok(size)
+    }
+
+    /// Records a stored chunk and pushes the session's expiry back,
+    /// returning the new expiry.
+    pub async fn record_session_chunk(
+        &self,
+        session_id: &str,
+        index: i64,
+        size: i64,
+    ) -> Result<String, sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO upload_session_chunks (session_id, chunk_index, size) VALUES ($1, $2, $3) \
+             ON CONFLICT(session_id, chunk_index) DO UPDATE SET size = excluded.size",
+        )
+        .bind(session_id)
+        .bind(index)
+        .bind(size)
+        .execute(&self.pool)
+        .await?;
+        let expires_at = self.upload_session_expiry();
+        sqlx::query("UPDATE upload_sessions SET expires_at = $1 WHERE id = $2")
+            .bind(&expires_at)
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(expires_at)
+    }
+
+    /// Concatenates a session's chunks into a regular upload, ready to be
+    /// recorded like any other. The chunks are kept if the result doesn't
+    /// match `expected`, so the client can resend the ones that were wrong.
+    pub async fn assemble_upload_session(
+        &self,
+        session: &UploadSession,
+        expected: &ExpectedChecksum,
+    ) -> Result<SavedUpload, UploadError> {
+        let dir = self.session_dir(&session.id);
+        let total = upload_sessions::total_chunks(session.file_size, session.chunk_size);
+        let storage = self.clone();
+        let body = stream::iter(0..total)
+            .then(move |index| {
+                let (storage, path) = (storage.clone(), dir.join(index.to_string()));
+                async move { storage.read_stored(path, 0, None).await }
+            })
+            .try_flatten()
+            .boxed();
+
+        self.save_file(&session.filename, body, expected).await
+    }
+
+    /// Removes a session and any chunks stored for it.
+    pub async fn delete_upload_session(&self, session_id: &str) -> Result<bool, sqlx::Error> {
+        sqlx::query("DELETE FROM upload_session_chunks WHERE session_id = $1")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+        let result = sqlx::query("DELETE FROM upload_sessions WHERE id = $1")
+            .bind(session_id)
+            .execute(&self.pool)
+            .await?;
+
+        let dir = self.session_dir(session_id);
+        if let Err(e) = fs::remove_dir_all(&dir).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove upload session chunks {:?}: {}", dir, e);
+            }
+        }
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Reclaims the disk space of uploads that will never finish: sessions
+    /// that went `UPLOAD_SESSION_TTL_SECS` without a chunk, chunk
+    /// directories whose session is gone, and `.part` files an upload left
+    /// behind when the server stopped mid-way. Runs at startup and on every
+    /// scheduler run.
+    pub async fn clean_up_uploads(&self) -> Result<Reclaimed, sqlx::Error> {
+        let ttl = std::time::Duration::from_secs(self.upload_session_ttl_secs);
+        let mut reclaimed = Reclaimed::default();
+
+        // Sessions from before expiry was recorded go by their creation time.
+        let now = Utc::now();
+        let created_before = now - chrono::Duration::seconds(self.upload_session_ttl_secs as i64);
+        let expired: Vec<String> = sqlx::query_scalar(
+            "SELECT id FROM upload_sessions WHERE expires_at <= $1 OR (expires_at IS NULL AND created_at <= $2)",
+        )
+        .bind(now.to_rfc3339())
+        .bind(created_before.to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+        for session_id in expired {
+            let size = disk_usage(&self.session_dir(&session_id)).await;
+            if self.delete_upload_session(&session_id).await? {
+                info!("Upload session {} expired", session_id);
+                reclaimed.sessions += 1;
+                reclaimed.bytes += size;
+            }
+        }
+
+        for dir in stale_entries(&self.upload_dir.join("sessions"), ttl).await {
+            let Some(session_id) = dir.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if self.get_upload_session(session_id).await?.is_some() {
+                continue;
+            }
+            let size = disk_usage(&dir).await;
+            match fs::remove_dir_all(&dir).await {
+                Ok(()) => {
+                    reclaimed.orphaned_directories += 1;
+                    reclaimed.bytes += size;
+                }
+                Err(e) => warn!("Failed to remove orphaned upload chunks {:?}: {}", dir, e),
+            }
+        }
+
+        for path in stale_entries(&self.upload_dir, ttl).await {
+            if path.extension().and_then(|e| e.to_str()) != Some("part") {
+                continue;
+            }
+            let size = fs::metadata(&path).await.map(|m| m.len()).unwrap_or(0);
+            match fs::remove_file(&path).await {
+                Ok(()) => {
+                    reclaimed.temp_files += 1;
+                    reclaimed.bytes += size;
+                }
+                Err(e) => warn!("Failed to remove interrupted upload {:?}: {}", path, e),
+            }
+        }
+
+        self.upload_cleanup.record(&reclaimed);
+        Ok(reclaimed)
+    }
+
+    fn archive_dir(&self) -> PathBuf {
+        self.upload_dir.join("archives")
+    }
+
+    /// Where the archive of a job is built and kept until it expires.
+    pub fn archive_path(&self, job_id: &str) -> PathBuf {
+        self.archive_dir().join(format!("{}.zip", job_id))
+    }
+
+    /// Opens `length` bytes of a job's finished archive, or the rest of it,
+    /// from byte `start`.
+    pub async fn open_archive(
+        &self,
+        job_id: &str,
+        start: u64,
+        length: Option<u64>,
+    ) -> std::io::Result<ContentStream> {
+        self.read_stored(self.archive_path(job_id), start, length).await
+    }
+
+    /// Room on the disk for temporary files such as built archives, which
+    /// don't count towards `MAX_TOTAL_STORAGE`.
+    pub fn disk_headroom(&self) -> Headroom {
+        Capacity {
+            max_total: None,
+            ..self.capacity
+        }
+        .headroom(&self.upload_dir, 0)
+    }
+
+    /// Queues an archive of `directory`, with what `user_id` may read of it.
+    pub async fn create_archive_job(
+        &self,
+        directory: &Directory,
+        user_id: Option<String>,
+    ) -> Result<ArchiveJob, sqlx::Error> {
+        let now = Utc::now();
+        let job = ArchiveJob {
+            id: Uuid::new_v4().to_string(),
+            directory_id: directory.id.clone(),
+            user_id,
+            status: ARCHIVE_PENDING.to_string(),
+            ticket: share_token(),
+            file_name: format!("{}.zip", directory.name),
+            file_size: None,
+            error: None,
+            created_at: now.to_rfc3339(),
+            finished_at: None,
+            expires_at: (now + chrono::Duration::seconds(self.archive_ttl_secs as i64)).to_rfc3339(),
+        };
+        sqlx::query(
+            r#"
+            INSERT INTO archive_jobs (id, directory_id, user_id, status, ticket, file_name, created_at, expires_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(&job.id)
+        .bind(&job.directory_id)
+        .bind(&job.user_id)
+        .bind(&job.status)
+        .bind(&job.ticket)
+        .bind(&job.file_name)
+        .bind(&job.created_at)
+        .bind(&job.expires_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(job)
+    }
+
+    pub async fn get_archive_job(&self, job_id: &str) -> Result<Option<ArchiveJob>, sqlx::Error> {
+        sqlx::query_as::<_, ArchiveJob>(
+            "SELECT id, directory_id, user_id, status, ticket, file_name, file_size, error, created_at, finished_at, expires_at FROM archive_jobs WHERE id = $1",
+        )
+        .bind(job_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn get_archive_job_by_ticket(
+        &self,
+        ticket: &str,
+    ) -> Result<Option<ArchiveJob>, sqlx::Error> {
+        sqlx::query_as::<_, ArchiveJob>(
+            "SELECT id, directory_id, user_id, status, ticket, file_name, file_size, error, created_at, finished_at, expires_at FROM archive_jobs WHERE ticket = $1",
+        )
+        .bind(ticket)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Jobs whose archive isn't built yet, such as those a restart cut off.
+    pub async fn pending_archive_jobs(&self) -> Result<Vec<ArchiveJob>, sqlx::Error> {
+        sqlx::query_as::<_, ArchiveJob>(
+            "SELECT id, directory_id, user_id, status, ticket, file_name, file_size, error, created_at, finished_at, expires_at FROM archive_jobs WHERE status = $1 ORDER BY created_at",
+        )
+        .bind(ARCHIVE_PENDING)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Records a job's archive as built with `result` bytes, or why it
+    /// couldn't be, and keeps it for `ARCHIVE_TTL_SECS` from now. Returns
+    /// whether the job still exists; it may have been cancelled meanwhile.
+    pub async fn finish_archive_job(
+        &self,
+        job_id: &str,
+        result: Result<i64, String>,
+    ) -> Result<bool, sqlx::Error> {
+        let now = Utc::now();
+        let (status, file_size, error) = match result {
+            Ok(size) => (ARCHIVE_READY, Some(size), None),
+            Err(e) => (ARCHIVE_FAILED, None, Some(e)),
+        };
+        let updated = sqlx::query(
+            "UPDATE archive_jobs SET status = $1, file_size = $2, error = $3, finished_at = $4, expires_at = $5 WHERE id = $6",
+        )
+        .bind(status)
+        .bind(file_size)
+        .bind(error)
+        .bind(now.to_rfc3339())
+        .bind((now + chrono::Duration::seconds(self.archive_ttl_secs as i64)).to_rfc3339())
+        .bind(job_id)
+        .execute(&self.pool)
+        .await?
+        .rows_affected();
+        Ok(updated > 0)
+    }
+
+    /// Removes a job and its archive, finished or not.
+    pub async fn delete_archive_job(&self, job_id: &str) -> Result<bool, sqlx::Error> {
+        let deleted = sqlx::query("DELETE FROM archive_jobs WHERE id = $1")
+            .bind(job_id)
+            .execute(&self.pool)
+            .await?
+            .rows_affected()
+            > 0;
+        let path = self.archive_path(job_id);
+        if let Err(e) = fs::remove_file(&path).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                warn!("Failed to remove archive {:?}: {}", path, e);
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Removes finished jobs past their `expires_at` with their archives,
+    /// and archive files no job refers to any more. Returns how many jobs
+    /// were removed.
+    pub async fn clean_up_archives(&self) -> Result<u64, sqlx::Error> {
+        let expired: Vec<String> = sqlx::query_scalar(
+            "SELECT id FROM archive_jobs WHERE status <> $1 AND expires_at < $2",
+        )
+        .bind(ARCHIVE_PENDING)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_all(&self.pool)
+        .await?;
+        for job_id in &expired {
+            self.delete_archive_job(job_id).await?;
+        }
+
+        let ttl = std::time::Duration::from_secs(self.archive_ttl_secs);
+        for path in stale_entries(&self.archive_dir(), ttl).await {
+            let Some(job_id) = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.split('.').next())
+            else {
+                continue;
+            };
+            if self.get_archive_job(job_id).await?.is_some() {
+                continue;
+            }
+            if let Err(e) = fs::remove_file(&path).await {
+                warn!("Failed to remove orphaned archive {:?}: {}", path, e);
+            }
+        }
+        Ok(expired.len() as u64)
+    }
+
+    // Directory management methods
+    /// Creates a directory, unless a sibling already goes by `name`, which
+    /// fails with `NameTaken`.
+    pub async fn create_directory(
+        &self,
+        name: &str,
+        parent_id: Option<String>,
+    ) -> Result<Directory, Box<dyn std::error::Error + Send + Sync>> {
+        if parent_id.as_deref().is_some_and(mirror::is_mirror_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let dir_id = Uuid::new_v4().to_string();
+        let now = Utc::now().to_rfc3339();
+
+        let directory = Directory {
+            id: dir_id.clone(),
+            name: name.to_string(),
+            parent_id: parent_id.clone(),
+            created_at: now.clone(),
+            updated_at: now.clone(),
+            color: None,
+            icon: None,
+            version: 1,
+        };
+
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        if let Some(taken) = self
+            .sibling_named(&mut tx, parent_id.as_deref(), name, None, true)
+            .await?
+        {
+            return Err(taken.into());
+        }
+        sqlx::query(
+            r#"
+            INSERT INTO directories (id, name, parent_id, created_at, updated_at)
+            VALUES ($1, $2, $3, $4, $5)
+            "#
+        )
+        .bind(&directory.id)
+        .bind(&directory.name)
+        .bind(&directory.parent_id)
+        .bind(&directory.created_at)
+        .bind(&directory.updated_at)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.events.publish(ChangeEvent::DirectoryCreated {
+            directory_id: directory.id.clone(),
+            name: directory.name.clone(),
+            parent_id: directory.parent_id.clone(),
+        });
+        info!("Directory created: {} ({})", name, dir_id);
+        Ok(directory)
+    }
+
+    /// Every directory and link beneath `root`, plus the files passing
+    /// `filter`, in no particular order. Directories `access` doesn't allow
+    /// reading are left out, along with everything in them.
+    pub async fn list_tree(
+        &self,
+        root: Option<String>,
+        filter: &FileFilter,
+        access: &Access,
+    ) -> Result<Vec<TreeEntry>, sqlx::Error> {
+        let join = |prefix: &str, name: &str| {
+            if prefix.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", prefix, name)
+            }
+        };
+
+        let mut entries = Vec::new();
+        let mut pending = vec![(root, String::new())];
+        while let Some((dir_id, prefix)) = pending.pop() {
+            for file in self
+                .list_files_matching(dir_id.clone(), filter, FileOrder::default())
+                .await?
+            {
+                entries.push(TreeEntry::File {
+                    path: join(&prefix, &file.original_filename),
+                    file: file.into(),
+                });
+            }
+            for link in self.list_links(dir_id.clone()).await? {
+                entries.push(TreeEntry::Link {
+                    path: join(&prefix, &link.name),
+                    link,
+                });
+            }
+            for directory in self.list_directories(dir_id).await? {
+                if !access.allows(Some(&directory.id), Role::Read) {
+                    continue;
+                }
+                let path = join(&prefix, &directory.name);
+                pending.push((Some(directory.id.clone()), path.clone()));
+                entries.push(TreeEntry::Directory { path, directory });
+            }
+        }
+
+        Ok(entries)
+    }
+
+    pub async fn list_directories(&self, parent_id: Option<String>) -> Result<Vec<Directory>, sqlx::Error> {
+        if let Some(dir_id) = parent_id.as_deref().filter(|id| mirror::is_mirror_id(id)) {
+            return match &self.mirror {
+                Some(m) => Ok(m.list(dir_id).await.map_err(sqlx::Error::Io)?.1),
+                None => Ok(Vec::new()),
+            };
+        }
+
+        let mut directories = if let Some(p_id) = parent_id {
+            sqlx::query_as::<_, Directory>(
+                "SELECT id, name, parent_id, created_at, updated_at, color, icon, version FROM directories WHERE parent_id = $1 AND trashed = 0 ORDER BY name ASC"
+            )
+            .bind(p_id)
+            .fetch_all(&self.pool)
+            .await?
+        } else {
+            let mut root = sqlx::query_as::<_, Directory>(
+                "SELECT id, name, parent_id, created_at, updated_at, color, icon, version FROM directories WHERE parent_id IS NULL AND trashed = 0 ORDER BY name ASC"
+            )
+            .fetch_all(&self.pool)
+            .await?;
+            if let Some(m) = &self.mirror {
+                root.push(m.root_directory().await.map_err(sqlx::Error::Io)?);
+            }
+            root
+        };
+        directories.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(directories)
+    }
+
+    /// Looks up a directory by name among the children of `parent_id`,
+    /// honoring the instance's case sensitivity setting.
+    pub async fn find_child_directory(
+        &self,
+        parent_id: Option<&str>,
+        name: &str,
+    ) -> Result<Option<Directory>, sqlx::Error> {
+        let siblings = self.list_directories(parent_id.map(|p| p.to_string())).await?;
+        Ok(siblings
+            .into_iter()
+            .find(|dir| self.names.same_name(&dir.name, name)))
+    }
+
+    /// Walks `names` down from `parent_id`, creating any directories that
+    /// don't exist yet, and returns the ID of the last one.
+    pub async fn ensure_directory_path(
+        &self,
+        parent_id: Option<String>,
+        names: &[String],
+    ) -> Result<Option<String>, Box<dyn std::error::Error + Send + Sync>> {
+        let mut current = parent_id;
+        for name in names {
+            let directory = match self.find_child_directory(current.as_deref(), name).await? {
+                Some(existing) => existing,
+                None => match self.create_directory(name, current.clone()).await {
+                    Ok(created) => created,
+                    // Someone else may have created it since the lookup.
+                    Err(e) if e.is::<NameTaken>() => self
+                        .find_child_directory(current.as_deref(), name)
+                        .await?
+                        .ok_or(e)?,
+                    Err(e) => return Err(e),
+                },
+            };
+            current = Some(directory.id);
+        }
+        Ok(current)
+    }
+
+    pub async fn get_directory(&self, dir_id: &str) -> Result<Option<Directory>, sqlx::Error> {
+        if mirror::is_mirror_id(dir_id) {
+            return match &self.mirror {
+                Some(m) => m.get_directory(dir_id).await.map_err(sqlx::Error::Io),
+                None => Ok(None),
+            };
+        }
+
+        let directory = sqlx::query_as::<_, Directory>(
+            "SELECT id, name, parent_id, created_at, updated_at, color, icon, version FROM directories WHERE id = $1 AND trashed = 0"
+        )
+        .bind(dir_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        Ok(directory)
+    }
+
+    /// Moves a directory, with everything inside it, to the trash. Returns
+    /// whether there was such a directory outside the trash.
+    pub async fn delete_directory(
+        &self,
+        dir_id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(dir_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let Some(directory) = self.get_directory(dir_id).await? else {
+            return Ok(false);
+        };
+        let mut tx = self.pool.begin().await?;
+        let trashed = sqlx::query("UPDATE directories SET trashed = 1, deleted_at = $1 WHERE id = $2 AND trashed = 0")
+            .bind(Utc::now().to_rfc3339())
+            .bind(dir_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+            > 0;
+        if trashed {
+            set_contents_trashed(&mut tx, dir_id, true).await?;
+        }
+        tx.commit().await?;
+
+        if trashed {
+            self.events.publish(ChangeEvent::DirectoryDeleted {
+                directory_id: directory.id,
+                parent_id: directory.parent_id,
+            });
+            info!("Directory moved to trash: {}", dir_id);
+        }
+        Ok(trashed)
+    }
+
+    /// Deletes a directory and everything in it for good, whether or not it
+    /// is in the trash.
+    pub async fn delete_directory_permanently(
+        &self,
+        dir_id: &str,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(dir_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let directory = sqlx::query_as::<_, Directory>(
+            "SELECT id, name, parent_id, created_at, updated_at, color, icon, version FROM directories WHERE id = $1",
+        )
+        .bind(dir_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some(directory) = directory else {
+            return Ok(false);
+        };
+
+        let subtree: Vec<(String,)> = sqlx::query_as(
+            r#"
+            WITH RECURSIVE subtree(id) AS (
+                SELECT CAST($1 AS TEXT)
+                UNION ALL
+                SELECT d.id FROM directories d JOIN subtree s ON d.parent_id = s.id
+            )
+            SELECT id FROM subtree
+            "#,
+        )
+        .bind(dir_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        // Children first, so no directory is removed before what is in it.
+        for (id,) in subtree.iter().rev() {
+            let files = sqlx::query_as::<_, FileMetadata>(
+                "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 \
+                 FROM files WHERE parent_directory_id = $1",
+            )
+            .bind(id)
+            .fetch_all(&self.pool)
+            .await?;
+            for file in files {
+                self.remove_file(file).await?;
+            }
+            delete_directory_rows(&mut *self.pool.acquire().await?, id).await?;
+            sqlx::query("DELETE FROM directories WHERE id = $1")
+                .bind(id)
+                .execute(&self.pool)
+                .await?;
+        }
+
+        self.events.publish(ChangeEvent::DirectoryDeleted {
+            directory_id: directory.id,
+            parent_id: directory.parent_id,
+        });
+        info!("Directory deleted: {}", dir_id);
+        Ok(true)
+    }
+
+    /// Files and directories in the trash, most recently deleted first.
+    /// What went into the trash inside a directory isn't listed on its own.
+    pub async fn list_trash(&self) -> Result<Vec<TrashItem>, sqlx::Error> {
+        self.trash_items(None).await
+    }
+
+    pub async fn get_trash_item(&self, id: &str) -> Result<Option<TrashItem>, sqlx::Error> {
+        Ok(self.trash_items(Some(id)).await?.pop())
+    }
+
+    async fn trash_items(&self, id: Option<&str>) -> Result<Vec<TrashItem>, sqlx::Error> {
+        let mut items = sqlx::query_as::<_, TrashItem>(
+            r#"
+            SELECT id, CAST('file' AS TEXT) AS kind, original_filename AS name,
+                   parent_directory_id AS parent_id, file_size, deleted_at
+            FROM files WHERE deleted_at IS NOT NULL AND (CAST($1 AS TEXT) IS NULL OR id = CAST($2 AS TEXT))
+            UNION ALL
+            SELECT id, CAST('directory' AS TEXT), name, parent_id, CAST(NULL AS BIGINT), deleted_at
+            FROM directories WHERE deleted_at IS NOT NULL AND (CAST($3 AS TEXT) IS NULL OR id = CAST($4 AS TEXT))
+            ORDER BY deleted_at DESC
+            "#,
+        )
+        .bind(id)
+        .bind(id)
+        .bind(id)
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let retention = chrono::Duration::days(self.trash_retention_days as i64);
+        for item in &mut items {
+            item.purge_at = DateTime::parse_from_rfc3339(&item.deleted_at)
+                .map(|t| (t + retention).to_rfc3339())
+                .unwrap_or_default();
+        }
+        Ok(items)
+    }
+
+    /// Takes an item out of the trash, along with everything that went into
+    /// the trash with it. Returns whether it was still in the trash.
+    pub async fn restore_from_trash(
+        &self,
+        item: &TrashItem,
+    ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        if let Some(taken) = self
+            .sibling_named(
+                &mut tx,
+                item.parent_id.as_deref(),
+                &item.name,
+                Some(&item.id),
+                item.kind == "directory",
+            )
+            .await?
+        {
+            return Err(taken.into());
+        }
+        let table = if item.kind == "directory" { "directories" } else { "files" };
+        let restored = sqlx::query(&format!(
+            "UPDATE {} SET trashed = 0, deleted_at = NULL WHERE id = $1 AND deleted_at IS NOT NULL",
+            table
+        ))
+        .bind(&item.id)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected()
+            > 0;
+        if restored && item.kind == "directory" {
+            set_contents_trashed(&mut tx, &item.id, false).await?;
+        }
+        tx.commit().await?;
+
+        if restored {
+            self.events.publish(if item.kind == "directory" {
+                ChangeEvent::DirectoryCreated {
+                    directory_id: item.id.clone(),
+                    name: item.name.clone(),
+                    parent_id: item.parent_id.clone(),
+                }
+            } else {
+                ChangeEvent::FileCreated {
+                    file_id: item.id.clone(),
+                    name: item.name.clone(),
+                    parent_directory_id: item.parent_id.clone(),
+                }
+            });
+            info!("Restored {} from trash: {}", item.kind, item.id);
+        }
+        Ok(restored)
+    }
+
+    /// Deletes for good everything that has been in the trash for longer
+    /// than the trash retention period. Returns how many items went.
+    pub async fn purge_trash(&self) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let cutoff = (Utc::now() - chrono::Duration::days(self.trash_retention_days as i64)).to_rfc3339();
+        let expired: Vec<(String, String)> = sqlx::query_as(
+            "SELECT id, CAST('file' AS TEXT) FROM files WHERE deleted_at < $1 \
+             UNION ALL SELECT id, CAST('directory' AS TEXT) FROM directories WHERE deleted_at < $2",
+        )
+        .bind(&cutoff)
+        .bind(&cutoff)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut purged = 0;
+        for (id, kind) in expired {
+            // An item may have gone with a directory purged before it.
+            let deleted = if kind == "directory" {
+                self.delete_directory_permanently(&id).await?
+            } else {
+                self.delete_file_permanently(&id).await?
+            };
+            if deleted {
+                purged += 1;
+            }
+        }
+        Ok(purged)
+    }
+
+    pub async fn get_directory_stats(&self, dir_id: &str) -> Result<(i64, i64), sqlx::Error> {
+        if mirror::is_mirror_id(dir_id) {
+            return match &self.mirror {
+                Some(m) => m.stats(dir_id).await.map_err(sqlx::Error::Io),
+                None => Ok((0, 0)),
+            };
+        }
+
+        // Get file count and total size for a directory
+        let result: Option<(Nullable<i64>, Nullable<i64>)> = sqlx::query_as(
+            "SELECT COUNT(*), CAST(SUM(file_size) AS BIGINT) FROM files WHERE parent_directory_id = $1 AND trashed = 0"
+        )
+        .bind(dir_id)
+        .fetch_optional(&self.pool)
+        .await?;
+
+        match result {
+            Some((Nullable(Some(count)), Nullable(Some(size)))) => Ok((count, size)),
+            Some((Nullable(Some(count)), Nullable(None))) => Ok((count, 0)),
+            _ => Ok((0, 0)),
+        }
+    }
+
+    /// Moves a file if it is still at `version`; returns `None` when it is
+    /// not, or no longer exists. Fails with `NameTaken` when a sibling in
+    /// the target already goes by its name.
+    pub async fn move_file(
+        &self,
+        file_id: &str,
+        parent_directory_id: Option<String>,
+        version: i64,
+    ) -> Result<Option<FileMetadata>, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(file_id)
+            || parent_directory_id.as_deref().is_some_and(mirror::is_mirror_id)
+        {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        let Some(file) = fetch_file(&mut tx, file_id).await? else {
+            return Ok(None);
+        };
+        if let Some(taken) = self
+            .sibling_named(
+                &mut tx,
+                parent_directory_id.as_deref(),
+                &file.original_filename,
+                Some(file_id),
+                false,
+            )
+            .await?
+        {
+            return Err(taken.into());
+        }
+        let result = sqlx::query(
+            "UPDATE files SET parent_directory_id = $1, version = version + 1 WHERE id = $2 AND version = $3"
+        )
+        .bind(&parent_directory_id)
+        .bind(file_id)
+        .bind(version)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        tx.commit().await?;
+        self.unpin_moved_entry(file_id, parent_directory_id.as_deref())
+            .await?;
+
+        let metadata = self.get_file_metadata(file_id).await?;
+        self.events.publish(ChangeEvent::FileMoved {
+            file_id: file_id.to_string(),
+            parent_directory_id: parent_directory_id.clone(),
+        });
+        info!("File moved: {} -> {:?}", file_id, parent_directory_id);
+        Ok(metadata)
+    }
+
+    /// Moves a directory if it is still at `version`; returns `None` when it
+    /// is not, or no longer exists. Moves into the directory itself or
+    /// anything beneath it are refused, as are moves next to a sibling with
+    /// its name (`NameTaken`), checked in the same transaction as the move
+    /// and under `lock_directory_tree`, so a concurrent change can't slip a
+    /// cycle or a second such name in between.
+    pub async fn move_directory(
+        &self,
+        dir_id: &str,
+        parent_id: Option<String>,
+        version: i64,
+    ) -> Result<Option<Directory>, Box<dyn std::error::Error + Send + Sync>> {
+        if mirror::is_mirror_id(dir_id) || parent_id.as_deref().is_some_and(mirror::is_mirror_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        if let Some(ref target_id) = parent_id {
+            if target_id == dir_id {
+                return Err("Cannot move a directory into itself".into());
+            }
+            if is_ancestor_within(&mut tx, dir_id, target_id).await? {
+                return Err("Cannot move a directory into one of its own subdirectories".into());
+            }
+        }
+        let Some(directory) = fetch_directory(&mut tx, dir_id).await? else {
+            return Ok(None);
+        };
+        if let Some(taken) = self
+            .sibling_named(&mut tx, parent_id.as_deref(), &directory.name, Some(dir_id), true)
+            .await?
+        {
+            return Err(taken.into());
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let result = sqlx::query(
+            "UPDATE directories SET parent_id = $1, updated_at = $2, version = version + 1 WHERE id = $3 AND version = $4"
+        )
+        .bind(&parent_id)
+        .bind(&now)
+        .bind(dir_id)
+        .bind(version)
+        .execute(&mut *tx)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        tx.commit().await?;
+        self.unpin_moved_entry(dir_id, parent_id.as_deref()).await?;
+
+        let directory = self.get_directory(dir_id).await?;
+        self.events.publish(ChangeEvent::DirectoryMoved {
+            directory_id: dir_id.to_string(),
+            parent_id: parent_id.clone(),
+        });
+        info!("Directory moved: {} -> {:?}", dir_id, parent_id);
+        Ok(directory)
+    }
+
+    /// Sets the color label and icon of a directory if it is still at
+    /// `version`; `None` clears them.
+    pub async fn set_directory_appearance(
+        &self,
+        dir_id: &str,
+        color: Option<&str>,
+        icon: Option<&str>,
+        version: i64,
+    ) -> Result<Option<Directory>, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE directories SET color = $1, icon = $2, updated_at = $3, version = version + 1 \
+             WHERE id = $4 AND version = $5",
+        )
+        .bind(color)
+        .bind(icon)
+        .bind(Utc::now().to_rfc3339())
+        .bind(dir_id)
+        .bind(version)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.get_directory(dir_id).await
+    }
+
+    /// Pins only apply within the directory an entry is in, so moving it
+    /// elsewhere unpins it.
+    async fn unpin_moved_entry(
+        &self,
+        entry_id: &str,
+        parent_id: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM directory_pins WHERE entry_id = $1 AND directory_id IS DISTINCT FROM CAST($2 AS TEXT)")
+            .bind(entry_id)
+            .bind(parent_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Makes directory moves and name checks take turns until the end of
+    /// the transaction on `conn`. Checking a move for cycles reads the
+    /// target's ancestors, and under Postgres' READ COMMITTED isolation two
+    /// transactions moving A into B and B into A would each find none and
+    /// both commit; likewise two creating `a` and `A` would each find the
+    /// name free. SQLite lets one transaction write at a time but refuses,
+    /// without waiting, a write from one that has read while another wrote,
+    /// so there the transaction starts with a write that changes nothing,
+    /// to wait its turn before it reads.
+    async fn lock_directory_tree(&self, conn: &mut DbConnection) -> Result<(), sqlx::Error> {
+        let lock = if self.database_backend == Backend::Postgres {
+            sqlx::query("SELECT pg_advisory_xact_lock($1)::TEXT").bind(DIRECTORY_TREE_LOCK)
+        } else {
+            sqlx::query("UPDATE directories SET version = version WHERE 1 = 0")
+        };
+        lock.execute(&mut *conn).await?;
+        Ok(())
+    }
+
+    /// Returns true if `ancestor_id` is an ancestor of `target_id` (walks up the tree).
+    async fn is_ancestor_of(&self, ancestor_id: &str, target_id: &str) -> Result<bool, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        is_ancestor_within(&mut conn, ancestor_id, target_id).await
+    }
+
+    /// Recursive size totals for the whole tree, nested `depth` levels below
+    /// the root. Totals always include everything beneath a directory, even
+    /// past the depth cut-off. Mirrored directories are not included.
+    pub async fn usage_tree(&self, depth: usize) -> Result<UsageNode, sqlx::Error> {
+        let rows: Vec<(String, String, Nullable<String>, i64, i64)> = sqlx::query_as(
+            r#"
+            WITH RECURSIVE subtree(root_id, id) AS (
+                SELECT id, id FROM directories
+                UNION ALL
+                SELECT subtree.root_id, d.id FROM directories d JOIN subtree ON d.parent_id = subtree.id
+            )
+            SELECT d.id, d.name, d.parent_id, COUNT(f.id), CAST(COALESCE(SUM(f.file_size), 0) AS BIGINT)
+            FROM directories d
+            JOIN subtree ON subtree.root_id = d.id
+            LEFT JOIN files f ON f.parent_directory_id = subtree.id
+            GROUP BY d.id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await?;
+
+        let (file_count, total_size): (i64, i64) =
+            sqlx::query_as("SELECT COUNT(*), CAST(COALESCE(SUM(file_size), 0) AS BIGINT) FROM files")
+                .fetch_one(&self.pool)
+                .await?;
+
+        let mut children_of: HashMap<Option<String>, Vec<UsageNode>> = HashMap::new();
+        for (id, name, parent_id, file_count, total_size) in rows {
+            children_of.entry(parent_id.into()).or_default().push(UsageNode {
+                id: Some(id),
+                name,
+                total_size,
+                file_count,
+                children: Vec::new(),
+            });
+        }
+
+        fn attach(
+            node: &mut UsageNode,
+            children_of: &mut HashMap<Option<String>, Vec<UsageNode>>,
+            depth: usize,
+        ) {
+            if depth == 0 {
+                return;
+            }
+            let mut children = children_of.remove(&node.id).unwrap_or_default();
+            for child in &mut children {
+                attach(child, children_of, depth - 1);
+            }
+            children.sort_by_key(|child| std::cmp::Reverse(child.total_size));
+            node.children = children;
+        }
+
+        let mut root = UsageNode {
+            id: None,
+            name: "/".to_string(),
+            total_size,
+            file_count,
+            children: Vec::new(),
+        };
+        attach(&mut root, &mut children_of, depth);
+        Ok(root)
+    }
+
+    /// Everything in a directory's subtree for a ZIP download: each
+    /// directory, then the files in it, with paths starting at the
+    /// directory's own name. Subdirectories `access` doesn't allow reading
+    /// are left out, along with everything in them.
+    pub async fn archive_entries(
+        &self,
+        dir_id: &str,
+        access: &Access,
+    ) -> Result<Vec<ArchiveEntry>, sqlx::Error> {
+        let directories: Vec<(String, String, String)> = sqlx::query_as(
+            r#"
+            WITH RECURSIVE tree(id, path, updated_at) AS (
+                SELECT id, name, updated_at FROM directories WHERE id = $1
+                UNION ALL
+                SELECT d.id, tree.path || '/' || d.name, d.updated_at
+                FROM directories d JOIN tree ON d.parent_id = tree.id
+                WHERE d.trashed = 0
+            )
+            SELECT id, path, updated_at FROM tree ORDER BY path
+            "#,
+        )
+        .bind(dir_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries = Vec::new();
+        let mut hidden: Vec<String> = Vec::new();
+        for (id, path, updated_at) in directories {
+            // Parents sort before their children, so a hidden directory is
+            // seen before anything beneath it.
+            if hidden.iter().any(|prefix| path.starts_with(&format!("{}/", prefix))) {
+                continue;
+            }
+            if !access.allows(Some(&id), Role::Read) {
+                hidden.push(path);
+                continue;
+            }
+            let files = self.list_files(Some(id)).await?;
+            entries.push(ArchiveEntry {
+                path: path.clone(),
+                modified: updated_at,
+                content: ArchiveContent::Directory,
+            });
+            entries.extend(files.into_iter().map(|file| ArchiveEntry {
+                path: format!("{}/{}", path, file.original_filename),
+                modified: file.uploaded_at.clone(),
+                content: ArchiveContent::File(Box::new(file)),
+            }));
+        }
+        Ok(entries)
+    }
+
+    /// Estimated monthly storage and egress cost in total, per directory and
+    /// per share, with egress based on the downloads of the last
+    /// `window_days`. Downloads are counted at the file's current size; the
+    /// access log doesn't record partial transfers. Lists are cut off at
+    /// `limit` entries each.
+    pub async fn cost_report(&self, window_days: u64, limit: usize) -> Result<CostReport, sqlx::Error> {
+        let since = (Utc::now() - chrono::Duration::days(window_days as i64)).to_rfc3339();
+        let rates = &self.costs;
+
+        let (stored, downloaded): (i64, i64) = sqlx::query_as(
+            r#"
+            WITH downloads(file_id, count) AS (
+                SELECT file_id, COUNT(*) FROM file_access_log WHERE accessed_at >= $1 GROUP BY file_id
+            )
+            SELECT CAST(COALESCE(SUM(f.file_size), 0) AS BIGINT),
+                   CAST(COALESCE(SUM(f.file_size * COALESCE(dl.count, 0)), 0) AS BIGINT)
+            FROM files f
+            LEFT JOIN downloads dl ON dl.file_id = f.id
+            "#,
+        )
+        .bind(&since)
+        .fetch_one(&self.pool)
+        .await?;
+
+        let rows: Vec<(String, String, Nullable<String>, i64, i64, i64)> = sqlx::query_as(
+            r#"
+            WITH RECURSIVE subtree(root_id, id) AS (
+                SELECT id, id FROM directories
+                UNION ALL
+                SELECT subtree.root_id, d.id FROM directories d JOIN subtree ON d.parent_id = subtree.id
+            ),
+            downloads(file_id, count) AS (
+                SELECT file_id, COUNT(*) FROM file_access_log WHERE accessed_at >= $1 GROUP BY file_id
+            )
+            SELECT d.id, d.name, d.parent_id, COUNT(f.id), CAST(COALESCE(SUM(f.file_size), 0) AS BIGINT),
+                   CAST(COALESCE(SUM(f.file_size * COALESCE(dl.count, 0)), 0) AS BIGINT)
+            FROM directories d
+            JOIN subtree ON subtree.root_id = d.id
+            LEFT JOIN files f ON f.parent_directory_id = subtree.id
+            LEFT JOIN downloads dl ON dl.file_id = f.id
+            GROUP BY d.id
+            "#,
+        )
+        .bind(&since)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut directories: Vec<DirectoryCost> = rows
+            .into_iter()
+            .map(|(id, name, parent_id, file_count, stored, downloaded)| DirectoryCost {
+                id,
+                name,
+                parent_id: parent_id.into(),
+                file_count,
+                estimate: rates.estimate(stored, downloaded, window_days),
+            })
+            .collect();
+        directories.sort_by(|a, b| b.estimate.total_cost.total_cmp(&a.estimate.total_cost));
+        directories.truncate(limit);
+
+        let rows: Vec<(String, String, String, i64, i64)> = sqlx::query_as(
+            r#"
+            SELECT s.id, f.id, f.original_filename, f.file_size,
+                   f.file_size * (SELECT COUNT(*) FROM file_access_log l WHERE l.share_id = s.id AND l.accessed_at >= $1)
+            FROM shares s
+            JOIN files f ON f.id = s.file_id
+            "#,
+        )
+        .bind(&since)
+        .fetch_all(&self.pool)
+        .await?;
+        let mut shares: Vec<ShareCost> = rows
+            .into_iter()
+            .map(|(share_id, file_id, file_name, stored, downloaded)| ShareCost {
+                share_id,
+                file_id,
+                file_name,
+                estimate: rates.estimate(stored, downloaded, window_days),
+            })
+            .collect();
+        shares.sort_by(|a, b| b.estimate.total_cost.total_cmp(&a.estimate.total_cost));
+        shares.truncate(limit);
+
+        Ok(CostReport {
+            currency: rates.currency.clone(),
+            storage_cost_per_gb_month: rates.storage_per_gb_month,
+            egress_cost_per_gb: rates.egress_per_gb,
+            window_days,
+            total: rates.estimate(stored, downloaded, window_days),
+            directories,
+            shares,
+        })
+    }
+
+    /// Counts a download of a file, adds it to the file's access log and
+    /// hands it to the audit export. Mirrored files aren't tracked.
+    pub async fn record_download(&self, metadata: &FileMetadata, access: &AccessContext) -> Result<(), sqlx::Error> {
+        let file_id = metadata.id.as_str();
+        if mirror::is_mirror_id(file_id) {
+            return Ok(());
+        }
+        let occurred_at = Utc::now();
+        let now = occurred_at.to_rfc3339();
+
+        sqlx::query(
+            "INSERT INTO file_access_log (file_id, accessed_at, share_id, client_ip, user_agent, accepted_terms) \
+             VALUES ($1, $2, $3, $4, $5, $6)"
+        )
+        .bind(file_id)
+        .bind(&now)
+        .bind(&access.share_id)
+        .bind(&access.client_ip)
+        .bind(&access.user_agent)
+        .bind(&access.accepted_terms)
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO file_access_stats (file_id, download_count, last_downloaded_at)
+            VALUES ($1, 1, $2)
+            ON CONFLICT(file_id) DO UPDATE SET
+                download_count = file_access_stats.download_count + 1,
+                last_downloaded_at = excluded.last_downloaded_at
+            "#,
+        )
+        .bind(file_id)
+        .bind(&now)
+        .execute(&self.pool)
+        .await?;
+
+        self.audit.publish(DownloadAudit {
+            occurred_at,
+            file_id: metadata.id.clone(),
+            file_name: metadata.original_filename.clone(),
+            file_size: metadata.file_size,
+            access: access.clone(),
+        });
+        Ok(())
+    }
+
+    /// Most recent downloads of a file, newest first.
+    pub async fn access_log(&self, file_id: &str, limit: i64) -> Result<Vec<AccessLogEntry>, sqlx::Error> {
+        sqlx::query_as::<_, AccessLogEntry>(
+            "SELECT accessed_at, share_id, client_ip, user_agent, accepted_terms FROM file_access_log \
+             WHERE file_id = $1 ORDER BY accessed_at DESC LIMIT $2"
+        )
+        .bind(file_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Deletes access log entries older than the retention period.
+    pub async fn prune_access_log(&self) -> Result<u64, sqlx::Error> {
+        let cutoff = Utc::now() - chrono::Duration::days(self.access_log_retention_days as i64);
+        let result = sqlx::query("DELETE FROM file_access_log WHERE accessed_at < $1")
+            .bind(cutoff.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Files for a cleanup report, with their download statistics.
+    pub async fn file_report(&self, report: FileReport, limit: i64) -> Result<Vec<FileUsage>, sqlx::Error> {
+        let (condition, order) = match report {
+            FileReport::Largest => ("1 = 1", "f.file_size DESC"),
+            FileReport::LeastAccessed => (
+                "1 = 1",
+                "COALESCE(s.last_downloaded_at, f.uploaded_at) ASC",
+            ),
+            FileReport::NeverDownloaded => (
+                "COALESCE(s.download_count, 0) = 0",
+                "f.uploaded_at ASC",
+            ),
+        };
+
+        sqlx::query_as::<_, FileUsage>(&format!(
+            "SELECT f.id, f.filename, f.original_filename, f.file_size, f.mime_type, f.storage_path, \
+             f.uploaded_at, f.description, f.parent_directory_id, f.version, f.owner_id, f.sha256, \
+             COALESCE(s.download_count, 0) AS download_count, s.last_downloaded_at \
+             FROM files f LEFT JOIN file_access_stats s ON s.file_id = f.id \
+             WHERE f.trashed = 0 AND {} ORDER BY {} LIMIT $1",
+            condition, order
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    // Notification methods
+    pub async fn get_notification_preferences(
+        &self,
+        user_id: &str,
+    ) -> Result<Option<NotificationPreferences>, sqlx::Error> {
+        let row = sqlx::query_as::<_, NotificationPreferencesRow>(
+            "SELECT user_id, email, on_share_access, on_new_files, delivery, updated_at \
+             FROM notification_preferences WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(row.map(NotificationPreferences::from))
+    }
+
+    /// Preferences of every user who has saved any.
+    pub async fn list_notification_preferences(
+        &self,
+    ) -> Result<Vec<NotificationPreferences>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, NotificationPreferencesRow>(
+            "SELECT user_id, email, on_share_access, on_new_files, delivery, updated_at \
+             FROM notification_preferences ORDER BY user_id",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(NotificationPreferences::from).collect())
+    }
+
+    pub async fn save_notification_preferences(
+        &self,
+        prefs: &NotificationPreferences,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO notification_preferences (user_id, email, on_share_access, on_new_files, delivery, updated_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(user_id) DO UPDATE SET
+                email = excluded.email,
+                on_share_access = excluded.on_share_access,
+                on_new_files = excluded.on_new_files,
+                delivery = excluded.delivery,
+                updated_at = excluded.updated_at
+            "#,
+        )
+        .bind(&prefs.user_id)
+        .bind(&prefs.email)
+        .bind(i64::from(prefs.on_share_access))
+        .bind(i64::from(prefs.on_new_files))
+        .bind(&prefs.delivery)
+        .bind(&prefs.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn watch_folder(&self, user_id: &str, directory_id: &str) -> Result<WatchedFolder, sqlx::Error> {
+        let watch = WatchedFolder {
+            user_id: user_id.to_string(),
+            directory_id: directory_id.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            "INSERT INTO watched_folders (user_id, directory_id, created_at) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING"
+        )
+        .bind(&watch.user_id)
+        .bind(&watch.directory_id)
+        .bind(&watch.created_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(watch)
+    }
+
+    pub async fn unwatch_folder(&self, user_id: &str, directory_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM watched_folders WHERE user_id = $1 AND directory_id = $2")
+            .bind(user_id)
+            .bind(directory_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_watched_folders(&self, user_id: &str) -> Result<Vec<WatchedFolder>, sqlx::Error> {
+        sqlx::query_as::<_, WatchedFolder>(
+            "SELECT user_id, directory_id, created_at FROM watched_folders WHERE user_id = $1 ORDER BY created_at ASC"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// The directory access lists as `user_id` sees them. The directory tree
+    /// is only loaded once some directory has an access list.
+    pub async fn access(&self, user_id: Option<&str>) -> Result<Access, sqlx::Error> {
+        let rows: Vec<(String, String, String)> =
+            sqlx::query_as("SELECT directory_id, user_id, role FROM directory_acl")
+                .fetch_all(&self.pool)
+                .await?;
+        let user_id = user_id.map(str::to_string);
+        if rows.is_empty() {
+            return Ok(Access::new(user_id, HashMap::new(), HashMap::new()));
+        }
+
+        let mut entries: HashMap<String, HashMap<String, Role>> = HashMap::new();
+        for (directory_id, user, role) in rows {
+            if let Some(role) = Role::from_name(&role) {
+                entries.entry(directory_id).or_default().insert(user, role);
+            }
+        }
+        let parents: Vec<(String, Nullable<String>)> =
+            sqlx::query_as("SELECT id, parent_id FROM directories")
+                .fetch_all(&self.pool)
+                .await?;
+        let parents = parents
+            .into_iter()
+            .map(|(id, Nullable(parent_id))| (id, parent_id))
+            .collect();
+        Ok(Access::new(user_id, parents, entries))
+    }
+
+    /// The access list entries on `directory_id` itself.
+    pub async fn list_directory_acl(&self, directory_id: &str) -> Result<Vec<DirectoryAclEntry>, sqlx::Error> {
+        sqlx::query_as::<_, DirectoryAclEntry>(
+            r#"
+            SELECT a.directory_id, a.user_id, u.username, a.role, a.created_at
+            FROM directory_acl a
+            LEFT JOIN users u ON u.id = a.user_id
+            WHERE a.directory_id = $1
+            ORDER BY a.created_at ASC, a.user_id ASC
+            "#,
+        )
+        .bind(directory_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Gives `user_id` `role` in `directory_id`, replacing any role they had
+    /// there.
+    pub async fn set_directory_acl(
+        &self,
+        directory_id: &str,
+        user_id: &str,
+        role: Role,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO directory_acl (directory_id, user_id, role, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (directory_id, user_id) DO UPDATE SET role = excluded.role
+            "#,
+        )
+        .bind(directory_id)
+        .bind(user_id)
+        .bind(role.as_str())
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        info!("Gave user {} {} access to directory {}", user_id, role.as_str(), directory_id);
+        Ok(())
+    }
+
+    pub async fn delete_directory_acl(&self, directory_id: &str, user_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM directory_acl WHERE directory_id = $1 AND user_id = $2")
+            .bind(directory_id)
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Preferences of every user watching `directory_id`, with defaults for
+    /// users who haven't saved any. Watchers who can no longer read the
+    /// directory, since an access list was added after they started
+    /// watching, are left out.
+    pub async fn folder_watchers(&self, directory_id: &str) -> Result<Vec<NotificationPreferences>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, NotificationPreferencesRow>(
+            r#"
+            SELECT w.user_id, p.email,
+                   COALESCE(p.on_share_access, 0) AS on_share_access,
+                   COALESCE(p.on_new_files, 1) AS on_new_files,
+                   COALESCE(p.delivery, 'immediate') AS delivery,
+                   COALESCE(p.updated_at, w.created_at) AS updated_at
+            FROM watched_folders w
+            LEFT JOIN notification_preferences p ON p.user_id = w.user_id
+            WHERE w.directory_id = $1
+            "#,
+        )
+        .bind(directory_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let access = self.access(None).await?;
+        Ok(rows
+            .into_iter()
+            .filter(|row| access.allows_user(&row.user_id, Some(directory_id), Role::Read))
+            .map(NotificationPreferences::from)
+            .collect())
+    }
+
+    /// Holds a notification back for the user's next digest.
+    pub async fn queue_notification(&self, user_id: &str, message: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO pending_notifications (user_id, message, created_at) VALUES ($1, $2, $3)")
+            .bind(user_id)
+            .bind(message)
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Removes and returns every queued notification with the user it is
+    /// for, oldest first. Rows are taken in the statement that deletes them,
+    /// so one queued meanwhile waits for the next digest.
+    pub async fn take_pending_notifications(
+        &self,
+    ) -> Result<Vec<(String, PendingNotification)>, sqlx::Error> {
+        let mut rows: Vec<(i64, String, String, String)> = sqlx::query_as(
+            "DELETE FROM pending_notifications RETURNING id, user_id, message, created_at",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        rows.sort_by_key(|(id, ..)| *id);
+        Ok(rows
+            .into_iter()
+            .map(|(_, user_id, message, created_at)| {
+                (user_id, PendingNotification { message, created_at })
+            })
+            .collect())
+    }
+
+    /// Queues notifications taken for a digest that couldn't be sent again,
+    /// keeping when they were first queued.
+    pub async fn requeue_notifications(
+        &self,
+        user_id: &str,
+        notifications: &[PendingNotification],
+    ) -> Result<(), sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        for notification in notifications {
+            sqlx::query("INSERT INTO pending_notifications (user_id, message, created_at) VALUES ($1, $2, $3)")
+                .bind(user_id)
+                .bind(&notification.message)
+                .bind(&notification.created_at)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Everything stored under `user_id`: the account and its sign-on
+    /// identities, access list entries, archive jobs, notification
+    /// settings, watched folders, queued notifications, push subscriptions
+    /// and clipboard. Also returns the files the user uploaded, whose
+    /// content belongs in the export too.
+    pub async fn export_user_data(
+        &self,
+        user_id: &str,
+    ) -> Result<(UserDataExport, Vec<FileMetadata>), sqlx::Error> {
+        let user = self.get_user(user_id).await?.map(|user| {
+            let is_admin = self.tokens.is_admin(&user.username);
+            UserResponse::new(user, is_admin)
+        });
+        let oidc_identities = sqlx::query_as::<_, OidcIdentity>(
+            "SELECT issuer, subject, created_at FROM oidc_identities WHERE user_id = $1 ORDER BY created_at ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let directory_access = sqlx::query_as::<_, DirectoryAclEntry>(
+            r#"
+            SELECT a.directory_id, a.user_id, u.username, a.role, a.created_at
+            FROM directory_acl a
+            LEFT JOIN users u ON u.id = a.user_id
+            WHERE a.user_id = $1
+            ORDER BY a.created_at ASC, a.directory_id ASC
+            "#,
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let archive_jobs = sqlx::query_as::<_, ArchiveJob>(
+            "SELECT id, directory_id, user_id, status, ticket, file_name, file_size, error, created_at, finished_at, expires_at \
+             FROM archive_jobs WHERE user_id = $1 ORDER BY created_at ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let files = self.files_owned_by(user_id).await?;
+        let pending_notifications = sqlx::query_as::<_, PendingNotification>(
+            "SELECT message, created_at FROM pending_notifications WHERE user_id = $1 ORDER BY id ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await?;
+        let clipboard = sqlx::query_as::<_, ClipboardEntry>(
+            "SELECT text, created_at, expires_at FROM clipboard_entries WHERE owner = $1",
+        )
+        .bind(format!("user:{}", user_id))
+        .fetch_optional(&self.pool)
+        .await?;
+
+        let export = UserDataExport {
+            user_id: user_id.to_string(),
+            exported_at: Utc::now().to_rfc3339(),
+            user,
+            oidc_identities,
+            directory_access,
+            archive_jobs,
+            files: files.iter().cloned().map(FileResponse::from).collect(),
+            notification_preferences: self.get_notification_preferences(user_id).await?,
+            watched_folders: self.list_watched_folders(user_id).await?,
+            pending_notifications,
+            push_subscriptions: self.list_push_subscriptions(user_id).await?,
+            clipboard,
+        };
+        Ok((export, files))
+    }
+
+    /// Files uploaded by `user_id`, including any in the trash.
+    async fn files_owned_by(&self, user_id: &str) -> Result<Vec<FileMetadata>, sqlx::Error> {
+        sqlx::query_as::<_, FileMetadata>(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 \
+             FROM files WHERE owner_id = $1 ORDER BY uploaded_at ASC",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Deletes the user's account and everything stored under `user_id`:
+    /// the files they uploaded with their content, versions, shares and
+    /// receipts, their archive jobs and archives, and then the remaining
+    /// rows in one transaction.
+    pub async fn erase_user_data(
+        &self,
+        user_id: &str,
+    ) -> Result<UserErasureReport, Box<dyn std::error::Error + Send + Sync>> {
+        let mut file_ids = Vec::new();
+        for meta in self.files_owned_by(user_id).await? {
+            let file_id = meta.id.clone();
+            if self.remove_file_and_announce(meta).await? {
+                file_ids.push(file_id);
+            }
+        }
+        let jobs: Vec<String> = sqlx::query_scalar("SELECT id FROM archive_jobs WHERE user_id = $1")
+            .bind(user_id)
+            .fetch_all(&self.pool)
+            .await?;
+        let mut archive_jobs = 0;
+        for job_id in jobs {
+            if self.delete_archive_job(&job_id).await? {
+                archive_jobs += 1;
+            }
+        }
+
+        let owner = format!("user:{}", user_id);
+        let tables = [
+            ("notification_preferences", "user_id", user_id),
+            ("watched_folders", "user_id", user_id),
+            ("pending_notifications", "user_id", user_id),
+            ("push_subscriptions", "user_id", user_id),
+            ("clipboard_entries", "owner", owner.as_str()),
+            ("directory_acl", "user_id", user_id),
+            ("oidc_identities", "user_id", user_id),
+            ("users", "id", user_id),
+        ];
+
+        let mut tx = self.pool.begin().await?;
+        // Deleting the files journaled their names, so drop those entries,
+        // and any earlier ones for the same files
+        let mut metadata_journal = 0;
+        for file_id in &file_ids {
+            metadata_journal += sqlx::query("DELETE FROM metadata_journal WHERE row_id = $1")
+                .bind(file_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        let mut removed = [0u64; 8];
+        for (count, (table, column, key)) in removed.iter_mut().zip(tables) {
+            *count = sqlx::query(&format!("DELETE FROM {} WHERE {} = $1", table, column))
+                .bind(key)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        tx.commit().await?;
+
+        let [
+            notification_preferences,
+            watched_folders,
+            pending_notifications,
+            push_subscriptions,
+            clipboard_entries,
+            directory_acl,
+            oidc_identities,
+            users,
+        ] = removed;
+        Ok(UserErasureReport {
+            user_id: user_id.to_string(),
+            erased_at: Utc::now().to_rfc3339(),
+            notification_preferences,
+            watched_folders,
+            pending_notifications,
+            push_subscriptions,
+            clipboard_entries,
+            files: file_ids.len() as u64,
+            metadata_journal,
+            archive_jobs,
+            directory_acl,
+            oidc_identities,
+            users,
+        })
+    }
+
+    /// Adds a push subscription, or moves an existing one for the same
+    /// endpoint to `user_id` with fresh keys.
+    pub async fn save_push_subscription(
+        &self,
+        user_id: &str,
+        endpoint: &str,
+        p256dh: &str,
+        auth: &str,
+    ) -> Result<PushSubscription, sqlx::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO push_subscriptions (id, user_id, endpoint, p256dh, auth, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT(endpoint) DO UPDATE SET
+                user_id = excluded.user_id,
+                p256dh = excluded.p256dh,
+                auth = excluded.auth
+            "#,
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(user_id)
+        .bind(endpoint)
+        .bind(p256dh)
+        .bind(auth)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query_as::<_, PushSubscription>(
+            "SELECT id, user_id, endpoint, p256dh, auth, created_at FROM push_subscriptions WHERE endpoint = $1"
+        )
+        .bind(endpoint)
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn list_push_subscriptions(&self, user_id: &str) -> Result<Vec<PushSubscription>, sqlx::Error> {
+        sqlx::query_as::<_, PushSubscription>(
+            "SELECT id, user_id, endpoint, p256dh, auth, created_at FROM push_subscriptions WHERE user_id = $1"
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn delete_push_subscription(&self, user_id: &str, id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM push_subscriptions WHERE user_id = $1 AND id = $2")
+            .bind(user_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Directory integration methods
+    pub async fn create_directory_integration(
+        &self,
+        directory_id: &str,
+        request: CreateDirectoryIntegrationRequest,
+    ) -> Result<DirectoryIntegration, sqlx::Error> {
+        let integration = DirectoryIntegration {
+            id: Uuid::new_v4().to_string(),
+            directory_id: directory_id.to_string(),
+            kind: request.kind,
+            url: request.url,
+            room_id: request.room_id,
+            access_token: request.access_token,
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO directory_integrations (id, directory_id, kind, url, room_id, access_token, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(&integration.id)
+        .bind(&integration.directory_id)
+        .bind(&integration.kind)
+        .bind(&integration.url)
+        .bind(&integration.room_id)
+        .bind(&integration.access_token)
+        .bind(&integration.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(integration)
+    }
+
+    pub async fn list_directory_integrations(
+        &self,
+        directory_id: &str,
+    ) -> Result<Vec<DirectoryIntegration>, sqlx::Error> {
+        sqlx::query_as::<_, DirectoryIntegration>(
+            r#"
+            SELECT id, directory_id, kind, url, room_id, access_token, created_at
+            FROM directory_integrations
+            WHERE directory_id = $1
+            ORDER BY created_at ASC
+            "#,
+        )
+        .bind(directory_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_directory_integration(&self, id: &str) -> Result<Option<DirectoryIntegration>, sqlx::Error> {
+        sqlx::query_as::<_, DirectoryIntegration>(
+            r#"
+            SELECT id, directory_id, kind, url, room_id, access_token, created_at
+            FROM directory_integrations
+            WHERE id = $1
+            "#,
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn delete_directory_integration(
+        &self,
+        directory_id: &str,
+        id: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM directory_integrations WHERE directory_id = $1 AND id = $2")
+            .bind(directory_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+        sqlx::query("DELETE FROM integration_deliveries WHERE integration_id = $1")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(true)
+    }
+
+    /// Keeps what was sent to an integration for an event, and the error if
+    /// it could not be delivered.
+    pub async fn record_integration_delivery(
+        &self,
+        integration_id: &str,
+        event_id: &str,
+        payload: &str,
+        error: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        let now = Utc::now().to_rfc3339();
+        let delivered_at = error.is_none().then(|| now.clone());
+        sqlx::query(
+            r#"
+            INSERT INTO integration_deliveries (id, integration_id, event_id, payload, attempts, error, created_at, delivered_at)
+            VALUES ($1, $2, $3, $4, 1, $5, $6, $7)
+            "#,
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(integration_id)
+        .bind(event_id)
+        .bind(payload)
+        .bind(error)
+        .bind(&now)
+        .bind(delivered_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// IDs and payloads of an integration's deliveries that haven't gone
+    /// through yet, oldest first.
+    pub async fn failed_integration_deliveries(
+        &self,
+        integration_id: &str,
+    ) -> Result<Vec<(String, String)>, sqlx::Error> {
+        sqlx::query_as::<_, (String, String)>(
+            "SELECT id, payload FROM integration_deliveries \
+             WHERE integration_id = $1 AND delivered_at IS NULL ORDER BY created_at ASC",
+        )
+        .bind(integration_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Records another attempt at a delivery, and whether it went through.
+    pub async fn retried_integration_delivery(
+        &self,
+        delivery_id: &str,
+        error: Option<String>,
+    ) -> Result<(), sqlx::Error> {
+        let delivered_at = error.is_none().then(|| Utc::now().to_rfc3339());
+        sqlx::query(
+            "UPDATE integration_deliveries SET attempts = attempts + 1, error = $1, delivered_at = $2 WHERE id = $3",
+        )
+        .bind(error)
+        .bind(delivered_at)
+        .bind(delivery_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Forgets integration deliveries past the retention period, delivered
+    /// or not.
+    pub async fn prune_integration_deliveries(&self) -> Result<u64, sqlx::Error> {
+        let cutoff = Utc::now() - chrono::Duration::days(self.integration_delivery_retention_days as i64);
+        let result = sqlx::query("DELETE FROM integration_deliveries WHERE created_at < $1")
+            .bind(cutoff.to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Forgets metadata journal entries past the retention period, after
+    /// which `restore-to` can't go back to them.
+    pub async fn prune_metadata_journal(&self) -> Result<u64, sqlx::Error> {
+        let cutoff = Utc::now() - chrono::Duration::days(self.metadata_journal_retention_days as i64);
+        let result = sqlx::query("DELETE FROM metadata_journal WHERE recorded_at < $1")
+            .bind(cutoff.format(journal::RECORDED_AT_FORMAT).to_string())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Directory arrangement methods
+    pub async fn get_directory_arrangement(
+        &self,
+        dir_id: &str,
+    ) -> Result<Option<DirectoryArrangement>, sqlx::Error> {
+        let row = sqlx::query_as::<_, (Nullable<String>, String)>(
+            "SELECT sort, updated_at FROM directory_arrangements WHERE directory_id = $1",
+        )
+        .bind(dir_id)
+        .fetch_optional(&self.pool)
+        .await?;
+        let Some((sort, updated_at)) = row else {
+            return Ok(None);
+        };
+
+        let pinned = sqlx::query_scalar::<_, String>(
+            "SELECT entry_id FROM directory_pins WHERE directory_id = $1 ORDER BY position ASC",
+        )
+        .bind(dir_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(Some(DirectoryArrangement {
+            directory_id: dir_id.to_string(),
+            sort: sort.into(),
+            pinned,
+            updated_at: Some(updated_at),
+        }))
+    }
+
+    /// Replaces a directory's sort and pins. Setting neither removes the
+    /// arrangement altogether.
+    pub async fn set_directory_arrangement(
+        &self,
+        dir_id: &str,
+        sort: Option<String>,
+        pinned: Vec<String>,
+    ) -> Result<DirectoryArrangement, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM directory_pins WHERE directory_id = $1")
+            .bind(dir_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let updated_at = if sort.is_none() && pinned.is_empty() {
+            sqlx::query("DELETE FROM directory_arrangements WHERE directory_id = $1")
+                .bind(dir_id)
+                .execute(&mut *tx)
+                .await?;
+            None
+        } else {
+            let now = Utc::now().to_rfc3339();
+            sqlx::query(
+                "INSERT INTO directory_arrangements (directory_id, sort, updated_at) VALUES ($1, $2, $3) \
+                 ON CONFLICT(directory_id) DO UPDATE SET sort = excluded.sort, updated_at = excluded.updated_at",
+            )
+            .bind(dir_id)
+            .bind(&sort)
+            .bind(&now)
+            .execute(&mut *tx)
+            .await?;
+            for (position, entry_id) in pinned.iter().enumerate() {
+                sqlx::query(
+                    "INSERT INTO directory_pins (directory_id, entry_id, position) VALUES ($1, $2, $3)",
+                )
+                .bind(dir_id)
+                .bind(entry_id)
+                .bind(position as i64)
+                .execute(&mut *tx)
+                .await?;
+            }
+            Some(now)
+        };
+        tx.commit().await?;
+
+        Ok(DirectoryArrangement {
+            directory_id: dir_id.to_string(),
+            sort,
+            pinned,
+            updated_at,
+        })
+    }
+
+    // File attachment methods
+    pub async fn create_file_attachment(
+        &self,
+        file_id: &str,
+        request: CreateAttachmentRequest,
+    ) -> Result<FileAttachment, sqlx::Error> {
+        let id = Uuid::new_v4().to_string();
+        sqlx::query(
+            r#"
+            INSERT INTO file_attachments (id, file_id, attachment_id, kind, language, label, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#,
+        )
+        .bind(&id)
+        .bind(file_id)
+        .bind(&request.attachment_id)
+        .bind(&request.kind)
+        .bind(&request.language)
+        .bind(&request.label)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query_as::<_, FileAttachment>(&format!("{} WHERE a.id = $1", ATTACHMENT_SELECT))
+            .bind(&id)
+            .fetch_one(&self.pool)
+            .await
+    }
+
+    pub async fn list_file_attachments(
+        &self,
+        file_id: &str,
+    ) -> Result<Vec<FileAttachment>, sqlx::Error> {
+        sqlx::query_as::<_, FileAttachment>(&format!(
+            "{} WHERE a.file_id = $1 ORDER BY a.kind, a.created_at",
+            ATTACHMENT_SELECT
+        ))
+        .bind(file_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn delete_file_attachment(&self, file_id: &str, id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM file_attachments WHERE file_id = $1 AND id = $2")
+            .bind(file_id)
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Share methods
+    /// Creates a public link to a file under a random token, which stops
+    /// resolving once `expires_at` has passed or the file has been
+    /// downloaded `max_downloads` times through it.
+    pub async fn create_share(
+        &self,
+        file_id: &str,
+        expires_at: Option<String>,
+        max_downloads: Option<i64>,
+    ) -> Result<Share, sqlx::Error> {
+        let share = Share {
+            id: Uuid::new_v4().to_string(),
+            token: share_token(),
+            file_id: file_id.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            expires_at,
+            terms: None,
+            max_downloads,
+            download_count: 0,
+            disabled_at: None,
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO shares (id, token, file_id, created_at, expires_at, max_downloads)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(&share.id)
+        .bind(&share.token)
+        .bind(&share.file_id)
+        .bind(&share.created_at)
+        .bind(&share.expires_at)
+        .bind(share.max_downloads)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Looks up a share by its token, ignoring expired ones, those out of
+    /// downloads and those taken down by reports.
+    pub async fn get_share_by_token(&self, token: &str) -> Result<Option<Share>, sqlx::Error> {
+        sqlx::query_as::<_, Share>(
+            r#"
+            SELECT id, token, file_id, created_at, expires_at, terms, max_downloads, download_count, disabled_at
+            FROM shares
+            WHERE token = $1
+              AND (expires_at IS NULL OR expires_at > $2)
+              AND (max_downloads IS NULL OR download_count < max_downloads)
+              AND disabled_at IS NULL
+            "#,
+        )
+        .bind(token)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Counts a download through a share, unless it has none left. Returns
+    /// whether the download may go ahead; the check and the count are one
+    /// statement, so concurrent downloads can't overdraw the limit.
+    pub async fn consume_share_download(&self, share_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE shares SET download_count = download_count + 1
+            WHERE id = $1 AND (max_downloads IS NULL OR download_count < max_downloads)
+            "#,
+        )
+        .bind(share_id)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Creates a public index of a directory tree under a short random token.
+    pub async fn create_directory_share(&self, directory_id: &str) -> Result<DirectoryShare, sqlx::Error> {
+        let share = DirectoryShare {
+            id: Uuid::new_v4().to_string(),
+            token: share_token(),
+            directory_id: directory_id.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            expires_at: None,
+        };
+
+        sqlx::query(
+            "INSERT INTO directory_shares (id, token, directory_id, created_at, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&share.id)
+        .bind(&share.token)
+        .bind(&share.directory_id)
+        .bind(&share.created_at)
+        .bind(&share.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(share)
+    }
+
+    /// Looks up a directory share by its token, ignoring expired ones and
+    /// those taken down by reports.
+    pub async fn get_directory_share_by_token(&self, token: &str) -> Result<Option<DirectoryShare>, sqlx::Error> {
+        sqlx::query_as::<_, DirectoryShare>(
+            r#"
+            SELECT id, token, directory_id, created_at, expires_at
+            FROM directory_shares
+            WHERE token = $1 AND (expires_at IS NULL OR expires_at > $2) AND disabled_at IS NULL
+            "#,
+        )
+        .bind(token)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Creates a public link to `file_ids`, listed in that order, under a
+    /// short random token.
+    pub async fn create_bundle_share(
+        &self,
+        file_ids: &[String],
+        title: Option<String>,
+        expires_at: Option<String>,
+    ) -> Result<BundleShare, sqlx::Error> {
+        let share = BundleShare {
+            id: Uuid::new_v4().to_string(),
+            token: share_token(),
+            title,
+            created_at: Utc::now().to_rfc3339(),
+            expires_at,
+        };
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO bundle_shares (id, token, title, created_at, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&share.id)
+        .bind(&share.token)
+        .bind(&share.title)
+        .bind(&share.created_at)
+        .bind(&share.expires_at)
+        .execute(&mut *tx)
+        .await?;
+        for (position, file_id) in file_ids.iter().enumerate() {
+            sqlx::query("INSERT INTO bundle_share_files (bundle_id, file_id, position) VALUES ($1, $2, $3)")
+                .bind(&share.id)
+                .bind(file_id)
+                .bind(position as i64)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        Ok(share)
+    }
+
+    /// Looks up a bundle share by its token, ignoring expired ones and those
+    /// taken down by reports.
+    pub async fn get_bundle_share_by_token(&self, token: &str) -> Result<Option<BundleShare>, sqlx::Error> {
+        sqlx::query_as::<_, BundleShare>(
+            r#"
+            SELECT id, token, title, created_at, expires_at
+            FROM bundle_shares
+            WHERE token = $1 AND (expires_at IS NULL OR expires_at > $2) AND disabled_at IS NULL
+            "#,
+        )
+        .bind(token)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// The files of a bundle share in the order they were shared, leaving
+    /// out those in the trash or in quarantine.
+    pub async fn bundle_files(&self, bundle_id: &str) -> Result<Vec<FileMetadata>, sqlx::Error> {
+        sqlx::query_as::<_, FileMetadata>(&format!(
+            r#"
+            SELECT f.id, f.filename, f.original_filename, f.file_size, f.mime_type, f.storage_path, f.uploaded_at,
+                   f.description, f.parent_directory_id, f.version, f.owner_id, f.sha256
+            FROM bundle_share_files b JOIN files f ON f.id = b.file_id
+            WHERE b.bundle_id = $1 AND f.{} AND f.{}
+            ORDER BY b.position
+            "#,
+            NOT_QUARANTINED, NOT_TRASHED
+        ))
+        .bind(bundle_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Adds a recipient's report against a share to the moderation queue,
+    /// unless their network already has one open, and takes the share down
+    /// once reports from enough networks are open. Returns whether this
+    /// report took it down.
+    pub async fn report_share(
+        &self,
+        share_id: &str,
+        share_kind: &str,
+        target_id: &str,
+        report: &ReportShareRequest,
+        reporter_network: &str,
+    ) -> Result<bool, sqlx::Error> {
+        let reported_at = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        let already_reported: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM share_reports WHERE share_id = $1 AND reporter_network = $2 AND resolved_at IS NULL",
+        )
+        .bind(share_id)
+        .bind(reporter_network)
+        .fetch_optional(&mut *tx)
+        .await?;
+        if already_reported.is_some() {
+            return Ok(false);
+        }
+
+        sqlx::query(
+            r#"
+            INSERT INTO share_reports (id, share_id, share_kind, target_id, reason, details, reporter_network, reported_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            "#,
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(share_id)
+        .bind(share_kind)
+        .bind(target_id)
+        .bind(&report.reason)
+        .bind(&report.details)
+        .bind(reporter_network)
+        .bind(&reported_at)
+        .execute(&mut *tx)
+        .await?;
+
+        let networks: i64 = sqlx::query_scalar(
+            "SELECT COUNT(DISTINCT reporter_network) FROM share_reports WHERE share_id = $1 AND resolved_at IS NULL",
+        )
+        .bind(share_id)
+        .fetch_one(&mut *tx)
+        .await?;
+        let mut disabled = false;
+        if self.share_report_threshold > 0 && networks as u64 >= self.share_report_threshold {
+            let table = match share_kind {
+                "directory" => "directory_shares",
+                "bundle" => "bundle_shares",
+                _ => "shares",
+            };
+            disabled = sqlx::query(&format!(
+                "UPDATE {} SET disabled_at = $1 WHERE id = $2 AND disabled_at IS NULL",
+                table
+            ))
+            .bind(&reported_at)
+            .bind(share_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected()
+                > 0;
+        }
+        tx.commit().await?;
+        Ok(disabled)
+    }
+
+    /// Open reports against shares that still exist, oldest first.
+    pub async fn list_share_reports(&self) -> Result<Vec<ShareReport>, sqlx::Error> {
+        sqlx::query_as::<_, ShareReport>(
+            r#"
+            SELECT id, share_id, share_kind, target_id, reason, details, reported_at, resolved_at
+            FROM share_reports
+            WHERE resolved_at IS NULL
+              AND (share_id IN (SELECT id FROM shares)
+                OR share_id IN (SELECT id FROM directory_shares)
+                OR share_id IN (SELECT id FROM bundle_shares))
+            ORDER BY reported_at ASC, id
+            "#,
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_share_report(&self, report_id: &str) -> Result<Option<ShareReport>, sqlx::Error> {
+        sqlx::query_as::<_, ShareReport>(
+            r#"
+            SELECT id, share_id, share_kind, target_id, reason, details, reported_at, resolved_at
+            FROM share_reports
+            WHERE id = $1
+            "#,
+        )
+        .bind(report_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Closes every open report against a share and, unless it is being
+    /// revoked, puts it back up if reports took it down. Returns how many
+    /// reports were closed.
+    pub async fn resolve_share_reports(&self, share_id: &str, revoke: bool) -> Result<u64, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let resolved = sqlx::query("UPDATE share_reports SET resolved_at = $1 WHERE share_id = $2 AND resolved_at IS NULL")
+            .bind(Utc::now().to_rfc3339())
+            .bind(share_id)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        for table in ["shares", "directory_shares", "bundle_shares"] {
+            let statement = if revoke {
+                format!("DELETE FROM {} WHERE id = $1", table)
+            } else {
+                format!("UPDATE {} SET disabled_at = NULL WHERE id = $1", table)
+            };
+            sqlx::query(&statement).bind(share_id).execute(&mut *tx).await?;
+        }
+        if revoke {
+            sqlx::query("DELETE FROM bundle_share_files WHERE bundle_id = $1")
+                .bind(share_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(resolved)
+    }
+
+    /// Creates an upload-only link into a directory under a random token.
+    pub async fn create_drop_link(
+        &self,
+        directory_id: &str,
+        expires_at: Option<String>,
+    ) -> Result<DropLink, sqlx::Error> {
+        let link = DropLink {
+            id: Uuid::new_v4().to_string(),
+            token: share_token(),
+            directory_id: directory_id.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            expires_at,
+        };
+
+        sqlx::query(
+            "INSERT INTO drop_links (id, token, directory_id, created_at, expires_at) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&link.id)
+        .bind(&link.token)
+        .bind(&link.directory_id)
+        .bind(&link.created_at)
+        .bind(&link.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    /// Looks up a drop link by its token, ignoring expired ones.
+    pub async fn get_drop_link_by_token(&self, token: &str) -> Result<Option<DropLink>, sqlx::Error> {
+        sqlx::query_as::<_, DropLink>(
+            r#"
+            SELECT id, token, directory_id, created_at, expires_at
+            FROM drop_links
+            WHERE token = $1 AND (expires_at IS NULL OR expires_at > $2)
+            "#,
+        )
+        .bind(token)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Lists a directory's drop links, expired ones included, newest first.
+    pub async fn list_drop_links(&self, directory_id: &str) -> Result<Vec<DropLink>, sqlx::Error> {
+        sqlx::query_as::<_, DropLink>(
+            r#"
+            SELECT id, token, directory_id, created_at, expires_at
+            FROM drop_links
+            WHERE directory_id = $1
+            ORDER BY created_at DESC, id
+            "#,
+        )
+        .bind(directory_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Creates an account, or returns `None` if the username is taken.
+    pub async fn create_user(
+        &self,
+        username: &str,
+        password_hash: String,
+    ) -> Result<Option<User>, sqlx::Error> {
+        let user = User {
+            id: Uuid::new_v4().to_string(),
+            username: username.to_lowercase(),
+            password_hash,
+            created_at: Utc::now().to_rfc3339(),
+        };
+        let result = sqlx::query(
+            "INSERT INTO users (id, username, password_hash, created_at) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (username) DO NOTHING",
+        )
+        .bind(&user.id)
+        .bind(&user.username)
+        .bind(&user.password_hash)
+        .bind(&user.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        info!("User created: {} ({})", user.username, user.id);
+        Ok(Some(user))
+    }
+
+    /// Gives a new user a directory of their own at the root, named after
+    /// them and restricted to them, with `USER_DIRECTORY_TEMPLATE` laid out
+    /// inside. Does nothing without a template, and never takes over an
+    /// existing directory of the same name.
+    pub async fn create_home_directory(
+        &self,
+        user: &User,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.user_template.is_empty() {
+            return Ok(());
+        }
+        let name = self.checked_name(&user.username)?;
+        if self.find_child_directory(None, &name).await?.is_some() {
+            warn!(
+                "Not creating a home directory for {}: a directory named {} already exists",
+                user.username, name
+            );
+            return Ok(());
+        }
+        let home = self.create_directory(&name, None).await?;
+        self.set_directory_acl(&home.id, &user.id, Role::Owner).await?;
+        self.user_template.apply(self, Some(home.id)).await?;
+        Ok(())
+    }
+
+    /// Whether nothing has been stored yet: no files, directories or links.
+    pub async fn is_empty(&self) -> Result<bool, sqlx::Error> {
+        let used: Option<String> = sqlx::query_scalar(
+            "SELECT id FROM files UNION ALL SELECT id FROM directories UNION ALL SELECT id FROM links LIMIT 1",
+        )
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(used.is_none())
+    }
+
+    pub async fn get_user_by_username(&self, username: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, password_hash, created_at FROM users WHERE username = $1",
+        )
+        .bind(username.to_lowercase())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn get_user(&self, id: &str) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT id, username, password_hash, created_at FROM users WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// The local user a single sign-on identity maps to, creating one named
+    /// after `username_hint` on its first login. SSO users get no password,
+    /// so they can only log in through their provider.
+    pub async fn user_for_oidc_identity(
+        &self,
+        issuer: &str,
+        subject: &str,
+        username_hint: Option<&str>,
+    ) -> Result<User, sqlx::Error> {
+        if let Some(user) = self.get_oidc_user(issuer, subject).await? {
+            return Ok(user);
+        }
+
+        let base = oidc::username_base(username_hint);
+        let mut user = None;
+        for n in 1.. {
+            let username = if n == 1 {
+                base.clone()
+            } else {
+                format!("{}-{}", base, n)
+            };
+            user = self.create_user(&username, String::new()).await?;
+            if user.is_some() {
+                break;
+            }
+        }
+        let user = user.expect("loop only ends with a user");
+
+        let linked = sqlx::query(
+            "INSERT INTO oidc_identities (issuer, subject, user_id, created_at) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT (issuer, subject) DO NOTHING",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .bind(&user.id)
+        .bind(Utc::now().to_rfc3339())
+        .execute(&self.pool)
+        .await?;
+        if linked.rows_affected() > 0 {
+            if let Err(e) = self.create_home_directory(&user).await {
+                warn!("Failed to create home directory for {}: {}", user.username, e);
+            }
+            return Ok(user);
+        }
+
+        // A concurrent first login linked the identity first.
+        sqlx::query("DELETE FROM users WHERE id = $1")
+            .bind(&user.id)
+            .execute(&self.pool)
+            .await?;
+        self.get_oidc_user(issuer, subject)
+            .await?
+            .ok_or(sqlx::Error::RowNotFound)
+    }
+
+    async fn get_oidc_user(
+        &self,
+        issuer: &str,
+        subject: &str,
+    ) -> Result<Option<User>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT u.id, u.username, u.password_hash, u.created_at FROM users u \
+             JOIN oidc_identities i ON i.user_id = u.id WHERE i.issuer = $1 AND i.subject = $2",
+        )
+        .bind(issuer)
+        .bind(subject)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Creates a guest upload link into a directory under a short random
+    /// token.
+    pub async fn create_upload_link(
+        &self,
+        directory_id: &str,
+        deadline: Option<String>,
+    ) -> Result<UploadLink, sqlx::Error> {
+        let link = UploadLink {
+            id: Uuid::new_v4().to_string(),
+            token: share_token(),
+            directory_id: directory_id.to_string(),
+            created_at: Utc::now().to_rfc3339(),
+            deadline,
+        };
+
+        sqlx::query(
+            "INSERT INTO upload_links (id, token, directory_id, created_at, deadline) VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(&link.id)
+        .bind(&link.token)
+        .bind(&link.directory_id)
+        .bind(&link.created_at)
+        .bind(&link.deadline)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(link)
+    }
+
+    /// Looks up an upload link by its token, including ones past their
+    /// deadline so guests can be told it has passed.
+    pub async fn get_upload_link_by_token(&self, token: &str) -> Result<Option<UploadLink>, sqlx::Error> {
+        sqlx::query_as::<_, UploadLink>(
+            "SELECT id, token, directory_id, created_at, deadline FROM upload_links WHERE token = $1",
+        )
+        .bind(token)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn get_upload_link(&self, link_id: &str) -> Result<Option<UploadLink>, sqlx::Error> {
+        sqlx::query_as::<_, UploadLink>(
+            "SELECT id, token, directory_id, created_at, deadline FROM upload_links WHERE id = $1",
+        )
+        .bind(link_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Records who sent a file through an upload link.
+    pub async fn record_submission(
+        &self,
+        link: &UploadLink,
+        file: &FileMetadata,
+        sender_name: &str,
+        sender_email: Option<&str>,
+    ) -> Result<UploadSubmission, sqlx::Error> {
+        let submission = UploadSubmission {
+            id: Uuid::new_v4().to_string(),
+            file_id: file.id.clone(),
+            original_filename: file.original_filename.clone(),
+            file_size: file.file_size,
+            sender_name: sender_name.to_string(),
+            sender_email: sender_email.map(str::to_string),
+            submitted_at: Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO upload_submissions (id, upload_link_id, file_id, sender_name, sender_email, submitted_at)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            "#,
+        )
+        .bind(&submission.id)
+        .bind(&link.id)
+        .bind(&submission.file_id)
+        .bind(&submission.sender_name)
+        .bind(&submission.sender_email)
+        .bind(&submission.submitted_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(submission)
+    }
+
+    /// The files sent through an upload link that still exist, oldest first.
+    pub async fn list_submissions(&self, link_id: &str) -> Result<Vec<UploadSubmission>, sqlx::Error> {
+        sqlx::query_as::<_, UploadSubmission>(
+            r#"
+            SELECT s.id, s.file_id, f.original_filename, f.file_size, s.sender_name, s.sender_email, s.submitted_at
+            FROM upload_submissions s JOIN files f ON f.id = s.file_id
+            WHERE s.upload_link_id = $1 AND f.trashed = 0
+            ORDER BY s.submitted_at ASC
+            "#,
+        )
+        .bind(link_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// The directories from `root_id` down to `dir_id`, or `None` if
+    /// `dir_id` isn't `root_id` or beneath it.
+    pub async fn directory_chain(&self, root_id: &str, dir_id: &str) -> Result<Option<Vec<Directory>>, sqlx::Error> {
+        let chain = sqlx::query_as::<_, Directory>(
+            r#"
+            WITH RECURSIVE chain(id, parent_id, depth) AS (
+                SELECT id, parent_id, 0 FROM directories WHERE id = $1 AND trashed = 0
+                UNION ALL
+                SELECT d.id, d.parent_id, chain.depth + 1
+                FROM directories d JOIN chain ON d.id = chain.parent_id
+                WHERE chain.id != $2
+            )
+            SELECT d.id, d.name, d.parent_id, d.created_at, d.updated_at, d.color, d.icon, d.version
+            FROM chain JOIN directories d ON d.id = chain.id
+            ORDER BY chain.depth DESC
+            "#,
+        )
+        .bind(dir_id)
+        .bind(root_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let inside = chain.first().is_some_and(|d| d.id == root_id);
+        Ok(inside.then_some(chain))
+    }
+
+    pub async fn get_share(&self, share_id: &str) -> Result<Option<Share>, sqlx::Error> {
+        sqlx::query_as::<_, Share>(
+            r#"
+            SELECT id, token, file_id, created_at, expires_at, terms, max_downloads, download_count, disabled_at
+            FROM shares
+            WHERE id = $1
+            "#,
+        )
+        .bind(share_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Lists a file's shares, expired ones included, newest first.
+    pub async fn list_shares(&self, file_id: &str) -> Result<Vec<Share>, sqlx::Error> {
+        sqlx::query_as::<_, Share>(
+            r#"
+            SELECT id, token, file_id, created_at, expires_at, terms, max_downloads, download_count, disabled_at
+            FROM shares
+            WHERE file_id = $1
+            ORDER BY created_at DESC, id
+            "#,
+        )
+        .bind(file_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Revokes a file, directory or bundle share or a drop link, so its
+    /// token stops resolving. Returns whether there was such a share.
+    pub async fn revoke_share(&self, share_id: &str) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let mut revoked = 0;
+        for table in ["shares", "directory_shares", "bundle_shares", "drop_links"] {
+            revoked += sqlx::query(&format!("DELETE FROM {} WHERE id = $1", table))
+                .bind(share_id)
+                .execute(&mut *tx)
+                .await?
+                .rows_affected();
+        }
+        sqlx::query("DELETE FROM bundle_share_files WHERE bundle_id = $1")
+            .bind(share_id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(revoked > 0)
+    }
+
+    /// The directories a file, directory or bundle share or a drop link
+    /// belongs to, and whether it covers each directory rather than files in
+    /// it. A bundle belongs to the directory of each of its files. Empty if
+    /// there is no such share.
+    pub async fn share_scope(&self, share_id: &str) -> Result<Vec<(Option<String>, bool)>, sqlx::Error> {
+        let rows: Vec<(Nullable<String>, i64)> = sqlx::query_as(
+            r#"
+            SELECT f.parent_directory_id, CAST(0 AS BIGINT)
+            FROM shares s JOIN files f ON f.id = s.file_id WHERE s.id = $1
+            UNION ALL
+            SELECT directory_id, CAST(1 AS BIGINT) FROM directory_shares WHERE id = $2
+            UNION ALL
+            SELECT directory_id, CAST(1 AS BIGINT) FROM drop_links WHERE id = $3
+            UNION ALL
+            SELECT DISTINCT f.parent_directory_id, CAST(0 AS BIGINT)
+            FROM bundle_share_files b JOIN files f ON f.id = b.file_id WHERE b.bundle_id = $4
+            "#,
+        )
+        .bind(share_id)
+        .bind(share_id)
+        .bind(share_id)
+        .bind(share_id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .map(|(Nullable(directory_id), whole)| (directory_id, whole != 0))
+            .collect())
+    }
+
+    /// Sets or clears the terms a share's recipients have to accept.
+    /// Returns `None` if there is no such share.
+    pub async fn set_share_terms(&self, share_id: &str, terms: Option<&str>) -> Result<Option<Share>, sqlx::Error> {
+        let result = sqlx::query("UPDATE shares SET terms = $1 WHERE id = $2")
+            .bind(terms)
+            .bind(share_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+        self.get_share(share_id).await
+    }
+
+    // Audio metadata methods
+    pub async fn save_audio_analysis(&self, analysis: &AudioAnalysis) -> Result<(), sqlx::Error> {
+        let m = &analysis.metadata;
+        sqlx::query(
+            r#"
+            INSERT INTO audio_metadata
+                (file_id, duration_secs, bitrate, sample_rate, channels, codec, title, artist,
+                 album, genre, year, track_number, peaks, analyzed_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14)
+            ON CONFLICT(file_id) DO UPDATE SET
+                duration_secs = excluded.duration_secs,
+                bitrate = excluded.bitrate,
+                sample_rate = excluded.sample_rate,
+                channels = excluded.channels,
+                codec = excluded.codec,
+                title = excluded.title,
+                artist = excluded.artist,
+                album = excluded.album,
+                genre = excluded.genre,
+                year = excluded.year,
+                track_number = excluded.track_number,
+                peaks = excluded.peaks,
+                analyzed_at = excluded.analyzed_at
+            "#,
+        )
+        .bind(&m.file_id)
+        .bind(m.duration_secs)
+        .bind(m.bitrate)
+        .bind(m.sample_rate)
+        .bind(m.channels)
+        .bind(&m.codec)
+        .bind(&m.title)
+        .bind(&m.artist)
+        .bind(&m.album)
+        .bind(&m.genre)
+        .bind(&m.year)
+        .bind(&m.track_number)
+        .bind(&analysis.peaks)
+        .bind(&m.analyzed_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn get_audio_metadata(
+        &self,
+        file_id: &str,
+    ) -> Result<Option<AudioMetadata>, sqlx::Error> {
+        sqlx::query_as::<_, AudioMetadata>(
+            r#"
+            SELECT file_id, duration_secs, bitrate, sample_rate, channels, codec, title, artist,
+                   album, genre, year, track_number, analyzed_at
+            FROM audio_metadata
+            WHERE file_id = $1
+            "#,
+        )
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Duration and waveform peaks of an analyzed audio file.
+    pub async fn get_audio_peaks(
+        &self,
+        file_id: &str,
+    ) -> Result<Option<(f64, Vec<u8>)>, sqlx::Error> {
+        sqlx::query_as::<_, (f64, Vec<u8>)>(
+            "SELECT duration_secs, peaks FROM audio_metadata WHERE file_id = $1",
+        )
+        .bind(file_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    // Clipboard methods
+    /// Replaces the owner's clipboard with `text`, expiring after `ttl_secs`.
+    pub async fn set_clipboard(
+        &self,
+        owner: &str,
+        text: String,
+        ttl_secs: u64,
+    ) -> Result<ClipboardEntry, sqlx::Error> {
+        let now = Utc::now();
+        let entry = ClipboardEntry {
+            text,
+            created_at: now.to_rfc3339(),
+            expires_at: (now + chrono::Duration::seconds(ttl_secs as i64)).to_rfc3339(),
+        };
+
+        sqlx::query(
+            "INSERT INTO clipboard_entries (owner, text, created_at, expires_at) VALUES ($1, $2, $3, $4) \
+             ON CONFLICT(owner) DO UPDATE SET text = excluded.text, created_at = excluded.created_at, expires_at = excluded.expires_at",
+        )
+        .bind(owner)
+        .bind(&entry.text)
+        .bind(&entry.created_at)
+        .bind(&entry.expires_at)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(entry)
+    }
+
+    /// The owner's clipboard, unless it is empty or has expired.
+    pub async fn get_clipboard(&self, owner: &str) -> Result<Option<ClipboardEntry>, sqlx::Error> {
+        sqlx::query_as::<_, ClipboardEntry>(
+            "SELECT text, created_at, expires_at FROM clipboard_entries WHERE owner = $1 AND expires_at > $2",
+        )
+        .bind(owner)
+        .bind(Utc::now().to_rfc3339())
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn clear_clipboard(&self, owner: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM clipboard_entries WHERE owner = $1")
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn prune_clipboard(&self) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM clipboard_entries WHERE expires_at <= $1")
+            .bind(Utc::now().to_rfc3339())
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    // Retention policy methods
+    pub async fn create_retention_policy(
+        &self,
+        directory_id: Option<String>,
+        pattern: &str,
+        keep_last: i64,
+    ) -> Result<RetentionPolicy, sqlx::Error> {
+        let policy = RetentionPolicy {
+            id: Uuid::new_v4().to_string(),
+            directory_id,
+            pattern: pattern.to_string(),
+            keep_last,
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            "INSERT INTO retention_policies (id, directory_id, pattern, keep_last, created_at) VALUES ($1, $2, $3, $4, $5)"
+        )
+        .bind(&policy.id)
+        .bind(&policy.directory_id)
+        .bind(&policy.pattern)
+        .bind(policy.keep_last)
+        .bind(&policy.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        info!("Retention policy created: {} ({})", policy.pattern, policy.id);
+        Ok(policy)
+    }
+
+    pub async fn list_retention_policies(&self) -> Result<Vec<RetentionPolicy>, sqlx::Error> {
+        sqlx::query_as::<_, RetentionPolicy>(
+            "SELECT id, directory_id, pattern, keep_last, created_at FROM retention_policies ORDER BY created_at ASC"
+        )
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn delete_retention_policy(&self, policy_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM retention_policies WHERE id = $1")
+            .bind(policy_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    // Thumbnail policy methods
+    pub async fn create_thumbnail_policy(
+        &self,
+        directory_id: Option<String>,
+        recursive: bool,
+        sizes: &[ThumbnailSize],
+    ) -> Result<ThumbnailPolicy, sqlx::Error> {
+        let policy = ThumbnailPolicy {
+            id: Uuid::new_v4().to_string(),
+            directory_id,
+            recursive,
+            sizes: sizes.iter().map(|size| size.name.to_string()).collect(),
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            "INSERT INTO thumbnail_policies (id, directory_id, recursive, sizes, created_at) VALUES ($1, $2, $3, $4, $5)"
+        )
+        .bind(&policy.id)
+        .bind(&policy.directory_id)
+        .bind(i64::from(policy.recursive))
+        .bind(policy.sizes.join(","))
+        .bind(&policy.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        info!("Thumbnail policy created: {}", policy.id);
+        Ok(policy)
+    }
+
+    pub async fn list_thumbnail_policies(&self) -> Result<Vec<ThumbnailPolicy>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, ThumbnailPolicyRow>(
+            "SELECT id, directory_id, recursive, sizes, created_at FROM thumbnail_policies ORDER BY created_at ASC"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(ThumbnailPolicy::from).collect())
+    }
+
+    pub async fn delete_thumbnail_policy(&self, policy_id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM thumbnail_policies WHERE id = $1")
+            .bind(policy_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Thumbnail sizes to render for images uploaded to `directory_id`: those
+    /// of its own policies and of recursive policies on its ancestors.
+    pub async fn thumbnail_sizes_for(
+        &self,
+        directory_id: Option<&str>,
+    ) -> Result<Vec<ThumbnailSize>, sqlx::Error> {
+        let policies = self.list_thumbnail_policies().await?;
+        if policies.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // The directory itself, then each ancestor up to the root.
+        let mut path = vec![directory_id.map(str::to_string)];
+        let mut next = directory_id.map(str::to_string);
+        while let Some(id) = next {
+            next = self.get_directory(&id).await?.and_then(|dir| dir.parent_id);
+            if path.contains(&next) {
+                break;
+            }
+            path.push(next.clone());
+        }
+
+        let mut sizes: Vec<ThumbnailSize> = Vec::new();
+        for policy in &policies {
+            let applies = match path.iter().position(|id| *id == policy.directory_id) {
+                Some(0) => true,
+                Some(_) => policy.recursive,
+                None => false,
+            };
+            if applies {
+                for size in policy.sizes.iter().filter_map(|name| ThumbnailSize::from_name(name)) {
+                    if !sizes.contains(&size) {
+                        sizes.push(size);
+                    }
+                }
+            }
+        }
+        sizes.sort_by_key(|size| size.pixels);
+        Ok(sizes)
+    }
+
+    // Link management methods
+    pub async fn create_link(
+        &self,
+        name: &str,
+        target_url: Option<String>,
+        target_file_id: Option<String>,
+        parent_directory_id: Option<String>,
+        description: Option<String>,
+    ) -> Result<Link, Box<dyn std::error::Error + Send + Sync>> {
+        if parent_directory_id.as_deref().is_some_and(mirror::is_mirror_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let link = Link {
+            id: Uuid::new_v4().to_string(),
+            name: name.to_string(),
+            target_url,
+            target_file_id,
+            parent_directory_id,
+            description,
+            created_at: Utc::now().to_rfc3339(),
+        };
+
+        sqlx::query(
+            r#"
+            INSERT INTO links (id, name, target_url, target_file_id, parent_directory_id, description, created_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            "#
+        )
+        .bind(&link.id)
+        .bind(&link.name)
+        .bind(&link.target_url)
+        .bind(&link.target_file_id)
+        .bind(&link.parent_directory_id)
+        .bind(&link.description)
+        .bind(&link.created_at)
+        .execute(&self.pool)
+        .await?;
+
+        self.events.publish(ChangeEvent::LinkCreated {
+            link_id: link.id.clone(),
+            name: link.name.clone(),
+            parent_directory_id: link.parent_directory_id.clone(),
+        });
+        info!("Link created: {} ({})", link.name, link.id);
+        Ok(link)
+    }
+
+    pub async fn get_link(&self, link_id: &str) -> Result<Option<Link>, sqlx::Error> {
+        sqlx::query_as::<_, Link>(
+            "SELECT id, name, target_url, target_file_id, parent_directory_id, description, created_at FROM links WHERE id = $1"
+        )
+        .bind(link_id)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    pub async fn list_links(&self, parent_directory_id: Option<String>) -> Result<Vec<Link>, sqlx::Error> {
+        sqlx::query_as::<_, Link>(
+            "SELECT id, name, target_url, target_file_id, parent_directory_id, description, created_at \
+             FROM links WHERE parent_directory_id IS NOT DISTINCT FROM CAST($1 AS TEXT) ORDER BY name ASC"
+        )
+        .bind(parent_directory_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn delete_link(&self, link_id: &str) -> Result<bool, sqlx::Error> {
+        let Some(link) = self.get_link(link_id).await? else {
+            return Ok(false);
+        };
+
+        let result = sqlx::query("DELETE FROM links WHERE id = $1")
+            .bind(link_id)
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query("DELETE FROM directory_pins WHERE entry_id = $1")
+            .bind(link_id)
+            .execute(&self.pool)
+            .await?;
+
+        let deleted = result.rows_affected() > 0;
+        if deleted {
+            self.events.publish(ChangeEvent::LinkDeleted {
+                link_id: link.id,
+                parent_directory_id: link.parent_directory_id,
+            });
+        }
+        Ok(deleted)
+    }
+
+    pub async fn list_recent_files(&self, limit: i64) -> Result<Vec<FileMetadata>, sqlx::Error> {
+        sqlx::query_as::<_, FileMetadata>(&format!(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 \
+             FROM files WHERE {} AND {} ORDER BY uploaded_at DESC LIMIT $1",
+            NOT_QUARANTINED, NOT_TRASHED
+        ))
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Files in any directory whose name or description contains `query`,
+    /// ignoring case, by name. Uses the `files_search` trigram index on
+    /// SQLite and the trigram indexes on PostgreSQL; trigrams need at
+    /// least three characters, so shorter queries scan the files table.
+    pub async fn search_files(&self, query: &str, limit: i64) -> Result<Vec<FileMetadata>, sqlx::Error> {
+        let mut search = NumberedQuery::new(
+            "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 FROM files WHERE ",
+        );
+        search.push(NOT_QUARANTINED).push(" AND ").push(NOT_TRASHED).push(" AND ");
+        if self.database_backend == Backend::Sqlite && query.chars().count() >= 3 {
+            search
+                .push(
+                    "id IN (SELECT e.file_id FROM files_search \
+                     JOIN files_search_entries e ON e.id = files_search.rowid \
+                     WHERE files_search MATCH ",
+                )
+                .push_bind(format!(
+                    "{{original_filename description}} : \"{}\"",
+                    query.replace('"', "\"\"")
+                ))
+                .push(")");
+        } else {
+            let pattern = contains_pattern(query);
+            search
+                .push("(LOWER(original_filename) LIKE ")
+                .push_bind(pattern.clone())
+                .push(" ESCAPE '\\' OR LOWER(description) LIKE ")
+                .push_bind(pattern)
+                .push(" ESCAPE '\\')");
+        }
+        search
+            .push(" ORDER BY original_filename, id LIMIT ")
+            .push_bind(limit);
+
+        let (sql, args) = search.into_parts();
+        sqlx::query_as_with::<_, FileMetadata, _>(&sql, args)
+            .fetch_all(&self.pool)
+            .await
+    }
+
+    /// The path from the root of each of `directory_ids`, such as
+    /// `/Photos/2024`, by directory ID. Directories that don't exist are
+    /// left out.
+    pub async fn directory_paths(&self, directory_ids: &[String]) -> Result<HashMap<String, String>, sqlx::Error> {
+        if directory_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let mut query = NumberedQuery::new(
+            "WITH RECURSIVE up(start_id, parent_id, name, depth) AS (\
+             SELECT id, parent_id, name, 0 FROM directories WHERE id IN (",
+        );
+        for (i, id) in directory_ids.iter().enumerate() {
+            if i > 0 {
+                query.push(", ");
+            }
+            query.push_bind(id.clone());
+        }
+        query.push(
+            ") UNION ALL \
+             SELECT up.start_id, d.parent_id, d.name, up.depth + 1 \
+             FROM directories d JOIN up ON d.id = up.parent_id) \
+             SELECT start_id, name FROM up ORDER BY start_id, depth DESC",
+        );
+
+        let (sql, args) = query.into_parts();
+        let rows: Vec<(String, String)> = sqlx::query_as_with(&sql, args)
+            .fetch_all(&self.pool)
+            .await?;
+        let mut paths: HashMap<String, String> = HashMap::new();
+        for (start_id, name) in rows {
+            let path = paths.entry(start_id).or_default();
+            path.push('/');
+            path.push_str(&name);
+        }
+        Ok(paths)
+    }
+
+    pub async fn bulk_delete(
+        &self,
+        file_ids: Vec<String>,
+        directory_ids: Vec<String>,
+    ) -> Result<(usize, usize), Box<dyn std::error::Error + Send + Sync>> {
+        let mut deleted_files = 0;
+        let mut deleted_directories = 0;
+
+        // Delete files
+        for file_id in file_ids {
+            if self.delete_file(&file_id).await? {
+                deleted_files += 1;
+            }
+        }
+
+        // Delete directories
+        for dir_id in directory_ids {
+            if self.delete_directory(&dir_id).await? {
+                deleted_directories += 1;
+            }
+        }
+
+        Ok((deleted_files, deleted_directories))
+    }
+
+    /// Moves files and directories into `target` in one transaction. If any
+    /// item can't be moved, nothing is, and the results say why.
+    pub async fn bulk_move(
+        &self,
+        file_ids: Vec<String>,
+        directory_ids: Vec<String>,
+        target: Option<String>,
+    ) -> Result<Vec<BulkItemResult>, sqlx::Error> {
+        let (mut results, files, directories) = self
+            .check_bulk_transfer(file_ids, directory_ids, target.as_deref(), false)
+            .await?;
+        if results.iter().any(|r| r.error.is_some()) {
+            return Ok(reject_bulk_items(results, "moved"));
+        }
+
+        let now = Utc::now().to_rfc3339();
+        let mut tx = self.pool.begin().await?;
+        // Cycles and names were checked above already, but are again under
+        // the lock, in case a concurrent change put the target beneath one
+        // of the directories or gave a sibling there one of the names.
+        self.lock_directory_tree(&mut tx).await?;
+        if let Some(target_id) = &target {
+            for directory in &directories {
+                if is_ancestor_within(&mut tx, &directory.id, target_id).await? {
+                    if let Some(result) = results.iter_mut().find(|r| r.id == directory.id) {
+                        result.error = Some(
+                            "Cannot move a directory into itself or one of its subdirectories"
+                                .to_string(),
+                        );
+                    }
+                    return Ok(reject_bulk_items(results, "moved"));
+                }
+            }
+        }
+        for (id, name, is_directory) in files
+            .iter()
+            .map(|f| (&f.id, &f.original_filename, false))
+            .chain(directories.iter().map(|d| (&d.id, &d.name, true)))
+        {
+            let taken = self
+                .sibling_named(&mut tx, target.as_deref(), name, Some(id), is_directory)
+                .await?;
+            if let Some(taken) = taken {
+                if let Some(result) = results.iter_mut().find(|r| &r.id == id) {
+                    result.error = Some(taken.to_string());
+                }
+                return Ok(reject_bulk_items(results, "moved"));
+            }
+        }
+        for file in &files {
+            sqlx::query(
+                "UPDATE files SET parent_directory_id = $1, version = version + 1 WHERE id = $2",
+            )
+            .bind(&target)
+            .bind(&file.id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for directory in &directories {
+            sqlx::query(
+                "UPDATE directories SET parent_id = $1, updated_at = $2, version = version + 1 \
+                 WHERE id = $3",
+            )
+            .bind(&target)
+            .bind(&now)
+            .bind(&directory.id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for id in files
+            .iter()
+            .map(|f| &f.id)
+            .chain(directories.iter().map(|d| &d.id))
+        {
+            sqlx::query("DELETE FROM directory_pins WHERE entry_id = $1 AND directory_id IS DISTINCT FROM CAST($2 AS TEXT)")
+                .bind(id)
+                .bind(&target)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        for file in files {
+            self.events.publish(ChangeEvent::FileMoved {
+                file_id: file.id,
+                parent_directory_id: target.clone(),
+            });
+        }
+        for directory in directories {
+            self.events.publish(ChangeEvent::DirectoryMoved {
+                directory_id: directory.id,
+                parent_id: target.clone(),
+            });
+        }
+        for result in &mut results {
+            result.success = true;
+        }
+        info!("Bulk moved {} items -> {:?}", results.len(), target);
+        Ok(results)
+    }
+
+    /// Applies the tag, description and expiry changes of `update` to each
+    /// of its files in one transaction. If any file can't be updated, none
+    /// is, and the results say why.
+    pub async fn bulk_update_files(
+        &self,
+        update: &BulkUpdateRequest,
+    ) -> Result<Vec<BulkItemResult>, sqlx::Error> {
+        let mut seen = HashSet::new();
+        let mut results = Vec::new();
+        for id in &update.file_ids {
+            if !seen.insert(id) {
+                continue;
+            }
+            let found = self.get_file_metadata(id).await?.is_some();
+            results.push(BulkItemResult {
+                id: id.clone(),
+                kind: "file".to_string(),
+                success: false,
+                new_id: None,
+                error: (!found).then(|| "File not found".to_string()),
+            });
+        }
+        if results.iter().any(|r| r.error.is_some()) {
+            return Ok(reject_bulk_items(results, "updated"));
+        }
+
+        let mut tx = self.pool.begin().await?;
+        for result in &results {
+            for tag in update.add_tags.iter().flatten() {
+                sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES ($1, $2) ON CONFLICT DO NOTHING")
+                    .bind(&result.id)
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            for tag in update.remove_tags.iter().flatten() {
+                sqlx::query("DELETE FROM file_tags WHERE file_id = $1 AND tag = $2")
+                    .bind(&result.id)
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            if let Some(description) = &update.description {
+                sqlx::query("UPDATE files SET description = $1 WHERE id = $2")
+                    .bind(description)
+                    .bind(&result.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            sqlx::query("UPDATE files SET version = version + 1 WHERE id = $1")
+                .bind(&result.id)
+                .execute(&mut *tx)
+                .await?;
+            match &update.expires_at {
+                Some(Some(expires_at)) => {
+                    sqlx::query(
+                        "INSERT INTO file_expirations (file_id, expires_at) VALUES ($1, $2) \
+                         ON CONFLICT(file_id) DO UPDATE SET expires_at = excluded.expires_at",
+                    )
+                    .bind(&result.id)
+                    .bind(expires_at)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                Some(None) => {
+                    sqlx::query("DELETE FROM file_expirations WHERE file_id = $1")
+                        .bind(&result.id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+                None => {}
+            }
+        }
+        tx.commit().await?;
+
+        for result in &mut results {
+            result.success = true;
+        }
+        info!("Bulk updated {} files", results.len());
+        Ok(results)
+    }
+
+    /// Applies the description, tag and expiry changes of `update` to a file
+    /// if it is still at `version`; returns `None` when it is not, or no
+    /// longer exists. Tags replace the ones the file has.
+    pub async fn update_file_metadata(
+        &self,
+        file_id: &str,
+        update: &UpdateFileRequest,
+        version: i64,
+    ) -> Result<Option<FileMetadata>, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        let result = sqlx::query("UPDATE files SET version = version + 1 WHERE id = $1 AND version = $2")
+            .bind(file_id)
+            .bind(version)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        if let Some(description) = &update.description {
+            sqlx::query("UPDATE files SET description = $1 WHERE id = $2")
+                .bind(description)
+                .bind(file_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        if let Some(tags) = &update.tags {
+            sqlx::query("DELETE FROM file_tags WHERE file_id = $1")
+                .bind(file_id)
+                .execute(&mut *tx)
+                .await?;
+            for tag in tags {
+                sqlx::query("INSERT INTO file_tags (file_id, tag) VALUES ($1, $2)")
+                    .bind(file_id)
+                    .bind(tag)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        match &update.expires_at {
+            Some(Some(expires_at)) => {
+                sqlx::query(
+                    "INSERT INTO file_expirations (file_id, expires_at) VALUES ($1, $2) \
+                     ON CONFLICT(file_id) DO UPDATE SET expires_at = excluded.expires_at",
+                )
+                .bind(file_id)
+                .bind(expires_at)
+                .execute(&mut *tx)
+                .await?;
+            }
+            Some(None) => {
+                sqlx::query("DELETE FROM file_expirations WHERE file_id = $1")
+                    .bind(file_id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            None => {}
+        }
+        tx.commit().await?;
+
+        info!("File metadata updated: {}", file_id);
+        self.get_file_metadata(file_id).await
+    }
+
+    pub async fn list_file_tags(&self, file_id: &str) -> Result<Vec<String>, sqlx::Error> {
+        let tags: Vec<(String,)> =
+            sqlx::query_as("SELECT tag FROM file_tags WHERE file_id = $1 ORDER BY tag")
+                .bind(file_id)
+                .fetch_all(&self.pool)
+                .await?;
+        Ok(tags.into_iter().map(|(tag,)| tag).collect())
+    }
+
+    pub async fn get_file_expiration(&self, file_id: &str) -> Result<Option<String>, sqlx::Error> {
+        let expires_at: Option<(String,)> =
+            sqlx::query_as("SELECT expires_at FROM file_expirations WHERE file_id = $1")
+                .bind(file_id)
+                .fetch_optional(&self.pool)
+                .await?;
+        Ok(expires_at.map(|(expires_at,)| expires_at))
+    }
+
+    /// Deletes every file whose expiry time has passed.
+    pub async fn delete_expired_files(
+        &self,
+    ) -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let expired: Vec<(String,)> =
+            sqlx::query_as("SELECT file_id FROM file_expirations WHERE expires_at <= $1")
+                .bind(Utc::now().to_rfc3339())
+                .fetch_all(&self.pool)
+                .await?;
+
+        let mut deleted = 0;
+        for (file_id,) in expired {
+            if self.delete_file(&file_id).await? {
+                deleted += 1;
+            } else {
+                // The file is already gone; forget its expiry.
+                sqlx::query("DELETE FROM file_expirations WHERE file_id = $1")
+                    .bind(&file_id)
+                    .execute(&self.pool)
+                    .await?;
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Applies the steps of a batch in order in one transaction, so either
+    /// all of them take effect or, when one fails, none do. The whole batch
+    /// runs under `lock_directory_tree`, as its steps check names and
+    /// directory moves.
+    pub async fn apply_batch(
+        &self,
+        operations: &[BatchOperation],
+    ) -> Result<Vec<BatchOperationResult>, BatchError> {
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        let mut created: Vec<Option<String>> = Vec::with_capacity(operations.len());
+        let mut results = Vec::with_capacity(operations.len());
+        let mut events = Vec::new();
+        let now = Utc::now().to_rfc3339();
+
+        for (index, operation) in operations.iter().enumerate() {
+            let reject = |message: String| BatchError::Rejected { index, message };
+            let resolve = |id: &str| match id.strip_prefix('$') {
+                None => Ok(id.to_string()),
+                Some(step) => step
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|step| created.get(step).cloned().flatten())
+                    .ok_or_else(|| reject(format!("'{}' is not an earlier mkdir step", id))),
+            };
+            let kind_error =
+                |kind: &str| reject(format!("Unknown kind '{}'; use file or directory", kind));
+
+            let (op, id) = match operation {
+                BatchOperation::Mkdir { name, parent_id } => {
+                    let parent_id = parent_id.as_deref().map(resolve).transpose()?;
+                    let name = self.checked_name(name).map_err(reject)?;
+                    if let Some(parent) = &parent_id {
+                        if fetch_directory(&mut tx, parent).await?.is_none() {
+                            return Err(reject("Parent directory not found".to_string()));
+                        }
+                    }
+                    if let Some(taken) = self
+                        .sibling_named(&mut tx, parent_id.as_deref(), &name, None, true)
+                        .await?
+                    {
+                        return Err(reject(taken.to_string()));
+                    }
+
+                    let id = Uuid::new_v4().to_string();
+                    sqlx::query(
+                        "INSERT INTO directories (id, name, parent_id, created_at, updated_at) VALUES ($1, $2, $3, $4, $5)",
+                    )
+                    .bind(&id)
+                    .bind(&name)
+                    .bind(&parent_id)
+                    .bind(&now)
+                    .bind(&now)
+                    .execute(&mut *tx)
+                    .await?;
+                    events.push(ChangeEvent::DirectoryCreated {
+                        directory_id: id.clone(),
+                        name,
+                        parent_id,
+                    });
+                    ("mkdir", id)
+                }
+                BatchOperation::Move {
+                    kind,
+                    id,
+                    parent_id,
+                    version,
+                } => {
+                    let id = resolve(id)?;
+                    let parent_id = parent_id.as_deref().map(resolve).transpose()?;
+                    if let Some(parent) = &parent_id {
+                        if fetch_directory(&mut tx, parent).await?.is_none() {
+                            return Err(reject("Target directory not found".to_string()));
+                        }
+                    }
+                    match kind.as_str() {
+                        "file" => {
+                            let Some(file) = fetch_file(&mut tx, &id).await? else {
+                                return Err(reject("File not found".to_string()));
+                            };
+                            check_version(*version, file.version).map_err(reject)?;
+                            if let Some(taken) = self
+                                .sibling_named(
+                                    &mut tx,
+                                    parent_id.as_deref(),
+                                    &file.original_filename,
+                                    Some(&id),
+                                    false,
+                                )
+                                .await?
+                            {
+                                return Err(reject(taken.to_string()));
+                            }
+                            sqlx::query(
+                                "UPDATE files SET parent_directory_id = $1, version = version + 1 WHERE id = $2",
+                            )
+                            .bind(&parent_id)
+                            .bind(&id)
+                            .execute(&mut *tx)
+                            .await?;
+                            events.push(ChangeEvent::FileMoved {
+                                file_id: id.clone(),
+                                parent_directory_id: parent_id.clone(),
+                            });
+                        }
+                        "directory" => {
+                            let Some(directory) = fetch_directory(&mut tx, &id).await? else {
+                                return Err(reject("Directory not found".to_string()));
+                            };
+                            check_version(*version, directory.version).map_err(reject)?;
+                            if let Some(parent) = &parent_id {
+                                if *parent == id || is_ancestor_within(&mut tx, &id, parent).await?
+                                {
+                                    return Err(reject(
+                                        "Cannot move a directory into itself or one of its subdirectories"
+                                            .to_string(),
+                                    ));
+                                }
+                            }
+                            if let Some(taken) = self
+                                .sibling_named(
+                                    &mut tx,
+                                    parent_id.as_deref(),
+                                    &directory.name,
+                                    Some(&id),
+                                    true,
+                                )
+                                .await?
+                            {
+                                return Err(reject(taken.to_string()));
+                            }
+                            sqlx::query(
+                                "UPDATE directories SET parent_id = $1, updated_at = $2, version = version + 1 WHERE id = $3",
+                            )
+                            .bind(&parent_id)
+                            .bind(&now)
+                            .bind(&id)
+                            .execute(&mut *tx)
+                            .await?;
+                            events.push(ChangeEvent::DirectoryMoved {
+                                directory_id: id.clone(),
+                                parent_id: parent_id.clone(),
+                            });
+                        }
+                        other => return Err(kind_error(other)),
+                    }
+                    sqlx::query(
+                        "DELETE FROM directory_pins WHERE entry_id = $1 AND directory_id IS DISTINCT FROM CAST($2 AS TEXT)",
+                    )
+                    .bind(&id)
+                    .bind(&parent_id)
+                    .execute(&mut *tx)
+                    .await?;
+                    ("move", id)
+                }
+                BatchOperation::Rename {
+                    kind,
+                    id,
+                    name,
+                    version,
+                } => {
+                    let id = resolve(id)?;
+                    let name = self.checked_name(name).map_err(reject)?;
+                    match kind.as_str() {
+                        "file" => {
+                            let Some(file) = fetch_file(&mut tx, &id).await? else {
+                                return Err(reject("File not found".to_string()));
+                            };
+                            check_version(*version, file.version).map_err(reject)?;
+                            if let Some(taken) = self
+                                .sibling_named(
+                                    &mut tx,
+                                    file.parent_directory_id.as_deref(),
+                                    &name,
+                                    Some(&id),
+                                    false,
+                                )
+                                .await?
+                            {
+                                return Err(reject(taken.to_string()));
+                            }
+                            sqlx::query(
+                                "UPDATE files SET original_filename = $1, version = version + 1 WHERE id = $2",
+                            )
+                            .bind(&name)
+                            .bind(&id)
+                            .execute(&mut *tx)
+                            .await?;
+                            events.push(ChangeEvent::FileRenamed {
+                                file_id: id.clone(),
+                                name,
+                                parent_directory_id: file.parent_directory_id,
+                            });
+                        }
+                        "directory" => {
+                            let Some(directory) = fetch_directory(&mut tx, &id).await? else {
+                                return Err(reject("Directory not found".to_string()));
+                            };
+                            check_version(*version, directory.version).map_err(reject)?;
+                            if let Some(taken) = self
+                                .sibling_named(
+                                    &mut tx,
+                                    directory.parent_id.as_deref(),
+                                    &name,
+                                    Some(&id),
+                                    true,
+                                )
+                                .await?
+                            {
+                                return Err(reject(taken.to_string()));
+                            }
+                            sqlx::query(
+                                "UPDATE directories SET name = $1, updated_at = $2, version = version + 1 WHERE id = $3",
+                            )
+                            .bind(&name)
+                            .bind(&now)
+                            .bind(&id)
+                            .execute(&mut *tx)
+                            .await?;
+                            events.push(ChangeEvent::DirectoryRenamed {
+                                directory_id: id.clone(),
+                                name,
+                                parent_id: directory.parent_id,
+                            });
+                        }
+                        other => return Err(kind_error(other)),
+                    }
+                    ("rename", id)
+                }
+                BatchOperation::Delete { kind, id } => {
+                    let id = resolve(id)?;
+                    match kind.as_str() {
+                        "file" => {
+                            let Some(file) = fetch_file(&mut tx, &id).await? else {
+                                return Err(reject("File not found".to_string()));
+                            };
+                            sqlx::query("UPDATE files SET trashed = 1, deleted_at = $1 WHERE id = $2")
+                                .bind(&now)
+                                .bind(&id)
+                                .execute(&mut *tx)
+                                .await?;
+                            events.push(ChangeEvent::FileDeleted {
+                                file_id: id.clone(),
+                                parent_directory_id: file.parent_directory_id,
+                            });
+                        }
+                        "directory" => {
+                            let Some(directory) = fetch_directory(&mut tx, &id).await? else {
+                                return Err(reject("Directory not found".to_string()));
+                            };
+                            sqlx::query("UPDATE directories SET trashed = 1, deleted_at = $1 WHERE id = $2")
+                                .bind(&now)
+                                .bind(&id)
+                                .execute(&mut *tx)
+                                .await?;
+                            set_contents_trashed(&mut tx, &id, true).await?;
+                            events.push(ChangeEvent::DirectoryDeleted {
+                                directory_id: id.clone(),
+                                parent_id: directory.parent_id,
+                            });
+                        }
+                        other => return Err(kind_error(other)),
+                    }
+                    ("delete", id)
+                }
+            };
+
+            created.push((op == "mkdir").then(|| id.clone()));
+            results.push(BatchOperationResult {
+                index,
+                op: op.to_string(),
+                id,
+            });
+        }
+        tx.commit().await?;
+
+        for event in events {
+            self.events.publish(event);
+        }
+        info!("Applied batch of {} operations", results.len());
+        Ok(results)
+    }
+
+    /// Normalizes `name` and checks it is allowed for a file or directory.
+    fn checked_name(&self, name: &str) -> Result<String, String> {
+        let name = self.names.normalize(name);
+        self.names
+            .validate(&name)
+            .map_err(|e| format!("Invalid name: {}", e))?;
+        Ok(name)
+    }
+
+    /// The sibling in `parent_id`, other than `except`, that already goes by
+    /// `name`, for a directory or, with `is_directory` false, a file. Among
+    /// directories names are always unique. With case-insensitive names
+    /// they are across files and directories too, as clients that treat
+    /// names that way can't tell such entries apart. Callers check under
+    /// `lock_directory_tree`, in the transaction that takes the name.
+    async fn sibling_named(
+        &self,
+        conn: &mut DbConnection,
+        parent_id: Option<&str>,
+        name: &str,
+        except: Option<&str>,
+        is_directory: bool,
+    ) -> Result<Option<NameTaken>, sqlx::Error> {
+        let taken = |kind| NameTaken {
+            kind,
+            name: name.to_string(),
+        };
+        let same = |(id, other): &(String, String)| {
+            Some(id.as_str()) != except && self.names.same_name(other, name)
+        };
+        if is_directory || self.names.case_insensitive {
+            let directories: Vec<(String, String)> =
+                sqlx::query_as("SELECT id, name FROM directories WHERE parent_id IS NOT DISTINCT FROM CAST($1 AS TEXT) AND trashed = 0")
+                    .bind(parent_id)
+                    .fetch_all(&mut *conn)
+                    .await?;
+            if directories.iter().any(same) {
+                return Ok(Some(taken("directory")));
+            }
+        }
+        if self.names.case_insensitive {
+            let files: Vec<(String, String)> =
+                sqlx::query_as("SELECT id, original_filename FROM files WHERE parent_directory_id IS NOT DISTINCT FROM CAST($1 AS TEXT) AND trashed = 0")
+                    .bind(parent_id)
+                    .fetch_all(&mut *conn)
+                    .await?;
+            if files.iter().any(same) {
+                return Ok(Some(taken("file")));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Like `sibling_named`, for checks ahead of a change that are made again
+    /// under the lock when it is recorded.
+    pub async fn name_taken(
+        &self,
+        parent_id: Option<&str>,
+        name: &str,
+        except: Option<&str>,
+        is_directory: bool,
+    ) -> Result<Option<NameTaken>, sqlx::Error> {
+        let mut conn = self.pool.acquire().await?;
+        self.sibling_named(&mut conn, parent_id, name, except, is_directory)
+            .await
+    }
+
+    /// Copies files and directories, with everything inside them, into
+    /// `target`. The copies are recorded in one transaction: if any item
+    /// can't be copied, nothing is, and the results say why.
+    pub async fn bulk_copy(
+        &self,
+        file_ids: Vec<String>,
+        directory_ids: Vec<String>,
+        target: Option<String>,
+    ) -> Result<Vec<BulkItemResult>, Box<dyn std::error::Error + Send + Sync>> {
+        if target.as_deref().is_some_and(mirror::is_mirror_id) {
+            return Err(mirror::READ_ONLY_MESSAGE.into());
+        }
+
+        let (mut results, files, directories) = self
+            .check_bulk_transfer(file_ids, directory_ids, target.as_deref(), true)
+            .await?;
+        if results.iter().any(|r| r.error.is_some()) {
+            return Ok(reject_bulk_items(results, "copied"));
+        }
+
+        // Plan every copy first, then copy the bytes, then record it all.
+        let now = Utc::now().to_rfc3339();
+        let mut plan = CopyPlan::default();
+        let mut new_ids = Vec::new();
+        for file in files {
+            new_ids.push(plan.add_file(self, file, target.clone(), &now));
+        }
+        for directory in directories {
+            let mut pending = vec![(directory, target.clone())];
+            let mut root_id = None;
+            while let Some((source, parent_id)) = pending.pop() {
+                let copy = Directory {
+                    id: Uuid::new_v4().to_string(),
+                    name: source.name,
+                    parent_id,
+                    created_at: now.clone(),
+                    updated_at: now.clone(),
+                    color: source.color,
+                    icon: source.icon,
+                    version: 1,
+                };
+                root_id.get_or_insert_with(|| copy.id.clone());
+                for file in self.list_files(Some(source.id.clone())).await? {
+                    plan.add_file(self, file, Some(copy.id.clone()), &now);
+                }
+                for link in self.list_links(Some(source.id.clone())).await? {
+                    plan.links.push(Link {
+                        id: Uuid::new_v4().to_string(),
+                        parent_directory_id: Some(copy.id.clone()),
+                        created_at: now.clone(),
+                        ..link
+                    });
+                }
+                for child in self.list_directories(Some(source.id.clone())).await? {
+                    pending.push((child, Some(copy.id.clone())));
+                }
+                plan.directories.push(copy);
+            }
+            new_ids.extend(root_id);
+        }
+
+        let mut copied_paths = Vec::new();
+        let recorded: Result<(), Box<dyn std::error::Error + Send + Sync>> = async {
+            let mut chunked = Vec::new();
+            for (copy, source) in &plan.files {
+                if self.file_chunks(&source.id).await?.is_empty() {
+                    layout::create_parent(Path::new(&copy.storage_path)).await?;
+                    fs::copy(&source.storage_path, &copy.storage_path).await?;
+                    copied_paths.push(copy.storage_path.clone());
+                } else {
+                    chunked.push((copy.id.clone(), source.id.clone()));
+                }
+            }
+            self.record_copy_plan(&plan, &chunked, target.as_deref())
+                .await?;
+            Ok(())
+        }
+        .await;
+        if let Err(e) = recorded {
+            for path in copied_paths {
+                let _ = fs::remove_file(path).await;
+            }
+            return Err(e);
+        }
+
+        for directory in &plan.directories {
+            self.events.publish(ChangeEvent::DirectoryCreated {
+                directory_id: directory.id.clone(),
+                name: directory.name.clone(),
+                parent_id: directory.parent_id.clone(),
+            });
+        }
+        for (copy, _) in &plan.files {
+            // Big copies are deduplicated like any other upload; the copy
+            // is already recorded, so a failure here only costs disk space.
+            if copied_paths.contains(&copy.storage_path)
+                && self
+                    .chunk_dedup_min_size
+                    .is_some_and(|min| copy.file_size as u64 >= min)
+            {
+                if let Err(e) = self.convert_to_chunks(copy).await {
+                    warn!("Failed to deduplicate copy {}: {}", copy.id, e);
+                }
+            }
+            self.events.publish(ChangeEvent::FileCreated {
+                file_id: copy.id.clone(),
+                name: copy.original_filename.clone(),
+                parent_directory_id: copy.parent_directory_id.clone(),
+            });
+        }
+        for link in &plan.links {
+            self.events.publish(ChangeEvent::LinkCreated {
+                link_id: link.id.clone(),
+                name: link.name.clone(),
+                parent_directory_id: link.parent_directory_id.clone(),
+            });
+        }
+
+        for (result, new_id) in results.iter_mut().zip(new_ids) {
+            result.success = true;
+            result.new_id = Some(new_id);
+        }
+        info!(
+            "Bulk copied {} items ({} files, {} directories) -> {:?}",
+            results.len(),
+            plan.files.len(),
+            plan.directories.len(),
+            target
+        );
+        Ok(results)
+    }
+
+    /// Inserts the rows of a planned copy in one transaction. `chunked`
+    /// pairs each copy with a deduplicated source whose chunks it shares.
+    /// Fails with `NameTaken` when a sibling in `target` has taken the name
+    /// of one of the copies since they were checked.
+    async fn record_copy_plan(
+        &self,
+        plan: &CopyPlan,
+        chunked: &[(String, String)],
+        target: Option<&str>,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let mut tx = self.pool.begin().await?;
+        self.lock_directory_tree(&mut tx).await?;
+        let copied_names = plan
+            .directories
+            .iter()
+            .filter(|d| d.parent_id.as_deref() == target)
+            .map(|d| (&d.name, true))
+            .chain(
+                plan.files
+                    .iter()
+                    .filter(|(f, _)| f.parent_directory_id.as_deref() == target)
+                    .map(|(f, _)| (&f.original_filename, false)),
+            );
+        for (name, is_directory) in copied_names {
+            if let Some(taken) = self
+                .sibling_named(&mut tx, target, name, None, is_directory)
+                .await?
+            {
+                return Err(taken.into());
+            }
+        }
+        for directory in &plan.directories {
+            sqlx::query(
+                r#"
+                INSERT INTO directories (id, name, parent_id, created_at, updated_at, color, icon)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            )
+            .bind(&directory.id)
+            .bind(&directory.name)
+            .bind(&directory.parent_id)
+            .bind(&directory.created_at)
+            .bind(&directory.updated_at)
+            .bind(&directory.color)
+            .bind(&directory.icon)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (copy, _) in &plan.files {
+            sqlx::query(
+                r#"
+                INSERT INTO files (id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, owner_id, sha256)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+                "#,
+            )
+            .bind(&copy.id)
+            .bind(&copy.filename)
+            .bind(&copy.original_filename)
+            .bind(copy.file_size)
+            .bind(&copy.mime_type)
+            .bind(&copy.storage_path)
+            .bind(&copy.uploaded_at)
+            .bind(&copy.description)
+            .bind(&copy.parent_directory_id)
+            .bind(&copy.owner_id)
+            .bind(&copy.sha256)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for (copy_id, source_id) in chunked {
+            sqlx::query(
+                r#"
+                UPDATE chunks SET ref_count = ref_count + (
+                    SELECT COUNT(*) FROM file_chunks WHERE file_id = $1 AND chunk_hash = chunks.hash
+                )
+                WHERE hash IN (SELECT chunk_hash FROM file_chunks WHERE file_id = $2)
+                "#,
+            )
+            .bind(source_id)
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await?;
+            sqlx::query(
+                r#"
+                INSERT INTO file_chunks (file_id, seq, chunk_hash, chunk_offset, length)
+                SELECT $1, seq, chunk_hash, chunk_offset, length FROM file_chunks WHERE file_id = $2
+                "#,
+            )
+            .bind(copy_id)
+            .bind(source_id)
+            .execute(&mut *tx)
+            .await?;
+        }
+        for link in &plan.links {
+            sqlx::query(
+                r#"
+                INSERT INTO links (id, name, target_url, target_file_id, parent_directory_id, description, created_at)
+                VALUES ($1, $2, $3, $4, $5, $6, $7)
+                "#,
+            )
+            .bind(&link.id)
+            .bind(&link.name)
+            .bind(&link.target_url)
+            .bind(&link.target_file_id)
+            .bind(&link.parent_directory_id)
+            .bind(&link.description)
+            .bind(&link.created_at)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Looks up the items of a bulk move or copy and checks each can go
+    /// into `target`. Returns a result per item, with `error` set on those
+    /// that can't, along with the items found. Repeated IDs count once.
+    async fn check_bulk_transfer(
+        &self,
+        file_ids: Vec<String>,
+        directory_ids: Vec<String>,
+        target: Option<&str>,
+        copy: bool,
+    ) -> Result<(Vec<BulkItemResult>, Vec<FileMetadata>, Vec<Directory>), sqlx::Error> {
+        let verb = if copy { "copy" } else { "move" };
+        let item = |id: String, kind: &str, error: Option<String>| BulkItemResult {
+            id,
+            kind: kind.to_string(),
+            success: false,
+            new_id: None,
+            error,
+        };
+        let mut seen = HashSet::new();
+        let mut results = Vec::new();
+        let mut files = Vec::new();
+        let mut directories: Vec<Directory> = Vec::new();
+
+        for id in file_ids {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            let error = match self.get_file_metadata(&id).await? {
+                Some(file) => {
+                    let except = (!copy).then_some(id.as_str());
+                    let name = &file.original_filename;
+                    if let Some(taken) = self.name_taken(target, name, except, false).await? {
+                        Some(taken.to_string())
+                    } else if self.names.case_insensitive
+                        && files
+                            .iter()
+                            .any(|other: &FileMetadata| self.names.same_name(&other.original_filename, name))
+                    {
+                        Some(format!("Another file named '{}' is in the same request", name))
+                    } else {
+                        files.push(file);
+                        None
+                    }
+                }
+                None => Some("File not found".to_string()),
+            };
+            results.push(item(id, "file", error));
+        }
+
+        for id in directory_ids {
+            if !seen.insert(id.clone()) {
+                continue;
+            }
+            let Some(directory) = self.get_directory(&id).await? else {
+                let error = Some("Directory not found".to_string());
+                results.push(item(id, "directory", error));
+                continue;
+            };
+
+            let into_itself = match target {
+                Some(target) => target == id || self.is_ancestor_of(&id, target).await?,
+                None => false,
+            };
+            let except = (!copy).then_some(id.as_str());
+            let existing = self.name_taken(target, &directory.name, except, true).await?;
+            let error = if into_itself {
+                Some(format!(
+                    "Cannot {} a directory into itself or one of its subdirectories",
+                    verb
+                ))
+            } else if let Some(taken) = existing {
+                Some(taken.to_string())
+            } else if self.names.case_insensitive
+                && files
+                    .iter()
+                    .any(|file| self.names.same_name(&file.original_filename, &directory.name))
+            {
+                Some(format!(
+                    "A file named '{}' is in the same request",
+                    directory.name
+                ))
+            } else if directories
+                .iter()
+                .any(|other| self.names.same_name(&other.name, &directory.name))
+            {
+                Some(format!(
+                    "Another directory named '{}' is in the same request",
+                    directory.name
+                ))
+            } else {
+                None
+            };
+            if error.is_none() {
+                directories.push(directory);
+            }
+            results.push(item(id, "directory", error));
+        }
+
+        Ok((results, files, directories))
+    }
+}
+
+async fn fetch_file(
+    conn: &mut DbConnection,
+    file_id: &str,
+) -> Result<Option<FileMetadata>, sqlx::Error> {
+    sqlx::query_as::<_, FileMetadata>(
+        "SELECT id, filename, original_filename, file_size, mime_type, storage_path, uploaded_at, description, parent_directory_id, version, owner_id, sha256 \
+         FROM files WHERE id = $1 AND trashed = 0",
+    )
+    .bind(file_id)
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+async fn fetch_directory(
+    conn: &mut DbConnection,
+    dir_id: &str,
+) -> Result<Option<Directory>, sqlx::Error> {
+    sqlx::query_as::<_, Directory>(
+        "SELECT id, name, parent_id, created_at, updated_at, color, icon, version FROM directories WHERE id = $1 AND trashed = 0",
+    )
+    .bind(dir_id)
+    .fetch_optional(&mut *conn)
+    .await
+}
+
+/// Like `FileStorage::is_ancestor_of`, on a connection that may be inside a
+/// transaction. A chain of parents that loops, which no move should have
+/// made, counts as an ancestor, so nothing is moved into it.
+async fn is_ancestor_within(
+    conn: &mut DbConnection,
+    ancestor_id: &str,
+    target_id: &str,
+) -> Result<bool, sqlx::Error> {
+    let mut seen = HashSet::new();
+    let mut current = target_id.to_string();
+    while let Some(directory) = fetch_directory(conn, &current).await? {
+        match directory.parent_id {
+            Some(parent_id) if parent_id == ancestor_id => return Ok(true),
+            Some(parent_id) if !seen.insert(parent_id.clone()) => return Ok(true),
+            Some(parent_id) => current = parent_id,
+            None => return Ok(false),
+        }
+    }
+    Ok(false)
+}
+
+/// Checks the version a batch step expects an entry to be at, if it gave one.
+fn check_version(expected: Option<i64>, current: i64) -> Result<(), String> {
+    match expected {
+        Some(expected) if expected != current => Err(format!(
+            "Entry was changed by someone else; it is at version {}, not {}",
+            current, expected
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Checks the SHA-256 of an upload against the one its client sent, if any.
+fn verify_checksum(expected: &ExpectedChecksum, sha256: &str) -> Result<(), UploadError> {
+    if expected.matches(sha256) {
+        return Ok(());
+    }
+    Err(UploadError::ChecksumMismatch {
+        expected: expected.value().unwrap_or_default(),
+        actual: sha256.to_string(),
+    })
+}
+
+/// Keeps a file's current content as an earlier version, replaced at
+/// `replaced_at`, and puts the content of `updated` in its place, if the
+/// file is still at `current.version`. Chunks of the current content move
+/// to the version.
+async fn replace_content(
+    conn: &mut DbConnection,
+    current: &FileMetadata,
+    updated: &FileMetadata,
+    replaced_at: &str,
+) -> Result<bool, sqlx::Error> {
+    let replaced = sqlx::query(
+        "UPDATE files SET filename = $1, file_size = $2, mime_type = $3, storage_path = $4, uploaded_at = $5, \
+         sha256 = $6, version = version + 1 WHERE id = $7 AND version = $8",
+    )
+    .bind(&updated.filename)
+    .bind(updated.file_size)
+    .bind(&updated.mime_type)
+    .bind(&updated.storage_path)
+    .bind(&updated.uploaded_at)
+    .bind(&updated.sha256)
+    .bind(&current.id)
+    .bind(current.version)
+    .execute(&mut *conn)
+    .await?
+    .rows_affected()
+        > 0;
+    if !replaced {
+        return Ok(false);
+    }
+
+    let version_id = Uuid::new_v4().to_string();
+    sqlx::query(
+        "INSERT INTO file_versions (id, file_id, filename, file_size, mime_type, storage_path, sha256, uploaded_at, replaced_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(&version_id)
+    .bind(&current.id)
+    .bind(&current.filename)
+    .bind(current.file_size)
+    .bind(&current.mime_type)
+    .bind(&current.storage_path)
+    .bind(&current.sha256)
+    .bind(&current.uploaded_at)
+    .bind(replaced_at)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("UPDATE file_chunks SET file_id = $1 WHERE file_id = $2")
+        .bind(&version_id)
+        .bind(&current.id)
+        .execute(&mut *conn)
+        .await?;
+    Ok(true)
+}
+
+/// Hides or shows everything inside a directory going into or coming out
+/// of the trash. Items deleted on their own before, and what is inside
+/// them, stay in the trash.
+async fn set_contents_trashed(
+    conn: &mut DbConnection,
+    dir_id: &str,
+    trashed: bool,
+) -> Result<(), sqlx::Error> {
+    const SUBTREE: &str = "WITH RECURSIVE subtree(id) AS (\
+         SELECT CAST($1 AS TEXT) \
+         UNION ALL \
+         SELECT d.id FROM directories d JOIN subtree s ON d.parent_id = s.id WHERE d.deleted_at IS NULL) ";
+
+    sqlx::query(&format!(
+        "{}UPDATE directories SET trashed = $2 WHERE id IN (SELECT id FROM subtree) AND deleted_at IS NULL",
+        SUBTREE
+    ))
+    .bind(dir_id)
+    .bind(trashed as i64)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(&format!(
+        "{}UPDATE files SET trashed = $2 WHERE parent_directory_id IN (SELECT id FROM subtree) AND deleted_at IS NULL",
+        SUBTREE
+    ))
+    .bind(dir_id)
+    .bind(trashed as i64)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Deletes a file's row and everything recorded about it, but not its
+/// content. Returns whether the file existed.
+async fn delete_file_rows(conn: &mut DbConnection, file_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM files WHERE id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM upload_receipts WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM file_access_stats WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM file_access_log WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM shares WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    // A bundle goes once it has no files left.
+    sqlx::query("DELETE FROM bundle_share_files WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM bundle_shares WHERE id NOT IN (SELECT bundle_id FROM bundle_share_files)")
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM audio_metadata WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM file_attachments WHERE file_id = $1 OR attachment_id = $2")
+        .bind(file_id)
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM directory_pins WHERE entry_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM file_tags WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM file_expirations WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM upload_submissions WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM quarantined_files WHERE file_id = $1")
+        .bind(file_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+async fn record_quarantine_decision(
+    conn: &mut DbConnection,
+    file: &FileMetadata,
+    action: QuarantineAction,
+    actor: Option<&str>,
+    reason: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO quarantine_decisions (id, file_id, original_filename, action, actor, reason, decided_at) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&file.id)
+    .bind(&file.original_filename)
+    .bind(action.as_str())
+    .bind(actor)
+    .bind(reason)
+    .bind(Utc::now().to_rfc3339())
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+fn share_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(SHARE_TOKEN_LEN)
+        .map(char::from)
+        .collect()
+}
+
+/// Deletes what is recorded about a directory, such as its links,
+/// integrations and arrangement, but not the directory or its files.
+async fn delete_directory_rows(
+    conn: &mut DbConnection,
+    dir_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM links WHERE parent_directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM watched_folders WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM directory_acl WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM thumbnail_policies WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        "DELETE FROM integration_deliveries WHERE integration_id IN \
+         (SELECT id FROM directory_integrations WHERE directory_id = $1)",
+    )
+    .bind(dir_id)
+    .execute(&mut *conn)
+    .await?;
+    sqlx::query("DELETE FROM directory_integrations WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM directory_arrangements WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM directory_shares WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM drop_links WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query(
+        "DELETE FROM upload_submissions WHERE upload_link_id IN (SELECT id FROM upload_links WHERE directory_id = $1)",
+    )
+    .bind(dir_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("DELETE FROM upload_links WHERE directory_id = $1")
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("DELETE FROM directory_pins WHERE directory_id = $1 OR entry_id = $2")
+        .bind(dir_id)
+        .bind(dir_id)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Everything a bulk copy creates: the new rows, and for each copied file
+/// the file it was copied from.
+#[derive(Default)]
+struct CopyPlan {
+    directories: Vec<Directory>,
+    files: Vec<(FileMetadata, FileMetadata)>,
+    links: Vec<Link>,
+}
+
+impl CopyPlan {
+    /// Plans a copy of `source` in `parent_directory_id` and returns its ID.
+    fn add_file(
+        &mut self,
+        storage: &FileStorage,
+        source: FileMetadata,
+        parent_directory_id: Option<String>,
+        now: &str,
+    ) -> String {
+        let (file_id, file_path, stored_filename) =
+            storage.prepare_upload_path(&source.original_filename);
+        let copy = FileMetadata {
+            id: file_id.clone(),
+            filename: stored_filename,
+            original_filename: source.original_filename.clone(),
+            file_size: source.file_size,
+            mime_type: source.mime_type.clone(),
+            storage_path: file_path.to_string_lossy().to_string(),
+            uploaded_at: now.to_string(),
+            description: source.description.clone(),
+            parent_directory_id,
+            version: 1,
+            owner_id: source.owner_id.clone(),
+            sha256: source.sha256.clone(),
+        };
+        self.files.push((copy, source));
+        file_id
+    }
+}
+
+/// Marks every item of a bulk operation that was refused because of another
+/// item's problem.
+fn reject_bulk_items(mut results: Vec<BulkItemResult>, done: &str) -> Vec<BulkItemResult> {
+    for result in &mut results {
+        result
+            .error
+            .get_or_insert_with(|| format!("Not {} because another item failed", done));
+    }
+    results
+}
+
+/// Entries of `dir` last modified more than `age` ago; none if `dir` can't
+/// be read.
+async fn stale_entries(dir: &Path, age: std::time::Duration) -> Vec<PathBuf> {
+    let mut stale = Vec::new();
+    let Ok(mut entries) = fs::read_dir(dir).await else {
+        return stale;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let modified = entry.metadata().await.and_then(|m| m.modified());
+        if modified.is_ok_and(|modified| modified.elapsed().is_ok_and(|elapsed| elapsed > age)) {
+            stale.push(entry.path());
+        }
+    }
+    stale
+}
+
+/// Bytes taken by the files directly in `dir`, such as a session's chunks.
+async fn disk_usage(dir: &Path) -> u64 {
+    let mut total = 0;
+    let Ok(mut entries) = fs::read_dir(dir).await else {
+        return 0;
+    };
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        if let Ok(metadata) = entry.metadata().await {
+            total += metadata.len();
+        }
+    }
+    total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth, db, push, receipts};
+
+    /// A storage backed by a fresh SQLite database and upload directory
+    /// under `root`, with `configure` applied to the configuration.
+    async fn storage_in(root: &Path, configure: impl FnOnce(&mut Config)) -> FileStorage {
+        let mut config = Config::from_env();
+        config.upload_dir = root.join("uploads");
+        config.database_url = format!("sqlite:{}", root.join("files.db").display());
+        configure(&mut config);
+        std::fs::create_dir_all(&config.upload_dir).unwrap();
+
+        let pool = db::init_db(&config.database_url).await.unwrap();
+        let storage = FileStorage::new(
+            &config,
+            pool,
+            receipts::ReceiptSigner::from_config(&config).unwrap(),
+            push::PushSender::from_config(&config).unwrap(),
+            auth::Tokens::from_config(&config).unwrap(),
+            None,
+            None,
+        );
+        storage.init().await.unwrap();
+        storage
+    }
+
+    #[tokio::test]
+    async fn deleting_an_imported_file_keeps_the_original() {
+        let root = std::env::temp_dir().join(format!("fileshare-test-{}", Uuid::new_v4()));
+        let storage = storage_in(&root, |_| {}).await;
+        let original = root.join("import").join("notes.txt");
+        std::fs::create_dir_all(original.parent().unwrap()).unwrap();
+        std::fs::write(&original, b"keep me").unwrap();
+
+        let file = storage
+            .register_existing_file(
+                &original,
+                "notes.txt".to_string(),
+                7,
+                Utc::now().to_rfc3339(),
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!storage.is_uploaded(&file));
+        assert!(storage.delete_file_permanently(&file.id).await.unwrap());
+
+        assert!(storage.get_file_metadata(&file.id).await.unwrap().is_none());
+        assert_eq!(std::fs::read(&original).unwrap(), b"keep me");
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[tokio::test]
+    async fn case_insensitive_names_cover_files_and_concurrent_creates() {
+        let root = std::env::temp_dir().join(format!("fileshare-test-{}", Uuid::new_v4()));
+        let storage = storage_in(&root, |config| config.case_insensitive_names = true).await;
+        let original = root.join("import").join("Notes.txt");
+        std::fs::create_dir_all(original.parent().unwrap()).unwrap();
+        std::fs::write(&original, b"notes").unwrap();
+        let register = |name: &str| {
+            storage.register_existing_file(&original, name.to_string(), 5, Utc::now().to_rfc3339(), None)
+        };
+
+        assert!(register("Notes.txt").await.unwrap().is_some());
+        assert!(register("NOTES.TXT").await.unwrap().is_none());
+        let clash = storage.create_directory("notes.txt", None).await.unwrap_err();
+        assert!(clash.is::<NameTaken>());
+
+        let (upper, lower) = tokio::join!(
+            storage.create_directory("Photos", None),
+            storage.create_directory("photos", None),
+        );
+        assert!(upper.is_ok() != lower.is_ok());
+        std::fs::remove_dir_all(&root).unwrap();
     }
 }
@@ -0,0 +1,100 @@
+use crate::config::Config;
+use crate::names::NamePolicy;
+use crate::storage::FileStorage;
+use tracing::info;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Directories created for every new tenant or user, listed as
+/// comma-separated paths such as `Inbox,Shared,Archive/2024`.
+#[derive(Debug, Clone, Default)]
+pub struct DirectoryTemplate {
+    paths: Vec<Vec<String>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{key}: {reason}, got {path:?}")]
+pub struct TemplateError {
+    key: &'static str,
+    path: String,
+    reason: String,
+}
+
+impl DirectoryTemplate {
+    /// The template new tenants start with, from `TENANT_DIRECTORY_TEMPLATE`.
+    pub fn for_tenants(config: &Config) -> Result<Self, TemplateError> {
+        Self::parse(
+            "TENANT_DIRECTORY_TEMPLATE",
+            &config.tenant_directory_template,
+            &NamePolicy::from_config(config),
+        )
+    }
+
+    /// The template created in each new user's home directory, from
+    /// `USER_DIRECTORY_TEMPLATE`.
+    pub fn for_users(config: &Config) -> Result<Self, TemplateError> {
+        Self::parse(
+            "USER_DIRECTORY_TEMPLATE",
+            &config.user_directory_template,
+            &NamePolicy::from_config(config),
+        )
+    }
+
+    fn parse(
+        key: &'static str,
+        paths: &[String],
+        names: &NamePolicy,
+    ) -> Result<Self, TemplateError> {
+        let mut parsed = Vec::new();
+        for path in paths {
+            let mut components = Vec::new();
+            for name in path.split('/').map(str::trim).filter(|n| !n.is_empty()) {
+                let name = names.normalize(name);
+                names.validate(&name).map_err(|reason| TemplateError {
+                    key,
+                    path: path.clone(),
+                    reason,
+                })?;
+                components.push(name);
+            }
+            if !components.is_empty() {
+                parsed.push(components);
+            }
+        }
+        Ok(Self { paths: parsed })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    /// Creates every directory of the template beneath `parent_id` that
+    /// doesn't exist yet. Returns how many paths were laid out.
+    pub async fn apply(
+        &self,
+        storage: &FileStorage,
+        parent_id: Option<String>,
+    ) -> Result<usize, BoxError> {
+        for path in &self.paths {
+            storage
+                .ensure_directory_path(parent_id.clone(), path)
+                .await?;
+        }
+        Ok(self.paths.len())
+    }
+}
+
+/// Lays out the tenant template in a storage that has nothing in it yet,
+/// which is how a new tenant, or a new instance, starts out. Storage that
+/// is in use is left alone, so directories removed later stay removed.
+pub async fn set_up_new_storage(
+    storage: &FileStorage,
+    template: &DirectoryTemplate,
+) -> Result<(), BoxError> {
+    if template.is_empty() || !storage.is_empty().await? {
+        return Ok(());
+    }
+    let created = template.apply(storage, None).await?;
+    info!("Created {} template directories in new storage", created);
+    Ok(())
+}
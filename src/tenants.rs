@@ -0,0 +1,304 @@
+use crate::config::Config;
+use crate::db::{self, Backend, DbPool};
+use crate::models::ErrorResponse;
+use crate::storage::FileStorage;
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+    Json, Router,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tower::ServiceExt;
+
+/// Header carrying the API key that selects a tenant.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// A tenant as listed in `TENANTS`.
+#[derive(Debug, Clone)]
+pub struct TenantSpec {
+    pub name: String,
+    /// Key that selects the tenant; tenants without one are only reached
+    /// through `TENANT_HEADER`.
+    pub api_key: Option<String>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TenantConfigError {
+    #[error("Tenant names are 1 to 63 lowercase letters, digits and dashes, got {0:?}")]
+    Name(String),
+    #[error("Tenant {0} is listed more than once")]
+    Duplicate(String),
+    #[error("Tenants {0} and {1} have the same API key")]
+    SharedKey(String, String),
+    #[error("TENANT_HEADER is not a valid header name: {0}")]
+    Header(String),
+}
+
+/// The tenants in `TENANTS`, checked for names usable as folder and schema
+/// names and for keys that select a single tenant.
+pub fn parse(config: &Config) -> Result<Vec<TenantSpec>, TenantConfigError> {
+    let mut specs: Vec<TenantSpec> = Vec::new();
+    for entry in &config.tenants {
+        let (name, api_key) = match entry.split_once(':') {
+            Some((name, key)) => (name.trim(), Some(key.trim()).filter(|k| !k.is_empty())),
+            None => (entry.trim(), None),
+        };
+        if !valid_name(name) {
+            return Err(TenantConfigError::Name(name.to_string()));
+        }
+        if specs.iter().any(|spec| spec.name == name) {
+            return Err(TenantConfigError::Duplicate(name.to_string()));
+        }
+        if let Some(other) = specs
+            .iter()
+            .find(|spec| api_key.is_some() && spec.api_key.as_deref() == api_key)
+        {
+            return Err(TenantConfigError::SharedKey(
+                other.name.clone(),
+                name.to_string(),
+            ));
+        }
+        specs.push(TenantSpec {
+            name: name.to_string(),
+            api_key: api_key.map(str::to_string),
+        });
+    }
+    if let Some(header) = &config.tenant_header {
+        HeaderName::try_from(header.as_str())
+            .map_err(|_| TenantConfigError::Header(header.clone()))?;
+    }
+    Ok(specs)
+}
+
+fn valid_name(name: &str) -> bool {
+    (1..=63).contains(&name.len())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// The configuration a tenant's storage runs with: the instance's, with a
+/// database and upload folder of its own. The import and mirror
+/// directories and single sign-on stay with the default tenant.
+pub fn config_for(config: &Config, name: &str) -> Config {
+    let mut tenant = config.clone();
+    tenant.database_url = database_url(&config.database_url, name);
+    tenant.upload_dir = config.upload_dir.join("tenants").join(name);
+    tenant.import_dir = None;
+    tenant.mirror_dir = None;
+    tenant.watch_external_changes = false;
+    tenant.oidc_issuer_url = None;
+    tenant.mqtt_topic = format!("{}/{}", config.mqtt_topic.trim_end_matches('/'), name);
+    tenant.audit_cef_file = config.audit_cef_file.as_ref().map(|path| {
+        path.with_file_name(with_tenant(
+            &path.file_name().unwrap_or_default().to_string_lossy(),
+            name,
+        ))
+    });
+    tenant
+}
+
+/// A tenant's database: a SQLite file next to the instance's, named after
+/// the tenant, or a schema of its own in the same PostgreSQL database.
+fn database_url(database_url: &str, name: &str) -> String {
+    let (base, query) = match database_url.split_once('?') {
+        Some((base, query)) => (base, Some(query)),
+        None => (database_url, None),
+    };
+    match Backend::from_url(database_url) {
+        Some(Backend::Postgres) => format!(
+            "{}{}options[search_path]={},public",
+            database_url,
+            if query.is_some() { '&' } else { '?' },
+            schema(name)
+        ),
+        _ if base.contains(":memory:") => database_url.to_string(),
+        _ => {
+            let file_start = base.rfind(['/', ':']).map_or(0, |i| i + 1);
+            let tenant_base = format!(
+                "{}{}",
+                &base[..file_start],
+                with_tenant(&base[file_start..], name)
+            );
+            match query {
+                Some(query) => format!("{}?{}", tenant_base, query),
+                None => tenant_base,
+            }
+        }
+    }
+}
+
+/// `files.db` as `files.<name>.db`.
+fn with_tenant(file_name: &str, name: &str) -> String {
+    match file_name.rfind('.').filter(|&dot| dot > 0) {
+        Some(dot) => format!("{}.{}{}", &file_name[..dot], name, &file_name[dot..]),
+        None => format!("{}.{}", file_name, name),
+    }
+}
+
+/// PostgreSQL schema of a tenant. Names can't contain underscores, so
+/// dashes map to them without two tenants sharing a schema.
+fn schema(name: &str) -> String {
+    format!("tenant_{}", name.replace('-', "_"))
+}
+
+/// Opens and migrates a tenant's database, first creating its schema on
+/// PostgreSQL through the instance's pool.
+pub async fn init_db(pool: &DbPool, config: &Config, name: &str) -> Result<DbPool, sqlx::Error> {
+    if db::backend(&config.database_url)? == Backend::Postgres {
+        sqlx::raw_sql(&format!("CREATE SCHEMA IF NOT EXISTS {}", schema(name)))
+            .execute(pool)
+            .await?;
+    }
+    db::init_db(&config_for(config, name).database_url).await
+}
+
+/// A storage and the routes serving it.
+#[derive(Clone)]
+pub struct Tenant {
+    storage: FileStorage,
+    router: Router,
+    api_key: Option<String>,
+}
+
+impl Tenant {
+    pub fn new(storage: FileStorage, router: Router, api_key: Option<String>) -> Self {
+        Self {
+            storage,
+            router,
+            api_key,
+        }
+    }
+}
+
+/// Every tenant of the instance, and how requests pick theirs.
+pub struct Tenants {
+    default: Tenant,
+    tenants: HashMap<String, Tenant>,
+    header: Option<HeaderName>,
+}
+
+impl Tenants {
+    pub fn new(config: &Config, default: Tenant, tenants: HashMap<String, Tenant>) -> Self {
+        Self {
+            default,
+            tenants,
+            header: config
+                .tenant_header
+                .as_deref()
+                .and_then(|header| HeaderName::try_from(header).ok()),
+        }
+    }
+
+    /// The tenant a request is for: the one its `X-Api-Key` belongs to, or
+    /// the one a trusted proxy named in `TENANT_HEADER`. Public share,
+    /// upload and drop links carry neither, so they go to whichever tenant
+    /// issued their token. Anything else goes to the default tenant.
+    async fn select(&self, headers: &HeaderMap, path: &str) -> Result<&Tenant, Response> {
+        if let Some(key) = headers.get(API_KEY_HEADER) {
+            let key = key.to_str().unwrap_or_default();
+            return self
+                .tenants
+                .values()
+                .find(|tenant| tenant.api_key.as_deref() == Some(key))
+                .ok_or_else(|| error(StatusCode::UNAUTHORIZED, "Invalid API key"));
+        }
+        if let Some(name) = self.header.as_ref().and_then(|header| headers.get(header)) {
+            return name
+                .to_str()
+                .ok()
+                .and_then(|name| self.tenants.get(name))
+                .ok_or_else(|| error(StatusCode::NOT_FOUND, "Unknown tenant"));
+        }
+        if let Some(link) = PublicLink::from_path(path) {
+            if !link.issued_by(&self.default.storage).await {
+                for tenant in self.tenants.values() {
+                    if link.issued_by(&tenant.storage).await {
+                        return Ok(tenant);
+                    }
+                }
+            }
+        }
+        Ok(&self.default)
+    }
+}
+
+/// Hands each request to the routes of its tenant. Responses name the
+/// headers that picked it in `Vary`, so caches in front of the server keep
+/// tenants apart.
+pub async fn route(State(tenants): State<Arc<Tenants>>, request: Request) -> Response {
+    let router = match tenants
+        .select(request.headers(), request.uri().path())
+        .await
+    {
+        Ok(tenant) => tenant.router.clone(),
+        Err(response) => return response,
+    };
+    let mut response = router.oneshot(request).await.into_response();
+    if !tenants.tenants.is_empty() {
+        let headers = response.headers_mut();
+        headers.append(header::VARY, HeaderValue::from_static(API_KEY_HEADER));
+        if let Some(name) = &tenants.header {
+            headers.append(header::VARY, HeaderValue::from(name.clone()));
+        }
+    }
+    response
+}
+
+fn error(status: StatusCode, message: &str) -> Response {
+    (
+        status,
+        Json(ErrorResponse {
+            error: message.to_string(),
+        }),
+    )
+        .into_response()
+}
+
+/// The token of a public link, which opens without credentials.
+enum PublicLink<'a> {
+    Share(&'a str),
+    Upload(&'a str),
+    Drop(&'a str),
+    Archive(&'a str),
+}
+
+impl<'a> PublicLink<'a> {
+    fn from_path(path: &'a str) -> Option<Self> {
+        let (prefix, token) = path.trim_start_matches('/').split_once('/')?;
+        match prefix {
+            "s" | "cdn" => Some(PublicLink::Share(token)),
+            "u" => Some(PublicLink::Upload(token)),
+            "drop" => Some(PublicLink::Drop(token)),
+            "z" => Some(PublicLink::Archive(token)),
+            _ => None,
+        }
+    }
+
+    async fn issued_by(&self, storage: &FileStorage) -> bool {
+        match *self {
+            PublicLink::Share(token) => {
+                matches!(storage.get_share_by_token(token).await, Ok(Some(_)))
+                    || matches!(
+                        storage.get_directory_share_by_token(token).await,
+                        Ok(Some(_))
+                    )
+                    || matches!(
+                        storage.get_bundle_share_by_token(token).await,
+                        Ok(Some(_))
+                    )
+            }
+            PublicLink::Upload(token) => {
+                matches!(storage.get_upload_link_by_token(token).await, Ok(Some(_)))
+            }
+            PublicLink::Drop(token) => {
+                matches!(storage.get_drop_link_by_token(token).await, Ok(Some(_)))
+            }
+            PublicLink::Archive(token) => {
+                matches!(storage.get_archive_job_by_ticket(token).await, Ok(Some(_)))
+            }
+        }
+    }
+}
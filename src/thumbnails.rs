@@ -0,0 +1,253 @@
+use crate::convert::{self, ConvertError};
+use crate::events::ChangeEvent;
+use crate::models::FileMetadata;
+use crate::storage::FileStorage;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::fs;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::Semaphore;
+use tracing::{info, warn};
+
+/// A size thumbnails are rendered at; images are scaled to fit a square of
+/// `pixels` on each side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ThumbnailSize {
+    pub name: &'static str,
+    pub pixels: u32,
+}
+
+pub const THUMBNAIL_SIZES: &[ThumbnailSize] = &[
+    ThumbnailSize {
+        name: "small",
+        pixels: 128,
+    },
+    ThumbnailSize {
+        name: "medium",
+        pixels: 256,
+    },
+    ThumbnailSize {
+        name: "large",
+        pixels: 512,
+    },
+    ThumbnailSize {
+        name: "preview",
+        pixels: 1280,
+    },
+];
+
+impl ThumbnailSize {
+    pub fn from_name(name: &str) -> Option<Self> {
+        THUMBNAIL_SIZES.iter().copied().find(|s| s.name == name)
+    }
+}
+
+/// Formats the thumbnail command is not asked to render; vector images are
+/// better shown as they are.
+const EXCLUDED_MIME_TYPES: &[&str] = &["image/svg+xml"];
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff", "heic", "heif", "avif", "dng",
+];
+
+/// Renders JPEG thumbnails of stored images with an external program such
+/// as ImageMagick or libvips, caching each one on disk. At most a set
+/// number of renders run at once, however many uploads come in.
+#[derive(Debug, Clone)]
+pub struct Thumbnailer {
+    /// Program and arguments; `{input}`, `{output}` and `{size}` are
+    /// replaced by paths and the size in pixels.
+    command: Vec<String>,
+    cache_dir: PathBuf,
+    permits: Arc<Semaphore>,
+}
+
+impl Thumbnailer {
+    /// Parses a command template such as
+    /// `magick {input}[0] -auto-orient -thumbnail {size}x{size} {output}`.
+    /// A template without `{input}` gets the input and output paths appended.
+    pub fn new(template: &str, cache_dir: PathBuf, workers: usize) -> Option<Self> {
+        let mut command: Vec<String> = template.split_whitespace().map(String::from).collect();
+        if command.is_empty() {
+            return None;
+        }
+        if !command.iter().any(|arg| arg.contains("{input}")) {
+            command.push("{input}".to_string());
+            command.push("{output}".to_string());
+        }
+        Some(Self {
+            command,
+            cache_dir,
+            permits: Arc::new(Semaphore::new(workers.max(1))),
+        })
+    }
+
+    pub fn is_thumbnailable(metadata: &FileMetadata) -> bool {
+        let mime = metadata.mime_type.as_deref().map(str::to_ascii_lowercase);
+        if mime
+            .as_deref()
+            .is_some_and(|mime| EXCLUDED_MIME_TYPES.contains(&mime))
+        {
+            return false;
+        }
+        let by_mime = mime.is_some_and(|mime| mime.starts_with("image/"));
+        let by_extension = Path::new(&metadata.original_filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        by_mime || by_extension
+    }
+
+    fn cache_path(&self, file_id: &str, size: ThumbnailSize) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}-{}.jpg", file_id, size.name))
+    }
+
+    /// Path of the file's thumbnail, rendering it first unless a cached one
+    /// exists.
+    pub async fn generate(
+        &self,
+        storage: &FileStorage,
+        metadata: &FileMetadata,
+        size: ThumbnailSize,
+    ) -> Result<PathBuf, ConvertError> {
+        let cached = self.cache_path(&metadata.id, size);
+        if fs::try_exists(&cached).await? {
+            return Ok(cached);
+        }
+        let _permit = self
+            .permits
+            .acquire()
+            .await
+            .map_err(|e| ConvertError::Failed(e.to_string()))?;
+        // Another request may have rendered it while this one waited.
+        if fs::try_exists(&cached).await? {
+            return Ok(cached);
+        }
+
+        fs::create_dir_all(&self.cache_dir).await?;
+        let content = storage
+            .open_content(metadata)
+            .await
+            .map_err(|e| ConvertError::Source(e.to_string()))?;
+        let source_extension = Path::new(&metadata.original_filename)
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("bin");
+        let nonce = uuid::Uuid::new_v4();
+        let input = self
+            .cache_dir
+            .join(format!("{}.{}.{}", metadata.id, nonce, source_extension));
+        let output = self
+            .cache_dir
+            .join(format!("{}.{}-{}.jpg", metadata.id, nonce, size.name));
+
+        let pixels = size.pixels.to_string();
+        let result = convert::run(
+            &self.command,
+            content,
+            &input,
+            &output,
+            &[("{size}", &pixels)],
+        )
+        .await;
+        let _ = fs::remove_file(&input).await;
+        match result {
+            Ok(()) => {
+                fs::rename(&output, &cached).await?;
+                Ok(cached)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&output).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops cached thumbnails of a deleted file.
+    pub async fn forget(&self, file_id: &str) {
+        for size in THUMBNAIL_SIZES {
+            let path = self.cache_path(file_id, *size);
+            if let Err(e) = fs::remove_file(&path).await {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("Failed to remove cached thumbnail {:?}: {}", path, e);
+                }
+            }
+        }
+    }
+}
+
+/// Starts rendering thumbnails in the background for images uploaded to
+/// directories with a thumbnail policy, so galleries of fresh uploads open
+/// without waiting on them.
+pub fn spawn(storage: FileStorage) {
+    let Some(thumbnailer) = storage.thumbnailer().cloned() else {
+        return;
+    };
+    let mut events = storage.events().subscribe();
+    tokio::spawn(async move {
+        loop {
+            let event = match events.recv().await {
+                Ok(event) => event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!(
+                        "Thumbnail pre-generation fell behind and missed {} events",
+                        missed
+                    );
+                    continue;
+                }
+                Err(RecvError::Closed) => break,
+            };
+
+            let (ChangeEvent::FileCreated {
+                file_id,
+                parent_directory_id,
+                ..
+            }
+            | ChangeEvent::FileUpdated {
+                file_id,
+                parent_directory_id,
+                ..
+            }) = &event.change
+            else {
+                continue;
+            };
+            let sizes = match storage
+                .thumbnail_sizes_for(parent_directory_id.as_deref())
+                .await
+            {
+                Ok(sizes) if !sizes.is_empty() => sizes,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!(
+                        "Failed to look up thumbnail policies for {}: {}",
+                        file_id, e
+                    );
+                    continue;
+                }
+            };
+            let metadata = match storage.get_file_metadata(file_id).await {
+                Ok(Some(metadata)) if Thumbnailer::is_thumbnailable(&metadata) => metadata,
+                Ok(_) => continue,
+                Err(e) => {
+                    warn!("Failed to look up {} for thumbnails: {}", file_id, e);
+                    continue;
+                }
+            };
+
+            let storage = storage.clone();
+            let thumbnailer = thumbnailer.clone();
+            tokio::spawn(async move {
+                for size in sizes {
+                    if let Err(e) = thumbnailer.generate(&storage, &metadata, size).await {
+                        warn!(
+                            "Failed to render {} thumbnail of {}: {}",
+                            size.name, metadata.id, e
+                        );
+                        return;
+                    }
+                }
+                info!("Pre-generated thumbnails of {}", metadata.id);
+            });
+        }
+    });
+}
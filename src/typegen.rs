@@ -0,0 +1,187 @@
+use crate::events::{ChangeEvent, Event, WebhookPayload};
+use crate::models::*;
+use ts_rs::TS;
+
+/// Declares every listed type as an exported TypeScript type, in order.
+macro_rules! declarations {
+    ($($model:ty),* $(,)?) => {
+        vec![$(format!("export {}", <$model as TS>::decl())),*]
+    };
+}
+
+/// TypeScript declarations for every request and response body of the API.
+/// New models need to be added here to be included.
+pub fn generate() -> String {
+    let declarations: Vec<String> = declarations![
+        // Files and listings
+        FileResponse,
+        UploadReceipt,
+        UploadResponse,
+        ReceiptResponse,
+        IngestResponse,
+        ArchiveLimitExceeded,
+        ListFilesResponse,
+        TreeEntry,
+        ListTreeResponse,
+        ManifestEntry,
+        CompareRequest,
+        ComparedFile,
+        CompareResponse,
+        MoveFileRequest,
+        UpdateFileRequest,
+        CreateUploadSessionRequest,
+        UploadSessionResponse,
+        // Directories
+        Directory,
+        DirectoryResponse,
+        DirectoryReadme,
+        CreateDirectoryRequest,
+        CreateDirectoryResponse,
+        MoveDirectoryRequest,
+        MoveDirectoryToRequest,
+        DirectoryArrangement,
+        UpdateDirectoryArrangementRequest,
+        DirectoryAclEntry,
+        DirectoryAclResponse,
+        SetDirectoryAclRequest,
+        // Links
+        Link,
+        LinkResponse,
+        CreateLinkRequest,
+        // Bulk operations
+        BulkDeleteRequest,
+        BulkDeleteResponse,
+        BulkTransferRequest,
+        BulkItemResult,
+        BulkMoveResponse,
+        BulkCopyResponse,
+        BulkUpdateRequest,
+        BulkUpdateResponse,
+        BatchOperation,
+        BatchRequest,
+        BatchOperationResult,
+        BatchResponse,
+        DeleteResponse,
+        ErrorResponse,
+        // Retention and reports
+        RetentionPolicy,
+        CreateRetentionPolicyRequest,
+        ThumbnailPolicy,
+        CreateThumbnailPolicyRequest,
+        UsageNode,
+        QuotaStatus,
+        WriteQueueStats,
+        UploadCleanupStats,
+        ArchiveJobResponse,
+        SegmentManifest,
+        FileSegment,
+        FileVersion,
+        WebhookReplayResponse,
+        ReportShareRequest,
+        ReportShareResponse,
+        ShareReport,
+        ResolveShareReportRequest,
+        TrashItem,
+        DatabaseStats,
+        DatabaseMaintenance,
+        DiskBenchmarkRequest,
+        DiskBenchmark,
+        CostEstimate,
+        DirectoryCost,
+        ShareCost,
+        CostReport,
+        FileUsageResponse,
+        FileReportResponse,
+        SearchMatch,
+        SearchResponse,
+        AccessLogEntry,
+        AccessLogResponse,
+        // Notifications
+        NotificationPreferences,
+        UpdateNotificationPreferencesRequest,
+        WatchedFolder,
+        WatchFolderRequest,
+        PendingNotification,
+        OidcIdentity,
+        ArchiveJob,
+        UserDataExport,
+        UserErasureReport,
+        PushSubscription,
+        PushSubscriptionKeys,
+        CreatePushSubscriptionRequest,
+        VapidPublicKeyResponse,
+        DirectoryIntegration,
+        CreateDirectoryIntegrationRequest,
+        CreateShareRequest,
+        ShareResponse,
+        SetShareTermsRequest,
+        ShareTerms,
+        DirectoryShareResponse,
+        CreateBundleShareRequest,
+        BundleShareResponse,
+        CreateUploadLinkRequest,
+        UploadLinkResponse,
+        UploadLinkInfo,
+        UploadSubmission,
+        CreateDropLinkRequest,
+        DropLinkResponse,
+        DroppedFile,
+        DropReceipt,
+        SubmissionsReport,
+        QuarantinedFileResponse,
+        QuarantineFileRequest,
+        QuarantineReviewRequest,
+        QuarantineDecision,
+        ShareTermsRequired,
+        DownloadConfirmationRequired,
+        ShareXUploader,
+        // Audio
+        AudioMetadata,
+        WaveformResponse,
+        // Attachments
+        FileAttachment,
+        CreateAttachmentRequest,
+        // Clipboard
+        ClipboardEntry,
+        SetClipboardRequest,
+        // Instance information
+        Capabilities,
+        Branding,
+        CapabilitiesResponse,
+        VersionResponse,
+        // Accounts
+        Credentials,
+        UserResponse,
+        TokenResponse,
+        // Change events
+        ChangeEvent,
+        Event,
+        WebhookPayload,
+    ];
+
+    format!(
+        "// Generated by `fileshare_rust generate-types`. Do not edit by hand.\n\n{}\n",
+        declarations.join("\n\n")
+    )
+}
+
+/// Runs `fileshare generate-types [FILE]`, writing to stdout when no file is
+/// given. Returns the process exit code.
+pub fn run_command(output: Option<&str>) -> i32 {
+    let types = generate();
+    let Some(path) = output else {
+        print!("{}", types);
+        return 0;
+    };
+
+    match std::fs::write(path, types) {
+        Ok(()) => {
+            eprintln!("Wrote TypeScript types to {}", path);
+            0
+        }
+        Err(e) => {
+            eprintln!("Failed to write {}: {}", path, e);
+            1
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use crate::models::UploadCleanupStats;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Smallest chunk a session will use, so tiny chunks can't flood the server
+/// with requests.
+pub const MIN_CHUNK_SIZE: i64 = 256 * 1024;
+pub const MAX_PARALLELISM: i64 = 8;
+
+/// How a client says it is connected. Slower, less reliable networks get
+/// smaller chunks, so a dropped connection only costs a short retry, and
+/// fewer of them in flight at once.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NetworkProfile {
+    Wired,
+    #[default]
+    Wifi,
+    Cellular,
+    Constrained,
+}
+
+impl NetworkProfile {
+    pub const NAMES: &'static [&'static str] = &["wired", "wifi", "cellular", "constrained"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "wired" => Some(Self::Wired),
+            "wifi" => Some(Self::Wifi),
+            "cellular" => Some(Self::Cellular),
+            "constrained" => Some(Self::Constrained),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Wired => "wired",
+            Self::Wifi => "wifi",
+            Self::Cellular => "cellular",
+            Self::Constrained => "constrained",
+        }
+    }
+
+    /// Default chunk size, largest chunk size a client may ask for, and
+    /// default number of chunks to send in parallel.
+    fn limits(self) -> (i64, i64, i64) {
+        const MIB: i64 = 1024 * 1024;
+        match self {
+            Self::Wired => (16 * MIB, 64 * MIB, 4),
+            Self::Wifi => (8 * MIB, 32 * MIB, 4),
+            Self::Cellular => (MIB, 4 * MIB, 2),
+            Self::Constrained => (MIN_CHUNK_SIZE, MIB, 1),
+        }
+    }
+}
+
+/// The chunking a session settled on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkPlan {
+    pub chunk_size: i64,
+    pub parallelism: i64,
+}
+
+/// Picks chunk size and parallelism for a profile. Values the client asks
+/// for are honored within the profile's limits; a client may raise
+/// parallelism to at most twice the profile's default.
+pub fn negotiate(
+    profile: NetworkProfile,
+    requested_chunk_size: Option<i64>,
+    requested_parallelism: Option<i64>,
+) -> ChunkPlan {
+    let (default_size, max_size, default_parallelism) = profile.limits();
+    ChunkPlan {
+        chunk_size: requested_chunk_size
+            .unwrap_or(default_size)
+            .clamp(MIN_CHUNK_SIZE, max_size),
+        parallelism: requested_parallelism
+            .unwrap_or(default_parallelism)
+            .clamp(1, (default_parallelism * 2).min(MAX_PARALLELISM)),
+    }
+}
+
+pub fn total_chunks(file_size: i64, chunk_size: i64) -> i64 {
+    (file_size + chunk_size - 1) / chunk_size
+}
+
+/// The exact size chunk `index` must have, or `None` if the file has no
+/// such chunk. Every chunk is full-sized except possibly the last.
+pub fn expected_chunk_size(file_size: i64, chunk_size: i64, index: i64) -> Option<i64> {
+    if index < 0 || index >= total_chunks(file_size, chunk_size) {
+        return None;
+    }
+    Some((file_size - index * chunk_size).min(chunk_size))
+}
+
+/// What one cleanup pass removed.
+#[derive(Debug, Default)]
+pub struct Reclaimed {
+    pub sessions: u64,
+    pub orphaned_directories: u64,
+    pub temp_files: u64,
+    pub bytes: u64,
+}
+
+impl Reclaimed {
+    pub fn is_empty(&self) -> bool {
+        self.sessions == 0 && self.orphaned_directories == 0 && self.temp_files == 0
+    }
+}
+
+/// Running totals of what cleanup has reclaimed since startup.
+#[derive(Debug, Clone, Default)]
+pub struct CleanupCounters {
+    inner: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    runs: AtomicU64,
+    sessions: AtomicU64,
+    orphaned_directories: AtomicU64,
+    temp_files: AtomicU64,
+    bytes: AtomicU64,
+    last_run_at: Mutex<Option<String>>,
+}
+
+impl CleanupCounters {
+    pub fn record(&self, reclaimed: &Reclaimed) {
+        let counters = &self.inner;
+        counters.runs.fetch_add(1, Ordering::Relaxed);
+        counters
+            .sessions
+            .fetch_add(reclaimed.sessions, Ordering::Relaxed);
+        counters
+            .orphaned_directories
+            .fetch_add(reclaimed.orphaned_directories, Ordering::Relaxed);
+        counters
+            .temp_files
+            .fetch_add(reclaimed.temp_files, Ordering::Relaxed);
+        counters.bytes.fetch_add(reclaimed.bytes, Ordering::Relaxed);
+        *counters.last_run_at.lock().unwrap() = Some(chrono::Utc::now().to_rfc3339());
+    }
+
+    pub fn stats(&self, session_ttl_secs: u64) -> UploadCleanupStats {
+        let counters = &self.inner;
+        UploadCleanupStats {
+            session_ttl_secs,
+            runs: counters.runs.load(Ordering::Relaxed),
+            sessions_expired: counters.sessions.load(Ordering::Relaxed),
+            orphaned_directories: counters.orphaned_directories.load(Ordering::Relaxed),
+            temp_files_removed: counters.temp_files.load(Ordering::Relaxed),
+            bytes_reclaimed: counters.bytes.load(Ordering::Relaxed),
+            last_run_at: counters.last_run_at.lock().unwrap().clone(),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use axum::body::Bytes;
+use std::io;
+use std::path::PathBuf;
+use tokio::sync::{mpsc, oneshot};
+
+/// Reads ahead of the client, per download.
+const READ_AHEAD: usize = 4;
+
+/// io_uring files can only be used on the thread whose runtime opened
+/// them, so all of them live on one thread, which the rest of the server
+/// hands jobs to.
+#[derive(Debug, Clone)]
+pub struct Driver {
+    jobs: mpsc::UnboundedSender<Job>,
+}
+
+enum Job {
+    Write {
+        file: std::fs::File,
+        chunks: mpsc::Receiver<Bytes>,
+        dequeued: Box<dyn Fn(usize) + Send>,
+        buffer: usize,
+        done: oneshot::Sender<io::Result<()>>,
+    },
+    Read {
+        path: PathBuf,
+        start: u64,
+        length: Option<u64>,
+        buffer: usize,
+        opened: oneshot::Sender<io::Result<()>>,
+        chunks: mpsc::Sender<io::Result<Bytes>>,
+    },
+}
+
+impl Driver {
+    /// Starts the io_uring thread. Fails where the kernel doesn't offer
+    /// io_uring or a sandbox forbids it.
+    pub fn start() -> io::Result<Self> {
+        let (jobs, mut queue) = mpsc::unbounded_channel();
+        let (ready_tx, ready_rx) = std::sync::mpsc::channel();
+        std::thread::Builder::new()
+            .name("io-uring".to_string())
+            .spawn(move || {
+                let runtime = match tokio_uring::Runtime::new(&tokio_uring::builder()) {
+                    Ok(runtime) => runtime,
+                    Err(e) => {
+                        let _ = ready_tx.send(Err(e));
+                        return;
+                    }
+                };
+                let _ = ready_tx.send(Ok(()));
+                runtime.block_on(async move {
+                    while let Some(job) = queue.recv().await {
+                        tokio_uring::spawn(run(job));
+                    }
+                });
+            })?;
+        ready_rx
+            .recv()
+            .map_err(|_| io::Error::other("io_uring thread exited"))??;
+        Ok(Self { jobs })
+    }
+
+    /// Writes the chunks received into `file`, from its start, in writes
+    /// of about `buffer` bytes, calling `dequeued` with the size of each
+    /// chunk taken off the queue.
+    pub async fn write(
+        &self,
+        file: std::fs::File,
+        chunks: mpsc::Receiver<Bytes>,
+        dequeued: Box<dyn Fn(usize) + Send>,
+        buffer: usize,
+    ) -> io::Result<()> {
+        let (done, result) = oneshot::channel();
+        self.submit(Job::Write {
+            file,
+            chunks,
+            dequeued,
+            buffer,
+            done,
+        })?;
+        result.await.map_err(|_| stopped())?
+    }
+
+    /// Streams `length` bytes of the file at `path` from byte `start`, or
+    /// all of it from there, in reads of `buffer` bytes.
+    pub async fn read(
+        &self,
+        path: PathBuf,
+        start: u64,
+        length: Option<u64>,
+        buffer: usize,
+    ) -> io::Result<mpsc::Receiver<io::Result<Bytes>>> {
+        let (opened, result) = oneshot::channel();
+        let (chunks, rx) = mpsc::channel(READ_AHEAD);
+        self.submit(Job::Read {
+            path,
+            start,
+            length,
+            buffer,
+            opened,
+            chunks,
+        })?;
+        result.await.map_err(|_| stopped())??;
+        Ok(rx)
+    }
+
+    fn submit(&self, job: Job) -> io::Result<()> {
+        self.jobs.send(job).map_err(|_| stopped())
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::other("io_uring thread stopped")
+}
+
+async fn run(job: Job) {
+    match job {
+        Job::Write {
+            file,
+            mut chunks,
+            dequeued,
+            buffer,
+            done,
+        } => {
+            let file = tokio_uring::fs::File::from_std(file);
+            let mut written = write_chunks(&file, &mut chunks, &*dequeued, buffer).await;
+            if written.is_err() {
+                chunks.close();
+                while let Ok(chunk) = chunks.try_recv() {
+                    dequeued(chunk.len());
+                }
+            }
+            let closed = file.close().await;
+            if written.is_ok() {
+                written = closed;
+            }
+            let _ = done.send(written);
+        }
+        Job::Read {
+            path,
+            start,
+            length,
+            buffer,
+            opened,
+            chunks,
+        } => {
+            let file = match tokio_uring::fs::File::open(&path).await {
+                Ok(file) => file,
+                Err(e) => {
+                    let _ = opened.send(Err(e));
+                    return;
+                }
+            };
+            if opened.send(Ok(())).is_ok() {
+                read_chunks(&file, start, length, buffer, &chunks).await;
+            }
+            let _ = file.close().await;
+        }
+    }
+}
+
+/// Gathers chunks smaller than `buffer` into writes of at least that
+/// size; larger ones are written as they are.
+async fn write_chunks(
+    file: &tokio_uring::fs::File,
+    chunks: &mut mpsc::Receiver<Bytes>,
+    dequeued: &dyn Fn(usize),
+    buffer: usize,
+) -> io::Result<()> {
+    let mut position = 0u64;
+    let mut pending = Vec::new();
+    while let Some(chunk) = chunks.recv().await {
+        dequeued(chunk.len());
+        if pending.is_empty() && chunk.len() >= buffer {
+            position = write_all_at(file, chunk, position).await?;
+            continue;
+        }
+        pending.extend_from_slice(&chunk);
+        if pending.len() >= buffer {
+            let full = std::mem::replace(&mut pending, Vec::with_capacity(buffer));
+            position = write_all_at(file, Bytes::from(full), position).await?;
+        }
+    }
+    if !pending.is_empty() {
+        write_all_at(file, Bytes::from(pending), position).await?;
+    }
+    Ok(())
+}
+
+/// Writes all of `chunk` at `position`, returning the position after it.
+async fn write_all_at(
+    file: &tokio_uring::fs::File,
+    mut chunk: Bytes,
+    mut position: u64,
+) -> io::Result<u64> {
+    while !chunk.is_empty() {
+        let (result, buf) = file.write_at(chunk, position).await;
+        let n = result?;
+        if n == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        position += n as u64;
+        chunk = buf.slice(n..);
+    }
+    Ok(position)
+}
+
+/// Reads until the end of the range or of the file, or until the reader
+/// has gone away.
+async fn read_chunks(
+    file: &tokio_uring::fs::File,
+    start: u64,
+    length: Option<u64>,
+    buffer: usize,
+    chunks: &mpsc::Sender<io::Result<Bytes>>,
+) {
+    let end = length.map(|length| start + length);
+    let mut position = start;
+    loop {
+        let wanted = end.map_or(buffer, |end| buffer.min((end - position) as usize));
+        if wanted == 0 {
+            return;
+        }
+        let (result, mut buf) = file.read_at(Vec::with_capacity(wanted), position).await;
+        let chunk = match result {
+            Ok(0) => return,
+            Ok(n) => {
+                buf.truncate(n);
+                position += n as u64;
+                Ok(Bytes::from(buf))
+            }
+            Err(e) => Err(e),
+        };
+        let failed = chunk.is_err();
+        if chunks.send(chunk).await.is_err() || failed {
+            return;
+        }
+    }
+}
@@ -0,0 +1,160 @@
+use crate::events::ChangeEvent;
+use crate::import::{self, ImportReport};
+use crate::mirror::Mirror;
+use crate::storage::FileStorage;
+use notify::event::{ModifyKind, RemoveKind};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::path::Path;
+use tokio::fs;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Watches the import and mirror directories for changes made outside the
+/// API. Imported trees are kept in sync with the database; both kinds of
+/// directory publish the same change events as API mutations.
+///
+/// The returned watchers must be kept alive for watching to continue.
+pub fn spawn(
+    storage: FileStorage,
+    import_dir: Option<&Path>,
+) -> notify::Result<Vec<RecommendedWatcher>> {
+    let import_root = import_dir.map(std::fs::canonicalize).transpose()?;
+    let mirror_root = storage
+        .mirror()
+        .map(|m| std::fs::canonicalize(m.root()))
+        .transpose()?;
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watchers = Vec::new();
+    for root in import_root.iter().chain(mirror_root.iter()) {
+        let tx = tx.clone();
+        let mut watcher = notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        })?;
+        watcher.watch(root, RecursiveMode::Recursive)?;
+        info!("Watching {:?} for external changes", root);
+        watchers.push(watcher);
+    }
+
+    tokio::spawn(async move {
+        while let Some(res) = rx.recv().await {
+            let event: Event = match res {
+                Ok(event) => event,
+                Err(e) => {
+                    warn!("Filesystem watcher error: {}", e);
+                    continue;
+                }
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+
+            for path in &event.paths {
+                let result = if let Some(root) = import_root.as_deref().filter(|r| path.starts_with(r)) {
+                    sync_imported_path(&storage, root, path).await
+                } else if let Some(root) = mirror_root.as_deref().filter(|r| path.starts_with(r)) {
+                    publish_mirror_change(&storage, root, path, &event.kind).await
+                } else {
+                    Ok(())
+                };
+                if let Err(e) = result {
+                    error!("Failed to process change to {:?}: {}", path, e);
+                }
+            }
+        }
+    });
+
+    Ok(watchers)
+}
+
+/// Brings the database entry for `path` in line with what is on disk.
+async fn sync_imported_path(storage: &FileStorage, root: &Path, path: &Path) -> Result<(), BoxError> {
+    if path == root {
+        return Ok(());
+    }
+
+    match fs::symlink_metadata(path).await {
+        Ok(meta) if meta.is_dir() => {
+            let dir_id = import::ensure_directory(storage, root, path).await?;
+            import::import_into(storage, path, dir_id, &mut ImportReport::default()).await?;
+        }
+        Ok(meta) if meta.is_file() => {
+            if let Some(existing) = storage.find_file_by_storage_path(path).await? {
+                if existing.file_size != meta.len() as i64 {
+                    storage.update_file_size(&existing.id, meta.len() as i64).await?;
+                }
+                return Ok(());
+            }
+
+            let name = storage
+                .name_policy()
+                .normalize(&path.file_name().unwrap_or_default().to_string_lossy());
+            if storage.name_policy().validate(&name).is_err() {
+                return Ok(());
+            }
+            let parent = path.parent().unwrap_or(root);
+            let parent_id = import::ensure_directory(storage, root, parent).await?;
+            import::import_file(storage, path, &name, parent_id).await?;
+        }
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            if let Some(file) = storage.find_file_by_storage_path(path).await? {
+                storage.delete_file_permanently(&file.id).await?;
+            } else if let Some(dir_id) = import::find_directory(storage, root, path).await? {
+                storage.delete_directory_permanently(&dir_id).await?;
+            }
+        }
+        Err(e) => return Err(e.into()),
+    }
+
+    Ok(())
+}
+
+/// Mirrored listings are read from disk, so only events need publishing.
+async fn publish_mirror_change(
+    storage: &FileStorage,
+    root: &Path,
+    path: &Path,
+    kind: &EventKind,
+) -> Result<(), BoxError> {
+    let Some(id) = Mirror::id_for_path(root, path) else {
+        return Ok(());
+    };
+    let parent_id = path.parent().and_then(|p| Mirror::id_for_path(root, p));
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+
+    let created = matches!(kind, EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)));
+    let removed = matches!(kind, EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(_)));
+
+    let change = match fs::symlink_metadata(path).await {
+        Ok(meta) if created && meta.is_dir() => ChangeEvent::DirectoryCreated {
+            directory_id: id,
+            name,
+            parent_id,
+        },
+        Ok(meta) if created && meta.is_file() => ChangeEvent::FileCreated {
+            file_id: id,
+            name,
+            parent_directory_id: parent_id,
+        },
+        Err(_) if removed && matches!(kind, EventKind::Remove(RemoveKind::Folder)) => {
+            ChangeEvent::DirectoryDeleted {
+                directory_id: id,
+                parent_id,
+            }
+        }
+        Err(_) if removed => ChangeEvent::FileDeleted {
+            file_id: id,
+            parent_directory_id: parent_id,
+        },
+        _ => return Ok(()),
+    };
+
+    storage.events().publish(change);
+    Ok(())
+}
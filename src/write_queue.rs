@@ -0,0 +1,147 @@
+use crate::encryption::Encryptor;
+use crate::file_io::FileIo;
+use crate::models::WriteQueueStats;
+use axum::body::Bytes;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tokio::fs::File;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+
+/// Hands upload data to the disk through a bounded queue per upload. While
+/// the disk keeps up, receiving the next chunk overlaps with writing the
+/// last; once the queue is full, reading from the client waits for the
+/// disk, so a slow upload directory (such as NFS) slows the sender down
+/// instead of piling up data in memory.
+#[derive(Debug, Clone)]
+pub struct WriteQueue {
+    capacity: usize,
+    io: FileIo,
+    counters: Arc<Counters>,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    writers: AtomicU64,
+    queued_chunks: AtomicU64,
+    queued_bytes: AtomicU64,
+    disk_waits: AtomicU64,
+}
+
+impl WriteQueue {
+    pub fn new(capacity: usize, io: FileIo) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            io,
+            counters: Arc::default(),
+        }
+    }
+
+    /// Starts writing to `file` in the background, through `encryptor` when
+    /// content is stored encrypted.
+    pub fn writer(&self, file: File, encryptor: Option<Encryptor>) -> QueuedWriter {
+        let (tx, rx) = mpsc::channel(self.capacity);
+        let counters = self.counters.clone();
+        counters.writers.fetch_add(1, Ordering::Relaxed);
+        let io = self.io.clone();
+        let task = tokio::spawn({
+            let counters = counters.clone();
+            async move {
+                let dequeued = {
+                    let counters = counters.clone();
+                    move |len: usize| {
+                        counters.queued_chunks.fetch_sub(1, Ordering::Relaxed);
+                        counters
+                            .queued_bytes
+                            .fetch_sub(len as u64, Ordering::Relaxed);
+                    }
+                };
+                let written = io.write(file, rx, dequeued).await;
+                counters.writers.fetch_sub(1, Ordering::Relaxed);
+                written
+            }
+        });
+        QueuedWriter {
+            tx,
+            task: Some(task),
+            counters,
+            encryptor,
+        }
+    }
+
+    pub fn stats(&self) -> WriteQueueStats {
+        WriteQueueStats {
+            active_writers: self.counters.writers.load(Ordering::Relaxed),
+            queued_chunks: self.counters.queued_chunks.load(Ordering::Relaxed),
+            queued_bytes: self.counters.queued_bytes.load(Ordering::Relaxed),
+            capacity_chunks: self.capacity as u64,
+            disk_waits: self.counters.disk_waits.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// The sending end of one upload's queue.
+pub struct QueuedWriter {
+    tx: mpsc::Sender<Bytes>,
+    /// Taken once the writer has stopped on an error.
+    task: Option<JoinHandle<io::Result<()>>>,
+    counters: Arc<Counters>,
+    encryptor: Option<Encryptor>,
+}
+
+impl QueuedWriter {
+    /// Queues a chunk, waiting for room if the disk is behind.
+    pub async fn write(&mut self, chunk: Bytes) -> io::Result<()> {
+        let Some(encryptor) = &mut self.encryptor else {
+            return self.send(chunk).await;
+        };
+        for sealed in encryptor.update(&chunk)? {
+            self.send(sealed).await?;
+        }
+        Ok(())
+    }
+
+    async fn send(&mut self, chunk: Bytes) -> io::Result<()> {
+        let len = chunk.len() as u64;
+        self.counters.queued_chunks.fetch_add(1, Ordering::Relaxed);
+        self.counters.queued_bytes.fetch_add(len, Ordering::Relaxed);
+        let sent = match self.tx.try_send(chunk) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(chunk)) => {
+                self.counters.disk_waits.fetch_add(1, Ordering::Relaxed);
+                self.tx.send(chunk).await.map_err(|_| ())
+            }
+            Err(TrySendError::Closed(_)) => Err(()),
+        };
+        if sent.is_ok() {
+            return Ok(());
+        }
+
+        // The writer stopped on an error, which is the one to report.
+        self.counters.queued_chunks.fetch_sub(1, Ordering::Relaxed);
+        self.counters.queued_bytes.fetch_sub(len, Ordering::Relaxed);
+        let stopped = match self.task.take() {
+            Some(task) => task.await.map_err(io::Error::other).and_then(|r| r),
+            None => Ok(()),
+        };
+        Err(stopped
+            .err()
+            .unwrap_or_else(|| io::Error::other("upload writer stopped early")))
+    }
+
+    /// Waits until everything queued is written and flushed. After `write`
+    /// has failed, there is nothing left to wait for.
+    pub async fn finish(mut self) -> io::Result<()> {
+        if let Some(encryptor) = self.encryptor.take() {
+            if self.task.is_some() {
+                self.send(encryptor.finish()?).await?;
+            }
+        }
+        drop(self.tx);
+        match self.task {
+            Some(task) => task.await.map_err(io::Error::other)?,
+            None => Ok(()),
+        }
+    }
+}
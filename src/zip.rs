@@ -0,0 +1,378 @@
+use crate::models::FileMetadata;
+use crate::storage::FileStorage;
+use axum::body::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use crc32fast::Hasher;
+use futures_util::StreamExt;
+use std::io;
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::warn;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+
+/// Version 4.5, the first with ZIP64, made on Unix so the external
+/// attributes carry permissions.
+const VERSION_MADE_BY: u16 = (3 << 8) | 45;
+const VERSION_DEFAULT: u16 = 20;
+const VERSION_ZIP64: u16 = 45;
+/// Names are UTF-8.
+const FLAG_UTF8: u16 = 1 << 11;
+/// CRC and sizes follow the data instead of being in the local header.
+const FLAG_DATA_DESCRIPTOR: u16 = 1 << 3;
+/// Entries are stored; compressing on the fly would keep the archive from
+/// being streamed at disk speed, and most large files are compressed
+/// already.
+const METHOD_STORED: u16 = 0;
+const FILE_ATTRIBUTES: u32 = 0o100644 << 16;
+const DIRECTORY_ATTRIBUTES: u32 = (0o040755 << 16) | 0x10;
+
+/// Pieces of the archive produced ahead of the client reading them.
+const CHANNEL_CAPACITY: usize = 8;
+
+/// Something to put in an archive: a directory, a file's content, or
+/// content made up on the spot.
+pub struct ArchiveEntry {
+    /// Path inside the archive, without a trailing slash.
+    pub path: String,
+    pub modified: String,
+    pub content: ArchiveContent,
+}
+
+impl ArchiveEntry {
+    /// Bytes of content the entry adds to the archive, before headers.
+    pub fn content_size(&self) -> u64 {
+        match &self.content {
+            ArchiveContent::Directory => 0,
+            ArchiveContent::File(file) => file.file_size.max(0) as u64,
+            ArchiveContent::Data(data) => data.len() as u64,
+        }
+    }
+}
+
+pub enum ArchiveContent {
+    Directory,
+    File(Box<FileMetadata>),
+    /// Bytes held in memory, such as a generated manifest.
+    Data(Bytes),
+}
+
+/// Streams a ZIP archive of `entries`, reading each file only as the client
+/// consumes the archive. Nothing is staged on disk; a failure partway
+/// through ends the stream with an error.
+pub fn archive(
+    storage: FileStorage,
+    entries: Vec<ArchiveEntry>,
+) -> ReceiverStream<io::Result<Bytes>> {
+    let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+    tokio::spawn(async move {
+        if let Err(e) = write_archive(&storage, entries, &tx).await {
+            warn!("Failed to stream archive: {}", e);
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+    ReceiverStream::new(rx)
+}
+
+async fn write_archive(
+    storage: &FileStorage,
+    entries: Vec<ArchiveEntry>,
+    tx: &mpsc::Sender<io::Result<Bytes>>,
+) -> io::Result<()> {
+    let send = |bytes: Bytes| async move {
+        tx.send(Ok(bytes))
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "Client went away"))
+    };
+
+    let mut zip = ZipWriter::default();
+    for entry in entries {
+        let modified = DateTime::parse_from_rfc3339(&entry.modified)
+            .map(|t| t.with_timezone(&Utc))
+            .unwrap_or_else(|_| Utc::now());
+        let file = match entry.content {
+            ArchiveContent::Directory => {
+                send(zip.directory(&entry.path, modified)).await?;
+                continue;
+            }
+            ArchiveContent::Data(data) => {
+                send(zip.start_file(&entry.path, modified, data.len() as u64)).await?;
+                zip.write(&data);
+                send(data).await?;
+                send(zip.end_file()?).await?;
+                continue;
+            }
+            ArchiveContent::File(file) => file,
+        };
+
+        send(zip.start_file(&entry.path, modified, file.file_size.max(0) as u64)).await?;
+        let mut content = storage
+            .open_content(&file)
+            .await
+            .map_err(|e| io::Error::other(format!("Failed to open {}: {}", entry.path, e)))?;
+        while let Some(chunk) = content.next().await {
+            let chunk = chunk?;
+            zip.write(&chunk);
+            send(chunk).await?;
+        }
+        send(zip.end_file()?).await?;
+    }
+    send(zip.finish()).await
+}
+
+/// A ZIP64-capable archive built front to back, handing out its bytes as
+/// they are written. Files go through `start_file`, `write` and `end_file`.
+#[derive(Default)]
+struct ZipWriter {
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: u64,
+    current: Option<OpenFile>,
+}
+
+struct OpenFile {
+    name: String,
+    modified: DateTime<Utc>,
+    header_offset: u64,
+    zip64: bool,
+    hasher: Hasher,
+    size: u64,
+}
+
+impl ZipWriter {
+    fn directory(&mut self, path: &str, modified: DateTime<Utc>) -> Bytes {
+        let name = format!("{}/", path);
+        let header_offset = self.offset;
+        let header = local_header(&name, modified, FLAG_UTF8, false);
+        self.record(&name, modified, header_offset, None);
+        self.emit(header)
+    }
+
+    /// Starts a file expected to be `size` bytes long, which decides
+    /// whether it needs ZIP64 sizes.
+    fn start_file(&mut self, path: &str, modified: DateTime<Utc>, size: u64) -> Bytes {
+        let zip64 = size >= u32::MAX as u64;
+        let header = local_header(path, modified, FLAG_UTF8 | FLAG_DATA_DESCRIPTOR, zip64);
+        self.current = Some(OpenFile {
+            name: path.to_string(),
+            modified,
+            header_offset: self.offset,
+            zip64,
+            hasher: Hasher::new(),
+            size: 0,
+        });
+        self.emit(header)
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if let Some(file) = &mut self.current {
+            file.hasher.update(data);
+            file.size += data.len() as u64;
+        }
+        self.offset += data.len() as u64;
+    }
+
+    fn end_file(&mut self) -> io::Result<Bytes> {
+        let Some(file) = self.current.take() else {
+            return Ok(Bytes::new());
+        };
+        if !file.zip64 && file.size >= u32::MAX as u64 {
+            return Err(io::Error::other(format!(
+                "{} is larger than its recorded size",
+                file.name
+            )));
+        }
+
+        let crc = file.hasher.finalize();
+        let mut descriptor = Vec::with_capacity(24);
+        put_u32(&mut descriptor, DATA_DESCRIPTOR_SIGNATURE);
+        put_u32(&mut descriptor, crc);
+        if file.zip64 {
+            put_u64(&mut descriptor, file.size);
+            put_u64(&mut descriptor, file.size);
+        } else {
+            put_u32(&mut descriptor, file.size as u32);
+            put_u32(&mut descriptor, file.size as u32);
+        }
+        self.record(
+            &file.name,
+            file.modified,
+            file.header_offset,
+            Some((crc, file.size)),
+        );
+        Ok(self.emit(descriptor))
+    }
+
+    /// Writes the central directory and end records.
+    fn finish(mut self) -> Bytes {
+        let directory_offset = self.offset;
+        let directory_size = self.central_directory.len() as u64;
+        let mut end = std::mem::take(&mut self.central_directory);
+
+        let zip64 = self.entries >= u16::MAX as u64
+            || directory_offset >= u32::MAX as u64
+            || directory_size >= u32::MAX as u64;
+        if zip64 {
+            let zip64_end_offset = directory_offset + directory_size;
+            put_u32(&mut end, ZIP64_END_SIGNATURE);
+            put_u64(&mut end, 44);
+            put_u16(&mut end, VERSION_MADE_BY);
+            put_u16(&mut end, VERSION_ZIP64);
+            put_u32(&mut end, 0);
+            put_u32(&mut end, 0);
+            put_u64(&mut end, self.entries);
+            put_u64(&mut end, self.entries);
+            put_u64(&mut end, directory_size);
+            put_u64(&mut end, directory_offset);
+
+            put_u32(&mut end, ZIP64_LOCATOR_SIGNATURE);
+            put_u32(&mut end, 0);
+            put_u64(&mut end, zip64_end_offset);
+            put_u32(&mut end, 1);
+        }
+
+        put_u32(&mut end, END_SIGNATURE);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, 0);
+        put_u16(&mut end, self.entries.min(u16::MAX as u64) as u16);
+        put_u16(&mut end, self.entries.min(u16::MAX as u64) as u16);
+        put_u32(&mut end, directory_size.min(u32::MAX as u64) as u32);
+        put_u32(&mut end, directory_offset.min(u32::MAX as u64) as u32);
+        put_u16(&mut end, 0);
+        self.emit(end)
+    }
+
+    fn emit(&mut self, bytes: Vec<u8>) -> Bytes {
+        self.offset += bytes.len() as u64;
+        Bytes::from(bytes)
+    }
+
+    /// Adds a central directory record, for a file given its CRC and size,
+    /// or for a directory.
+    fn record(
+        &mut self,
+        name: &str,
+        modified: DateTime<Utc>,
+        header_offset: u64,
+        content: Option<(u32, u64)>,
+    ) {
+        let (flags, crc, size, attributes) = match content {
+            Some((crc, size)) => (FLAG_UTF8 | FLAG_DATA_DESCRIPTOR, crc, size, FILE_ATTRIBUTES),
+            None => (FLAG_UTF8, 0, 0, DIRECTORY_ATTRIBUTES),
+        };
+        // Values too large for their field are replaced by 0xFFFFFFFF and
+        // moved to the ZIP64 extra field, in this order.
+        let mut zip64_extra = Vec::new();
+        if size >= u32::MAX as u64 {
+            put_u64(&mut zip64_extra, size);
+            put_u64(&mut zip64_extra, size);
+        }
+        if header_offset >= u32::MAX as u64 {
+            put_u64(&mut zip64_extra, header_offset);
+        }
+        let (time, date) = dos_time(modified);
+
+        let record = &mut self.central_directory;
+        put_u32(record, CENTRAL_HEADER_SIGNATURE);
+        put_u16(record, VERSION_MADE_BY);
+        put_u16(
+            record,
+            if zip64_extra.is_empty() {
+                VERSION_DEFAULT
+            } else {
+                VERSION_ZIP64
+            },
+        );
+        put_u16(record, flags);
+        put_u16(record, METHOD_STORED);
+        put_u16(record, time);
+        put_u16(record, date);
+        put_u32(record, crc);
+        put_u32(record, size.min(u32::MAX as u64) as u32);
+        put_u32(record, size.min(u32::MAX as u64) as u32);
+        put_u16(record, name.len() as u16);
+        put_u16(
+            record,
+            if zip64_extra.is_empty() {
+                0
+            } else {
+                zip64_extra.len() as u16 + 4
+            },
+        );
+        put_u16(record, 0);
+        put_u16(record, 0);
+        put_u16(record, 0);
+        put_u32(record, attributes);
+        put_u32(record, header_offset.min(u32::MAX as u64) as u32);
+        record.extend_from_slice(name.as_bytes());
+        if !zip64_extra.is_empty() {
+            put_u16(record, ZIP64_EXTRA_ID);
+            put_u16(record, zip64_extra.len() as u16);
+            record.extend_from_slice(&zip64_extra);
+        }
+        self.entries += 1;
+    }
+}
+
+/// A local file header. CRC and sizes are left zero, to be given by the
+/// data descriptor; a ZIP64 entry marks its sizes as being in the extra
+/// field, so readers expect 8-byte sizes in the descriptor.
+fn local_header(name: &str, modified: DateTime<Utc>, flags: u16, zip64: bool) -> Vec<u8> {
+    let (time, date) = dos_time(modified);
+    let mut header = Vec::with_capacity(30 + name.len() + 20);
+    put_u32(&mut header, LOCAL_HEADER_SIGNATURE);
+    put_u16(
+        &mut header,
+        if zip64 {
+            VERSION_ZIP64
+        } else {
+            VERSION_DEFAULT
+        },
+    );
+    put_u16(&mut header, flags);
+    put_u16(&mut header, METHOD_STORED);
+    put_u16(&mut header, time);
+    put_u16(&mut header, date);
+    put_u32(&mut header, 0);
+    let size_marker = if zip64 { u32::MAX } else { 0 };
+    put_u32(&mut header, size_marker);
+    put_u32(&mut header, size_marker);
+    put_u16(&mut header, name.len() as u16);
+    put_u16(&mut header, if zip64 { 20 } else { 0 });
+    header.extend_from_slice(name.as_bytes());
+    if zip64 {
+        put_u16(&mut header, ZIP64_EXTRA_ID);
+        put_u16(&mut header, 16);
+        put_u64(&mut header, 0);
+        put_u64(&mut header, 0);
+    }
+    header
+}
+
+/// MS-DOS time and date, which can't go before 1980.
+fn dos_time(t: DateTime<Utc>) -> (u16, u16) {
+    if t.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (t.hour() << 11) | (t.minute() << 5) | (t.second() / 2);
+    let date = (((t.year() - 1980) as u32).min(127) << 9) | (t.month() << 5) | t.day();
+    (time as u16, date as u16)
+}
+
+fn put_u16(buf: &mut Vec<u8>, value: u16) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(buf: &mut Vec<u8>, value: u32) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u64(buf: &mut Vec<u8>, value: u64) {
+    buf.extend_from_slice(&value.to_le_bytes());
+}